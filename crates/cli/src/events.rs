@@ -0,0 +1,114 @@
+//! # Lifecycle Events
+//!
+//! With `--events ndjson`, the long-running commands print one JSON object
+//! per line on stdout as their session changes, so wrapper scripts and
+//! editor plugins can react without scraping log output:
+//!
+//! ```text
+//! {"time":"2026-01-02T03:04:05.678Z","event":"connected","target":"work"}
+//! {"time":"2026-01-02T03:04:06.001Z","event":"forward-opened","local_port":8080,"remote_host":"localhost","remote_port":80}
+//! ```
+//!
+//! Logs go to stderr, so stdout holds nothing but events.
+
+use serde::Serialize;
+use std::io::Write;
+use std::time::SystemTime;
+
+/// How events are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum EventFormat {
+    /// Newline-delimited JSON
+    Ndjson,
+}
+
+/// A change of a long-running command's session
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    /// A session to `target` is up
+    Connected { target: &'a str },
+    /// The session dropped and `attempt` to reconnect starts after `delay_ms`
+    Reconnecting {
+        target: &'a str,
+        attempt: u32,
+        delay_ms: u64,
+        error: String,
+    },
+    /// The remote listens on `local_port`, forwarding to `remote_host:remote_port`
+    ForwardOpened {
+        local_port: u16,
+        remote_host: &'a str,
+        remote_port: u16,
+    },
+    /// Data of a forwarded connection arrived, `total` of it so far
+    BytesTransferred {
+        connection_id: u32,
+        bytes: u64,
+        total: u64,
+    },
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    time: String,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+/// Where a command reports its events, nowhere without `--events`
+#[derive(Debug, Clone, Copy)]
+pub struct Events {
+    format: Option<EventFormat>,
+}
+
+impl Events {
+    pub fn new(format: Option<EventFormat>) -> Self {
+        Self { format }
+    }
+
+    /// Write `event` to stdout
+    pub fn emit(&self, event: Event<'_>) {
+        let Some(EventFormat::Ndjson) = self.format else {
+            return;
+        };
+        let line = ndjson(&event, SystemTime::now());
+        let mut stdout = std::io::stdout().lock();
+        // A reader that went away does not stop the command
+        let _ = writeln!(stdout, "{}", line).and_then(|()| stdout.flush());
+    }
+}
+
+fn ndjson(event: &Event<'_>, time: SystemTime) -> String {
+    let record = Record {
+        time: humantime::format_rfc3339_millis(time).to_string(),
+        event,
+    };
+    serde_json::to_string(&record).expect("events serialize to JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_ndjson() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_500);
+        assert_eq!(
+            ndjson(&Event::Connected { target: "work" }, time),
+            r#"{"time":"1970-01-01T00:00:01.500Z","event":"connected","target":"work"}"#
+        );
+        assert_eq!(
+            ndjson(
+                &Event::BytesTransferred {
+                    connection_id: 3,
+                    bytes: 10,
+                    total: 25,
+                },
+                time
+            ),
+            r#"{"time":"1970-01-01T00:00:01.500Z","event":"bytes-transferred","connection_id":3,"bytes":10,"total":25}"#
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! # Exit Codes
+//!
+//! A failing command exits with the code of its failure class, kept stable
+//! across releases so scripts can branch on the outcome:
+//!
+//! | Code | Failure                                                 |
+//! |------|---------------------------------------------------------|
+//! | 1    | anything not classified below                           |
+//! | 2    | invalid command line                                    |
+//! | 3    | a prompt was needed under `--non-interactive`           |
+//! | 4    | the remote could not be reached or the connection broke |
+//! | 5    | the remote rejected the credentials                     |
+//! | 6    | invalid configuration                                   |
+//! | 7    | the remote failed the request                           |
+//! | 8    | a `--timeout` ran out                                   |
+//! | 9    | `verify` found differing files                          |
+
+use std::fmt;
+use std::time::Duration;
+use yuha_client::ClientError;
+use yuha_client::daemon_protocol::ErrorCode;
+use yuha_core::YuhaError;
+use yuha_core::error::TransportError;
+use yuha_core::error::categories::{ErrorCategory, ErrorClassification};
+
+// 2 is what clap exits with on an invalid command line
+pub const FAILURE: i32 = 1;
+pub const INTERACTION_REQUIRED: i32 = 3;
+pub const CONNECTION: i32 = 4;
+pub const AUTHENTICATION: i32 = 5;
+pub const CONFIGURATION: i32 = 6;
+pub const REMOTE: i32 = 7;
+pub const TIMEOUT: i32 = 8;
+pub const MISMATCH: i32 = 9;
+
+/// A wait that did not reach its state within its timeout
+#[derive(Debug)]
+pub struct TimedOut {
+    pub waiting_for: String,
+    pub timeout: Duration,
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Timed out after {} waiting for {}",
+            humantime::format_duration(self.timeout),
+            self.waiting_for
+        )
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// A comparison that found files differing
+#[derive(Debug)]
+pub struct Mismatch {
+    pub differences: usize,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.differences {
+            1 => write!(f, "1 file differs"),
+            n => write!(f, "{} files differ", n),
+        }
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// Exit code of `error`, classified by the first cause with a known class
+pub fn code(error: &anyhow::Error) -> i32 {
+    error
+        .chain()
+        .find_map(|cause| {
+            if cause.is::<TimedOut>() {
+                return Some(TIMEOUT);
+            }
+            if cause.is::<Mismatch>() {
+                return Some(MISMATCH);
+            }
+            if let Some(error) = cause.downcast_ref::<ClientError>() {
+                return client_code(error);
+            }
+            cause.downcast_ref::<YuhaError>().and_then(core_code)
+        })
+        .unwrap_or(FAILURE)
+}
+
+fn client_code(error: &ClientError) -> Option<i32> {
+    match error {
+        ClientError::InteractionRequired { .. } => Some(INTERACTION_REQUIRED),
+        ClientError::Authentication(_) | ClientError::Key(_) => Some(AUTHENTICATION),
+        ClientError::Ssh(_)
+        | ClientError::Io(_)
+        | ClientError::Connection(_)
+        | ClientError::DaemonError {
+            code: ErrorCode::ConnectionFailed,
+            ..
+        } => Some(CONNECTION),
+        ClientError::RemoteExecution { .. } | ClientError::Unsupported { .. } => Some(REMOTE),
+        ClientError::Request(error) => core_code(error),
+        ClientError::Channel(_)
+        | ClientError::BinaryTransfer(_)
+        | ClientError::DaemonError { .. } => None,
+    }
+}
+
+fn core_code(error: &YuhaError) -> Option<i32> {
+    match error {
+        YuhaError::Transport(TransportError::AuthenticationFailed { .. }) => {
+            return Some(AUTHENTICATION);
+        }
+        YuhaError::Transport(TransportError::ConfigurationError { .. }) => {
+            return Some(CONFIGURATION);
+        }
+        _ => {}
+    }
+    match ErrorClassification::category(error) {
+        ErrorCategory::Network => Some(CONNECTION),
+        ErrorCategory::Authentication => Some(AUTHENTICATION),
+        ErrorCategory::Configuration => Some(CONFIGURATION),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_codes() {
+        let connection = anyhow::Error::new(ClientError::Connection("refused".to_string()));
+        assert_eq!(code(&connection), CONNECTION);
+        let wrapped = Err::<(), _>(ClientError::Authentication("denied".to_string()))
+            .context("Connecting to work")
+            .unwrap_err();
+        assert_eq!(code(&wrapped), AUTHENTICATION);
+        let config = anyhow::Error::new(YuhaError::config("bad port"));
+        assert_eq!(code(&config), CONFIGURATION);
+        let transport_config =
+            anyhow::Error::new(YuhaError::Transport(TransportError::ConfigurationError {
+                reason: "no key".to_string(),
+            }));
+        assert_eq!(code(&transport_config), CONFIGURATION);
+        let timed_out = anyhow::Error::new(TimedOut {
+            waiting_for: "work to connect".to_string(),
+            timeout: Duration::from_secs(30),
+        });
+        assert_eq!(code(&timed_out), TIMEOUT);
+        assert_eq!(
+            timed_out.to_string(),
+            "Timed out after 30s waiting for work to connect"
+        );
+        let mismatch = anyhow::Error::new(Mismatch { differences: 2 });
+        assert_eq!(code(&mismatch), MISMATCH);
+        assert_eq!(mismatch.to_string(), "2 files differ");
+        assert_eq!(code(&anyhow::anyhow!("unclassified")), FAILURE);
+    }
+}
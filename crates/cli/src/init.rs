@@ -0,0 +1,293 @@
+//! First-run setup
+//!
+//! `yuha init` asks for the settings a new user most likely wants, builds a
+//! config from the defaults and the answers, and writes it once validated:
+//!
+//! - hosts of `~/.ssh/config` to import as connection profiles
+//! - how the remote binary reaches SSH hosts and local sessions
+//! - a Noise key pair identifying this machine to remotes serving TCP
+//!   sessions (see [`yuha_core::protocol::noise`])
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use yuha_client::ClientError;
+use yuha_core::YuhaConfig;
+use yuha_core::config::{ConnectionProfile, SshConfig};
+use yuha_core::protocol::noise::NoiseKeypair;
+
+use crate::confirm;
+
+/// Noise key file generated next to the config
+const NOISE_KEY_FILE: &str = "noise.key";
+
+/// A `Host` entry of an OpenSSH client config
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct SshHost {
+    alias: String,
+    hostname: Option<String>,
+    user: Option<String>,
+    port: Option<u16>,
+    identity_file: Option<String>,
+}
+
+/// Walk through creating a config at `output`, the user config by default
+pub fn run(output: Option<&Path>, non_interactive: bool) -> Result<()> {
+    if non_interactive {
+        return Err(ClientError::InteractionRequired {
+            prompt: "yuha init asks for its settings on the terminal".to_string(),
+        }
+        .into());
+    }
+
+    let path = match output {
+        Some(path) => path.to_path_buf(),
+        None => dirs::config_dir()
+            .context("No user config directory")?
+            .join("yuha")
+            .join("config.toml"),
+    };
+    if path.exists() && !confirm(&format!("{} exists. Overwrite it?", path.display())) {
+        println!("Setup cancelled");
+        return Ok(());
+    }
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut config = YuhaConfig::default();
+    import_ssh_hosts(&mut config)?;
+    choose_transports(&mut config)?;
+    if confirm("Generate a Noise key for encrypted TCP sessions?") {
+        generate_noise_key(&dir.join(NOISE_KEY_FILE))?;
+    }
+
+    config.validate()?;
+    config.save_to_file(&path)?;
+    println!("Configuration saved to: {}", path.display());
+    Ok(())
+}
+
+/// Generate the Noise key pair at `path`, keeping one already there, and
+/// show how remotes come to trust it
+fn generate_noise_key(path: &Path) -> Result<()> {
+    let existed = path.exists();
+    let keypair = NoiseKeypair::load_or_generate(path)
+        .with_context(|| format!("Failed to generate {}", path.display()))?;
+    if existed {
+        println!("Using the existing key {}", path.display());
+    } else {
+        println!("Generated {}", path.display());
+    }
+    println!("Public key: {}", keypair.public());
+    println!("Use it as the key_file of a TCP profile's noise settings, and trust it on");
+    println!(
+        "the remote with: yuha-remote --noise-peer {}",
+        keypair.public()
+    );
+    Ok(())
+}
+
+/// Offer the hosts of `~/.ssh/config` as connection profiles
+fn import_ssh_hosts(config: &mut YuhaConfig) -> Result<()> {
+    let Some(ssh_config) = dirs::home_dir().map(|home| home.join(".ssh").join("config")) else {
+        return Ok(());
+    };
+    let Ok(text) = std::fs::read_to_string(&ssh_config) else {
+        return Ok(());
+    };
+    let hosts = parse_ssh_config(&text);
+    if hosts.is_empty() {
+        return Ok(());
+    }
+
+    println!("Hosts in {}:", ssh_config.display());
+    for (i, host) in hosts.iter().enumerate() {
+        println!("  {}) {}", i + 1, host.alias);
+    }
+    let answer = ask("Hosts to import as profiles (numbers, 'all', or empty for none)")?;
+    let chosen: Vec<&SshHost> = if answer.eq_ignore_ascii_case("all") {
+        hosts.iter().collect()
+    } else {
+        answer
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|number| !number.is_empty())
+            .map(|number| {
+                number
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|n| hosts.get(n.checked_sub(1)?))
+                    .with_context(|| format!("No host numbered '{}'", number))
+            })
+            .collect::<Result<_>>()?
+    };
+
+    let default_user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default();
+    for host in chosen {
+        let ssh = SshConfig {
+            host: host.hostname.clone().unwrap_or_else(|| host.alias.clone()),
+            port: host.port.unwrap_or(config.network.ssh_port),
+            username: host.user.clone().unwrap_or_else(|| default_user.clone()),
+            password: None,
+            key_path: host.identity_file.as_deref().map(expand_home),
+            cert_path: None,
+            cert_principal: None,
+            pkcs11: None,
+            auto_upload_binary: false,
+            algorithms: Default::default(),
+            socket: None,
+        };
+        config.profiles.insert(
+            host.alias.clone(),
+            ConnectionProfile {
+                name: host.alias.clone(),
+                ssh: Some(ssh),
+                local: None,
+                tcp: None,
+                env_vars: HashMap::new(),
+                overrides: HashMap::new(),
+            },
+        );
+    }
+    Ok(())
+}
+
+/// Ask how the remote binary is started over SSH and locally
+fn choose_transports(config: &mut YuhaConfig) -> Result<()> {
+    let upload = confirm("Upload the remote binary to SSH hosts that lack it?");
+    config.client.auto_upload_binary = upload;
+    for ssh in config.profiles.values_mut().filter_map(|p| p.ssh.as_mut()) {
+        ssh.auto_upload_binary = upload;
+    }
+
+    let binary = ask("Remote binary for local sessions (empty for the bundled one)")?;
+    if !binary.is_empty() {
+        config.client.default_binary_path = Some(expand_home(&binary));
+    }
+    Ok(())
+}
+
+/// Read a line answering `prompt`
+fn ask(prompt: &str) -> Result<String> {
+    eprint!("{}: ", prompt);
+    let _ = std::io::stderr().flush();
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+/// `path` with a leading `~/` replaced by the home directory
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// The concrete hosts of an OpenSSH client config
+///
+/// Patterns and `Match` blocks are skipped. As in `ssh`, the first value of
+/// a setting for a host wins.
+fn parse_ssh_config(text: &str) -> Vec<SshHost> {
+    let mut hosts: Vec<SshHost> = Vec::new();
+    // Hosts the current block applies to
+    let mut current = Vec::new();
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = match line.split_once(|c: char| c == '=' || c.is_whitespace()) {
+            Some((key, value)) => (key, value.trim_start_matches(['=', ' ', '\t']).trim()),
+            None => (line, ""),
+        };
+        let value = value.trim_matches('"');
+
+        match key.to_ascii_lowercase().as_str() {
+            "host" => {
+                current.clear();
+                for alias in value.split_whitespace() {
+                    if alias.contains(['*', '?', '!']) {
+                        continue;
+                    }
+                    let index = match hosts.iter().position(|host| host.alias == alias) {
+                        Some(index) => index,
+                        None => {
+                            hosts.push(SshHost {
+                                alias: alias.to_string(),
+                                ..Default::default()
+                            });
+                            hosts.len() - 1
+                        }
+                    };
+                    current.push(index);
+                }
+            }
+            "match" => current.clear(),
+            key => {
+                for &index in &current {
+                    let host = &mut hosts[index];
+                    match key {
+                        "hostname" => set_once(&mut host.hostname, value),
+                        "user" => set_once(&mut host.user, value),
+                        "identityfile" => set_once(&mut host.identity_file, value),
+                        "port" if host.port.is_none() => host.port = value.parse().ok(),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    hosts
+}
+
+fn set_once(setting: &mut Option<String>, value: &str) {
+    if setting.is_none() && !value.is_empty() {
+        *setting = Some(value.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ssh_config() {
+        let hosts = parse_ssh_config(
+            "# comment\n\
+             Host web web-alias\n\
+             \tHostName web.example.com\n\
+             \tUser deploy\n\
+             \tPort 2222\n\
+             \tIdentityFile ~/.ssh/web\n\
+             \tUser ignored\n\
+             Host *.internal !bastion\n\
+             \tUser nobody\n\
+             Match host db\n\
+             \tUser nobody\n\
+             Host db\n\
+             \tHostname=\"10.0.0.5\"\n",
+        );
+
+        let web = SshHost {
+            alias: "web".to_string(),
+            hostname: Some("web.example.com".to_string()),
+            user: Some("deploy".to_string()),
+            port: Some(2222),
+            identity_file: Some("~/.ssh/web".to_string()),
+        };
+        let web_alias = SshHost {
+            alias: "web-alias".to_string(),
+            ..web.clone()
+        };
+        let db = SshHost {
+            alias: "db".to_string(),
+            hostname: Some("10.0.0.5".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(hosts, vec![web, web_alias, db]);
+    }
+}
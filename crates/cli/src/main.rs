@@ -1,10 +1,35 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
-use std::path::PathBuf;
-use tracing::{debug, info};
-use yuha_client::client;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use yuha_client::edit;
+use yuha_client::open::{self, OpenDirection};
+use yuha_client::tail;
+use yuha_client::transport_factory::AnyTransport;
+use yuha_client::{Client, ClientError, client};
+use yuha_core::access_log::{self, AccessEntry, AccessOutcome};
+use yuha_core::checksum::HashAlgorithm;
+use yuha_core::clipboard::ClipboardFormat;
+use yuha_core::error::retry::RetryPolicy;
+use yuha_core::protocol::ResponseItem;
+use yuha_core::protocol::extension;
+use yuha_core::protocol::request_response::{DisplayEnv, Sandbox};
 use yuha_core::{YuhaConfig, config::ConnectionProfile};
 
+mod events;
+mod exit;
+mod init;
+mod scripting;
+mod target;
+#[cfg(any(feature = "tray", test))]
+mod tray;
+
+use events::{Event, EventFormat, Events};
+use scripting::Automations;
+use target::Target;
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
@@ -20,6 +45,23 @@ struct Cli {
     #[arg(short, long)]
     verbose: bool,
 
+    /// Connect even if the remote binary does not match the expected hash
+    #[arg(long, global = true)]
+    allow_modified_remote: bool,
+
+    /// Workspace of the remote server to work in
+    #[arg(long, global = true)]
+    workspace: Option<String>,
+
+    /// Fail with exit code 3 instead of prompting for passwords or
+    /// confirmations
+    #[arg(long, global = true)]
+    non_interactive: bool,
+
+    /// Print lifecycle events of long-running commands on stdout
+    #[arg(long, global = true, value_name = "FORMAT")]
+    events: Option<EventFormat>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -66,16 +108,237 @@ enum Commands {
         #[arg(long)]
         no_daemon: bool,
     },
+    /// Hold a session to a remote open, reconnecting when it drops
+    Connect {
+        /// Remote to connect to: `local`, a profile name, or `[user@]host[:port]`
+        target: String,
+
+        /// Exit once the session reaches this state instead of holding it,
+        /// retrying while the remote is unreachable
+        #[arg(long, value_enum)]
+        wait_until: Option<WaitState>,
+
+        /// Give up waiting after this long, e.g. `30s` (exit code 8)
+        #[arg(long, requires = "wait_until", value_parser = humantime::parse_duration)]
+        timeout: Option<Duration>,
+
+        /// Lua script reacting to the session, e.g. starting forwards for
+        /// URLs copied on the remote
+        #[arg(long, value_name = "FILE", conflicts_with = "wait_until")]
+        script: Option<PathBuf>,
+    },
+    /// Forward a port of the remote to a host reachable from it, for as long
+    /// as the command runs
+    Forward {
+        /// Remote to forward on: `local`, a profile name, or `[user@]host[:port]`
+        target: String,
+
+        /// Port the remote listens on
+        local_port: u16,
+
+        /// Port connections are forwarded to
+        remote_port: u16,
+
+        /// Host connections are forwarded to, as seen from the remote
+        #[arg(long, default_value = "localhost")]
+        remote_host: String,
+    },
+    /// Paste the remote clipboard, transferring copied files into a local directory
+    Paste {
+        /// Remote to paste from: `local`, a profile name, or `[user@]host[:port]`
+        target: String,
+
+        /// Directory that receives pasted files
+        #[arg(short, long, default_value = ".")]
+        dest: PathBuf,
+
+        /// Transfer files without asking, regardless of their size
+        #[arg(short, long)]
+        yes: bool,
+
+        /// Replace local files that already exist
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Launch a GUI application on the remote desktop session
+    Launch {
+        /// Remote to launch on: `local`, a profile name, or `[user@]host[:port]`
+        target: String,
+
+        /// X11 display to use instead of the detected one, e.g. `:0`
+        #[arg(long)]
+        display: Option<String>,
+
+        /// Wayland socket to use instead of the detected one, e.g. `wayland-0`
+        #[arg(long)]
+        wayland_display: Option<String>,
+
+        /// Run as this remote user; the remote server must run as root
+        #[arg(long)]
+        user: Option<String>,
+
+        /// Limit the application to this percentage of one core
+        #[arg(long, value_name = "PERCENT")]
+        cpu_percent: Option<u32>,
+
+        /// Limit the memory of the application
+        #[arg(long, value_name = "BYTES")]
+        memory_bytes: Option<u64>,
+
+        /// Show the application a read-only filesystem
+        #[arg(long)]
+        read_only: bool,
+
+        /// Command and arguments to run
+        #[arg(required = true, trailing_var_arg = true)]
+        command: Vec<String>,
+    },
+    /// Open a remote path with the application handling its extension
+    Open {
+        /// Remote holding the path: `local`, a profile name, or `[user@]host[:port]`
+        target: String,
+
+        /// Path on the remote host, or on this machine with `--upload`
+        path: String,
+
+        /// Download the file and open it on this machine instead of the remote desktop
+        #[arg(short, long, conflicts_with = "upload")]
+        local: bool,
+
+        /// Open a local file on the remote desktop, through a copy uploaded
+        /// into `--remote-staging-dir`
+        #[arg(short, long)]
+        upload: bool,
+
+        /// Existing directory on the remote for copies of uploaded files
+        #[arg(long, default_value = open::DEFAULT_REMOTE_STAGING_DIR, requires = "upload")]
+        remote_staging_dir: String,
+    },
+    /// Edit a remote file with the local editor, uploading each save
+    Edit {
+        /// File to edit as `TARGET:PATH`, where the target is `local`, a
+        /// profile name, or `[user@]host[:port]`
+        spec: String,
+
+        /// Editor command to use instead of `$VISUAL` / `$EDITOR`
+        #[arg(short, long)]
+        editor: Option<String>,
+    },
+    /// Print the last lines of remote files, optionally following them
+    ///
+    /// Lines of several files, from more patterns or wildcards, are printed
+    /// with the time they were read and their file.
+    Tail {
+        /// File to print as `TARGET:PATH`, or only the target when patterns
+        /// follow; the target is `local`, a profile name, or
+        /// `[user@]host[:port]`
+        spec: String,
+
+        /// Remote files to print, as paths or globs with `*` and `?`
+        patterns: Vec<String>,
+
+        /// Number of lines to print
+        #[arg(short = 'n', long, default_value_t = 10)]
+        lines: usize,
+
+        /// Keep printing what is appended to the files
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Compare a local file or tree with a remote one by their hashes
+    ///
+    /// Prints the files that differ and exits with code 9 when there are
+    /// any, e.g. to check a sync before or after it ran.
+    Verify {
+        /// Local file or directory
+        local: PathBuf,
+
+        /// Remote counterpart as `TARGET:PATH`, where the target is `local`,
+        /// a profile name, or `[user@]host[:port]`
+        spec: String,
+
+        /// Hash algorithm: `blake3`, or the cheaper `crc32c` that only
+        /// catches accidental changes
+        #[arg(long, default_value_t = HashAlgorithm::Blake3)]
+        algo: HashAlgorithm,
+    },
+    /// Show the sizes of remote directory trees, computed on the remote
+    Du {
+        /// Directory to scan as `TARGET:PATH`, where the target is `local`, a
+        /// profile name, or `[user@]host[:port]`
+        spec: String,
+
+        /// Also show the directories this many levels below it
+        #[arg(short, long, default_value_t = 1)]
+        depth: u32,
+    },
+    /// Remove a remote file or directory tree
+    Rm {
+        /// Path to remove as `TARGET:PATH`, where the target is `local`, a
+        /// profile name, or `[user@]host[:port]`
+        spec: String,
+
+        /// Move it to the remote's trash instead, to be restored with
+        /// `yuha trash restore`
+        #[arg(long)]
+        trash: bool,
+    },
+    /// Move a remote file or directory tree, replacing a file at the
+    /// destination
+    Mv {
+        /// Path to move as `TARGET:PATH`, where the target is `local`, a
+        /// profile name, or `[user@]host[:port]`
+        spec: String,
+
+        /// Destination on the same remote
+        to: String,
+
+        /// Move a replaced file to the remote's trash instead of losing it
+        #[arg(long)]
+        trash: bool,
+    },
+    /// List or restore what `rm --trash` and `mv --trash` kept on a remote
+    Trash {
+        /// Remote of the trash: `local`, a profile name, or `[user@]host[:port]`
+        target: String,
+
+        #[command(subcommand)]
+        action: TrashAction,
+    },
+    /// Check a remote for the tools integrations rely on
+    Doctor {
+        /// Remote to check: `local`, a profile name, or `[user@]host[:port]`
+        target: String,
+
+        /// Tools to look for instead of the common ones (repeatable)
+        #[arg(short, long = "tool", value_name = "NAME")]
+        tools: Vec<String>,
+    },
     /// Daemon management
     Daemon {
         #[command(subcommand)]
         action: DaemonAction,
     },
+    /// Show request history recorded by the daemon
+    History {
+        #[command(subcommand)]
+        kind: HistoryKind,
+    },
     /// Configuration management
     Config {
         #[command(subcommand)]
         action: ConfigAction,
     },
+    /// Create a config interactively: import SSH hosts, choose how the
+    /// remote binary is started and generate a Noise key for TCP sessions
+    Init {
+        /// Path of the config file, the user config by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Show sessions in the system tray
+    #[cfg(feature = "tray")]
+    Tray,
 }
 
 #[derive(Subcommand)]
@@ -93,9 +356,26 @@ enum DaemonAction {
     /// Stop the daemon
     Stop,
     /// Check daemon status
-    Status,
+    Status {
+        /// Also show the last N requests executed on sessions
+        #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "20")]
+        recent: Option<usize>,
+    },
     /// List active sessions
     Sessions,
+    /// Show the TCP connections a remote server accepted or rejected
+    AccessLog {
+        /// Remote server to ask
+        target: String,
+
+        /// Summarize the connections by source address instead
+        #[arg(short, long)]
+        summary: bool,
+
+        /// Maximum number of connections to show
+        #[arg(short = 'n', long, default_value = "50")]
+        limit: usize,
+    },
     /// Show session details
     Info {
         /// Session ID
@@ -103,6 +383,35 @@ enum DaemonAction {
     },
 }
 
+#[derive(Subcommand)]
+enum HistoryKind {
+    /// URLs that remote sessions asked to open
+    OpenUrl {
+        /// Only show requests from this session name
+        #[arg(short, long)]
+        session: Option<String>,
+
+        /// Maximum number of entries to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum TrashAction {
+    /// List the entries, oldest first
+    List,
+    /// Move an entry back to where it was removed from
+    Restore {
+        /// Entry id, as listed
+        id: String,
+
+        /// Restore to this remote path instead
+        #[arg(long)]
+        to: Option<String>,
+    },
+}
+
 #[derive(Subcommand)]
 enum ConfigAction {
     /// Show current configuration
@@ -131,13 +440,34 @@ enum ConfigAction {
         /// SSH key path
         #[arg(long)]
         key_path: Option<PathBuf>,
+        /// OpenSSH certificate of the key
+        #[arg(long)]
+        cert_path: Option<PathBuf>,
+        /// Principal the certificate must be valid for, when not the username
+        #[arg(long)]
+        cert_principal: Option<String>,
+        /// PKCS#11 provider library of token-backed keys
+        #[arg(long)]
+        pkcs11: Option<String>,
     },
 }
 
+/// State `yuha connect --wait-until` waits for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum WaitState {
+    /// The session is up
+    Connected,
+}
+
 #[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+async fn main() {
+    if let Err(e) = run(Cli::parse()).await {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(exit::code(&e));
+    }
+}
 
+async fn run(cli: Cli) -> Result<()> {
     // Load configuration
     let mut config = if let Some(config_path) = &cli.config {
         YuhaConfig::load_from_file(config_path)?
@@ -147,6 +477,15 @@ async fn main() -> Result<()> {
 
     // Merge with environment variables
     config.merge_with_env();
+    if cli.allow_modified_remote {
+        config.client.allow_modified_remote = true;
+    }
+    if let Some(workspace) = &cli.workspace {
+        config.client.workspace = Some(workspace.clone());
+    }
+    if cli.non_interactive {
+        config.client.non_interactive = true;
+    }
 
     // Validate configuration
     config.validate()?;
@@ -281,12 +620,264 @@ async fn main() -> Result<()> {
                 handle_local_via_daemon(effective_binary_path).await?;
             }
         }
+        Commands::Connect {
+            target: spec,
+            wait_until: Some(WaitState::Connected),
+            timeout,
+            ..
+        } => {
+            let target = Target::parse(spec, &config)?;
+            let events = Events::new(cli.events);
+            let wait = wait_until_connected(spec, &target, &config, events);
+            match timeout {
+                Some(timeout) => {
+                    tokio::time::timeout(*timeout, wait)
+                        .await
+                        .map_err(|_| exit::TimedOut {
+                            waiting_for: format!("{} to connect", spec),
+                            timeout: *timeout,
+                        })??
+                }
+                None => wait.await?,
+            }
+        }
+        Commands::Connect {
+            target: spec,
+            wait_until: None,
+            script,
+            ..
+        } => {
+            let target = Target::parse(spec, &config)?;
+            let events = Events::new(cli.events);
+            let automations = script.as_deref().map(Automations::load).transpose()?;
+            hold_session(spec, &target, &config, events, async |client| {
+                if let Some(automations) = &automations {
+                    return automations.serve(client, spec).await;
+                }
+                loop {
+                    let items = client.poll_data().await?;
+                    grant_polled(client, &items).await?;
+                }
+            })
+            .await?;
+        }
+        Commands::Forward {
+            target: spec,
+            local_port,
+            remote_port,
+            remote_host,
+        } => {
+            let target = Target::parse(spec, &config)?;
+            let events = Events::new(cli.events);
+            hold_session(spec, &target, &config, events, async |client| {
+                forward(client, *local_port, remote_host, *remote_port, events).await
+            })
+            .await?;
+        }
+        Commands::Paste {
+            target,
+            dest,
+            yes,
+            overwrite,
+        } => {
+            let target = Target::parse(target, &config)?;
+            handle_paste(&target, dest, *yes, *overwrite, &config).await?;
+        }
+        Commands::Launch {
+            target,
+            display,
+            wayland_display,
+            user,
+            cpu_percent,
+            memory_bytes,
+            read_only,
+            command,
+        } => {
+            let target = Target::parse(target, &config)?;
+            let client = target.connect(&config).await?;
+            let display_env = DisplayEnv {
+                display: display.clone(),
+                wayland_display: wayland_display.clone(),
+            };
+            let sandbox = Sandbox {
+                user: user.clone(),
+                cpu_percent: *cpu_percent,
+                memory_bytes: *memory_bytes,
+                read_only: *read_only,
+            };
+            let pid = client
+                .launch_app(command.clone(), display_env, sandbox)
+                .await?;
+            println!("{}", pid);
+        }
+        Commands::Open {
+            target,
+            path,
+            upload: true,
+            remote_staging_dir,
+            ..
+        } => {
+            let target = Target::parse(target, &config)?;
+            let client = target.connect(&config).await?;
+            let pid = client
+                .open_local_path(Path::new(path), remote_staging_dir)
+                .await?;
+            println!("{}", pid);
+        }
+        Commands::Open {
+            target,
+            path,
+            local,
+            ..
+        } => {
+            let target = Target::parse(target, &config)?;
+            let client = target.connect(&config).await?;
+            let staging_dir = open::default_staging_dir();
+            let direction = if *local {
+                OpenDirection::Local {
+                    handlers: &config.client.open_handlers,
+                    staging_dir: &staging_dir,
+                }
+            } else {
+                OpenDirection::Remote
+            };
+            let pid = client.open_path(path, direction).await?;
+            println!("{}", pid);
+        }
+        Commands::Edit { spec, editor } => {
+            let (target, path) = target::split_remote_path(spec)?;
+            let client = Target::parse(target, &config)?.connect(&config).await?;
+            let editor = match editor {
+                Some(editor) => editor.split_whitespace().map(str::to_string).collect(),
+                None => edit::default_editor(),
+            };
+            let uploads = client
+                .edit(path, &open::default_staging_dir(), &editor)
+                .await?;
+            println!("Uploaded {} saves of {}", uploads, path);
+        }
+        Commands::Tail {
+            spec,
+            patterns,
+            lines,
+            follow,
+        } => {
+            let (target, patterns) = match patterns.as_slice() {
+                [] => {
+                    let (target, path) = target::split_remote_path(spec)?;
+                    (target, vec![path.to_string()])
+                }
+                _ => (spec.as_str(), patterns.clone()),
+            };
+            let client = Target::parse(target, &config)?.connect(&config).await?;
+            match patterns.as_slice() {
+                [path] if !tail::has_wildcards(path) => {
+                    client
+                        .tail(path, *lines, *follow, &mut std::io::stdout())
+                        .await?
+                }
+                _ => {
+                    client
+                        .tail_files(&patterns, *lines, *follow, |line| {
+                            println!(
+                                "{} {}:{} {}",
+                                humantime::format_rfc3339_millis(line.read_at),
+                                target,
+                                line.path,
+                                line.line
+                            );
+                        })
+                        .await?
+                }
+            }
+        }
+        Commands::Verify { local, spec, algo } => {
+            let (target, path) = target::split_remote_path(spec)?;
+            let client = Target::parse(target, &config)?.connect(&config).await?;
+            let differences = client.compare(local, path, *algo).await?;
+            for difference in &differences {
+                println!("{}", difference);
+            }
+            if !differences.is_empty() {
+                return Err(exit::Mismatch {
+                    differences: differences.len(),
+                }
+                .into());
+            }
+        }
+        Commands::Du { spec, depth } => {
+            use std::io::IsTerminal;
+
+            let (target, path) = target::split_remote_path(spec)?;
+            let client = Target::parse(target, &config)?.connect(&config).await?;
+            let show_progress = std::io::stderr().is_terminal();
+            let usages = client
+                .disk_usage(path, *depth, |entries, size| {
+                    if show_progress {
+                        eprint!("\rScanned {} entries, {}", entries, format_size(size));
+                    }
+                })
+                .await?;
+            if show_progress {
+                eprint!("\r\x1b[K");
+            }
+            for usage in usages {
+                println!("{:>10}  {}", format_size(usage.size), usage.path);
+            }
+        }
+        Commands::Rm { spec, trash } => {
+            let (target, path) = target::split_remote_path(spec)?;
+            let client = Target::parse(target, &config)?.connect(&config).await?;
+            if let Some(entry) = client.remove_path(path, *trash).await? {
+                println!("Trashed {} as {}", entry.original_path, entry.id);
+            }
+        }
+        Commands::Mv { spec, to, trash } => {
+            let (target, from) = target::split_remote_path(spec)?;
+            let client = Target::parse(target, &config)?.connect(&config).await?;
+            if let Some(entry) = client.move_path(from, to, *trash).await? {
+                println!("Trashed {} as {}", entry.original_path, entry.id);
+            }
+        }
+        Commands::Trash { target, action } => {
+            let client = Target::parse(target, &config)?.connect(&config).await?;
+            match action {
+                TrashAction::List => {
+                    for entry in client.list_trash().await? {
+                        println!(
+                            "{}  {}  {}",
+                            entry.id,
+                            humantime::format_rfc3339_seconds(entry.trashed_at),
+                            entry.original_path
+                        );
+                    }
+                }
+                TrashAction::Restore { id, to } => {
+                    client.restore_path(id, to.clone()).await?;
+                }
+            }
+        }
+        Commands::Doctor { target, tools } => {
+            let target = Target::parse(target, &config)?;
+            handle_doctor(&target, tools, &config).await?;
+        }
         Commands::Daemon { action } => {
-            handle_daemon_command(action).await?;
+            handle_daemon_command(action, &config).await?;
+        }
+        Commands::History { kind } => {
+            handle_history_command(kind).await?;
         }
         Commands::Config { action } => {
             handle_config_command(action, &config).await?;
         }
+        Commands::Init { output } => {
+            init::run(output.as_deref(), config.client.non_interactive)?;
+        }
+        #[cfg(feature = "tray")]
+        Commands::Tray => {
+            ensure_daemon_running().await?;
+            tokio::task::block_in_place(|| tray::run(config))?;
+        }
     }
 
     Ok(())
@@ -306,16 +897,31 @@ fn init_logging(config: &YuhaConfig, verbose: bool) -> Result<()> {
 
     match config.logging.format {
         yuha_core::LogFormat::Json => {
-            fmt().json().with_env_filter(env_filter).init();
+            fmt()
+                .json()
+                .with_writer(std::io::stderr)
+                .with_env_filter(env_filter)
+                .init();
         }
         yuha_core::LogFormat::Compact => {
-            fmt().compact().with_env_filter(env_filter).init();
+            fmt()
+                .compact()
+                .with_writer(std::io::stderr)
+                .with_env_filter(env_filter)
+                .init();
         }
         yuha_core::LogFormat::Pretty => {
-            fmt().pretty().with_env_filter(env_filter).init();
+            fmt()
+                .pretty()
+                .with_writer(std::io::stderr)
+                .with_env_filter(env_filter)
+                .init();
         }
         yuha_core::LogFormat::Full => {
-            fmt().with_env_filter(env_filter).init();
+            fmt()
+                .with_writer(std::io::stderr)
+                .with_env_filter(env_filter)
+                .init();
         }
     }
 
@@ -327,7 +933,7 @@ async fn handle_config_command(action: &ConfigAction, config: &YuhaConfig) -> Re
     match action {
         ConfigAction::Show => {
             println!("Current Configuration:");
-            println!("{}", toml::to_string_pretty(config)?);
+            println!("{}", config.to_redacted_string()?);
         }
         ConfigAction::Init { output } => {
             let default_config = YuhaConfig::default();
@@ -350,6 +956,9 @@ async fn handle_config_command(action: &ConfigAction, config: &YuhaConfig) -> Re
             port,
             username,
             key_path,
+            cert_path,
+            cert_principal,
+            pkcs11,
         } => {
             if host.is_some() {
                 let ssh_config = yuha_core::config::SshConfig {
@@ -358,13 +967,19 @@ async fn handle_config_command(action: &ConfigAction, config: &YuhaConfig) -> Re
                     username: username.clone().unwrap_or_default(),
                     password: None,
                     key_path: key_path.clone(),
+                    cert_path: cert_path.clone(),
+                    cert_principal: cert_principal.clone(),
+                    pkcs11: pkcs11.clone(),
                     auto_upload_binary: false,
+                    algorithms: Default::default(),
+                    socket: None,
                 };
 
                 let profile = ConnectionProfile {
                     name: name.clone(),
                     ssh: Some(ssh_config),
                     local: None,
+                    tcp: None,
                     env_vars: std::collections::HashMap::new(),
                     overrides: std::collections::HashMap::new(),
                 };
@@ -380,12 +995,431 @@ async fn handle_config_command(action: &ConfigAction, config: &YuhaConfig) -> Re
     Ok(())
 }
 
+/// Handle history commands
+async fn handle_history_command(kind: &HistoryKind) -> Result<()> {
+    use yuha_client::daemon_client::DaemonClient;
+    use yuha_client::daemon_protocol::{HistoryQuery, OpenUrlOutcome};
+
+    match kind {
+        HistoryKind::OpenUrl { session, limit } => {
+            let mut client = DaemonClient::connect(None).await?;
+            let records = client
+                .open_url_history(HistoryQuery {
+                    session: session.clone(),
+                    limit: Some(*limit),
+                })
+                .await?;
+
+            if records.is_empty() {
+                println!("No URL open requests recorded");
+                return Ok(());
+            }
+
+            println!("{:<20} {:<20} {:<10} URL", "Time", "Session", "Outcome");
+            println!("{}", "-".repeat(80));
+            for record in records {
+                let outcome = match &record.outcome {
+                    OpenUrlOutcome::Opened => "opened",
+                    OpenUrlOutcome::Failed { .. } => "failed",
+                };
+                println!(
+                    "{:<20} {:<20} {:<10} {}",
+                    humantime::format_rfc3339_seconds(record.requested_at),
+                    record.session_name,
+                    outcome,
+                    record.url
+                );
+                if let OpenUrlOutcome::Failed { reason } = &record.outcome {
+                    println!("  {}", reason);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print the last `limit` requests executed on sessions
+async fn print_request_log(
+    client: &mut yuha_client::daemon_client::DaemonClient,
+    limit: usize,
+) -> Result<()> {
+    use yuha_client::daemon_protocol::{HistoryQuery, RequestOutcome};
+
+    let records = client
+        .request_log(HistoryQuery {
+            session: None,
+            limit: Some(limit),
+        })
+        .await?;
+    println!();
+    if records.is_empty() {
+        println!("No requests recorded");
+        return Ok(());
+    }
+
+    println!(
+        "{:<20} {:<20} {:<18} {:>9} {:>9} {:>9} Outcome",
+        "Time", "Session", "Command", "Sent", "Received", "Duration"
+    );
+    println!("{}", "-".repeat(100));
+    for record in records {
+        let outcome = match &record.outcome {
+            RequestOutcome::Succeeded => "ok",
+            RequestOutcome::Failed { .. } => "failed",
+        };
+        println!(
+            "{:<20} {:<20} {:<18} {:>9} {:>9} {:>7}ms {}",
+            humantime::format_rfc3339_seconds(record.started_at),
+            record.session_name,
+            record.command,
+            record.request_bytes,
+            record.response_bytes,
+            record.duration.as_millis(),
+            outcome
+        );
+        if let RequestOutcome::Failed { reason } = &record.outcome {
+            println!("  {}", reason);
+        }
+    }
+    Ok(())
+}
+
+/// Print the `limit` most recent connections of an access log
+fn print_access_log(entries: &[AccessEntry], limit: usize) {
+    if entries.is_empty() {
+        println!("No connections recorded");
+        return;
+    }
+
+    println!(
+        "{:<20} {:<40} {:<9} {:>10}",
+        "Time", "Source", "Outcome", "Duration"
+    );
+    println!("{}", "-".repeat(82));
+    for entry in entries.iter().take(limit) {
+        let outcome = match &entry.outcome {
+            AccessOutcome::Accepted => "accepted",
+            AccessOutcome::Rejected { .. } => "rejected",
+        };
+        println!(
+            "{:<20} {:<40} {:<9} {:>10}",
+            humantime::format_rfc3339_seconds(entry.connected_at),
+            entry.source,
+            outcome,
+            humantime::format_duration(Duration::from_secs(entry.duration.as_secs())).to_string()
+        );
+        if let AccessOutcome::Rejected { reason } = &entry.outcome {
+            println!("  {}", reason);
+        }
+    }
+}
+
+/// Print the connections of an access log grouped by source address
+fn print_access_summary(entries: &[AccessEntry]) {
+    let summaries = access_log::summarize(entries);
+    if summaries.is_empty() {
+        println!("No connections recorded");
+        return;
+    }
+
+    println!(
+        "{:<40} {:>8} {:>8} {:>12} Last seen",
+        "Source", "Accepted", "Rejected", "Connected"
+    );
+    println!("{}", "-".repeat(92));
+    for summary in summaries {
+        println!(
+            "{:<40} {:>8} {:>8} {:>12} {}",
+            summary.source,
+            summary.accepted,
+            summary.rejected,
+            humantime::format_duration(Duration::from_secs(summary.connected.as_secs()))
+                .to_string(),
+            humantime::format_rfc3339_seconds(summary.last_seen)
+        );
+    }
+}
+
+/// Paste the remote clipboard content
+async fn handle_paste(
+    target: &Target,
+    dest: &std::path::Path,
+    yes: bool,
+    overwrite: bool,
+    config: &YuhaConfig,
+) -> Result<()> {
+    let client = target.connect(config).await?;
+
+    let Some(item) = client
+        .get_clipboard_data(vec![ClipboardFormat::FileList, ClipboardFormat::Text])
+        .await?
+    else {
+        println!("Remote clipboard is empty");
+        return Ok(());
+    };
+
+    if !item.format.is_file_list() {
+        println!("{}", String::from_utf8_lossy(&item.data));
+        return Ok(());
+    }
+
+    let threshold = config.client.paste_confirm_bytes;
+    let mut refused = None;
+    let pasted = client
+        .paste_file_list(&item, dest, overwrite, |plan| {
+            if yes || plan.total_size <= threshold {
+                return true;
+            }
+            let prompt = format!(
+                "Transfer {} files ({}) into {}?",
+                plan.files.len(),
+                format_size(plan.total_size),
+                dest.display()
+            );
+            if config.client.non_interactive {
+                refused = Some(prompt);
+                return false;
+            }
+            confirm(&prompt)
+        })
+        .await?;
+    if let Some(prompt) = refused {
+        return Err(ClientError::InteractionRequired { prompt }.into());
+    }
+
+    match pasted {
+        Some(item) => {
+            for path in item.paths()? {
+                println!("{}", path.display());
+            }
+        }
+        None => println!("Paste cancelled"),
+    }
+    Ok(())
+}
+
+/// Keep a session to `target` up while `serve` uses it, reconnecting with
+/// backoff when it drops
+///
+/// `serve` runs on each new session until it fails. The first connection
+/// is not retried, and reconnecting gives up after `client.max_retries`
+/// failed attempts in a row.
+async fn hold_session(
+    spec: &str,
+    target: &Target,
+    config: &YuhaConfig,
+    events: Events,
+    mut serve: impl AsyncFnMut(&Client<AnyTransport>) -> Result<()>,
+) -> Result<()> {
+    let policy =
+        RetryPolicy::new(config.client.max_retries).with_initial_delay(Duration::from_secs(1));
+    let mut client = target.connect(config).await?;
+    loop {
+        events.emit(Event::Connected { target: spec });
+        let error = match serve(&client).await {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        warn!("Session to {} dropped: {:#}", spec, error);
+
+        let mut attempt = 0;
+        let mut error = error;
+        client = loop {
+            if attempt >= policy.max_attempts {
+                return Err(error.context(format!("Gave up reconnecting to {}", spec)));
+            }
+            attempt += 1;
+            let delay = policy.delay_for_attempt(attempt);
+            events.emit(Event::Reconnecting {
+                target: spec,
+                attempt,
+                delay_ms: delay.as_millis() as u64,
+                error: format!("{:#}", error),
+            });
+            tokio::time::sleep(delay).await;
+            match target.connect(config).await {
+                Ok(client) => break client,
+                Err(e) => error = e,
+            }
+        };
+    }
+}
+
+/// Connect to `target` and drop the session once it is up, retrying while
+/// the remote is unreachable
+async fn wait_until_connected(
+    spec: &str,
+    target: &Target,
+    config: &YuhaConfig,
+    events: Events,
+) -> Result<()> {
+    let policy = RetryPolicy::default()
+        .with_initial_delay(Duration::from_secs(1))
+        .with_max_delay(Duration::from_secs(10));
+    let mut attempt = 0;
+    loop {
+        let error = match target.connect(config).await {
+            Ok(_client) => {
+                events.emit(Event::Connected { target: spec });
+                return Ok(());
+            }
+            Err(e) if exit::code(&e) == exit::CONNECTION => e,
+            Err(e) => return Err(e),
+        };
+        attempt += 1;
+        let delay = policy.delay_for_attempt(attempt);
+        debug!("{} not reachable yet: {:#}", spec, error);
+        events.emit(Event::Reconnecting {
+            target: spec,
+            attempt,
+            delay_ms: delay.as_millis() as u64,
+            error: format!("{:#}", error),
+        });
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Forward `local_port` of the remote to `remote_host:remote_port`, reporting
+/// the data coming back until the session fails
+async fn forward(
+    client: &Client<AnyTransport>,
+    local_port: u16,
+    remote_host: &str,
+    remote_port: u16,
+    events: Events,
+) -> Result<()> {
+    client
+        .start_port_forward(local_port, remote_host.to_string(), remote_port)
+        .await?;
+    events.emit(Event::ForwardOpened {
+        local_port,
+        remote_host,
+        remote_port,
+    });
+
+    let flow_control = client.extensions().contains(&extension::FLOW_CONTROL.id);
+    let mut totals: HashMap<u32, u64> = HashMap::new();
+    loop {
+        let mut consumed: HashMap<u32, u32> = HashMap::new();
+        let mut closed = Vec::new();
+        for item in client.poll_data().await? {
+            match item {
+                ResponseItem::PortForwardData {
+                    connection_id,
+                    data,
+                } => {
+                    *consumed.entry(connection_id).or_default() += data.len() as u32;
+                }
+                ResponseItem::CloseConnection { connection_id } => closed.push(connection_id),
+                _ => {}
+            }
+        }
+        for (connection_id, bytes) in consumed {
+            let total = totals.entry(connection_id).or_default();
+            *total += bytes as u64;
+            events.emit(Event::BytesTransferred {
+                connection_id,
+                bytes: bytes as u64,
+                total: *total,
+            });
+            // Data the remote mirrors to the client counts against its credit
+            if flow_control {
+                client.grant_credit(connection_id, bytes).await?;
+            }
+        }
+        for connection_id in closed {
+            totals.remove(&connection_id);
+        }
+    }
+}
+
+/// Grant back the credit of the forwarded data among `items`, which the
+/// caller consumed; without flow control there is nothing to grant
+async fn grant_polled(client: &Client<AnyTransport>, items: &[ResponseItem]) -> Result<()> {
+    if !client.extensions().contains(&extension::FLOW_CONTROL.id) {
+        return Ok(());
+    }
+    let mut consumed: HashMap<u32, u32> = HashMap::new();
+    for item in items {
+        if let ResponseItem::PortForwardData {
+            connection_id,
+            data,
+        } = item
+        {
+            *consumed.entry(*connection_id).or_default() += data.len() as u32;
+        }
+    }
+    for (connection_id, bytes) in consumed {
+        client.grant_credit(connection_id, bytes).await?;
+    }
+    Ok(())
+}
+
+/// Report the tools found on the remote, failing when any is missing
+async fn handle_doctor(target: &Target, tools: &[String], config: &YuhaConfig) -> Result<()> {
+    let client = target.connect(config).await?;
+    let probed = client.probe_tools(tools.to_vec()).await?;
+
+    let width = probed.iter().map(|tool| tool.name.len()).max().unwrap_or(0);
+    let mut missing = Vec::new();
+    for tool in &probed {
+        match &tool.path {
+            Some(path) => println!(
+                "ok       {:width$}  {}  ({})",
+                tool.name,
+                tool.version.as_deref().unwrap_or("unknown version"),
+                path
+            ),
+            None => {
+                println!("missing  {:width$}", tool.name);
+                missing.push(tool.name.as_str());
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        anyhow::bail!("Missing on the remote: {}", missing.join(", "));
+    }
+    Ok(())
+}
+
+/// Ask a yes/no question on the terminal, defaulting to no
+fn confirm(prompt: &str) -> bool {
+    use std::io::Write;
+
+    eprint!("{} [y/N] ", prompt);
+    let _ = std::io::stderr().flush();
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).is_ok()
+        && matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// Format a byte count for display
+fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
 /// Describe a connection profile
 fn describe_profile(profile: &ConnectionProfile) -> String {
     if let Some(ssh) = &profile.ssh {
         format!("SSH {}@{}:{}", ssh.username, ssh.host, ssh.port)
     } else if let Some(local) = &profile.local {
         format!("Local {}", local.binary_path.display())
+    } else if let Some(tcp) = &profile.tcp {
+        format!("TCP {}:{}", tcp.host, tcp.port)
     } else {
         "Unknown".to_string()
     }
@@ -566,7 +1600,7 @@ async fn ensure_daemon_running() -> Result<()> {
 }
 
 /// Handle daemon subcommands
-async fn handle_daemon_command(action: &DaemonAction) -> Result<()> {
+async fn handle_daemon_command(action: &DaemonAction, config: &YuhaConfig) -> Result<()> {
     use yuha_client::daemon_client::DaemonClient;
 
     match action {
@@ -607,7 +1641,7 @@ async fn handle_daemon_command(action: &DaemonAction) -> Result<()> {
             client.shutdown().await?;
             println!("Daemon shutdown requested");
         }
-        DaemonAction::Status => {
+        DaemonAction::Status { recent } => {
             match DaemonClient::connect(None).await {
                 Ok(mut client) => {
                     if client.ping().await? {
@@ -616,6 +1650,10 @@ async fn handle_daemon_command(action: &DaemonAction) -> Result<()> {
                         // Show session count
                         let sessions = client.list_sessions().await?;
                         println!("Active sessions: {}", sessions.len());
+
+                        if let Some(limit) = recent {
+                            print_request_log(&mut client, *limit).await?;
+                        }
                     } else {
                         println!("Daemon is not responding properly");
                     }
@@ -650,6 +1688,19 @@ async fn handle_daemon_command(action: &DaemonAction) -> Result<()> {
                 }
             }
         }
+        DaemonAction::AccessLog {
+            target,
+            summary,
+            limit,
+        } => {
+            let client = Target::parse(target, config)?.connect(config).await?;
+            let entries = client.get_access_log().await?;
+            if *summary {
+                print_access_summary(&entries);
+            } else {
+                print_access_log(&entries, *limit);
+            }
+        }
         DaemonAction::Info { session_id } => {
             let mut client = DaemonClient::connect(None).await?;
             let session_id: yuha_core::session::SessionId = session_id.parse()?;
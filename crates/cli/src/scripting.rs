@@ -0,0 +1,327 @@
+//! # Automation Scripts
+//!
+//! `yuha connect --script FILE` runs a Lua script alongside the session it
+//! holds, so automations are written without recompiling. The script
+//! registers handlers with `yuha.on(event, handler)`:
+//!
+//! - `connected`: the session is up, again after every reconnect; called
+//!   with the target
+//! - `clipboard`: text was copied on the remote; called with the text
+//!
+//! Handlers act through `yuha.run(program, args...)`, starting a local
+//! command, and `yuha.forward(local_port, remote_host, remote_port)`,
+//! starting a port forward on the session. `yuha.match(text, regex)` returns
+//! the captures of a regular expression, or the whole match without groups,
+//! and `yuha.log(message)` writes to the log.
+//!
+//! ```lua
+//! yuha.on("connected", function(target)
+//!   yuha.run("notify-send", "yuha", "Connected to " .. target)
+//! end)
+//!
+//! yuha.on("clipboard", function(text)
+//!   local port = yuha.match(text, [[^https?://localhost:(\d+)]])
+//!   if port then
+//!     yuha.forward(tonumber(port), "localhost", tonumber(port))
+//!   end
+//! end)
+//! ```
+//!
+//! Actions are carried out once the handler returns. A failing handler or
+//! action is logged and does not end the session.
+
+use anyhow::{Context, Result, anyhow};
+use mlua::{Function, Lua, Table, Variadic};
+use regex::Regex;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::path::Path;
+use std::process::Stdio;
+use std::rc::Rc;
+use tokio::process::Command;
+use tracing::{info, warn};
+use yuha_client::Client;
+use yuha_client::transport_factory::AnyTransport;
+use yuha_core::protocol::ResponseItem;
+
+/// Registry entry mapping event names to their handlers
+const HANDLERS: &str = "yuha.handlers";
+
+/// Events a script may handle
+const EVENTS: &[&str] = &["connected", "clipboard"];
+
+/// Something a handler asked for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Start a local command
+    Run { program: String, args: Vec<String> },
+    /// Start a port forward on the session
+    Forward {
+        local_port: u16,
+        remote_host: String,
+        remote_port: u16,
+    },
+}
+
+/// A loaded script and the actions its handlers asked for
+pub struct Automations {
+    lua: Lua,
+    actions: Rc<RefCell<Vec<Action>>>,
+}
+
+impl Automations {
+    /// Load and run the script at `path`, registering its handlers
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read script {}", path.display()))?;
+        Self::new(&source, &path.display().to_string())
+    }
+
+    fn new(source: &str, name: &str) -> Result<Self> {
+        let lua = Lua::new();
+        let actions = Rc::new(RefCell::new(Vec::new()));
+        install(&lua, &actions).map_err(|e| anyhow!("Failed to set up scripting: {}", e))?;
+        lua.load(source)
+            .set_name(name)
+            .exec()
+            .map_err(|e| anyhow!("Failed to run script {}: {}", name, e))?;
+        Ok(Self { lua, actions })
+    }
+
+    pub fn connected(&self, target: &str) -> Vec<Action> {
+        self.dispatch("connected", target)
+    }
+
+    pub fn clipboard(&self, text: &str) -> Vec<Action> {
+        self.dispatch("clipboard", text)
+    }
+
+    /// Call the handlers of `event` with `arg` in the order they were
+    /// registered, returning the actions they asked for
+    fn dispatch(&self, event: &str, arg: &str) -> Vec<Action> {
+        let handlers = self
+            .lua
+            .named_registry_value::<Table>(HANDLERS)
+            .and_then(|handlers| handlers.get::<_, Option<Table>>(event));
+        match handlers {
+            Ok(Some(handlers)) => {
+                for handler in handlers.sequence_values::<Function>() {
+                    if let Err(e) = handler.and_then(|handler| handler.call::<_, ()>(arg)) {
+                        warn!("The {} handler of the script failed: {}", event, e);
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to look up the {} handlers: {}", event, e),
+        }
+        self.actions.take()
+    }
+
+    /// React to the session of `client` to `target` until it fails
+    pub async fn serve(&self, client: &Client<AnyTransport>, target: &str) -> Result<()> {
+        // A forward is started once per session, however often it is asked for
+        let mut forwards = HashSet::new();
+        perform(client, self.connected(target), &mut forwards).await;
+        loop {
+            let items = client.poll_data().await?;
+            for item in &items {
+                if let ResponseItem::ClipboardContent { content } = item {
+                    perform(client, self.clipboard(content), &mut forwards).await;
+                }
+            }
+            crate::grant_polled(client, &items).await?;
+        }
+    }
+}
+
+/// Define the `yuha` table of the script
+fn install(lua: &Lua, actions: &Rc<RefCell<Vec<Action>>>) -> mlua::Result<()> {
+    lua.set_named_registry_value(HANDLERS, lua.create_table()?)?;
+    let yuha = lua.create_table()?;
+
+    yuha.set(
+        "on",
+        lua.create_function(|lua, (event, handler): (String, Function)| {
+            if !EVENTS.contains(&event.as_str()) {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "Unknown event {}, expected one of {}",
+                    event,
+                    EVENTS.join(", ")
+                )));
+            }
+            let handlers: Table = lua.named_registry_value(HANDLERS)?;
+            let list = match handlers.get::<_, Option<Table>>(event.as_str())? {
+                Some(list) => list,
+                None => {
+                    let list = lua.create_table()?;
+                    handlers.set(event, list.clone())?;
+                    list
+                }
+            };
+            list.push(handler)
+        })?,
+    )?;
+
+    let queue = actions.clone();
+    yuha.set(
+        "run",
+        lua.create_function(move |_, (program, args): (String, Variadic<String>)| {
+            queue.borrow_mut().push(Action::Run {
+                program,
+                args: args.into_iter().collect(),
+            });
+            Ok(())
+        })?,
+    )?;
+
+    let queue = actions.clone();
+    yuha.set(
+        "forward",
+        lua.create_function(
+            move |_, (local_port, remote_host, remote_port): (u16, String, u16)| {
+                queue.borrow_mut().push(Action::Forward {
+                    local_port,
+                    remote_host,
+                    remote_port,
+                });
+                Ok(())
+            },
+        )?,
+    )?;
+
+    yuha.set(
+        "match",
+        lua.create_function(|_, (text, pattern): (String, String)| {
+            let regex = Regex::new(&pattern).map_err(mlua::Error::external)?;
+            let Some(captures) = regex.captures(&text) else {
+                return Ok(Variadic::new());
+            };
+            let groups: Variadic<Option<String>> = if captures.len() == 1 {
+                Variadic::from_iter([Some(captures[0].to_string())])
+            } else {
+                captures
+                    .iter()
+                    .skip(1)
+                    .map(|group| group.map(|group| group.as_str().to_string()))
+                    .collect()
+            };
+            Ok(groups)
+        })?,
+    )?;
+
+    yuha.set(
+        "log",
+        lua.create_function(|_, message: String| {
+            info!("{}", message);
+            Ok(())
+        })?,
+    )?;
+
+    lua.globals().set("yuha", yuha)
+}
+
+/// Carry out `actions` on the session of `client`
+async fn perform(client: &Client<AnyTransport>, actions: Vec<Action>, forwards: &mut HashSet<u16>) {
+    for action in actions {
+        match action {
+            Action::Run { program, args } => {
+                let spawned = Command::new(&program)
+                    .args(&args)
+                    .stdin(Stdio::null())
+                    // Stdout carries the events of `--events`
+                    .stdout(Stdio::null())
+                    .spawn();
+                match spawned {
+                    Ok(mut child) => {
+                        tokio::spawn(async move {
+                            match child.wait().await {
+                                Ok(status) if !status.success() => {
+                                    warn!("Script command {} failed with {}", program, status)
+                                }
+                                Ok(_) => {}
+                                Err(e) => warn!("Failed to wait for {}: {}", program, e),
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Failed to run script command {}: {}", program, e),
+                }
+            }
+            Action::Forward {
+                local_port,
+                remote_host,
+                remote_port,
+            } => {
+                if !forwards.insert(local_port) {
+                    continue;
+                }
+                if let Err(e) = client
+                    .start_port_forward(local_port, remote_host.clone(), remote_port)
+                    .await
+                {
+                    forwards.remove(&local_port);
+                    warn!(
+                        "Failed to forward port {} to {}:{}: {}",
+                        local_port, remote_host, remote_port, e
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handlers_queue_actions() {
+        let automations = Automations::new(
+            r#"
+            yuha.on("connected", function(target)
+              yuha.run("notify-send", "yuha", "Connected to " .. target)
+            end)
+            yuha.on("clipboard", function(text)
+              local port = yuha.match(text, [[^http://localhost:(\d+)]])
+              if port then
+                yuha.forward(tonumber(port), "localhost", tonumber(port))
+              end
+            end)
+            yuha.on("clipboard", function(text)
+              error("broken handler")
+            end)
+            "#,
+            "test.lua",
+        )
+        .unwrap();
+
+        assert_eq!(
+            automations.connected("work"),
+            [Action::Run {
+                program: "notify-send".to_string(),
+                args: vec!["yuha".to_string(), "Connected to work".to_string()],
+            }]
+        );
+        // The failing handler does not drop the actions of the others
+        assert_eq!(
+            automations.clipboard("http://localhost:5173/index.html"),
+            [Action::Forward {
+                local_port: 5173,
+                remote_host: "localhost".to_string(),
+                remote_port: 5173,
+            }]
+        );
+        assert_eq!(automations.clipboard("no url here"), []);
+    }
+
+    #[test]
+    fn test_script_errors() {
+        assert!(Automations::new(r#"yuha.on("copied", print)"#, "test.lua").is_err());
+        assert!(Automations::new("yuha.on(", "test.lua").is_err());
+        let automations = Automations::new(
+            r#"yuha.on("clipboard", function(text) yuha.match(text, "(") end)"#,
+            "test.lua",
+        )
+        .unwrap();
+        assert_eq!(automations.clipboard("text"), []);
+    }
+}
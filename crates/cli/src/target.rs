@@ -0,0 +1,387 @@
+//! Connection targets for commands that talk to a remote
+//!
+//! A target is written as `local`, the name of a configured profile, or an
+//! SSH destination `[user@]host[:port]`. A path on it is written
+//! `TARGET:PATH`.
+
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use std::sync::Arc;
+use yuha_client::Client;
+use yuha_client::credentials::{
+    CredentialsProvider, EnvCredentials, Fallback, NonInteractiveCredentials, TerminalCredentials,
+};
+use yuha_client::server_requests::BrowserHandler;
+use yuha_client::transport_factory::{AnyTransport, ClientTransportFactory};
+use yuha_core::YuhaConfig;
+use yuha_core::config::ConnectionProfile;
+use yuha_core::transport::{TransportBuilder, TransportConfig, TransportType};
+
+/// Private keys tried, in order, when an SSH target has no configured key
+const DEFAULT_KEY_FILES: &[&str] = &["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// Where a command connects to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Local,
+    Profile(String),
+    Ssh {
+        username: Option<String>,
+        host: String,
+        port: Option<u16>,
+    },
+}
+
+impl Target {
+    /// Parse a target, preferring configured profile names over SSH destinations
+    pub fn parse(spec: &str, config: &YuhaConfig) -> Result<Self> {
+        if spec == "local" {
+            return Ok(Target::Local);
+        }
+        if config.profiles.contains_key(spec) {
+            return Ok(Target::Profile(spec.to_string()));
+        }
+
+        let (username, destination) = match spec.split_once('@') {
+            Some((username, destination)) => (Some(username.to_string()), destination),
+            None => (None, spec),
+        };
+        let (host, port) = match destination.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                Some(
+                    port.parse()
+                        .with_context(|| format!("Invalid port in target '{}'", spec))?,
+                ),
+            ),
+            None => (destination, None),
+        };
+        if host.is_empty() {
+            anyhow::bail!("Missing host in target '{}'", spec);
+        }
+
+        Ok(Target::Ssh {
+            username,
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    /// Build the transport configuration for this target
+    pub fn transport_config(&self, config: &YuhaConfig) -> Result<TransportConfig> {
+        let mut transport_config = self.base_transport_config(config)?;
+        transport_config.general.read_buffer = config.network.read_buffer.clone();
+        transport_config.general.keepalive = config.network.keepalive.clone();
+        transport_config.general.child_env = config.client.child_env.clone();
+        // Sockets a profile does not tune get those of the network section
+        if let Some(ssh) = &mut transport_config.ssh {
+            ssh.socket = Some(ssh.socket_options(&config.network.socket));
+        }
+        if let Some(tcp) = &mut transport_config.tcp {
+            tcp.socket
+                .get_or_insert_with(|| config.network.socket.clone());
+        }
+        Ok(transport_config)
+    }
+
+    fn base_transport_config(&self, config: &YuhaConfig) -> Result<TransportConfig> {
+        match self {
+            Target::Local => local_transport_config(config),
+            Target::Profile(name) => {
+                let profile = config
+                    .get_profile(name)
+                    .with_context(|| format!("Profile '{}' not found", name))?;
+                profile_transport_config(profile)
+            }
+            Target::Ssh {
+                username,
+                host,
+                port,
+            } => {
+                let username = match username {
+                    Some(username) => username.clone(),
+                    None => std::env::var("USER")
+                        .or_else(|_| std::env::var("USERNAME"))
+                        .context("No username in target and $USER is not set")?,
+                };
+                let mut builder = TransportBuilder::ssh()
+                    .host(host)
+                    .port(port.unwrap_or(config.network.ssh_port))
+                    .username(username);
+                if config.client.auto_upload_binary {
+                    builder = builder.auto_upload_binary();
+                }
+                if let Some(key) = default_key_file() {
+                    builder = builder.key_file(key);
+                }
+                Ok(builder.build()?)
+            }
+        }
+    }
+
+    /// Connect a client to this target
+    pub async fn connect(&self, config: &YuhaConfig) -> Result<Client<AnyTransport>> {
+        let credentials: Arc<dyn CredentialsProvider> = if config.client.non_interactive {
+            Arc::new(Fallback(EnvCredentials, NonInteractiveCredentials))
+        } else {
+            Arc::new(Fallback(EnvCredentials, TerminalCredentials))
+        };
+        let transport = ClientTransportFactory::create_transport_with_credentials(
+            &self.transport_config(config)?,
+            credentials,
+        )?;
+        // Every poll loop of the CLI grants back the forwarded data it
+        // consumed, and streamed responses are granted by the client
+        let mut client = Client::new(transport)
+            .allow_modified_binary(config.client.allow_modified_remote)
+            .with_flow_control()
+            .with_msgpack();
+        if let Some(hash) = &config.client.remote_binary_hash {
+            client = client.with_binary_hash(hash);
+        }
+        if let Some(workspace) = &config.client.workspace {
+            client = client.with_workspace(workspace);
+        }
+        // Scripts have nobody to look at the browser
+        if !config.client.non_interactive {
+            client = client.with_server_request_handler(BrowserHandler);
+        }
+        client.connect().await?;
+        Ok(client)
+    }
+}
+
+/// Split `TARGET:PATH` into the target and the path
+///
+/// A colon followed by digits and another colon separates an SSH port, so
+/// `host:22:/etc/hosts` is a path on port 22.
+pub fn split_remote_path(spec: &str) -> Result<(&str, &str)> {
+    let mut start = 0;
+    while let Some(found) = spec[start..].find(':') {
+        let colon = start + found;
+        let rest = &spec[colon + 1..];
+        let is_port = rest
+            .split_once(':')
+            .is_some_and(|(port, _)| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()));
+        if !is_port {
+            if colon == 0 || rest.is_empty() {
+                break;
+            }
+            return Ok((&spec[..colon], rest));
+        }
+        start = colon + 1;
+    }
+    anyhow::bail!("Expected TARGET:PATH, got '{}'", spec)
+}
+
+fn local_transport_config(config: &YuhaConfig) -> Result<TransportConfig> {
+    let binary_path = config
+        .client
+        .default_binary_path
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(yuha_client::get_remote_binary_path()));
+    Ok(TransportBuilder::local()
+        .binary_path(binary_path)
+        .args(vec!["--stdio".to_string()])
+        .build()?)
+}
+
+fn profile_transport_config(profile: &ConnectionProfile) -> Result<TransportConfig> {
+    if let Some(ssh) = &profile.ssh {
+        let mut builder = TransportBuilder::ssh()
+            .host(&ssh.host)
+            .port(ssh.port)
+            .username(&ssh.username);
+        if ssh.auto_upload_binary {
+            builder = builder.auto_upload_binary();
+        }
+        builder = builder.algorithms(ssh.algorithms.clone());
+        if let Some(password) = &ssh.password {
+            builder = builder.password(password);
+        }
+        if let Some(key) = ssh.key_path.clone().or_else(default_key_file) {
+            builder = builder.key_file(key);
+        }
+        if let Some(cert) = &ssh.cert_path {
+            builder = builder.cert_file(cert);
+        }
+        if let Some(principal) = &ssh.cert_principal {
+            builder = builder.cert_principal(principal);
+        }
+        if let Some(provider) = &ssh.pkcs11 {
+            builder = builder.pkcs11(provider);
+        }
+        if let Some(socket) = &ssh.socket {
+            builder = builder.socket(socket.clone());
+        }
+        Ok(builder.build()?)
+    } else if let Some(local) = &profile.local {
+        Ok(TransportBuilder::local()
+            .binary_path(local.binary_path.clone())
+            .args(local.args.clone())
+            .build()?)
+    } else if let Some(tcp) = &profile.tcp {
+        let config = TransportConfig {
+            transport_type: TransportType::Tcp,
+            local: None,
+            tcp: Some(tcp.clone()),
+            ..TransportConfig::default()
+        };
+        config.validate()?;
+        Ok(config)
+    } else {
+        anyhow::bail!("Profile '{}' has no connection settings", profile.name)
+    }
+}
+
+fn default_key_file() -> Option<PathBuf> {
+    let ssh_dir = dirs::home_dir()?.join(".ssh");
+    DEFAULT_KEY_FILES
+        .iter()
+        .map(|name| ssh_dir.join(name))
+        .find(|path| path.exists())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yuha_core::transport::SshAlgorithms;
+
+    #[test]
+    fn test_parse_targets() {
+        let mut config = YuhaConfig::default();
+        config.profiles.insert(
+            "work".to_string(),
+            ConnectionProfile {
+                name: "work".to_string(),
+                ssh: None,
+                local: None,
+                tcp: None,
+                env_vars: Default::default(),
+                overrides: Default::default(),
+            },
+        );
+
+        assert_eq!(Target::parse("local", &config).unwrap(), Target::Local);
+        assert_eq!(
+            Target::parse("work", &config).unwrap(),
+            Target::Profile("work".to_string())
+        );
+        assert_eq!(
+            Target::parse("alice@example.com:2222", &config).unwrap(),
+            Target::Ssh {
+                username: Some("alice".to_string()),
+                host: "example.com".to_string(),
+                port: Some(2222),
+            }
+        );
+        assert_eq!(
+            Target::parse("example.com", &config).unwrap(),
+            Target::Ssh {
+                username: None,
+                host: "example.com".to_string(),
+                port: None,
+            }
+        );
+        assert!(Target::parse("example.com:ssh", &config).is_err());
+        assert!(Target::parse("alice@", &config).is_err());
+    }
+
+    #[test]
+    fn test_transport_config_applies_network_and_profile_settings() {
+        let mut config = YuhaConfig::default();
+        config.network.socket.recv_buffer = Some(4 * 1024 * 1024);
+        config.profiles.insert(
+            "work".to_string(),
+            ConnectionProfile {
+                name: "work".to_string(),
+                ssh: Some(yuha_core::config::SshConfig {
+                    host: "example.com".to_string(),
+                    port: 22,
+                    username: "alice".to_string(),
+                    password: Some("secret".to_string()),
+                    key_path: None,
+                    cert_path: None,
+                    cert_principal: None,
+                    pkcs11: None,
+                    auto_upload_binary: false,
+                    algorithms: SshAlgorithms {
+                        ciphers: vec!["aes128-ctr".to_string()],
+                        compression: true,
+                        ..Default::default()
+                    },
+                    socket: None,
+                }),
+                local: None,
+                tcp: None,
+                env_vars: Default::default(),
+                overrides: Default::default(),
+            },
+        );
+
+        let transport_config = Target::Profile("work".to_string())
+            .transport_config(&config)
+            .unwrap();
+        let ssh = transport_config.ssh.unwrap();
+        assert_eq!(ssh.socket, Some(config.network.socket.clone()));
+        assert_eq!(ssh.algorithms.ciphers, ["aes128-ctr"]);
+        assert!(ssh.algorithms.compression);
+    }
+
+    #[test]
+    fn test_tcp_profile_keeps_its_settings() {
+        let mut config = YuhaConfig::default();
+        let profile: ConnectionProfile = toml::from_str(
+            r#"
+            name = "daemon"
+
+            [tcp]
+            host = "example.com"
+            port = 9999
+
+            [tcp.socket]
+            nodelay = false
+
+            [tcp.knock]
+            sequence = [{ port = 7000 }, { port = 8000, protocol = "udp" }]
+            command = ["fwknop", "-n", "example.com"]
+            "#,
+        )
+        .unwrap();
+        config.set_profile(profile);
+        config.network.socket.recv_buffer = Some(4 * 1024 * 1024);
+
+        let transport_config = Target::Profile("daemon".to_string())
+            .transport_config(&config)
+            .unwrap();
+        assert_eq!(transport_config.transport_type, TransportType::Tcp);
+        let tcp = transport_config.tcp.unwrap();
+        assert_eq!(tcp.port, 9999);
+        // The profile's socket options win over the network section's
+        let socket = tcp.socket.unwrap();
+        assert!(!socket.nodelay);
+        assert_eq!(socket.recv_buffer, None);
+        let knock = tcp.knock.unwrap();
+        assert_eq!(knock.sequence.len(), 2);
+        assert_eq!(knock.command.unwrap()[0], "fwknop");
+    }
+
+    #[test]
+    fn test_split_remote_path() {
+        assert_eq!(
+            split_remote_path("host:/etc/hosts").unwrap(),
+            ("host", "/etc/hosts")
+        );
+        assert_eq!(
+            split_remote_path("alice@host:2222:notes.txt").unwrap(),
+            ("alice@host:2222", "notes.txt")
+        );
+        assert_eq!(
+            split_remote_path(r"local:C:\notes.txt").unwrap(),
+            ("local", r"C:\notes.txt")
+        );
+        for spec in ["host", "host:", ":/etc/hosts", "host:22:"] {
+            assert!(split_remote_path(spec).is_err(), "{}", spec);
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! Tray menu contents
+//!
+//! The menu is described as plain [`Entry`] values built from a
+//! [`Snapshot`] of the daemon, so its layout does not depend on the
+//! toolkit drawing it. Clickable entries carry an [`Action`], which
+//! round-trips through the string id the toolkit reports back.
+
+use yuha_client::daemon_protocol::PortForwardInfo;
+use yuha_core::session::SessionId;
+
+/// What the tray shows
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub daemon_running: bool,
+    pub sessions: Vec<SessionView>,
+    /// Profiles to quick-connect to, sorted
+    pub profiles: Vec<String>,
+    /// Outcome of the last action, if it failed
+    pub notice: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionView {
+    pub id: SessionId,
+    pub name: String,
+    pub status: String,
+    pub clipboard_sync: bool,
+    pub forwards: Vec<ForwardView>,
+}
+
+/// A port forward, running or paused from the tray
+#[derive(Debug, Clone)]
+pub struct ForwardView {
+    pub forward: PortForwardInfo,
+    pub enabled: bool,
+}
+
+/// Something the user can do from the menu
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    ToggleClipboardSync(SessionId),
+    ToggleForward(SessionId, u16),
+    Connect(String),
+    Quit,
+}
+
+impl Action {
+    /// Menu item id
+    pub fn id(&self) -> String {
+        match self {
+            Self::ToggleClipboardSync(session) => format!("sync:{}", session),
+            Self::ToggleForward(session, port) => format!("forward:{}:{}", session, port),
+            Self::Connect(profile) => format!("connect:{}", profile),
+            Self::Quit => "quit".to_string(),
+        }
+    }
+
+    /// The action of a menu item id
+    pub fn parse(id: &str) -> Option<Self> {
+        if id == "quit" {
+            return Some(Self::Quit);
+        }
+        let (kind, rest) = id.split_once(':')?;
+        match kind {
+            "sync" => Some(Self::ToggleClipboardSync(rest.parse().ok()?)),
+            "forward" => {
+                let (session, port) = rest.rsplit_once(':')?;
+                Some(Self::ToggleForward(
+                    session.parse().ok()?,
+                    port.parse().ok()?,
+                ))
+            }
+            "connect" => Some(Self::Connect(rest.to_string())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    Label(String),
+    Separator,
+    Toggle {
+        action: Action,
+        text: String,
+        checked: bool,
+    },
+    Button {
+        action: Action,
+        text: String,
+    },
+    Submenu {
+        text: String,
+        entries: Vec<Entry>,
+    },
+}
+
+/// Menu entries showing `snapshot`
+pub fn entries(snapshot: &Snapshot) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    if !snapshot.daemon_running {
+        entries.push(Entry::Label("Daemon not running".to_string()));
+    } else if snapshot.sessions.is_empty() {
+        entries.push(Entry::Label("No sessions".to_string()));
+    }
+    for session in &snapshot.sessions {
+        let mut items = vec![Entry::Toggle {
+            action: Action::ToggleClipboardSync(session.id),
+            text: "Sync clipboard".to_string(),
+            checked: session.clipboard_sync,
+        }];
+        if !session.forwards.is_empty() {
+            items.push(Entry::Separator);
+        }
+        items.extend(session.forwards.iter().map(|view| Entry::Toggle {
+            action: Action::ToggleForward(session.id, view.forward.local_port),
+            text: format!(
+                "{} → {}:{}",
+                view.forward.local_port, view.forward.remote_host, view.forward.remote_port
+            ),
+            checked: view.enabled,
+        }));
+        entries.push(Entry::Submenu {
+            text: format!("{} ({})", session.name, session.status),
+            entries: items,
+        });
+    }
+
+    entries.push(Entry::Separator);
+    if !snapshot.profiles.is_empty() {
+        entries.push(Entry::Submenu {
+            text: "Connect".to_string(),
+            entries: snapshot
+                .profiles
+                .iter()
+                .map(|profile| Entry::Button {
+                    action: Action::Connect(profile.clone()),
+                    text: profile.clone(),
+                })
+                .collect(),
+        });
+    }
+    if let Some(notice) = &snapshot.notice {
+        entries.push(Entry::Label(notice.clone()));
+    }
+    entries.push(Entry::Button {
+        action: Action::Quit,
+        text: "Quit".to_string(),
+    });
+    entries
+}
+
+/// Tray icon tooltip summing up `snapshot`
+pub fn tooltip(snapshot: &Snapshot) -> String {
+    if !snapshot.daemon_running {
+        return "yuha: daemon not running".to_string();
+    }
+    let forwards: usize = snapshot
+        .sessions
+        .iter()
+        .map(|session| session.forwards.iter().filter(|view| view.enabled).count())
+        .sum();
+    format!(
+        "yuha: {} session(s), {} forward(s)",
+        snapshot.sessions.len(),
+        forwards
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(id: SessionId) -> SessionView {
+        SessionView {
+            id,
+            name: "dev".to_string(),
+            status: "Active".to_string(),
+            clipboard_sync: true,
+            forwards: vec![ForwardView {
+                forward: PortForwardInfo {
+                    local_port: 8080,
+                    remote_host: "localhost".to_string(),
+                    remote_port: 80,
+                    active_connections: 0,
+                },
+                enabled: false,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_action_ids_round_trip() {
+        let id = SessionId::new();
+        for action in [
+            Action::ToggleClipboardSync(id),
+            Action::ToggleForward(id, 8080),
+            Action::Connect("work:prod".to_string()),
+            Action::Quit,
+        ] {
+            assert_eq!(Action::parse(&action.id()), Some(action));
+        }
+        assert_eq!(Action::parse("sync:not-a-uuid"), None);
+        assert_eq!(Action::parse("forward:1"), None);
+        assert_eq!(Action::parse("other"), None);
+    }
+
+    #[test]
+    fn test_entries() {
+        let id = SessionId::new();
+        let snapshot = Snapshot {
+            daemon_running: true,
+            sessions: vec![session(id)],
+            profiles: vec!["prod".to_string()],
+            notice: None,
+        };
+
+        let entries = entries(&snapshot);
+        let Entry::Submenu {
+            text,
+            entries: items,
+        } = &entries[0]
+        else {
+            panic!("expected a session submenu");
+        };
+        assert_eq!(text, "dev (Active)");
+        assert_eq!(
+            items[0],
+            Entry::Toggle {
+                action: Action::ToggleClipboardSync(id),
+                text: "Sync clipboard".to_string(),
+                checked: true,
+            }
+        );
+        assert_eq!(
+            items[2],
+            Entry::Toggle {
+                action: Action::ToggleForward(id, 8080),
+                text: "8080 → localhost:80".to_string(),
+                checked: false,
+            }
+        );
+        assert!(matches!(&entries[2], Entry::Submenu { text, .. } if text == "Connect"));
+        assert!(matches!(
+            entries.last(),
+            Some(Entry::Button {
+                action: Action::Quit,
+                ..
+            })
+        ));
+        assert_eq!(tooltip(&snapshot), "yuha: 1 session(s), 0 forward(s)");
+    }
+
+    #[test]
+    fn test_entries_without_daemon() {
+        let snapshot = Snapshot {
+            notice: Some("Failed to connect".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            entries(&snapshot),
+            vec![
+                Entry::Label("Daemon not running".to_string()),
+                Entry::Separator,
+                Entry::Label("Failed to connect".to_string()),
+                Entry::Button {
+                    action: Action::Quit,
+                    text: "Quit".to_string(),
+                },
+            ]
+        );
+        assert_eq!(tooltip(&snapshot), "yuha: daemon not running");
+    }
+}
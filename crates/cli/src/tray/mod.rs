@@ -0,0 +1,19 @@
+//! Desktop tray mode
+//!
+//! `yuha tray` puts an icon in the system tray listing the daemon's
+//! sessions. Each session's submenu toggles clipboard sync and its port
+//! forwards, and configured profiles can be connected to in one click.
+//!
+//! The menu layout and the daemon worker are plain code, built with the
+//! tests; only [`ui`] needs the `tray` feature and its GUI toolkit.
+
+// Without the feature only the tests use the menu and worker
+#![cfg_attr(not(feature = "tray"), allow(dead_code))]
+
+mod menu;
+#[cfg(feature = "tray")]
+mod ui;
+mod worker;
+
+#[cfg(feature = "tray")]
+pub use ui::run;
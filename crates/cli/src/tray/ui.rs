@@ -0,0 +1,153 @@
+//! Tray icon and menu drawn with tray-icon on a tao event loop
+
+use anyhow::Result;
+use tao::event::{Event, StartCause};
+use tao::event_loop::{ControlFlow, EventLoopBuilder};
+use tokio::sync::mpsc;
+use tracing::{error, warn};
+use tray_icon::menu::{
+    CheckMenuItem, IsMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem, Submenu,
+};
+use tray_icon::{Icon, TrayIcon, TrayIconBuilder};
+use yuha_core::YuhaConfig;
+
+use super::menu::{self, Action, Entry, Snapshot};
+use super::worker::{DesktopClipboard, Worker};
+
+/// Side of the tray icon in pixels
+const ICON_SIZE: u32 = 32;
+
+enum UserEvent {
+    Snapshot(Snapshot),
+    Exit,
+}
+
+/// Show the tray icon until the user quits
+///
+/// Must be called on the main thread, from within the tokio runtime the
+/// worker is spawned on.
+pub fn run(config: YuhaConfig) -> Result<()> {
+    let event_loop = EventLoopBuilder::<UserEvent>::with_user_event().build();
+    let clipboard = arboard::Clipboard::new()?;
+
+    let (actions_tx, actions_rx) = mpsc::unbounded_channel();
+    MenuEvent::set_event_handler(Some(move |event: MenuEvent| {
+        if let Some(action) = Action::parse(event.id().as_ref()) {
+            let _ = actions_tx.send(action);
+        }
+    }));
+
+    let snapshots = event_loop.create_proxy();
+    let exit = event_loop.create_proxy();
+    tokio::runtime::Handle::current().spawn(async move {
+        Worker::new(config, Box::new(clipboard))
+            .run(actions_rx, move |snapshot| {
+                snapshots.send_event(UserEvent::Snapshot(snapshot)).is_ok()
+            })
+            .await;
+        let _ = exit.send_event(UserEvent::Exit);
+    });
+
+    let mut tray: Option<TrayIcon> = None;
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Wait;
+        match event {
+            // The icon can only be created once the event loop runs
+            Event::NewEvents(StartCause::Init) => {
+                let snapshot = Snapshot::default();
+                match build_menu(&snapshot).and_then(|menu| {
+                    Ok(TrayIconBuilder::new()
+                        .with_icon(icon()?)
+                        .with_tooltip(menu::tooltip(&snapshot))
+                        .with_menu(Box::new(menu))
+                        .build()?)
+                }) {
+                    Ok(icon) => tray = Some(icon),
+                    Err(e) => {
+                        error!("Failed to create the tray icon: {}", e);
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+            }
+            Event::UserEvent(UserEvent::Snapshot(snapshot)) => {
+                if let Some(tray) = &tray {
+                    match build_menu(&snapshot) {
+                        Ok(menu) => tray.set_menu(Some(Box::new(menu))),
+                        Err(e) => warn!("Failed to build the tray menu: {}", e),
+                    }
+                    let _ = tray.set_tooltip(Some(menu::tooltip(&snapshot)));
+                }
+            }
+            Event::UserEvent(UserEvent::Exit) => {
+                tray = None;
+                *control_flow = ControlFlow::Exit;
+            }
+            _ => {}
+        }
+    })
+}
+
+impl DesktopClipboard for arboard::Clipboard {
+    fn text(&mut self) -> Result<Option<String>> {
+        match self.get_text() {
+            Ok(text) => Ok(Some(text)),
+            Err(arboard::Error::ContentNotAvailable) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn set_text(&mut self, text: &str) -> Result<()> {
+        Ok(arboard::Clipboard::set_text(self, text)?)
+    }
+}
+
+fn build_menu(snapshot: &Snapshot) -> Result<Menu> {
+    let menu = Menu::new();
+    for entry in menu::entries(snapshot) {
+        menu.append(item(&entry)?.as_ref())?;
+    }
+    Ok(menu)
+}
+
+fn item(entry: &Entry) -> Result<Box<dyn IsMenuItem>> {
+    Ok(match entry {
+        Entry::Label(text) => Box::new(MenuItem::new(text, false, None)),
+        Entry::Separator => Box::new(PredefinedMenuItem::separator()),
+        Entry::Toggle {
+            action,
+            text,
+            checked,
+        } => Box::new(CheckMenuItem::with_id(
+            action.id(),
+            text,
+            true,
+            *checked,
+            None,
+        )),
+        Entry::Button { action, text } => {
+            Box::new(MenuItem::with_id(action.id(), text, true, None))
+        }
+        Entry::Submenu { text, entries } => {
+            let submenu = Submenu::new(text, true);
+            for entry in entries {
+                submenu.append(item(entry)?.as_ref())?;
+            }
+            Box::new(submenu)
+        }
+    })
+}
+
+/// A filled circle, drawn rather than shipped as an image file
+fn icon() -> Result<Icon> {
+    let center = ICON_SIZE as f32 / 2.0 - 0.5;
+    let radius = ICON_SIZE as f32 / 2.0 - 2.0;
+    let mut rgba = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let distance = (x as f32 - center).hypot(y as f32 - center);
+            let alpha = if distance <= radius { 0xff } else { 0 };
+            rgba.extend_from_slice(&[0x2e, 0x86, 0xc1, alpha]);
+        }
+    }
+    Ok(Icon::from_rgba(rgba, ICON_SIZE, ICON_SIZE)?)
+}
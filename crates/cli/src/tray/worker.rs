@@ -0,0 +1,309 @@
+//! Daemon side of the tray
+//!
+//! The [`Worker`] runs on the tokio runtime while the toolkit owns the main
+//! thread. It applies the menu's actions through the daemon, polls it for
+//! a fresh [`Snapshot`] and keeps the clipboards of the sessions with sync
+//! turned on in step, the [`DesktopClipboard`] acting as the hub between
+//! them.
+
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::debug;
+use yuha_client::daemon_client::DaemonClient;
+use yuha_client::daemon_protocol::{CommandResult, DaemonCommand, PortForwardInfo};
+use yuha_core::YuhaConfig;
+use yuha_core::session::SessionId;
+
+use super::menu::{Action, ForwardView, SessionView, Snapshot};
+use crate::target::Target;
+
+/// How often sessions are polled and clipboards synced
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The clipboard of the desktop the tray runs on
+pub trait DesktopClipboard: Send {
+    /// The text on the clipboard, `None` if it holds none
+    fn text(&mut self) -> Result<Option<String>>;
+
+    /// Replace the clipboard content with text
+    fn set_text(&mut self, text: &str) -> Result<()>;
+}
+
+pub struct Worker {
+    config: YuhaConfig,
+    clipboard: Box<dyn DesktopClipboard>,
+    daemon: Option<DaemonClient>,
+    syncs: HashMap<SessionId, ClipboardSync>,
+    /// Forwards stopped from the tray, to start again when toggled back
+    paused: HashMap<SessionId, Vec<PortForwardInfo>>,
+    notice: Option<String>,
+}
+
+impl Worker {
+    pub fn new(config: YuhaConfig, clipboard: Box<dyn DesktopClipboard>) -> Self {
+        Self {
+            config,
+            clipboard,
+            daemon: None,
+            syncs: HashMap::new(),
+            paused: HashMap::new(),
+            notice: None,
+        }
+    }
+
+    /// Apply `actions` until `Quit`, handing a snapshot to `publish` after
+    /// each action and refresh until it returns false
+    pub async fn run(
+        mut self,
+        mut actions: mpsc::UnboundedReceiver<Action>,
+        mut publish: impl FnMut(Snapshot) -> bool,
+    ) {
+        let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            tokio::select! {
+                action = actions.recv() => match action {
+                    None | Some(Action::Quit) => break,
+                    Some(action) => {
+                        let result = self.apply(action).await;
+                        self.notice = result.err().map(|e| e.to_string());
+                    }
+                },
+                _ = refresh.tick() => {
+                    if let Err(e) = self.sync_clipboards().await {
+                        self.notice = Some(format!("Clipboard sync failed: {}", e));
+                    }
+                }
+            }
+            let snapshot = self.snapshot().await;
+            if !publish(snapshot) {
+                break;
+            }
+        }
+    }
+
+    async fn apply(&mut self, action: Action) -> Result<()> {
+        match action {
+            Action::ToggleClipboardSync(session) => {
+                if self.syncs.remove(&session).is_none() {
+                    self.syncs.insert(session, ClipboardSync::default());
+                }
+            }
+            Action::ToggleForward(session, local_port) => {
+                let paused = self.paused.entry(session).or_default();
+                let daemon = connected(&mut self.daemon).await?;
+                if let Some(index) = paused.iter().position(|f| f.local_port == local_port) {
+                    let forward = paused.remove(index);
+                    let command = DaemonCommand::StartPortForward {
+                        local_port,
+                        remote_host: forward.remote_host.clone(),
+                        remote_port: forward.remote_port,
+                    };
+                    if let Err(e) = daemon.execute_command(session, command).await {
+                        paused.insert(index, forward);
+                        return Err(e.into());
+                    }
+                } else {
+                    let details = daemon.get_session_info(session).await?;
+                    let Some(forward) = details
+                        .active_port_forwards
+                        .into_iter()
+                        .find(|f| f.local_port == local_port)
+                    else {
+                        bail!("No forward on port {}", local_port);
+                    };
+                    daemon
+                        .execute_command(session, DaemonCommand::StopPortForward { local_port })
+                        .await?;
+                    paused.push(forward);
+                }
+            }
+            Action::Connect(profile) => {
+                crate::ensure_daemon_running().await?;
+                let transport_config =
+                    Target::Profile(profile.clone()).transport_config(&self.config)?;
+                connected(&mut self.daemon)
+                    .await?
+                    .connect_session(profile, transport_config)
+                    .await?;
+            }
+            Action::Quit => {}
+        }
+        Ok(())
+    }
+
+    async fn sync_clipboards(&mut self) -> Result<()> {
+        if self.syncs.is_empty() {
+            return Ok(());
+        }
+        let daemon = connected(&mut self.daemon).await?;
+        for (&session, sync) in &mut self.syncs {
+            let remote = match daemon
+                .execute_command(session, DaemonCommand::GetClipboard)
+                .await?
+            {
+                CommandResult::ClipboardContent { content } => content,
+                _ => bail!("Unexpected command result"),
+            };
+            let local = self.clipboard.text()?.unwrap_or_default();
+            match sync.step(&local, &remote) {
+                SyncStep::None => {}
+                SyncStep::ToLocal(content) => self.clipboard.set_text(&content)?,
+                SyncStep::ToRemote(content) => {
+                    daemon
+                        .execute_command(session, DaemonCommand::SetClipboard { content })
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn snapshot(&mut self) -> Snapshot {
+        let sessions = match self.sessions().await {
+            Ok(sessions) => Some(sessions),
+            Err(e) => {
+                debug!("Cannot reach the daemon: {}", e);
+                self.daemon = None;
+                None
+            }
+        };
+        let mut profiles: Vec<_> = self.config.profiles.keys().cloned().collect();
+        profiles.sort();
+        Snapshot {
+            daemon_running: sessions.is_some(),
+            sessions: sessions.unwrap_or_default(),
+            profiles,
+            notice: self.notice.clone(),
+        }
+    }
+
+    async fn sessions(&mut self) -> Result<Vec<SessionView>> {
+        let daemon = connected(&mut self.daemon).await?;
+        let mut sessions = Vec::new();
+        for summary in daemon.list_sessions().await? {
+            let details = daemon.get_session_info(summary.id).await?;
+            let paused = self.paused.get(&summary.id).cloned().unwrap_or_default();
+            sessions.push(SessionView {
+                id: summary.id,
+                name: summary.name,
+                status: summary.status,
+                clipboard_sync: self.syncs.contains_key(&summary.id),
+                forwards: forward_views(details.active_port_forwards, paused),
+            });
+        }
+        // Forget disconnected sessions
+        self.syncs
+            .retain(|id, _| sessions.iter().any(|session| session.id == *id));
+        self.paused
+            .retain(|id, _| sessions.iter().any(|session| session.id == *id));
+        Ok(sessions)
+    }
+}
+
+/// The daemon client, connecting first if needed
+async fn connected(daemon: &mut Option<DaemonClient>) -> Result<&mut DaemonClient> {
+    if daemon.is_none() {
+        *daemon = Some(DaemonClient::connect(None).await?);
+    }
+    Ok(daemon.as_mut().unwrap())
+}
+
+/// Running and paused forwards by local port
+fn forward_views(active: Vec<PortForwardInfo>, paused: Vec<PortForwardInfo>) -> Vec<ForwardView> {
+    let mut views: Vec<_> = active
+        .into_iter()
+        .map(|forward| ForwardView {
+            forward,
+            enabled: true,
+        })
+        .chain(paused.into_iter().map(|forward| ForwardView {
+            forward,
+            enabled: false,
+        }))
+        .collect();
+    views.sort_by_key(|view| view.forward.local_port);
+    views
+}
+
+/// What to do to bring two clipboards in step
+#[derive(Debug, PartialEq, Eq)]
+enum SyncStep {
+    None,
+    ToLocal(String),
+    ToRemote(String),
+}
+
+/// Two-way sync of the local clipboard with a session's
+///
+/// A side that changed since the last step wins. A session just turned on
+/// brings its clipboard in, as the local one has nothing to compare with.
+#[derive(Debug, Default)]
+struct ClipboardSync {
+    last: Option<String>,
+}
+
+impl ClipboardSync {
+    fn step(&mut self, local: &str, remote: &str) -> SyncStep {
+        if local == remote {
+            self.last = Some(local.to_string());
+            return SyncStep::None;
+        }
+        let local_changed = self.last.as_deref().is_some_and(|last| last != local);
+        if local_changed {
+            self.last = Some(local.to_string());
+            SyncStep::ToRemote(local.to_string())
+        } else {
+            self.last = Some(remote.to_string());
+            SyncStep::ToLocal(remote.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forward(local_port: u16) -> PortForwardInfo {
+        PortForwardInfo {
+            local_port,
+            remote_host: "localhost".to_string(),
+            remote_port: 80,
+            active_connections: 0,
+        }
+    }
+
+    #[test]
+    fn test_clipboard_sync() {
+        let mut sync = ClipboardSync::default();
+        // The session's clipboard comes in first
+        assert_eq!(
+            sync.step("", "remote"),
+            SyncStep::ToLocal("remote".to_string())
+        );
+        assert_eq!(sync.step("remote", "remote"), SyncStep::None);
+
+        assert_eq!(
+            sync.step("copied", "remote"),
+            SyncStep::ToRemote("copied".to_string())
+        );
+        assert_eq!(sync.step("copied", "copied"), SyncStep::None);
+
+        assert_eq!(
+            sync.step("copied", "pasted"),
+            SyncStep::ToLocal("pasted".to_string())
+        );
+        assert_eq!(sync.step("pasted", "pasted"), SyncStep::None);
+    }
+
+    #[test]
+    fn test_forward_views() {
+        let views = forward_views(vec![forward(9090)], vec![forward(8080)]);
+        let ports: Vec<_> = views
+            .iter()
+            .map(|view| (view.forward.local_port, view.enabled))
+            .collect();
+        assert_eq!(ports, [(8080, false), (9090, true)]);
+    }
+}
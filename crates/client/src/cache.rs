@@ -0,0 +1,190 @@
+//! # Response Cache
+//!
+//! Optional cache of idempotent read responses (clipboard reads, port forward
+//! listings, the slow log) for frontends that poll. Entries expire after a
+//! TTL and are dropped as soon as the client sends a request that mutates
+//! what they describe, e.g. `SetClipboard` drops cached clipboard reads.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use yuha_core::protocol::{ProtocolRequest, ProtocolResponse};
+
+/// TTLs of a [`ResponseCache`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseCacheConfig {
+    /// How long a response stays fresh
+    pub ttl: Duration,
+    /// Per request kind TTLs (e.g. `"GetSlowLog"`) overriding `ttl`
+    #[serde(default)]
+    pub ttl_overrides: HashMap<String, Duration>,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(2),
+            ttl_overrides: HashMap::new(),
+        }
+    }
+}
+
+struct CachedResponse {
+    kind: &'static str,
+    response: ProtocolResponse,
+    expires_at: Instant,
+}
+
+/// Cache of read responses keyed by the encoded request
+pub struct ResponseCache {
+    config: ResponseCacheConfig,
+    entries: Mutex<HashMap<String, CachedResponse>>,
+}
+
+impl ResponseCache {
+    pub fn new(config: ResponseCacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fresh cached response to `request`, if any
+    pub fn get(&self, request: &ProtocolRequest) -> Option<ProtocolResponse> {
+        let key = cache_key(request)?;
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(&key) {
+            Some(entry) if entry.expires_at > Instant::now() => {
+                debug!("Serving {} from the response cache", entry.kind);
+                Some(entry.response.clone())
+            }
+            Some(_) => {
+                entries.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Record the remote's answer to `request`
+    ///
+    /// Successful reads are cached; mutations drop the entries they invalidate.
+    pub fn update(&self, request: &ProtocolRequest, response: &ProtocolResponse) {
+        let invalidated = request.invalidated_kinds();
+        if !invalidated.is_empty() {
+            self.entries
+                .lock()
+                .unwrap()
+                .retain(|_, entry| !invalidated.contains(&entry.kind));
+        }
+
+        let Some(key) = cache_key(request) else {
+            return;
+        };
+        if matches!(response, ProtocolResponse::Error { .. }) {
+            return;
+        }
+        let kind = request.kind();
+        let ttl = self
+            .config
+            .ttl_overrides
+            .get(kind)
+            .copied()
+            .unwrap_or(self.config.ttl);
+        self.entries.lock().unwrap().insert(
+            key,
+            CachedResponse {
+                kind,
+                response: response.clone(),
+                expires_at: Instant::now() + ttl,
+            },
+        );
+    }
+
+    /// Drop every cached response
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+fn cache_key(request: &ProtocolRequest) -> Option<String> {
+    if !request.is_cacheable() {
+        return None;
+    }
+    serde_json::to_string(request).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yuha_core::protocol::{ErrorCode, ResponseItem};
+
+    fn clipboard(content: &str) -> ProtocolResponse {
+        ProtocolResponse::Data {
+            items: vec![ResponseItem::ClipboardContent {
+                content: content.to_string(),
+            }],
+            next: None,
+        }
+    }
+
+    fn content(response: Option<ProtocolResponse>) -> Option<String> {
+        match response? {
+            ProtocolResponse::Data { items, .. } => match items.into_iter().next()? {
+                ResponseItem::ClipboardContent { content } => Some(content),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_reads_cached_until_mutation() {
+        let cache = ResponseCache::new(ResponseCacheConfig::default());
+        let read = ProtocolRequest::GetClipboard;
+
+        assert!(cache.get(&read).is_none());
+        cache.update(&read, &clipboard("a"));
+        assert_eq!(content(cache.get(&read)), Some("a".to_string()));
+
+        // Unrelated mutations keep the entry
+        cache.update(
+            &ProtocolRequest::StopPortForward { local_port: 8080 },
+            &ProtocolResponse::Success,
+        );
+        assert!(cache.get(&read).is_some());
+
+        cache.update(
+            &ProtocolRequest::SetClipboard {
+                content: "b".to_string(),
+            },
+            &ProtocolResponse::Success,
+        );
+        assert!(cache.get(&read).is_none());
+    }
+
+    #[test]
+    fn test_expiry_and_uncacheable_responses() {
+        let cache = ResponseCache::new(ResponseCacheConfig {
+            ttl: Duration::from_secs(60),
+            ttl_overrides: HashMap::from([("GetClipboard".to_string(), Duration::ZERO)]),
+        });
+
+        cache.update(&ProtocolRequest::GetClipboard, &clipboard("a"));
+        assert!(cache.get(&ProtocolRequest::GetClipboard).is_none());
+
+        let slow_log = ProtocolRequest::GetSlowLog;
+        cache.update(
+            &slow_log,
+            &ProtocolResponse::error(ErrorCode::Internal, "failed"),
+        );
+        assert!(cache.get(&slow_log).is_none());
+
+        let poll = ProtocolRequest::PollData;
+        cache.update(&poll, &clipboard("a"));
+        assert!(cache.get(&poll).is_none());
+    }
+}
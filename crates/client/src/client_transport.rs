@@ -10,6 +10,8 @@
 //! - **Clean API**: Request-response pattern with async/await support
 //! - **Error Handling**: Comprehensive error reporting and recovery
 //! - **Connection Management**: Automatic connection handling and lifecycle
+//! - **Connection Sharing**: Cloned clients issue concurrent requests over one connection
+//! - **Handoff**: A live session moves to another transport without dropping its forwards
 //!
 //! ## Usage Example
 //!
@@ -37,15 +39,45 @@
 //! The type is also available as `Client<T>` for direct usage.
 
 use anyhow::Result;
+use async_trait::async_trait;
 use bytes::Bytes;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tracing::{info, warn};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
 
+use yuha_core::access_log::AccessEntry;
+use yuha_core::checksum::{self, HashAlgorithm};
+use yuha_core::clipboard::{ClipboardDedup, ClipboardFormat, ClipboardItem};
 use yuha_core::message_channel::MessageChannel;
-use yuha_core::protocol::{ProtocolRequest, ProtocolResponse, ResponseItem};
+use yuha_core::protocol::codec;
+use yuha_core::protocol::command::{Command, CommandSender};
+use yuha_core::protocol::extension::{self, Extension, ExtensionId};
+use yuha_core::protocol::flow::{Credit, INITIAL_CREDIT};
+use yuha_core::protocol::request_response::{
+    ClipboardWatch, DirectoryUsage, DisplayEnv, PortForwardEntry, Sandbox, SessionState, TaskId,
+    TaskInfo, TaskKind, ToolInfo, TrashEntry, Usage,
+};
+use yuha_core::protocol::{
+    CorrelationId, ErrorCode, ListQuery, ProtocolRequest, ProtocolResponse, ResponseItem, Topic,
+    TopicEvent,
+};
+use yuha_core::slow_log::SlowRequest;
+
+/// How often a handed off connection checks whether the forwarded
+/// connections it still carries have closed
+const DRAIN_INTERVAL: Duration = Duration::from_secs(1);
 
 use crate::ClientError;
+use crate::cache::{ResponseCache, ResponseCacheConfig};
+use crate::compare::FileHash;
+use crate::connection::Connection;
+use crate::file_transfer::{RemoteEntry, RemoteFile};
+use crate::pipeline::PendingResponse;
+use crate::server_requests::{self, Handlers, ServerRequestHandler};
+use crate::stream::{PageStream, ResponseStream};
+use crate::subscriptions::Broker;
 use crate::transport::{Transport, TransportConfig};
 
 /// Client using request-response protocol with transport abstraction.
@@ -58,6 +90,14 @@ use crate::transport::{Transport, TransportConfig};
 ///
 /// - `T`: Transport implementation that handles the underlying connection
 ///
+/// # Sharing
+///
+/// Clones are cheap handles to the same connection, response cache and
+/// negotiated extensions, so several tasks can issue requests concurrently
+/// without wrapping the client in a lock.
+/// Connecting a clone gives it a connection of its own, while a
+/// [`handoff`](Self::handoff) moves every clone to the new transport.
+///
 /// # Example
 ///
 /// ```rust,no_run
@@ -75,63 +115,635 @@ use crate::transport::{Transport, TransportConfig};
 /// # }
 /// ```
 pub struct Client<T: Transport> {
-    /// The underlying transport for communication
-    transport: T,
-    /// Message channel for sending/receiving protocol messages
-    message_channel: Option<Arc<Mutex<MessageChannel<T::Stream>>>>,
+    /// The underlying transport for communication, shared by all clones
+    transport: Arc<RwLock<Arc<T>>>,
+    /// Task owning the message channel, shared by all clones
+    connection: Option<Connection>,
+    /// Cache of idempotent read responses, when enabled
+    cache: Option<Arc<ResponseCache>>,
+    /// Extensions negotiated with the remote on connect
+    extensions: Vec<ExtensionId>,
+    /// How the remote watches its clipboard, when it pushes copies
+    clipboard_watch: Option<ClipboardWatch>,
+    /// Hash the remote binary must report, overriding the transport's
+    expected_binary_hash: Option<String>,
+    /// Warn instead of failing when the remote binary does not match
+    allow_modified_binary: bool,
+    /// Workspace of the remote selected on connect
+    workspace: Option<String>,
+    /// Credit left to send on each forwarded connection, shared by all clones
+    credits: Arc<Mutex<HashMap<u32, Arc<Credit>>>>,
+    /// Clipboard text last synced with the remote, shared by all clones
+    clipboard_dedup: Arc<Mutex<ClipboardDedup>>,
+    /// Handlers of requests of the remote, asked in order
+    server_request_handlers: Handlers,
+    /// Whether polled data is granted back, so `flow-control` is offered
+    flow_control: bool,
+    /// Whether `msgpack` is offered
+    msgpack: bool,
+    /// Receivers of the events of subscribed topics, shared by all clones
+    broker: Arc<Broker>,
+}
+
+// Not derived: cloning must not require `T: Clone`
+impl<T: Transport> Clone for Client<T> {
+    fn clone(&self) -> Self {
+        Self {
+            transport: self.transport.clone(),
+            connection: self.connection.clone(),
+            cache: self.cache.clone(),
+            extensions: self.extensions.clone(),
+            clipboard_watch: self.clipboard_watch,
+            expected_binary_hash: self.expected_binary_hash.clone(),
+            allow_modified_binary: self.allow_modified_binary,
+            workspace: self.workspace.clone(),
+            credits: self.credits.clone(),
+            clipboard_dedup: self.clipboard_dedup.clone(),
+            server_request_handlers: self.server_request_handlers.clone(),
+            flow_control: self.flow_control,
+            msgpack: self.msgpack,
+            broker: self.broker.clone(),
+        }
+    }
 }
 
 impl<T: Transport> Client<T> {
     /// Create a new client with the given transport
     pub fn new(transport: T) -> Self {
         Self {
-            transport,
-            message_channel: None,
+            transport: Arc::new(RwLock::new(Arc::new(transport))),
+            connection: None,
+            cache: None,
+            extensions: Vec::new(),
+            clipboard_watch: None,
+            expected_binary_hash: None,
+            allow_modified_binary: false,
+            workspace: None,
+            credits: Arc::default(),
+            clipboard_dedup: Arc::default(),
+            server_request_handlers: Vec::new(),
+            flow_control: false,
+            msgpack: false,
+            broker: Arc::default(),
+        }
+    }
+
+    /// Select the remote's workspace `name` on connect, isolating this
+    /// client's clipboard, forwards and files from other workspaces
+    pub fn with_workspace(mut self, name: impl Into<String>) -> Self {
+        self.workspace = Some(name.into());
+        self
+    }
+
+    /// Require the remote binary to have this BLAKE3 hash, instead of the
+    /// hash of the binary the transport deploys
+    pub fn with_binary_hash(mut self, hash: impl Into<String>) -> Self {
+        self.expected_binary_hash = Some(hash.into());
+        self
+    }
+
+    /// Connect even when the remote binary does not match the expected hash
+    pub fn allow_modified_binary(mut self, allow: bool) -> Self {
+        self.allow_modified_binary = allow;
+        self
+    }
+
+    /// Answer requests of the remote with `handler`, after the handlers
+    /// added before; the `server-requests` extension is only offered once a
+    /// handler is added
+    pub fn with_server_request_handler(
+        mut self,
+        handler: impl ServerRequestHandler + 'static,
+    ) -> Self {
+        self.server_request_handlers.push(Arc::new(handler));
+        self
+    }
+
+    /// Offer the `flow-control` extension, promising to grant back the
+    /// credit of the forwarded data polled (see [`Self::grant_credit`]);
+    /// without it, the remote never waits for grants. Batches of streamed
+    /// responses are granted back as they are taken.
+    pub fn with_flow_control(mut self) -> Self {
+        self.flow_control = true;
+        self
+    }
+
+    /// Offer the `msgpack` extension, so messages after the handshake are
+    /// encoded with MessagePack once the remote accepts it; without it, the
+    /// channel keeps its codec
+    pub fn with_msgpack(mut self) -> Self {
+        self.msgpack = true;
+        self
+    }
+
+    /// Cache idempotent read responses, reusing them until they expire or a
+    /// related mutation is sent through this client
+    pub fn with_cache(mut self, config: ResponseCacheConfig) -> Self {
+        self.cache = Some(Arc::new(ResponseCache::new(config)));
+        self
+    }
+
+    /// Drop all cached responses, e.g. after the remote changed state behind
+    /// the client's back
+    pub fn invalidate_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.clear();
         }
     }
 
     /// Connect to the remote server
     pub async fn connect(&mut self) -> Result<(), ClientError> {
-        info!("Connecting using {} transport", self.transport.name());
+        let transport = self.transport();
+        info!("Connecting using {} transport", transport.name());
 
-        let stream =
-            self.transport.connect().await.map_err(|e| {
-                ClientError::Connection(format!("Transport connection failed: {}", e))
+        let stream = transport
+            .connect()
+            .await
+            .map_err(|e| match e.downcast_ref() {
+                Some(ClientError::InteractionRequired { prompt }) => {
+                    ClientError::InteractionRequired {
+                        prompt: prompt.clone(),
+                    }
+                }
+                Some(ClientError::Authentication(reason)) => {
+                    ClientError::Authentication(reason.clone())
+                }
+                _ => ClientError::Connection(format!("Transport connection failed: {}", e)),
             })?;
 
-        let message_channel = MessageChannel::new_with_stream(stream);
-        self.message_channel = Some(Arc::new(Mutex::new(message_channel)));
+        let mut message_channel = MessageChannel::new_with_stream(stream)
+            .with_read_buffer(transport.read_buffer())
+            .with_keepalive(transport.keepalive());
+        if let Some(noise) = transport.noise() {
+            message_channel
+                .encrypt_client(noise)
+                .await
+                .map_err(|e| ClientError::Authentication(e.to_string()))?;
+        }
+        if let Some(token) = transport.auth_token() {
+            message_channel
+                .authenticate_client(token)
+                .await
+                .map_err(|e| ClientError::Authentication(e.to_string()))?;
+        }
+        self.connection = Some(Connection::spawn(message_channel));
+        self.handshake().await?;
+
+        info!("Connected successfully via {} transport", transport.name());
+        Ok(())
+    }
+
+    /// Move the session to `transport`, e.g. from SSH to direct TCP once a
+    /// VPN comes up, without disconnecting the clones sharing it
+    ///
+    /// The new connection is set up first. Requests are then paused while
+    /// the ones in flight complete, the session's forwards and clipboard are
+    /// carried over unless the remote reached already has them, and the
+    /// paused requests resume in order over `transport`. The remote must
+    /// negotiate the same extensions. On failure the session stays on the
+    /// current transport.
+    ///
+    /// The current remote releases the forwarded ports before the new one
+    /// binds them, so both may run on the same host. Connections it accepted
+    /// before keep running on it: with the `tasks` extension, the current
+    /// connection stays open until they closed, and is closed right away
+    /// otherwise.
+    pub async fn handoff(&mut self, transport: T) -> Result<(), ClientError> {
+        let connection = self.connection()?.clone();
+        let mut next = Self {
+            expected_binary_hash: self.expected_binary_hash.clone(),
+            allow_modified_binary: self.allow_modified_binary,
+            workspace: self.workspace.clone(),
+            server_request_handlers: self.server_request_handlers.clone(),
+            flow_control: self.flow_control,
+            msgpack: self.msgpack,
+            broker: self.broker.clone(),
+            ..Self::new(transport)
+        };
+        next.connect().await?;
+        if next.extensions != self.extensions {
+            return Err(ClientError::Connection(format!(
+                "Remote reached via {} negotiated other extensions",
+                next.transport().name()
+            )));
+        }
 
+        let paused = connection.pause().await;
+        let current = Self {
+            connection: Some(paused.current()),
+            cache: None,
+            ..self.clone()
+        };
+        let state = current.export_session_state().await?;
+        let ports: Vec<_> = state
+            .forwards
+            .iter()
+            .map(|forward| forward.local_port)
+            .collect();
+        // Unexposed first, as both transports may expose ports the same way
+        let previous = self.transport();
+        notify_forwards(&*previous, &ports, false).await;
+        // Then released, as a remote on the same host cannot bind them before
+        let released = current.release_forwards(&state.forwards).await;
+        if let Err(e) = next.import_session_state(state).await {
+            current.restart_forwards(released).await;
+            notify_forwards(&*previous, &ports, true).await;
+            return Err(e);
+        }
+        paused.resume_on(next.connection()?).await;
+        if self.extensions.contains(&extension::TASKS.id) {
+            tokio::spawn(drain(current.connection()?.clone(), ports));
+        }
+
+        *self.transport.write().unwrap() = next.transport();
+        self.clipboard_watch = next.clipboard_watch;
+        // The remote may be another process with other state
+        self.invalidate_cache();
+        self.credits.lock().unwrap().clear();
+        self.clipboard_dedup.lock().unwrap().forget();
         info!(
-            "Connected successfully via {} transport",
-            self.transport.name()
+            "Handed the session off from {} to {} transport",
+            previous.name(),
+            next.transport().name()
         );
         Ok(())
     }
 
-    /// Send a request and wait for response
-    async fn send_request(
+    fn transport(&self) -> Arc<T> {
+        self.transport.read().unwrap().clone()
+    }
+
+    /// Release the ports of `forwards` for another remote, returning those
+    /// released
+    async fn release_forwards(&self, forwards: &[PortForwardEntry]) -> Vec<PortForwardEntry> {
+        let mut released = Vec::new();
+        for forward in forwards {
+            match self.release_port_forward(forward.local_port).await {
+                Ok(()) => released.push(forward.clone()),
+                Err(e) => warn!("Failed to release port {}: {}", forward.local_port, e),
+            }
+        }
+        released
+    }
+
+    /// Listen on the ports of `forwards` again after a failed handoff
+    async fn restart_forwards(&self, forwards: Vec<PortForwardEntry>) {
+        for forward in forwards {
+            let request = ProtocolRequest::StartPortForward {
+                local_port: forward.local_port,
+                remote_host: forward.remote_host,
+                remote_port: forward.remote_port,
+            };
+            match self.send_request(request).await {
+                Ok(ProtocolResponse::Success) => {}
+                Ok(response) => warn!(
+                    "Failed to restart the forward of port {}: {:?}",
+                    forward.local_port, response
+                ),
+                Err(e) => warn!(
+                    "Failed to restart the forward of port {}: {}",
+                    forward.local_port, e
+                ),
+            }
+        }
+    }
+
+    /// Offer every registered extension, keep those the remote accepted,
+    /// select the workspace and verify the binary the remote runs
+    ///
+    /// `server-requests` is only offered with handlers to answer them, and
+    /// `flow-control` and `msgpack` only when asked for with
+    /// [`Self::with_flow_control`] and [`Self::with_msgpack`].
+    ///
+    /// A remote predating negotiation answers `Hello` as unsupported; the
+    /// session continues without extensions then, unless a workspace was
+    /// asked for, which such a remote cannot select.
+    async fn handshake(&mut self) -> Result<(), ClientError> {
+        let mut extensions = extension::registered();
+        if self.server_request_handlers.is_empty() {
+            extensions.retain(|id| *id != extension::SERVER_REQUESTS.id);
+        }
+        if !self.flow_control {
+            extensions.retain(|id| *id != extension::FLOW_CONTROL.id);
+        }
+        if !self.msgpack {
+            extensions.retain(|id| !codec::msgpack_negotiated(&[*id]));
+        }
+        let request = ProtocolRequest::Hello {
+            extensions,
+            workspace: self.workspace.clone(),
+        };
+        let items = match self.send_uncached_request(request).await {
+            Ok(ProtocolResponse::Data { items, .. }) => items,
+            Err(ClientError::Unsupported { .. })
+            | Ok(ProtocolResponse::Error {
+                code: ErrorCode::Unsupported,
+                ..
+            }) => match &self.workspace {
+                Some(workspace) => {
+                    return Err(ClientError::Connection(format!(
+                        "Remote cannot select workspace {}: it predates extension negotiation",
+                        workspace
+                    )));
+                }
+                None => {
+                    debug!("Remote predates extension negotiation; continuing without extensions");
+                    Vec::new()
+                }
+            },
+            Ok(ProtocolResponse::Error { message, .. }) => {
+                return Err(ClientError::Connection(format!(
+                    "Extension negotiation failed: {}",
+                    message
+                )));
+            }
+            Ok(_) => return Err(ClientError::Channel("Unexpected response type".to_string())),
+            Err(e) => return Err(e),
+        };
+
+        let mut binary_hash = None;
+        for item in items {
+            match item {
+                ResponseItem::Extensions { extensions } => self.extensions = extensions,
+                ResponseItem::BinaryHash { hash } => binary_hash = Some(hash),
+                ResponseItem::ClipboardWatch { watch } => self.clipboard_watch = Some(watch),
+                _ => {}
+            }
+        }
+        if self.extensions.contains(&extension::GRACEFUL_CLOSE.id) {
+            self.connection()?.close_gracefully().await?;
+        }
+        if self.extensions.contains(&extension::FLOW_CONTROL.id) {
+            self.connection()?.grant_batches();
+        }
+        if self.extensions.contains(&extension::SERVER_REQUESTS.id) {
+            let calls = self.connection()?.listen().await?;
+            let handlers = self.server_request_handlers.clone();
+            tokio::spawn(server_requests::dispatch(handlers, calls));
+        }
+        if self.extensions.contains(&extension::SUBSCRIPTIONS.id) {
+            self.connection()?.publish_to(self.broker.clone()).await?;
+            // Topics subscribed to before a handoff
+            for topic in self.broker.topics() {
+                self.topic_request(ProtocolRequest::Subscribe { topic })
+                    .await?;
+            }
+        }
+        self.verify_binary(binary_hash.as_deref()).await
+    }
+
+    /// Compare the hash the remote reported for its binary with the expected
+    /// one, if any is known
+    async fn verify_binary(&self, reported: Option<&str>) -> Result<(), ClientError> {
+        let expected = match (&self.expected_binary_hash, self.transport().remote_binary()) {
+            (Some(hash), _) => hash.clone(),
+            (None, Some(binary)) => {
+                let binary = binary.to_path_buf();
+                match tokio::task::spawn_blocking(move || checksum::digest_file(&binary)).await {
+                    Ok(Ok(hash)) => hash,
+                    // A binary found on the remote's PATH has no local copy
+                    _ => return Ok(()),
+                }
+            }
+            (None, None) => return Ok(()),
+        };
+        if reported == Some(expected.as_str()) {
+            return Ok(());
+        }
+
+        let problem = match reported {
+            Some(hash) => format!(
+                "Remote binary hash {} does not match the expected {}",
+                hash, expected
+            ),
+            None => "Remote did not report its binary hash".to_string(),
+        };
+        if self.allow_modified_binary {
+            warn!("{}", problem);
+            return Ok(());
+        }
+        Err(ClientError::Connection(format!(
+            "{}; refusing to use a modified remote binary",
+            problem
+        )))
+    }
+
+    /// Extensions negotiated with the remote
+    pub fn extensions(&self) -> &[ExtensionId] {
+        &self.extensions
+    }
+
+    /// How the remote watches its clipboard, if it pushes copied text as
+    /// `ClipboardContent` items of `PollData` (`clipboard-watch` extension)
+    pub fn clipboard_watch(&self) -> Option<ClipboardWatch> {
+        self.clipboard_watch
+    }
+
+    /// Fail unless `extension` was negotiated, before sending one of its requests
+    pub(crate) fn require(&self, extension: &Extension) -> Result<(), ClientError> {
+        if self.extensions.contains(&extension.id) {
+            return Ok(());
+        }
+        Err(ClientError::Unsupported {
+            request: extension.name.to_string(),
+        })
+    }
+
+    /// Send a request and wait for response, going through the cache when enabled
+    pub(crate) async fn send_request(
         &self,
         request: ProtocolRequest,
     ) -> Result<ProtocolResponse, ClientError> {
-        let channel = self
-            .message_channel
-            .as_ref()
-            .ok_or_else(|| ClientError::Connection("Not connected".to_string()))?;
+        let Some(cache) = &self.cache else {
+            return self.send_uncached_request(request).await;
+        };
+        if let Some(response) = cache.get(&request) {
+            return Ok(response);
+        }
 
-        let mut channel = channel.lock().await;
+        let response = self.send_uncached_request(request.clone()).await?;
+        cache.update(&request, &response);
+        Ok(response)
+    }
 
-        channel
-            .send_request(&request)
-            .await
-            .map_err(|e| ClientError::Channel(format!("Failed to send request: {}", e)))?;
+    async fn send_uncached_request(
+        &self,
+        request: ProtocolRequest,
+    ) -> Result<ProtocolResponse, ClientError> {
+        self.connection()?.submit(request).await?.next().await
+    }
+
+    /// Send a request answered with streamed `Batch` responses, passing each
+    /// batch's items to `on_items` as it arrives
+    async fn send_streaming_request<F>(
+        &self,
+        request: ProtocolRequest,
+        mut on_items: F,
+    ) -> Result<(), ClientError>
+    where
+        F: FnMut(Vec<ResponseItem>),
+    {
+        let mut responses = self.connection()?.submit(request).await?;
+        loop {
+            match responses.next().await? {
+                ProtocolResponse::Batch { items, more } => {
+                    on_items(items);
+                    if !more {
+                        return Ok(());
+                    }
+                }
+                ProtocolResponse::Error {
+                    message,
+                    code,
+                    details,
+                } => {
+                    return Err(ClientError::RemoteExecution {
+                        code,
+                        message,
+                        details,
+                    });
+                }
+                _ => return Err(ClientError::Channel("Unexpected response type".to_string())),
+            }
+        }
+    }
+
+    /// Send a request and stream the items of its responses as they arrive,
+    /// e.g. the entries of a `ListFiles` request batch by batch
+    pub async fn stream_request(
+        &self,
+        request: ProtocolRequest,
+    ) -> Result<ResponseStream, ClientError> {
+        Ok(ResponseStream::new(
+            self.connection()?.submit(request).await?,
+        ))
+    }
 
-        let response = channel
-            .receive_response()
+    /// Send a request without waiting for its response, so the next ones go
+    /// out before it is answered (see [`crate::pipeline`])
+    pub async fn send_pipelined(
+        &self,
+        request: ProtocolRequest,
+    ) -> Result<PendingResponse, ClientError> {
+        let Some(cache) = &self.cache else {
+            let responses = self.connection()?.submit(request).await?;
+            return Ok(PendingResponse::sent(responses, None));
+        };
+        if let Some(response) = cache.get(&request) {
+            return Ok(PendingResponse::answered(response));
+        }
+        let responses = self.connection()?.submit(request.clone()).await?;
+        Ok(PendingResponse::sent(
+            responses,
+            Some((cache.clone(), request)),
+        ))
+    }
+
+    /// Send `requests` back to back, returning their pending responses in
+    /// the same order
+    pub async fn pipeline(
+        &self,
+        requests: impl IntoIterator<Item = ProtocolRequest>,
+    ) -> Result<Vec<PendingResponse>, ClientError> {
+        let mut pending = Vec::new();
+        for request in requests {
+            pending.push(self.send_pipelined(request).await?);
+        }
+        Ok(pending)
+    }
+
+    /// Stream the items of every page of a listing, sending the request
+    /// `request` builds for `query` and then for the cursor of each page
+    ///
+    /// ```rust,no_run
+    /// # async fn example(client: yuha_client::Client<yuha_client::transport::LocalTransport>) {
+    /// use yuha_core::protocol::{ListQuery, ProtocolRequest};
+    ///
+    /// let query = ListQuery {
+    ///     limit: Some(100),
+    ///     ..Default::default()
+    /// };
+    /// let forwards = client.paginate(query, |query| ProtocolRequest::ListPortForwards { query });
+    /// # }
+    /// ```
+    pub fn paginate<F>(&self, query: ListQuery, mut request: F) -> PageStream
+    where
+        F: FnMut(ListQuery) -> ProtocolRequest + Send + 'static,
+        T: 'static,
+    {
+        let client = self.clone();
+        PageStream::new(query, move |query| {
+            let client = client.clone();
+            let request = request(query);
+            Box::pin(async move { client.send_request(request).await })
+        })
+    }
+
+    /// Ask the remote to stop working on the request `request_id` (see
+    /// [`ResponseStream::request_id`])
+    ///
+    /// The request ends with a `RemoteExecution` error once the remote
+    /// stopped it. A request already answered, or one the remote cannot
+    /// interrupt and finishes first, is left as is.
+    pub async fn cancel(&self, request_id: CorrelationId) -> Result<(), ClientError> {
+        self.require(&extension::CANCEL)?;
+        self.connection()?.cancel(request_id).await
+    }
+
+    /// Receive the events of `topic` pushed by the remote
+    /// (`subscriptions` extension)
+    ///
+    /// The topic is subscribed to on the remote the first time; the
+    /// receivers of every clone share that subscription until
+    /// [`unsubscribe`](Self::unsubscribe) closes them.
+    pub async fn subscribe(
+        &self,
+        topic: Topic,
+    ) -> Result<broadcast::Receiver<TopicEvent>, ClientError> {
+        self.require(&extension::SUBSCRIPTIONS)?;
+        // Ready before the remote may push its first event
+        let (receiver, new) = self.broker.receiver(topic);
+        if new
+            && let Err(e) = self
+                .topic_request(ProtocolRequest::Subscribe { topic })
+                .await
+        {
+            self.broker.remove(topic);
+            return Err(e);
+        }
+        Ok(receiver)
+    }
+
+    /// Stop the events of `topic`, closing its receivers
+    pub async fn unsubscribe(&self, topic: Topic) -> Result<(), ClientError> {
+        self.require(&extension::SUBSCRIPTIONS)?;
+        if !self.broker.remove(topic) {
+            return Ok(());
+        }
+        self.topic_request(ProtocolRequest::Unsubscribe { topic })
             .await
-            .map_err(|e| ClientError::Channel(format!("Failed to receive response: {}", e)))?;
+    }
 
-        Ok(response)
+    async fn topic_request(&self, request: ProtocolRequest) -> Result<(), ClientError> {
+        match self.send_uncached_request(request).await? {
+            ProtocolResponse::Success => Ok(()),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    fn connection(&self) -> Result<&Connection, ClientError> {
+        self.connection
+            .as_ref()
+            .ok_or_else(|| ClientError::Connection("Not connected".to_string()))
     }
 
     /// Start port forwarding
@@ -141,11 +753,7 @@ impl<T: Transport> Client<T> {
         remote_host: String,
         remote_port: u16,
     ) -> Result<(), ClientError> {
-        let request = ProtocolRequest::StartPortForward {
-            local_port,
-            remote_host: remote_host.clone(),
-            remote_port,
-        };
+        let request = ProtocolRequest::start_port_forward(local_port, &remote_host, remote_port)?;
 
         match self.send_request(request).await? {
             ProtocolResponse::Success => {
@@ -153,33 +761,91 @@ impl<T: Transport> Client<T> {
                     "Port forwarding started: {} -> {}:{}",
                     local_port, remote_host, remote_port
                 );
+                // The forward itself works; only local reachability is degraded
+                if let Err(e) = self.transport().forward_started(local_port).await {
+                    warn!("Failed to expose port {}: {}", local_port, e);
+                }
                 Ok(())
             }
-            ProtocolResponse::Error { message } => Err(ClientError::RemoteExecution(message)),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
             _ => Err(ClientError::Channel("Unexpected response type".to_string())),
         }
     }
 
     /// Stop port forwarding
     pub async fn stop_port_forward(&self, local_port: u16) -> Result<(), ClientError> {
-        let request = ProtocolRequest::StopPortForward { local_port };
+        let request = ProtocolRequest::stop_port_forward(local_port)?;
 
         match self.send_request(request).await? {
             ProtocolResponse::Success => {
                 info!("Port forwarding stopped for port {}", local_port);
+                if let Err(e) = self.transport().forward_stopped(local_port).await {
+                    warn!("Failed to unexpose port {}: {}", local_port, e);
+                }
                 Ok(())
             }
-            ProtocolResponse::Error { message } => Err(ClientError::RemoteExecution(message)),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// Stop the remote accepting connections on `local_port`, leaving those
+    /// it accepted running until they close
+    pub async fn release_port_forward(&self, local_port: u16) -> Result<(), ClientError> {
+        let request = ProtocolRequest::ReleasePortForward { local_port };
+
+        match self.send_request(request).await? {
+            ProtocolResponse::Success => Ok(()),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
             _ => Err(ClientError::Channel("Unexpected response type".to_string())),
         }
     }
 
     /// Send port forward data
+    ///
+    /// With flow control, waits for the connection's credit and sends the
+    /// data in as many requests as it takes.
     pub async fn send_port_forward_data(
         &self,
         connection_id: u32,
-        data: Bytes,
+        mut data: Bytes,
     ) -> Result<(), ClientError> {
+        let Some(credit) = self.credit(connection_id) else {
+            return self.send_data(connection_id, data).await;
+        };
+        while !data.is_empty() {
+            let chunk = data.split_to(credit.available().await.min(data.len()));
+            credit.consume(chunk.len());
+            self.send_data(connection_id, chunk).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_data(&self, connection_id: u32, data: Bytes) -> Result<(), ClientError> {
         let request = ProtocolRequest::PortForwardData {
             connection_id,
             data,
@@ -187,7 +853,15 @@ impl<T: Transport> Client<T> {
 
         match self.send_request(request).await? {
             ProtocolResponse::Success => Ok(()),
-            ProtocolResponse::Error { message } => Err(ClientError::RemoteExecution(message)),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
             _ => Err(ClientError::Channel("Unexpected response type".to_string())),
         }
     }
@@ -197,9 +871,10 @@ impl<T: Transport> Client<T> {
         let request = ProtocolRequest::GetClipboard;
 
         match self.send_request(request).await? {
-            ProtocolResponse::Data { items } => {
+            ProtocolResponse::Data { items, .. } => {
                 for item in items {
                     if let ResponseItem::ClipboardContent { content } = item {
+                        self.clipboard_dedup.lock().unwrap().took(&content);
                         return Ok(content);
                     }
                 }
@@ -207,58 +882,841 @@ impl<T: Transport> Client<T> {
                     "No clipboard content in response".to_string(),
                 ))
             }
-            ProtocolResponse::Error { message } => Err(ClientError::RemoteExecution(message)),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
             _ => Err(ClientError::Channel("Unexpected response type".to_string())),
         }
     }
 
     /// Set clipboard content
+    ///
+    /// The content is sent even if the remote is known to hold it, as it may
+    /// have changed there since; automatic syncs use
+    /// [`sync_clipboard`](Self::sync_clipboard) instead.
     pub async fn set_clipboard(&self, content: String) -> Result<(), ClientError> {
+        self.clipboard_dedup.lock().unwrap().sent(&content);
+        self.send_clipboard(content).await
+    }
+
+    /// Sync local clipboard content to the remote
+    ///
+    /// Content the remote is known to hold, as it was last set or read, is
+    /// not sent again, so a watcher of the local clipboard does not bounce
+    /// text back to where it came from.
+    pub async fn sync_clipboard(&self, content: String) -> Result<(), ClientError> {
+        if !self.clipboard_dedup.lock().unwrap().should_send(&content) {
+            debug!("Remote clipboard already holds the content, not sending it");
+            return Ok(());
+        }
+        self.send_clipboard(content).await
+    }
+
+    async fn send_clipboard(&self, content: String) -> Result<(), ClientError> {
         let request = ProtocolRequest::SetClipboard { content };
 
+        let result = match self.send_request(request).await {
+            Ok(ProtocolResponse::Success) => Ok(()),
+            Ok(ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            }) => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            Ok(_) => Err(ClientError::Channel("Unexpected response type".to_string())),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            self.clipboard_dedup.lock().unwrap().forget();
+        }
+        result
+    }
+
+    /// Get clipboard content in the best of the accepted formats (most preferred first)
+    ///
+    /// Returns `None` if the remote clipboard is empty.
+    pub async fn get_clipboard_data(
+        &self,
+        accept: Vec<ClipboardFormat>,
+    ) -> Result<Option<ClipboardItem>, ClientError> {
+        let request = ProtocolRequest::get_clipboard_data(accept)?;
+
+        match self.send_request(request).await? {
+            ProtocolResponse::Data { items, .. } => {
+                Ok(items.into_iter().find_map(|item| match item {
+                    ResponseItem::ClipboardData { item } => Some(item),
+                    _ => None,
+                }))
+            }
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// Set clipboard content with one or more representations
+    pub async fn set_clipboard_data(&self, items: Vec<ClipboardItem>) -> Result<(), ClientError> {
+        let request = ProtocolRequest::set_clipboard_data(items)?;
+        // Whether or not this succeeds, the remote may hold other text now
+        self.clipboard_dedup.lock().unwrap().forget();
+
         match self.send_request(request).await? {
             ProtocolResponse::Success => Ok(()),
-            ProtocolResponse::Error { message } => Err(ClientError::RemoteExecution(message)),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
             _ => Err(ClientError::Channel("Unexpected response type".to_string())),
         }
     }
 
     /// Open browser with URL
     pub async fn open_browser(&self, url: String) -> Result<(), ClientError> {
-        let request = ProtocolRequest::OpenBrowser { url };
+        let request = ProtocolRequest::open_browser(url)?;
 
         match self.send_request(request).await? {
             ProtocolResponse::Success => Ok(()),
-            ProtocolResponse::Error { message } => Err(ClientError::RemoteExecution(message)),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// List the remote files under the given paths
+    pub async fn list_files(
+        &self,
+        paths: Vec<String>,
+        query: ListQuery,
+    ) -> Result<Vec<RemoteFile>, ClientError> {
+        let mut files = Vec::new();
+        self.list_files_streamed(paths, query, |file| files.push(file))
+            .await?;
+        Ok(files)
+    }
+
+    /// List remote files, passing each to `on_file` as its batch arrives
+    pub async fn list_files_streamed<F>(
+        &self,
+        paths: Vec<String>,
+        query: ListQuery,
+        mut on_file: F,
+    ) -> Result<(), ClientError>
+    where
+        F: FnMut(RemoteFile),
+    {
+        let request = ProtocolRequest::list_files(paths).query(query).build()?;
+
+        self.send_streaming_request(request, |items| {
+            for item in items {
+                if let ResponseItem::FileEntry {
+                    path,
+                    relative_path,
+                    size,
+                } = item
+                {
+                    on_file(RemoteFile {
+                        path,
+                        relative_path,
+                        size,
+                    });
+                }
+            }
+        })
+        .await
+    }
+
+    /// List the entries of the remote directory `path` in name order,
+    /// without descending into them
+    pub async fn list_directory(&self, path: &str) -> Result<Vec<RemoteEntry>, ClientError> {
+        self.require(&extension::LIST_DIRECTORY)?;
+        let request = ProtocolRequest::list_directory(path)?;
+
+        let mut entries = Vec::new();
+        self.send_streaming_request(request, |items| {
+            entries.extend(items.into_iter().filter_map(|item| match item {
+                ResponseItem::DirectoryEntry { name, dir } => Some(RemoteEntry { name, dir }),
+                _ => None,
+            }))
+        })
+        .await?;
+        Ok(entries)
+    }
+
+    /// Hash the remote file at `path`, or every regular file under it, with
+    /// `algo`
+    pub async fn hash_path(
+        &self,
+        path: &str,
+        algo: HashAlgorithm,
+    ) -> Result<Vec<FileHash>, ClientError> {
+        self.require(&extension::HASH_PATH)?;
+        let request = ProtocolRequest::hash_path(path, algo)?;
+
+        let mut hashes = Vec::new();
+        self.send_streaming_request(request, |items| {
+            hashes.extend(items.into_iter().filter_map(|item| match item {
+                ResponseItem::FileHash {
+                    relative_path,
+                    size,
+                    hash,
+                    ..
+                } => Some(FileHash {
+                    relative_path,
+                    size,
+                    hash,
+                }),
+                _ => None,
+            }));
+        })
+        .await?;
+        Ok(hashes)
+    }
+
+    /// Sum the sizes under the remote `path` per directory, `depth` levels
+    /// down, passing the entries and bytes scanned so far to `on_progress`
+    /// while a large tree is scanned
+    pub async fn disk_usage(
+        &self,
+        path: &str,
+        depth: u32,
+        mut on_progress: impl FnMut(u64, u64),
+    ) -> Result<Vec<DirectoryUsage>, ClientError> {
+        self.require(&extension::DISK_USAGE)?;
+        let request = ProtocolRequest::disk_usage(path, depth)?;
+
+        let mut usages = Vec::new();
+        self.send_streaming_request(request, |items| {
+            for item in items {
+                match item {
+                    ResponseItem::DirectoryUsage { usage } => usages.push(usage),
+                    ResponseItem::DiskUsageProgress { entries, size } => on_progress(entries, size),
+                    _ => {}
+                }
+            }
+        })
+        .await?;
+        Ok(usages)
+    }
+
+    /// List the remote's active port forwards in the page selected by `query`
+    pub async fn list_port_forwards(
+        &self,
+        query: ListQuery,
+    ) -> Result<Vec<PortForwardEntry>, ClientError> {
+        match self
+            .send_request(ProtocolRequest::ListPortForwards { query })
+            .await?
+        {
+            ProtocolResponse::Data { items, .. } => Ok(items
+                .into_iter()
+                .filter_map(|item| match item {
+                    ResponseItem::PortForward { forward } => Some(forward),
+                    _ => None,
+                })
+                .collect()),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// Read a chunk of a remote file, returning the data and whether the end was reached
+    pub async fn read_file_chunk(
+        &self,
+        path: String,
+        offset: u64,
+        len: u32,
+    ) -> Result<(Bytes, bool), ClientError> {
+        let request = ProtocolRequest::read_file_chunk(&path, offset, len)?;
+
+        match self.send_request(request).await? {
+            ProtocolResponse::Data { items, .. } => {
+                let (data, crc32c, eof) = items
+                    .into_iter()
+                    .find_map(|item| match item {
+                        ResponseItem::FileChunk {
+                            data, crc32c, eof, ..
+                        } => Some((data, crc32c, eof)),
+                        _ => None,
+                    })
+                    .ok_or_else(|| ClientError::Channel("No file chunk in response".to_string()))?;
+                if checksum::crc32c(&data) != crc32c {
+                    return Err(ClientError::Channel(format!(
+                        "Checksum mismatch in {} at offset {}",
+                        path, offset
+                    )));
+                }
+                Ok((data, eof))
+            }
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// Read a range of a remote file, returning the data and the size of the
+    /// file; a `len` of 0 only gets the size
+    pub async fn read_file_range(
+        &self,
+        path: &str,
+        offset: u64,
+        len: u32,
+    ) -> Result<(Bytes, u64), ClientError> {
+        self.require(&extension::FILE_RANGE)?;
+        let request = ProtocolRequest::read_file_range(path, offset, len)?;
+
+        match self.send_request(request).await? {
+            ProtocolResponse::Data { items, .. } => {
+                let (data, crc32c, size) = items
+                    .into_iter()
+                    .find_map(|item| match item {
+                        ResponseItem::FileRange {
+                            data, crc32c, size, ..
+                        } => Some((data, crc32c, size)),
+                        _ => None,
+                    })
+                    .ok_or_else(|| ClientError::Channel("No file range in response".to_string()))?;
+                if checksum::crc32c(&data) != crc32c {
+                    return Err(ClientError::Channel(format!(
+                        "Checksum mismatch in {} at offset {}",
+                        path, offset
+                    )));
+                }
+                Ok((data, size))
+            }
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// Write a chunk of an upload of a remote file; the chunk marked `last`
+    /// replaces the file with the uploaded content, unless the file no longer
+    /// has `expected_digest`
+    pub async fn write_file_chunk(
+        &self,
+        path: String,
+        offset: u64,
+        data: Bytes,
+        last: bool,
+        expected_digest: Option<String>,
+    ) -> Result<(), ClientError> {
+        let request = ProtocolRequest::write_file_chunk(path, offset, data, last, expected_digest)?;
+
+        match self.send_request(request).await? {
+            ProtocolResponse::Success => Ok(()),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// Remove the remote `path`, moving it to the remote's trash when
+    /// `trash` is set; returns the trash entry it became
+    pub async fn remove_path(
+        &self,
+        path: &str,
+        trash: bool,
+    ) -> Result<Option<TrashEntry>, ClientError> {
+        self.require(&extension::FILE_TRASH)?;
+        let request = ProtocolRequest::remove_path(path, trash)?;
+        trashed(self.send_request(request).await?)
+    }
+
+    /// Move the remote `from` to `to`, replacing a file there; with `trash`
+    /// set, what is replaced is moved to the remote's trash and its entry
+    /// returned
+    pub async fn move_path(
+        &self,
+        from: &str,
+        to: &str,
+        trash: bool,
+    ) -> Result<Option<TrashEntry>, ClientError> {
+        self.require(&extension::FILE_TRASH)?;
+        let request = ProtocolRequest::move_path(from, to, trash)?;
+        trashed(self.send_request(request).await?)
+    }
+
+    /// Move the trash entry `id` back to where it was trashed from, or to
+    /// `to`
+    pub async fn restore_path(&self, id: &str, to: Option<String>) -> Result<(), ClientError> {
+        self.require(&extension::FILE_TRASH)?;
+        let request = ProtocolRequest::restore_path(id, to)?;
+        match self.send_request(request).await? {
+            ProtocolResponse::Success => Ok(()),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// List the entries of the remote's trash, oldest first
+    pub async fn list_trash(&self) -> Result<Vec<TrashEntry>, ClientError> {
+        self.require(&extension::FILE_TRASH)?;
+        match self.send_request(ProtocolRequest::ListTrash).await? {
+            ProtocolResponse::Data { items, .. } => Ok(items
+                .into_iter()
+                .filter_map(|item| match item {
+                    ResponseItem::Trashed { entry } => Some(entry),
+                    _ => None,
+                })
+                .collect()),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// Start a GUI application on the remote desktop session under
+    /// `sandbox` and return its process id; unset display fields are
+    /// detected on the remote
+    pub async fn launch_app(
+        &self,
+        command: Vec<String>,
+        display_env: DisplayEnv,
+        sandbox: Sandbox,
+    ) -> Result<u32, ClientError> {
+        self.require(&extension::APP_LAUNCH)?;
+        let request = ProtocolRequest::launch_app(command)
+            .display_env(display_env)
+            .sandbox(sandbox)
+            .build()?;
+        match self.send_request(request).await? {
+            ProtocolResponse::Data { items, .. } => items
+                .into_iter()
+                .find_map(|item| match item {
+                    ResponseItem::AppLaunched { pid } => Some(pid),
+                    _ => None,
+                })
+                .ok_or_else(|| ClientError::Channel("Missing launched process".to_string())),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// Get the requests recorded in the remote's slow log, newest first
+    pub async fn get_slow_log(&self) -> Result<Vec<SlowRequest>, ClientError> {
+        self.require(&extension::SLOW_LOG)?;
+        match self.send_request(ProtocolRequest::GetSlowLog).await? {
+            ProtocolResponse::Data { items, .. } => Ok(items
+                .into_iter()
+                .filter_map(|item| match item {
+                    ResponseItem::SlowRequest { request } => Some(request),
+                    _ => None,
+                })
+                .collect()),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// Get the connections recorded in the remote's access log, newest first
+    pub async fn get_access_log(&self) -> Result<Vec<AccessEntry>, ClientError> {
+        self.require(&extension::ACCESS_LOG)?;
+        match self.send_request(ProtocolRequest::GetAccessLog).await? {
+            ProtocolResponse::Data { items, .. } => Ok(items
+                .into_iter()
+                .filter_map(|item| match item {
+                    ResponseItem::Access { entry } => Some(entry),
+                    _ => None,
+                })
+                .collect()),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// Locate `tools` (or the common ones when empty) on the remote and get
+    /// their versions
+    pub async fn probe_tools(&self, tools: Vec<String>) -> Result<Vec<ToolInfo>, ClientError> {
+        self.require(&extension::TOOL_PROBE)?;
+        match self
+            .send_request(ProtocolRequest::probe_tools(tools)?)
+            .await?
+        {
+            ProtocolResponse::Data { items, .. } => Ok(items
+                .into_iter()
+                .filter_map(|item| match item {
+                    ResponseItem::Tool { tool } => Some(tool),
+                    _ => None,
+                })
+                .collect()),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// List the background tasks the remote runs for this session
+    pub async fn list_tasks(&self) -> Result<Vec<TaskInfo>, ClientError> {
+        self.require(&extension::TASKS)?;
+        match self.send_request(ProtocolRequest::ListTasks).await? {
+            ProtocolResponse::Data { items, .. } => Ok(items
+                .into_iter()
+                .filter_map(|item| match item {
+                    ResponseItem::Task { task } => Some(task),
+                    _ => None,
+                })
+                .collect()),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// Cancel the background task `id` of this session
+    pub async fn cancel_task(&self, id: TaskId) -> Result<(), ClientError> {
+        self.require(&extension::TASKS)?;
+        match self
+            .send_request(ProtocolRequest::CancelTask { id })
+            .await?
+        {
+            ProtocolResponse::Success => Ok(()),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// Get the resources used by this client and its quotas
+    pub async fn get_usage(&self) -> Result<Usage, ClientError> {
+        match self.send_request(ProtocolRequest::GetUsage).await? {
+            ProtocolResponse::Data { items, .. } => items
+                .into_iter()
+                .find_map(|item| match item {
+                    ResponseItem::Usage { usage } => Some(usage),
+                    _ => None,
+                })
+                .ok_or_else(|| ClientError::Channel("Missing usage".to_string())),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// Make the remote delay, drop or fail the next `count` requests of
+    /// `kind` (see [`ProtocolRequest::kind`])
+    #[cfg(feature = "fault-injection")]
+    pub async fn inject_fault(
+        &self,
+        kind: &str,
+        fault: yuha_core::protocol::fault::Fault,
+        count: u32,
+    ) -> Result<(), ClientError> {
+        self.require(&extension::FAULT_INJECTION)?;
+        let request = ProtocolRequest::InjectFault {
+            kind: kind.to_string(),
+            fault,
+            count,
+        };
+        match self.send_request(request).await? {
+            ProtocolResponse::Success => Ok(()),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// Snapshot the forwards and topic subscriptions of the remote session
+    pub async fn export_session_state(&self) -> Result<SessionState, ClientError> {
+        match self
+            .send_request(ProtocolRequest::ExportSessionState)
+            .await?
+        {
+            ProtocolResponse::Data { items, .. } => items
+                .into_iter()
+                .find_map(|item| match item {
+                    ResponseItem::SessionState { state } => Some(state),
+                    _ => None,
+                })
+                .ok_or_else(|| ClientError::Channel("Missing session state".to_string())),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// Re-apply a snapshot taken by [`Self::export_session_state`], possibly
+    /// of another host
+    pub async fn import_session_state(&self, state: SessionState) -> Result<(), ClientError> {
+        let ports: Vec<_> = state
+            .forwards
+            .iter()
+            .map(|forward| forward.local_port)
+            .collect();
+        match self
+            .send_request(ProtocolRequest::ImportSessionState { state })
+            .await?
+        {
+            ProtocolResponse::Success => {
+                notify_forwards(&*self.transport(), &ports, true).await;
+                Ok(())
+            }
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// Credit to send on a forwarded connection, `None` without flow control
+    fn credit(&self, connection_id: u32) -> Option<Arc<Credit>> {
+        if !self.extensions.contains(&extension::FLOW_CONTROL.id) {
+            return None;
+        }
+        let mut credits = self.credits.lock().unwrap();
+        let credit = credits
+            .entry(connection_id)
+            .or_insert_with(|| Arc::new(Credit::new(INITIAL_CREDIT)));
+        Some(credit.clone())
+    }
+
+    /// Let the remote send `bytes` more of a connection's data, once what
+    /// was polled of it has been consumed
+    pub async fn grant_credit(&self, connection_id: u32, bytes: u32) -> Result<(), ClientError> {
+        self.require(&extension::FLOW_CONTROL)?;
+        let request = ProtocolRequest::GrantCredit {
+            connection_id,
+            bytes,
+        };
+
+        match self.send_request(request).await? {
+            ProtocolResponse::Success => Ok(()),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
             _ => Err(ClientError::Channel("Unexpected response type".to_string())),
         }
     }
 
     /// Poll for data (used for simulating bidirectional communication)
+    ///
+    /// `Credit` items are applied to the connections' credits rather than
+    /// returned, and pushed `ClipboardContent` the remote is known to hold
+    /// already, like text this client just set, is dropped.
     pub async fn poll_data(&self) -> Result<Vec<ResponseItem>, ClientError> {
         let request = ProtocolRequest::PollData;
 
         match self.send_request(request).await? {
-            ProtocolResponse::Data { items } => Ok(items),
-            ProtocolResponse::Error { message } => Err(ClientError::RemoteExecution(message)),
+            ProtocolResponse::Data { items, .. } => Ok(items
+                .into_iter()
+                .filter(|item| match *item {
+                    ResponseItem::Credit {
+                        connection_id,
+                        bytes,
+                    } => {
+                        if let Some(credit) = self.credit(connection_id) {
+                            credit.grant(bytes as usize);
+                        }
+                        false
+                    }
+                    ResponseItem::CloseConnection { connection_id } => {
+                        self.credits.lock().unwrap().remove(&connection_id);
+                        true
+                    }
+                    ResponseItem::ClipboardContent { ref content } => {
+                        self.clipboard_dedup.lock().unwrap().should_take(content)
+                    }
+                    _ => true,
+                })
+                .collect()),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
             _ => Err(ClientError::Channel("Unexpected response type".to_string())),
         }
     }
 
     /// Start polling loop for receiving data from remote
+    ///
+    /// With flow control, the credit of the data `handler` consumed is
+    /// granted back after each poll.
     pub async fn start_polling_loop<F>(&self, mut handler: F) -> Result<(), ClientError>
     where
         F: FnMut(ResponseItem) -> bool + Send + 'static,
     {
+        let flow_control = self.extensions.contains(&extension::FLOW_CONTROL.id);
         loop {
             match self.poll_data().await {
                 Ok(items) => {
+                    let mut consumed = HashMap::new();
                     for item in items {
+                        if let ResponseItem::PortForwardData {
+                            connection_id,
+                            data,
+                        } = &item
+                        {
+                            *consumed.entry(*connection_id).or_insert(0) += data.len() as u32;
+                        }
                         let should_continue = handler(item);
                         if !should_continue {
                             return Ok(());
                         }
                     }
+                    if flow_control {
+                        for (connection_id, bytes) in consumed {
+                            if let Err(e) = self.grant_credit(connection_id, bytes).await {
+                                warn!(
+                                    "Error granting credit to connection {}: {}",
+                                    connection_id, e
+                                );
+                            }
+                        }
+                    }
                 }
                 Err(e) => {
                     warn!("Error polling data: {}", e);
@@ -270,6 +1728,100 @@ impl<T: Transport> Client<T> {
     }
 }
 
+#[async_trait]
+impl<T: Transport> CommandSender for Client<T> {
+    type Error = ClientError;
+
+    async fn call<C: Command>(&self, command: C) -> Result<C::Output, ClientError> {
+        match self.send_request(command.request()?).await? {
+            ProtocolResponse::Data { items, .. } => {
+                C::output(items).map_err(|e| ClientError::Channel(e.to_string()))
+            }
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+}
+
+/// Trash entry of a `RemovePath` or `MovePath` response, if it trashed
+/// anything
+fn trashed(response: ProtocolResponse) -> Result<Option<TrashEntry>, ClientError> {
+    match response {
+        ProtocolResponse::Success => Ok(None),
+        ProtocolResponse::Data { items, .. } => Ok(items.into_iter().find_map(|item| match item {
+            ResponseItem::Trashed { entry } => Some(entry),
+            _ => None,
+        })),
+        ProtocolResponse::Error {
+            message,
+            code,
+            details,
+        } => Err(ClientError::RemoteExecution {
+            code,
+            message,
+            details,
+        }),
+        _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+    }
+}
+
+/// Tell `transport` that the forwards on `ports` started, or stopped
+/// Hold `connection` until the remote's connections accepted on `ports`
+/// closed, as they end with its session
+async fn drain(connection: Connection, ports: Vec<u16>) {
+    loop {
+        let response = match connection.submit(ProtocolRequest::ListTasks).await {
+            Ok(mut responses) => responses.next().await,
+            Err(e) => Err(e),
+        };
+        let live = match response {
+            Ok(ProtocolResponse::Data { items, .. }) => items.iter().any(|item| {
+                matches!(
+                    item,
+                    ResponseItem::Task { task } if matches!(
+                        task.kind,
+                        TaskKind::Connection { local_port, .. } if ports.contains(&local_port)
+                    )
+                )
+            }),
+            Ok(response) => {
+                debug!("Stopped draining the handed off connection: {:?}", response);
+                false
+            }
+            Err(e) => {
+                debug!("Stopped draining the handed off connection: {}", e);
+                false
+            }
+        };
+        if !live {
+            break;
+        }
+        tokio::time::sleep(DRAIN_INTERVAL).await;
+    }
+}
+
+async fn notify_forwards<T: Transport>(transport: &T, ports: &[u16], started: bool) {
+    for &port in ports {
+        let result = if started {
+            transport.forward_started(port).await
+        } else {
+            transport.forward_stopped(port).await
+        };
+        if let Err(e) = result {
+            let action = if started { "expose" } else { "unexpose" };
+            warn!("Failed to {} port {}: {}", action, port, e);
+        }
+    }
+}
+
 /// Helper function to create a client with local transport
 pub async fn connect_local(
     binary_path: std::path::PathBuf,
@@ -280,6 +1832,7 @@ pub async fn connect_local(
     let local_config = LocalTransportConfig {
         binary_path,
         args: vec!["--stdio".to_string()],
+        isolated: false,
     };
 
     let transport = LocalTransport::new(local_config, transport_config);
@@ -306,6 +1859,12 @@ pub async fn connect_ssh_transport(
         username: username.to_string(),
         password: password.map(|s| s.to_string()),
         key_path: key_path.map(|p| p.to_path_buf()),
+        cert_path: None,
+        cert_principal: None,
+        pkcs11: None,
+        connection_timeout: std::time::Duration::from_secs(30),
+        socket: Default::default(),
+        algorithms: Default::default(),
     };
 
     let transport = SshTransport::new(ssh_config, transport_config);
@@ -314,3 +1873,707 @@ pub async fn connect_ssh_transport(
 
     Ok(client)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::DuplexTransport;
+    use std::path::Path;
+    use std::time::SystemTime;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::task::JoinHandle;
+
+    /// Answer the handshake, reporting `hash` as the binary hash and hosting
+    /// only the workspace `web`
+    async fn serve_hello(stream: DuplexStream, hash: Option<String>) {
+        let mut channel = MessageChannel::new_with_stream(stream);
+        while let Ok(request) = channel.receive_request().await {
+            let response = match request {
+                ProtocolRequest::Hello {
+                    workspace: Some(workspace),
+                    ..
+                } if workspace != "web" => ProtocolResponse::error(
+                    ErrorCode::PermissionDenied,
+                    format!("Unknown workspace {}", workspace),
+                ),
+                ProtocolRequest::Hello { .. } => ProtocolResponse::Data {
+                    items: std::iter::once(ResponseItem::Extensions {
+                        extensions: Vec::new(),
+                    })
+                    .chain(hash.clone().map(|hash| ResponseItem::BinaryHash { hash }))
+                    .collect(),
+                    next: None,
+                },
+                _ => ProtocolResponse::error(ErrorCode::Unsupported, "unsupported"),
+            };
+            channel.send_response(&response).await.unwrap();
+        }
+    }
+
+    fn client(binary: &Path, reported: Option<&str>) -> Client<DuplexTransport> {
+        let (client_stream, server_stream) = tokio::io::duplex(1 << 16);
+        tokio::spawn(serve_hello(server_stream, reported.map(str::to_string)));
+        Client::new(DuplexTransport::new(client_stream).with_remote_binary(binary))
+    }
+
+    #[tokio::test]
+    async fn test_connect_verifies_binary_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = dir.path().join("yuha-remote");
+        std::fs::write(&binary, b"remote build").unwrap();
+        let hash = checksum::digest_file(&binary).unwrap();
+
+        assert!(client(&binary, Some(&hash)).connect().await.is_ok());
+        assert!(client(&binary, Some("modified")).connect().await.is_err());
+        assert!(client(&binary, None).connect().await.is_err());
+        assert!(
+            client(&binary, Some("modified"))
+                .allow_modified_binary(true)
+                .connect()
+                .await
+                .is_ok()
+        );
+
+        // A configured hash takes precedence over the deployed binary
+        assert!(
+            client(&binary, Some("pinned"))
+                .with_binary_hash("pinned")
+                .connect()
+                .await
+                .is_ok()
+        );
+        // Binaries without a local copy cannot be verified
+        let missing = dir.path().join("missing");
+        assert!(client(&missing, Some("any")).connect().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_connect_selects_workspace() {
+        // Without a local copy of the binary, no hash is required
+        let missing = Path::new("/nonexistent/yuha-remote");
+        let mut web = client(missing, None).with_workspace("web");
+        assert!(web.connect().await.is_ok());
+        let mut ops = client(missing, None).with_workspace("ops");
+        assert!(ops.connect().await.is_err());
+    }
+
+    /// Session recorded with a remote predating extension negotiation, see
+    /// `yuha-core`'s wire tests
+    const SESSION_WITHOUT_EXTENSIONS: &str =
+        include_str!("../../core/src/tests/golden/v9/session-without-extensions.golden");
+
+    /// Answer each request of the kind recorded with what the recorded remote
+    /// answered, its sealed frames sent as plain ones
+    async fn serve_recording(stream: DuplexStream, recording: &str) {
+        let mut channel = MessageChannel::new_with_stream(stream);
+        for line in recording.lines() {
+            let Some((direction, frame)) = line
+                .split_once(" sealed ")
+                .and_then(|(direction, sealed)| Some((direction, sealed.splitn(3, ' ').nth(2)?)))
+            else {
+                continue;
+            };
+            let payload = frame.strip_prefix("json ").expect("Recorded attachment");
+            if direction == ">" {
+                let Ok(request) = channel.receive_request().await else {
+                    return;
+                };
+                let recorded: ProtocolRequest = serde_json::from_str(payload).unwrap();
+                assert_eq!(request.kind(), recorded.kind());
+            } else {
+                channel
+                    .send(Bytes::copy_from_slice(payload.as_bytes()))
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_without_extension_negotiation() {
+        let (client_stream, server_stream) = tokio::io::duplex(1 << 16);
+        tokio::spawn(serve_recording(server_stream, SESSION_WITHOUT_EXTENSIONS));
+        let mut client = Client::new(DuplexTransport::new(client_stream));
+        client.connect().await.unwrap();
+        assert!(client.extensions().is_empty());
+        let entries = client
+            .list_files(vec!["/data".to_string()], ListQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+
+        // Without negotiation no workspace can be selected
+        let (client_stream, server_stream) = tokio::io::duplex(1 << 16);
+        tokio::spawn(serve_recording(server_stream, SESSION_WITHOUT_EXTENSIONS));
+        let mut client = Client::new(DuplexTransport::new(client_stream)).with_workspace("web");
+        assert!(client.connect().await.is_err());
+    }
+
+    /// Serve a session holding `state`: its export and import, and
+    /// `GetClipboard`; returns the state once closed
+    async fn serve_session(stream: DuplexStream, mut state: SessionState) -> SessionState {
+        let mut channel = MessageChannel::new_with_stream(stream);
+        while let Ok(request) = channel.receive_request().await {
+            let response = match request {
+                ProtocolRequest::Hello { .. } => ProtocolResponse::Data {
+                    items: vec![ResponseItem::Extensions {
+                        extensions: Vec::new(),
+                    }],
+                    next: None,
+                },
+                ProtocolRequest::ExportSessionState => ProtocolResponse::Data {
+                    items: vec![ResponseItem::SessionState {
+                        state: state.clone(),
+                    }],
+                    next: None,
+                },
+                ProtocolRequest::ImportSessionState { state: imported } => {
+                    state = imported;
+                    ProtocolResponse::Success
+                }
+                ProtocolRequest::GetClipboard => ProtocolResponse::Data {
+                    items: vec![ResponseItem::ClipboardContent {
+                        content: "copied".to_string(),
+                    }],
+                    next: None,
+                },
+                _ => ProtocolResponse::error(ErrorCode::Unsupported, "unsupported"),
+            };
+            channel.send_response(&response).await.unwrap();
+        }
+        state
+    }
+
+    #[tokio::test]
+    async fn test_handoff_moves_session_and_clones() {
+        let transport = DuplexTransport::new;
+        let state = SessionState {
+            forwards: vec![PortForwardEntry {
+                local_port: 8080,
+                remote_host: "localhost".to_string(),
+                remote_port: 80,
+            }],
+            topics: vec![Topic::Clipboard],
+        };
+        let (current_stream, current_server) = tokio::io::duplex(1 << 16);
+        let current = tokio::spawn(serve_session(current_server, state.clone()));
+        let (next_stream, next_server) = tokio::io::duplex(1 << 16);
+        let next = tokio::spawn(serve_session(next_server, SessionState::default()));
+
+        let mut client = Client::new(transport(current_stream));
+        client.connect().await.unwrap();
+        let clone = client.clone();
+        client.handoff(transport(next_stream)).await.unwrap();
+
+        // Nothing uses the first connection anymore, so it closed
+        assert_eq!(current.await.unwrap(), state);
+        assert_eq!(clone.get_clipboard().await.unwrap(), "copied");
+        drop((client, clone));
+        assert_eq!(next.await.unwrap(), state);
+    }
+
+    /// Ports of the open connections a [`serve_forwards`] session accepted
+    type Accepted = Arc<std::sync::Mutex<Vec<u16>>>;
+
+    /// Listen on `port` of all interfaces, greeting each connection with
+    /// `name` before echoing it
+    async fn listen(
+        port: u16,
+        name: &'static str,
+        accepted: Accepted,
+    ) -> std::io::Result<JoinHandle<()>> {
+        let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+        Ok(tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                accepted.lock().unwrap().push(port);
+                let accepted = accepted.clone();
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = stream.split();
+                    if writer.write_all(name.as_bytes()).await.is_ok() {
+                        let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                    }
+                    let mut accepted = accepted.lock().unwrap();
+                    let index = accepted.iter().position(|open| *open == port).unwrap();
+                    accepted.remove(index);
+                });
+            }
+        }))
+    }
+
+    /// Serve a session listening on the ports of its forwards like a remote
+    /// on this host; connections accepted outlive the release of their port
+    /// and are listed as tasks
+    async fn serve_forwards(stream: DuplexStream, name: &'static str, state: SessionState) {
+        let accepted = Accepted::default();
+        let mut listeners = HashMap::new();
+        for forward in &state.forwards {
+            let port = forward.local_port;
+            let listener = listen(port, name, accepted.clone()).await.unwrap();
+            listeners.insert(port, listener);
+        }
+
+        let mut channel = MessageChannel::new_with_stream(stream);
+        while let Ok(request) = channel.receive_request().await {
+            let response = match request {
+                ProtocolRequest::Hello { .. } => ProtocolResponse::Data {
+                    items: vec![ResponseItem::Extensions {
+                        extensions: vec![extension::TASKS.id],
+                    }],
+                    next: None,
+                },
+                ProtocolRequest::ExportSessionState => ProtocolResponse::Data {
+                    items: vec![ResponseItem::SessionState {
+                        state: state.clone(),
+                    }],
+                    next: None,
+                },
+                ProtocolRequest::ImportSessionState { state } => {
+                    let mut response = ProtocolResponse::Success;
+                    for forward in state.forwards {
+                        let port = forward.local_port;
+                        match listen(port, name, accepted.clone()).await {
+                            Ok(listener) => {
+                                listeners.insert(port, listener);
+                            }
+                            Err(e) => {
+                                response = ProtocolResponse::error(
+                                    ErrorCode::from_io(e.kind()),
+                                    e.to_string(),
+                                );
+                            }
+                        }
+                    }
+                    response
+                }
+                ProtocolRequest::ReleasePortForward { local_port } => {
+                    if let Some(listener) = listeners.remove(&local_port) {
+                        listener.abort();
+                        let _ = listener.await;
+                    }
+                    ProtocolResponse::Success
+                }
+                ProtocolRequest::ListTasks => {
+                    let accepted = accepted.lock().unwrap();
+                    let items = accepted
+                        .iter()
+                        .enumerate()
+                        .map(|(index, &local_port)| ResponseItem::Task {
+                            task: TaskInfo {
+                                id: index as TaskId,
+                                kind: TaskKind::Connection {
+                                    connection_id: index as u32,
+                                    local_port,
+                                },
+                                session: name.to_string(),
+                                started_at: SystemTime::now(),
+                            },
+                        })
+                        .collect();
+                    ProtocolResponse::Data { items, next: None }
+                }
+                _ => ProtocolResponse::error(ErrorCode::Unsupported, "unsupported"),
+            };
+            channel.send_response(&response).await.unwrap();
+        }
+        for listener in listeners.into_values() {
+            listener.abort();
+        }
+    }
+
+    /// Connect to a forwarded `port`, expecting the greeting of `name`
+    async fn connect_forward(port: u16, name: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut greeting = vec![0; name.len()];
+        stream.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(greeting, name.as_bytes());
+        stream
+    }
+
+    #[tokio::test]
+    async fn test_handoff_on_same_host() {
+        let transport = DuplexTransport::new;
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let state = SessionState {
+            forwards: vec![PortForwardEntry {
+                local_port: port,
+                remote_host: "localhost".to_string(),
+                remote_port: 80,
+            }],
+            topics: Vec::new(),
+        };
+        let (current_stream, current_server) = tokio::io::duplex(1 << 16);
+        let current = tokio::spawn(serve_forwards(current_server, "current", state));
+        let (next_stream, next_server) = tokio::io::duplex(1 << 16);
+        tokio::spawn(serve_forwards(next_server, "next", SessionState::default()));
+
+        let mut client = Client::new(transport(current_stream));
+        client.connect().await.unwrap();
+        let mut live = connect_forward(port, "current").await;
+        client.handoff(transport(next_stream)).await.unwrap();
+
+        // The next remote took the port over, while the connection accepted
+        // before still reaches the current one
+        connect_forward(port, "next").await;
+        live.write_all(b"ping").await.unwrap();
+        let mut echo = [0; 4];
+        live.read_exact(&mut echo).await.unwrap();
+        assert_eq!(&echo, b"ping");
+
+        // The current connection is kept until its forwarded one closed
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!current.is_finished());
+        drop(live);
+        tokio::time::timeout(Duration::from_secs(10), current)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    /// Serve a session with flow control, granting 6 bytes of credit to
+    /// connection 7 on each poll; returns the data chunks received once closed
+    async fn serve_flow(stream: DuplexStream) -> Vec<Bytes> {
+        let mut channel = MessageChannel::new_with_stream(stream);
+        let mut chunks = Vec::new();
+        while let Ok(request) = channel.receive_request().await {
+            let response = match request {
+                ProtocolRequest::Hello { .. } => ProtocolResponse::Data {
+                    items: vec![ResponseItem::Extensions {
+                        extensions: vec![extension::FLOW_CONTROL.id],
+                    }],
+                    next: None,
+                },
+                ProtocolRequest::PortForwardData { data, .. } => {
+                    chunks.push(data);
+                    ProtocolResponse::Success
+                }
+                ProtocolRequest::PollData => ProtocolResponse::Data {
+                    items: vec![
+                        ResponseItem::Credit {
+                            connection_id: 7,
+                            bytes: 6,
+                        },
+                        ResponseItem::CloseConnection { connection_id: 8 },
+                    ],
+                    next: None,
+                },
+                _ => ProtocolResponse::error(ErrorCode::Unsupported, "unsupported"),
+            };
+            channel.send_response(&response).await.unwrap();
+        }
+        chunks
+    }
+
+    #[tokio::test]
+    async fn test_forward_data_waits_for_credit() {
+        let (stream, server) = tokio::io::duplex(1 << 16);
+        let served = tokio::spawn(serve_flow(server));
+        let mut client = Client::new(DuplexTransport::new(stream));
+        client.connect().await.unwrap();
+        client
+            .credits
+            .lock()
+            .unwrap()
+            .insert(7, Arc::new(Credit::new(4)));
+
+        let sending = tokio::spawn({
+            let client = client.clone();
+            async move {
+                client
+                    .send_port_forward_data(7, Bytes::from_static(b"0123456789"))
+                    .await
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert!(!sending.is_finished());
+
+        // Credit items are applied, not handed to the caller
+        let items = client.poll_data().await.unwrap();
+        assert!(matches!(
+            &items[..],
+            [ResponseItem::CloseConnection { connection_id: 8 }]
+        ));
+        sending.await.unwrap().unwrap();
+        drop(client);
+        assert_eq!(served.await.unwrap(), ["0123", "456789"]);
+    }
+
+    /// Serve a clipboard, pushing what was set and then `pasted` on polls;
+    /// returns the contents set once closed
+    async fn serve_clipboard(stream: DuplexStream) -> Vec<String> {
+        let mut channel = MessageChannel::new_with_stream(stream);
+        let mut set = Vec::new();
+        while let Ok(request) = channel.receive_request().await {
+            let response = match request {
+                ProtocolRequest::Hello { .. } => ProtocolResponse::Data {
+                    items: vec![ResponseItem::Extensions {
+                        extensions: Vec::new(),
+                    }],
+                    next: None,
+                },
+                ProtocolRequest::SetClipboard { content } => {
+                    set.push(content);
+                    ProtocolResponse::Success
+                }
+                ProtocolRequest::PollData => ProtocolResponse::Data {
+                    items: set
+                        .iter()
+                        .cloned()
+                        .chain(["pasted".to_string()])
+                        .map(|content| ResponseItem::ClipboardContent { content })
+                        .collect(),
+                    next: None,
+                },
+                _ => ProtocolResponse::error(ErrorCode::Unsupported, "unsupported"),
+            };
+            channel.send_response(&response).await.unwrap();
+        }
+        set
+    }
+
+    #[tokio::test]
+    async fn test_clipboard_dedup() {
+        let (stream, server) = tokio::io::duplex(1 << 16);
+        let served = tokio::spawn(serve_clipboard(server));
+        let mut client = Client::new(DuplexTransport::new(stream));
+        client.connect().await.unwrap();
+
+        client.sync_clipboard("copied".to_string()).await.unwrap();
+        client
+            .clone()
+            .sync_clipboard("copied".to_string())
+            .await
+            .unwrap();
+        // The echo of the text set is dropped, the remote's own copy is not
+        let pushed = client.poll_data().await.unwrap();
+        assert!(matches!(
+            &pushed[..],
+            [ResponseItem::ClipboardContent { content }] if content == "pasted"
+        ));
+        // Text taken from the remote is not synced back
+        client.sync_clipboard("pasted".to_string()).await.unwrap();
+        client.sync_clipboard("copied".to_string()).await.unwrap();
+        // Explicit sets always go out
+        client.set_clipboard("copied".to_string()).await.unwrap();
+        drop(client);
+        assert_eq!(served.await.unwrap(), ["copied", "copied", "copied"]);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_long_poll() {
+        use futures_core::Stream;
+        use std::pin::Pin;
+        use std::time::Duration;
+
+        let client = crate::testing::connect_local(crate::testing::remote_binary())
+            .await
+            .unwrap();
+        // Nothing to poll, so the remote holds the request for seconds
+        let mut poll = client
+            .stream_request(ProtocolRequest::PollData)
+            .await
+            .unwrap();
+        client.cancel(poll.request_id()).await.unwrap();
+        let next = std::future::poll_fn(|cx| Pin::new(&mut poll).poll_next(cx));
+        let ended = tokio::time::timeout(Duration::from_secs(2), next).await;
+        assert!(matches!(
+            ended.expect("long poll not cut short"),
+            Some(Err(ClientError::RemoteExecution {
+                code: ErrorCode::Cancelled,
+                ..
+            }))
+        ));
+        // The session goes on
+        client.set_clipboard("after".to_string()).await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_cancel_file_read() {
+        use futures_core::Stream;
+        use std::pin::Pin;
+        use std::time::Duration;
+
+        let client = crate::testing::connect_local(crate::testing::remote_binary())
+            .await
+            .unwrap();
+        // Opening a FIFO blocks the read until a writer comes
+        let dir = tempfile::tempdir().unwrap();
+        let fifo = dir.path().join("fifo");
+        let made = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap();
+        assert!(made.success());
+        let mut read = client
+            .stream_request(ProtocolRequest::ReadFileChunk {
+                path: fifo.to_string_lossy().into_owned(),
+                offset: 0,
+                len: 16,
+            })
+            .await
+            .unwrap();
+        client.cancel(read.request_id()).await.unwrap();
+        let next = std::future::poll_fn(|cx| Pin::new(&mut read).poll_next(cx));
+        let ended = tokio::time::timeout(Duration::from_secs(2), next).await;
+        assert!(matches!(
+            ended.expect("read not cut short"),
+            Some(Err(ClientError::RemoteExecution {
+                code: ErrorCode::Cancelled,
+                ..
+            }))
+        ));
+        client.set_clipboard("after".to_string()).await.unwrap();
+        // Lets an abandoned open finish; read-write never waits for a reader
+        // in case the cancel arrived before the remote got to open it
+        let mut unblock = std::fs::OpenOptions::new();
+        drop(unblock.read(true).write(true).open(&fifo).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_requests_answered_out_of_order() {
+        let (client_stream, server_stream) = tokio::io::duplex(1 << 16);
+        // Answers only once every request arrived, last one first
+        let server = tokio::spawn(async move {
+            let mut channel = MessageChannel::new_with_stream(server_stream);
+            let (id, _) = channel.receive_request_with_id().await.unwrap();
+            let hello = ProtocolResponse::Data {
+                items: vec![ResponseItem::Extensions {
+                    extensions: Vec::new(),
+                }],
+                next: None,
+            };
+            channel.send_response_with_id(id, &hello).await.unwrap();
+
+            let mut received = Vec::new();
+            for _ in 0..3 {
+                received.push(channel.receive_request_with_id().await.unwrap());
+            }
+            for (id, request) in received.into_iter().rev() {
+                let response = match request {
+                    ProtocolRequest::SetClipboard { content } if content == "bad" => {
+                        ProtocolResponse::error(ErrorCode::PermissionDenied, "bad content")
+                    }
+                    _ => ProtocolResponse::Success,
+                };
+                channel.send_response_with_id(id, &response).await.unwrap();
+            }
+        });
+
+        let mut client = Client::new(DuplexTransport::new(client_stream));
+        client.connect().await.unwrap();
+        let requests = ["first", "bad", "last"].map(|content| ProtocolRequest::SetClipboard {
+            content: content.to_string(),
+        });
+        let mut pending = client.pipeline(requests).await.unwrap().into_iter();
+        let (first, bad, last) = (
+            pending.next().unwrap(),
+            pending.next().unwrap(),
+            pending.next().unwrap(),
+        );
+        assert!(first.request_id().is_some());
+
+        let first = tokio::time::timeout(std::time::Duration::from_secs(5), first).await;
+        assert!(matches!(
+            first.expect("requests not pipelined"),
+            Ok(ProtocolResponse::Success)
+        ));
+        assert!(matches!(
+            bad.await,
+            Err(ClientError::RemoteExecution {
+                code: ErrorCode::PermissionDenied,
+                ..
+            })
+        ));
+        assert!(matches!(last.await, Ok(ProtocolResponse::Success)));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_subscribed_events_pushed() {
+        use std::time::Duration;
+
+        let client = crate::testing::connect_local(crate::testing::remote_binary())
+            .await
+            .unwrap();
+        let mut clipboard = client.subscribe(Topic::Clipboard).await.unwrap();
+        let mut again = client.clone().subscribe(Topic::Clipboard).await.unwrap();
+        let mut files = client.subscribe(Topic::Files).await.unwrap();
+
+        client.set_clipboard("copied".to_string()).await.unwrap();
+        let changed = TopicEvent::ClipboardChanged {
+            text: Some("copied".to_string()),
+        };
+        // Pushed without a request in flight
+        let event = tokio::time::timeout(Duration::from_secs(5), clipboard.recv()).await;
+        assert_eq!(event.expect("event not pushed").unwrap(), changed);
+        assert_eq!(again.recv().await.unwrap(), changed);
+        assert!(files.try_recv().is_err());
+
+        client.unsubscribe(Topic::Clipboard).await.unwrap();
+        assert!(matches!(
+            clipboard.recv().await,
+            Err(broadcast::error::RecvError::Closed)
+        ));
+        client.set_clipboard("later".to_string()).await.unwrap();
+        assert!(client.subscribe(Topic::Files).await.is_ok());
+        assert!(files.try_recv().is_err());
+    }
+
+    /// Client of a `yuha-remote` found on `PATH`, which must be built with
+    /// its `fault-injection` feature
+    #[cfg(feature = "fault-injection")]
+    async fn fault_injection_client() -> Client<crate::transport::LocalTransport> {
+        connect_local("yuha-remote".into(), TransportConfig::default())
+            .await
+            .unwrap()
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[tokio::test]
+    async fn test_injected_error() {
+        use yuha_core::protocol::fault::Fault;
+
+        let client = fault_injection_client().await;
+        let fault = Fault::Error {
+            message: "injected".to_string(),
+        };
+        client.inject_fault("GetClipboard", fault, 1).await.unwrap();
+
+        match client.get_clipboard().await {
+            Err(ClientError::RemoteExecution { message, .. }) => assert_eq!(message, "injected"),
+            other => panic!("expected the injected error, got {:?}", other),
+        }
+        // Only the next request is hit
+        assert!(client.get_clipboard().await.is_ok());
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[tokio::test]
+    async fn test_injected_delay_and_drop() {
+        use std::time::{Duration, Instant};
+        use yuha_core::protocol::fault::Fault;
+
+        let client = fault_injection_client().await;
+        let delay = Duration::from_millis(300);
+        client
+            .inject_fault("SetClipboard", Fault::Delay { delay }, 1)
+            .await
+            .unwrap();
+        let started = Instant::now();
+        client.set_clipboard("delayed".to_string()).await.unwrap();
+        assert!(started.elapsed() >= delay);
+
+        client
+            .inject_fault("GetClipboard", Fault::Drop, 1)
+            .await
+            .unwrap();
+        let dropped =
+            tokio::time::timeout(Duration::from_millis(500), client.get_clipboard()).await;
+        assert!(dropped.is_err(), "dropped request was answered");
+    }
+}
@@ -0,0 +1,234 @@
+//! # Comparing Local and Remote Files
+//!
+//! Hashes a local file or tree and the remote one it should match, then
+//! reports the files that differ, e.g. to verify a sync before or after it
+//! ran. The remote hashes its files itself, so only the hashes are
+//! transferred. Both sides are walked the same way: regular files and links
+//! to them are hashed, links to directories are not followed, and files are
+//! named by their path relative to the compared path.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use yuha_core::checksum::HashAlgorithm;
+
+use crate::ClientError;
+use crate::client_transport::Client;
+use crate::transport::Transport;
+
+/// Hash of a file of a compared tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileHash {
+    /// Path relative to the compared path, empty when that is the file itself
+    pub relative_path: String,
+    pub size: u64,
+    pub hash: String,
+}
+
+/// A file that differs between the local and the remote side
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// Present on both sides with different content
+    Changed {
+        relative_path: String,
+        local_size: u64,
+        remote_size: u64,
+    },
+    /// Only present locally
+    OnlyLocal { relative_path: String },
+    /// Only present on the remote
+    OnlyRemote { relative_path: String },
+}
+
+impl Difference {
+    /// Path of the differing file relative to the compared paths
+    pub fn relative_path(&self) -> &str {
+        match self {
+            Difference::Changed { relative_path, .. }
+            | Difference::OnlyLocal { relative_path }
+            | Difference::OnlyRemote { relative_path } => relative_path,
+        }
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = match self.relative_path() {
+            "" => ".",
+            path => path,
+        };
+        match self {
+            Difference::Changed {
+                local_size,
+                remote_size,
+                ..
+            } => write!(
+                f,
+                "changed      {} ({} bytes local, {} bytes remote)",
+                path, local_size, remote_size
+            ),
+            Difference::OnlyLocal { .. } => write!(f, "only local   {}", path),
+            Difference::OnlyRemote { .. } => write!(f, "only remote  {}", path),
+        }
+    }
+}
+
+/// Hash the local file at `root`, or every regular file under it, in path
+/// order
+///
+/// Reads the files synchronously; call from a blocking context.
+pub fn hash_local(root: &Path, algo: HashAlgorithm) -> io::Result<Vec<FileHash>> {
+    let mut hashes = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(path) = pending.pop() {
+        let metadata = std::fs::symlink_metadata(&path)?;
+        if metadata.is_dir() {
+            let mut children = std::fs::read_dir(&path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<PathBuf>>>()?;
+            // Pushed in reverse so they are popped in path order
+            children.sort_by(|a, b| b.cmp(a));
+            pending.extend(children);
+            continue;
+        }
+
+        let metadata = if metadata.file_type().is_symlink() {
+            std::fs::metadata(&path)?
+        } else {
+            metadata
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        hashes.push(FileHash {
+            relative_path: relative_path(root, &path),
+            size: metadata.len(),
+            hash: algo.hash_file(&path)?,
+        });
+    }
+
+    Ok(hashes)
+}
+
+/// `path` relative to `root`, with `/` separators as the remote reports
+fn relative_path(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Files differing between `local` and `remote`, in path order
+pub fn diff(local: &[FileHash], remote: &[FileHash]) -> Vec<Difference> {
+    let mut remote: BTreeMap<&str, &FileHash> = remote
+        .iter()
+        .map(|file| (file.relative_path.as_str(), file))
+        .collect();
+    let mut differences = Vec::new();
+
+    for file in local {
+        match remote.remove(file.relative_path.as_str()) {
+            Some(other) if other.size == file.size && other.hash == file.hash => {}
+            Some(other) => differences.push(Difference::Changed {
+                relative_path: file.relative_path.clone(),
+                local_size: file.size,
+                remote_size: other.size,
+            }),
+            None => differences.push(Difference::OnlyLocal {
+                relative_path: file.relative_path.clone(),
+            }),
+        }
+    }
+    differences.extend(remote.into_keys().map(|path| Difference::OnlyRemote {
+        relative_path: path.to_string(),
+    }));
+
+    differences.sort_by(|a, b| a.relative_path().cmp(b.relative_path()));
+    differences
+}
+
+impl<T: Transport> Client<T> {
+    /// Compare the local file or tree at `local` with the remote one at
+    /// `remote`, returning the files that differ
+    ///
+    /// Both sides are hashed with `algo` at the same time.
+    pub async fn compare(
+        &self,
+        local: &Path,
+        remote: &str,
+        algo: HashAlgorithm,
+    ) -> Result<Vec<Difference>, ClientError> {
+        let local = local.to_path_buf();
+        let local_hashes = tokio::task::spawn_blocking(move || hash_local(&local, algo));
+        let (local_hashes, remote_hashes) =
+            tokio::join!(local_hashes, self.hash_path(remote, algo));
+
+        let local_hashes = local_hashes
+            .map_err(|e| ClientError::Channel(format!("Local hashing failed: {}", e)))??;
+        Ok(diff(&local_hashes, &remote_hashes?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(relative_path: &str, size: u64, hash: &str) -> FileHash {
+        FileHash {
+            relative_path: relative_path.to_string(),
+            size,
+            hash: hash.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let local = [file("a", 1, "aa"), file("b", 2, "bb"), file("c", 3, "cc")];
+        let remote = [file("a", 1, "aa"), file("b", 2, "b2"), file("d", 4, "dd")];
+        assert_eq!(
+            diff(&local, &remote),
+            [
+                Difference::Changed {
+                    relative_path: "b".to_string(),
+                    local_size: 2,
+                    remote_size: 2,
+                },
+                Difference::OnlyLocal {
+                    relative_path: "c".to_string(),
+                },
+                Difference::OnlyRemote {
+                    relative_path: "d".to_string(),
+                },
+            ]
+        );
+        assert!(diff(&local, &local).is_empty());
+    }
+
+    #[test]
+    fn test_hash_local() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("nested")).unwrap();
+        std::fs::write(dir.path().join("b.txt"), b"bb").unwrap();
+        std::fs::write(dir.path().join("nested/a.txt"), b"aaa").unwrap();
+
+        let hashes = hash_local(dir.path(), HashAlgorithm::Crc32c).unwrap();
+        assert_eq!(
+            hashes,
+            [
+                file("b.txt", 2, &HashAlgorithm::Crc32c.hash(b"bb")),
+                file("nested/a.txt", 3, &HashAlgorithm::Crc32c.hash(b"aaa")),
+            ]
+        );
+
+        let hashes = hash_local(&dir.path().join("b.txt"), HashAlgorithm::Blake3).unwrap();
+        assert_eq!(hashes, [file("", 2, &HashAlgorithm::Blake3.hash(b"bb"))]);
+    }
+}
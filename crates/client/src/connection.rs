@@ -0,0 +1,1022 @@
+//! # Shared Connection
+//!
+//! The message channel of a connected [`Client`](crate::Client) is owned by a
+//! background task. Client handles submit requests to the task, which writes
+//! them in submission order and routes every response back to the handle
+//! waiting for it. Each request is tagged with a [`CorrelationId`] that its
+//! responses echo, so responses are matched whatever order they arrive in;
+//! a streamed request stays pending until its final batch. Untagged
+//! responses, from a remote answering in order, are matched first in, first
+//! out. Requests are written as soon as they are
+//! submitted, even while earlier responses are outstanding, which lets the
+//! remote answer a control request while a long poll (see `PollData`) is
+//! pending.
+//!
+//! Port forward data (see [`ProtocolRequest::is_data`]) takes a lane of its
+//! own with its own flow control: a bounded queue, drained only while fewer
+//! than [`DATA_WINDOW`] data requests await their responses. Control
+//! requests queue apart and are taken first, so a forward whose data the
+//! remote stalls on holds back its producers, never a clipboard or file
+//! request.
+//!
+//! Handles never lock the channel, so any number of tasks can share one
+//! connection, and a caller that stops waiting cannot leave the stream out
+//! of sync for the others.
+//!
+//! A reset of a corrupted channel fails the requests in flight, whose
+//! responses may be lost, and keeps the connection for the next ones.
+//!
+//! A request still in flight can be cancelled by its id (see
+//! [`Responses::id`]): the task sends the remote a cancel control frame,
+//! and the remote answers the request with an error once it stopped
+//! working on it.
+//!
+//! Once told the remote understands it, the task ends the connection with a
+//! close handshake, so the remote sees a clean shutdown rather than a lost
+//! connection.
+//!
+//! Requests of the remote (`server-requests` extension) are told apart
+//! from responses before matching, and go to the listener set with
+//! [`Connection::listen`]; without one, the task answers them `Unhandled`.
+//! Replies are written like any other request, their acknowledgement
+//! dropped. Events of subscribed topics are handed to the broker set with
+//! [`Connection::publish_to`].
+//!
+//! With `flow-control`, see [`Connection::grant_batches`], each `Batch`
+//! response a caller takes is granted back to the remote, which otherwise
+//! stops streaming a few batches ahead of a slow caller.
+//!
+//! Handles can also be moved to another task, i.e. another transport:
+//! [`Connection::pause`] holds new submissions in order while the old task
+//! completes the requests in flight, and the paused submissions then go to
+//! the new task.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{RwLock, RwLockWriteGuard, mpsc};
+use tracing::debug;
+
+use yuha_core::message_channel::MessageChannel;
+use yuha_core::protocol::codec::Codec;
+use yuha_core::protocol::{
+    CorrelationId, ErrorCode, ProtocolRequest, ProtocolResponse, ServerReply, ServerRequestId,
+};
+use yuha_core::{ChannelError, YuhaError};
+
+use crate::ClientError;
+use crate::server_requests::ServerCall;
+use crate::subscriptions::Broker;
+
+type ResponseResult = Result<ProtocolResponse, String>;
+
+/// Data requests in flight at once; later ones wait in the data queue
+pub(crate) const DATA_WINDOW: usize = 16;
+
+/// Data requests queued beyond the window before producers wait
+const DATA_QUEUE_LEN: usize = 64;
+
+enum Submission {
+    Request {
+        id: CorrelationId,
+        request: ProtocolRequest,
+        responses: mpsc::UnboundedSender<ResponseResult>,
+    },
+    Cancel(CorrelationId),
+    /// Send requests of the remote to this listener from now on
+    Listen(mpsc::UnboundedSender<ServerCall>),
+    /// Hand events of subscribed topics to this broker from now on
+    PublishTo(Arc<Broker>),
+    /// Close the channel with a close handshake once the task ends
+    CloseGracefully,
+}
+
+/// Correlation id of the last request submitted by any connection; unique
+/// across connections so submissions held by a pause keep theirs on the
+/// next one
+static LAST_ID: AtomicU64 = AtomicU64::new(0);
+
+impl Submission {
+    /// Submission of `request` and the stream of its responses, granting
+    /// the batches taken to `grants` if set
+    fn request(
+        request: ProtocolRequest,
+        grants: Option<mpsc::UnboundedSender<Submission>>,
+    ) -> (Self, Responses) {
+        let id = next_id();
+        let (responses, receiver) = mpsc::unbounded_channel();
+        let submission = Submission::Request {
+            id,
+            request,
+            responses,
+        };
+        (
+            submission,
+            Responses {
+                id,
+                receiver,
+                grants,
+            },
+        )
+    }
+}
+
+/// The queues of a connection's task
+#[derive(Clone)]
+struct Submissions {
+    /// Control requests and everything else but data, taken first
+    control: mpsc::UnboundedSender<Submission>,
+    /// Port forward data
+    data: mpsc::Sender<Submission>,
+}
+
+/// Receiving ends of [`Submissions`]
+struct Queues {
+    control: mpsc::UnboundedReceiver<Submission>,
+    data: mpsc::Receiver<Submission>,
+}
+
+impl Queues {
+    /// Refuse further submissions
+    fn close(&mut self) {
+        self.control.close();
+        self.data.close();
+    }
+}
+
+/// A request awaiting its responses
+struct InFlight {
+    id: CorrelationId,
+    /// Whether it counts against the [`DATA_WINDOW`]
+    data: bool,
+    responses: mpsc::UnboundedSender<ResponseResult>,
+}
+
+/// Requests awaiting responses, in submission order
+type Pending = VecDeque<InFlight>;
+
+/// Handle to the task owning a connection's message channel
+#[derive(Clone)]
+pub(crate) struct Connection {
+    submissions: Arc<RwLock<Submissions>>,
+    /// Whether to grant the remote credit for the batches taken
+    grants: Arc<AtomicBool>,
+}
+
+impl Connection {
+    /// Hand `channel` to a new background task
+    ///
+    /// The task ends, closing the channel, once every handle is dropped or
+    /// moved away and the requests in flight are answered, or the channel
+    /// fails.
+    pub(crate) fn spawn<S, C>(channel: MessageChannel<S, C>) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        C: Codec + 'static,
+    {
+        let (control, control_queue) = mpsc::unbounded_channel();
+        let (data, data_queue) = mpsc::channel(DATA_QUEUE_LEN);
+        let queues = Queues {
+            control: control_queue,
+            data: data_queue,
+        };
+        tokio::spawn(run(channel, queues));
+        Self::to(Submissions { control, data })
+    }
+
+    fn to(submissions: Submissions) -> Self {
+        Self {
+            submissions: Arc::new(RwLock::new(submissions)),
+            grants: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Queue `request` and return the stream of its responses
+    ///
+    /// Data requests wait for room in the data queue.
+    pub(crate) async fn submit(&self, request: ProtocolRequest) -> Result<Responses, ClientError> {
+        let grants = self.grants.load(Ordering::Relaxed);
+        submit(&*self.submissions.read().await, request, grants).await
+    }
+
+    /// Grant the remote a `Batch` response back for each one taken by the
+    /// requests submitted from now on; the remote must have negotiated
+    /// `flow-control`
+    pub(crate) fn grant_batches(&self) {
+        self.grants.store(true, Ordering::Relaxed);
+    }
+
+    /// Cancel the request `id` if it is still in flight
+    pub(crate) async fn cancel(&self, id: CorrelationId) -> Result<(), ClientError> {
+        self.submissions
+            .read()
+            .await
+            .control
+            .send(Submission::Cancel(id))
+            .map_err(|_| ClientError::Connection("Connection closed".to_string()))
+    }
+
+    /// Close the channel with a close handshake, rather than just dropping
+    /// it, once the task ends; the remote must understand it
+    pub(crate) async fn close_gracefully(&self) -> Result<(), ClientError> {
+        self.submissions
+            .read()
+            .await
+            .control
+            .send(Submission::CloseGracefully)
+            .map_err(|_| ClientError::Connection("Connection closed".to_string()))
+    }
+
+    /// Receive the requests of the remote until the connection closes
+    pub(crate) async fn listen(&self) -> Result<mpsc::UnboundedReceiver<ServerCall>, ClientError> {
+        let (listener, calls) = mpsc::unbounded_channel();
+        self.submissions
+            .read()
+            .await
+            .control
+            .send(Submission::Listen(listener))
+            .map_err(|_| ClientError::Connection("Connection closed".to_string()))?;
+        Ok(calls)
+    }
+
+    /// Hand the events of subscribed topics to `broker` until the connection
+    /// closes
+    pub(crate) async fn publish_to(&self, broker: Arc<Broker>) -> Result<(), ClientError> {
+        self.submissions
+            .read()
+            .await
+            .control
+            .send(Submission::PublishTo(broker))
+            .map_err(|_| ClientError::Connection("Connection closed".to_string()))
+    }
+
+    /// Hold new submissions of every handle until the pause ends
+    pub(crate) async fn pause(&self) -> Paused<'_> {
+        Paused(self.submissions.write().await)
+    }
+}
+
+/// Queue `request` on its lane
+async fn submit(
+    submissions: &Submissions,
+    request: ProtocolRequest,
+    grants: bool,
+) -> Result<Responses, ClientError> {
+    let data = request.is_data();
+    let (submission, responses) =
+        Submission::request(request, grants.then(|| submissions.control.clone()));
+    let queued = if data {
+        submissions.data.send(submission).await.is_ok()
+    } else {
+        submissions.control.send(submission).is_ok()
+    };
+    match queued {
+        true => Ok(responses),
+        false => Err(ClientError::Connection("Connection closed".to_string())),
+    }
+}
+
+fn next_id() -> CorrelationId {
+    LAST_ID.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Submissions held by [`Connection::pause`]; dropping it resumes them on
+/// the same task
+pub(crate) struct Paused<'a>(RwLockWriteGuard<'a, Submissions>);
+
+impl Paused<'_> {
+    /// Separate handle to the paused task, for requests jumping the pause
+    pub(crate) fn current(&self) -> Connection {
+        Connection::to(self.0.clone())
+    }
+
+    /// Resume the held submissions, and send all later ones, to the task of
+    /// `next`, leaving the paused task to finish the requests in flight
+    pub(crate) async fn resume_on(mut self, next: &Connection) {
+        *self.0 = next.submissions.read().await.clone();
+    }
+}
+
+/// Responses to one submitted request
+pub(crate) struct Responses {
+    id: CorrelationId,
+    receiver: mpsc::UnboundedReceiver<ResponseResult>,
+    /// Where to grant the batches taken, with `flow-control`
+    grants: Option<mpsc::UnboundedSender<Submission>>,
+}
+
+impl Responses {
+    /// Correlation id of the request
+    pub(crate) fn id(&self) -> CorrelationId {
+        self.id
+    }
+
+    /// Wait for the next response
+    pub(crate) async fn next(&mut self) -> Result<ProtocolResponse, ClientError> {
+        std::future::poll_fn(|cx| self.poll_next(cx)).await
+    }
+
+    /// Poll for the next response
+    pub(crate) fn poll_next(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<ProtocolResponse, ClientError>> {
+        self.receiver.poll_recv(cx).map(|response| match response {
+            Some(Ok(batch @ ProtocolResponse::Batch { more: true, .. })) => {
+                if let Some(control) = &self.grants {
+                    // Acknowledged like any request, but nobody waits for it;
+                    // a closed connection has no stream left to grant
+                    let grant = ProtocolRequest::GrantBatches {
+                        id: self.id,
+                        batches: 1,
+                    };
+                    let _ = control.send(Submission::request(grant, None).0);
+                }
+                Ok(batch)
+            }
+            Some(Ok(ProtocolResponse::Unsupported { request_name })) => {
+                Err(ClientError::Unsupported {
+                    request: request_name,
+                })
+            }
+            Some(response) => response.map_err(ClientError::Channel),
+            None => Err(ClientError::Connection("Connection closed".to_string())),
+        })
+    }
+}
+
+async fn run<S, C>(mut channel: MessageChannel<S, C>, mut queues: Queues)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    C: Codec,
+{
+    let mut pending = Pending::new();
+    // Whether handles may still submit control requests, and data
+    let mut open = true;
+    let mut data_open = true;
+    let mut graceful = false;
+    // Cancellations of requests not in flight, the last of which may be of
+    // requests still in the data queue
+    let mut cancelled_queued = VecDeque::new();
+    let mut listener = None;
+    let mut broker: Option<Arc<Broker>> = None;
+    let (replies, mut replied) = mpsc::unbounded_channel();
+    while open || data_open || !pending.is_empty() {
+        // Only read while a response is due or the remote may push requests
+        // or events; receiving is cancel safe, so a new submission can
+        // interrupt the wait and be written right away. Control goes first.
+        let event = tokio::select! {
+            biased;
+            submission = queues.control.recv(), if open => match submission {
+                Some(submission) => Event::Submitted(submission),
+                // No handle is left; answer the requests in flight
+                None => {
+                    open = false;
+                    continue;
+                }
+            },
+            submission = queues.data.recv(), if data_open && data_window_open(&pending) => {
+                match submission {
+                    Some(submission) => Event::Submitted(submission),
+                    None => {
+                        data_open = false;
+                        continue;
+                    }
+                }
+            }
+            response = channel.receive_response_with_id(),
+                if !pending.is_empty() || listener.is_some() || broker.is_some() =>
+            {
+                Event::Received(response)
+            }
+            Some((id, reply)) = replied.recv() => Event::Replied(id, reply),
+        };
+
+        match event {
+            Event::Submitted(Submission::Request {
+                id,
+                request,
+                responses,
+            }) if cancelled_queued.contains(&id) => {
+                cancelled_queued.retain(|cancelled| *cancelled != id);
+                let cancelled = ProtocolResponse::error(
+                    ErrorCode::Cancelled,
+                    "Request cancelled before it was sent",
+                );
+                let _ = responses.send(Ok(cancelled));
+                debug!(
+                    "Request {} ({}) cancelled before it was sent",
+                    id,
+                    request.kind()
+                );
+            }
+            Event::Submitted(Submission::Request {
+                id,
+                request,
+                responses,
+            }) => {
+                let in_flight = InFlight {
+                    id,
+                    data: request.is_data(),
+                    responses,
+                };
+                match channel.send_request_with_id(id, &request).await {
+                    Ok(()) => pending.push_back(in_flight),
+                    // The stream is gone; fail everything in flight
+                    Err(e @ YuhaError::Io(_)) => {
+                        pending.push_back(in_flight);
+                        let message = format!("Failed to send request: {}", e);
+                        close(&mut queues, &mut pending, message);
+                        return;
+                    }
+                    Err(e) => {
+                        let message = format!("Failed to send request: {}", e);
+                        let _ = in_flight.responses.send(Err(message));
+                    }
+                }
+            }
+            // A request answered already has nothing left to cancel
+            Event::Submitted(Submission::Cancel(id))
+                if pending.iter().any(|request| request.id == id) =>
+            {
+                if let Err(e) = channel.send_cancel(id).await {
+                    let message = format!("Failed to cancel request: {}", e);
+                    close(&mut queues, &mut pending, message);
+                    return;
+                }
+            }
+            // It may still wait in the data queue, which the cancel overtook
+            Event::Submitted(Submission::Cancel(id)) => {
+                debug!("Request {} not in flight, cancelled if still queued", id);
+                if cancelled_queued.len() == DATA_QUEUE_LEN {
+                    cancelled_queued.pop_front();
+                }
+                cancelled_queued.push_back(id);
+            }
+            Event::Submitted(Submission::Listen(calls)) => listener = Some(calls),
+            Event::Submitted(Submission::PublishTo(events)) => broker = Some(events),
+            Event::Received(Ok((_, ProtocolResponse::Event { event }))) => match &broker {
+                Some(broker) => broker.publish(event),
+                None => debug!("Dropping event {:?}", event),
+            },
+            Event::Submitted(Submission::CloseGracefully) => graceful = true,
+            Event::Received(Ok((_, ProtocolResponse::ServerRequest { id, request }))) => {
+                let call = ServerCall {
+                    id,
+                    request,
+                    replies: replies.clone(),
+                };
+                match &listener {
+                    Some(listener) => {
+                        if let Err(unheard) = listener.send(call) {
+                            unheard.0.reply(ServerReply::Unhandled);
+                        }
+                    }
+                    None => call.reply(ServerReply::Unhandled),
+                }
+            }
+            Event::Replied(id, reply) => {
+                // Acknowledged like any request, but nobody waits for it
+                let (acknowledged, _) = mpsc::unbounded_channel();
+                let request = ProtocolRequest::ServerReply { id, reply };
+                let correlation = next_id();
+                if let Err(e) = channel.send_request_with_id(correlation, &request).await {
+                    let message = format!("Failed to reply to server request: {}", e);
+                    close(&mut queues, &mut pending, message);
+                    return;
+                }
+                pending.push_back(InFlight {
+                    id: correlation,
+                    data: false,
+                    responses: acknowledged,
+                });
+            }
+            Event::Received(Ok((id, response))) => {
+                let last = !matches!(response, ProtocolResponse::Batch { more: true, .. });
+                let position = match id {
+                    Some(id) => pending.iter().position(|request| request.id == id),
+                    None => (!pending.is_empty()).then_some(0),
+                };
+                let responses = match position {
+                    Some(position) if last => pending.remove(position).map(|r| r.responses),
+                    Some(position) => Some(pending[position].responses.clone()),
+                    None => None,
+                };
+                match responses {
+                    // The caller may have stopped waiting; the response is dropped
+                    Some(responses) => {
+                        let _ = responses.send(Ok(response));
+                    }
+                    None => debug!("Dropping unsolicited response"),
+                }
+            }
+            Event::Received(Err(YuhaError::Protocol(ChannelError::ChannelReset))) => {
+                for request in pending.drain(..) {
+                    let message = "Channel reset, response lost".to_string();
+                    let _ = request.responses.send(Err(message));
+                }
+            }
+            Event::Received(Err(e)) => {
+                let message = format!("Failed to receive response: {}", e);
+                close(&mut queues, &mut pending, message);
+                return;
+            }
+        }
+        // Requests submitted together, e.g. forwarded data and the credit
+        // granted for it, go out with one write once the queue runs dry
+        let queued =
+            !queues.control.is_empty() || (data_window_open(&pending) && !queues.data.is_empty());
+        if queued {
+            channel.cork();
+        } else if let Err(e) = channel.uncork().await {
+            let message = format!("Failed to send request: {}", e);
+            close(&mut queues, &mut pending, message);
+            return;
+        }
+    }
+    if graceful && let Err(e) = channel.close().await {
+        debug!("Remote did not acknowledge the close: {}", e);
+    }
+    debug!("Connection task finished");
+}
+
+enum Event {
+    Submitted(Submission),
+    Received(yuha_core::Result<(Option<CorrelationId>, ProtocolResponse)>),
+    Replied(ServerRequestId, ServerReply),
+}
+
+/// Refuse further submissions and fail the pending requests with `message`
+fn close(queues: &mut Queues, pending: &mut Pending, message: String) {
+    queues.close();
+    for request in pending.drain(..) {
+        let _ = request.responses.send(Err(message.clone()));
+    }
+}
+
+/// Whether fewer than [`DATA_WINDOW`] data requests await responses
+fn data_window_open(pending: &Pending) -> bool {
+    pending.iter().filter(|request| request.data).count() < DATA_WINDOW
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, duplex};
+    use yuha_core::message_channel::HEADER_LEN;
+    use yuha_core::protocol::{ResponseItem, ServerRequest};
+
+    fn content(response: ProtocolResponse) -> String {
+        match response {
+            ProtocolResponse::Data { items, .. } => match &items[..] {
+                [ResponseItem::ClipboardContent { content }] => content.clone(),
+                other => panic!("unexpected items {:?}", other),
+            },
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    /// Answer every `SetClipboard` with its content and stream `ListFiles`
+    /// as two batches
+    async fn echo_server<S: AsyncRead + AsyncWrite + Unpin>(stream: S) {
+        let mut channel = MessageChannel::new_with_stream(stream);
+        while let Ok(request) = channel.receive_request().await {
+            match request {
+                ProtocolRequest::SetClipboard { content } => {
+                    let items = vec![ResponseItem::ClipboardContent { content }];
+                    channel
+                        .send_response(&ProtocolResponse::Data { items, next: None })
+                        .await
+                        .unwrap();
+                }
+                ProtocolRequest::ListFiles { .. } => {
+                    for more in [true, false] {
+                        let batch = ProtocolResponse::Batch {
+                            items: Vec::new(),
+                            more,
+                        };
+                        channel.send_response(&batch).await.unwrap();
+                    }
+                }
+                other => panic!("unexpected request {:?}", other),
+            }
+        }
+    }
+
+    /// Answer every `SetClipboard` with its content prefixed by `name`
+    async fn named_server<S: AsyncRead + AsyncWrite + Unpin>(stream: S, name: &str) {
+        let mut channel = MessageChannel::new_with_stream(stream);
+        while let Ok(ProtocolRequest::SetClipboard { content }) = channel.receive_request().await {
+            let content = format!("{}:{}", name, content);
+            let items = vec![ResponseItem::ClipboardContent { content }];
+            channel
+                .send_response(&ProtocolResponse::Data { items, next: None })
+                .await
+                .unwrap();
+        }
+    }
+
+    fn set_clipboard(content: &str) -> ProtocolRequest {
+        ProtocolRequest::SetClipboard {
+            content: content.to_string(),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_requests_get_their_responses() {
+        let (client, server) = duplex(1 << 16);
+        tokio::spawn(echo_server(server));
+        let connection = Connection::spawn(MessageChannel::new_with_stream(client));
+
+        // Queued together, so both are written before any response is read
+        let mut listing = connection
+            .submit(ProtocolRequest::ListFiles {
+                paths: Vec::new(),
+                query: Default::default(),
+            })
+            .await
+            .unwrap();
+        let mut first = connection.submit(set_clipboard("first")).await.unwrap();
+
+        let tasks: Vec<_> = (0..16)
+            .map(|i| {
+                let connection = connection.clone();
+                tokio::spawn(async move {
+                    let mut responses = connection
+                        .submit(set_clipboard(&i.to_string()))
+                        .await
+                        .unwrap();
+                    assert_eq!(content(responses.next().await.unwrap()), i.to_string());
+                })
+            })
+            .collect();
+
+        for more in [true, false] {
+            match listing.next().await.unwrap() {
+                ProtocolResponse::Batch { more: received, .. } => assert_eq!(received, more),
+                other => panic!("unexpected response {:?}", other),
+            }
+        }
+        assert_eq!(content(first.next().await.unwrap()), "first");
+        for task in tasks {
+            task.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_written_while_response_outstanding() {
+        let (client, server) = duplex(1 << 16);
+        let connection = Connection::spawn(MessageChannel::new_with_stream(client));
+
+        // Like a long poll, the first request is only answered once the
+        // second one arrives
+        let server = tokio::spawn(async move {
+            let mut channel = MessageChannel::new_with_stream(server);
+            let first = channel.receive_request().await.unwrap();
+            let second = channel.receive_request().await.unwrap();
+            for request in [first, second] {
+                let ProtocolRequest::SetClipboard { content } = request else {
+                    panic!("unexpected request {:?}", request);
+                };
+                let items = vec![ResponseItem::ClipboardContent { content }];
+                channel
+                    .send_response(&ProtocolResponse::Data { items, next: None })
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let mut poll = connection.submit(set_clipboard("poll")).await.unwrap();
+        tokio::task::yield_now().await;
+        let mut control = connection.submit(set_clipboard("control")).await.unwrap();
+
+        let responses = async { (poll.next().await, control.next().await) };
+        let (poll, control) = tokio::time::timeout(Duration::from_secs(5), responses)
+            .await
+            .expect("second request was not written");
+        assert_eq!(content(poll.unwrap()), "poll");
+        assert_eq!(content(control.unwrap()), "control");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_responses_matched_by_correlation_id() {
+        let (client, server) = duplex(1 << 16);
+        let connection = Connection::spawn(MessageChannel::new_with_stream(client));
+
+        // The second request is answered first
+        let server = tokio::spawn(async move {
+            let mut channel = MessageChannel::new_with_stream(server);
+            let first = channel.receive_request_with_id().await.unwrap();
+            let second = channel.receive_request_with_id().await.unwrap();
+            for (id, request) in [second, first] {
+                let ProtocolRequest::SetClipboard { content } = request else {
+                    panic!("unexpected request {:?}", request);
+                };
+                let items = vec![ResponseItem::ClipboardContent { content }];
+                channel
+                    .send_response_with_id(id, &ProtocolResponse::Data { items, next: None })
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let mut first = connection.submit(set_clipboard("first")).await.unwrap();
+        let mut second = connection.submit(set_clipboard("second")).await.unwrap();
+        assert_eq!(content(second.next().await.unwrap()), "second");
+        assert_eq!(content(first.next().await.unwrap()), "first");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_server_requests_answered() {
+        let (client, server) = duplex(1 << 16);
+        let connection = Connection::spawn(MessageChannel::new_with_stream(client));
+        let open_url = |id| ProtocolResponse::ServerRequest {
+            id,
+            request: ServerRequest::OpenUrl {
+                url: "https://example.com".to_string(),
+            },
+        };
+
+        let server = tokio::spawn(async move {
+            let mut channel = MessageChannel::new_with_stream(server);
+            // Sent ahead of an untagged answer, which must still match
+            let request = channel.receive_request().await.unwrap();
+            assert!(matches!(request, ProtocolRequest::SetClipboard { .. }));
+            channel.send_response(&open_url(1)).await.unwrap();
+            let items = vec![ResponseItem::ClipboardContent {
+                content: "first".to_string(),
+            }];
+            channel
+                .send_response(&ProtocolResponse::Data { items, next: None })
+                .await
+                .unwrap();
+            let mut replies = Vec::new();
+            for id in [1, 2] {
+                if id == 2 {
+                    channel.send_response(&open_url(2)).await.unwrap();
+                }
+                let (correlation, request) = channel.receive_request_with_id().await.unwrap();
+                channel
+                    .send_response_with_id(correlation, &ProtocolResponse::Success)
+                    .await
+                    .unwrap();
+                let ProtocolRequest::ServerReply { id: replied, reply } = request else {
+                    panic!("unexpected request {:?}", request);
+                };
+                assert_eq!(replied, id);
+                replies.push(reply);
+            }
+            replies
+        });
+
+        // Without a listener the connection answers by itself
+        let mut first = connection.submit(set_clipboard("first")).await.unwrap();
+        assert_eq!(content(first.next().await.unwrap()), "first");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let mut calls = connection.listen().await.unwrap();
+        let call = calls.recv().await.unwrap();
+        assert_eq!(
+            (call.id, &call.request),
+            (
+                2,
+                &ServerRequest::OpenUrl {
+                    url: "https://example.com".to_string()
+                }
+            )
+        );
+        call.reply(ServerReply::Done);
+
+        let replies = server.await.unwrap();
+        assert_eq!(replies, [ServerReply::Unhandled, ServerReply::Done]);
+    }
+
+    #[tokio::test]
+    async fn test_close_gracefully() {
+        let (client, server) = duplex(1 << 16);
+        let connection = Connection::spawn(MessageChannel::new_with_stream(client));
+        let mut server = MessageChannel::new_with_stream(server);
+
+        connection.close_gracefully().await.unwrap();
+        drop(connection);
+        assert!(matches!(
+            server.receive_request().await,
+            Err(YuhaError::Protocol(ChannelError::ClosedByPeer))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_request_in_flight() {
+        let (client, server) = duplex(1 << 16);
+        let connection = Connection::spawn(MessageChannel::new_with_stream(client));
+
+        // Answers the first request, then ends the second once cancelled
+        let server = tokio::spawn(async move {
+            let mut channel = MessageChannel::new_with_stream(server);
+            let (answered, _) = channel.receive_request_with_id().await.unwrap();
+            channel
+                .send_response_with_id(answered, &ProtocolResponse::Success)
+                .await
+                .unwrap();
+            let (running, _) = channel.receive_request_with_id().await.unwrap();
+            let cancelled = match channel.receive_request_with_id().await {
+                Err(YuhaError::Protocol(ChannelError::RequestCancelled { id })) => id,
+                other => panic!("unexpected receive {:?}", other),
+            };
+            assert_eq!(Some(cancelled), running);
+            let error = ProtocolResponse::error(ErrorCode::Cancelled, "cancelled");
+            channel
+                .send_response_with_id(running, &error)
+                .await
+                .unwrap();
+            (answered.unwrap(), cancelled)
+        });
+
+        let mut answered = connection.submit(set_clipboard("done")).await.unwrap();
+        assert!(matches!(
+            answered.next().await.unwrap(),
+            ProtocolResponse::Success
+        ));
+        // Nothing is sent for a request answered already
+        connection.cancel(answered.id()).await.unwrap();
+        let mut running = connection.submit(set_clipboard("slow")).await.unwrap();
+        connection.cancel(running.id()).await.unwrap();
+        assert!(matches!(
+            running.next().await.unwrap(),
+            ProtocolResponse::Error { .. }
+        ));
+        assert_eq!(server.await.unwrap(), (answered.id(), running.id()));
+    }
+
+    #[tokio::test]
+    async fn test_connection_survives_channel_reset() {
+        let (client, server) = duplex(1 << 16);
+        let connection =
+            Connection::spawn(MessageChannel::new_with_stream(client).with_frame_magic());
+
+        // The response to the first request arrives corrupted
+        let server = tokio::spawn(async move {
+            let mut stream = server;
+            let mut header = [0; HEADER_LEN];
+            stream.read_exact(&mut header).await.unwrap();
+            let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+            stream.read_exact(&mut vec![0; len]).await.unwrap();
+            stream.write_all(b"noise").await.unwrap();
+
+            let mut channel = MessageChannel::new_with_stream(stream).with_frame_magic();
+            assert!(matches!(
+                channel.receive_request().await,
+                Err(YuhaError::Protocol(ChannelError::ChannelReset))
+            ));
+            let request = channel.receive_request().await.unwrap();
+            let ProtocolRequest::SetClipboard { content } = request else {
+                panic!("unexpected request {:?}", request);
+            };
+            let items = vec![ResponseItem::ClipboardContent { content }];
+            channel
+                .send_response(&ProtocolResponse::Data { items, next: None })
+                .await
+                .unwrap();
+        });
+
+        let mut lost = connection.submit(set_clipboard("lost")).await.unwrap();
+        assert!(matches!(lost.next().await, Err(ClientError::Channel(_))));
+        let mut next = connection.submit(set_clipboard("next")).await.unwrap();
+        assert_eq!(content(next.next().await.unwrap()), "next");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_paused_submissions_resume_on_next_connection() {
+        let (old_stream, old_server) = duplex(1 << 16);
+        let (next_stream, next_server) = duplex(1 << 16);
+        let old_server = tokio::spawn(named_server(old_server, "old"));
+        tokio::spawn(named_server(next_server, "next"));
+        let connection = Connection::spawn(MessageChannel::new_with_stream(old_stream));
+        let next = Connection::spawn(MessageChannel::new_with_stream(next_stream));
+
+        let mut in_flight = connection.submit(set_clipboard("a")).await.unwrap();
+        let paused = connection.pause().await;
+        let held = tokio::spawn({
+            let connection = connection.clone();
+            async move {
+                let mut responses = connection.submit(set_clipboard("b")).await.unwrap();
+                responses.next().await.unwrap()
+            }
+        });
+        let mut jumping = paused.current().submit(set_clipboard("c")).await.unwrap();
+        assert_eq!(content(jumping.next().await.unwrap()), "old:c");
+        assert!(!held.is_finished());
+        paused.resume_on(&next).await;
+
+        assert_eq!(content(held.await.unwrap()), "next:b");
+        assert_eq!(content(in_flight.next().await.unwrap()), "old:a");
+        // No handle refers to the old task anymore, so it closed the channel
+        old_server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pending_requests_fail_when_connection_closes() {
+        let (client, server) = duplex(1 << 16);
+        let connection = Connection::spawn(MessageChannel::new_with_stream(client));
+
+        let mut responses = connection.submit(set_clipboard("lost")).await.unwrap();
+        drop(server);
+
+        assert!(matches!(
+            responses.next().await,
+            Err(ClientError::Channel(_))
+        ));
+        assert!(matches!(
+            connection.submit(set_clipboard("late")).await,
+            Err(ClientError::Connection(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_control_overtakes_stalled_data() {
+        let (client, server) = duplex(1 << 16);
+        let connection = Connection::spawn(MessageChannel::new_with_stream(client));
+
+        // A remote stalling on forwarded data, but answering control at once
+        let data_received = Arc::new(AtomicU64::new(0));
+        let received = data_received.clone();
+        tokio::spawn(async move {
+            let mut channel = MessageChannel::new_with_stream(server);
+            while let Ok((id, request)) = channel.receive_request_with_id().await {
+                match request {
+                    ProtocolRequest::PortForwardData { .. } => {
+                        received.fetch_add(1, Ordering::SeqCst);
+                    }
+                    ProtocolRequest::SetClipboard { content } => {
+                        let items = vec![ResponseItem::ClipboardContent { content }];
+                        let response = ProtocolResponse::Data { items, next: None };
+                        channel.send_response_with_id(id, &response).await.unwrap();
+                    }
+                    other => panic!("unexpected request {:?}", other),
+                }
+            }
+        });
+
+        let mut stalled = Vec::new();
+        for _ in 0..DATA_WINDOW + 4 {
+            let data = ProtocolRequest::PortForwardData {
+                connection_id: 1,
+                data: bytes::Bytes::from_static(b"data"),
+            };
+            stalled.push(connection.submit(data).await.unwrap());
+        }
+        let mut control = connection.submit(set_clipboard("control")).await.unwrap();
+        let reply = tokio::time::timeout(Duration::from_secs(5), control.next())
+            .await
+            .expect("Control request stuck behind stalled data");
+        assert_eq!(content(reply.unwrap()), "control");
+
+        // Only a window of data went out; the rest waits in its queue
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(data_received.load(Ordering::SeqCst), DATA_WINDOW as u64);
+    }
+
+    #[tokio::test]
+    async fn test_batches_taken_are_granted() {
+        let (client, server) = duplex(1 << 16);
+        let connection = Connection::spawn(MessageChannel::new_with_stream(client));
+        connection.grant_batches();
+        let mut server = MessageChannel::new_with_stream(server);
+
+        let listing = ProtocolRequest::ListFiles {
+            paths: Vec::new(),
+            query: Default::default(),
+        };
+        let mut responses = connection.submit(listing).await.unwrap();
+        let (id, _) = server.receive_request_with_id().await.unwrap();
+        for more in [true, false] {
+            let batch = ProtocolResponse::Batch {
+                items: Vec::new(),
+                more,
+            };
+            server.send_response_with_id(id, &batch).await.unwrap();
+        }
+        responses.next().await.unwrap();
+        responses.next().await.unwrap();
+
+        // Only the batch announcing more is granted back
+        let (grant, request) = server.receive_request_with_id().await.unwrap();
+        assert!(matches!(
+            request,
+            ProtocolRequest::GrantBatches { id: granted, batches: 1 } if Some(granted) == id
+        ));
+        server
+            .send_response_with_id(grant, &ProtocolResponse::Success)
+            .await
+            .unwrap();
+        drop(connection);
+        drop(responses);
+        assert!(server.receive_request_with_id().await.is_err());
+    }
+}
@@ -11,6 +11,10 @@ pub fn default_socket_path() -> PathBuf {
     }
 }
 
+/// Environment variable naming the IPC socket of a yuha-remote, read by the
+/// remote and by the `yuha-remote <command>` calls of its session
+pub const IPC_SOCKET_ENV: &str = "YUHA_IPC_SOCKET";
+
 /// Get the default socket path as a string
 pub fn default_socket_path_str() -> String {
     default_socket_path().to_string_lossy().to_string()
@@ -0,0 +1,218 @@
+//! # Credentials
+//!
+//! Transports ask a [`CredentialsProvider`] for the secrets they were not
+//! configured with: the SSH password when the server refuses the key, the
+//! passphrase of an encrypted key, one-time codes of keyboard-interactive
+//! logins and PINs of PKCS#11 tokens. The provider also relays notices, such
+//! as asking the user to touch their security key.
+//!
+//! - [`TerminalCredentials`] asks on the controlling terminal
+//! - [`EnvCredentials`] reads `YUHA_*` environment variables, for scripts and
+//!   the daemon, which has no terminal
+//! - [`FnCredentials`] answers from a closure, for embedders and tests
+//! - [`NonInteractiveCredentials`] fails every question with
+//!   [`ClientError::InteractionRequired`], so scripts never wait for input
+//!
+//! GUI embedders implement the trait to show their own dialogs.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::fmt;
+use tracing::{info, warn};
+
+use crate::ClientError;
+
+/// A secret a transport may ask for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Credential {
+    Password,
+    Passphrase,
+    /// One-time code of a keyboard-interactive login
+    Otp,
+    /// PIN of a PKCS#11 token
+    Pin,
+}
+
+impl Credential {
+    /// Environment variable [`EnvCredentials`] reads this secret from
+    pub fn env_var(self) -> &'static str {
+        match self {
+            Credential::Password => "YUHA_SSH_PASSWORD",
+            Credential::Passphrase => "YUHA_SSH_PASSPHRASE",
+            Credential::Otp => "YUHA_SSH_OTP",
+            Credential::Pin => "YUHA_PKCS11_PIN",
+        }
+    }
+}
+
+/// Source of secrets asked of the user
+#[async_trait]
+pub trait CredentialsProvider: Send + Sync + fmt::Debug {
+    /// The `credential` asked with `prompt`, `None` when there is none
+    async fn ask(&self, credential: Credential, prompt: &str) -> Result<Option<String>>;
+
+    /// Tell the user something that needs no answer
+    fn notify(&self, message: &str) {
+        warn!("{}", message);
+    }
+}
+
+/// Asks on the controlling terminal without echoing the answer
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TerminalCredentials;
+
+#[async_trait]
+impl CredentialsProvider for TerminalCredentials {
+    async fn ask(&self, _credential: Credential, prompt: &str) -> Result<Option<String>> {
+        let prompt = prompt.to_string();
+        tokio::task::spawn_blocking(move || read_hidden(&prompt)).await?
+    }
+
+    fn notify(&self, message: &str) {
+        eprintln!("{}", message);
+    }
+}
+
+#[cfg(unix)]
+fn read_hidden(prompt: &str) -> Result<Option<String>> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::process::Command;
+
+    let mut tty = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .context("No terminal to ask for credentials on")?;
+    let input = tty.try_clone()?;
+    let echo = |setting: &str| -> Result<()> {
+        Command::new("stty")
+            .arg(setting)
+            .stdin(input.try_clone()?)
+            .status()
+            .context("Failed to run stty")?;
+        Ok(())
+    };
+    write!(tty, "{}", prompt)?;
+    tty.flush()?;
+    echo("-echo")?;
+    let mut line = String::new();
+    let read = BufReader::new(&input).read_line(&mut line);
+    echo("echo")?;
+    writeln!(tty)?;
+    Ok((read? > 0).then(|| line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+#[cfg(not(unix))]
+fn read_hidden(prompt: &str) -> Result<Option<String>> {
+    use std::io::{BufRead, Write};
+
+    // The console offers no portable way to turn echo off
+    eprint!("{}", prompt);
+    std::io::stderr().flush()?;
+    let mut line = String::new();
+    let read = std::io::stdin().lock().read_line(&mut line)?;
+    Ok((read > 0).then(|| line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+/// Reads each credential from its [`Credential::env_var`]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EnvCredentials;
+
+#[async_trait]
+impl CredentialsProvider for EnvCredentials {
+    async fn ask(&self, credential: Credential, _prompt: &str) -> Result<Option<String>> {
+        Ok(std::env::var(credential.env_var()).ok())
+    }
+}
+
+/// Answers from a closure
+pub struct FnCredentials<F>(pub F);
+
+impl<F> fmt::Debug for FnCredentials<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FnCredentials")
+    }
+}
+
+#[async_trait]
+impl<F> CredentialsProvider for FnCredentials<F>
+where
+    F: Fn(Credential, &str) -> Option<String> + Send + Sync,
+{
+    async fn ask(&self, credential: Credential, prompt: &str) -> Result<Option<String>> {
+        Ok((self.0)(credential, prompt))
+    }
+
+    fn notify(&self, message: &str) {
+        info!("{}", message);
+    }
+}
+
+/// Refuses every question
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NonInteractiveCredentials;
+
+#[async_trait]
+impl CredentialsProvider for NonInteractiveCredentials {
+    async fn ask(&self, _credential: Credential, prompt: &str) -> Result<Option<String>> {
+        Err(ClientError::InteractionRequired {
+            prompt: prompt.trim_end().to_string(),
+        }
+        .into())
+    }
+}
+
+/// Asks the first provider, then the second when the first has no answer;
+/// notices go to the second
+#[derive(Debug, Clone, Copy)]
+pub struct Fallback<A, B>(pub A, pub B);
+
+#[async_trait]
+impl<A: CredentialsProvider, B: CredentialsProvider> CredentialsProvider for Fallback<A, B> {
+    async fn ask(&self, credential: Credential, prompt: &str) -> Result<Option<String>> {
+        match self.0.ask(credential, prompt).await? {
+            Some(answer) => Ok(Some(answer)),
+            None => self.1.ask(credential, prompt).await,
+        }
+    }
+
+    fn notify(&self, message: &str) {
+        self.1.notify(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fallback() {
+        let first = FnCredentials(|credential, _: &str| {
+            (credential == Credential::Otp).then(|| "123456".to_string())
+        });
+        let second = FnCredentials(|_, prompt: &str| Some(format!("answer to {}", prompt)));
+        let provider = Fallback(first, second);
+
+        assert_eq!(
+            provider.ask(Credential::Otp, "Code: ").await.unwrap(),
+            Some("123456".to_string())
+        );
+        assert_eq!(
+            provider
+                .ask(Credential::Password, "Password: ")
+                .await
+                .unwrap(),
+            Some("answer to Password: ".to_string())
+        );
+
+        let refusing = Fallback(provider.0, NonInteractiveCredentials);
+        let error = refusing
+            .ask(Credential::Password, "Password: ")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(ClientError::InteractionRequired { prompt }) if prompt == "Password:"
+        ));
+    }
+}
@@ -3,12 +3,14 @@
 //! This module handles incoming requests from CLI clients and manages
 //! the session lifecycle.
 
+use super::history::{OpenUrlHistory, RequestLog};
 use crate::daemon_protocol::{
-    CommandResult, DaemonCommand, DaemonRequest, DaemonResponse, ErrorCode, SessionDetails,
-    SessionSummary,
+    CommandResult, DaemonCommand, DaemonRequest, DaemonResponse, ErrorCode, OpenUrlOutcome,
+    OpenUrlRecord, RequestOutcome, RequestRecord, SessionDetails, SessionSummary,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 use tokio::sync::Mutex;
 use tracing::{debug, error, info, warn};
 use yuha_core::session::{SessionId, SessionManager, SessionStatus};
@@ -20,14 +22,23 @@ type ClientMap = Arc<Mutex<HashMap<SessionId, Arc<Mutex<Box<dyn std::any::Any +
 pub struct RequestHandler {
     session_manager: Arc<SessionManager>,
     active_clients: ClientMap,
+    open_url_history: OpenUrlHistory,
+    request_log: RequestLog,
 }
 
 impl RequestHandler {
-    /// Create a new request handler
-    pub fn new(session_manager: Arc<SessionManager>) -> Self {
+    /// Create a new request handler keeping up to `open_url_history_size` URL open records
+    /// and the last `request_log_size` requests of each session
+    pub fn new(
+        session_manager: Arc<SessionManager>,
+        open_url_history_size: usize,
+        request_log_size: usize,
+    ) -> Self {
         Self {
             session_manager,
             active_clients: Arc::new(Mutex::new(HashMap::new())),
+            open_url_history: OpenUrlHistory::new(open_url_history_size),
+            request_log: RequestLog::new(request_log_size),
         }
     }
 
@@ -66,6 +77,14 @@ impl RequestHandler {
                 command,
             } => self.handle_execute_command(session_id, command).await,
 
+            DaemonRequest::GetOpenUrlHistory { query } => DaemonResponse::OpenUrlHistory {
+                records: self.open_url_history.query(&query).await,
+            },
+
+            DaemonRequest::GetRequestLog { query } => DaemonResponse::RequestLog {
+                records: self.request_log.query(&query).await,
+            },
+
             DaemonRequest::Shutdown => {
                 // Shutdown is handled by the server
                 DaemonResponse::ShuttingDown
@@ -233,11 +252,101 @@ impl RequestHandler {
         }
     }
 
-    /// Handle command execution
+    /// Handle command execution, recording it in the request log and URL
+    /// open requests in the history
     async fn handle_execute_command(
         &self,
         session_id: SessionId,
         command: DaemonCommand,
+    ) -> DaemonResponse {
+        let url = match &command {
+            DaemonCommand::OpenBrowser { url } => Some(url.clone()),
+            _ => None,
+        };
+        let name = command.name();
+        let request_bytes = encoded_len(&command);
+        let requested_at = SystemTime::now();
+        let start = Instant::now();
+
+        let response = self.execute_command(session_id, command).await;
+
+        let session_name = self
+            .session_manager
+            .get_session(session_id)
+            .await
+            .map(|metadata| metadata.name)
+            .unwrap_or_default();
+        let failure = failure(&response);
+        // Sessions end without telling the handler, e.g. when idle ones
+        // expire, so their records go once another session logs a request
+        let live: HashSet<_> = self
+            .session_manager
+            .list_sessions()
+            .await
+            .into_iter()
+            .map(|metadata| metadata.id)
+            .collect();
+        self.request_log
+            .retain(|id| *id == session_id || live.contains(id))
+            .await;
+        self.request_log
+            .record(RequestRecord {
+                session_id,
+                session_name: session_name.clone(),
+                command: name.to_string(),
+                request_bytes,
+                response_bytes: encoded_len(&response),
+                started_at: requested_at,
+                duration: start.elapsed(),
+                outcome: match &failure {
+                    None => RequestOutcome::Succeeded,
+                    Some(reason) => RequestOutcome::Failed {
+                        reason: reason.clone(),
+                    },
+                },
+            })
+            .await;
+        if let Some(url) = url {
+            self.record_open_url(session_id, session_name, url, requested_at, failure)
+                .await;
+        }
+        response
+    }
+
+    /// Record the outcome of a URL open request
+    async fn record_open_url(
+        &self,
+        session_id: SessionId,
+        session_name: String,
+        url: String,
+        requested_at: SystemTime,
+        failure: Option<String>,
+    ) {
+        let outcome = match failure {
+            None => OpenUrlOutcome::Opened,
+            Some(reason) => OpenUrlOutcome::Failed { reason },
+        };
+        info!(
+            "Session {} ({}) requested URL {}: {:?}",
+            session_id, session_name, url, outcome
+        );
+
+        self.open_url_history
+            .record(OpenUrlRecord {
+                url,
+                session_id,
+                session_name,
+                requested_at,
+                outcome,
+            })
+            .await;
+    }
+
+    /// Execute a command on the client of a connected session
+    async fn execute_command(
+        &self,
+        session_id: SessionId,
+        command: DaemonCommand,
     ) -> DaemonResponse {
         // Get the client for this session
         let clients = self.active_clients.lock().await;
@@ -316,6 +425,8 @@ impl RequestHandler {
             }
             DaemonCommand::OpenBrowser { url } => {
                 debug!("Opening browser to: {}", url);
+                // The outcome recorded in the history is the browser's
+                yuha_core::browser::open_url(&url).await?;
                 Ok(CommandResult::BrowserOpened)
             }
             DaemonCommand::StartPortForward {
@@ -347,3 +458,177 @@ impl RequestHandler {
         }
     }
 }
+
+/// Why a command failed, if it did
+fn failure(response: &DaemonResponse) -> Option<String> {
+    match response {
+        DaemonResponse::CommandSuccess { .. } => None,
+        DaemonResponse::Error { message, .. } => Some(message.clone()),
+        other => Some(format!("Unexpected response: {:?}", other)),
+    }
+}
+
+/// Size of `value` as encoded on the control socket
+fn encoded_len(value: &impl serde::Serialize) -> u64 {
+    serde_json::to_vec(value).map_or(0, |bytes| bytes.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon_protocol::HistoryQuery;
+    use yuha_core::session::SessionManagerConfig;
+    use yuha_core::transport::TransportBuilder;
+
+    fn handler() -> RequestHandler {
+        RequestHandler::new(
+            Arc::new(SessionManager::new(SessionManagerConfig::default())),
+            10,
+            10,
+        )
+    }
+
+    async fn open_url_history(handler: &RequestHandler) -> Vec<OpenUrlRecord> {
+        match handler
+            .handle_request(DaemonRequest::GetOpenUrlHistory {
+                query: HistoryQuery::default(),
+            })
+            .await
+        {
+            DaemonResponse::OpenUrlHistory { records } => records,
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_open_browser_requests_are_recorded() {
+        let handler = handler();
+        let transport_config = TransportBuilder::tcp()
+            .host("localhost")
+            .port(9999)
+            .build()
+            .unwrap();
+        let session_id = match handler
+            .handle_request(DaemonRequest::CreateSession {
+                name: "work".to_string(),
+                transport_config,
+                tags: vec![],
+                description: None,
+            })
+            .await
+        {
+            DaemonResponse::SessionCreated { session_id, .. } => session_id,
+            other => panic!("unexpected response {:?}", other),
+        };
+
+        handler
+            .handle_request(DaemonRequest::ExecuteCommand {
+                session_id,
+                command: DaemonCommand::OpenBrowser {
+                    url: "https://example.com".to_string(),
+                },
+            })
+            .await;
+        handler
+            .handle_request(DaemonRequest::ExecuteCommand {
+                session_id,
+                command: DaemonCommand::GetClipboard,
+            })
+            .await;
+
+        let records = open_url_history(&handler).await;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].url, "https://example.com");
+        assert_eq!(records[0].session_id, session_id);
+        assert_eq!(records[0].session_name, "work");
+        assert_eq!(records[0].outcome, OpenUrlOutcome::Opened);
+    }
+
+    #[tokio::test]
+    async fn test_refused_url_is_recorded_as_failed() {
+        let handler = handler();
+        let transport_config = TransportBuilder::tcp()
+            .host("localhost")
+            .port(9999)
+            .build()
+            .unwrap();
+        let session_id = match handler
+            .handle_request(DaemonRequest::CreateSession {
+                name: "work".to_string(),
+                transport_config,
+                tags: vec![],
+                description: None,
+            })
+            .await
+        {
+            DaemonResponse::SessionCreated { session_id, .. } => session_id,
+            other => panic!("unexpected response {:?}", other),
+        };
+
+        let response = handler
+            .handle_request(DaemonRequest::ExecuteCommand {
+                session_id,
+                command: DaemonCommand::OpenBrowser {
+                    url: "javascript:alert(1)".to_string(),
+                },
+            })
+            .await;
+        assert!(matches!(response, DaemonResponse::Error { .. }));
+
+        let records = open_url_history(&handler).await;
+        assert_eq!(records.len(), 1);
+        assert!(matches!(records[0].outcome, OpenUrlOutcome::Failed { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_failed_open_browser_request_is_recorded() {
+        let handler = handler();
+        let session_id = SessionId::new();
+
+        handler
+            .handle_request(DaemonRequest::ExecuteCommand {
+                session_id,
+                command: DaemonCommand::OpenBrowser {
+                    url: "https://example.com".to_string(),
+                },
+            })
+            .await;
+
+        let records = open_url_history(&handler).await;
+        assert_eq!(records.len(), 1);
+        assert!(matches!(records[0].outcome, OpenUrlOutcome::Failed { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_commands_are_logged() {
+        let handler = handler();
+        let unknown = SessionId::new();
+
+        handler
+            .handle_request(DaemonRequest::ExecuteCommand {
+                session_id: unknown,
+                command: DaemonCommand::SetClipboard {
+                    content: "secret".to_string(),
+                },
+            })
+            .await;
+
+        let records = match handler
+            .handle_request(DaemonRequest::GetRequestLog {
+                query: HistoryQuery::default(),
+            })
+            .await
+        {
+            DaemonResponse::RequestLog { records } => records,
+            other => panic!("unexpected response {:?}", other),
+        };
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].session_id, unknown);
+        assert_eq!(records[0].command, "SetClipboard");
+        assert!(records[0].request_bytes > 0);
+        assert!(records[0].response_bytes > 0);
+        assert!(matches!(records[0].outcome, RequestOutcome::Failed { .. }));
+        // Payloads are not kept
+        assert!(!format!("{:?}", records[0]).contains("secret"));
+    }
+}
@@ -0,0 +1,250 @@
+//! URL open history and request log
+//!
+//! Keeps a bounded record of every URL open request the daemon handles so
+//! users can audit what remote processes asked to open, and of the last
+//! requests each session executed, for `yuha daemon status --recent`.
+
+use crate::daemon_protocol::{HistoryQuery, OpenUrlRecord, RequestRecord};
+use std::collections::{HashMap, VecDeque};
+use tokio::sync::Mutex;
+use yuha_core::session::SessionId;
+
+/// Records in arrival order, evicting the oldest past a capacity
+struct Ring<T> {
+    records: VecDeque<T>,
+    capacity: usize,
+}
+
+impl<T> Ring<T> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::with_capacity(capacity.min(1024)),
+            capacity,
+        }
+    }
+
+    fn push(&mut self, record: T) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+}
+
+/// Bounded in-memory history of URL open requests
+pub struct OpenUrlHistory {
+    records: Mutex<Ring<OpenUrlRecord>>,
+}
+
+impl OpenUrlHistory {
+    /// Create a history that keeps at most `capacity` records
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: Mutex::new(Ring::new(capacity)),
+        }
+    }
+
+    /// Append a record, evicting the oldest one when full
+    pub async fn record(&self, record: OpenUrlRecord) {
+        self.records.lock().await.push(record);
+    }
+
+    /// Return the records matching `query`, newest first
+    pub async fn query(&self, query: &HistoryQuery) -> Vec<OpenUrlRecord> {
+        self.records
+            .lock()
+            .await
+            .records
+            .iter()
+            .rev()
+            .filter(|record| query.matches(&record.session_name))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+}
+
+/// The last requests of each session, types and sizes but no payloads
+pub struct RequestLog {
+    sessions: Mutex<HashMap<SessionId, Ring<RequestRecord>>>,
+    capacity: usize,
+}
+
+impl RequestLog {
+    /// Create a log that keeps at most `capacity` records per session
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            capacity,
+        }
+    }
+
+    /// Append a record, evicting the session's oldest one when full
+    pub async fn record(&self, record: RequestRecord) {
+        self.sessions
+            .lock()
+            .await
+            .entry(record.session_id)
+            .or_insert_with(|| Ring::new(self.capacity))
+            .push(record);
+    }
+
+    /// Drop the records of the sessions `live` does not keep, e.g. those
+    /// that ended
+    pub async fn retain(&self, live: impl Fn(&SessionId) -> bool) {
+        self.sessions
+            .lock()
+            .await
+            .retain(|session_id, _| live(session_id));
+    }
+
+    /// Return the records matching `query` across sessions, newest first
+    pub async fn query(&self, query: &HistoryQuery) -> Vec<RequestRecord> {
+        let mut records: Vec<_> = self
+            .sessions
+            .lock()
+            .await
+            .values()
+            .flat_map(|ring| ring.records.iter())
+            .filter(|record| query.matches(&record.session_name))
+            .cloned()
+            .collect();
+        records.sort_by_key(|record| std::cmp::Reverse(record.started_at));
+        records.truncate(query.limit.unwrap_or(usize::MAX));
+        records
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon_protocol::{OpenUrlOutcome, RequestOutcome};
+    use std::time::{Duration, SystemTime};
+
+    fn record(url: &str, session_name: &str) -> OpenUrlRecord {
+        OpenUrlRecord {
+            url: url.to_string(),
+            session_id: SessionId::new(),
+            session_name: session_name.to_string(),
+            requested_at: SystemTime::now(),
+            outcome: OpenUrlOutcome::Opened,
+        }
+    }
+
+    fn urls(records: &[OpenUrlRecord]) -> Vec<&str> {
+        records.iter().map(|record| record.url.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_query_newest_first_with_filters() {
+        let history = OpenUrlHistory::new(10);
+        history.record(record("https://a.example", "work")).await;
+        history.record(record("https://b.example", "home")).await;
+        history.record(record("https://c.example", "work")).await;
+
+        let all = history.query(&HistoryQuery::default()).await;
+        assert_eq!(
+            urls(&all),
+            vec![
+                "https://c.example",
+                "https://b.example",
+                "https://a.example"
+            ]
+        );
+
+        let work = history
+            .query(&HistoryQuery {
+                session: Some("work".to_string()),
+                limit: Some(1),
+            })
+            .await;
+        assert_eq!(urls(&work), vec!["https://c.example"]);
+    }
+
+    #[tokio::test]
+    async fn test_capacity_evicts_oldest() {
+        let history = OpenUrlHistory::new(2);
+        for url in [
+            "https://a.example",
+            "https://b.example",
+            "https://c.example",
+        ] {
+            history.record(record(url, "work")).await;
+        }
+
+        let all = history.query(&HistoryQuery::default()).await;
+        assert_eq!(urls(&all), vec!["https://c.example", "https://b.example"]);
+    }
+
+    fn request(session_id: SessionId, session_name: &str, command: &str, at: u64) -> RequestRecord {
+        RequestRecord {
+            session_id,
+            session_name: session_name.to_string(),
+            command: command.to_string(),
+            request_bytes: 20,
+            response_bytes: 40,
+            started_at: SystemTime::UNIX_EPOCH + Duration::from_secs(at),
+            duration: Duration::from_millis(3),
+            outcome: RequestOutcome::Succeeded,
+        }
+    }
+
+    fn commands(records: &[RequestRecord]) -> Vec<&str> {
+        records
+            .iter()
+            .map(|record| record.command.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_request_log_is_bounded_per_session() {
+        let log = RequestLog::new(2);
+        let (work, home) = (SessionId::new(), SessionId::new());
+        log.record(request(home, "home", "GetClipboard", 1)).await;
+        for (at, command) in ["OpenBrowser", "SetClipboard", "StartPortForward"]
+            .into_iter()
+            .enumerate()
+        {
+            log.record(request(work, "work", command, 2 + at as u64))
+                .await;
+        }
+
+        let all = log.query(&HistoryQuery::default()).await;
+        assert_eq!(
+            commands(&all),
+            vec!["StartPortForward", "SetClipboard", "GetClipboard"]
+        );
+
+        let home = log
+            .query(&HistoryQuery {
+                session: Some("home".to_string()),
+                limit: None,
+            })
+            .await;
+        assert_eq!(commands(&home), vec!["GetClipboard"]);
+
+        let latest = log
+            .query(&HistoryQuery {
+                session: None,
+                limit: Some(1),
+            })
+            .await;
+        assert_eq!(commands(&latest), vec!["StartPortForward"]);
+    }
+
+    #[tokio::test]
+    async fn test_request_log_drops_ended_sessions() {
+        let log = RequestLog::new(2);
+        let (work, home) = (SessionId::new(), SessionId::new());
+        log.record(request(work, "work", "GetClipboard", 1)).await;
+        log.record(request(home, "home", "SetClipboard", 2)).await;
+
+        log.retain(|session_id| *session_id == home).await;
+        let all = log.query(&HistoryQuery::default()).await;
+        assert_eq!(commands(&all), vec!["SetClipboard"]);
+        assert_eq!(log.sessions.lock().await.len(), 1);
+    }
+}
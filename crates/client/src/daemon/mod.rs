@@ -10,6 +10,7 @@
 //! - **Session Management**: Handles multiple concurrent client sessions
 //! - **Resource Sharing**: Allows multiple clients to share the same remote connections
 //! - **Background Operation**: Runs as a system service or user daemon
+//! - **URL Open Auditing**: Records every URL open request for `yuha history open-url`
 //!
 //! ## Architecture
 //!
@@ -49,6 +50,7 @@
 //! See `DaemonConfig` for available options.
 
 pub mod handler;
+pub mod history;
 pub mod server;
 
 use crate::daemon_protocol::DaemonConfig;
@@ -46,7 +46,11 @@ impl DaemonServer {
         };
 
         let session_manager = Arc::new(SessionManager::new(session_config));
-        let request_handler = Arc::new(RequestHandler::new(Arc::clone(&session_manager)));
+        let request_handler = Arc::new(RequestHandler::new(
+            Arc::clone(&session_manager),
+            config.open_url_history_size,
+            config.request_log_size,
+        ));
 
         Self {
             config,
@@ -5,7 +5,8 @@
 
 use crate::constants::default_socket_path;
 use crate::daemon_protocol::{
-    DaemonCommand, DaemonRequest, DaemonResponse, SessionDetails, SessionSummary,
+    DaemonCommand, DaemonRequest, DaemonResponse, HistoryQuery, OpenUrlRecord, RequestRecord,
+    SessionDetails, SessionSummary,
 };
 use anyhow::Result;
 use bytes::Bytes;
@@ -219,6 +220,40 @@ impl DaemonClient {
         })
     }
 
+    /// Query the history of URL open requests, newest first
+    pub async fn open_url_history(
+        &mut self,
+        query: HistoryQuery,
+    ) -> Result<Vec<OpenUrlRecord>, ClientError> {
+        let request = DaemonRequest::GetOpenUrlHistory { query };
+
+        let response = self.send_request(request).await?;
+        Self::handle_daemon_response(response, |resp| {
+            if let DaemonResponse::OpenUrlHistory { records } = resp {
+                Some(records)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Query the log of recent requests, newest first
+    pub async fn request_log(
+        &mut self,
+        query: HistoryQuery,
+    ) -> Result<Vec<RequestRecord>, ClientError> {
+        let request = DaemonRequest::GetRequestLog { query };
+
+        let response = self.send_request(request).await?;
+        Self::handle_daemon_response(response, |resp| {
+            if let DaemonResponse::RequestLog { records } = resp {
+                Some(records)
+            } else {
+                None
+            }
+        })
+    }
+
     /// Execute a command on a session
     pub async fn execute_command(
         &mut self,
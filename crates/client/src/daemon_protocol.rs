@@ -6,6 +6,7 @@
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
 use yuha_core::session::SessionId;
 use yuha_core::transport::TransportConfig;
 
@@ -44,6 +45,12 @@ pub enum DaemonRequest {
         command: DaemonCommand,
     },
 
+    /// Query the history of URL open requests
+    GetOpenUrlHistory { query: HistoryQuery },
+
+    /// Query the log of recent requests executed on sessions
+    GetRequestLog { query: HistoryQuery },
+
     /// Shutdown the daemon
     Shutdown,
 }
@@ -74,6 +81,20 @@ pub enum DaemonCommand {
     PortForwardData { connection_id: u32, data: Bytes },
 }
 
+impl DaemonCommand {
+    /// Name of the command, as recorded in the request log
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::GetClipboard => "GetClipboard",
+            Self::SetClipboard { .. } => "SetClipboard",
+            Self::OpenBrowser { .. } => "OpenBrowser",
+            Self::StartPortForward { .. } => "StartPortForward",
+            Self::StopPortForward { .. } => "StopPortForward",
+            Self::PortForwardData { .. } => "PortForwardData",
+        }
+    }
+}
+
 /// Response from daemon to CLI
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DaemonResponse {
@@ -95,6 +116,12 @@ pub enum DaemonResponse {
     /// Command executed successfully
     CommandSuccess { result: CommandResult },
 
+    /// Recorded URL open requests, newest first
+    OpenUrlHistory { records: Vec<OpenUrlRecord> },
+
+    /// Recent requests, newest first
+    RequestLog { records: Vec<RequestRecord> },
+
     /// Daemon shutting down
     ShuttingDown,
 
@@ -138,6 +165,64 @@ pub struct PortForwardInfo {
     pub active_connections: u32,
 }
 
+/// A URL open request handled by the daemon
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenUrlRecord {
+    pub url: String,
+    /// Session that requested the URL
+    pub session_id: SessionId,
+    pub session_name: String,
+    pub requested_at: SystemTime,
+    pub outcome: OpenUrlOutcome,
+}
+
+/// Outcome of a URL open request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OpenUrlOutcome {
+    Opened,
+    Failed { reason: String },
+}
+
+/// A request executed on a session, described without its payload
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestRecord {
+    pub session_id: SessionId,
+    pub session_name: String,
+    /// Name of the command
+    pub command: String,
+    /// Encoded sizes of the request and its response
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+    pub started_at: SystemTime,
+    pub duration: Duration,
+    pub outcome: RequestOutcome,
+}
+
+/// Outcome of a request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RequestOutcome {
+    Succeeded,
+    Failed { reason: String },
+}
+
+/// Filter for history and request log queries
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistoryQuery {
+    /// Only include requests from the session with this name
+    pub session: Option<String>,
+    /// Maximum number of records to return
+    pub limit: Option<usize>,
+}
+
+impl HistoryQuery {
+    /// Whether requests from `session_name` are included
+    pub fn matches(&self, session_name: &str) -> bool {
+        self.session
+            .as_deref()
+            .is_none_or(|session| session == session_name)
+    }
+}
+
 /// Result of command execution
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CommandResult {
@@ -212,10 +297,26 @@ pub struct DaemonConfig {
     /// PID file path (optional)
     pub pid_file: Option<String>,
 
+    /// Maximum number of URL open requests kept in the history
+    #[serde(default = "default_open_url_history_size")]
+    pub open_url_history_size: usize,
+
+    /// Maximum number of requests kept in the log of each session
+    #[serde(default = "default_request_log_size")]
+    pub request_log_size: usize,
+
     /// Additional configuration options
     pub options: HashMap<String, String>,
 }
 
+fn default_open_url_history_size() -> usize {
+    1000
+}
+
+fn default_request_log_size() -> usize {
+    100
+}
+
 impl Default for DaemonConfig {
     fn default() -> Self {
         Self {
@@ -225,6 +326,8 @@ impl Default for DaemonConfig {
             session_idle_timeout: 300, // 5 minutes
             log_file: None,
             pid_file: None,
+            open_url_history_size: default_open_url_history_size(),
+            request_log_size: default_request_log_size(),
             options: HashMap::new(),
         }
     }
@@ -0,0 +1,286 @@
+//! # Editing Remote Files
+//!
+//! Edits a remote file in place with a local editor: the file is downloaded
+//! into the staging directory shared with [`crate::open`], and every save of
+//! the local copy is uploaded back while the editor runs. A failed upload is
+//! retried on the next save, and once more when the editor exits, which
+//! reports it if it still fails. The copy is removed once the last save
+//! reached the remote, and kept when an upload failed so no edit is lost. A save is not uploaded over a remote file that changed
+//! since it was downloaded; the upload fails with
+//! [`ErrorCode::Conflict`](yuha_core::protocol::ErrorCode::Conflict) instead.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+use yuha_core::checksum;
+use yuha_core::error::YuhaError;
+
+use crate::ClientError;
+use crate::client_transport::Client;
+use crate::transport::Transport;
+
+/// How often the local copy is checked for saves
+const SAVE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Modification time and size of a saved file
+type Stamp = (SystemTime, u64);
+
+fn stamp(path: &Path) -> io::Result<Stamp> {
+    let metadata = std::fs::metadata(path)?;
+    Ok((metadata.modified()?, metadata.len()))
+}
+
+/// Local copy of a remote file being edited
+#[derive(Debug)]
+pub struct StagedFile {
+    /// Path on the remote host
+    pub remote_path: String,
+    /// Copy the editor works on
+    pub local_path: PathBuf,
+    /// State of the copy when it last matched the remote file
+    synced: Stamp,
+    /// BLAKE3 digest of the remote file when it was last synced
+    remote_digest: String,
+}
+
+impl StagedFile {
+    /// Whether the copy was saved since it last matched the remote file
+    pub fn is_modified(&self) -> io::Result<bool> {
+        Ok(stamp(&self.local_path)? != self.synced)
+    }
+}
+
+/// The user's editor from `$VISUAL` or `$EDITOR`, split on whitespace
+pub fn default_editor() -> Vec<String> {
+    ["VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(std::env::var_os)
+        .map(|editor| {
+            editor
+                .to_string_lossy()
+                .split_whitespace()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .find(|command| !command.is_empty())
+        .unwrap_or_else(|| {
+            let editor = if cfg!(windows) { "notepad" } else { "vi" };
+            vec![editor.to_string()]
+        })
+}
+
+impl<T: Transport> Client<T> {
+    /// Download the remote file `path` under `staging_dir` for editing
+    pub async fn stage_for_edit(
+        &self,
+        path: &str,
+        staging_dir: &Path,
+    ) -> Result<StagedFile, ClientError> {
+        let local_path = self.stage_file(path, staging_dir).await?;
+        // The download is verified, so the copy has the remote content
+        let remote_digest = checksum::digest(&tokio::fs::read(&local_path).await?);
+        Ok(StagedFile {
+            remote_path: path.to_string(),
+            synced: stamp(&local_path)?,
+            remote_digest,
+            local_path,
+        })
+    }
+
+    /// Upload the copy if it was saved since the last sync; returns whether
+    /// it was uploaded
+    pub async fn sync_staged(&self, file: &mut StagedFile) -> Result<bool, ClientError> {
+        if !file.is_modified()? {
+            return Ok(false);
+        }
+        let saved = stamp(&file.local_path)?;
+        file.remote_digest = self
+            .upload_file_if_unchanged(
+                &file.local_path,
+                &file.remote_path,
+                Some(&file.remote_digest),
+            )
+            .await?;
+        file.synced = saved;
+        info!("Uploaded {}", file.remote_path);
+        Ok(true)
+    }
+
+    /// Edit the remote file `path` with `editor`, uploading each save, and
+    /// return the number of uploads
+    pub async fn edit(
+        &self,
+        path: &str,
+        staging_dir: &Path,
+        editor: &[String],
+    ) -> Result<usize, ClientError> {
+        let Some((program, args)) = editor.split_first() else {
+            return Err(ClientError::Request(YuhaError::config("No editor to run")));
+        };
+        let mut file = self.stage_for_edit(path, staging_dir).await?;
+        match self.run_editor(program, args, &mut file).await {
+            Ok(uploads) => {
+                std::fs::remove_file(&file.local_path)?;
+                Ok(uploads)
+            }
+            Err(e) => {
+                warn!("Edits kept in {}", file.local_path.display());
+                Err(e)
+            }
+        }
+    }
+
+    /// Run the editor on the copy, uploading saves until it exits
+    ///
+    /// A save whose upload failed is left until the next one, so the editor
+    /// keeps running; the copy is synced once more after it exited.
+    async fn run_editor(
+        &self,
+        program: &str,
+        args: &[String],
+        file: &mut StagedFile,
+    ) -> Result<usize, ClientError> {
+        let mut child = tokio::process::Command::new(program)
+            .args(args)
+            .arg(&file.local_path)
+            .spawn()?;
+
+        let mut uploads = 0;
+        // Save whose upload failed, not retried until saved again
+        let mut failed = None;
+        let status = loop {
+            tokio::select! {
+                status = child.wait() => break status?,
+                _ = tokio::time::sleep(SAVE_POLL_INTERVAL) => {
+                    let saved = stamp(&file.local_path)?;
+                    if failed == Some(saved) {
+                        continue;
+                    }
+                    match self.sync_staged(file).await {
+                        Ok(uploaded) => uploads += uploaded as usize,
+                        Err(e) => {
+                            warn!(
+                                "Failed to upload {}, retrying on the next save: {}",
+                                file.remote_path, e
+                            );
+                            failed = Some(saved);
+                        }
+                    }
+                }
+            }
+        };
+        // Saves right before the editor exited, and a failed one again
+        uploads += self.sync_staged(file).await? as usize;
+
+        if !status.success() {
+            warn!("{} exited with {}", program, status);
+        }
+        Ok(uploads)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::DuplexTransport;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::DuplexStream;
+    use yuha_core::message_channel::MessageChannel;
+    use yuha_core::protocol::{ErrorCode, ProtocolRequest, ProtocolResponse, ResponseItem};
+
+    /// Serve `/src/main.rs` holding `content`, failing its first upload and
+    /// recording the content of each later one in `uploads`
+    async fn serve_edits(stream: DuplexStream, content: &[u8], uploads: Arc<Mutex<Vec<String>>>) {
+        let mut channel = MessageChannel::new_with_stream(stream);
+        let mut attempts = 0;
+        while let Ok(request) = channel.receive_request().await {
+            let response = match request {
+                ProtocolRequest::Hello { .. } => ProtocolResponse::Data {
+                    items: vec![ResponseItem::Extensions {
+                        extensions: Vec::new(),
+                    }],
+                    next: None,
+                },
+                ProtocolRequest::ListFiles { .. } => ProtocolResponse::Batch {
+                    items: vec![ResponseItem::FileEntry {
+                        path: "/src/main.rs".to_string(),
+                        relative_path: "main.rs".to_string(),
+                        size: content.len() as u64,
+                    }],
+                    more: false,
+                },
+                ProtocolRequest::ReadFileChunk { path, offset, .. } => ProtocolResponse::Data {
+                    items: vec![ResponseItem::FileChunk {
+                        path,
+                        offset,
+                        crc32c: checksum::crc32c(content),
+                        data: bytes::Bytes::copy_from_slice(content),
+                        eof: true,
+                    }],
+                    next: None,
+                },
+                ProtocolRequest::WriteFileChunk { data, .. } => {
+                    attempts += 1;
+                    if attempts == 1 {
+                        ProtocolResponse::error(ErrorCode::Internal, "disk full")
+                    } else {
+                        let data = String::from_utf8_lossy(&data).into_owned();
+                        uploads.lock().unwrap().push(data);
+                        ProtocolResponse::Success
+                    }
+                }
+                _ => ProtocolResponse::error(ErrorCode::Unsupported, "unsupported"),
+            };
+            channel.send_response(&response).await.unwrap();
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_failed_upload_retried_on_next_save() {
+        let (client_stream, server_stream) = tokio::io::duplex(1 << 20);
+        let uploads = Arc::new(Mutex::new(Vec::new()));
+        tokio::spawn(serve_edits(server_stream, b"fn main() {}", uploads.clone()));
+        let mut client = Client::new(DuplexTransport::new(client_stream));
+        client.connect().await.unwrap();
+
+        // Saves twice, each seen by a few save polls before the next; saved
+        // by renaming so no poll sees a half written file
+        let save = |content: &str| format!("echo '{}' > \"$0.new\"; mv \"$0.new\" \"$0\"", content);
+        let editor = [
+            "sh".to_string(),
+            "-c".to_string(),
+            format!(
+                "{}; sleep 1.5; {}; sleep 1.5",
+                save("first"),
+                save("second save")
+            ),
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        let uploaded = client.edit("/src/main.rs", dir.path(), &editor).await;
+
+        // The first save failed once, without retries until the second one
+        assert_eq!(uploaded.unwrap(), 1);
+        assert_eq!(*uploads.lock().unwrap(), ["second save\n"]);
+    }
+
+    #[test]
+    fn test_is_modified() {
+        let dir = tempfile::tempdir().unwrap();
+        let local_path = dir.path().join("main.rs");
+        std::fs::write(&local_path, "fn main() {}").unwrap();
+        let file = StagedFile {
+            remote_path: "/src/main.rs".to_string(),
+            synced: stamp(&local_path).unwrap(),
+            remote_digest: checksum::digest(b"fn main() {}"),
+            local_path,
+        };
+        assert!(!file.is_modified().unwrap());
+
+        // A different size is a save even within the timestamp granularity
+        std::fs::write(&file.local_path, "fn main() { run() }").unwrap();
+        assert!(file.is_modified().unwrap());
+    }
+}
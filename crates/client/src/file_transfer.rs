@@ -0,0 +1,445 @@
+//! # File Transfer
+//!
+//! Downloads remote files referenced by a copied file list, so pasting a
+//! remote `text/uri-list` / `CF_HDROP` clipboard locally yields real files.
+//!
+//! Transfers are planned first so callers can show the total size and ask for
+//! confirmation before any data moves.
+//!
+//! Local files are uploaded the other way, replacing the remote file only
+//! once all of it arrived.
+
+use bytes::Bytes;
+use std::path::{Component, Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, info};
+
+use yuha_core::checksum;
+use yuha_core::clipboard::ClipboardItem;
+use yuha_core::protocol::request_response::MAX_FILE_CHUNK_LEN;
+use yuha_core::protocol::{ErrorCode, ListQuery};
+
+use crate::ClientError;
+use crate::client_transport::Client;
+use crate::transport::Transport;
+
+/// A regular file on the remote host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteFile {
+    /// Absolute path on the remote host
+    pub path: String,
+    /// Path relative to the parent of the copied entry
+    pub relative_path: String,
+    /// Size in bytes
+    pub size: u64,
+}
+
+/// An entry of a remote directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteEntry {
+    /// Name within the directory
+    pub name: String,
+    /// Whether it is a directory, or a link to one
+    pub dir: bool,
+}
+
+/// Files that a paste would transfer
+#[derive(Debug, Clone)]
+pub struct TransferPlan {
+    pub files: Vec<RemoteFile>,
+    pub total_size: u64,
+}
+
+impl TransferPlan {
+    pub fn new(files: Vec<RemoteFile>) -> Self {
+        let total_size = files.iter().map(|file| file.size).sum();
+        Self { files, total_size }
+    }
+}
+
+/// Resolve a remote relative path under `dest_dir`, rejecting paths that escape it
+fn local_destination(dest_dir: &Path, relative_path: &str) -> Result<PathBuf, ClientError> {
+    let relative = Path::new(relative_path);
+    if relative.as_os_str().is_empty()
+        || !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(ClientError::remote(
+            ErrorCode::PermissionDenied,
+            format!(
+                "Refusing to write outside the destination: {}",
+                relative_path
+            ),
+        ));
+    }
+    Ok(dest_dir.join(relative))
+}
+
+impl<T: Transport> Client<T> {
+    /// Plan the transfer of the files referenced by a remote file list
+    pub async fn plan_file_list_transfer(
+        &self,
+        item: &ClipboardItem,
+    ) -> Result<TransferPlan, ClientError> {
+        let paths = item
+            .paths()
+            .map_err(|e| ClientError::Channel(e.to_string()))?
+            .into_iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        Ok(TransferPlan::new(
+            self.list_files(paths, ListQuery::default()).await?,
+        ))
+    }
+
+    /// Download a single remote file to a local path
+    ///
+    /// An existing local file is only replaced with `overwrite`; otherwise
+    /// the download fails with an `AlreadyExists` I/O error.
+    pub async fn download_file(
+        &self,
+        file: &RemoteFile,
+        dest: &Path,
+        overwrite: bool,
+    ) -> Result<(), ClientError> {
+        debug!("Downloading {} to {}", file.path, dest.display());
+
+        if let Some(parent) = dest.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut output = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .create_new(!overwrite)
+            .open(dest)
+            .await
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::AlreadyExists => {
+                    std::io::Error::new(e.kind(), format!("{} already exists", dest.display()))
+                }
+                _ => e,
+            })?;
+
+        let mut offset = 0;
+        loop {
+            let (data, eof) = self
+                .read_file_chunk(file.path.clone(), offset, MAX_FILE_CHUNK_LEN)
+                .await?;
+            output.write_all(&data).await?;
+            offset += data.len() as u64;
+            if eof || data.is_empty() {
+                break;
+            }
+        }
+        output.flush().await?;
+        Ok(())
+    }
+
+    /// Upload the local file `src` over the remote file `path`
+    pub async fn upload_file(&self, src: &Path, path: &str) -> Result<(), ClientError> {
+        self.upload_file_if_unchanged(src, path, None).await?;
+        Ok(())
+    }
+
+    /// Upload the local file `src` over the remote file `path` if it still
+    /// has the BLAKE3 digest `expected`, and return the digest of the
+    /// uploaded content
+    ///
+    /// A remote file changed meanwhile fails with [`ErrorCode::Conflict`]
+    /// and is left as it is.
+    pub async fn upload_file_if_unchanged(
+        &self,
+        src: &Path,
+        path: &str,
+        expected: Option<&str>,
+    ) -> Result<String, ClientError> {
+        debug!("Uploading {} to {}", src.display(), path);
+
+        // Read at once so a file saved again meanwhile is not sent half old
+        let content = Bytes::from(tokio::fs::read(src).await?);
+        let chunk_len = MAX_FILE_CHUNK_LEN as usize;
+        let mut offset = 0;
+        loop {
+            let end = (offset + chunk_len).min(content.len());
+            let last = end == content.len();
+            self.write_file_chunk(
+                path.to_string(),
+                offset as u64,
+                content.slice(offset..end),
+                last,
+                expected.filter(|_| last).map(str::to_string),
+            )
+            .await?;
+            if last {
+                return Ok(checksum::digest(&content));
+            }
+            offset = end;
+        }
+    }
+
+    /// Paste a remote file list into `dest_dir`
+    ///
+    /// `confirm` is shown the plan before anything is transferred; returning
+    /// `false` cancels the paste and yields `None`. On success, returns a file
+    /// list referencing the local copies of the copied entries. Existing
+    /// local files are only replaced with `overwrite`.
+    pub async fn paste_file_list<F>(
+        &self,
+        item: &ClipboardItem,
+        dest_dir: &Path,
+        overwrite: bool,
+        confirm: F,
+    ) -> Result<Option<ClipboardItem>, ClientError>
+    where
+        F: FnOnce(&TransferPlan) -> bool,
+    {
+        let plan = self.plan_file_list_transfer(item).await?;
+        if !confirm(&plan) {
+            info!("File list paste cancelled");
+            return Ok(None);
+        }
+
+        let mut pasted_roots = Vec::new();
+        for file in &plan.files {
+            let dest = local_destination(dest_dir, &file.relative_path)?;
+            self.download_file(file, &dest, overwrite).await?;
+
+            let root = dest_dir.join(
+                Path::new(&file.relative_path)
+                    .components()
+                    .next()
+                    .expect("destination validated as non-empty"),
+            );
+            if !pasted_roots.contains(&root) {
+                pasted_roots.push(root);
+            }
+        }
+
+        info!(
+            "Pasted {} files ({} bytes) into {}",
+            plan.files.len(),
+            plan.total_size,
+            dest_dir.display()
+        );
+        ClipboardItem::file_list(&pasted_roots)
+            .map(Some)
+            .map_err(|e| ClientError::Channel(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::DuplexTransport;
+    use tokio::io::DuplexStream;
+    use yuha_core::checksum;
+    use yuha_core::message_channel::MessageChannel;
+    use yuha_core::protocol::{ProtocolRequest, ProtocolResponse, ResponseItem};
+
+    /// Serve file requests from an in-memory listing, corrupting chunks at or
+    /// past `corrupt_from`
+    async fn serve_files(stream: DuplexStream, content: Vec<u8>, corrupt_from: u64) {
+        let mut channel = MessageChannel::new_with_stream(stream);
+        while let Ok(request) = channel.receive_request().await {
+            let response = match request {
+                ProtocolRequest::Hello { .. } => ProtocolResponse::Data {
+                    items: vec![ResponseItem::Extensions {
+                        extensions: Vec::new(),
+                    }],
+                    next: None,
+                },
+                ProtocolRequest::ListFiles { .. } => {
+                    // Stream the listing over two batches
+                    let first = ProtocolResponse::Batch {
+                        items: vec![ResponseItem::FileEntry {
+                            path: "/remote/dir/data.bin".to_string(),
+                            relative_path: "dir/data.bin".to_string(),
+                            size: content.len() as u64,
+                        }],
+                        more: true,
+                    };
+                    channel.send_response(&first).await.unwrap();
+                    ProtocolResponse::Batch {
+                        items: Vec::new(),
+                        more: false,
+                    }
+                }
+                ProtocolRequest::ReadFileChunk { path, offset, len } => {
+                    let start = offset as usize;
+                    let end = (start + len as usize).min(content.len());
+                    let data = &content[start..end];
+                    ProtocolResponse::Data {
+                        items: vec![ResponseItem::FileChunk {
+                            path,
+                            offset,
+                            // Corrupt the checksum of chunks past the marker offset
+                            crc32c: checksum::crc32c(data) ^ (offset >= corrupt_from) as u32,
+                            data: bytes::Bytes::copy_from_slice(data),
+                            eof: end == content.len(),
+                        }],
+                        next: None,
+                    }
+                }
+                _ => ProtocolResponse::error(ErrorCode::Unsupported, "unsupported"),
+            };
+            channel.send_response(&response).await.unwrap();
+        }
+    }
+
+    /// Accept uploads, returning the content of the one completed last
+    async fn serve_uploads(stream: DuplexStream) -> Vec<u8> {
+        let mut channel = MessageChannel::new_with_stream(stream);
+        let (mut staged, mut uploaded) = (Vec::new(), Vec::new());
+        while let Ok(request) = channel.receive_request().await {
+            let response = match request {
+                ProtocolRequest::Hello { .. } => ProtocolResponse::Data {
+                    items: vec![ResponseItem::Extensions {
+                        extensions: Vec::new(),
+                    }],
+                    next: None,
+                },
+                ProtocolRequest::WriteFileChunk {
+                    offset,
+                    data,
+                    crc32c,
+                    last,
+                    ..
+                } => {
+                    assert_eq!(crc32c, checksum::crc32c(&data));
+                    staged.truncate(offset as usize);
+                    assert_eq!(staged.len() as u64, offset, "chunk out of order");
+                    staged.extend_from_slice(&data);
+                    if last {
+                        uploaded = std::mem::take(&mut staged);
+                    }
+                    ProtocolResponse::Success
+                }
+                _ => ProtocolResponse::error(ErrorCode::Unsupported, "unsupported"),
+            };
+            channel.send_response(&response).await.unwrap();
+        }
+        uploaded
+    }
+
+    async fn connected_client(content: Vec<u8>, corrupt_from: u64) -> Client<DuplexTransport> {
+        let (client_stream, server_stream) = tokio::io::duplex(1 << 20);
+        tokio::spawn(serve_files(server_stream, content, corrupt_from));
+
+        let mut client = Client::new(DuplexTransport::new(client_stream));
+        client.connect().await.unwrap();
+        client
+    }
+
+    #[test]
+    fn test_local_destination_rejects_escapes() {
+        let dest = Path::new("/tmp/paste");
+        assert_eq!(
+            local_destination(dest, "dir/file.txt").unwrap(),
+            PathBuf::from("/tmp/paste/dir/file.txt")
+        );
+        assert!(local_destination(dest, "../etc/passwd").is_err());
+        assert!(local_destination(dest, "/etc/passwd").is_err());
+        assert!(local_destination(dest, "").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_paste_file_list_downloads_files() {
+        let content: Vec<u8> = (0..(MAX_FILE_CHUNK_LEN * 2 + 5))
+            .map(|i| (i % 256) as u8)
+            .collect();
+        let client = connected_client(content.clone(), u64::MAX).await;
+        let dest = tempfile::tempdir().unwrap();
+        let item = ClipboardItem::file_list(&["/remote/dir"]).unwrap();
+
+        let mut seen_size = 0;
+        let pasted = client
+            .paste_file_list(&item, dest.path(), false, |plan| {
+                seen_size = plan.total_size;
+                true
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(seen_size, content.len() as u64);
+        assert_eq!(
+            std::fs::read(dest.path().join("dir/data.bin")).unwrap(),
+            content
+        );
+        assert_eq!(pasted.paths().unwrap(), vec![dest.path().join("dir")]);
+
+        // Pasting again leaves the local copy alone unless asked to replace it
+        std::fs::write(dest.path().join("dir/data.bin"), b"edited").unwrap();
+        let err = client
+            .paste_file_list(&item, dest.path(), false, |_| true)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ClientError::Io(e) if e.kind() == std::io::ErrorKind::AlreadyExists));
+        assert_eq!(
+            std::fs::read(dest.path().join("dir/data.bin")).unwrap(),
+            b"edited"
+        );
+        client
+            .paste_file_list(&item, dest.path(), true, |_| true)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read(dest.path().join("dir/data.bin")).unwrap(),
+            content
+        );
+    }
+
+    #[tokio::test]
+    async fn test_paste_file_list_cancelled() {
+        let client = connected_client(vec![1, 2, 3], u64::MAX).await;
+        let dest = tempfile::tempdir().unwrap();
+        let item = ClipboardItem::file_list(&["/remote/dir"]).unwrap();
+
+        let pasted = client
+            .paste_file_list(&item, dest.path(), false, |_| false)
+            .await
+            .unwrap();
+
+        assert!(pasted.is_none());
+        assert!(!dest.path().join("dir").exists());
+    }
+
+    #[tokio::test]
+    async fn test_upload_file() {
+        for len in [0, MAX_FILE_CHUNK_LEN as usize * 2 + 5] {
+            let content: Vec<u8> = (0..len).map(|i| (i % 256) as u8).collect();
+            let dir = tempfile::tempdir().unwrap();
+            let src = dir.path().join("data.bin");
+            std::fs::write(&src, &content).unwrap();
+
+            let (client_stream, server_stream) = tokio::io::duplex(1 << 20);
+            let server = tokio::spawn(serve_uploads(server_stream));
+            let mut client = Client::new(DuplexTransport::new(client_stream));
+            client.connect().await.unwrap();
+
+            client.upload_file(&src, "/remote/data.bin").await.unwrap();
+            drop(client);
+            assert_eq!(server.await.unwrap(), content);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_rejects_corrupt_chunk() {
+        let content = vec![7; MAX_FILE_CHUNK_LEN as usize * 2];
+        let client = connected_client(content, MAX_FILE_CHUNK_LEN as u64).await;
+        let dest = tempfile::tempdir().unwrap();
+        let file = RemoteFile {
+            path: "/remote/dir/data.bin".to_string(),
+            relative_path: "dir/data.bin".to_string(),
+            size: MAX_FILE_CHUNK_LEN as u64 * 2,
+        };
+
+        let err = client
+            .download_file(&file, &dest.path().join("data.bin"), false)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Checksum mismatch"), "{}", err);
+    }
+}
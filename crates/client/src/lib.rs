@@ -9,6 +9,27 @@
 //! - **Daemon Client**: Connection to local daemon for managing multiple sessions
 //! - **Transport Layer**: Abstraction over SSH, TCP, and local connections
 //! - **Protocol Handling**: Support for both client and daemon communication protocols
+//! - **Opening Paths**: Open remote files in remote or local applications
+//! - **Editing**: Edit remote files with a local editor, uploading each save
+//! - **Tailing**: Print the last lines of remote files and follow what is
+//!   appended, reading only the ranges needed
+//! - **Comparing**: Report the files differing between a local and a remote
+//!   tree by their hashes, e.g. to verify a sync
+//! - **Credentials**: Passwords, passphrases and one-time codes asked through a
+//!   `CredentialsProvider`, on the terminal or in an embedder's own dialogs
+//! - **Response Cache**: Optional TTL cache of idempotent reads for polling frontends
+//! - **Response Streams**: Items of streamed responses as a `Stream`, delivered
+//!   batch by batch, and of paged listings, following each page's cursor
+//! - **Pipelining**: Requests sent back to back without waiting for responses,
+//!   each awaited on its own
+//! - **Server Requests**: Handlers answering what the remote asks of the
+//!   client, such as opening a URL in a local browser
+//! - **Subscriptions**: Clipboard, port forward and file events of the remote
+//!   as `broadcast` receivers, pushed rather than polled
+//! - **Handoff**: Moving a live session to another transport, e.g. `AnyTransport`
+//!   switching from SSH to TCP
+//! - **Testing**: Isolated local remotes and free ports for tests running in
+//!   parallel (`testing` feature)
 //!
 //! ## Connection Types
 //!
@@ -34,12 +55,26 @@
 //! # }
 //! ```
 
+pub mod cache;
 pub mod client;
 pub mod client_transport;
+pub mod compare;
+mod connection;
 pub mod constants;
+pub mod credentials;
 pub mod daemon;
 pub mod daemon_client;
 pub mod daemon_protocol;
+pub mod edit;
+pub mod file_transfer;
+pub mod open;
+pub mod pipeline;
+pub mod server_requests;
+pub mod stream;
+mod subscriptions;
+pub mod tail;
+#[cfg(any(feature = "testing", test))]
+pub mod testing;
 pub mod transport;
 pub mod transport_factory;
 
@@ -102,8 +137,23 @@ pub enum ClientError {
     #[error("Connection error: {0}")]
     Connection(String),
 
-    #[error("Remote execution error: {0}")]
-    RemoteExecution(String),
+    /// The remote rejected the credentials
+    #[error("Authentication failed: {0}")]
+    Authentication(String),
+
+    /// The remote answered with an `Error` response, or the client refused
+    /// a request the same way on its behalf
+    #[error("Remote execution error: {message}")]
+    RemoteExecution {
+        code: yuha_core::protocol::ErrorCode,
+        message: String,
+        details: std::collections::BTreeMap<String, String>,
+    },
+
+    /// The remote does not know the request, e.g. as it is older than the
+    /// client; callers may fall back to older requests
+    #[error("Request {request} is not supported by the remote")]
+    Unsupported { request: String },
 
     #[error("Channel error: {0}")]
     Channel(String),
@@ -114,6 +164,14 @@ pub enum ClientError {
     #[error("Binary transfer error: {0}")]
     BinaryTransfer(String),
 
+    /// A request failed validation before it was sent
+    #[error("{0}")]
+    Request(#[from] yuha_core::YuhaError),
+
+    /// A prompt was needed while prompting is not allowed
+    #[error("Interaction required: {prompt}")]
+    InteractionRequired { prompt: String },
+
     #[error("Daemon error: {message} (code: {code:?})")]
     DaemonError {
         code: crate::daemon_protocol::ErrorCode,
@@ -121,6 +179,32 @@ pub enum ClientError {
     },
 }
 
+impl ClientError {
+    /// `RemoteExecution` error with `code` and no details
+    pub fn remote(code: yuha_core::protocol::ErrorCode, message: impl Into<String>) -> Self {
+        ClientError::RemoteExecution {
+            code,
+            message: message.into(),
+            details: Default::default(),
+        }
+    }
+
+    /// Code of an error the remote reported, `Unsupported` for requests it
+    /// does not know
+    pub fn error_code(&self) -> Option<yuha_core::protocol::ErrorCode> {
+        match self {
+            ClientError::RemoteExecution { code, .. } => Some(*code),
+            ClientError::Unsupported { .. } => Some(yuha_core::protocol::ErrorCode::Unsupported),
+            _ => None,
+        }
+    }
+
+    /// Whether the request may succeed when sent again later
+    pub fn is_retriable(&self) -> bool {
+        self.error_code().is_some_and(|code| code.is_retriable())
+    }
+}
+
 // Re-export commonly used transport types
 pub use transport::ssh::{MyHandler, SshChannelAdapter};
 
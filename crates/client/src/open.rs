@@ -0,0 +1,362 @@
+//! # Opening Paths
+//!
+//! Opens a remote path either on the remote desktop, with the handlers
+//! configured on the remote, or on this machine: the file is downloaded into
+//! a staging directory on demand and the copy is opened with local handlers.
+//! The other way round, a local file is uploaded into a staging directory
+//! on the remote and the copy is opened on the remote desktop.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tracing::info;
+
+use yuha_core::checksum;
+use yuha_core::error::{ProtocolError, YuhaError};
+use yuha_core::open::OpenHandlers;
+use yuha_core::protocol::extension;
+use yuha_core::protocol::{ErrorCode, ListQuery, ProtocolRequest, ProtocolResponse, ResponseItem};
+
+use crate::ClientError;
+use crate::client_transport::Client;
+use crate::transport::Transport;
+
+/// Where a path is opened
+#[derive(Debug, Clone, Copy)]
+pub enum OpenDirection<'a> {
+    /// On the remote desktop, with the remote's handlers
+    Remote,
+    /// On this machine, opening a copy kept under `staging_dir`
+    Local {
+        handlers: &'a OpenHandlers,
+        staging_dir: &'a Path,
+    },
+}
+
+/// Default directory for local copies of opened remote files
+pub fn default_staging_dir() -> PathBuf {
+    std::env::temp_dir().join("yuha-open")
+}
+
+/// Default directory on the remote for copies of opened local files
+pub const DEFAULT_REMOTE_STAGING_DIR: &str = "/tmp";
+
+/// Remote copy of local `path` in the remote directory `staging_dir`, which
+/// must exist; files of the same name from different directories get
+/// separate copies
+fn remote_staging_path(staging_dir: &str, path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map_or_else(|| "file".into(), |name| name.to_string_lossy());
+    format!(
+        "{}/yuha-open-{:08x}-{}",
+        staging_dir.trim_end_matches(['/', '\\']),
+        checksum::crc32c(path.to_string_lossy().as_bytes()),
+        name
+    )
+}
+
+/// Local copy of remote `path`; files of the same name from different
+/// directories get separate copies
+fn staging_path(staging_dir: &Path, path: &str) -> PathBuf {
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    staging_dir
+        .join(format!("{:08x}", checksum::crc32c(path.as_bytes())))
+        .join(name)
+}
+
+impl<T: Transport> Client<T> {
+    /// Open `path` of the remote host and return the process id of the
+    /// application that opened it
+    pub async fn open_path(
+        &self,
+        path: &str,
+        direction: OpenDirection<'_>,
+    ) -> Result<u32, ClientError> {
+        match direction {
+            OpenDirection::Remote => self.open_remote_path(path).await,
+            OpenDirection::Local {
+                handlers,
+                staging_dir,
+            } => {
+                let copy = self.stage_file(path, staging_dir).await?;
+                launch_local(&handlers.command(&copy.to_string_lossy()))
+            }
+        }
+    }
+
+    /// Upload the local file `path` into the remote directory `staging_dir`
+    /// and open the copy on the remote desktop, returning the process id of
+    /// the application that opened it
+    pub async fn open_local_path(
+        &self,
+        path: &Path,
+        staging_dir: &str,
+    ) -> Result<u32, ClientError> {
+        // Before uploading anything the remote could not open
+        self.require(&extension::OPEN_PATH)?;
+        let copy = remote_staging_path(staging_dir, path);
+        self.upload_file(path, &copy).await?;
+        info!("Uploaded {} to {}", path.display(), copy);
+        self.open_remote_path(&copy).await
+    }
+
+    async fn open_remote_path(&self, path: &str) -> Result<u32, ClientError> {
+        self.require(&extension::OPEN_PATH)?;
+        let request = ProtocolRequest::OpenPath {
+            path: path.to_string(),
+        };
+        match self.send_request(request).await? {
+            ProtocolResponse::Data { items, .. } => items
+                .into_iter()
+                .find_map(|item| match item {
+                    ResponseItem::AppLaunched { pid } => Some(pid),
+                    _ => None,
+                })
+                .ok_or_else(|| ClientError::Channel("Missing launched process".to_string())),
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            _ => Err(ClientError::Channel("Unexpected response type".to_string())),
+        }
+    }
+
+    /// Download the remote file `path` under `staging_dir`
+    pub(crate) async fn stage_file(
+        &self,
+        path: &str,
+        staging_dir: &Path,
+    ) -> Result<PathBuf, ClientError> {
+        let files = self
+            .list_files(vec![path.to_string()], ListQuery::default())
+            .await?;
+        // A directory lists its files under its own name
+        let file = match &files[..] {
+            [] => {
+                return Err(ClientError::remote(
+                    ErrorCode::NotFound,
+                    format!("{} not found", path),
+                ));
+            }
+            [file] if !file.relative_path.contains(['/', '\\']) => file,
+            _ => {
+                return Err(ClientError::Request(
+                    ProtocolError::InvalidRequest {
+                        reason: format!("{} is not a regular file", path),
+                    }
+                    .into(),
+                ));
+            }
+        };
+
+        let copy = staging_path(staging_dir, path);
+        // Staged copies are ours to replace
+        self.download_file(file, &copy, true).await?;
+        Ok(copy)
+    }
+}
+
+/// Start `command` detached from this process
+fn launch_local(command: &[String]) -> Result<u32, ClientError> {
+    let Some((program, args)) = command.split_first() else {
+        return Err(ClientError::Request(YuhaError::config(
+            "No command to open with",
+        )));
+    };
+    let child = std::process::Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    info!("Opened with {} (pid {})", program, child.id());
+    Ok(child.id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::DuplexTransport;
+    use std::sync::Arc;
+    use tokio::io::DuplexStream;
+    use tokio::sync::Mutex;
+    use yuha_core::message_channel::MessageChannel;
+
+    const CONTENT: &[u8] = b"fn main() {}\n";
+
+    /// Serve `/remote/main.rs`, and `/remote/dir` holding two files
+    async fn serve(stream: DuplexStream) {
+        let mut channel = MessageChannel::new_with_stream(stream);
+        while let Ok(request) = channel.receive_request().await {
+            let response = match request {
+                ProtocolRequest::Hello { .. } => ProtocolResponse::Data {
+                    items: vec![ResponseItem::Extensions {
+                        extensions: Vec::new(),
+                    }],
+                    next: None,
+                },
+                ProtocolRequest::ListFiles { paths, .. } => {
+                    let names: &[&str] = if paths[0] == "/remote/dir" {
+                        &["dir/a", "dir/b"]
+                    } else {
+                        &["main.rs"]
+                    };
+                    let items = names
+                        .iter()
+                        .map(|name| ResponseItem::FileEntry {
+                            path: format!("/remote/{}", name),
+                            relative_path: name.to_string(),
+                            size: CONTENT.len() as u64,
+                        })
+                        .collect();
+                    ProtocolResponse::Batch { items, more: false }
+                }
+                ProtocolRequest::ReadFileChunk { path, .. } => ProtocolResponse::Data {
+                    items: vec![ResponseItem::FileChunk {
+                        path,
+                        offset: 0,
+                        data: bytes::Bytes::from_static(CONTENT),
+                        crc32c: checksum::crc32c(CONTENT),
+                        eof: true,
+                    }],
+                    next: None,
+                },
+                _ => ProtocolResponse::error(ErrorCode::Unsupported, "unsupported"),
+            };
+            channel.send_response(&response).await.unwrap();
+        }
+    }
+
+    /// Paths a [`serve_open`] session wrote or opened, with the uploaded
+    /// content, empty for an opened path
+    type Served = Arc<Mutex<Vec<(String, Vec<u8>)>>>;
+
+    /// Accept uploads and open requests with `open-path` negotiated,
+    /// recording each uploaded file and opened path in `served`
+    async fn serve_open(stream: DuplexStream, served: Served) {
+        let mut channel = MessageChannel::new_with_stream(stream);
+        let mut uploading = Vec::new();
+        while let Ok(request) = channel.receive_request().await {
+            let response = match request {
+                ProtocolRequest::Hello { .. } => ProtocolResponse::Data {
+                    items: vec![ResponseItem::Extensions {
+                        extensions: vec![extension::OPEN_PATH.id],
+                    }],
+                    next: None,
+                },
+                ProtocolRequest::WriteFileChunk {
+                    path, data, last, ..
+                } => {
+                    uploading.extend_from_slice(&data);
+                    if last {
+                        let content = std::mem::take(&mut uploading);
+                        served.lock().await.push((path, content));
+                    }
+                    ProtocolResponse::Success
+                }
+                ProtocolRequest::OpenPath { path } => {
+                    served.lock().await.push((path, Vec::new()));
+                    ProtocolResponse::Data {
+                        items: vec![ResponseItem::AppLaunched { pid: 42 }],
+                        next: None,
+                    }
+                }
+                _ => ProtocolResponse::error(ErrorCode::Unsupported, "unsupported"),
+            };
+            channel.send_response(&response).await.unwrap();
+        }
+    }
+
+    async fn connected_client() -> Client<DuplexTransport> {
+        let (client_stream, server_stream) = tokio::io::duplex(1 << 16);
+        tokio::spawn(serve(server_stream));
+        let mut client = Client::new(DuplexTransport::new(client_stream));
+        client.connect().await.unwrap();
+        client
+    }
+
+    #[test]
+    fn test_staging_path() {
+        let dir = Path::new("/stage");
+        let a = staging_path(dir, "/a/main.rs");
+        let b = staging_path(dir, "/b/main.rs");
+        assert_eq!(a.file_name().unwrap(), "main.rs");
+        assert_eq!(b.file_name().unwrap(), "main.rs");
+        assert_ne!(a, b);
+        assert_eq!(
+            staging_path(dir, r"C:\src\lib.rs").file_name().unwrap(),
+            "lib.rs"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stage_file() {
+        let client = connected_client().await;
+        let staging = tempfile::tempdir().unwrap();
+
+        let copy = client
+            .stage_file("/remote/main.rs", staging.path())
+            .await
+            .unwrap();
+        assert_eq!(copy, staging_path(staging.path(), "/remote/main.rs"));
+        assert_eq!(std::fs::read(&copy).unwrap(), CONTENT);
+
+        let result = client.stage_file("/remote/dir", staging.path()).await;
+        assert!(matches!(result, Err(ClientError::Request(_))));
+    }
+
+    #[test]
+    fn test_remote_staging_path() {
+        let a = remote_staging_path("/tmp/", Path::new("/a/main.rs"));
+        let b = remote_staging_path("/tmp", Path::new("/b/main.rs"));
+        assert!(a.starts_with("/tmp/yuha-open-") && a.ends_with("-main.rs"));
+        assert!(b.starts_with("/tmp/yuha-open-") && b.ends_with("-main.rs"));
+        assert_ne!(a, b);
+    }
+
+    #[tokio::test]
+    async fn test_open_local_path_remotely() {
+        let (client_stream, server_stream) = tokio::io::duplex(1 << 16);
+        let served = Arc::new(Mutex::new(Vec::new()));
+        tokio::spawn(serve_open(server_stream, served.clone()));
+        let mut client = Client::new(DuplexTransport::new(client_stream));
+        client.connect().await.unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let local = dir.path().join("main.rs");
+        std::fs::write(&local, CONTENT).unwrap();
+        let pid = client.open_local_path(&local, "/stage").await.unwrap();
+        assert_eq!(pid, 42);
+
+        // The copy is uploaded, then opened where it was uploaded to
+        let copy = remote_staging_path("/stage", &local);
+        assert_eq!(
+            *served.lock().await,
+            [(copy.clone(), CONTENT.to_vec()), (copy, Vec::new())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_local_path_requires_extension() {
+        let client = connected_client().await;
+        let dir = tempfile::tempdir().unwrap();
+        let local = dir.path().join("main.rs");
+        std::fs::write(&local, CONTENT).unwrap();
+        let result = client.open_local_path(&local, "/stage").await;
+        assert!(matches!(result, Err(ClientError::Unsupported { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_open_remote_requires_extension() {
+        let client = connected_client().await;
+        let result = client
+            .open_path("/remote/main.rs", OpenDirection::Remote)
+            .await;
+        assert!(matches!(result, Err(ClientError::Unsupported { .. })));
+    }
+}
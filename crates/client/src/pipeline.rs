@@ -0,0 +1,113 @@
+//! # Pipelined Requests
+//!
+//! [`Client::send_pipelined`](crate::Client::send_pipelined) writes a
+//! request and returns at once with a [`PendingResponse`], so a script can
+//! send its next requests before the first one is answered and pay for one
+//! round trip instead of one per request. Each pending response is awaited
+//! on its own, in any order; responses are matched to their requests by
+//! correlation id.
+//!
+//! ```rust,no_run
+//! # async fn example(client: yuha_client::Client<yuha_client::transport::LocalTransport>) -> Result<(), yuha_client::ClientError> {
+//! use yuha_core::protocol::ProtocolRequest;
+//!
+//! let pending = client
+//!     .pipeline([
+//!         ProtocolRequest::SetClipboard { content: "copied".to_string() },
+//!         ProtocolRequest::GetClipboard,
+//!     ])
+//!     .await?;
+//! for response in pending {
+//!     println!("{:?}", response.await?);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A pending response resolves to the first response of its request, so
+//! requests answered with streamed batches are better sent with
+//! [`Client::stream_request`](crate::Client::stream_request).
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+
+use yuha_core::protocol::{CorrelationId, ProtocolRequest, ProtocolResponse};
+
+use crate::ClientError;
+use crate::cache::ResponseCache;
+use crate::connection::Responses;
+
+/// Response to a request sent without waiting for it
+///
+/// Resolves to the response, or to a `RemoteExecution` error for an
+/// `Error` response.
+pub struct PendingResponse {
+    state: State,
+}
+
+enum State {
+    /// Answered from the response cache without sending the request
+    Answered(Option<ProtocolResponse>),
+    Sent {
+        responses: Responses,
+        /// Cache to update with the response, and the request it answers
+        cache: Option<(Arc<ResponseCache>, ProtocolRequest)>,
+    },
+}
+
+impl PendingResponse {
+    pub(crate) fn answered(response: ProtocolResponse) -> Self {
+        Self {
+            state: State::Answered(Some(response)),
+        }
+    }
+
+    pub(crate) fn sent(
+        responses: Responses,
+        cache: Option<(Arc<ResponseCache>, ProtocolRequest)>,
+    ) -> Self {
+        Self {
+            state: State::Sent { responses, cache },
+        }
+    }
+
+    /// Correlation id of the request, to cancel it with; `None` for a
+    /// request answered from the cache
+    pub fn request_id(&self) -> Option<CorrelationId> {
+        match &self.state {
+            State::Answered(_) => None,
+            State::Sent { responses, .. } => Some(responses.id()),
+        }
+    }
+}
+
+impl Future for PendingResponse {
+    type Output = Result<ProtocolResponse, ClientError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let response = match &mut self.state {
+            State::Answered(response) => response.take().expect("polled after completion"),
+            State::Sent { responses, cache } => {
+                let response = ready!(responses.poll_next(cx))?;
+                if let Some((cache, request)) = cache {
+                    cache.update(request, &response);
+                }
+                response
+            }
+        };
+        Poll::Ready(match response {
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => Err(ClientError::RemoteExecution {
+                code,
+                message,
+                details,
+            }),
+            response => Ok(response),
+        })
+    }
+}
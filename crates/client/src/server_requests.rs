@@ -0,0 +1,141 @@
+//! # Server Requests
+//!
+//! A remote negotiating the `server-requests` extension may ask the client
+//! something, e.g. to open a URL on the client's host when a program on the
+//! remote calls `yuha open-browser`. Requests go to the
+//! [`ServerRequestHandler`]s added with
+//! [`Client::with_server_request_handler`](crate::Client::with_server_request_handler),
+//! in the order they were added, until one handles the request; the remote
+//! gets `Unhandled` when none does.
+//!
+//! The extension is only offered when a handler was added, so a remote never
+//! waits for a client that cannot answer.
+//!
+//! - [`FnHandler`] answers from a closure
+//! - [`BrowserHandler`] opens URLs with the platform's browser
+
+use async_trait::async_trait;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::debug;
+use yuha_core::protocol::{ServerReply, ServerRequest, ServerRequestId};
+
+/// Handler of requests of the remote
+#[async_trait]
+pub trait ServerRequestHandler: Send + Sync + fmt::Debug {
+    /// Answer `request`, `Unhandled` to leave it to the next handler
+    async fn handle(&self, request: &ServerRequest) -> ServerReply;
+}
+
+/// Answers from a closure
+pub struct FnHandler<F>(pub F);
+
+impl<F> fmt::Debug for FnHandler<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FnHandler")
+    }
+}
+
+#[async_trait]
+impl<F> ServerRequestHandler for FnHandler<F>
+where
+    F: Fn(&ServerRequest) -> ServerReply + Send + Sync,
+{
+    async fn handle(&self, request: &ServerRequest) -> ServerReply {
+        (self.0)(request)
+    }
+}
+
+/// Opens the URLs of `OpenUrl` requests in the platform's browser
+#[derive(Debug, Default)]
+pub struct BrowserHandler;
+
+#[async_trait]
+impl ServerRequestHandler for BrowserHandler {
+    async fn handle(&self, request: &ServerRequest) -> ServerReply {
+        let ServerRequest::OpenUrl { url } = request else {
+            return ServerReply::Unhandled;
+        };
+        match yuha_core::browser::open_url(url).await {
+            Ok(()) => ServerReply::Done,
+            Err(e) => ServerReply::Failed {
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
+/// Handlers of a client, asked in order
+pub(crate) type Handlers = Vec<Arc<dyn ServerRequestHandler>>;
+
+/// A request received by the connection task, answered with [`Self::reply`]
+pub(crate) struct ServerCall {
+    pub(crate) id: ServerRequestId,
+    pub(crate) request: ServerRequest,
+    pub(crate) replies: mpsc::UnboundedSender<(ServerRequestId, ServerReply)>,
+}
+
+impl ServerCall {
+    /// Send `reply` to the remote
+    pub(crate) fn reply(self, reply: ServerReply) {
+        // The connection may have closed meanwhile
+        let _ = self.replies.send((self.id, reply));
+    }
+}
+
+/// Answer the requests of `calls` with `handlers` until the connection
+/// closes
+pub(crate) async fn dispatch(handlers: Handlers, mut calls: mpsc::UnboundedReceiver<ServerCall>) {
+    while let Some(call) = calls.recv().await {
+        let reply = answer(&handlers, &call.request).await;
+        debug!(
+            "Server request {} ({}) answered {:?}",
+            call.id,
+            call.request.kind(),
+            reply
+        );
+        call.reply(reply);
+    }
+}
+
+/// Reply of the first handler handling `request`
+async fn answer(handlers: &Handlers, request: &ServerRequest) -> ServerReply {
+    for handler in handlers {
+        match handler.handle(request).await {
+            ServerReply::Unhandled => continue,
+            reply => return reply,
+        }
+    }
+    ServerReply::Unhandled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_first_handling_handler_replies() {
+        let handlers: Handlers = vec![
+            Arc::new(FnHandler(|request: &ServerRequest| match request {
+                ServerRequest::ClipboardChanged { .. } => ServerReply::Done,
+                _ => ServerReply::Unhandled,
+            })),
+            Arc::new(FnHandler(|_: &ServerRequest| ServerReply::Failed {
+                message: "no browser".to_string(),
+            })),
+        ];
+        let copied = ServerRequest::ClipboardChanged {
+            content: "copied".to_string(),
+        };
+        let url = ServerRequest::OpenUrl {
+            url: "https://example.com".to_string(),
+        };
+        assert_eq!(answer(&handlers, &copied).await, ServerReply::Done);
+        assert!(matches!(
+            answer(&handlers, &url).await,
+            ServerReply::Failed { .. }
+        ));
+        assert_eq!(answer(&Vec::new(), &url).await, ServerReply::Unhandled);
+    }
+}
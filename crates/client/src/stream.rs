@@ -0,0 +1,304 @@
+//! # Response Streams
+//!
+//! A request answered with streamed `Batch` responses (see
+//! [`yuha_core::protocol::batch`]) yields its items as a [`Stream`] while
+//! the batches arrive, so callers can handle a directory listing or log
+//! output incrementally instead of waiting for all of it. A request
+//! answered with a single `Data` response yields its items the same way.
+//!
+//! A stream that is no longer wanted can be cut short on the remote too by
+//! passing its [`request_id`](ResponseStream::request_id) to
+//! [`Client::cancel`](crate::Client::cancel).
+//!
+//! A listing answered page by page with `Data` responses carrying a cursor
+//! yields the items of all its pages as a [`PageStream`], from
+//! [`Client::paginate`](crate::Client::paginate); each next page is
+//! requested as soon as the previous one arrives.
+
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use yuha_core::protocol::{CorrelationId, ListQuery, ProtocolResponse, ResponseItem};
+
+use crate::ClientError;
+use crate::connection::Responses;
+
+/// Items of the responses to one request
+///
+/// Ends after the final batch, or after yielding the error that cut the
+/// stream short.
+pub struct ResponseStream {
+    responses: Responses,
+    items: VecDeque<ResponseItem>,
+    done: bool,
+}
+
+impl ResponseStream {
+    pub(crate) fn new(responses: Responses) -> Self {
+        Self {
+            responses,
+            items: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Correlation id of the request, to cancel it with
+    pub fn request_id(&self) -> CorrelationId {
+        self.responses.id()
+    }
+}
+
+impl Stream for ResponseStream {
+    type Item = Result<ResponseItem, ClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(item) = self.items.pop_front() {
+                return Poll::Ready(Some(Ok(item)));
+            }
+            if self.done {
+                return Poll::Ready(None);
+            }
+
+            let response = ready!(self.responses.poll_next(cx));
+            let (items, more) = match response {
+                Ok(ProtocolResponse::Batch { items, more }) => (items, more),
+                Ok(ProtocolResponse::Data { items, .. }) => (items, false),
+                Ok(ProtocolResponse::Success) => (Vec::new(), false),
+                Ok(ProtocolResponse::Error {
+                    message,
+                    code,
+                    details,
+                }) => {
+                    self.done = true;
+                    return Poll::Ready(Some(Err(ClientError::RemoteExecution {
+                        code,
+                        message,
+                        details,
+                    })));
+                }
+                // The connection routes these to the client's handlers and
+                // subscribers
+                Ok(ProtocolResponse::ServerRequest { .. } | ProtocolResponse::Event { .. }) => {
+                    continue;
+                }
+                Ok(ProtocolResponse::Unsupported { request_name }) => {
+                    self.done = true;
+                    return Poll::Ready(Some(Err(ClientError::Unsupported {
+                        request: request_name,
+                    })));
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+            };
+            self.items = items.into();
+            self.done = !more;
+        }
+    }
+}
+
+/// Response to the request of one page
+pub(crate) type PageFuture =
+    Pin<Box<dyn Future<Output = Result<ProtocolResponse, ClientError>> + Send>>;
+
+/// Items of the pages of a listing, following the cursor of each page
+///
+/// Ends after the page without a next cursor, or after yielding the error
+/// that cut the stream short.
+pub struct PageStream {
+    fetch: Box<dyn FnMut(ListQuery) -> PageFuture + Send>,
+    query: ListQuery,
+    page: Option<PageFuture>,
+    items: VecDeque<ResponseItem>,
+}
+
+impl PageStream {
+    /// Stream the pages `fetch` answers, starting with the one of `query`
+    pub(crate) fn new(
+        query: ListQuery,
+        mut fetch: impl FnMut(ListQuery) -> PageFuture + Send + 'static,
+    ) -> Self {
+        Self {
+            page: Some(fetch(query.clone())),
+            fetch: Box::new(fetch),
+            query,
+            items: VecDeque::new(),
+        }
+    }
+}
+
+impl Stream for PageStream {
+    type Item = Result<ResponseItem, ClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(item) = self.items.pop_front() {
+                return Poll::Ready(Some(Ok(item)));
+            }
+            let Some(page) = self.page.as_mut() else {
+                return Poll::Ready(None);
+            };
+
+            let response = ready!(page.as_mut().poll(cx));
+            self.page = None;
+            let error = match response {
+                Ok(ProtocolResponse::Data { items, next }) => {
+                    self.items = items.into();
+                    if let Some(cursor) = next {
+                        self.query.cursor = Some(cursor);
+                        let query = self.query.clone();
+                        self.page = Some((self.fetch)(query));
+                    }
+                    continue;
+                }
+                Ok(ProtocolResponse::Error {
+                    message,
+                    code,
+                    details,
+                }) => ClientError::RemoteExecution {
+                    code,
+                    message,
+                    details,
+                },
+                Ok(ProtocolResponse::Unsupported { request_name }) => ClientError::Unsupported {
+                    request: request_name,
+                },
+                Ok(_) => ClientError::Channel("Unexpected response type".to_string()),
+                Err(e) => e,
+            };
+            return Poll::Ready(Some(Err(error)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Connection;
+    use tokio::io::duplex;
+    use yuha_core::message_channel::MessageChannel;
+    use yuha_core::protocol::{ErrorCode, ProtocolRequest};
+
+    fn entry(i: u64) -> ResponseItem {
+        ResponseItem::FileEntry {
+            path: format!("/data/{}", i),
+            relative_path: i.to_string(),
+            size: i,
+        }
+    }
+
+    async fn next(stream: &mut ResponseStream) -> Option<Result<ResponseItem, ClientError>> {
+        std::future::poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx)).await
+    }
+
+    #[tokio::test]
+    async fn test_batches_streamed_as_items() {
+        let (client, server) = duplex(1 << 16);
+        let connection = Connection::spawn(MessageChannel::new_with_stream(client));
+
+        // The second batch is only sent once the first one was consumed
+        let (consumed, mut consumed_rx) = tokio::sync::mpsc::unbounded_channel();
+        let server = tokio::spawn(async move {
+            let mut channel = MessageChannel::new_with_stream(server);
+            channel.receive_request().await.unwrap();
+            let first = ProtocolResponse::Batch {
+                items: vec![entry(1), entry(2)],
+                more: true,
+            };
+            channel.send_response(&first).await.unwrap();
+            consumed_rx.recv().await.unwrap();
+            let last = ProtocolResponse::Batch {
+                items: vec![entry(3)],
+                more: false,
+            };
+            channel.send_response(&last).await.unwrap();
+
+            channel.receive_request().await.unwrap();
+            let error = ProtocolResponse::error(ErrorCode::Internal, "walk failed");
+            channel.send_response(&error).await.unwrap();
+        });
+
+        let request = ProtocolRequest::ListFiles {
+            paths: Vec::new(),
+            query: Default::default(),
+        };
+        let mut stream = ResponseStream::new(connection.submit(request.clone()).await.unwrap());
+        let mut sizes = Vec::new();
+        while let Some(item) = next(&mut stream).await {
+            let ResponseItem::FileEntry { size, .. } = item.unwrap() else {
+                panic!("unexpected item");
+            };
+            sizes.push(size);
+            if size == 2 {
+                consumed.send(()).unwrap();
+            }
+        }
+        assert_eq!(sizes, vec![1, 2, 3]);
+
+        let mut stream = ResponseStream::new(connection.submit(request).await.unwrap());
+        assert!(matches!(
+            next(&mut stream).await,
+            Some(Err(ClientError::RemoteExecution { .. }))
+        ));
+        assert!(next(&mut stream).await.is_none());
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pages_followed_by_cursor() {
+        let (client, server) = duplex(1 << 16);
+        let connection = Connection::spawn(MessageChannel::new_with_stream(client));
+
+        // Serve entries 1 to 5 in pages of the requested size
+        let server = tokio::spawn(async move {
+            let mut channel = MessageChannel::new_with_stream(server);
+            let mut requests = 0;
+            while let Ok(request) = channel.receive_request().await {
+                let ProtocolRequest::ListPortForwards { query } = request else {
+                    panic!("unexpected request");
+                };
+                requests += 1;
+                let mut pager = query.pager();
+                let items = (1..=5)
+                    .filter(|i| pager.accept(&i.to_string()))
+                    .map(entry)
+                    .collect();
+                let page = ProtocolResponse::Data {
+                    items,
+                    next: pager.next_cursor(),
+                };
+                channel.send_response(&page).await.unwrap();
+            }
+            requests
+        });
+
+        let query = ListQuery {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let fetch = move |query| -> PageFuture {
+            let connection = connection.clone();
+            Box::pin(async move {
+                let request = ProtocolRequest::ListPortForwards { query };
+                connection.submit(request).await?.next().await
+            })
+        };
+        let mut stream = PageStream::new(query, fetch);
+        let mut sizes = Vec::new();
+        while let Some(item) = std::future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await
+        {
+            let ResponseItem::FileEntry { size, .. } = item.unwrap() else {
+                panic!("unexpected item");
+            };
+            sizes.push(size);
+        }
+        assert_eq!(sizes, vec![1, 2, 3, 4, 5]);
+        drop(stream);
+        assert_eq!(server.await.unwrap(), 3);
+    }
+}
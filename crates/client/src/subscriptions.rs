@@ -0,0 +1,90 @@
+//! # Subscriptions
+//!
+//! Events of the topics a client subscribed to (`subscriptions` extension)
+//! are pushed by the remote out of turn. The connection task hands them to
+//! the [`Broker`] shared by the clones of a client, which fans each event
+//! out to the `broadcast` receivers of its topic returned by
+//! [`Client::subscribe`](crate::Client::subscribe). Receivers that fall
+//! behind miss the oldest events, as `broadcast` receivers do.
+//!
+//! The broker keeps the topics subscribed to, so they are subscribed again
+//! on the remote a client is handed off to.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use yuha_core::protocol::{Topic, TopicEvent};
+
+/// Events kept for receivers that fall behind
+const EVENTS_KEPT: usize = 256;
+
+/// Senders of the events of each subscribed topic
+#[derive(Debug, Default)]
+pub(crate) struct Broker {
+    topics: Mutex<HashMap<Topic, broadcast::Sender<TopicEvent>>>,
+}
+
+impl Broker {
+    /// A receiver of the events of `topic`, and whether the topic was not
+    /// subscribed to before
+    pub(crate) fn receiver(&self, topic: Topic) -> (broadcast::Receiver<TopicEvent>, bool) {
+        let mut topics = self.topics.lock().unwrap();
+        match topics.get(&topic) {
+            Some(sender) => (sender.subscribe(), false),
+            None => {
+                let (sender, receiver) = broadcast::channel(EVENTS_KEPT);
+                topics.insert(topic, sender);
+                (receiver, true)
+            }
+        }
+    }
+
+    /// Forget `topic`, closing its receivers; returns whether it was
+    /// subscribed to
+    pub(crate) fn remove(&self, topic: Topic) -> bool {
+        self.topics.lock().unwrap().remove(&topic).is_some()
+    }
+
+    /// The topics subscribed to
+    pub(crate) fn topics(&self) -> Vec<Topic> {
+        self.topics.lock().unwrap().keys().copied().collect()
+    }
+
+    /// Send `event` to the receivers of its topic
+    pub(crate) fn publish(&self, event: TopicEvent) {
+        if let Some(sender) = self.topics.lock().unwrap().get(&event.topic()) {
+            // Fails only while every receiver is dropped
+            let _ = sender.send(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_fan_out_to_their_topic() {
+        let broker = Broker::default();
+        let (mut first, new) = broker.receiver(Topic::Clipboard);
+        assert!(new);
+        let (mut second, new) = broker.receiver(Topic::Clipboard);
+        assert!(!new);
+
+        let changed = TopicEvent::ClipboardChanged {
+            text: Some("copied".to_string()),
+        };
+        broker.publish(TopicEvent::ConnectionClosed { connection_id: 1 });
+        broker.publish(changed.clone());
+        assert_eq!(first.try_recv().unwrap(), changed);
+        assert_eq!(second.try_recv().unwrap(), changed);
+        assert_eq!(broker.topics(), [Topic::Clipboard]);
+
+        assert!(broker.remove(Topic::Clipboard));
+        assert!(matches!(
+            first.try_recv(),
+            Err(broadcast::error::TryRecvError::Closed)
+        ));
+        assert!(!broker.remove(Topic::Clipboard));
+    }
+}
@@ -0,0 +1,491 @@
+//! # Tailing Remote Files
+//!
+//! Prints the last lines of a remote file and, when following, what is
+//! appended to it afterwards, like `tail -f`. Only the needed ranges are
+//! transferred: the end of the file is read backwards block by block until
+//! enough lines were found, then the file is polled for growth from the last
+//! offset read. A file that shrank was truncated or rotated and is followed
+//! again from its start.
+//!
+//! [`Client::tail_files`] follows every file matching a set of glob patterns
+//! at once, handing out whole lines tagged with their file and the time they
+//! were read. The patterns are expanded again now and then, so files created
+//! later, e.g. by date-based rotation, are followed from their start. They
+//! are expanded a path component at a time, listing only the directories
+//! leading to matches and skipping those that cannot be read. A file
+//! that went missing, e.g. between the rename and the create of a rotation,
+//! is retried and read from its start once it is back.
+
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+use yuha_core::protocol::extension;
+use yuha_core::protocol::request_response::MAX_FILE_RANGE_LEN;
+use yuha_core::protocol::{ErrorCode, ListQuery};
+
+use crate::ClientError;
+use crate::client_transport::Client;
+use crate::transport::Transport;
+
+/// How often a followed file is checked for growth
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often glob patterns are expanded again while following
+const RESCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Bytes read per step while looking backwards for the last lines
+const TAIL_READ_LEN: u32 = 64 * 1024;
+
+/// Offset in `data` where its last `lines` lines start, `None` when it holds
+/// fewer lines and more data before it is needed
+///
+/// A newline ending `data` ends its last line rather than starting another.
+pub fn last_lines_start(data: &[u8], lines: usize) -> Option<usize> {
+    if lines == 0 {
+        return Some(data.len());
+    }
+    let body = data.strip_suffix(b"\n").unwrap_or(data);
+    body.iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, byte)| **byte == b'\n')
+        .nth(lines - 1)
+        .map(|(newline, _)| newline + 1)
+}
+
+/// Whether `path` matches the glob `pattern`, where `*` stands for any run
+/// of characters and `?` for any one character, neither crossing a `/`
+pub fn glob_matches(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    // Position after the last `*` and the path position it matched up to
+    let mut star = None;
+    let (mut p, mut s) = (0, 0);
+    while s < path.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, s));
+                p += 1;
+            }
+            Some('?') if path[s] != '/' => {
+                p += 1;
+                s += 1;
+            }
+            Some(&c) if c != '?' && c == path[s] => {
+                p += 1;
+                s += 1;
+            }
+            _ => match star {
+                // Let the last `*` take one more character
+                Some((after, matched)) if path[matched] != '/' => {
+                    star = Some((after, matched + 1));
+                    p = after;
+                    s = matched + 1;
+                }
+                _ => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Whether `pattern` has wildcards, rather than naming a file
+pub fn has_wildcards(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Directory holding every match of `pattern`: its components before the
+/// first one with a wildcard
+fn glob_root(pattern: &str) -> &str {
+    let first_wildcard = pattern.find(['*', '?']).unwrap_or(pattern.len());
+    match pattern[..first_wildcard].rfind('/') {
+        Some(0) => "/",
+        Some(slash) => &pattern[..slash],
+        None => ".",
+    }
+}
+
+/// `name` in the directory `dir`, relative like `dir` when that is `.`
+fn join(dir: &str, name: &str) -> String {
+    match dir {
+        "." => name.to_string(),
+        _ if dir.ends_with('/') => format!("{}{}", dir, name),
+        _ => format!("{}/{}", dir, name),
+    }
+}
+
+/// A line read by [`Client::tail_files`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TailedLine {
+    /// Remote file the line is from
+    pub path: String,
+    /// When the line was read
+    pub read_at: SystemTime,
+    /// The line without its newline, invalid UTF-8 replaced
+    pub line: String,
+}
+
+/// A file followed by [`Client::tail_files`]
+struct Followed {
+    path: String,
+    /// Offset up to which the file was read
+    offset: u64,
+    /// Start of a line whose newline was not read yet
+    partial: Vec<u8>,
+    /// Whether the last read failed
+    missing: bool,
+}
+
+impl Followed {
+    fn new(path: String, offset: u64) -> Self {
+        Self {
+            path,
+            offset,
+            partial: Vec::new(),
+            missing: false,
+        }
+    }
+
+    /// Take in `data` read from the file, handing its whole lines to `on_line`
+    fn take(&mut self, data: &[u8], on_line: &mut impl FnMut(TailedLine)) {
+        self.partial.extend_from_slice(data);
+        let Some(last_newline) = self.partial.iter().rposition(|&byte| byte == b'\n') else {
+            return;
+        };
+        let rest = self.partial.split_off(last_newline + 1);
+        let complete = std::mem::replace(&mut self.partial, rest);
+        let read_at = SystemTime::now();
+        for line in complete[..last_newline].split(|&byte| byte == b'\n') {
+            on_line(TailedLine {
+                path: self.path.clone(),
+                read_at,
+                line: String::from_utf8_lossy(line).into_owned(),
+            });
+        }
+    }
+
+    /// Hand out the unterminated last line, if any
+    fn finish(&mut self, on_line: &mut impl FnMut(TailedLine)) {
+        if !self.partial.is_empty() {
+            self.partial.push(b'\n');
+            self.take(&[], on_line);
+        }
+    }
+}
+
+impl<T: Transport> Client<T> {
+    /// Write the last `lines` lines of the remote file `path` to `out`, then
+    /// keep writing what is appended to it if `follow` is set
+    ///
+    /// Following only ends with an error, e.g. when the file is removed.
+    pub async fn tail(
+        &self,
+        path: &str,
+        lines: usize,
+        follow: bool,
+        out: &mut impl Write,
+    ) -> Result<(), ClientError> {
+        let (tail, mut offset) = self.last_lines(path, lines).await?;
+        out.write_all(&tail)?;
+        out.flush()?;
+        if !follow {
+            return Ok(());
+        }
+
+        loop {
+            let data = self.appended(path, &mut offset).await?;
+            if !data.is_empty() {
+                out.write_all(&data)?;
+                out.flush()?;
+            }
+            tokio::time::sleep(FOLLOW_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Hand the last `lines` lines of every remote file matching one of the
+    /// glob `patterns` to `on_line`, file by file, then keep handing out the
+    /// lines appended to any of them if `follow` is set
+    ///
+    /// Following only ends with an error reaching the remote; files that
+    /// cannot be read are retried.
+    pub async fn tail_files(
+        &self,
+        patterns: &[String],
+        lines: usize,
+        follow: bool,
+        mut on_line: impl FnMut(TailedLine),
+    ) -> Result<(), ClientError> {
+        let paths = self.expand_globs(patterns).await?;
+        if paths.is_empty() {
+            return Err(ClientError::remote(
+                ErrorCode::NotFound,
+                format!("No remote file matches {}", patterns.join(" ")),
+            ));
+        }
+
+        let mut followed = Vec::new();
+        for path in paths {
+            let (tail, offset) = self.last_lines(&path, lines).await?;
+            let mut file = Followed::new(path, offset);
+            file.take(&tail, &mut on_line);
+            if !follow {
+                file.finish(&mut on_line);
+            }
+            followed.push(file);
+        }
+        if !follow {
+            return Ok(());
+        }
+
+        let mut scanned = Instant::now();
+        loop {
+            tokio::time::sleep(FOLLOW_POLL_INTERVAL).await;
+            if scanned.elapsed() >= RESCAN_INTERVAL {
+                scanned = Instant::now();
+                match self.expand_globs(patterns).await {
+                    Ok(paths) => {
+                        for path in paths {
+                            if !followed.iter().any(|file| file.path == path) {
+                                info!("Following {}", path);
+                                followed.push(Followed::new(path, 0));
+                            }
+                        }
+                    }
+                    Err(ClientError::RemoteExecution { message, .. }) => {
+                        warn!("Failed to look for new files: {}", message);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            for file in &mut followed {
+                if file.missing {
+                    // Back after a rotation, so a new file
+                    file.offset = 0;
+                }
+                match self.appended(&file.path, &mut file.offset).await {
+                    Ok(data) => {
+                        if file.missing {
+                            info!("{} is back, following it from the start", file.path);
+                            file.missing = false;
+                            file.partial.clear();
+                        }
+                        file.take(&data, &mut on_line);
+                    }
+                    Err(ClientError::RemoteExecution { message, .. }) => {
+                        if !file.missing {
+                            warn!("Failed to read {}: {}", file.path, message);
+                            file.missing = true;
+                        }
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+
+    /// Remote files matching one of the glob `patterns` (see
+    /// [`glob_matches`]), sorted; a pattern without wildcards names a file
+    pub async fn expand_globs(&self, patterns: &[String]) -> Result<Vec<String>, ClientError> {
+        let mut paths = BTreeSet::new();
+        for pattern in patterns {
+            if !has_wildcards(pattern) {
+                paths.insert(pattern.clone());
+            } else if self.extensions().contains(&extension::LIST_DIRECTORY.id) {
+                paths.extend(self.expand_components(pattern).await?);
+            } else {
+                paths.extend(self.expand_by_walk(pattern).await?);
+            }
+        }
+        Ok(paths.into_iter().collect())
+    }
+
+    /// Files matching `pattern`, listing one directory per path component
+    /// from its root; directories that cannot be listed are skipped
+    async fn expand_components(&self, pattern: &str) -> Result<Vec<String>, ClientError> {
+        let root = glob_root(pattern);
+        let rest = match root {
+            "." => pattern,
+            _ => pattern[root.len()..].trim_start_matches('/'),
+        };
+        let components: Vec<&str> = rest.split('/').filter(|c| !c.is_empty()).collect();
+
+        let mut found = vec![root.to_string()];
+        for (i, component) in components.iter().enumerate() {
+            let last = i + 1 == components.len();
+            let mut next = Vec::new();
+            for dir in &found {
+                // Directories on the way need not be listed to be entered
+                if !last && !has_wildcards(component) {
+                    next.push(join(dir, component));
+                    continue;
+                }
+                let entries = match self.list_directory(dir).await {
+                    Ok(entries) => entries,
+                    Err(ClientError::RemoteExecution { message, .. }) => {
+                        debug!("Skipping {}: {}", dir, message);
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+                next.extend(
+                    entries
+                        .into_iter()
+                        .filter(|entry| entry.dir != last && glob_matches(component, &entry.name))
+                        .map(|entry| join(dir, &entry.name)),
+                );
+            }
+            found = next;
+        }
+        Ok(found)
+    }
+
+    /// Files matching `pattern`, walking every directory under its root,
+    /// for remotes without `list-directory`
+    async fn expand_by_walk(&self, pattern: &str) -> Result<Vec<String>, ClientError> {
+        let root = glob_root(pattern);
+        let files = self
+            .list_files(vec![root.to_string()], ListQuery::default())
+            .await?;
+        Ok(files
+            .into_iter()
+            .map(|file| match root {
+                "." => file.path.trim_start_matches("./").to_string(),
+                _ => file.path,
+            })
+            .filter(|path| glob_matches(pattern, path))
+            .collect())
+    }
+
+    /// The last `lines` lines of `path` and the offset of their end
+    async fn last_lines(&self, path: &str, lines: usize) -> Result<(Vec<u8>, u64), ClientError> {
+        let (_, size) = self.read_file_range(path, 0, 0).await?;
+
+        // The end of the file, grown backwards until it holds enough lines
+        let mut start = size;
+        let mut tail = Vec::new();
+        let skip = loop {
+            if let Some(skip) = last_lines_start(&tail, lines) {
+                break skip;
+            }
+            if start == 0 {
+                break 0;
+            }
+            let len = start.min(u64::from(TAIL_READ_LEN));
+            start -= len;
+            let (data, _) = self.read_file_range(path, start, len as u32).await?;
+            tail.splice(0..0, data);
+        };
+        let end = start + tail.len() as u64;
+        tail.drain(..skip);
+        Ok((tail, end))
+    }
+
+    /// Everything appended to `path` since `offset`, moving `offset` past
+    /// it; a file shorter than `offset` was truncated or replaced and is read
+    /// again from its start
+    async fn appended(&self, path: &str, offset: &mut u64) -> Result<Vec<u8>, ClientError> {
+        let mut appended = Vec::new();
+        loop {
+            let (data, size) = self
+                .read_file_range(path, *offset, MAX_FILE_RANGE_LEN)
+                .await?;
+            if size < *offset {
+                warn!("{} was truncated, following it from the start", path);
+                *offset = 0;
+                continue;
+            }
+            *offset += data.len() as u64;
+            appended.extend_from_slice(&data);
+            // More was appended than a single read returns
+            if data.is_empty() || *offset >= size {
+                return Ok(appended);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_lines_start() {
+        let data = b"one\ntwo\nthree\n";
+        assert_eq!(last_lines_start(data, 0), Some(data.len()));
+        assert_eq!(last_lines_start(data, 1), Some(8));
+        assert_eq!(last_lines_start(data, 2), Some(4));
+        // The first line may continue before the data
+        assert_eq!(last_lines_start(data, 3), None);
+
+        // An unterminated last line counts as a line
+        assert_eq!(last_lines_start(b"one\ntwo", 1), Some(4));
+        assert_eq!(last_lines_start(b"", 1), None);
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("/var/log/*.log", "/var/log/app.log"));
+        assert!(glob_matches("/var/log/app.?", "/var/log/app.1"));
+        assert!(glob_matches("/srv/*/access.log", "/srv/web/access.log"));
+        assert!(glob_matches("*", "app.log"));
+        // Wildcards stay within a path component
+        assert!(!glob_matches("/var/log/*.log", "/var/log/nginx/error.log"));
+        assert!(!glob_matches("/var/log/app.?", "/var/log/app.10"));
+        assert!(!glob_matches("/var/log/*.log", "/var/log/app.log.1"));
+
+        assert_eq!(glob_root("/var/log/*.log"), "/var/log");
+        assert_eq!(glob_root("/srv/*/access.log"), "/srv");
+        assert_eq!(glob_root("/*.log"), "/");
+        assert_eq!(glob_root("*.log"), ".");
+    }
+
+    #[tokio::test]
+    async fn test_expand_globs_by_component() {
+        let dir = tempfile::tempdir().unwrap();
+        for path in [
+            "web/logs/a.log",
+            "web/logs/old/b.log",
+            "api/logs/c.log",
+            "api/x.log",
+        ] {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"line\n").unwrap();
+        }
+        // No `logs` in it, so skipped
+        std::fs::create_dir(dir.path().join("db")).unwrap();
+        // Matches `*` but is no directory
+        std::fs::write(dir.path().join("logs"), b"").unwrap();
+
+        let client = crate::testing::connect_local(crate::testing::remote_binary())
+            .await
+            .unwrap();
+        let root = dir.path().to_string_lossy();
+        let paths = client
+            .expand_globs(&[format!("{}/*/logs/*.log", root)])
+            .await
+            .unwrap();
+        assert_eq!(
+            paths,
+            [
+                format!("{}/api/logs/c.log", root),
+                format!("{}/web/logs/a.log", root),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_followed_splits_lines() {
+        let mut lines = Vec::new();
+        let mut on_line = |line: TailedLine| lines.push(line.line);
+        let mut file = Followed::new("/var/log/app.log".to_string(), 0);
+        file.take(b"first\nsec", &mut on_line);
+        file.take(b"ond\n\nthi", &mut on_line);
+        file.finish(&mut on_line);
+        assert_eq!(lines, ["first", "second", "", "thi"]);
+    }
+}
@@ -0,0 +1,162 @@
+//! # Testing Utilities
+//!
+//! Helpers for tests running real `yuha-remote` processes, safe to call from
+//! tests that run in parallel, in this crate and downstream (`testing`
+//! feature):
+//!
+//! - [`local_transport`] and [`connect_local`] start remotes isolated from
+//!   each other, each with its own temp directory and IPC socket
+//! - [`free_port`] picks a port for a remote to listen on that no other
+//!   caller in the process gets
+//! - [`DuplexTransport`] connects a client to a remote simulated by the test
+//!   over an in-memory stream
+//!
+//! ```rust,no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use yuha_client::testing;
+//!
+//! let client = testing::connect_local(testing::remote_binary()).await?;
+//! client
+//!     .start_port_forward(testing::free_port()?, "localhost".to_string(), 80)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::BTreeSet;
+use std::io;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::io::DuplexStream;
+
+use crate::transport::{LocalTransport, LocalTransportConfig, Transport, TransportConfig};
+use crate::{Client, ClientError};
+
+/// Ports already handed out by [`free_port`]
+static GIVEN_PORTS: Mutex<BTreeSet<u16>> = Mutex::new(BTreeSet::new());
+
+/// Attempts of [`free_port`] to find a port not handed out before
+const PORT_ATTEMPTS: usize = 64;
+
+/// The `yuha-remote` binary built along with this crate
+pub fn remote_binary() -> PathBuf {
+    PathBuf::from(crate::REMOTE_BINARY_PATH)
+}
+
+/// A transport starting the stdio remote at `binary_path`, isolated from
+/// other local remotes
+pub fn local_transport(
+    binary_path: impl Into<PathBuf>,
+    transport_config: TransportConfig,
+) -> LocalTransport {
+    let config = LocalTransportConfig {
+        binary_path: binary_path.into(),
+        isolated: true,
+        ..Default::default()
+    };
+    LocalTransport::new(config, transport_config)
+}
+
+/// Start the remote at `binary_path` isolated from other local remotes and
+/// connect to it
+pub async fn connect_local(
+    binary_path: impl Into<PathBuf>,
+) -> Result<Client<LocalTransport>, ClientError> {
+    let mut client = Client::new(local_transport(binary_path, TransportConfig::default()));
+    client.connect().await?;
+    Ok(client)
+}
+
+/// A loopback port that is free now and was not returned before
+///
+/// Another process may still take the port before it is used.
+pub fn free_port() -> io::Result<u16> {
+    let mut given = GIVEN_PORTS.lock().unwrap();
+    for _ in 0..PORT_ATTEMPTS {
+        let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+        if given.insert(port) {
+            return Ok(port);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AddrInUse,
+        "No free port left that was not handed out before",
+    ))
+}
+
+/// A transport handing out one end of an in-memory stream, whose other end
+/// a task of the test serves as the remote
+///
+/// Connects once; a second connect panics.
+pub struct DuplexTransport {
+    stream: tokio::sync::Mutex<Option<DuplexStream>>,
+    binary: Option<PathBuf>,
+}
+
+impl DuplexTransport {
+    /// A transport connecting to `stream`
+    pub fn new(stream: DuplexStream) -> Self {
+        Self {
+            stream: tokio::sync::Mutex::new(Some(stream)),
+            binary: None,
+        }
+    }
+
+    /// Claim `binary` as the local copy of the binary the remote runs
+    pub fn with_remote_binary(mut self, binary: impl Into<PathBuf>) -> Self {
+        self.binary = Some(binary.into());
+        self
+    }
+}
+
+#[async_trait]
+impl Transport for DuplexTransport {
+    type Stream = DuplexStream;
+
+    async fn connect(&self) -> Result<Self::Stream> {
+        Ok(self.stream.lock().await.take().expect("connected once"))
+    }
+
+    fn name(&self) -> &'static str {
+        "duplex"
+    }
+
+    fn remote_binary(&self) -> Option<&Path> {
+        self.binary.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yuha_core::protocol::ListQuery;
+
+    #[tokio::test]
+    async fn test_parallel_local_remotes() {
+        let remotes: Vec<_> = (0..4)
+            .map(|_| {
+                tokio::spawn(async {
+                    let client = connect_local(remote_binary()).await.unwrap();
+                    let port = free_port().unwrap();
+                    client
+                        .start_port_forward(port, "localhost".to_string(), 9)
+                        .await
+                        .unwrap();
+                    let forwards = client.list_port_forwards(ListQuery::default()).await;
+                    (port, forwards.unwrap().len())
+                })
+            })
+            .collect();
+
+        let mut ports = BTreeSet::new();
+        for remote in remotes {
+            let (port, forwards) = remote.await.unwrap();
+            assert!(ports.insert(port));
+            // Each remote sees its own forward only
+            assert_eq!(forwards, 1);
+        }
+    }
+}
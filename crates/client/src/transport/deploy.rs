@@ -0,0 +1,89 @@
+//! Remote binary deployment
+//!
+//! Uploaded remote binaries are kept at a fixed path on each host, and a
+//! copy of what was deployed is kept locally. When the binary changes, only
+//! a [`delta`] against the deployed version is uploaded and applied by that
+//! version; hosts without a deployment, or whose deployment no longer
+//! matches, get the whole binary. The deployed version checks the digest of
+//! the result, so a deployment changed on the host is never trusted.
+
+use std::path::PathBuf;
+use yuha_core::delta;
+
+/// Where uploaded binaries are deployed, relative to the remote home
+pub const DEPLOY_PATH: &str = ".cache/yuha/yuha-remote";
+
+/// What needs to be sent to bring a host to the current binary
+#[derive(Debug, PartialEq, Eq)]
+pub enum Upload {
+    /// The deployed binary is current
+    Unchanged,
+    /// A delta against the deployed binary
+    Delta(Vec<u8>),
+    /// The whole binary
+    Full,
+}
+
+/// Plan the upload of `binary` to a host last deployed with `deployed`
+pub fn plan(deployed: Option<&[u8]>, binary: &[u8]) -> Upload {
+    let Some(deployed) = deployed else {
+        return Upload::Full;
+    };
+    if deployed == binary {
+        return Upload::Unchanged;
+    }
+    let patch = delta::diff(deployed, binary);
+    // Unrelated builds share too little for a delta to pay off
+    if patch.len() < binary.len() / 2 {
+        Upload::Delta(patch)
+    } else {
+        Upload::Full
+    }
+}
+
+/// Local copy of the binary deployed to `destination` (`user@host:port`)
+pub fn deployed_copy(destination: &str) -> PathBuf {
+    let name: String = destination
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "@.-".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("yuha")
+        .join("deployed")
+        .join(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan() {
+        let deployed: Vec<u8> = (0..64 * 1024).map(|i| (i * 7 % 251) as u8).collect();
+        assert_eq!(plan(None, &deployed), Upload::Full);
+        assert_eq!(plan(Some(&deployed), &deployed), Upload::Unchanged);
+
+        let mut rebuilt = deployed.clone();
+        rebuilt[1000..1010].copy_from_slice(b"new symbol");
+        let Upload::Delta(patch) = plan(Some(&deployed), &rebuilt) else {
+            panic!("expected a delta");
+        };
+        assert_eq!(delta::apply(&deployed, &patch).unwrap(), rebuilt);
+
+        assert_eq!(plan(Some(b"old"), b"unrelated"), Upload::Full);
+    }
+
+    #[test]
+    fn test_deployed_copy() {
+        let copy = deployed_copy("alice@example.com:22");
+        assert_eq!(copy.file_name().unwrap(), "alice@example.com_22");
+        assert!(copy.starts_with(dirs::cache_dir().unwrap_or_else(std::env::temp_dir)));
+    }
+}
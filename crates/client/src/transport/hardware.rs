@@ -0,0 +1,160 @@
+//! Hardware-backed SSH keys
+//!
+//! FIDO2 (`sk-`) keys and PKCS#11 tokens keep their secret on the device, so
+//! they sign through the ssh-agent, which drives the device: `sk-` key files
+//! are lent to the agent for the connection and PKCS#11 providers are loaded
+//! into it. Signing may wait for the user to touch the device, which the
+//! SSH transport announces through its credentials provider.
+
+use anyhow::{Context, Result};
+use russh::keys::agent::Constraint;
+use russh::keys::agent::client::{AgentClient, AgentStream};
+use russh::keys::{Algorithm, PrivateKey, PublicKey};
+use std::future::Future;
+use std::pin::Pin;
+use tracing::{debug, warn};
+
+/// How long an `sk-` key lent to the agent stays there
+const LEND_SECONDS: u32 = 60;
+
+pub type Agent = AgentClient<Box<dyn AgentStream + Send + Unpin>>;
+
+/// Whether keys of `algorithm` live on a FIDO2 authenticator
+pub fn is_security_key(algorithm: &Algorithm) -> bool {
+    matches!(
+        algorithm,
+        Algorithm::SkEd25519 | Algorithm::SkEcdsaSha2NistP256
+    )
+}
+
+/// Connect to the user's ssh-agent
+pub async fn agent() -> Result<Agent> {
+    #[cfg(unix)]
+    let agent = AgentClient::connect_env()
+        .await
+        .context("Failed to connect to the ssh-agent, is SSH_AUTH_SOCK set?")?;
+    #[cfg(windows)]
+    let agent = AgentClient::connect_pageant().await;
+    Ok(agent.dynamic())
+}
+
+/// Make the agent hold `key`, lending it for a while if it does not yet
+pub async fn lend(agent: &mut Agent, key: &PrivateKey) -> Result<()> {
+    let identities = agent.request_identities().await?;
+    if !holds(&identities, key.public_key()) {
+        let constraints = [Constraint::KeyLifetime {
+            seconds: LEND_SECONDS,
+        }];
+        agent
+            .add_identity(key, &constraints)
+            .await
+            .context("The ssh-agent refused the security key")?;
+    }
+    Ok(())
+}
+
+/// Load the keys of the PKCS#11 `provider` into the agent, unlocking the
+/// token with `pin` unless it has a pinpad or no PIN, and return the keys
+/// of the token, only `configured` if given
+pub async fn load_provider(
+    agent: &mut Agent,
+    provider: &str,
+    pin: &str,
+    configured: Option<&PublicKey>,
+) -> Result<Vec<PublicKey>> {
+    // Reloaded, so that the keys it adds tell the token's apart from the
+    // other keys the agent holds
+    if agent.remove_smartcard_key(provider, &[]).await.is_ok() {
+        debug!("Unloaded {} from the ssh-agent", provider);
+    }
+    let before = agent.request_identities().await?;
+    if let Err(e) = agent.add_smartcard_key(provider, pin.as_bytes(), &[]).await {
+        warn!("The ssh-agent did not load {}: {}", provider, e);
+    }
+    let after = agent.request_identities().await?;
+    let keys = provider_keys(&before, after, configured);
+    if keys.is_empty() {
+        match configured {
+            Some(key) => anyhow::bail!(
+                "The ssh-agent holds no key {} of {}",
+                key.fingerprint(Default::default()),
+                provider
+            ),
+            None => anyhow::bail!("The ssh-agent holds no keys of {}", provider),
+        }
+    }
+    Ok(keys)
+}
+
+/// Box an authentication signed by the agent, whose future the compiler
+/// cannot otherwise prove `Send` inside an `async_trait` method
+pub fn sendable<'a, T>(
+    request: impl Future<Output = T> + Send + 'a,
+) -> Pin<Box<dyn Future<Output = T> + Send + 'a>> {
+    Box::pin(request)
+}
+
+/// Keys the agent holds `after` loading a provider but did not `before`,
+/// only `configured` if given
+fn provider_keys(
+    before: &[PublicKey],
+    after: Vec<PublicKey>,
+    configured: Option<&PublicKey>,
+) -> Vec<PublicKey> {
+    after
+        .into_iter()
+        .filter(|key| !holds(before, key))
+        .filter(|key| configured.is_none_or(|configured| configured.key_data() == key.key_data()))
+        .collect()
+}
+
+fn holds(identities: &[PublicKey], key: &PublicKey) -> bool {
+    identities
+        .iter()
+        .any(|identity| identity.key_data() == key.key_data())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh::keys::ssh_key::private::Ed25519Keypair;
+    use russh::keys::ssh_key::public::{KeyData, SkEd25519};
+
+    fn ed25519(seed: u8) -> PublicKey {
+        PrivateKey::from(Ed25519Keypair::from_seed(&[seed; 32]))
+            .public_key()
+            .clone()
+    }
+
+    #[test]
+    fn test_security_keys() {
+        let Ed25519Keypair { public, .. } = Ed25519Keypair::from_seed(&[1; 32]);
+        let sk = PublicKey::from(KeyData::SkEd25519(SkEd25519::new(
+            public,
+            "ssh:".to_string(),
+        )));
+        assert!(is_security_key(&sk.algorithm()));
+        assert!(!is_security_key(&ed25519(1).algorithm()));
+
+        assert!(holds(&[ed25519(2), sk.clone()], &sk));
+        // The same point is a different key without the authenticator
+        assert!(!holds(&[ed25519(1)], &sk));
+        assert!(!holds(&[], &ed25519(1)));
+    }
+
+    #[test]
+    fn test_provider_keys() {
+        let before = [ed25519(1), ed25519(2)];
+        let after = vec![ed25519(1), ed25519(2), ed25519(3), ed25519(4)];
+        // Keys the agent held already are not the token's
+        assert_eq!(
+            provider_keys(&before, after.clone(), None),
+            [ed25519(3), ed25519(4)]
+        );
+        assert_eq!(
+            provider_keys(&before, after.clone(), Some(&ed25519(4))),
+            [ed25519(4)]
+        );
+        assert!(provider_keys(&before, after, Some(&ed25519(1))).is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+//! Knocking before a TCP connection
+//!
+//! Some daemons sit behind firewalls that open their port only to hosts
+//! that knocked first, by connecting to a sequence of closed ports or by
+//! sending a single packet authorization with a client such as `fwknop`.
+//! The TCP transport calls [`knock`] with the configured [`KnockConfig`]
+//! before connecting.
+//!
+//! Knocks go to the first address `host` resolves to, which is also the
+//! first the connection tries, and come from the configured bind address
+//! so the firewall opens the port to the address that connects.
+
+use super::socket;
+use anyhow::{Context, Result, bail};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::net::{UdpSocket, lookup_host};
+use tokio::process::Command;
+use tokio::time::{Instant, sleep_until, timeout};
+use tracing::{debug, info};
+use yuha_core::transport::{KnockConfig, KnockProtocol, SocketOptions};
+
+/// Knock on `host` as `config` says, returning when the connection may follow
+pub async fn knock(host: &str, config: &KnockConfig, options: &SocketOptions) -> Result<()> {
+    let delay = Duration::from_millis(config.delay_ms);
+    if let Some(command) = &config.command {
+        let started = Instant::now();
+        run_command(command).await?;
+        sleep_until(started + delay).await;
+    }
+    if config.sequence.is_empty() {
+        return Ok(());
+    }
+
+    let ip = lookup_host((host, 0))
+        .await
+        .with_context(|| format!("Failed to resolve address: {}", host))?
+        .next()
+        .with_context(|| format!("No addresses resolved for: {}", host))?
+        .ip();
+    info!("Knocking on {} ports of {}", config.sequence.len(), ip);
+    for step in &config.sequence {
+        let started = Instant::now();
+        let addr = SocketAddr::new(ip, step.port);
+        debug!("Knocking on {} over {:?}", addr, step.protocol);
+        match step.protocol {
+            // The firewall drops the attempt, so it is given up on at the
+            // next step rather than awaited
+            KnockProtocol::Tcp => {
+                let _ = timeout(delay, socket::connect_addr(addr, options)).await;
+            }
+            KnockProtocol::Udp => send_datagram(addr, options)
+                .await
+                .with_context(|| format!("Failed to knock on {}", addr))?,
+        }
+        sleep_until(started + delay).await;
+    }
+    Ok(())
+}
+
+/// Send an empty datagram to `addr`
+async fn send_datagram(addr: SocketAddr, options: &SocketOptions) -> std::io::Result<()> {
+    let local = match options.bind_address {
+        Some(ip) => SocketAddr::new(ip, 0),
+        None if addr.is_ipv4() => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        None => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.send_to(&[], addr).await?;
+    Ok(())
+}
+
+/// Run the single packet authorization `command`, failing unless it succeeds
+async fn run_command(command: &[String]) -> Result<()> {
+    let (program, args) = command.split_first().context("Empty knock command")?;
+    info!("Running knock command {}", program);
+    let status = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .status()
+        .await
+        .with_context(|| format!("Failed to run knock command {}", program))?;
+    if !status.success() {
+        bail!("Knock command {} failed with {}", program, status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use yuha_core::transport::KnockStep;
+
+    fn config(sequence: Vec<KnockStep>, command: Option<&[&str]>) -> KnockConfig {
+        KnockConfig {
+            sequence,
+            command: command.map(|command| command.iter().map(|arg| arg.to_string()).collect()),
+            delay_ms: 10,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_knock_sequence() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sequence = vec![
+            KnockStep {
+                port: listener.local_addr().unwrap().port(),
+                protocol: KnockProtocol::Tcp,
+            },
+            KnockStep {
+                port: udp.local_addr().unwrap().port(),
+                protocol: KnockProtocol::Udp,
+            },
+        ];
+
+        knock(
+            "127.0.0.1",
+            &config(sequence, None),
+            &SocketOptions::default(),
+        )
+        .await
+        .unwrap();
+        timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        let mut buf = [0u8; 16];
+        let (len, _) = timeout(Duration::from_secs(5), udp.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(len, 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_knock_command() {
+        let options = SocketOptions::default();
+        knock("localhost", &config(Vec::new(), Some(&["true"])), &options)
+            .await
+            .unwrap();
+        let err = knock("localhost", &config(Vec::new(), Some(&["false"])), &options)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("failed"));
+        assert!(
+            knock("localhost", &config(Vec::new(), Some(&[])), &options)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -2,14 +2,27 @@
 //!
 //! This module provides a transport that runs the yuha-remote process locally
 //! and communicates via stdin/stdout.
+//!
+//! The process starts with the allow-listed environment of
+//! [`TransportConfig::child_env`](super::TransportConfig::child_env) plus the
+//! configured `env_vars`, never the full environment of the client.
+//!
+//! By default every process uses the shared temp directory and the default
+//! IPC socket, so a second one takes the socket over from the first. An
+//! [`isolated`](super::LocalTransportConfig::isolated) transport instead
+//! gives each process a new temp directory, set as its `TMPDIR` and holding
+//! its IPC socket, and removes it along with the process.
 
 use super::shared::{ProcessStream, configure_command, spawn_stderr_logger};
 use super::{LocalTransportConfig, Transport, TransportConfig};
+use crate::constants::IPC_SOCKET_ENV;
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+use std::path::Path;
 use std::process::Stdio;
 use tokio::process::Command;
 use tracing::info;
+use yuha_core::message_channel::{KeepaliveConfig, ReadBufferConfig};
 
 /// Local transport that runs yuha-remote as a subprocess
 #[derive(Debug)]
@@ -45,6 +58,9 @@ impl Transport for LocalTransport {
             cmd.arg(arg);
         }
 
+        // Start from the allow-listed environment, not the client's
+        cmd.env_clear().envs(self.transport_config.child_env.vars());
+
         // Configure common command options
         configure_command(
             &mut cmd,
@@ -52,6 +68,17 @@ impl Transport for LocalTransport {
             &self.transport_config.working_dir,
         );
 
+        let temp_dir = if self.config.isolated {
+            let dir = tempfile::Builder::new()
+                .prefix("yuha-local-")
+                .tempdir()
+                .context("Failed to create a temp directory for yuha-remote")?;
+            isolate(&mut cmd, dir.path());
+            Some(dir)
+        } else {
+            None
+        };
+
         // Configure stdio
         cmd.stdin(Stdio::piped())
             .stdout(Stdio::piped())
@@ -84,12 +111,36 @@ impl Transport for LocalTransport {
 
         info!("Local yuha-remote process started successfully");
 
-        Ok(ProcessStream::new(child, stdin, stdout))
+        let stream = ProcessStream::new(child, stdin, stdout);
+        Ok(match temp_dir {
+            Some(dir) => stream.with_temp_dir(dir),
+            None => stream,
+        })
     }
 
     fn name(&self) -> &'static str {
         "local"
     }
+
+    fn remote_binary(&self) -> Option<&Path> {
+        Some(&self.config.binary_path)
+    }
+
+    fn read_buffer(&self) -> ReadBufferConfig {
+        self.transport_config.read_buffer.clone()
+    }
+
+    fn keepalive(&self) -> KeepaliveConfig {
+        self.transport_config.keepalive.clone()
+    }
+}
+
+/// Point the temp directory and IPC socket of `cmd` into `dir`
+fn isolate(cmd: &mut Command, dir: &Path) {
+    for var in ["TMPDIR", "TMP", "TEMP"] {
+        cmd.env(var, dir);
+    }
+    cmd.env(IPC_SOCKET_ENV, dir.join("ipc.sock"));
 }
 
 #[cfg(test)]
@@ -102,6 +153,7 @@ mod tests {
         let config = LocalTransportConfig {
             binary_path: PathBuf::from("yuha-remote"),
             args: vec!["--stdio".to_string()],
+            isolated: false,
         };
         let transport_config = TransportConfig::default();
         let transport = LocalTransport::new(config, transport_config);
@@ -6,13 +6,21 @@
 //!
 //! ## Available Transports
 //!
-//! - **SSH Transport** (`ssh`): Connect via SSH with automatic binary management
-//! - **Local Transport** (`local`): Spawn local yuha-remote process
-//! - **TCP Transport** (`tcp`): Direct TCP connection to daemon
-//! - **WSL Transport** (`wsl`): Windows Subsystem for Linux integration
+//! - **SSH Transport** (`ssh`): Connect via SSH with automatic binary management,
+//!   redeploying changed binaries as deltas (`deploy`) and signing with
+//!   hardware-backed keys through the ssh-agent (`hardware`)
+//! - **Local Transport** (`local`): Spawn local yuha-remote process, optionally
+//!   isolated from other local processes
+//! - **TCP Transport** (`tcp`): Direct TCP connection to daemon, optionally
+//!   knocking first (`knock`)
+//! - **WSL Transport** (`wsl`): Windows Subsystem for Linux integration, with
+//!   port proxy rules (`portproxy`) exposing forwarded ports to Windows
 //! - **Unix Transport** (`unix`): Unix domain sockets (Unix only)
 //! - **Windows Transport** (`windows`): Named pipes (Windows only)
 //!
+//! The SSH and TCP transports open their TCP connection through `socket`,
+//! applying the configured socket options.
+//!
 //! ## Transport Selection
 //!
 //! Each transport is optimized for specific use cases:
@@ -40,11 +48,21 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
+use yuha_core::child_env::ChildEnv;
+use yuha_core::message_channel::{KeepaliveConfig, ReadBufferConfig};
+use yuha_core::protocol::noise::NoiseConfig;
+use yuha_core::transport::{SocketOptions, SshAlgorithms};
 
+pub mod deploy;
+pub mod hardware;
+pub mod knock;
 pub mod local;
+pub mod portproxy;
 pub mod shared;
+pub mod socket;
 pub mod ssh;
 pub mod tcp;
 pub mod wsl;
@@ -75,8 +93,20 @@ pub struct TransportConfig {
     pub env_vars: HashMap<String, String>,
     /// Working directory for the remote process
     pub working_dir: Option<PathBuf>,
+    /// Read buffer sizing for the message channel
+    pub read_buffer: ReadBufferConfig,
+    /// Dead-peer detection for the message channel
+    pub keepalive: KeepaliveConfig,
+    /// Environment a locally spawned remote process starts with, before
+    /// `env_vars`
+    pub child_env: ChildEnv,
 }
 
+/// Bidirectional stream usable behind a trait object
+pub trait TransportStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> TransportStream for S {}
+
 /// Trait for transport implementations
 #[async_trait]
 pub trait Transport: Send + Sync {
@@ -88,6 +118,42 @@ pub trait Transport: Send + Sync {
 
     /// Get the name of this transport type
     fn name(&self) -> &'static str;
+
+    /// Shared token used to authenticate the session, if the transport requires one
+    fn auth_token(&self) -> Option<&str> {
+        None
+    }
+
+    /// Keys the session is encrypted with, if the transport requires that
+    fn noise(&self) -> Option<&NoiseConfig> {
+        None
+    }
+
+    /// Local copy of the binary the remote runs, when the transport starts
+    /// or uploads a known one; the remote must report its hash
+    fn remote_binary(&self) -> Option<&Path> {
+        None
+    }
+
+    /// Read buffer sizing for the message channel over this transport
+    fn read_buffer(&self) -> ReadBufferConfig {
+        ReadBufferConfig::default()
+    }
+
+    /// Dead-peer detection for the message channel over this transport
+    fn keepalive(&self) -> KeepaliveConfig {
+        KeepaliveConfig::default()
+    }
+
+    /// Called once the remote listens on `local_port` for a port forward
+    async fn forward_started(&self, _local_port: u16) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once the port forward on `local_port` has stopped
+    async fn forward_stopped(&self, _local_port: u16) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// SSH transport configuration
@@ -98,6 +164,16 @@ pub struct SshTransportConfig {
     pub username: String,
     pub password: Option<String>,
     pub key_path: Option<PathBuf>,
+    /// OpenSSH certificate of the key, `<key_path>-cert.pub` when unset
+    pub cert_path: Option<PathBuf>,
+    /// Principal the certificate must be valid for, `username` when unset
+    pub cert_principal: Option<String>,
+    /// PKCS#11 provider library loaded into the ssh-agent, `key_path` then
+    /// the public key of the one token key to use
+    pub pkcs11: Option<String>,
+    pub connection_timeout: Duration,
+    pub socket: SocketOptions,
+    pub algorithms: SshAlgorithms,
 }
 
 /// Local transport configuration (for running the remote process locally)
@@ -107,6 +183,9 @@ pub struct LocalTransportConfig {
     pub binary_path: PathBuf,
     /// Additional arguments to pass to the binary
     pub args: Vec<String>,
+    /// Give each process a temp directory of its own, holding its IPC socket,
+    /// so several can run side by side
+    pub isolated: bool,
 }
 
 impl Default for LocalTransportConfig {
@@ -114,6 +193,7 @@ impl Default for LocalTransportConfig {
         Self {
             binary_path: PathBuf::from("yuha-remote"),
             args: vec!["--stdio".to_string()],
+            isolated: false,
         }
     }
 }
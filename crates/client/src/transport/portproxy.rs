@@ -0,0 +1,290 @@
+//! Windows port proxy rules for WSL port forwards
+//!
+//! A port forward started on a WSL remote listens inside the WSL VM. Under
+//! NAT networking, Windows applications cannot always reach it through
+//! `localhost`, so [`PortProxy`] adds a `netsh interface portproxy` rule
+//! relaying the Windows loopback port to the VM address. Under mirrored
+//! networking the VM shares the Windows network stack and no rule is needed.
+//!
+//! Rules are removed when the forward stops, and any left over are removed
+//! when the proxy is dropped with the transport at session close. Adding
+//! rules requires an elevated prompt.
+
+use anyhow::{Context, Result};
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+/// Address Windows applications connect to
+const LISTEN_ADDRESS: Ipv4Addr = Ipv4Addr::LOCALHOST;
+
+/// Networking mode of WSL 2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkingMode {
+    /// The VM sits behind a NAT with its own address
+    Nat,
+    /// The VM shares the Windows network interfaces
+    Mirrored,
+}
+
+impl NetworkingMode {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "nat" => Some(Self::Nat),
+            "mirrored" => Some(Self::Mirrored),
+            _ => None,
+        }
+    }
+
+    /// Mode set by `networkingMode` in the `[wsl2]` section of a `.wslconfig`
+    pub fn from_wslconfig(text: &str) -> Option<Self> {
+        let mut in_wsl2 = false;
+        let mut mode = None;
+        for line in text.lines().map(str::trim) {
+            if line.starts_with('[') {
+                in_wsl2 = line.eq_ignore_ascii_case("[wsl2]");
+            } else if in_wsl2
+                && let Some((key, value)) = line.split_once('=')
+                && key.trim().eq_ignore_ascii_case("networkingMode")
+            {
+                mode = Self::parse(value);
+            }
+        }
+        mode
+    }
+}
+
+/// A `v4tov4` port proxy rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortProxyRule {
+    pub listen_port: u16,
+    pub connect_address: Ipv4Addr,
+    pub connect_port: u16,
+}
+
+impl PortProxyRule {
+    /// `netsh` arguments adding this rule
+    pub fn add_args(&self) -> Vec<String> {
+        let mut args = self.args("add");
+        args.push(format!("connectaddress={}", self.connect_address));
+        args.push(format!("connectport={}", self.connect_port));
+        args
+    }
+
+    /// `netsh` arguments deleting this rule
+    pub fn delete_args(&self) -> Vec<String> {
+        self.args("delete")
+    }
+
+    fn args(&self, action: &str) -> Vec<String> {
+        vec![
+            "interface".to_string(),
+            "portproxy".to_string(),
+            action.to_string(),
+            "v4tov4".to_string(),
+            format!("listenaddress={}", LISTEN_ADDRESS),
+            format!("listenport={}", self.listen_port),
+        ]
+    }
+}
+
+/// First IPv4 address printed by `hostname -I`
+pub fn parse_vm_address(output: &str) -> Option<Ipv4Addr> {
+    output
+        .split_whitespace()
+        .find_map(|address| address.parse().ok())
+}
+
+/// Port proxy rules added for one WSL distribution
+#[derive(Debug)]
+pub struct PortProxy {
+    distribution: Option<String>,
+    rules: Mutex<Vec<PortProxyRule>>,
+}
+
+impl PortProxy {
+    pub fn new(distribution: Option<String>) -> Self {
+        Self {
+            distribution,
+            rules: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Make `port` of the VM reachable on the Windows loopback address
+    pub async fn expose(&self, port: u16) -> Result<()> {
+        if self.networking_mode().await == NetworkingMode::Mirrored {
+            debug!("Mirrored networking, port {} needs no proxy rule", port);
+            return Ok(());
+        }
+
+        let rule = PortProxyRule {
+            listen_port: port,
+            connect_address: self.vm_address().await?,
+            connect_port: port,
+        };
+        netsh(&rule.add_args()).await?;
+        info!(
+            "Added port proxy {}:{} -> {}:{}",
+            LISTEN_ADDRESS, port, rule.connect_address, port
+        );
+        // `add` replaces the rule of a port exposed before
+        let mut rules = self.rules.lock().unwrap();
+        rules.retain(|added| added.listen_port != port);
+        rules.push(rule);
+        Ok(())
+    }
+
+    /// Remove the rule added for `port`, if any
+    pub async fn unexpose(&self, port: u16) -> Result<()> {
+        let rule = {
+            let mut rules = self.rules.lock().unwrap();
+            let Some(index) = rules.iter().position(|rule| rule.listen_port == port) else {
+                return Ok(());
+            };
+            rules.remove(index)
+        };
+        netsh(&rule.delete_args()).await?;
+        info!("Removed port proxy for port {}", port);
+        Ok(())
+    }
+
+    /// Networking mode reported by `wslinfo`, falling back to `.wslconfig`
+    pub async fn networking_mode(&self) -> NetworkingMode {
+        let reported = self
+            .wsl_output(&["wslinfo", "--networking-mode"])
+            .await
+            .ok()
+            .and_then(|output| NetworkingMode::parse(&output));
+        if let Some(mode) = reported {
+            return mode;
+        }
+
+        std::env::var_os("USERPROFILE")
+            .map(|profile| PathBuf::from(profile).join(".wslconfig"))
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|text| NetworkingMode::from_wslconfig(&text))
+            .unwrap_or(NetworkingMode::Nat)
+    }
+
+    async fn vm_address(&self) -> Result<Ipv4Addr> {
+        let output = self.wsl_output(&["hostname", "-I"]).await?;
+        parse_vm_address(&output)
+            .ok_or_else(|| anyhow::anyhow!("No IPv4 address reported by WSL: {:?}", output))
+    }
+
+    /// Run `command` in the distribution and return its stdout
+    async fn wsl_output(&self, command: &[&str]) -> Result<String> {
+        let mut cmd = Command::new("wsl");
+        if let Some(ref distribution) = self.distribution {
+            cmd.args(["--distribution", distribution]);
+        }
+        let output = cmd
+            .arg("--exec")
+            .args(command)
+            .output()
+            .await
+            .with_context(|| format!("Failed to run {:?} in WSL", command))?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "{:?} failed in WSL: {}",
+                command,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+impl Drop for PortProxy {
+    fn drop(&mut self) {
+        let rules = std::mem::take(self.rules.get_mut().unwrap());
+        for rule in rules {
+            // Async commands cannot run here; block briefly per rule instead
+            match std::process::Command::new("netsh")
+                .args(rule.delete_args())
+                .output()
+            {
+                Ok(output) if output.status.success() => {
+                    debug!("Removed port proxy for port {}", rule.listen_port)
+                }
+                Ok(output) => warn!(
+                    "Failed to remove port proxy for port {}: {}",
+                    rule.listen_port,
+                    String::from_utf8_lossy(&output.stdout)
+                ),
+                Err(e) => warn!(
+                    "Failed to remove port proxy for port {}: {}",
+                    rule.listen_port, e
+                ),
+            }
+        }
+    }
+}
+
+async fn netsh(args: &[String]) -> Result<()> {
+    let output = Command::new("netsh")
+        .args(args)
+        .output()
+        .await
+        .context("Failed to execute netsh")?;
+    // netsh reports errors such as missing elevation on stdout
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "netsh {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stdout).trim()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_networking_mode_from_wslconfig() {
+        let text = "[wsl2]\nmemory=4GB\nnetworkingMode = Mirrored\n";
+        assert_eq!(
+            NetworkingMode::from_wslconfig(text),
+            Some(NetworkingMode::Mirrored)
+        );
+
+        // Only the [wsl2] section counts
+        let text = "[experimental]\nnetworkingMode=mirrored\n[wsl2]\nswap=0\n";
+        assert_eq!(NetworkingMode::from_wslconfig(text), None);
+        assert_eq!(
+            NetworkingMode::from_wslconfig("[WSL2]\nnetworkingmode=nat"),
+            Some(NetworkingMode::Nat)
+        );
+    }
+
+    #[test]
+    fn test_parse_vm_address() {
+        assert_eq!(
+            parse_vm_address("fe80::1 172.23.64.5 10.255.255.254 \n"),
+            Some(Ipv4Addr::new(172, 23, 64, 5))
+        );
+        assert_eq!(parse_vm_address("\n"), None);
+    }
+
+    #[test]
+    fn test_rule_args() {
+        let rule = PortProxyRule {
+            listen_port: 8080,
+            connect_address: Ipv4Addr::new(172, 23, 64, 5),
+            connect_port: 8080,
+        };
+        assert_eq!(
+            rule.add_args().join(" "),
+            "interface portproxy add v4tov4 listenaddress=127.0.0.1 listenport=8080 \
+             connectaddress=172.23.64.5 connectport=8080"
+        );
+        assert_eq!(
+            rule.delete_args().join(" "),
+            "interface portproxy delete v4tov4 listenaddress=127.0.0.1 listenport=8080"
+        );
+    }
+}
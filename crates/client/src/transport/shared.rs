@@ -2,6 +2,7 @@
 
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use tempfile::TempDir;
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::process::{Child, ChildStdin, ChildStdout, Command};
 use tracing::warn;
@@ -11,6 +12,8 @@ pub struct ProcessStream {
     child: Option<Child>,
     stdin: ChildStdin,
     stdout: ChildStdout,
+    /// Private temp directory of the process, removed with the stream
+    temp_dir: Option<TempDir>,
 }
 
 impl ProcessStream {
@@ -20,8 +23,15 @@ impl ProcessStream {
             child: Some(child),
             stdin,
             stdout,
+            temp_dir: None,
         }
     }
+
+    /// Remove `dir` once the stream is dropped
+    pub fn with_temp_dir(mut self, dir: TempDir) -> Self {
+        self.temp_dir = Some(dir);
+        self
+    }
 }
 
 impl Drop for ProcessStream {
@@ -0,0 +1,155 @@
+//! TCP connections tuned with [`SocketOptions`]
+//!
+//! Shared by the transports that open a TCP connection themselves: the TCP
+//! transport to the remote server and the SSH transport to the SSH server.
+
+use anyhow::{Context, Result};
+use socket2::{SockRef, TcpKeepalive};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpSocket, TcpStream, lookup_host};
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+use yuha_core::transport::SocketOptions;
+
+/// Connect to the first reachable address of `host`, giving each address
+/// `connection_timeout`
+pub async fn connect(
+    host: &str,
+    port: u16,
+    options: &SocketOptions,
+    connection_timeout: Duration,
+) -> Result<TcpStream> {
+    let addr = format!("{}:{}", host, port);
+
+    // Resolve the address
+    let socket_addrs: Vec<SocketAddr> = lookup_host(&addr)
+        .await
+        .with_context(|| format!("Failed to resolve address: {}", addr))?
+        .collect();
+
+    if socket_addrs.is_empty() {
+        anyhow::bail!("No addresses resolved for: {}", addr);
+    }
+
+    debug!("Resolved addresses: {:?}", socket_addrs);
+
+    // Try connecting to each resolved address
+    let mut last_error = None;
+    for socket_addr in socket_addrs {
+        debug!("Attempting to connect to {}", socket_addr);
+
+        match timeout(connection_timeout, connect_addr(socket_addr, options)).await {
+            Ok(Ok(stream)) => {
+                info!("Successfully connected to {}", socket_addr);
+                return Ok(stream);
+            }
+            Ok(Err(e)) => {
+                warn!("Failed to connect to {}: {}", socket_addr, e);
+                last_error = Some(e.into());
+            }
+            Err(_) => {
+                let timeout_error = anyhow::anyhow!(
+                    "Connection timeout after {:?} to {}",
+                    connection_timeout,
+                    socket_addr
+                );
+                warn!("{}", timeout_error);
+                last_error = Some(timeout_error);
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        anyhow::anyhow!("Failed to connect to any resolved address for {}", addr)
+    }))
+}
+
+/// Connect to `addr` from a socket set up with `options`
+pub(super) async fn connect_addr(
+    addr: SocketAddr,
+    options: &SocketOptions,
+) -> std::io::Result<TcpStream> {
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    // Buffer sizes must be set before connecting to affect the window scale
+    if let Some(size) = options.send_buffer {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = options.recv_buffer {
+        socket.set_recv_buffer_size(size)?;
+    }
+    if let Some(ip) = options.bind_address {
+        socket.bind(SocketAddr::new(ip, 0))?;
+    }
+
+    let stream = socket.connect(addr).await?;
+    stream.set_nodelay(options.nodelay)?;
+    if options.keepalive > 0 {
+        let keepalive = TcpKeepalive::new().with_time(Duration::from_secs(options.keepalive));
+        SockRef::from(&stream).set_tcp_keepalive(&keepalive)?;
+    }
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_connect_applies_options() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let options = SocketOptions {
+            keepalive: 30,
+            nodelay: false,
+            send_buffer: Some(256 * 1024),
+            recv_buffer: Some(256 * 1024),
+            bind_address: Some("127.0.0.1".parse().unwrap()),
+        };
+
+        let stream = connect("127.0.0.1", port, &options, Duration::from_secs(5))
+            .await
+            .unwrap();
+        let socket = SockRef::from(&stream);
+        assert!(!stream.nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        // The system may round sizes up, e.g. Linux doubles them
+        assert!(socket.send_buffer_size().unwrap() >= 256 * 1024);
+        assert!(socket.recv_buffer_size().unwrap() >= 256 * 1024);
+        assert_eq!(
+            stream.local_addr().unwrap().ip(),
+            options.bind_address.unwrap()
+        );
+
+        let defaults = connect(
+            "localhost",
+            port,
+            &SocketOptions::default(),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        assert!(defaults.nodelay().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_connect_from_unavailable_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let options = SocketOptions {
+            // TEST-NET-1, never assigned to a local interface
+            bind_address: Some("192.0.2.1".parse().unwrap()),
+            ..SocketOptions::default()
+        };
+        assert!(
+            connect("127.0.0.1", port, &options, Duration::from_secs(5))
+                .await
+                .is_err()
+        );
+    }
+}
@@ -3,18 +3,33 @@
 //! This module provides a transport that connects to a remote server via SSH
 //! and runs the yuha-remote process.
 
-use super::{SshTransportConfig, Transport, TransportConfig};
+use super::deploy::{self, DEPLOY_PATH, Upload};
+use super::{SshTransportConfig, Transport, TransportConfig, hardware, socket};
+use crate::credentials::{Credential, CredentialsProvider, EnvCredentials};
 use crate::{ClientError, REMOTE_BINARY_PATH};
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use russh::ChannelId;
-use russh::client::{AuthResult, Config, Handle, Handler, Session, connect};
+use russh::ChannelMsg;
+use russh::MethodKind;
+use russh::client::{
+    AuthResult, Config, Handle, Handler, KeyboardInteractiveAuthResponse, Session, connect_stream,
+};
+use russh::keys::ssh_key::certificate::CertType;
+use russh::keys::{Certificate, HashAlg, PrivateKey};
+use russh::{Preferred, compression, kex};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context as TaskContext, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::sync::{Mutex, mpsc};
 use tracing::{error, info};
+use yuha_core::checksum;
+use yuha_core::message_channel::{KeepaliveConfig, ReadBufferConfig};
+use yuha_core::transport::SshAlgorithms;
 
 /// Handler for SSH client events
 pub struct MyHandler {
@@ -159,149 +174,465 @@ impl AsyncWrite for SshChannelAdapter {
 pub struct SshTransport {
     config: SshTransportConfig,
     transport_config: TransportConfig,
+    credentials: Arc<dyn CredentialsProvider>,
 }
 
 impl SshTransport {
-    /// Create a new SSH transport
+    /// Create a new SSH transport, taking missing credentials from the
+    /// environment
     pub fn new(config: SshTransportConfig, transport_config: TransportConfig) -> Self {
         Self {
             config,
             transport_config,
+            credentials: Arc::new(EnvCredentials),
         }
     }
 
-    /// Transfer binary to remote host and return the path
+    /// Ask `credentials` for the secrets the configuration lacks
+    pub fn with_credentials(mut self, credentials: Arc<dyn CredentialsProvider>) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Certificate to authenticate with instead of the bare key, if any
+    fn certificate_path(&self) -> Option<PathBuf> {
+        if let Some(path) = &self.config.cert_path {
+            return Some(path.clone());
+        }
+        let mut path = self.config.key_path.clone()?.into_os_string();
+        path.push("-cert.pub");
+        let path = PathBuf::from(path);
+        path.exists().then_some(path)
+    }
+
+    /// Authenticate with the configured method, then with a password or
+    /// one-time codes if the server asks for more
+    async fn authenticate(&self, handle: &mut Handle<MyHandler>) -> Result<()> {
+        let user = &self.config.username;
+        let result = if let Some(password) = &self.config.password {
+            handle
+                .authenticate_password(user, password)
+                .await
+                .context("Failed to authenticate with password")?
+        } else if let Some(provider) = &self.config.pkcs11 {
+            self.authenticate_pkcs11(handle, provider).await?
+        } else if let Some(key_path) = &self.config.key_path {
+            let key = self.load_key(key_path).await?;
+            self.authenticate_key(handle, key).await?
+        } else {
+            anyhow::bail!("No authentication method provided");
+        };
+
+        let AuthResult::Failure {
+            remaining_methods, ..
+        } = result
+        else {
+            return Ok(());
+        };
+        if remaining_methods.contains(&MethodKind::KeyboardInteractive)
+            && self.keyboard_interactive(handle).await?
+        {
+            return Ok(());
+        }
+        if remaining_methods.contains(&MethodKind::Password)
+            && self.config.password.is_none()
+            && let Some(password) = self
+                .credentials
+                .ask(
+                    Credential::Password,
+                    &format!("{}@{}'s password: ", user, self.config.host),
+                )
+                .await?
+            && handle
+                .authenticate_password(user, password)
+                .await
+                .context("Failed to authenticate with password")?
+                .success()
+        {
+            return Ok(());
+        }
+        Err(ClientError::Authentication(format!(
+            "{}@{} rejected every method",
+            user, self.config.host
+        ))
+        .into())
+    }
+
+    /// Read the key at `path`, asking for its passphrase if encrypted
+    async fn load_key(&self, path: &Path) -> Result<PrivateKey> {
+        let key_str = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read key file: {:?}", path))?;
+        let key = PrivateKey::from_openssh(&key_str).context("Failed to parse SSH key")?;
+        if !key.is_encrypted() {
+            return Ok(key);
+        }
+        let Some(passphrase) = self
+            .credentials
+            .ask(
+                Credential::Passphrase,
+                &format!("Enter passphrase for key {:?}: ", path),
+            )
+            .await?
+        else {
+            anyhow::bail!("Key {:?} is encrypted and no passphrase was given", path);
+        };
+        key.decrypt(passphrase)
+            .with_context(|| format!("Failed to decrypt key {:?}", path))
+    }
+
+    async fn authenticate_key(
+        &self,
+        handle: &mut Handle<MyHandler>,
+        key: PrivateKey,
+    ) -> Result<AuthResult> {
+        let user = &self.config.username;
+        if hardware::is_security_key(&key.algorithm()) {
+            if self.config.cert_path.is_some() {
+                anyhow::bail!("Certificates of security keys are not supported");
+            }
+            let mut agent = hardware::agent().await?;
+            hardware::lend(&mut agent, &key).await?;
+            self.credentials.notify(&format!(
+                "Confirm user presence for key {}",
+                key.fingerprint(HashAlg::Sha256)
+            ));
+            return hardware::sendable(handle.authenticate_publickey_with(
+                user.clone(),
+                key.public_key().clone(),
+                None,
+                &mut agent,
+            ))
+            .await
+            .context("Failed to authenticate with security key");
+        }
+        if let Some(cert_path) = self.certificate_path() {
+            let cert = russh::keys::load_openssh_certificate(&cert_path)
+                .with_context(|| format!("Failed to read certificate: {:?}", cert_path))?;
+            let principal = self.config.cert_principal.as_deref().unwrap_or(user);
+            check_certificate(&cert, &key, principal, SystemTime::now())?;
+            info!(
+                "Authenticating with certificate {} as {}",
+                cert.key_id(),
+                user
+            );
+            return handle
+                .authenticate_openssh_cert(user, Arc::new(key), cert)
+                .await
+                .context("Failed to authenticate with certificate");
+        }
+        let key_with_hash = russh::keys::PrivateKeyWithHashAlg::new(Arc::new(key), None);
+        handle
+            .authenticate_publickey(user, key_with_hash)
+            .await
+            .context("Failed to authenticate with key")
+    }
+
+    /// Try the keys of the PKCS#11 `provider` until the server accepts one
+    async fn authenticate_pkcs11(
+        &self,
+        handle: &mut Handle<MyHandler>,
+        provider: &str,
+    ) -> Result<AuthResult> {
+        let pin = self
+            .credentials
+            .ask(Credential::Pin, &format!("Enter PIN for {}: ", provider))
+            .await?
+            .unwrap_or_default();
+        let mut agent = hardware::agent().await?;
+        let configured = self
+            .config
+            .key_path
+            .as_ref()
+            .map(|path| {
+                russh::keys::load_public_key(path)
+                    .with_context(|| format!("Failed to load public key {}", path.display()))
+            })
+            .transpose()?;
+        let keys = hardware::load_provider(&mut agent, provider, &pin, configured.as_ref()).await?;
+        self.credentials.notify(&format!(
+            "Authenticating with the keys of {}, touch the token if it asks for presence",
+            provider
+        ));
+        let mut result = None;
+        for key in keys {
+            let auth_result = hardware::sendable(handle.authenticate_publickey_with(
+                self.config.username.clone(),
+                key,
+                None,
+                &mut agent,
+            ))
+            .await
+            .context("Failed to authenticate with PKCS#11 key")?;
+            if auth_result.success() {
+                return Ok(auth_result);
+            }
+            result = Some(auth_result);
+        }
+        result.ok_or_else(|| anyhow::anyhow!("No PKCS#11 keys of {}", provider))
+    }
+
+    /// Answer keyboard-interactive prompts, typically one-time codes; false
+    /// when the server refuses the answers or some are missing
+    async fn keyboard_interactive(&self, handle: &mut Handle<MyHandler>) -> Result<bool> {
+        let mut response = handle
+            .authenticate_keyboard_interactive_start(&self.config.username, None)
+            .await
+            .context("Failed to start keyboard-interactive authentication")?;
+        loop {
+            let (instructions, prompts) = match response {
+                KeyboardInteractiveAuthResponse::Success => return Ok(true),
+                KeyboardInteractiveAuthResponse::Failure { .. } => return Ok(false),
+                KeyboardInteractiveAuthResponse::InfoRequest {
+                    instructions,
+                    prompts,
+                    ..
+                } => (instructions, prompts),
+            };
+            if !instructions.is_empty() {
+                self.credentials.notify(&instructions);
+            }
+            let mut answers = Vec::with_capacity(prompts.len());
+            for prompt in prompts {
+                let credential = prompt_credential(&prompt.prompt);
+                match self.credentials.ask(credential, &prompt.prompt).await? {
+                    Some(answer) => answers.push(answer),
+                    None => return Ok(false),
+                }
+            }
+            response = handle
+                .authenticate_keyboard_interactive_respond(answers)
+                .await
+                .context("Failed to answer keyboard-interactive prompts")?;
+        }
+    }
+
+    /// Deploy the binary at `binary_path` to the remote host, uploading only
+    /// what changed since the last deployment, and return its remote path
     async fn transfer_binary_to_remote(
+        &self,
         handle: &Handle<MyHandler>,
         binary_path: &str,
     ) -> Result<String, ClientError> {
-        info!("Starting binary transfer to remote host");
-
-        // Read the local binary
-        let binary_data = tokio::fs::read(binary_path).await.map_err(|e| {
+        let binary = tokio::fs::read(binary_path).await.map_err(|e| {
             ClientError::BinaryTransfer(format!(
                 "Failed to read local binary at {}: {}",
                 binary_path, e
             ))
         })?;
+        let copy = deploy::deployed_copy(&format!(
+            "{}@{}:{}",
+            self.config.username, self.config.host, self.config.port
+        ));
+        let deployed = tokio::fs::read(&copy).await.ok();
+
+        // Unique names, so concurrent deployments to a host do not clobber
+        // each other's files
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let staging = format!("{}.{}-{}", DEPLOY_PATH, std::process::id(), nanos);
+        let new_path = format!("{}.new", staging);
+        // The deployed binary checks the result, so a deployment that was
+        // changed or lost on the host is replaced with the whole binary
+        let digest = checksum::digest(&binary);
+        let check = |path: &str| format!("{} check-digest {} {}", DEPLOY_PATH, path, digest);
+
+        let current = match deploy::plan(deployed.as_deref(), &binary) {
+            Upload::Unchanged => run(handle, &check(DEPLOY_PATH)).await? == 0,
+            Upload::Delta(patch) => {
+                info!(
+                    "Uploading a {} byte delta for the {} byte binary",
+                    patch.len(),
+                    binary.len()
+                );
+                let delta_path = format!("{}.delta", staging);
+                upload(handle, &patch, &delta_path).await?;
+                let apply = format!(
+                    "{deployed} apply-delta {deployed} {delta} {new} && {check} \
+                     && mv {new} {deployed}; status=$?; rm -f {delta} {new}; exit $status",
+                    deployed = DEPLOY_PATH,
+                    delta = delta_path,
+                    new = new_path,
+                    check = check(&new_path)
+                );
+                run(handle, &apply).await? == 0
+            }
+            Upload::Full => false,
+        };
 
-        info!(
-            "Read {} bytes from local binary: {}",
-            binary_data.len(),
-            binary_path
-        );
-
-        // Generate a unique temporary path on the remote
-        let remote_temp_path = format!("/tmp/yuha-remote-{}", std::process::id());
-
-        // Use a simpler approach: write the binary directly using cat
-        info!(
-            "Transferring {} bytes to {}",
-            binary_data.len(),
-            remote_temp_path
-        );
+        if !current {
+            info!("Uploading the {} byte binary", binary.len());
+            upload(handle, &binary, &new_path).await?;
+            let install = format!(
+                "chmod +x {new} && mv {new} {deployed}; status=$?; rm -f {new}; exit $status",
+                deployed = DEPLOY_PATH,
+                new = new_path
+            );
+            if run(handle, &install).await? != 0 {
+                return Err(ClientError::BinaryTransfer(format!(
+                    "Failed to install the binary at {}",
+                    DEPLOY_PATH
+                )));
+            }
+        }
 
-        // Create a channel for the transfer
-        let channel = handle.channel_open_session().await.map_err(|e| {
-            ClientError::BinaryTransfer(format!("Failed to open transfer channel: {}", e))
-        })?;
+        if let Some(parent) = copy.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&copy, &binary).await?;
+        info!("Binary deployed to {}", DEPLOY_PATH);
+        Ok(DEPLOY_PATH.to_string())
+    }
+}
 
-        // Encode binary as base64
-        use base64::Engine;
-        let encoded_data = base64::engine::general_purpose::STANDARD.encode(&binary_data);
+/// Run a silent shell command on the remote host and return its exit status
+async fn run(handle: &Handle<MyHandler>, command: &str) -> Result<u32, ClientError> {
+    let transfer_error =
+        |e: russh::Error| ClientError::BinaryTransfer(format!("Failed to run {}: {}", command, e));
+    let mut channel = handle
+        .channel_open_session()
+        .await
+        .map_err(transfer_error)?;
+    // Output would end up in the data of the server channel
+    let silent = format!("({}) >/dev/null 2>&1", command);
+    channel
+        .exec(true, silent.as_bytes())
+        .await
+        .map_err(transfer_error)?;
+    while let Some(message) = channel.wait().await {
+        if let ChannelMsg::ExitStatus { exit_status } = message {
+            return Ok(exit_status);
+        }
+    }
+    Err(ClientError::BinaryTransfer(format!(
+        "{} exited without a status",
+        command
+    )))
+}
 
-        // Check if we need to split the transfer
-        const MAX_COMMAND_SIZE: usize = 128 * 1024; // 128KB command limit for safety
+/// What a keyboard-interactive `prompt` asks for: PAM asks for the password
+/// this way too, anything else is taken for a one-time code
+fn prompt_credential(prompt: &str) -> Credential {
+    if prompt.to_lowercase().contains("password") {
+        Credential::Password
+    } else {
+        Credential::Otp
+    }
+}
 
-        if encoded_data.len() <= MAX_COMMAND_SIZE {
-            let transfer_command = format!(
-                "echo '{}' | base64 -d > {} && chmod +x {} && echo 'Transfer completed'",
-                encoded_data, remote_temp_path, remote_temp_path
-            );
+/// Check that `cert` is a user certificate of `key` valid for `principal`
+/// at `now`
+///
+/// The login user is never taken from the certificate: a CA issuing role
+/// principals leaves it to the server to map them to users, so a principal
+/// other than the user name has to be configured.
+fn check_certificate(
+    cert: &Certificate,
+    key: &PrivateKey,
+    principal: &str,
+    now: SystemTime,
+) -> Result<()> {
+    let id = cert.key_id();
+    if cert.cert_type() != CertType::User {
+        anyhow::bail!("Certificate {} is not a user certificate", id);
+    }
+    if cert.public_key() != key.public_key().key_data() {
+        anyhow::bail!("Certificate {} is not for the given key", id);
+    }
+    let now = now.duration_since(UNIX_EPOCH)?.as_secs();
+    if now < cert.valid_after() {
+        anyhow::bail!(
+            "Certificate {} is not valid before Unix time {}",
+            id,
+            cert.valid_after()
+        );
+    }
+    if now >= cert.valid_before() {
+        anyhow::bail!(
+            "Certificate {} expired at Unix time {}",
+            id,
+            cert.valid_before()
+        );
+    }
 
-            // Execute the transfer command
-            channel
-                .exec(false, transfer_command.as_bytes())
-                .await
-                .map_err(|e| {
-                    ClientError::BinaryTransfer(format!(
-                        "Failed to execute transfer command: {}",
-                        e
-                    ))
-                })?;
-        } else {
-            // For larger files, use chunked transfer
-            info!(
-                "Binary is large ({} bytes, {} encoded), using chunked transfer",
-                binary_data.len(),
-                encoded_data.len()
-            );
+    let principals = cert.valid_principals();
+    if !principals.is_empty() && !principals.iter().any(|p| p == principal) {
+        anyhow::bail!(
+            "Certificate {} is not valid for {} (principals: {})",
+            id,
+            principal,
+            principals.join(", ")
+        );
+    }
+    Ok(())
+}
 
-            const CHUNK_SIZE: usize = 64 * 1024; // 64KB chunks
-            let chunks: Vec<&str> = encoded_data
-                .as_bytes()
-                .chunks(CHUNK_SIZE)
-                .map(|chunk| std::str::from_utf8(chunk).unwrap())
-                .collect();
+/// russh preferences following `algorithms`
+fn preferred(algorithms: &SshAlgorithms) -> Result<Preferred> {
+    let mut preferred = Preferred::default();
+    if !algorithms.kex.is_empty() {
+        let mut kex: Vec<kex::Name> = names(&algorithms.kex, "key exchange")?;
+        // Not algorithms but markers, keeping extension negotiation and the
+        // strict key exchange that defeats prefix truncation
+        kex.extend([
+            kex::EXTENSION_SUPPORT_AS_CLIENT,
+            kex::EXTENSION_OPENSSH_STRICT_KEX_AS_CLIENT,
+        ]);
+        preferred.kex = kex.into();
+    }
+    if !algorithms.ciphers.is_empty() {
+        preferred.cipher = names(&algorithms.ciphers, "cipher")?.into();
+    }
+    if !algorithms.macs.is_empty() {
+        preferred.mac = names(&algorithms.macs, "MAC")?.into();
+    }
+    if algorithms.compression {
+        preferred.compression = Cow::Borrowed(&[
+            compression::ZLIB_LEGACY,
+            compression::ZLIB,
+            compression::NONE,
+        ]);
+    }
+    Ok(preferred)
+}
 
-            info!("Splitting into {} chunks", chunks.len());
+/// Algorithms called `names`, failing on the first unknown to russh
+fn names<N: for<'a> TryFrom<&'a str>>(names: &[String], kind: &str) -> Result<Vec<N>> {
+    names
+        .iter()
+        .map(|name| {
+            N::try_from(name.as_str())
+                .map_err(|_| anyhow::anyhow!("Unsupported SSH {} algorithm '{}'", kind, name))
+        })
+        .collect()
+}
 
-            // First chunk - create the file
-            let first_command =
-                format!("echo -n '{}' | base64 -d > {}", chunks[0], remote_temp_path);
-            channel
-                .exec(false, first_command.as_bytes())
-                .await
-                .map_err(|e| {
-                    ClientError::BinaryTransfer(format!("Failed to start chunked transfer: {}", e))
-                })?;
-
-            // Remaining chunks - append to the file
-            for (i, chunk) in chunks[1..].iter().enumerate() {
-                let append_command =
-                    format!("echo -n '{}' | base64 -d >> {}", chunk, remote_temp_path);
-
-                let append_channel = handle.channel_open_session().await.map_err(|e| {
-                    ClientError::BinaryTransfer(format!(
-                        "Failed to open channel for chunk {}: {}",
-                        i + 2,
-                        e
-                    ))
-                })?;
-
-                append_channel
-                    .exec(false, append_command.as_bytes())
-                    .await
-                    .map_err(|e| {
-                        ClientError::BinaryTransfer(format!(
-                            "Failed to transfer chunk {}: {}",
-                            i + 2,
-                            e
-                        ))
-                    })?;
-            }
+/// Write `data` to `path` on the remote host, creating its directory
+async fn upload(handle: &Handle<MyHandler>, data: &[u8], path: &str) -> Result<(), ClientError> {
+    use base64::Engine;
 
-            // Set executable permissions
-            let chmod_channel = handle.channel_open_session().await.map_err(|e| {
-                ClientError::BinaryTransfer(format!("Failed to open channel for chmod: {}", e))
-            })?;
+    // Stays well below common command line limits once encoded
+    const CHUNK_LEN: usize = 48 * 1024;
 
-            let chmod_command = format!("chmod +x {}", remote_temp_path);
-            chmod_channel
-                .exec(false, chmod_command.as_bytes())
-                .await
-                .map_err(|e| {
-                    ClientError::BinaryTransfer(format!(
-                        "Failed to set executable permissions: {}",
-                        e
-                    ))
-                })?;
+    let engine = &base64::engine::general_purpose::STANDARD;
+    let mut commands = vec![format!("mkdir -p \"$(dirname {path})\" && : > {path}")];
+    commands.extend(data.chunks(CHUNK_LEN).map(|chunk| {
+        format!(
+            "printf %s '{}' | base64 -d >> {}",
+            engine.encode(chunk),
+            path
+        )
+    }));
+    for command in commands {
+        if run(handle, &command).await? != 0 {
+            return Err(ClientError::BinaryTransfer(format!(
+                "Failed to write {}",
+                path
+            )));
         }
-
-        info!("Binary transferred successfully to {}", remote_temp_path);
-
-        Ok(remote_temp_path)
     }
+    Ok(())
 }
 
 #[async_trait]
@@ -314,51 +645,32 @@ impl Transport for SshTransport {
             self.config.host, self.config.port, self.config.username
         );
 
-        let config = Arc::new(Config::default());
+        let config = Arc::new(Config {
+            preferred: preferred(&self.config.algorithms)?,
+            ..Default::default()
+        });
         let (handler, data_rx) = MyHandler::new();
 
         // Connect to SSH server
-        let mut handle = connect(config, (&self.config.host[..], self.config.port), handler)
+        let stream = socket::connect(
+            &self.config.host,
+            self.config.port,
+            &self.config.socket,
+            self.config.connection_timeout,
+        )
+        .await?;
+        let mut handle = connect_stream(config, stream, handler)
             .await
             .context("Failed to connect to SSH server")?;
 
-        // Authenticate
-        if let Some(ref password) = self.config.password {
-            let auth_result = handle
-                .authenticate_password(&self.config.username, password)
-                .await
-                .context("Failed to authenticate with password")?;
-            if !matches!(auth_result, AuthResult::Success) {
-                anyhow::bail!("Password authentication failed");
-            }
-        } else if let Some(ref key_path) = self.config.key_path {
-            let key_str = std::fs::read_to_string(key_path)
-                .with_context(|| format!("Failed to read key file: {:?}", key_path))?;
-            let russh_key = russh::keys::PrivateKey::from_openssh(&key_str)
-                .context("Failed to parse SSH key")?;
-            let key_with_hash = russh::keys::PrivateKeyWithHashAlg::new(Arc::new(russh_key), None);
-            let auth_result = handle
-                .authenticate_publickey(&self.config.username, key_with_hash)
-                .await
-                .context("Failed to authenticate with key")?;
-            if !matches!(auth_result, AuthResult::Success) {
-                anyhow::bail!("Key authentication failed");
-            }
-        } else {
-            anyhow::bail!("No authentication method provided");
-        }
-
+        self.authenticate(&mut handle).await?;
         info!("Authentication successful");
 
         // Determine the remote binary path
-        let remote_path = if self.transport_config.auto_upload_binary {
+        let remote_path = if let Some(binary_path) = self.remote_binary() {
             info!("Auto-uploading binary enabled, transferring binary to remote");
-            let binary_path = if let Some(ref path) = self.transport_config.remote_binary_path {
-                path.to_string_lossy().to_string()
-            } else {
-                REMOTE_BINARY_PATH.to_string()
-            };
-            Self::transfer_binary_to_remote(&handle, &binary_path).await?
+            self.transfer_binary_to_remote(&handle, &binary_path.to_string_lossy())
+                .await?
         } else {
             info!("Using pre-installed binary at /usr/local/bin/yuha-remote");
             "/usr/local/bin/yuha-remote".to_string()
@@ -404,4 +716,144 @@ impl Transport for SshTransport {
     fn name(&self) -> &'static str {
         "ssh"
     }
+
+    fn remote_binary(&self) -> Option<&Path> {
+        if !self.transport_config.auto_upload_binary {
+            return None;
+        }
+        Some(
+            self.transport_config
+                .remote_binary_path
+                .as_deref()
+                .unwrap_or(Path::new(REMOTE_BINARY_PATH)),
+        )
+    }
+
+    fn read_buffer(&self) -> ReadBufferConfig {
+        self.transport_config.read_buffer.clone()
+    }
+
+    fn keepalive(&self) -> KeepaliveConfig {
+        self.transport_config.keepalive.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use russh::keys::ssh_key::certificate::Builder;
+    use russh::keys::ssh_key::private::Ed25519Keypair;
+    use std::time::Duration;
+
+    fn ed25519(seed: u8) -> PrivateKey {
+        PrivateKey::from(Ed25519Keypair::from_seed(&[seed; 32]))
+    }
+
+    /// Certificate of `key` valid for `principals` from Unix time 1000 to 2000
+    fn certificate(key: &PrivateKey, cert_type: CertType, principals: &[&str]) -> Certificate {
+        let mut builder =
+            Builder::new([0; 16], key.public_key().key_data().clone(), 1000, 2000).unwrap();
+        builder.cert_type(cert_type).unwrap();
+        builder.key_id("alice@corp").unwrap();
+        if principals.is_empty() {
+            builder.all_principals_valid().unwrap();
+        }
+        for principal in principals {
+            builder.valid_principal(*principal).unwrap();
+        }
+        builder.sign(&ed25519(0)).unwrap()
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_certificate_principal() {
+        let key = ed25519(1);
+        let cert = certificate(&key, CertType::User, &["alice", "deploy"]);
+        assert!(check_certificate(&cert, &key, "deploy", at(1500)).is_ok());
+        assert!(check_certificate(&cert, &key, "bob", at(1500)).is_err());
+
+        // A single other principal does not stand in for the user name
+        let cert = certificate(&key, CertType::User, &["role-admin"]);
+        let error = check_certificate(&cert, &key, "alice", at(1500)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Certificate alice@corp is not valid for alice (principals: role-admin)"
+        );
+        assert!(check_certificate(&cert, &key, "role-admin", at(1500)).is_ok());
+
+        let cert = certificate(&key, CertType::User, &[]);
+        assert!(check_certificate(&cert, &key, "alice", at(1500)).is_ok());
+    }
+
+    #[test]
+    fn test_certificate_validity() {
+        let key = ed25519(1);
+        let cert = certificate(&key, CertType::User, &["alice"]);
+        assert!(check_certificate(&cert, &key, "alice", at(999)).is_err());
+        assert!(check_certificate(&cert, &key, "alice", at(1000)).is_ok());
+        let error = check_certificate(&cert, &key, "alice", at(2000)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Certificate alice@corp expired at Unix time 2000"
+        );
+
+        // Host certificates and certificates of other keys are refused
+        let host = certificate(&key, CertType::Host, &["alice"]);
+        assert!(check_certificate(&host, &key, "alice", at(1500)).is_err());
+        assert!(check_certificate(&cert, &ed25519(2), "alice", at(1500)).is_err());
+    }
+
+    #[test]
+    fn test_prompt_credential() {
+        assert_eq!(prompt_credential("Password: "), Credential::Password);
+        assert_eq!(prompt_credential("alice's password:"), Credential::Password);
+        assert_eq!(prompt_credential("Verification code: "), Credential::Otp);
+    }
+
+    fn strs<N: AsRef<str>>(names: &[N]) -> Vec<&str> {
+        names.iter().map(AsRef::as_ref).collect()
+    }
+
+    #[test]
+    fn test_preferred_algorithms() {
+        let defaults = preferred(&SshAlgorithms::default()).unwrap();
+        assert_eq!(strs(&defaults.cipher), strs(&Preferred::DEFAULT.cipher));
+        assert_eq!(strs(&defaults.compression)[0], "none");
+
+        let algorithms = SshAlgorithms {
+            kex: vec!["curve25519-sha256".to_string()],
+            ciphers: vec![
+                "aes128-ctr".to_string(),
+                "chacha20-poly1305@openssh.com".to_string(),
+            ],
+            macs: vec!["hmac-sha2-256".to_string()],
+            compression: true,
+        };
+        let tuned = preferred(&algorithms).unwrap();
+        assert_eq!(
+            strs(&tuned.kex),
+            [
+                "curve25519-sha256",
+                "ext-info-c",
+                "kex-strict-c-v00@openssh.com"
+            ]
+        );
+        assert_eq!(strs(&tuned.cipher), algorithms.ciphers);
+        assert_eq!(strs(&tuned.mac), algorithms.macs);
+        assert_eq!(strs(&tuned.compression)[0], "zlib@openssh.com");
+
+        let unknown = SshAlgorithms {
+            ciphers: vec!["rot13".to_string()],
+            ..Default::default()
+        };
+        let error = preferred(&unknown).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unsupported SSH cipher algorithm 'rot13'"
+        );
+    }
 }
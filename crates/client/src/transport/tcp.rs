@@ -1,16 +1,17 @@
 //! TCP transport implementation
 //!
 //! This module provides a transport that connects directly to a yuha-remote
-//! process via TCP socket connection.
+//! process via TCP socket connection, knocking first when configured to.
 
-use super::{Transport, TransportConfig};
-use anyhow::{Context, Result};
+use super::{Transport, TransportConfig, knock, socket};
+use anyhow::Result;
 use async_trait::async_trait;
-use std::net::SocketAddr;
 use std::time::Duration;
-use tokio::net::{TcpStream, lookup_host};
-use tokio::time::timeout;
-use tracing::{debug, info, warn};
+use tokio::net::TcpStream;
+use tracing::info;
+use yuha_core::message_channel::{KeepaliveConfig, ReadBufferConfig};
+use yuha_core::protocol::noise::NoiseConfig;
+use yuha_core::transport::{KnockConfig, SocketOptions};
 
 /// TCP transport configuration
 #[derive(Debug, Clone)]
@@ -18,7 +19,13 @@ pub struct TcpTransportConfig {
     pub host: String,
     pub port: u16,
     pub connection_timeout: Duration,
-    pub keepalive: bool,
+    pub socket: SocketOptions,
+    /// Shared token for authenticating the session with the remote server
+    pub auth_token: Option<String>,
+    /// Keys encrypting the session, trusting only the remote server's key
+    pub noise: Option<NoiseConfig>,
+    /// Knocks opening the port before connecting
+    pub knock: Option<KnockConfig>,
 }
 
 impl Default for TcpTransportConfig {
@@ -27,7 +34,10 @@ impl Default for TcpTransportConfig {
             host: "localhost".to_string(),
             port: 9999,
             connection_timeout: Duration::from_secs(30),
-            keepalive: true,
+            socket: SocketOptions::default(),
+            auth_token: None,
+            noise: None,
+            knock: None,
         }
     }
 }
@@ -36,7 +46,6 @@ impl Default for TcpTransportConfig {
 #[derive(Debug)]
 pub struct TcpTransport {
     config: TcpTransportConfig,
-    #[allow(dead_code)]
     transport_config: TransportConfig,
 }
 
@@ -48,68 +57,6 @@ impl TcpTransport {
             transport_config,
         }
     }
-
-    /// Connect to the remote TCP server
-    async fn connect_tcp(&self) -> Result<TcpStream> {
-        let addr = format!("{}:{}", self.config.host, self.config.port);
-        info!("Connecting to TCP server at {}", addr);
-
-        // Resolve the address
-        let socket_addrs: Vec<SocketAddr> = lookup_host(&addr)
-            .await
-            .with_context(|| format!("Failed to resolve address: {}", addr))?
-            .collect();
-
-        if socket_addrs.is_empty() {
-            anyhow::bail!("No addresses resolved for: {}", addr);
-        }
-
-        debug!("Resolved addresses: {:?}", socket_addrs);
-
-        // Try connecting to each resolved address
-        let mut last_error = None;
-        for socket_addr in socket_addrs {
-            debug!("Attempting to connect to {}", socket_addr);
-
-            match timeout(
-                self.config.connection_timeout,
-                TcpStream::connect(socket_addr),
-            )
-            .await
-            {
-                Ok(Ok(stream)) => {
-                    info!("Successfully connected to {}", socket_addr);
-
-                    // Configure keep-alive if enabled
-                    if self.config.keepalive {
-                        let socket = socket2::Socket::from(stream.into_std()?);
-                        socket.set_keepalive(true)?;
-                        let stream = TcpStream::from_std(socket.into())?;
-                        return Ok(stream);
-                    }
-
-                    return Ok(stream);
-                }
-                Ok(Err(e)) => {
-                    warn!("Failed to connect to {}: {}", socket_addr, e);
-                    last_error = Some(e.into());
-                }
-                Err(_) => {
-                    let timeout_error = anyhow::anyhow!(
-                        "Connection timeout after {:?} to {}",
-                        self.config.connection_timeout,
-                        socket_addr
-                    );
-                    warn!("{}", timeout_error);
-                    last_error = Some(timeout_error);
-                }
-            }
-        }
-
-        Err(last_error.unwrap_or_else(|| {
-            anyhow::anyhow!("Failed to connect to any resolved address for {}", addr)
-        }))
-    }
 }
 
 #[async_trait]
@@ -122,12 +69,17 @@ impl Transport for TcpTransport {
             self.config.host, self.config.port
         );
 
-        let stream = self.connect_tcp().await?;
+        if let Some(config) = &self.config.knock {
+            knock::knock(&self.config.host, config, &self.config.socket).await?;
+        }
 
-        // Set TCP no-delay for better latency
-        stream
-            .set_nodelay(true)
-            .with_context(|| "Failed to set TCP_NODELAY on connection")?;
+        let stream = socket::connect(
+            &self.config.host,
+            self.config.port,
+            &self.config.socket,
+            self.config.connection_timeout,
+        )
+        .await?;
 
         info!(
             "TCP connection established successfully to {}:{}",
@@ -140,6 +92,22 @@ impl Transport for TcpTransport {
     fn name(&self) -> &'static str {
         "tcp"
     }
+
+    fn read_buffer(&self) -> ReadBufferConfig {
+        self.transport_config.read_buffer.clone()
+    }
+
+    fn keepalive(&self) -> KeepaliveConfig {
+        self.transport_config.keepalive.clone()
+    }
+
+    fn auth_token(&self) -> Option<&str> {
+        self.config.auth_token.as_deref()
+    }
+
+    fn noise(&self) -> Option<&NoiseConfig> {
+        self.config.noise.as_ref()
+    }
 }
 
 #[cfg(test)]
@@ -153,7 +121,10 @@ mod tests {
             host: "localhost".to_string(),
             port: 8080,
             connection_timeout: Duration::from_secs(10),
-            keepalive: true,
+            socket: SocketOptions::default(),
+            auth_token: None,
+            noise: None,
+            knock: None,
         };
         let transport_config = TransportConfig::default();
         let transport = TcpTransport::new(config, transport_config);
@@ -166,6 +137,19 @@ mod tests {
         assert_eq!(config.host, "localhost");
         assert_eq!(config.port, 9999);
         assert_eq!(config.connection_timeout, Duration::from_secs(30));
-        assert!(config.keepalive);
+        assert_eq!(config.socket, SocketOptions::default());
+        assert!(config.auth_token.is_none());
+        assert!(config.noise.is_none());
+        assert!(config.knock.is_none());
+    }
+
+    #[test]
+    fn test_tcp_transport_auth_token() {
+        let config = TcpTransportConfig {
+            auth_token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let transport = TcpTransport::new(config, TransportConfig::default());
+        assert_eq!(transport.auth_token(), Some("secret"));
     }
 }
@@ -2,6 +2,7 @@
 //!
 //! This module provides a transport that runs yuha-remote in Windows Subsystem for Linux (WSL).
 
+use super::portproxy::PortProxy;
 use super::shared::{ProcessStream, configure_command, spawn_stderr_logger};
 use super::{Transport, TransportConfig};
 use anyhow::{Context, Result};
@@ -10,6 +11,7 @@ use std::path::PathBuf;
 use std::process::Stdio;
 use tokio::process::Command;
 use tracing::{debug, info};
+use yuha_core::message_channel::{KeepaliveConfig, ReadBufferConfig};
 
 /// WSL transport configuration
 #[derive(Debug, Clone)]
@@ -22,6 +24,8 @@ pub struct WslTransportConfig {
     pub binary_path: PathBuf,
     /// Working directory in WSL
     pub working_dir: Option<PathBuf>,
+    /// Make forwarded ports reachable from Windows applications via localhost
+    pub expose_ports: bool,
 }
 
 impl Default for WslTransportConfig {
@@ -31,6 +35,7 @@ impl Default for WslTransportConfig {
             user: None,
             binary_path: PathBuf::from("yuha-remote"),
             working_dir: None,
+            expose_ports: false,
         }
     }
 }
@@ -40,14 +45,17 @@ impl Default for WslTransportConfig {
 pub struct WslTransport {
     config: WslTransportConfig,
     transport_config: TransportConfig,
+    proxy: PortProxy,
 }
 
 impl WslTransport {
     /// Create a new WSL transport
     pub fn new(config: WslTransportConfig, transport_config: TransportConfig) -> Self {
+        let proxy = PortProxy::new(config.distribution.clone());
         Self {
             config,
             transport_config,
+            proxy,
         }
     }
 
@@ -206,6 +214,25 @@ impl Transport for WslTransport {
     fn name(&self) -> &'static str {
         "wsl"
     }
+
+    fn read_buffer(&self) -> ReadBufferConfig {
+        self.transport_config.read_buffer.clone()
+    }
+
+    fn keepalive(&self) -> KeepaliveConfig {
+        self.transport_config.keepalive.clone()
+    }
+
+    async fn forward_started(&self, local_port: u16) -> Result<()> {
+        if !self.config.expose_ports {
+            return Ok(());
+        }
+        self.proxy.expose(local_port).await
+    }
+
+    async fn forward_stopped(&self, local_port: u16) -> Result<()> {
+        self.proxy.unexpose(local_port).await
+    }
 }
 
 #[cfg(test)]
@@ -219,6 +246,7 @@ mod tests {
             user: Some("user".to_string()),
             binary_path: PathBuf::from("yuha-remote"),
             working_dir: None,
+            expose_ports: false,
         };
         let transport_config = TransportConfig::default();
         let transport = WslTransport::new(config, transport_config);
@@ -232,6 +260,7 @@ mod tests {
         assert!(config.user.is_none());
         assert_eq!(config.binary_path, PathBuf::from("yuha-remote"));
         assert!(config.working_dir.is_none());
+        assert!(!config.expose_ports);
     }
 
     #[tokio::test]
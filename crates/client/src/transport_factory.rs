@@ -3,16 +3,22 @@
 //! This module provides a unified factory for creating transport instances
 //! from transport configurations.
 
+use crate::credentials::{CredentialsProvider, EnvCredentials};
 use crate::transport::tcp::TcpTransportConfig;
 use crate::transport::wsl::WslTransportConfig;
 use crate::transport::{
     LocalTransport, LocalTransportConfig, SshTransport, SshTransportConfig, TcpTransport,
-    Transport, TransportConfig, WslTransport,
+    Transport, TransportConfig, TransportStream, WslTransport,
 };
-use anyhow::Result;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 use tracing::{debug, info};
-use yuha_core::transport::{TransportConfig as CoreTransportConfig, TransportType};
+use yuha_core::message_channel::{KeepaliveConfig, ReadBufferConfig};
+use yuha_core::protocol::noise::{NoiseConfig, NoiseKeypair};
+use yuha_core::transport::{SocketOptions, TransportConfig as CoreTransportConfig, TransportType};
 
 /// Enum that can hold any transport type
 #[derive(Debug)]
@@ -23,9 +29,20 @@ pub enum AnyTransport {
     Wsl(WslTransport),
 }
 
-impl AnyTransport {
-    /// Get the name of the transport
-    pub fn name(&self) -> &'static str {
+#[async_trait]
+impl Transport for AnyTransport {
+    type Stream = Box<dyn TransportStream>;
+
+    async fn connect(&self) -> Result<Self::Stream> {
+        Ok(match self {
+            AnyTransport::Local(t) => Box::new(t.connect().await?),
+            AnyTransport::Ssh(t) => Box::new(t.connect().await?),
+            AnyTransport::Tcp(t) => Box::new(t.connect().await?),
+            AnyTransport::Wsl(t) => Box::new(t.connect().await?),
+        })
+    }
+
+    fn name(&self) -> &'static str {
         match self {
             AnyTransport::Local(t) => t.name(),
             AnyTransport::Ssh(t) => t.name(),
@@ -33,6 +50,69 @@ impl AnyTransport {
             AnyTransport::Wsl(t) => t.name(),
         }
     }
+
+    fn auth_token(&self) -> Option<&str> {
+        match self {
+            AnyTransport::Local(t) => t.auth_token(),
+            AnyTransport::Ssh(t) => t.auth_token(),
+            AnyTransport::Tcp(t) => t.auth_token(),
+            AnyTransport::Wsl(t) => t.auth_token(),
+        }
+    }
+
+    fn noise(&self) -> Option<&NoiseConfig> {
+        match self {
+            AnyTransport::Local(t) => t.noise(),
+            AnyTransport::Ssh(t) => t.noise(),
+            AnyTransport::Tcp(t) => t.noise(),
+            AnyTransport::Wsl(t) => t.noise(),
+        }
+    }
+
+    fn remote_binary(&self) -> Option<&Path> {
+        match self {
+            AnyTransport::Local(t) => t.remote_binary(),
+            AnyTransport::Ssh(t) => t.remote_binary(),
+            AnyTransport::Tcp(t) => t.remote_binary(),
+            AnyTransport::Wsl(t) => t.remote_binary(),
+        }
+    }
+
+    fn read_buffer(&self) -> ReadBufferConfig {
+        match self {
+            AnyTransport::Local(t) => t.read_buffer(),
+            AnyTransport::Ssh(t) => t.read_buffer(),
+            AnyTransport::Tcp(t) => t.read_buffer(),
+            AnyTransport::Wsl(t) => t.read_buffer(),
+        }
+    }
+
+    fn keepalive(&self) -> KeepaliveConfig {
+        match self {
+            AnyTransport::Local(t) => t.keepalive(),
+            AnyTransport::Ssh(t) => t.keepalive(),
+            AnyTransport::Tcp(t) => t.keepalive(),
+            AnyTransport::Wsl(t) => t.keepalive(),
+        }
+    }
+
+    async fn forward_started(&self, local_port: u16) -> Result<()> {
+        match self {
+            AnyTransport::Local(t) => t.forward_started(local_port).await,
+            AnyTransport::Ssh(t) => t.forward_started(local_port).await,
+            AnyTransport::Tcp(t) => t.forward_started(local_port).await,
+            AnyTransport::Wsl(t) => t.forward_started(local_port).await,
+        }
+    }
+
+    async fn forward_stopped(&self, local_port: u16) -> Result<()> {
+        match self {
+            AnyTransport::Local(t) => t.forward_stopped(local_port).await,
+            AnyTransport::Ssh(t) => t.forward_stopped(local_port).await,
+            AnyTransport::Tcp(t) => t.forward_stopped(local_port).await,
+            AnyTransport::Wsl(t) => t.forward_stopped(local_port).await,
+        }
+    }
 }
 
 /// Factory for creating transport instances from configurations
@@ -41,14 +121,42 @@ pub struct ClientTransportFactory;
 impl ClientTransportFactory {
     /// Create a transport instance from a core transport configuration
     pub fn create_transport(config: &CoreTransportConfig) -> Result<AnyTransport> {
+        Self::create_transport_with_credentials(config, Arc::new(EnvCredentials))
+    }
+
+    /// Create a transport instance asking `credentials` for the secrets the
+    /// configuration lacks
+    pub fn create_transport_with_credentials(
+        config: &CoreTransportConfig,
+        credentials: Arc<dyn CredentialsProvider>,
+    ) -> Result<AnyTransport> {
         match config.transport_type {
             TransportType::Local => Ok(AnyTransport::Local(Self::create_local_transport(config)?)),
-            TransportType::Ssh => Ok(AnyTransport::Ssh(Self::create_ssh_transport(config)?)),
+            TransportType::Ssh => Ok(AnyTransport::Ssh(
+                Self::create_ssh_transport(config)?.with_credentials(credentials),
+            )),
             TransportType::Tcp => Ok(AnyTransport::Tcp(Self::create_tcp_transport(config)?)),
             TransportType::Wsl => Ok(AnyTransport::Wsl(Self::create_wsl_transport(config)?)),
         }
     }
 
+    /// Settings shared by every transport, taken from the general section
+    fn transport_config(
+        config: &CoreTransportConfig,
+        auto_upload_binary: bool,
+        working_dir: Option<PathBuf>,
+    ) -> TransportConfig {
+        TransportConfig {
+            remote_binary_path: config.general.remote_binary_path.clone(),
+            auto_upload_binary,
+            env_vars: config.general.env_vars.clone(),
+            working_dir,
+            read_buffer: config.general.read_buffer.clone(),
+            keepalive: config.general.keepalive.clone(),
+            child_env: config.general.child_env.clone(),
+        }
+    }
+
     /// Create a local transport
     fn create_local_transport(config: &CoreTransportConfig) -> Result<LocalTransport> {
         let local_config = config
@@ -56,21 +164,14 @@ impl ClientTransportFactory {
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("Local transport configuration is required"))?;
 
-        let transport_config = TransportConfig {
-            remote_binary_path: config.general.remote_binary_path.clone(),
-            auto_upload_binary: false, // Local transport doesn't need to upload
-            env_vars: config
-                .general
-                .env_vars
-                .iter()
-                .map(|(k, v)| (k.clone(), v.clone()))
-                .collect(),
-            working_dir: local_config.working_dir.clone(),
-        };
+        // Local transport doesn't need to upload
+        let transport_config =
+            Self::transport_config(config, false, local_config.working_dir.clone());
 
         let local_transport_config = LocalTransportConfig {
             binary_path: local_config.binary_path.clone(),
             args: local_config.args.clone(),
+            isolated: false,
         };
 
         info!(
@@ -90,17 +191,8 @@ impl ClientTransportFactory {
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("SSH transport configuration is required"))?;
 
-        let transport_config = TransportConfig {
-            remote_binary_path: config.general.remote_binary_path.clone(),
-            auto_upload_binary: ssh_config.auto_upload_binary,
-            env_vars: config
-                .general
-                .env_vars
-                .iter()
-                .map(|(k, v)| (k.clone(), v.clone()))
-                .collect(),
-            working_dir: None, // SSH transport doesn't use working_dir in the same way
-        };
+        // SSH transport doesn't use working_dir in the same way
+        let transport_config = Self::transport_config(config, ssh_config.auto_upload_binary, None);
 
         let ssh_transport_config = SshTransportConfig {
             host: ssh_config.host.clone(),
@@ -108,6 +200,12 @@ impl ClientTransportFactory {
             username: ssh_config.username.clone(),
             password: ssh_config.password.clone(),
             key_path: ssh_config.key_path.clone(),
+            cert_path: ssh_config.cert_path.clone(),
+            cert_principal: ssh_config.cert_principal.clone(),
+            pkcs11: ssh_config.pkcs11.clone(),
+            connection_timeout: Duration::from_secs(ssh_config.timeout),
+            socket: ssh_config.socket_options(&SocketOptions::default()),
+            algorithms: ssh_config.algorithms.clone(),
         };
 
         info!(
@@ -124,23 +222,37 @@ impl ClientTransportFactory {
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("TCP transport configuration is required"))?;
 
-        let transport_config = TransportConfig {
-            remote_binary_path: config.general.remote_binary_path.clone(),
-            auto_upload_binary: false, // TCP transport connects to existing server
-            env_vars: config
-                .general
-                .env_vars
-                .iter()
-                .map(|(k, v)| (k.clone(), v.clone()))
-                .collect(),
-            working_dir: None,
-        };
+        // TCP transport connects to existing server
+        let transport_config = Self::transport_config(config, false, None);
 
         let tcp_transport_config = TcpTransportConfig {
             host: tcp_config.host.clone(),
             port: tcp_config.port,
             connection_timeout: Duration::from_secs(tcp_config.timeout),
-            keepalive: true, // Enable keepalive
+            socket: tcp_config.socket.clone().unwrap_or_default(),
+            auth_token: tcp_config.auth_token.clone(),
+            knock: tcp_config.knock.clone(),
+            noise: tcp_config
+                .noise
+                .as_ref()
+                .map(|noise| -> Result<NoiseConfig> {
+                    let keypair = match &noise.sealed_to {
+                        Some(key) => {
+                            info!(
+                                "Unsealing the Noise key with the ssh-agent's {} key, touch the token if it asks for presence",
+                                key.algorithm()
+                            );
+                            NoiseKeypair::load_or_generate_sealed(&noise.key_file, key)
+                        }
+                        None => NoiseKeypair::load_or_generate(&noise.key_file),
+                    }
+                    .with_context(|| {
+                        format!("Failed to load Noise key {}", noise.key_file.display())
+                    })?;
+                    info!("Noise public key: {}", keypair.public());
+                    Ok(NoiseConfig::new(keypair, vec![noise.remote_key]))
+                })
+                .transpose()?,
         };
 
         info!(
@@ -157,17 +269,9 @@ impl ClientTransportFactory {
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("WSL transport configuration is required"))?;
 
-        let transport_config = TransportConfig {
-            remote_binary_path: config.general.remote_binary_path.clone(),
-            auto_upload_binary: false, // WSL uses local filesystem
-            env_vars: config
-                .general
-                .env_vars
-                .iter()
-                .map(|(k, v)| (k.clone(), v.clone()))
-                .collect(),
-            working_dir: wsl_config.working_dir.clone(),
-        };
+        // WSL uses local filesystem
+        let transport_config =
+            Self::transport_config(config, false, wsl_config.working_dir.clone());
 
         let wsl_transport_config = WslTransportConfig {
             distribution: wsl_config.distribution.clone(),
@@ -177,6 +281,7 @@ impl ClientTransportFactory {
                 .clone()
                 .unwrap_or_else(|| std::path::PathBuf::from("yuha-remote")),
             working_dir: wsl_config.working_dir.clone(),
+            expose_ports: wsl_config.expose_ports,
         };
 
         info!(
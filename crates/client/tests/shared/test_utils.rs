@@ -1,8 +1,8 @@
 //! Test utilities and helpers for yuha client tests
 
 use std::path::PathBuf;
+use yuha_client::Client;
 use yuha_client::transport::{LocalTransport, LocalTransportConfig, TransportConfig};
-use yuha_client::{Client, client_transport::connect_local};
 use yuha_core::protocol::ProtocolResponse;
 
 /// Test categories for filtering
@@ -21,20 +21,22 @@ pub fn get_remote_binary_path() -> PathBuf {
     PathBuf::from(yuha_client::client::get_remote_binary_path())
 }
 
-/// Create a local transport with optional custom configuration
+/// Create a local transport with optional custom configuration, isolated
+/// from the remotes of tests running in parallel
 pub fn create_local_transport(transport_config: Option<TransportConfig>) -> LocalTransport {
     let local_config = LocalTransportConfig {
         binary_path: get_remote_binary_path(),
         args: vec!["--stdio".to_string()],
+        isolated: true,
     };
     LocalTransport::new(local_config, transport_config.unwrap_or_default())
 }
 
 /// Create and connect a local client for testing
 pub async fn create_local_client() -> anyhow::Result<Client<LocalTransport>> {
-    connect_local(get_remote_binary_path(), TransportConfig::default())
-        .await
-        .map_err(|e| anyhow::anyhow!(e))
+    let mut client = Client::new(create_local_transport(None));
+    client.connect().await?;
+    Ok(client)
 }
 
 /// Create and connect a local transport client for testing (alias for compatibility)
@@ -47,26 +49,50 @@ macro_rules! assert_response {
     (success, $response:expr) => {
         match $response {
             ProtocolResponse::Success => {}
-            ProtocolResponse::Error { message } => {
+            ProtocolResponse::Error { message, .. } => {
                 panic!("Expected success, got error: {}", message)
             }
             ProtocolResponse::Data { .. } => panic!("Expected success, got data response"),
+            ProtocolResponse::Batch { .. } => panic!("Expected success, got batch response"),
+            ProtocolResponse::ServerRequest { .. } => {
+                panic!("Expected success, got server request")
+            }
+            ProtocolResponse::Event { .. } => panic!("Expected success, got event"),
+            ProtocolResponse::Unsupported { request_name } => {
+                panic!("Expected success, got {} unsupported", request_name)
+            }
         }
     };
     (data, $response:expr) => {
         match $response {
-            ProtocolResponse::Data { items } => items,
+            ProtocolResponse::Data { items, .. } => items,
             ProtocolResponse::Success => panic!("Expected data response, got success"),
-            ProtocolResponse::Error { message } => {
+            ProtocolResponse::Error { message, .. } => {
                 panic!("Expected data response, got error: {}", message)
             }
+            ProtocolResponse::Batch { .. } => panic!("Expected data response, got batch response"),
+            ProtocolResponse::ServerRequest { .. } => {
+                panic!("Expected data response, got server request")
+            }
+            ProtocolResponse::Event { .. } => panic!("Expected data response, got event"),
+            ProtocolResponse::Unsupported { request_name } => {
+                panic!("Expected data response, got {} unsupported", request_name)
+            }
         }
     };
     (error, $response:expr) => {
         match $response {
-            ProtocolResponse::Error { message } => message,
+            ProtocolResponse::Error { message, .. } => message,
             ProtocolResponse::Success => panic!("Expected error, got success"),
             ProtocolResponse::Data { .. } => panic!("Expected error, got data response"),
+            ProtocolResponse::Batch { .. } => panic!("Expected error, got batch response"),
+            ProtocolResponse::ServerRequest { .. } => {
+                panic!("Expected error, got server request")
+            }
+            ProtocolResponse::Event { .. } => panic!("Expected error, got event"),
+            ProtocolResponse::Unsupported { request_name } => {
+                panic!("Expected error, got {} unsupported", request_name)
+            }
         }
     };
 }
@@ -14,6 +14,7 @@ async fn test_local_transport_creation() -> Result<()> {
     let config = LocalTransportConfig {
         binary_path: PathBuf::from("yuha-remote"),
         args: vec!["--stdio".to_string()],
+        isolated: false,
     };
     let transport_config = TransportConfig::default();
     let _transport = LocalTransport::new(config, transport_config);
@@ -32,6 +33,7 @@ async fn test_local_transport_with_custom_binary() -> Result<()> {
     let config = LocalTransportConfig {
         binary_path: PathBuf::from("/nonexistent/binary"),
         args: vec!["--stdio".to_string()],
+        isolated: false,
     };
     let transport_config = TransportConfig::default();
     let _transport = LocalTransport::new(config, transport_config);
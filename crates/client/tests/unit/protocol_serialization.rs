@@ -1,7 +1,7 @@
 use anyhow::Result;
 use serde_json;
 use serial_test::serial;
-use yuha_core::protocol::{ProtocolRequest, ProtocolResponse, ResponseItem};
+use yuha_core::protocol::{ErrorCode, ProtocolRequest, ProtocolResponse, ResponseItem};
 
 #[tokio::test]
 #[serial]
@@ -78,9 +78,7 @@ async fn test_response_types_serialization() -> Result<()> {
     // Test all response types
     let responses = vec![
         ProtocolResponse::Success,
-        ProtocolResponse::Error {
-            message: "Test error".to_string(),
-        },
+        ProtocolResponse::error(ErrorCode::NotFound, "Test error").with_detail("path", "a.txt"),
         ProtocolResponse::Data {
             items: vec![
                 ResponseItem::ClipboardContent {
@@ -96,6 +94,7 @@ async fn test_response_types_serialization() -> Result<()> {
                 },
                 ResponseItem::CloseConnection { connection_id: 789 },
             ],
+            next: None,
         },
     ];
 
@@ -0,0 +1,204 @@
+//! # Access Log
+//!
+//! Records the TCP connections a server accepted or rejected, with their
+//! source, authentication result and duration, so probing of a daemon can
+//! be noticed and inspected via `ProtocolRequest::GetAccessLog`.
+//! [`summarize`] groups the entries by source address; sources are not
+//! looked up anywhere, so summaries work offline and leak nothing.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+/// Default number of entries kept
+pub const DEFAULT_CAPACITY: usize = 1000;
+
+/// How a connection was dealt with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AccessOutcome {
+    /// Authenticated, or not asked to authenticate, and served
+    Accepted,
+    /// Closed before being served, e.g. on a wrong token or untrusted key
+    Rejected { reason: String },
+}
+
+/// A connection to the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessEntry {
+    pub source: SocketAddr,
+    pub outcome: AccessOutcome,
+    pub connected_at: SystemTime,
+    /// How long the connection lasted, up to its rejection if rejected
+    pub duration: Duration,
+    /// Noise public key the client authenticated with, as hex
+    #[serde(default)]
+    pub client_key: Option<String>,
+}
+
+impl AccessEntry {
+    pub fn is_rejected(&self) -> bool {
+        matches!(self.outcome, AccessOutcome::Rejected { .. })
+    }
+}
+
+/// Ring buffer of the most recent connections, shared by the sessions of a
+/// server and optionally appended to a file as JSON lines
+#[derive(Debug)]
+pub struct AccessLog {
+    capacity: usize,
+    entries: Mutex<VecDeque<AccessEntry>>,
+    file: Option<Mutex<File>>,
+}
+
+impl AccessLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity.min(1024))),
+            file: None,
+        }
+    }
+
+    /// Also append every entry to the file at `path`
+    pub fn with_file(mut self, path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.file = Some(Mutex::new(file));
+        Ok(self)
+    }
+
+    pub fn record(&self, entry: AccessEntry) {
+        if let Some(file) = &self.file {
+            let mut line = serde_json::to_vec(&entry).unwrap_or_default();
+            line.push(b'\n');
+            if let Err(e) = file.lock().unwrap().write_all(&line) {
+                warn!("Failed to append to the access log: {}", e);
+            }
+        }
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Logged connections, newest first
+    pub fn entries(&self) -> Vec<AccessEntry> {
+        self.entries.lock().unwrap().iter().rev().cloned().collect()
+    }
+}
+
+/// Connections from one source address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceSummary {
+    pub source: IpAddr,
+    pub accepted: usize,
+    pub rejected: usize,
+    /// Time the accepted connections lasted in total
+    pub connected: Duration,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+/// Group `entries` by source address, sources with the most rejections first
+pub fn summarize(entries: &[AccessEntry]) -> Vec<SourceSummary> {
+    let mut sources: HashMap<IpAddr, SourceSummary> = HashMap::new();
+    for entry in entries {
+        let summary = sources
+            .entry(entry.source.ip())
+            .or_insert_with(|| SourceSummary {
+                source: entry.source.ip(),
+                accepted: 0,
+                rejected: 0,
+                connected: Duration::ZERO,
+                first_seen: entry.connected_at,
+                last_seen: entry.connected_at,
+            });
+        if entry.is_rejected() {
+            summary.rejected += 1;
+        } else {
+            summary.accepted += 1;
+            summary.connected += entry.duration;
+        }
+        summary.first_seen = summary.first_seen.min(entry.connected_at);
+        summary.last_seen = summary.last_seen.max(entry.connected_at);
+    }
+    let mut summaries: Vec<_> = sources.into_values().collect();
+    summaries.sort_by(|a, b| {
+        (b.rejected, b.last_seen, a.source).cmp(&(a.rejected, a.last_seen, b.source))
+    });
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(source: &str, rejected: bool, secs: u64) -> AccessEntry {
+        AccessEntry {
+            source: source.parse().unwrap(),
+            outcome: if rejected {
+                AccessOutcome::Rejected {
+                    reason: "Authentication failed".to_string(),
+                }
+            } else {
+                AccessOutcome::Accepted
+            },
+            connected_at: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            duration: Duration::from_secs(10),
+            client_key: None,
+        }
+    }
+
+    #[test]
+    fn test_capacity_evicts_oldest() {
+        let log = AccessLog::new(2);
+        for secs in [1, 2, 3] {
+            log.record(entry("10.0.0.1:4000", false, secs));
+        }
+        let times: Vec<_> = log
+            .entries()
+            .into_iter()
+            .map(|entry| entry.connected_at)
+            .collect();
+        assert_eq!(
+            times,
+            [3, 2].map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+        );
+    }
+
+    #[test]
+    fn test_summarize_by_source() {
+        let entries = [
+            entry("10.0.0.1:4000", false, 5),
+            entry("10.0.0.1:4001", false, 1),
+            entry("192.0.2.7:5000", true, 3),
+            entry("192.0.2.7:5001", true, 4),
+            entry("[2001:db8::1]:6000", false, 2),
+        ];
+        let summaries = summarize(&entries);
+        let sources: Vec<_> = summaries.iter().map(|s| s.source.to_string()).collect();
+        assert_eq!(sources, ["192.0.2.7", "10.0.0.1", "2001:db8::1"]);
+
+        assert_eq!(summaries[0].rejected, 2);
+        assert_eq!(summaries[0].connected, Duration::ZERO);
+        assert_eq!(summaries[1].accepted, 2);
+        assert_eq!(summaries[1].connected, Duration::from_secs(20));
+        assert_eq!(
+            summaries[1].first_seen,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1)
+        );
+        assert_eq!(
+            summaries[1].last_seen,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(5)
+        );
+    }
+}
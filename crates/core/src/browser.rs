@@ -18,7 +18,7 @@ pub async fn open_url(url: &str) -> Result<()> {
 }
 
 /// Validate URL format
-fn validate_url(url: &str) -> Result<()> {
+pub fn validate_url(url: &str) -> Result<()> {
     match Url::parse(url) {
         Ok(parsed_url) => {
             debug!(
@@ -0,0 +1,136 @@
+//! # Buffer Pool
+//!
+//! A [`MessageChannel`](crate::message_channel::MessageChannel) builds
+//! outgoing messages and reassembles fragmented incoming ones in buffers
+//! taken from its pool, so steady traffic keeps reusing a handful of
+//! allocations instead of making new ones per message. A buffer returns to
+//! the pool once the last [`Bytes`] frozen from it is recycled; buffers
+//! still referenced elsewhere, like attachments handed to the caller, are
+//! simply left to be freed.
+//!
+//! Frames themselves are split off the channel's read buffer, which reuses
+//! its allocation once they are dropped, so they need no pooling.
+
+use bytes::{Bytes, BytesMut};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Free buffers kept for reuse
+const MAX_FREE: usize = 16;
+
+/// Buffers grown beyond this are freed rather than kept, so one bulk
+/// transfer does not pin its memory
+const MAX_POOLED_CAPACITY: usize = 1024 * 1024;
+
+/// Capacity of newly allocated buffers
+const MIN_CAPACITY: usize = 4096;
+
+/// Buffers shared by the sending and receiving side of a channel
+#[derive(Debug, Default)]
+pub struct BufferPool {
+    free: Mutex<Vec<BytesMut>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// How often a [`BufferPool`] could serve a buffer without allocating
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Buffers served from the pool
+    pub hits: u64,
+    /// Buffers allocated because the pool had none large enough
+    pub misses: u64,
+}
+
+impl PoolStats {
+    /// Share of buffers served from the pool, 1 before any was taken
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 1.0,
+            taken => self.hits as f64 / taken as f64,
+        }
+    }
+}
+
+impl BufferPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An empty buffer with room for at least `len` bytes
+    pub fn take(&self, len: usize) -> BytesMut {
+        let reused = self.free.lock().unwrap().pop();
+        match reused {
+            Some(mut buf) if buf.capacity() >= len => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                buf.clear();
+                buf
+            }
+            Some(mut buf) => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                buf.clear();
+                buf.reserve(len);
+                buf
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                BytesMut::with_capacity(len.max(MIN_CAPACITY))
+            }
+        }
+    }
+
+    /// Return the buffer of `data` to the pool if nothing else refers to it
+    pub fn recycle(&self, data: Bytes) {
+        if let Ok(buf) = data.try_into_mut() {
+            self.put(buf);
+        }
+    }
+
+    /// Return `buf` to the pool
+    pub fn put(&self, mut buf: BytesMut) {
+        if buf.capacity() == 0 || buf.capacity() > MAX_POOLED_CAPACITY {
+            return;
+        }
+        buf.clear();
+        let mut free = self.free.lock().unwrap();
+        if free.len() < MAX_FREE {
+            free.push(buf);
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffers_reused() {
+        let pool = BufferPool::new();
+        let mut buf = pool.take(10);
+        buf.extend_from_slice(b"message");
+        let data = buf.freeze();
+
+        // Still referenced, so not reusable yet
+        let held = data.clone();
+        pool.recycle(data);
+        assert!(pool.free.lock().unwrap().is_empty());
+        pool.recycle(held);
+
+        let buf = pool.take(100);
+        assert!(buf.is_empty() && buf.capacity() >= 100);
+        pool.put(buf);
+        pool.take(2 * MIN_CAPACITY);
+        assert_eq!(pool.stats(), PoolStats { hits: 1, misses: 2 });
+        assert!((pool.stats().hit_rate() - 1.0 / 3.0).abs() < 1e-9);
+
+        pool.put(BytesMut::with_capacity(MAX_POOLED_CAPACITY + 1));
+        assert!(pool.free.lock().unwrap().is_empty());
+    }
+}
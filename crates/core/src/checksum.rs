@@ -0,0 +1,215 @@
+//! # Checksums
+//!
+//! CRC32C for cheap per-chunk corruption checks and BLAKE3 for file
+//! integrity. Both pick the fastest implementation the CPU supports at
+//! runtime, so integrity checks keep up with gigabit transfers.
+//!
+//! Hardware CRC32C (SSE4.2 or the ARMv8 CRC extension) is enabled by the
+//! default `simd-checksum` feature; without it a portable table driven
+//! implementation is used. BLAKE3 always dispatches to its SIMD backends.
+//!
+//! Files compared by hash, e.g. by `HashPath` requests, are hashed with
+//! either, chosen as a [`HashAlgorithm`].
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+/// CRC32C implementation selected for this CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumBackend {
+    Sse42,
+    ArmCrc,
+    Software,
+}
+
+impl fmt::Display for ChecksumBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChecksumBackend::Sse42 => write!(f, "sse4.2"),
+            ChecksumBackend::ArmCrc => write!(f, "arm-crc"),
+            ChecksumBackend::Software => write!(f, "software"),
+        }
+    }
+}
+
+/// Detect which CRC32C implementation [`crc32c`] uses
+pub fn backend() -> ChecksumBackend {
+    #[cfg(all(feature = "simd-checksum", target_arch = "x86_64"))]
+    if std::arch::is_x86_feature_detected!("sse4.2") {
+        return ChecksumBackend::Sse42;
+    }
+    #[cfg(all(feature = "simd-checksum", target_arch = "aarch64"))]
+    if std::arch::is_aarch64_feature_detected!("crc") {
+        return ChecksumBackend::ArmCrc;
+    }
+    ChecksumBackend::Software
+}
+
+/// CRC32C (Castagnoli) of `data`
+pub fn crc32c(data: &[u8]) -> u32 {
+    crc32c_append(0, data)
+}
+
+/// CRC32C of `data` following data whose CRC32C is `crc`
+#[cfg(feature = "simd-checksum")]
+pub fn crc32c_append(crc: u32, data: &[u8]) -> u32 {
+    ::crc32c::crc32c_append(crc, data)
+}
+
+/// CRC32C of `data` following data whose CRC32C is `crc`
+#[cfg(not(feature = "simd-checksum"))]
+pub fn crc32c_append(crc: u32, data: &[u8]) -> u32 {
+    software_crc32c(crc, data)
+}
+
+#[cfg(any(not(feature = "simd-checksum"), test))]
+fn software_crc32c(crc: u32, data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0x82F6_3B78
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+
+    !data.iter().fold(!crc, |crc, &byte| {
+        TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// BLAKE3 digest of `data` as lowercase hex
+pub fn digest(data: &[u8]) -> String {
+    blake3::hash(data).to_hex().to_string()
+}
+
+/// BLAKE3 digest of the file at `path` as lowercase hex
+///
+/// Reads the file synchronously; call from a blocking context.
+pub fn digest_file(path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(std::fs::File::open(path)?)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Hash function files are compared with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    /// BLAKE3, also catching deliberate changes
+    #[default]
+    Blake3,
+    /// CRC32C, cheaper but only catching accidental ones
+    Crc32c,
+}
+
+impl HashAlgorithm {
+    /// Hash of `data` as lowercase hex
+    pub fn hash(self, data: &[u8]) -> String {
+        match self {
+            Self::Blake3 => digest(data),
+            Self::Crc32c => format!("{:08x}", crc32c(data)),
+        }
+    }
+
+    /// Hash of the file at `path` as lowercase hex
+    ///
+    /// Reads the file synchronously; call from a blocking context.
+    pub fn hash_file(self, path: &Path) -> io::Result<String> {
+        match self {
+            Self::Blake3 => digest_file(path),
+            Self::Crc32c => {
+                let mut file = std::fs::File::open(path)?;
+                let mut buf = vec![0u8; 64 * 1024];
+                let mut crc = 0;
+                loop {
+                    match file.read(&mut buf)? {
+                        0 => return Ok(format!("{:08x}", crc)),
+                        read => crc = crc32c_append(crc, &buf[..read]),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Blake3 => write!(f, "blake3"),
+            Self::Crc32c => write!(f, "crc32c"),
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "blake3" => Ok(Self::Blake3),
+            "crc32c" => Ok(Self::Crc32c),
+            other => Err(format!("Unknown hash algorithm {}", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32c() {
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+
+        let data: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        assert_eq!(crc32c(&data), software_crc32c(0, &data));
+        assert_eq!(
+            crc32c_append(crc32c(&data[..123]), &data[123..]),
+            crc32c(&data)
+        );
+    }
+
+    #[test]
+    fn test_digest_file() {
+        assert_eq!(
+            digest(b"abc"),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        let data = vec![0x5a; 200_000];
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(digest_file(&path).unwrap(), digest(&data));
+    }
+
+    #[test]
+    fn test_hash_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        let data: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+
+        for algo in [HashAlgorithm::Blake3, HashAlgorithm::Crc32c] {
+            assert_eq!(algo.hash_file(&path).unwrap(), algo.hash(&data));
+            assert_eq!(algo.to_string().parse::<HashAlgorithm>(), Ok(algo));
+        }
+        assert_eq!(HashAlgorithm::Crc32c.hash(b"123456789"), "e3069283");
+    }
+}
@@ -0,0 +1,189 @@
+//! # Child Process Environment
+//!
+//! Processes yuha spawns, local remotes started by the client and
+//! applications the remote launches, start from an empty environment
+//! instead of inheriting the whole environment of the client, daemon or
+//! server, so tokens and credentials held there do not leak into them.
+//! Only allow-listed variables are passed through, and the locale is fixed:
+//!
+//! ```toml
+//! [client.child_env]
+//! allow = ["PATH", "HOME", "JAVA_HOME"]
+//! locale = "en_US.UTF-8"
+//! ```
+//!
+//! Variables a spawner sets explicitly, like the configured `env_vars` of a
+//! transport, are added on top.
+
+use serde::{Deserialize, Serialize};
+use std::ffi::{OsStr, OsString};
+
+/// Variables passed through unless configured otherwise
+pub const DEFAULT_ALLOWED: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "TERM",
+    "TZ",
+    "LANG",
+    "LC_ALL",
+    "LC_CTYPE",
+    "LC_MESSAGES",
+    "TMPDIR",
+    "XDG_RUNTIME_DIR",
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "RUST_LOG",
+    "YUHA_IPC_SOCKET",
+    // Needed by nearly every Windows process
+    "SYSTEMROOT",
+    "WINDIR",
+    "COMSPEC",
+    "PATHEXT",
+    "TEMP",
+    "TMP",
+    "USERPROFILE",
+    "APPDATA",
+    "LOCALAPPDATA",
+    "PROGRAMDATA",
+];
+
+/// Locale of children when neither configured nor passed through
+pub const DEFAULT_LOCALE: &str = "C.UTF-8";
+
+/// Environment spawned processes start with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChildEnv {
+    /// Variables passed through from the spawning process
+    pub allow: Vec<String>,
+    /// Locale set as `LANG` and `LC_ALL`; the passed through locale, or
+    /// `C.UTF-8` without one, when unset
+    pub locale: Option<String>,
+}
+
+impl Default for ChildEnv {
+    fn default() -> Self {
+        Self {
+            allow: DEFAULT_ALLOWED
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            locale: None,
+        }
+    }
+}
+
+impl ChildEnv {
+    /// Also pass through the variables `names`
+    pub fn allowing(mut self, names: impl IntoIterator<Item = String>) -> Self {
+        for name in names {
+            if !self.allows(OsStr::new(&name)) {
+                self.allow.push(name);
+            }
+        }
+        self
+    }
+
+    /// Environment of a child of this process; clear the child's
+    /// environment before setting it
+    pub fn vars(&self) -> Vec<(OsString, OsString)> {
+        self.vars_from(std::env::vars_os())
+    }
+
+    /// Environment of a child of a process with the variables `parent`
+    pub fn vars_from(
+        &self,
+        parent: impl IntoIterator<Item = (OsString, OsString)>,
+    ) -> Vec<(OsString, OsString)> {
+        let mut vars: Vec<_> = parent
+            .into_iter()
+            .filter(|(name, _)| self.allows(name))
+            .collect();
+        let has_locale = vars.iter().any(|(name, _)| {
+            same_name(name, OsStr::new("LANG")) || same_name(name, OsStr::new("LC_ALL"))
+        });
+
+        match &self.locale {
+            Some(locale) => {
+                vars.retain(|(name, _)| {
+                    !same_name(name, OsStr::new("LANG")) && !same_name(name, OsStr::new("LC_ALL"))
+                });
+                vars.push(("LANG".into(), locale.into()));
+                vars.push(("LC_ALL".into(), locale.into()));
+            }
+            None if !has_locale => vars.push(("LANG".into(), DEFAULT_LOCALE.into())),
+            None => {}
+        }
+        vars
+    }
+
+    fn allows(&self, name: &OsStr) -> bool {
+        self.allow
+            .iter()
+            .any(|allowed| same_name(name, OsStr::new(allowed)))
+    }
+}
+
+/// Whether two variable names are the same, ignoring case on Windows
+fn same_name(a: &OsStr, b: &OsStr) -> bool {
+    if cfg!(windows) {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a == b
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parent(vars: &[(&str, &str)]) -> Vec<(OsString, OsString)> {
+        vars.iter()
+            .map(|(name, value)| (name.into(), value.into()))
+            .collect()
+    }
+
+    #[test]
+    fn test_vars_filtered() {
+        let parent = parent(&[
+            ("PATH", "/usr/bin"),
+            ("YUHA_AUTH_TOKEN", "secret"),
+            ("AWS_SECRET_ACCESS_KEY", "secret"),
+            ("JAVA_HOME", "/opt/java"),
+        ]);
+        let env = ChildEnv::default();
+        assert_eq!(
+            env.vars_from(parent.clone()),
+            self::parent(&[("PATH", "/usr/bin"), ("LANG", DEFAULT_LOCALE)])
+        );
+
+        let env = env.allowing(["JAVA_HOME".to_string()]);
+        assert_eq!(
+            env.vars_from(parent),
+            self::parent(&[
+                ("PATH", "/usr/bin"),
+                ("JAVA_HOME", "/opt/java"),
+                ("LANG", DEFAULT_LOCALE),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_locale() {
+        let parent = parent(&[("LANG", "ja_JP.UTF-8")]);
+        assert_eq!(ChildEnv::default().vars_from(parent.clone()), parent);
+
+        let env = ChildEnv {
+            locale: Some("en_US.UTF-8".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            env.vars_from(parent),
+            self::parent(&[("LANG", "en_US.UTF-8"), ("LC_ALL", "en_US.UTF-8")])
+        );
+    }
+}
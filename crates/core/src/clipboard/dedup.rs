@@ -0,0 +1,111 @@
+//! Clipboard deduplication and echo suppression
+//!
+//! Syncing a clipboard both ways bounces content back and forth: text taken
+//! from the remote changes the local clipboard, which a watcher then sends
+//! back, which the remote announces again. A [`ClipboardDedup`] remembers the
+//! digest of the text last synced in each direction, so text the other side
+//! is known to hold is neither sent nor taken again.
+
+/// Digests of the clipboard text last synced with a peer, per direction
+///
+/// Only the latest sync counts: once text comes in, the peer no longer holds
+/// what was sent before, and the other way around.
+#[derive(Debug, Default)]
+pub struct ClipboardDedup {
+    /// Text last sent to the peer
+    sent: Option<blake3::Hash>,
+    /// Text last taken from the peer
+    received: Option<blake3::Hash>,
+}
+
+impl ClipboardDedup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `text` must be sent to the peer, recording it as sent if so
+    ///
+    /// Text just sent, or just taken from the peer, is already there.
+    pub fn should_send(&mut self, text: &str) -> bool {
+        let digest = blake3::hash(text.as_bytes());
+        if self.peer_holds(&digest) {
+            return false;
+        }
+        self.sent = Some(digest);
+        self.received = None;
+        true
+    }
+
+    /// Note that `text` was sent to the peer, whether it held it or not
+    pub fn sent(&mut self, text: &str) {
+        self.sent = Some(blake3::hash(text.as_bytes()));
+        self.received = None;
+    }
+
+    /// Whether `text` the peer announced is new, recording it as taken if so
+    ///
+    /// The peer announcing text just sent to it is an echo.
+    pub fn should_take(&mut self, text: &str) -> bool {
+        let digest = blake3::hash(text.as_bytes());
+        if self.peer_holds(&digest) {
+            return false;
+        }
+        self.received = Some(digest);
+        self.sent = None;
+        true
+    }
+
+    /// Note that `text` was read from the peer, whether new or not
+    pub fn took(&mut self, text: &str) {
+        self.received = Some(blake3::hash(text.as_bytes()));
+        self.sent = None;
+    }
+
+    /// Forget what the peer holds, e.g. after a failed send or when the peer
+    /// may have been replaced
+    pub fn forget(&mut self) {
+        *self = Self::default();
+    }
+
+    fn peer_holds(&self, digest: &blake3::Hash) -> bool {
+        self.sent.as_ref() == Some(digest) || self.received.as_ref() == Some(digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redundant_sends_suppressed() {
+        let mut dedup = ClipboardDedup::new();
+        assert!(dedup.should_send("a"));
+        assert!(!dedup.should_send("a"));
+        assert!(dedup.should_send("b"));
+        assert!(dedup.should_send("a"));
+
+        dedup.forget();
+        assert!(dedup.should_send("a"));
+
+        // Text sent regardless still counts as held
+        dedup.sent("a");
+        assert!(!dedup.should_send("a"));
+    }
+
+    #[test]
+    fn test_echoes_suppressed() {
+        let mut dedup = ClipboardDedup::new();
+        assert!(dedup.should_send("copied"));
+        // The peer announcing what it was sent
+        assert!(!dedup.should_take("copied"));
+
+        assert!(dedup.should_take("pasted"));
+        assert!(!dedup.should_take("pasted"));
+        // The local watcher seeing the text just taken
+        assert!(!dedup.should_send("pasted"));
+
+        // Once the peer holds other text, sending the earlier one is needed
+        dedup.took("other");
+        assert!(dedup.should_send("copied"));
+    }
+}
@@ -0,0 +1,745 @@
+//! Clipboard format negotiation and conversion
+//!
+//! Platforms expose rich clipboard content under different names and encodings
+//! (e.g. Windows `CF_HTML` vs `text/html` on Linux and macOS). This module maps
+//! those names onto a common [`ClipboardFormat`], picks the best format both
+//! sides understand, and converts content when no format is shared.
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use url::Url;
+
+use crate::error::{ClipboardError, Result};
+
+/// Clipboard formats understood across platforms
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipboardFormat {
+    /// UTF-8 plain text
+    Text,
+    /// HTML markup (`text/html`)
+    Html,
+    /// Windows HTML clipboard format with offset header (`CF_HTML`)
+    CfHtml,
+    /// Rich Text Format
+    Rtf,
+    /// PNG image
+    Png,
+    /// List of file references (`text/uri-list`, Windows `CF_HDROP`)
+    FileList,
+}
+
+impl ClipboardFormat {
+    /// Canonical MIME type of this format
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ClipboardFormat::Text => "text/plain;charset=utf-8",
+            ClipboardFormat::Html => "text/html",
+            ClipboardFormat::CfHtml => "application/x-cf-html",
+            ClipboardFormat::Rtf => "text/rtf",
+            ClipboardFormat::Png => "image/png",
+            ClipboardFormat::FileList => "text/uri-list",
+        }
+    }
+
+    fn is_image(&self) -> bool {
+        matches!(self, ClipboardFormat::Png)
+    }
+
+    /// Whether content in this format references files rather than carrying data
+    pub fn is_file_list(&self) -> bool {
+        matches!(self, ClipboardFormat::FileList)
+    }
+}
+
+impl fmt::Display for ClipboardFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.mime_type())
+    }
+}
+
+impl FromStr for ClipboardFormat {
+    type Err = ClipboardError;
+
+    /// Parse a MIME type or a platform-specific clipboard format name
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let name = s.split(';').next().unwrap_or(s).trim();
+        match name.to_ascii_lowercase().as_str() {
+            "text/plain"
+            | "text"
+            | "utf8_string"
+            | "string"
+            | "cf_text"
+            | "cf_unicodetext"
+            | "public.utf8-plain-text" => Ok(ClipboardFormat::Text),
+            "text/html" | "public.html" => Ok(ClipboardFormat::Html),
+            "application/x-cf-html" | "html format" | "cf_html" => Ok(ClipboardFormat::CfHtml),
+            "text/rtf" | "application/rtf" | "rich text format" | "public.rtf" => {
+                Ok(ClipboardFormat::Rtf)
+            }
+            "image/png" | "png" | "public.png" => Ok(ClipboardFormat::Png),
+            "text/uri-list" | "cf_hdrop" | "x-special/gnome-copied-files" | "public.file-url" => {
+                Ok(ClipboardFormat::FileList)
+            }
+            _ => Err(ClipboardError::UnsupportedFormat {
+                format: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// A single representation of clipboard content
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClipboardItem {
+    pub format: ClipboardFormat,
+    #[serde(with = "crate::protocol::attachment")]
+    pub data: Bytes,
+}
+
+impl ClipboardItem {
+    pub fn new(format: ClipboardFormat, data: impl Into<Bytes>) -> Self {
+        Self {
+            format,
+            data: data.into(),
+        }
+    }
+
+    /// Create a plain text item
+    pub fn text(content: &str) -> Self {
+        Self::new(ClipboardFormat::Text, content.to_string())
+    }
+
+    /// Create a file list item referencing absolute paths
+    pub fn file_list<P: AsRef<Path>>(paths: &[P]) -> Result<Self> {
+        let lines = paths
+            .iter()
+            .map(|path| {
+                Url::from_file_path(path.as_ref())
+                    .map(String::from)
+                    .map_err(|_| ClipboardError::WriteFailed {
+                        reason: format!("Not an absolute path: {}", path.as_ref().display()),
+                    })
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(Self::new(ClipboardFormat::FileList, lines.join("\r\n")))
+    }
+
+    /// Paths referenced by a file list item
+    pub fn paths(&self) -> Result<Vec<PathBuf>> {
+        if !self.format.is_file_list() {
+            return Err(ClipboardError::UnsupportedFormat {
+                format: format!("{} is not a file list", self.format),
+            }
+            .into());
+        }
+        Ok(parse_uri_list(self.as_str()?))
+    }
+
+    fn as_str(&self) -> Result<&str> {
+        std::str::from_utf8(&self.data).map_err(|e| {
+            ClipboardError::ReadFailed {
+                reason: format!("{} content is not valid UTF-8: {}", self.format, e),
+            }
+            .into()
+        })
+    }
+}
+
+/// Outcome of format negotiation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negotiation {
+    /// Format taken from the clipboard
+    pub source: ClipboardFormat,
+    /// Format delivered to the requester
+    pub target: ClipboardFormat,
+}
+
+/// Relative loss of converting between two formats, `None` if impossible
+fn conversion_cost(from: ClipboardFormat, to: ClipboardFormat) -> Option<u8> {
+    use ClipboardFormat::*;
+    match (from, to) {
+        _ if from == to => Some(0),
+        _ if from.is_image() || to.is_image() => None,
+        // File references can be shown as text but never recreated from it
+        (FileList, Text) => Some(2),
+        _ if from.is_file_list() || to.is_file_list() => None,
+        (Html, CfHtml) | (CfHtml, Html) => Some(LOSSLESS_COST),
+        (_, Text) => Some(2),
+        (Text, _) => Some(3),
+        _ => Some(4),
+    }
+}
+
+/// Highest conversion cost that preserves the content exactly
+const LOSSLESS_COST: u8 = 1;
+
+/// Pick the best format to serve a request
+///
+/// `accept` lists the requester's formats in order of preference. The most
+/// preferred format that can be served without loss wins; otherwise the
+/// cheapest lossy conversion is used.
+pub fn negotiate(available: &[ClipboardFormat], accept: &[ClipboardFormat]) -> Option<Negotiation> {
+    let candidates = accept.iter().filter_map(|&target| {
+        available
+            .iter()
+            .filter_map(|&source| {
+                conversion_cost(source, target).map(|cost| (cost, Negotiation { source, target }))
+            })
+            .min_by_key(|(cost, _)| *cost)
+    });
+
+    let mut best: Option<(u8, Negotiation)> = None;
+    for (cost, negotiation) in candidates {
+        if cost <= LOSSLESS_COST {
+            return Some(negotiation);
+        }
+        if best.is_none_or(|(best_cost, _)| cost < best_cost) {
+            best = Some((cost, negotiation));
+        }
+    }
+    best.map(|(_, negotiation)| negotiation)
+}
+
+/// Convert an item into the target format
+pub fn convert(item: &ClipboardItem, target: ClipboardFormat) -> Result<ClipboardItem> {
+    use ClipboardFormat::*;
+
+    let unsupported = || -> crate::error::YuhaError {
+        ClipboardError::UnsupportedFormat {
+            format: format!("{} -> {}", item.format, target),
+        }
+        .into()
+    };
+
+    let converted = match (item.format, target) {
+        (from, to) if from == to => return Ok(item.clone()),
+        (from, to) if conversion_cost(from, to).is_none() => return Err(unsupported()),
+        (CfHtml, Html) => cf_html_to_html(item.as_str()?),
+        (Html, CfHtml) => html_to_cf_html(item.as_str()?),
+        (Html, Text) => html_to_text(item.as_str()?),
+        (CfHtml, Text) => html_to_text(&cf_html_to_html(item.as_str()?)),
+        (Rtf, Text) => rtf_to_text(item.as_str()?),
+        (FileList, Text) => item
+            .paths()?
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        (Text, Html) => text_to_html(item.as_str()?),
+        (Text, CfHtml) => html_to_cf_html(&text_to_html(item.as_str()?)),
+        (Text, Rtf) => text_to_rtf(item.as_str()?),
+        // Rich-to-rich conversions without a direct mapping pivot through plain text
+        (_, to) => return convert(&convert(item, Text)?, to),
+    };
+
+    Ok(ClipboardItem::new(target, converted))
+}
+
+/// Pick and convert the best representation for a requester
+pub fn select(items: &[ClipboardItem], accept: &[ClipboardFormat]) -> Result<ClipboardItem> {
+    let available: Vec<_> = items.iter().map(|item| item.format).collect();
+    let negotiation =
+        negotiate(&available, accept).ok_or_else(|| ClipboardError::UnsupportedFormat {
+            format: format!(
+                "none of [{}] can be produced from [{}]",
+                join_formats(accept),
+                join_formats(&available)
+            ),
+        })?;
+
+    let source = items
+        .iter()
+        .find(|item| item.format == negotiation.source)
+        .expect("negotiated source is available");
+    convert(source, negotiation.target)
+}
+
+fn join_formats(formats: &[ClipboardFormat]) -> String {
+    formats
+        .iter()
+        .map(|format| format.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Parse a `text/uri-list`, skipping comments and non-file URIs
+fn parse_uri_list(uri_list: &str) -> Vec<PathBuf> {
+    uri_list
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| Url::parse(line).ok())
+        .filter_map(|url| url.to_file_path().ok())
+        .collect()
+}
+
+const CF_HTML_START_FRAGMENT: &str = "<!--StartFragment-->";
+const CF_HTML_END_FRAGMENT: &str = "<!--EndFragment-->";
+
+/// Extract the HTML fragment from a `CF_HTML` payload
+fn cf_html_to_html(cf_html: &str) -> String {
+    let offset = |key: &str| -> Option<usize> {
+        cf_html
+            .lines()
+            .find_map(|line| line.strip_prefix(key))
+            .and_then(|value| value.trim().parse().ok())
+    };
+
+    let range = match (offset("StartFragment:"), offset("EndFragment:")) {
+        (Some(start), Some(end)) => Some((start, end)),
+        _ => offset("StartHTML:").zip(offset("EndHTML:")),
+    };
+
+    range
+        .and_then(|(start, end)| cf_html.get(start..end))
+        .map(str::to_string)
+        // Malformed headers: fall back to everything after the header block
+        .unwrap_or_else(|| {
+            cf_html
+                .find('<')
+                .map(|start| cf_html[start..].to_string())
+                .unwrap_or_default()
+        })
+}
+
+/// Wrap HTML in a `CF_HTML` payload with byte offsets
+fn html_to_cf_html(html: &str) -> String {
+    const HEADER_LEN: usize = "Version:0.9\r\nStartHTML:0000000000\r\nEndHTML:0000000000\r\nStartFragment:0000000000\r\nEndFragment:0000000000\r\n".len();
+
+    let prefix = format!("<html><body>\r\n{}", CF_HTML_START_FRAGMENT);
+    let suffix = format!("{}\r\n</body></html>", CF_HTML_END_FRAGMENT);
+
+    let start_html = HEADER_LEN;
+    let start_fragment = start_html + prefix.len();
+    let end_fragment = start_fragment + html.len();
+    let end_html = end_fragment + suffix.len();
+
+    format!(
+        "Version:0.9\r\nStartHTML:{:010}\r\nEndHTML:{:010}\r\nStartFragment:{:010}\r\nEndFragment:{:010}\r\n{}{}{}",
+        start_html, end_html, start_fragment, end_fragment, prefix, html, suffix
+    )
+}
+
+/// Render HTML as plain text
+fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            rest = "";
+            break;
+        };
+        let tag = rest[start + 1..start + end].trim().to_ascii_lowercase();
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("");
+        rest = &rest[start + end + 1..];
+
+        match name {
+            "br" => text.push('\n'),
+            "p" | "div" | "li" | "tr" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6"
+                if tag.starts_with('/') =>
+            {
+                text.push('\n')
+            }
+            "script" | "style" if !tag.starts_with('/') => {
+                let close = format!("</{}", name);
+                rest = rest
+                    .to_ascii_lowercase()
+                    .find(&close)
+                    .map(|index| &rest[index..])
+                    .unwrap_or("");
+            }
+            _ => {}
+        }
+    }
+    text.push_str(rest);
+
+    decode_entities(&text).trim_end().to_string()
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest[1..].find(';').map(|end| &rest[1..end + 1]);
+        let replacement = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        });
+
+        match (entity, replacement) {
+            (Some(entity), Some(c)) => {
+                decoded.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Render plain text as HTML
+fn text_to_html(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\n' => html.push_str("<br>"),
+            _ => html.push(c),
+        }
+    }
+    html
+}
+
+/// Extract plain text from RTF
+fn rtf_to_text(rtf: &str) -> String {
+    // Destinations whose content is metadata rather than document text
+    const SKIPPED_DESTINATIONS: &[&str] = &[
+        "fonttbl",
+        "colortbl",
+        "stylesheet",
+        "info",
+        "pict",
+        "header",
+        "footer",
+    ];
+
+    let mut text = String::new();
+    let mut chars = rtf.chars().peekable();
+    // Group depth at which a skipped destination started
+    let mut skip_from: Option<usize> = None;
+    let mut depth = 0usize;
+    // Characters to drop after a \uN escape (its ANSI fallback)
+    let mut fallback = 0usize;
+    // Code page of \'hh escapes, declared by \ansicpgN
+    let mut encoding = encoding_rs::WINDOWS_1252;
+    // Bytes of consecutive \'hh escapes, decoded together so characters
+    // of double-byte code pages stay whole
+    let mut escaped = Vec::new();
+
+    while let Some(c) = chars.next() {
+        let continues_escapes = c == '\\' && chars.peek() == Some(&'\'');
+        if !escaped.is_empty() && !continues_escapes {
+            text.push_str(&encoding.decode_without_bom_handling(&escaped).0);
+            escaped.clear();
+        }
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                if skip_from == Some(depth) {
+                    skip_from = None;
+                }
+                depth = depth.saturating_sub(1);
+            }
+            '\\' => {
+                let Some(&next) = chars.peek() else { break };
+                if !next.is_ascii_alphabetic() {
+                    chars.next();
+                    match next {
+                        '\'' => {
+                            let hex: String = chars.by_ref().take(2).collect();
+                            if skip_from.is_none()
+                                && let Ok(byte) = u8::from_str_radix(&hex, 16)
+                            {
+                                if fallback > 0 {
+                                    fallback -= 1;
+                                } else {
+                                    escaped.push(byte);
+                                }
+                            }
+                        }
+                        '*' => skip_from = skip_from.or(Some(depth)),
+                        '\\' | '{' | '}' if skip_from.is_none() => text.push(next),
+                        '~' if skip_from.is_none() => text.push(' '),
+                        _ => {}
+                    }
+                    continue;
+                }
+
+                let mut word = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphabetic()) {
+                    word.push(c);
+                    chars.next();
+                }
+                let mut param = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '-') {
+                    param.push(c);
+                    chars.next();
+                }
+                if chars.peek() == Some(&' ') {
+                    chars.next();
+                }
+
+                if SKIPPED_DESTINATIONS.contains(&word.as_str()) {
+                    skip_from = skip_from.or(Some(depth));
+                }
+                if skip_from.is_some() {
+                    continue;
+                }
+
+                match word.as_str() {
+                    "ansicpg" => {
+                        if let Some(declared) = param.parse().ok().and_then(codepage::to_encoding) {
+                            encoding = declared;
+                        }
+                    }
+                    "par" | "line" => text.push('\n'),
+                    "tab" => text.push('\t'),
+                    "u" => {
+                        if let Ok(code) = param.parse::<i32>() {
+                            // RTF encodes code points above i16::MAX as negative numbers
+                            let code = if code < 0 { code + 65536 } else { code };
+                            text.extend(char::from_u32(code as u32));
+                            fallback = 1;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            '\r' | '\n' => {}
+            _ if skip_from.is_some() => {}
+            _ if fallback > 0 => fallback -= 1,
+            _ => text.push(c),
+        }
+    }
+
+    text.push_str(&encoding.decode_without_bom_handling(&escaped).0);
+
+    text.trim_end().to_string()
+}
+
+/// Render plain text as RTF
+fn text_to_rtf(text: &str) -> String {
+    let mut rtf = String::from("{\\rtf1\\ansi\\deff0 ");
+    for c in text.chars() {
+        match c {
+            '\\' | '{' | '}' => {
+                rtf.push('\\');
+                rtf.push(c);
+            }
+            '\n' => rtf.push_str("\\par\n"),
+            '\t' => rtf.push_str("\\tab "),
+            c if c.is_ascii() => rtf.push(c),
+            c => {
+                let mut units = [0u16; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    rtf.push_str(&format!("\\u{}?", *unit as i16));
+                }
+            }
+        }
+    }
+    rtf.push('}');
+    rtf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ClipboardFormat::*;
+
+    #[test]
+    fn test_parse_platform_names() {
+        assert_eq!(
+            "text/plain;charset=utf-8"
+                .parse::<ClipboardFormat>()
+                .unwrap(),
+            Text
+        );
+        assert_eq!("CF_UNICODETEXT".parse::<ClipboardFormat>().unwrap(), Text);
+        assert_eq!("HTML Format".parse::<ClipboardFormat>().unwrap(), CfHtml);
+        assert_eq!("public.html".parse::<ClipboardFormat>().unwrap(), Html);
+        assert_eq!("application/rtf".parse::<ClipboardFormat>().unwrap(), Rtf);
+        assert_eq!("image/png".parse::<ClipboardFormat>().unwrap(), Png);
+        assert!("application/x-unknown".parse::<ClipboardFormat>().is_err());
+    }
+
+    #[test]
+    fn test_negotiate_prefers_lossless_format() {
+        let negotiation = negotiate(&[Text, Html], &[Rtf, Html, Text]).unwrap();
+        assert_eq!(negotiation.source, Html);
+        assert_eq!(negotiation.target, Html);
+    }
+
+    #[test]
+    fn test_negotiate_picks_cheapest_conversion() {
+        // A Windows requester gets CF_HTML converted from HTML rather than degraded text
+        let negotiation = negotiate(&[Text, Html], &[CfHtml, Text]).unwrap();
+        assert_eq!(negotiation.source, Html);
+        assert_eq!(negotiation.target, CfHtml);
+
+        let negotiation = negotiate(&[Rtf], &[Html, Text]).unwrap();
+        assert_eq!(negotiation.source, Rtf);
+        assert_eq!(negotiation.target, Text);
+    }
+
+    #[test]
+    fn test_negotiate_images_do_not_convert() {
+        assert!(negotiate(&[Png], &[Text, Html]).is_none());
+        assert!(negotiate(&[Text], &[Png]).is_none());
+        assert_eq!(negotiate(&[Png, Text], &[Png]).unwrap().source, Png);
+    }
+
+    #[test]
+    fn test_cf_html_roundtrip() {
+        let html = "<b>bold</b> &amp; <i>ünïcode</i>";
+        let cf_html = convert(&ClipboardItem::new(Html, html), CfHtml).unwrap();
+        let header = std::str::from_utf8(&cf_html.data).unwrap();
+        assert!(header.starts_with("Version:0.9\r\nStartHTML:"));
+
+        let back = convert(&cf_html, Html).unwrap();
+        assert_eq!(back.data, Bytes::from(html));
+    }
+
+    #[test]
+    fn test_cf_html_from_windows() {
+        let fragment = "<p>Hello <b>world</b></p>";
+        let body = format!(
+            "<html><body>\r\n<!--StartFragment-->{fragment}<!--EndFragment-->\r\n</body></html>"
+        );
+        let header_len = "Version:0.9\r\nStartHTML:0000000000\r\nEndHTML:0000000000\r\nStartFragment:0000000000\r\nEndFragment:0000000000\r\n".len();
+        let start_fragment = header_len + body.find(fragment).unwrap();
+        let cf_html = format!(
+            "Version:0.9\r\nStartHTML:{:010}\r\nEndHTML:{:010}\r\nStartFragment:{:010}\r\nEndFragment:{:010}\r\n{}",
+            header_len,
+            header_len + body.len(),
+            start_fragment,
+            start_fragment + fragment.len(),
+            body
+        );
+
+        let text = convert(&ClipboardItem::new(CfHtml, cf_html), Text).unwrap();
+        assert_eq!(text.data, Bytes::from("Hello world"));
+    }
+
+    #[test]
+    fn test_html_to_text() {
+        let html = "<p>a &lt; b</p><p>line<br>break</p><script>ignored()</script>&#x41;";
+        let text = convert(&ClipboardItem::new(Html, html), Text).unwrap();
+        assert_eq!(text.data, Bytes::from("a < b\nline\nbreak\nA"));
+    }
+
+    #[test]
+    fn test_text_to_html_escapes() {
+        let html = convert(&ClipboardItem::text("<a> & b\nc"), Html).unwrap();
+        assert_eq!(html.data, Bytes::from("&lt;a&gt; &amp; b<br>c"));
+    }
+
+    #[test]
+    fn test_rtf_roundtrip() {
+        let text = "Braces {and} back\\slash\nnext\tline é";
+        let rtf = convert(&ClipboardItem::text(text), Rtf).unwrap();
+        let back = convert(&rtf, Text).unwrap();
+        assert_eq!(back.data, Bytes::from(text));
+    }
+
+    #[test]
+    fn test_rtf_skips_metadata() {
+        let rtf = r"{\rtf1\ansi{\fonttbl{\f0 Arial;}}{\colortbl;\red0\green0\blue0;}{\*\generator Word;}\f0 Hello\par World\'21}";
+        let text = convert(&ClipboardItem::new(Rtf, rtf), Text).unwrap();
+        assert_eq!(text.data, Bytes::from("Hello\nWorld!"));
+    }
+
+    #[test]
+    fn test_rtf_escapes_use_declared_code_page() {
+        // Without a declaration escapes are Windows-1252, where 0x80 is the euro sign
+        let rtf = r"{\rtf1\ansi caf\'e9 \'80}";
+        let text = convert(&ClipboardItem::new(Rtf, rtf), Text).unwrap();
+        assert_eq!(text.data, Bytes::from("café €"));
+
+        let rtf = r"{\rtf1\ansi\ansicpg1251 \'cf\'f0\'e8\'e2\'e5\'f2}";
+        let text = convert(&ClipboardItem::new(Rtf, rtf), Text).unwrap();
+        assert_eq!(text.data, Bytes::from("Привет"));
+
+        // Double-byte characters span two escapes
+        let rtf = r"{\rtf1\ansi\ansicpg932 \'93\'fa\'96\'7b}";
+        let text = convert(&ClipboardItem::new(Rtf, rtf), Text).unwrap();
+        assert_eq!(text.data, Bytes::from("日本"));
+    }
+
+    #[test]
+    fn test_rtf_to_html_pivots_through_text() {
+        let html = convert(&ClipboardItem::new(Rtf, r"{\rtf1 a\par b}"), Html).unwrap();
+        assert_eq!(html.data, Bytes::from("a<br>b"));
+    }
+
+    #[test]
+    fn test_file_list_roundtrip() {
+        let paths = [
+            PathBuf::from("/home/user/report 1.pdf"),
+            PathBuf::from("/tmp/ä"),
+        ];
+        let item = ClipboardItem::file_list(&paths).unwrap();
+        assert_eq!(item.format, FileList);
+        assert!(
+            std::str::from_utf8(&item.data)
+                .unwrap()
+                .contains("file:///home/user/report%201.pdf")
+        );
+        assert_eq!(item.paths().unwrap(), paths);
+
+        assert!(ClipboardItem::file_list(&["relative.txt"]).is_err());
+        assert!(ClipboardItem::text("x").paths().is_err());
+    }
+
+    #[test]
+    fn test_file_list_parsing_and_conversion() {
+        let uri_list =
+            "# copied by nautilus\r\nfile:///a/b.txt\r\nhttps://example.com\r\nfile:///c\r\n";
+        let item = ClipboardItem::new(FileList, uri_list);
+        assert_eq!(
+            item.paths().unwrap(),
+            vec![PathBuf::from("/a/b.txt"), PathBuf::from("/c")]
+        );
+
+        let text = convert(&item, Text).unwrap();
+        assert_eq!(text.data, Bytes::from("/a/b.txt\n/c"));
+
+        assert!(negotiate(&[Text], &[FileList]).is_none());
+        assert!(negotiate(&[FileList], &[Html]).is_none());
+        assert_eq!("CF_HDROP".parse::<ClipboardFormat>().unwrap(), FileList);
+    }
+
+    #[test]
+    fn test_item_serialization() {
+        let item = ClipboardItem::new(CfHtml, "<b>x</b>");
+        let json = serde_json::to_string(&item).unwrap();
+        assert!(json.contains("\"cf_html\""));
+        assert_eq!(serde_json::from_str::<ClipboardItem>(&json).unwrap(), item);
+    }
+
+    #[test]
+    fn test_select_reports_unsupported() {
+        let items = vec![ClipboardItem::new(Png, vec![0x89, b'P', b'N', b'G'])];
+        let err = select(&items, &[Text]).unwrap_err();
+        assert!(err.to_string().contains("image/png"));
+    }
+}
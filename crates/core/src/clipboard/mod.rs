@@ -0,0 +1,222 @@
+use crate::error::{ClipboardError, Result};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tracing::debug;
+
+pub mod dedup;
+pub mod format;
+
+pub use dedup::ClipboardDedup;
+pub use format::{ClipboardFormat, ClipboardItem};
+
+/// Every representation of some clipboard content
+#[derive(Debug, Default)]
+pub struct ClipboardStore {
+    items: RwLock<Vec<ClipboardItem>>,
+}
+
+/// The clipboard of this host
+static SYSTEM: ClipboardStore = ClipboardStore::new();
+
+/// The clipboard of this host, which the free functions of this module use
+pub fn system() -> &'static ClipboardStore {
+    &SYSTEM
+}
+
+impl ClipboardStore {
+    /// An empty clipboard
+    pub const fn new() -> Self {
+        Self {
+            items: RwLock::new(Vec::new()),
+        }
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, Vec<ClipboardItem>>> {
+        self.items.read().map_err(|e| {
+            ClipboardError::LockFailed {
+                reason: format!("Read lock poisoned: {}", e),
+            }
+            .into()
+        })
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, Vec<ClipboardItem>>> {
+        self.items.write().map_err(|e| {
+            ClipboardError::LockFailed {
+                reason: format!("Write lock poisoned: {}", e),
+            }
+            .into()
+        })
+    }
+
+    /// The content as text
+    ///
+    /// Returns `None` if the clipboard is empty or holds nothing that
+    /// converts to text, e.g. only an image (see [`item`](Self::item)).
+    pub fn text(&self) -> Result<Option<String>> {
+        let items = self.read()?;
+        let available: Vec<_> = items.iter().map(|item| item.format).collect();
+        if format::negotiate(&available, &[ClipboardFormat::Text]).is_none() {
+            return Ok(None);
+        }
+        let item = format::select(&items, &[ClipboardFormat::Text])?;
+        String::from_utf8(item.data.to_vec())
+            .map(Some)
+            .map_err(|e| {
+                ClipboardError::ReadFailed {
+                    reason: format!("Clipboard text is not valid UTF-8: {}", e),
+                }
+                .into()
+            })
+    }
+
+    /// The content in the best of the accepted formats
+    ///
+    /// Returns `None` if the clipboard is empty and an error if the content
+    /// cannot be delivered in any accepted format.
+    pub fn item(&self, accept: &[ClipboardFormat]) -> Result<Option<ClipboardItem>> {
+        let items = self.read()?;
+        if items.is_empty() {
+            return Ok(None);
+        }
+        format::select(&items, accept).map(Some)
+    }
+
+    /// Every representation of the content
+    pub fn items(&self) -> Result<Vec<ClipboardItem>> {
+        Ok(self.read()?.clone())
+    }
+
+    /// The formats the content is held in
+    pub fn formats(&self) -> Result<Vec<ClipboardFormat>> {
+        Ok(self.read()?.iter().map(|item| item.format).collect())
+    }
+
+    /// Replace the content with text, clearing it if `content` is empty
+    pub fn set_text(&self, content: &str) -> Result<()> {
+        let items = if content.is_empty() {
+            Vec::new()
+        } else {
+            vec![ClipboardItem::text(content)]
+        };
+        self.set_items(items)
+    }
+
+    /// Replace the content with one or more representations
+    pub fn set_items(&self, items: Vec<ClipboardItem>) -> Result<()> {
+        *self.write()? = items;
+        Ok(())
+    }
+}
+
+/// Get clipboard content asynchronously
+pub async fn get() -> Result<Option<String>> {
+    get_clipboard()
+}
+
+/// Get clipboard content synchronously
+///
+/// Returns `None` if the clipboard is empty or holds no text, e.g. only an
+/// image (see [`get_clipboard_item`]).
+pub fn get_clipboard() -> Result<Option<String>> {
+    debug!("Attempting to read clipboard");
+    let content = SYSTEM.text()?;
+    debug!(
+        "Successfully read clipboard content (length: {})",
+        content.as_ref().map_or(0, String::len)
+    );
+    Ok(content)
+}
+
+/// Get the clipboard content in the best of the accepted formats
+///
+/// Returns `None` if the clipboard is empty and an error if the content
+/// cannot be delivered in any accepted format.
+pub fn get_clipboard_item(accept: &[ClipboardFormat]) -> Result<Option<ClipboardItem>> {
+    SYSTEM.item(accept)
+}
+
+/// Get the formats the current clipboard content is held in
+pub fn available_formats() -> Result<Vec<ClipboardFormat>> {
+    SYSTEM.formats()
+}
+
+/// Set clipboard content
+pub fn set_clipboard(content: &str) -> Result<()> {
+    debug!(
+        "Attempting to set clipboard content (length: {})",
+        content.len()
+    );
+    SYSTEM.set_text(content)
+}
+
+/// Replace the clipboard content with one or more representations
+pub fn set_clipboard_items(items: Vec<ClipboardItem>) -> Result<()> {
+    SYSTEM.set_items(items)?;
+    debug!("Successfully set clipboard content");
+    Ok(())
+}
+
+/// Clear clipboard content
+pub fn clear_clipboard() -> Result<()> {
+    debug!("Clearing clipboard content");
+    set_clipboard("")
+}
+
+/// Check if clipboard is empty
+pub fn is_clipboard_empty() -> Result<bool> {
+    Ok(SYSTEM.formats()?.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clipboard_operations() {
+        // Test setting and getting content
+        let test_content = "Hello, clipboard!";
+        assert!(set_clipboard(test_content).is_ok());
+
+        let retrieved = get_clipboard().unwrap();
+        assert_eq!(retrieved.as_deref(), Some(test_content));
+
+        // Test clearing
+        assert!(clear_clipboard().is_ok());
+        assert!(is_clipboard_empty().unwrap());
+    }
+
+    #[test]
+    fn test_stores_are_separate() {
+        let store = ClipboardStore::new();
+        store.set_text("workspace").unwrap();
+        assert_eq!(store.text().unwrap().as_deref(), Some("workspace"));
+        assert_eq!(store.formats().unwrap(), vec![ClipboardFormat::Text]);
+        assert_ne!(system().text().unwrap().as_deref(), Some("workspace"));
+
+        store.set_text("").unwrap();
+        assert_eq!(store.item(&[ClipboardFormat::Text]).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_async_clipboard() {
+        let test_content = "Async test";
+        assert!(set_clipboard(test_content).is_ok());
+
+        let retrieved = get().await.unwrap();
+        assert_eq!(retrieved.as_deref(), Some(test_content));
+    }
+
+    #[test]
+    fn test_image_only_has_no_text() {
+        let store = ClipboardStore::new();
+        let png = ClipboardItem::new(ClipboardFormat::Png, vec![0x89, b'P', b'N', b'G']);
+        store.set_items(vec![png.clone()]).unwrap();
+        assert_eq!(store.text().unwrap(), None);
+        assert_eq!(store.item(&[ClipboardFormat::Png]).unwrap(), Some(png));
+
+        store
+            .set_items(vec![ClipboardItem::new(ClipboardFormat::Html, "<b>x</b>")])
+            .unwrap();
+        assert_eq!(store.text().unwrap().as_deref(), Some("x"));
+    }
+}
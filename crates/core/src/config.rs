@@ -1,8 +1,13 @@
 //! Configuration management for yuha
 
+use crate::child_env::ChildEnv;
 use crate::error::{Result, YuhaError};
 use crate::logging::LoggingConfig;
+use crate::message_channel::{KeepaliveConfig, ReadBufferConfig};
 use crate::metrics::MetricsConfig;
+use crate::open::OpenHandlers;
+use crate::secrets::{self, DecryptedValues, SecretsConfig};
+use crate::transport::{SocketOptions, SshAlgorithms, TcpConfig};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
@@ -25,6 +30,12 @@ pub struct YuhaConfig {
     /// Connection profiles
     #[serde(default)]
     pub profiles: HashMap<String, ConnectionProfile>,
+    /// Identities decrypting encrypted values
+    #[serde(default)]
+    pub secrets: SecretsConfig,
+    /// Values decrypted at load time
+    #[serde(skip)]
+    decrypted: DecryptedValues,
 }
 
 /// Client-side configuration
@@ -43,6 +54,28 @@ pub struct ClientConfig {
     pub auto_upload_binary: bool,
     /// Working directory for remote execution
     pub working_dir: Option<PathBuf>,
+    /// Pasting copied files larger than this in total asks for confirmation
+    #[serde(default = "default_paste_confirm_bytes")]
+    pub paste_confirm_bytes: u64,
+    /// Applications opening remote files locally
+    #[serde(default)]
+    pub open_handlers: OpenHandlers,
+    /// BLAKE3 hash the remote binary must report; defaults to the hash of
+    /// the local binary a transport runs or uploads
+    #[serde(default)]
+    pub remote_binary_hash: Option<String>,
+    /// Connect even when the remote binary does not match its expected hash
+    #[serde(default)]
+    pub allow_modified_remote: bool,
+    /// Fail instead of prompting for passwords or confirmations, e.g. in CI
+    #[serde(default)]
+    pub non_interactive: bool,
+    /// Workspace of the remote to select on connect
+    #[serde(default)]
+    pub workspace: Option<String>,
+    /// Environment of local remote processes (see [`crate::child_env`])
+    #[serde(default)]
+    pub child_env: ChildEnv,
 }
 
 /// Remote server configuration
@@ -57,11 +90,12 @@ pub struct RemoteConfig {
     /// Polling interval in milliseconds
     #[serde(default = "default_polling_interval")]
     pub polling_interval: u64,
-    /// Buffer size for message channels
-    #[serde(default = "default_buffer_size")]
-    pub buffer_size: usize,
     /// Temporary file paths
     pub temp_files: TempFileConfig,
+    /// Removed `buffer_size` key, read into
+    /// `network.read_buffer.initial_capacity` so older files keep working
+    #[serde(default, skip_serializing)]
+    buffer_size: Option<usize>,
 }
 
 /// Network configuration
@@ -77,6 +111,15 @@ pub struct NetworkConfig {
     pub port_forward: PortForwardConfig,
     /// Socket timeouts
     pub timeouts: TimeoutConfig,
+    /// Read buffer sizing for message channels
+    #[serde(default)]
+    pub read_buffer: ReadBufferConfig,
+    /// Dead-peer detection for message channels
+    #[serde(default)]
+    pub keepalive: KeepaliveConfig,
+    /// Tuning of the TCP sockets of SSH and TCP connections
+    #[serde(default)]
+    pub socket: SocketOptions,
 }
 
 /// Port forwarding configuration
@@ -130,6 +173,8 @@ pub struct ConnectionProfile {
     pub ssh: Option<SshConfig>,
     /// Local execution settings
     pub local: Option<LocalConfig>,
+    /// TCP connection to a running server, with its knocks if any
+    pub tcp: Option<TcpConfig>,
     /// Environment variables
     #[serde(default)]
     pub env_vars: HashMap<String, String>,
@@ -152,9 +197,24 @@ pub struct SshConfig {
     pub password: Option<String>,
     /// Private key file path
     pub key_path: Option<PathBuf>,
+    /// OpenSSH certificate of the private key, `<key_path>-cert.pub` when unset
+    pub cert_path: Option<PathBuf>,
+    /// Principal the certificate must be valid for, `username` when unset
+    pub cert_principal: Option<String>,
+    /// PKCS#11 provider library of token-backed keys, `key_path` then the
+    /// public key of the one to use
+    pub pkcs11: Option<String>,
     /// Auto-upload binary
     #[serde(default)]
     pub auto_upload_binary: bool,
+    /// Algorithm preferences and compression, e.g. cheaper ciphers for
+    /// CPU-constrained remotes or an approved set for compliance
+    #[serde(default)]
+    pub algorithms: SshAlgorithms,
+    /// Tuning of the connection to the SSH server, overriding the network
+    /// section's
+    #[serde(default)]
+    pub socket: Option<SocketOptions>,
 }
 
 /// Local execution configuration
@@ -176,6 +236,9 @@ fn default_connection_timeout() -> u64 {
 fn default_max_retries() -> u32 {
     3
 }
+fn default_paste_confirm_bytes() -> u64 {
+    10 * 1024 * 1024
+}
 fn default_tcp_port() -> u16 {
     9999
 }
@@ -227,6 +290,13 @@ impl Default for ClientConfig {
             default_binary_path: None,
             auto_upload_binary: false,
             working_dir: None,
+            paste_confirm_bytes: default_paste_confirm_bytes(),
+            open_handlers: OpenHandlers::default(),
+            remote_binary_hash: None,
+            allow_modified_remote: false,
+            non_interactive: false,
+            workspace: None,
+            child_env: ChildEnv::default(),
         }
     }
 }
@@ -237,8 +307,8 @@ impl Default for RemoteConfig {
             default_port: default_tcp_port(),
             polling_timeout: default_polling_timeout(),
             polling_interval: default_polling_interval(),
-            buffer_size: default_buffer_size(),
             temp_files: TempFileConfig::default(),
+            buffer_size: None,
         }
     }
 }
@@ -250,6 +320,9 @@ impl Default for NetworkConfig {
             bind_address: default_bind_address(),
             port_forward: PortForwardConfig::default(),
             timeouts: TimeoutConfig::default(),
+            read_buffer: ReadBufferConfig::default(),
+            keepalive: KeepaliveConfig::default(),
+            socket: SocketOptions::default(),
         }
     }
 }
@@ -286,26 +359,42 @@ impl Default for TempFileConfig {
 
 impl YuhaConfig {
     /// Load configuration from file
+    ///
+    /// Files named by a top-level `include` (a path or list of paths,
+    /// relative to the including file) are merged beneath the file's own
+    /// settings, and a profile with `extends = "base"` inherits every
+    /// setting of profile `base` it does not set itself. Encrypted values
+    /// are decrypted (see [`crate::secrets`]) and the result is validated.
     pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref();
         debug!("Loading configuration from: {}", path.display());
 
-        let contents = std::fs::read_to_string(path)
-            .map_err(|e| YuhaError::config(format!("Failed to read config file: {}", e)))?;
+        let mut table = load_table(path, &mut Vec::new())?;
+        resolve_profiles(&mut table)?;
+        let decrypted =
+            secrets::decrypt_values(&mut table, path.parent().unwrap_or(Path::new("")))?;
 
-        let config: YuhaConfig = toml::from_str(&contents)
+        let mut config: YuhaConfig = toml::Value::Table(table)
+            .try_into()
             .map_err(|e| YuhaError::config(format!("Failed to parse config file: {}", e)))?;
+        config.decrypted = decrypted;
+        config.apply_removed_keys();
+        config.validate()?;
 
         info!("Configuration loaded successfully from: {}", path.display());
         Ok(config)
     }
 
     /// Save configuration to file
+    ///
+    /// Values decrypted at load time are written back encrypted.
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
         let path = path.as_ref();
         debug!("Saving configuration to: {}", path.display());
 
-        let contents = toml::to_string_pretty(self)
+        let mut table = self.to_table()?;
+        self.decrypted.seal(&mut table)?;
+        let contents = toml::to_string_pretty(&table)
             .map_err(|e| YuhaError::config(format!("Failed to serialize config: {}", e)))?;
 
         // Create parent directory if it doesn't exist
@@ -322,6 +411,20 @@ impl YuhaConfig {
         Ok(())
     }
 
+    /// The configuration as TOML, with the values decrypted at load time
+    /// redacted
+    pub fn to_redacted_string(&self) -> Result<String> {
+        let mut table = self.to_table()?;
+        self.decrypted.redact(&mut table);
+        toml::to_string_pretty(&table)
+            .map_err(|e| YuhaError::config(format!("Failed to serialize config: {}", e)))
+    }
+
+    fn to_table(&self) -> Result<toml::Table> {
+        toml::Table::try_from(self)
+            .map_err(|e| YuhaError::config(format!("Failed to serialize config: {}", e)))
+    }
+
     /// Load configuration with fallback paths
     pub fn load_with_fallback() -> Self {
         let mut config_paths = vec![
@@ -419,9 +522,23 @@ impl YuhaConfig {
             self.client.default_binary_path = Some(PathBuf::from(binary_path));
         }
 
+        if let Ok(value) = std::env::var("YUHA_NON_INTERACTIVE") {
+            self.client.non_interactive = matches!(value.as_str(), "1" | "true");
+        }
+
         debug!("Configuration merged with environment variables");
     }
 
+    /// Move the values of removed keys to the settings replacing them
+    fn apply_removed_keys(&mut self) {
+        if let Some(buffer_size) = self.remote.buffer_size.take() {
+            warn!("remote.buffer_size is deprecated, use network.read_buffer.initial_capacity");
+            let read_buffer = &mut self.network.read_buffer;
+            read_buffer.initial_capacity = buffer_size;
+            read_buffer.max_capacity = read_buffer.max_capacity.max(buffer_size);
+        }
+    }
+
     /// Validate configuration
     pub fn validate(&self) -> Result<()> {
         // Validate ports are in valid range
@@ -445,8 +562,28 @@ impl YuhaConfig {
         }
 
         // Validate buffer sizes
-        if self.remote.buffer_size == 0 {
-            return Err(YuhaError::config("Buffer size must be greater than 0"));
+        let read_buffer = &self.network.read_buffer;
+        if read_buffer.initial_capacity == 0 {
+            return Err(YuhaError::config("Read buffer size must be greater than 0"));
+        }
+        if read_buffer.max_capacity < read_buffer.initial_capacity {
+            return Err(YuhaError::config(
+                "Maximum read buffer size must not be below the initial size",
+            ));
+        }
+
+        let keepalive = &self.network.keepalive;
+        if keepalive.interval_ms > 0 && keepalive.timeout_ms == 0 {
+            return Err(YuhaError::config(
+                "Keepalive timeout must be greater than 0 when keepalive is enabled",
+            ));
+        }
+
+        let socket = &self.network.socket;
+        if socket.send_buffer == Some(0) || socket.recv_buffer == Some(0) {
+            return Err(YuhaError::config(
+                "Socket buffer sizes must be greater than 0",
+            ));
         }
 
         // Validate log level (LogLevel enum is already validated by its type)
@@ -456,6 +593,175 @@ impl YuhaConfig {
     }
 }
 
+/// Key of a config file naming the files merged beneath it
+const INCLUDE_KEY: &str = "include";
+
+/// Key of a profile naming the profile it inherits from
+const EXTENDS_KEY: &str = "extends";
+
+/// Settings of the config file at `path` merged over its includes
+///
+/// `stack` holds the files whose includes are being loaded.
+fn load_table(path: &Path, stack: &mut Vec<PathBuf>) -> Result<toml::Table> {
+    let canonical = path.canonicalize().map_err(|e| {
+        YuhaError::config(format!(
+            "Failed to read config file {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    if stack.contains(&canonical) {
+        let chain: Vec<_> = stack
+            .iter()
+            .chain([&canonical])
+            .map(|path| path.display().to_string())
+            .collect();
+        return Err(YuhaError::config(format!(
+            "Config include cycle: {}",
+            chain.join(" -> ")
+        )));
+    }
+
+    let contents = std::fs::read_to_string(&canonical)
+        .map_err(|e| YuhaError::config(format!("Failed to read config file: {}", e)))?;
+    let mut table: toml::Table = toml::from_str(&contents).map_err(|e| {
+        YuhaError::config(format!(
+            "Failed to parse config file {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    let includes = match table.remove(INCLUDE_KEY) {
+        None => Vec::new(),
+        Some(toml::Value::String(include)) => vec![include],
+        Some(toml::Value::Array(includes)) => includes
+            .into_iter()
+            .map(|include| match include {
+                toml::Value::String(include) => Ok(include),
+                _ => Err(YuhaError::config(format!(
+                    "Includes of {} must be paths",
+                    path.display()
+                ))),
+            })
+            .collect::<Result<_>>()?,
+        Some(_) => {
+            return Err(YuhaError::config(format!(
+                "Includes of {} must be paths",
+                path.display()
+            )));
+        }
+    };
+
+    let dir = canonical
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    stack.push(canonical);
+    let mut merged = toml::Table::new();
+    for include in includes {
+        debug!("Including configuration from: {}", include);
+        merge_tables(&mut merged, load_table(&dir.join(include), stack)?);
+    }
+    stack.pop();
+
+    merge_tables(&mut merged, table);
+    Ok(merged)
+}
+
+/// Merge `overlay` into `base`, tables key by key and other values whole
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_tables(base, overlay)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Replace each profile extending another by its merge over the base
+fn resolve_profiles(table: &mut toml::Table) -> Result<()> {
+    let Some(toml::Value::Table(profiles)) = table.get_mut("profiles") else {
+        return Ok(());
+    };
+
+    let mut resolved = toml::Table::new();
+    for (name, profile) in profiles.iter() {
+        if profile.is_table() {
+            resolve_profile(profiles, name, &mut resolved, &mut Vec::new())?;
+        } else {
+            // Left for deserialization to reject
+            resolved.insert(name.clone(), profile.clone());
+        }
+    }
+    *profiles = resolved;
+    Ok(())
+}
+
+/// Settings of profile `name` including inherited ones
+///
+/// `stack` holds the profiles whose bases are being resolved.
+fn resolve_profile(
+    profiles: &toml::Table,
+    name: &str,
+    resolved: &mut toml::Table,
+    stack: &mut Vec<String>,
+) -> Result<toml::Table> {
+    if let Some(toml::Value::Table(profile)) = resolved.get(name) {
+        return Ok(profile.clone());
+    }
+    if stack.iter().any(|extending| extending == name) {
+        let chain: Vec<_> = stack.iter().map(String::as_str).chain([name]).collect();
+        return Err(YuhaError::config(format!(
+            "Profile inheritance cycle: {}",
+            chain.join(" -> ")
+        )));
+    }
+    let Some(toml::Value::Table(profile)) = profiles.get(name) else {
+        return Err(YuhaError::config(format!(
+            "Profile {} is not a table",
+            name
+        )));
+    };
+
+    let mut profile = profile.clone();
+    let profile = match profile.remove(EXTENDS_KEY) {
+        None => profile,
+        Some(toml::Value::String(base)) => {
+            if !profiles.contains_key(&base) {
+                return Err(YuhaError::config(format!(
+                    "Profile {} extends unknown profile {}",
+                    name, base
+                )));
+            }
+            stack.push(name.to_string());
+            let mut merged = resolve_profile(profiles, &base, resolved, stack)?;
+            stack.pop();
+
+            // The name is the only setting not inherited
+            merged.remove("name");
+            merge_tables(&mut merged, profile);
+            merged
+                .entry("name")
+                .or_insert_with(|| toml::Value::String(name.to_string()));
+            merged
+        }
+        Some(_) => {
+            return Err(YuhaError::config(format!(
+                "Profile {} must extend a profile name",
+                name
+            )));
+        }
+    };
+
+    resolved.insert(name.to_string(), toml::Value::Table(profile.clone()));
+    Ok(profile)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -467,6 +773,7 @@ mod tests {
         assert_eq!(config.network.ssh_port, 22);
         assert_eq!(config.remote.default_port, 9999);
         assert_eq!(config.client.connection_timeout, 30);
+        assert_eq!(config.client.paste_confirm_bytes, 10 * 1024 * 1024);
     }
 
     #[test]
@@ -505,6 +812,137 @@ mod tests {
         config.network.ssh_port = 22;
         config.client.connection_timeout = 0;
         assert!(config.validate().is_err());
+
+        // Test read buffer cap below its initial size
+        config.client.connection_timeout = 30;
+        config.network.read_buffer.max_capacity = config.network.read_buffer.initial_capacity - 1;
+        assert!(config.validate().is_err());
+
+        // Test keepalive that gives up without waiting for an answer
+        config.network.read_buffer = ReadBufferConfig::default();
+        config.network.keepalive.timeout_ms = 0;
+        assert!(config.validate().is_err());
+
+        // Test empty socket buffer
+        config.network.keepalive = KeepaliveConfig::default();
+        config.network.socket.recv_buffer = Some(0);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_removed_buffer_size_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let mut config = YuhaConfig::default();
+        config.save_to_file(&path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(
+            &path,
+            contents.replace("[remote]\n", "[remote]\nbuffer_size = 1048576\n"),
+        )
+        .unwrap();
+
+        config = YuhaConfig::load_from_file(&path).unwrap();
+        assert_eq!(config.network.read_buffer.initial_capacity, 1048576);
+        assert!(config.network.read_buffer.max_capacity >= 1048576);
+    }
+
+    #[test]
+    fn test_config_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("fleet")).unwrap();
+        let common = dir.path().join("fleet/common.toml");
+        let mut config = YuhaConfig::default();
+        config.network.ssh_port = 2222;
+        config.save_to_file(&common).unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "include = \"fleet/common.toml\"\n[network]\nbind_address = \"0.0.0.0\"\n",
+        )
+        .unwrap();
+
+        let config = YuhaConfig::load_from_file(&path).unwrap();
+        assert_eq!(config.network.ssh_port, 2222);
+        assert_eq!(config.network.bind_address, "0.0.0.0");
+
+        // Files including each other
+        let contents = std::fs::read_to_string(&common).unwrap();
+        std::fs::write(
+            &common,
+            format!("include = [\"../config.toml\"]\n{}", contents),
+        )
+        .unwrap();
+        let err = YuhaConfig::load_from_file(&path).unwrap_err();
+        assert!(err.to_string().contains("include cycle"));
+    }
+
+    #[test]
+    fn test_profile_inheritance() {
+        let dir = tempfile::tempdir().unwrap();
+        YuhaConfig::default()
+            .save_to_file(dir.path().join("common.toml"))
+            .unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            r#"
+include = "common.toml"
+
+[profiles.base]
+name = "base"
+env_vars = { LANG = "C", EDITOR = "vi" }
+[profiles.base.ssh]
+host = "gateway"
+username = "ops"
+port = 2222
+
+[profiles.web]
+extends = "base"
+env_vars = { EDITOR = "nano" }
+[profiles.web.ssh]
+host = "web-1"
+
+[profiles.web-2]
+extends = "web"
+ssh = { host = "web-2" }
+"#,
+        )
+        .unwrap();
+
+        let config = YuhaConfig::load_from_file(&path).unwrap();
+        let web = config.get_profile("web").unwrap();
+        assert_eq!(web.name, "web");
+        assert_eq!(web.env_vars["LANG"], "C");
+        assert_eq!(web.env_vars["EDITOR"], "nano");
+        assert!(!web.overrides.contains_key("extends"));
+        let ssh = web.ssh.as_ref().unwrap();
+        assert_eq!((ssh.host.as_str(), ssh.username.as_str()), ("web-1", "ops"));
+        assert_eq!(ssh.port, 2222);
+
+        let web2 = config.get_profile("web-2").unwrap();
+        assert_eq!(web2.ssh.as_ref().unwrap().host, "web-2");
+        assert_eq!(web2.env_vars["EDITOR"], "nano");
+
+        let include = "include = \"common.toml\"\n";
+        std::fs::write(
+            &path,
+            format!("{}[profiles.a]\nextends = \"missing\"\n", include),
+        )
+        .unwrap();
+        let err = YuhaConfig::load_from_file(&path).unwrap_err();
+        assert!(err.to_string().contains("unknown profile missing"));
+
+        std::fs::write(
+            &path,
+            format!(
+                "{}[profiles.a]\nextends = \"b\"\n[profiles.b]\nextends = \"a\"\n",
+                include
+            ),
+        )
+        .unwrap();
+        let err = YuhaConfig::load_from_file(&path).unwrap_err();
+        assert!(err.to_string().contains("inheritance cycle"));
     }
 
     #[test]
@@ -519,9 +957,15 @@ mod tests {
                 username: "user".to_string(),
                 password: None,
                 key_path: None,
+                cert_path: None,
+                cert_principal: None,
+                pkcs11: None,
                 auto_upload_binary: false,
+                algorithms: SshAlgorithms::default(),
+                socket: None,
             }),
             local: None,
+            tcp: None,
             env_vars: HashMap::new(),
             overrides: HashMap::new(),
         };
@@ -0,0 +1,227 @@
+//! # Binary Deltas
+//!
+//! Encodes a file as the difference to an older version of it, so a
+//! redeployed remote binary only transfers what changed. The delta copies
+//! matching ranges of the base and inserts the remaining bytes, and is
+//! deflate compressed.
+//!
+//! Matches are found at any offset of the new file, as recompiled code
+//! shifts, by looking up a rolling hash of each window in an index of the
+//! base's aligned blocks. The BLAKE3 hashes of base and result are recorded,
+//! so applying to another base or a corrupted delta fails instead of
+//! producing a broken file.
+
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+/// Leading bytes of every delta
+const MAGIC: &[u8; 4] = b"YDLT";
+
+/// Length of the base blocks matches are searched for
+const BLOCK_LEN: usize = 64;
+
+/// Multiplier of the rolling hash
+const HASH_BASE: u32 = 0x0100_0193;
+
+const OP_COPY: u8 = 1;
+const OP_INSERT: u8 = 2;
+
+/// Hash of `window` as updated by [`roll`]
+fn hash(window: &[u8]) -> u32 {
+    window.iter().fold(0u32, |hash, &byte| {
+        hash.wrapping_mul(HASH_BASE).wrapping_add(byte as u32)
+    })
+}
+
+/// Slide a window hash by one byte; `factor` is `HASH_BASE ^ (BLOCK_LEN - 1)`
+fn roll(hash: u32, out: u8, into: u8, factor: u32) -> u32 {
+    hash.wrapping_sub((out as u32).wrapping_mul(factor))
+        .wrapping_mul(HASH_BASE)
+        .wrapping_add(into as u32)
+}
+
+/// Encode `target` as a delta against `base`
+pub fn diff(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut index = HashMap::new();
+    for (block, chunk) in base.chunks_exact(BLOCK_LEN).enumerate() {
+        index.entry(hash(chunk)).or_insert(block * BLOCK_LEN);
+    }
+    let factor = (1..BLOCK_LEN).fold(1u32, |factor, _| factor.wrapping_mul(HASH_BASE));
+
+    let mut ops = Vec::new();
+    let (mut literal, mut pos) = (0, 0);
+    let mut window = target.get(..BLOCK_LEN).map(hash);
+    while let Some(window_hash) = window {
+        let matched = index
+            .get(&window_hash)
+            .filter(|&&start| base[start..start + BLOCK_LEN] == target[pos..pos + BLOCK_LEN]);
+        let Some(&start) = matched else {
+            window = target
+                .get(pos + BLOCK_LEN)
+                .map(|&into| roll(window_hash, target[pos], into, factor));
+            pos += 1;
+            continue;
+        };
+
+        // Grow the match over equal bytes on both sides
+        let (mut from, mut to) = (start, pos);
+        while to > literal && from > 0 && base[from - 1] == target[to - 1] {
+            from -= 1;
+            to -= 1;
+        }
+        let len = base[from..]
+            .iter()
+            .zip(&target[to..])
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        push_insert(&mut ops, &target[literal..to]);
+        ops.push(OP_COPY);
+        ops.extend_from_slice(&(from as u64).to_le_bytes());
+        ops.extend_from_slice(&(len as u64).to_le_bytes());
+        pos = to + len;
+        literal = pos;
+        window = target.get(pos..pos + BLOCK_LEN).map(hash);
+    }
+    push_insert(&mut ops, &target[literal..]);
+
+    let mut delta = Vec::with_capacity(MAGIC.len() + 64 + ops.len() / 4);
+    delta.extend_from_slice(MAGIC);
+    delta.extend_from_slice(blake3::hash(base).as_bytes());
+    delta.extend_from_slice(blake3::hash(target).as_bytes());
+    let mut encoder = DeflateEncoder::new(delta, Compression::best());
+    encoder
+        .write_all(&ops)
+        .expect("writing to memory cannot fail");
+    encoder.finish().expect("writing to memory cannot fail")
+}
+
+fn push_insert(ops: &mut Vec<u8>, data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+    ops.push(OP_INSERT);
+    ops.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    ops.extend_from_slice(data);
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Rebuild the target of `delta` from `base`
+pub fn apply(base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let header_len = MAGIC.len() + 64;
+    if delta.len() < header_len || &delta[..MAGIC.len()] != MAGIC {
+        return Err(invalid("Not a delta"));
+    }
+    let (base_hash, target_hash) = delta[MAGIC.len()..header_len].split_at(32);
+    if blake3::hash(base).as_bytes() != base_hash {
+        return Err(invalid("Delta was made against another base"));
+    }
+
+    let mut ops = Vec::new();
+    DeflateDecoder::new(&delta[header_len..]).read_to_end(&mut ops)?;
+    let mut ops = &ops[..];
+    let mut target = Vec::new();
+    while let Some((&op, rest)) = ops.split_first() {
+        ops = rest;
+        match op {
+            OP_COPY => {
+                let start = take_len(&mut ops)?;
+                let len = take_len(&mut ops)?;
+                let range = start
+                    .checked_add(len)
+                    .and_then(|end| base.get(start..end))
+                    .ok_or_else(|| invalid("Delta copies past the end of the base"))?;
+                target.extend_from_slice(range);
+            }
+            OP_INSERT => {
+                let len = take_len(&mut ops)?;
+                if len > ops.len() {
+                    return Err(invalid("Truncated delta"));
+                }
+                let (data, rest) = ops.split_at(len);
+                target.extend_from_slice(data);
+                ops = rest;
+            }
+            _ => return Err(invalid("Unknown delta operation")),
+        }
+    }
+
+    if blake3::hash(&target).as_bytes() != target_hash {
+        return Err(invalid("Delta result does not match its checksum"));
+    }
+    Ok(target)
+}
+
+fn take_len(ops: &mut &[u8]) -> io::Result<usize> {
+    let Some((bytes, rest)) = ops.split_first_chunk::<8>() else {
+        return Err(invalid("Truncated delta"));
+    };
+    *ops = rest;
+    usize::try_from(u64::from_le_bytes(*bytes)).map_err(|_| invalid("Delta range too large"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic incompressible bytes
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip_of_shifted_content() {
+        let base = noise(256 * 1024, 1);
+        // Insert, drop and change bytes, shifting everything after them
+        let mut target = base[..1000].to_vec();
+        target.extend_from_slice(b"inserted code");
+        target.extend_from_slice(&base[1000..100_000]);
+        target.extend_from_slice(&base[100_500..200_000]);
+        target.extend_from_slice(&noise(300, 2));
+        target.extend_from_slice(&base[200_000..]);
+
+        let delta = diff(&base, &target);
+        assert!(delta.len() < 2 * 1024, "delta of {} bytes", delta.len());
+        assert_eq!(apply(&base, &delta).unwrap(), target);
+    }
+
+    #[test]
+    fn test_round_trip_without_matches() {
+        for (base, target) in [
+            (Vec::new(), noise(100, 3)),
+            (noise(100, 3), Vec::new()),
+            (noise(10, 4), noise(10, 5)),
+        ] {
+            assert_eq!(apply(&base, &diff(&base, &target)).unwrap(), target);
+        }
+    }
+
+    #[test]
+    fn test_apply_rejects_other_base_and_corruption() {
+        let base = noise(4096, 6);
+        let mut target = base.clone();
+        target[2000] ^= 1;
+        let delta = diff(&base, &target);
+
+        assert!(apply(&noise(4096, 7), &delta).is_err());
+        let mut corrupt = delta.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0xff;
+        assert!(apply(&base, &corrupt).is_err());
+        assert!(apply(&base, b"YDLT").is_err());
+    }
+}
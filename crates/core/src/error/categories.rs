@@ -69,7 +69,14 @@ impl ErrorClassification for YuhaError {
             YuhaError::Protocol(protocol_err) => match protocol_err {
                 super::ProtocolError::Timeout { .. } => ErrorSeverity::Warning,
                 super::ProtocolError::ChannelClosed => ErrorSeverity::Error,
+                super::ProtocolError::ClosedByPeer => ErrorSeverity::Info,
+                super::ProtocolError::ChannelReset => ErrorSeverity::Warning,
+                super::ProtocolError::PeerUnreachable { .. } => ErrorSeverity::Warning,
+                super::ProtocolError::RequestCancelled { .. } => ErrorSeverity::Info,
                 super::ProtocolError::BufferOverflow { .. } => ErrorSeverity::Critical,
+                super::ProtocolError::IntegrityCheckFailed { .. } => ErrorSeverity::Critical,
+                super::ProtocolError::ChecksumMismatch { .. } => ErrorSeverity::Warning,
+                super::ProtocolError::ReplayDetected { .. } => ErrorSeverity::Critical,
                 _ => ErrorSeverity::Error,
             },
             YuhaError::Session(session_err) => match session_err {
@@ -97,6 +104,9 @@ impl ErrorClassification for YuhaError {
     fn category(&self) -> ErrorCategory {
         match self {
             YuhaError::Transport(_) => ErrorCategory::Network,
+            YuhaError::Protocol(super::ProtocolError::InvalidRequest { .. }) => {
+                ErrorCategory::UserInput
+            }
             YuhaError::Protocol(_) => ErrorCategory::Network,
             YuhaError::Session(_) => ErrorCategory::Logic,
             YuhaError::Config(_) => ErrorCategory::Configuration,
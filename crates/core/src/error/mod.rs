@@ -190,17 +190,55 @@ pub enum ProtocolError {
     #[error("Invalid message format: {reason}")]
     InvalidFormat { reason: String },
 
+    /// Request arguments the remote would reject
+    #[error("Invalid request: {reason}")]
+    InvalidRequest { reason: String },
+
     /// Channel closed unexpectedly
     #[error("Protocol channel closed unexpectedly")]
     ChannelClosed,
 
+    /// The peer closed the channel with a close handshake
+    #[error("Protocol channel closed by the peer")]
+    ClosedByPeer,
+
     /// Timeout occurred
-    #[error("Protocol timeout after {seconds} seconds")]
-    Timeout { seconds: u64 },
+    #[error("Protocol timeout after {millis} ms")]
+    Timeout { millis: u64 },
 
     /// Buffer overflow
     #[error("Protocol buffer overflow: message too large ({size} bytes)")]
     BufferOverflow { size: usize },
+
+    /// A message carries more attachments, or more attachment bytes, than
+    /// allowed
+    #[error("Message attachments over the limit: {count} attachments of {size} bytes")]
+    AttachmentsOverLimit { count: usize, size: usize },
+
+    /// Frame failed integrity verification
+    #[error("Frame integrity check failed: {reason}")]
+    IntegrityCheckFailed { reason: String },
+
+    /// Frame payload does not match its checksum, or lacks one once
+    /// checksums are in use
+    #[error("Frame checksum mismatch: {reason}")]
+    ChecksumMismatch { reason: String },
+
+    /// Frame sequence number was already seen or out of order
+    #[error("Replayed frame detected: expected sequence {expected}, got {actual}")]
+    ReplayDetected { expected: u64, actual: u64 },
+
+    /// The channel was reset after corruption, losing the messages in flight
+    #[error("Protocol channel reset after corrupted frames")]
+    ChannelReset,
+
+    /// The peer stopped answering keepalive pings
+    #[error("Peer unreachable: no answer {seconds} seconds after a ping")]
+    PeerUnreachable { seconds: u64 },
+
+    /// The peer cancelled the request with this correlation id
+    #[error("Request {id} cancelled by the peer")]
+    RequestCancelled { id: u64 },
 }
 
 /// Session management errors
@@ -380,6 +418,8 @@ impl YuhaError {
             YuhaError::Transport(TransportError::ConnectionFailed { .. })
                 | YuhaError::Protocol(ProtocolError::Timeout { .. })
                 | YuhaError::Protocol(ProtocolError::ChannelClosed)
+                | YuhaError::Protocol(ProtocolError::ChannelReset)
+                | YuhaError::Protocol(ProtocolError::PeerUnreachable { .. })
                 | YuhaError::Io(_)
                 | YuhaError::Daemon(DaemonError::SocketError { .. })
         )
@@ -0,0 +1,75 @@
+//! # Frame Hooks
+//!
+//! Hooks attached to a [`MessageChannel`](crate::message_channel::MessageChannel)
+//! with [`with_hook`](crate::message_channel::MessageChannel::with_hook) see
+//! the payload of every message it sends and receives, and may replace it,
+//! so embedders can count traffic, encrypt payloads their own way or record
+//! and replay them without forking the channel.
+//!
+//! A hook sees a message's payload whole, once encoded and compressed and
+//! before it is fragmented, sealed and framed; received payloads are handed
+//! to it once reassembled, before they are decompressed. Control frames do
+//! not pass through hooks. Unlike a [`WireRecorder`](crate::wire_capture::WireRecorder),
+//! a hook sees payloads unsealed on authenticated channels.
+//!
+//! Hooks stack like layers: a payload sent passes through them in the order
+//! they were attached, and a payload received in the reverse order, so each
+//! hook undoes on receipt what it did on sending when both peers attach the
+//! same hooks.
+//!
+//! ```rust
+//! use bytes::Bytes;
+//! use std::sync::Arc;
+//! use std::sync::atomic::{AtomicUsize, Ordering};
+//! use yuha_core::frame_hook::FrameHook;
+//!
+//! /// Counts the bytes of the payloads sent
+//! #[derive(Default)]
+//! struct Meter(AtomicUsize);
+//!
+//! impl FrameHook for Meter {
+//!     fn on_frame_sent(&self, payload: Bytes) -> Bytes {
+//!         self.0.fetch_add(payload.len(), Ordering::Relaxed);
+//!         payload
+//!     }
+//! }
+//!
+//! # fn example(stream: tokio::io::DuplexStream) {
+//! let meter = Arc::new(Meter::default());
+//! let channel = yuha_core::message_channel::MessageChannel::new_with_stream(stream)
+//!     .with_hook(meter.clone());
+//! # }
+//! ```
+
+use bytes::Bytes;
+use std::sync::Arc;
+
+use crate::error::Result;
+
+/// Sees, and may replace, the payloads of a channel's messages
+///
+/// Both methods pass payloads through unchanged by default, so a hook
+/// implements only the directions it cares about. They are called on the
+/// task sending or receiving, so they should not block.
+pub trait FrameHook: Send + Sync {
+    /// The payload to send in place of `payload`
+    fn on_frame_sent(&self, payload: Bytes) -> Bytes {
+        payload
+    }
+
+    /// The payload received in place of `payload`; an error fails the
+    /// receive
+    fn on_frame_received(&self, payload: Bytes) -> Result<Bytes> {
+        Ok(payload)
+    }
+}
+
+impl<H: FrameHook + ?Sized> FrameHook for Arc<H> {
+    fn on_frame_sent(&self, payload: Bytes) -> Bytes {
+        (**self).on_frame_sent(payload)
+    }
+
+    fn on_frame_received(&self, payload: Bytes) -> Result<Bytes> {
+        (**self).on_frame_received(payload)
+    }
+}
@@ -9,9 +9,20 @@
 //! - **Protocol**: Request/response protocol definitions for client-server communication
 //! - **Transport**: Abstraction layer for different connection types (SSH, TCP, local)
 //! - **Session Management**: Multi-connection session handling and lifecycle management
+//! - **Checksums**: Hardware accelerated CRC32C and BLAKE3 for data integrity
+//! - **Child Environment**: Allow-listed environment of spawned processes
+//! - **Binary Deltas**: Compact differences between file versions for redeploys
 //! - **Message Channel**: Binary message framing and JSON serialization
+//! - **Wire Capture**: Recordings of channel traffic, replayable in tests
+//! - **Frame Hooks**: Embedder code seeing and replacing channel payloads
+//! - **Buffer Pool**: Buffers a channel reuses across messages
 //! - **Configuration**: Centralized configuration management
+//! - **Secrets**: age-encrypted config values, decrypted at load time
+//! - **ssh-agent**: Signatures of agent-held keys, e.g. keys of PKCS#11 tokens
 //! - **Metrics & Logging**: Observability and debugging infrastructure
+//! - **Path Handlers**: Applications opening files, chosen by extension
+//! - **Slow Log**: Ring buffer of requests exceeding duration or payload thresholds
+//! - **Access Log**: Connections a server accepted or rejected, summarized by source
 //!
 //! ## Architecture
 //!
@@ -19,16 +30,30 @@
 //! for pseudo-bidirectional communication, prioritizing simplicity and reliability
 //! over complex bidirectional messaging.
 
+// Paths in code generated by `yuha_command` resolve within this crate too
+extern crate self as yuha_core;
+
+pub mod access_log;
 pub mod browser;
+pub mod buffer_pool;
+pub mod checksum;
+pub mod child_env;
 pub mod clipboard;
 pub mod config;
+pub mod delta;
 pub mod error;
+pub mod frame_hook;
 pub mod logging;
 pub mod message_channel;
 pub mod metrics;
+pub mod open;
 pub mod protocol;
+pub mod secrets;
 pub mod session;
+pub mod slow_log;
+pub mod ssh_agent;
 pub mod transport;
+pub mod wire_capture;
 
 // Re-export commonly used types
 pub use config::YuhaConfig;
@@ -43,4 +68,5 @@ pub use transport::{TransportConfig, TransportType};
 #[cfg(test)]
 mod tests {
     mod transport_tests;
+    mod wire_tests;
 }
@@ -0,0 +1,117 @@
+//! # Path Handlers
+//!
+//! Chooses the application that opens a file, by file extension, for the
+//! `OpenPath` request on the remote and for remote files opened locally.
+//! Files without a configured handler go to the platform's default opener.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Placeholder replaced by the path in handler arguments
+const PATH_PLACEHOLDER: &str = "{path}";
+
+/// Applications opening files, keyed by extension
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenHandlers {
+    /// Commands by lowercase extension without the dot; `{path}` in an
+    /// argument is replaced by the path, which is appended otherwise
+    #[serde(default)]
+    pub by_extension: BTreeMap<String, Vec<String>>,
+}
+
+impl OpenHandlers {
+    /// Add a handler from a `EXT=COMMAND ARGS...` specification
+    ///
+    /// The command is split on whitespace; quoting is not supported.
+    pub fn insert_spec(&mut self, spec: &str) -> crate::Result<()> {
+        let invalid = || {
+            crate::YuhaError::config(format!(
+                "Invalid open handler {:?}, expected EXT=COMMAND",
+                spec
+            ))
+        };
+        let (extension, command) = spec.split_once('=').ok_or_else(invalid)?;
+        let command: Vec<String> = command.split_whitespace().map(str::to_string).collect();
+        let extension = extension
+            .trim()
+            .trim_start_matches('.')
+            .to_ascii_lowercase();
+        if extension.is_empty() || command.is_empty() {
+            return Err(invalid());
+        }
+        self.by_extension.insert(extension, command);
+        Ok(())
+    }
+
+    /// Command line opening `path`
+    pub fn command(&self, path: &str) -> Vec<String> {
+        let handler = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| self.by_extension.get(&extension.to_ascii_lowercase()));
+        let Some(handler) = handler else {
+            let mut command = default_opener();
+            command.push(path.to_string());
+            return command;
+        };
+
+        let mut command: Vec<String> = handler
+            .iter()
+            .map(|arg| arg.replace(PATH_PLACEHOLDER, path))
+            .collect();
+        if !handler.iter().any(|arg| arg.contains(PATH_PLACEHOLDER)) {
+            command.push(path.to_string());
+        }
+        command
+    }
+}
+
+/// The platform's default opener, taking the path as last argument
+pub fn default_opener() -> Vec<String> {
+    let opener: &[&str] = if cfg!(windows) {
+        // The empty argument is the window title `start` expects first
+        &["cmd", "/C", "start", ""]
+    } else if cfg!(target_os = "macos") {
+        &["open"]
+    } else {
+        &["xdg-open"]
+    };
+    opener.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_by_extension() {
+        let mut handlers = OpenHandlers::default();
+        handlers.insert_spec("rs=code --wait").unwrap();
+        handlers
+            .insert_spec(".PDF=zathura --page 1 {path}")
+            .unwrap();
+
+        assert_eq!(
+            handlers.command("/src/main.RS"),
+            vec!["code", "--wait", "/src/main.RS"]
+        );
+        assert_eq!(
+            handlers.command("/doc/a.pdf"),
+            vec!["zathura", "--page", "1", "/doc/a.pdf"]
+        );
+
+        let mut expected = default_opener();
+        expected.push("/doc/notes".to_string());
+        assert_eq!(handlers.command("/doc/notes"), expected);
+    }
+
+    #[test]
+    fn test_invalid_spec() {
+        let mut handlers = OpenHandlers::default();
+        for spec in ["code", "=code", "rs=", "rs=  "] {
+            assert!(handlers.insert_spec(spec).is_err(), "{}", spec);
+        }
+        assert!(handlers.by_extension.is_empty());
+    }
+}
@@ -0,0 +1,187 @@
+//! # Binary Attachments
+//!
+//! Binary fields of protocol messages (file chunks, forwarded data, clipboard
+//! images) would be bloated several times over as JSON number arrays. When a
+//! [`MessageChannel`](crate::message_channel::MessageChannel) uses
+//! [`BinaryEncoding::Attachment`], such fields are serialized as a reference
+//! `{"attachment": n}` and their bytes travel in separate raw frames sent
+//! ahead of the message envelope:
+//!
+//! ```text
+//! [0x00][attachment 0 bytes]
+//! [0x00][attachment 1 bytes]
+//! {"Data":{"items":[{"FileChunk":{..,"data":{"attachment":0},..}}]}}
+//! ```
+//!
+//! An envelope, a JSON object or CBOR map, never starts with `0x00`, so receivers recognize
+//! attachment frames without any negotiation and accept both encodings.
+//! Outside a channel, binary fields always serialize inline.
+
+use super::codec::Codec;
+use bytes::{Bytes, BytesMut};
+use serde::de::{self, DeserializeOwned, MapAccess, SeqAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::RefCell;
+use std::fmt;
+
+/// First byte of an attachment frame
+pub const ATTACHMENT_MARKER: u8 = 0x00;
+
+const ATTACHMENT_KEY: &str = "attachment";
+
+/// How binary fields are carried on a channel
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BinaryEncoding {
+    /// Bytes stay inside the JSON envelope, keeping every frame self-contained
+    Inline,
+    /// Bytes are sent as raw attachment frames referenced from the envelope
+    #[default]
+    Attachment,
+}
+
+thread_local! {
+    static OUTGOING: RefCell<Option<Vec<Bytes>>> = const { RefCell::new(None) };
+    static INCOMING: RefCell<Option<Vec<Bytes>>> = const { RefCell::new(None) };
+}
+
+/// Append the encoding of `value` with `codec` to `out`, moving its binary
+/// fields into the returned attachments
+pub fn encode_with_attachments<C: Codec, T: Serialize>(
+    codec: &C,
+    value: &T,
+    out: &mut BytesMut,
+) -> Result<Vec<Bytes>, C::Error> {
+    OUTGOING.with(|outgoing| *outgoing.borrow_mut() = Some(Vec::new()));
+    let encoded = codec.encode_into(value, out);
+    let attachments = OUTGOING.with(|outgoing| outgoing.borrow_mut().take().unwrap_or_default());
+    encoded.map(|()| attachments)
+}
+
+/// Decode `envelope` with `codec`, resolving attachment references against
+/// `attachments`
+pub fn decode_with_attachments<C: Codec, T: DeserializeOwned>(
+    codec: &C,
+    envelope: &[u8],
+    attachments: Vec<Bytes>,
+) -> Result<T, C::Error> {
+    INCOMING.with(|incoming| *incoming.borrow_mut() = Some(attachments));
+    let value = codec.decode(envelope);
+    INCOMING.with(|incoming| incoming.borrow_mut().take());
+    value
+}
+
+/// `#[serde(with = "attachment")]` for `Bytes` fields
+pub fn serialize<S: Serializer>(data: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+    let index = OUTGOING.with(|outgoing| {
+        outgoing.borrow_mut().as_mut().map(|attachments| {
+            attachments.push(data.clone());
+            attachments.len() - 1
+        })
+    });
+    match index {
+        Some(index) => {
+            let mut map = serializer.serialize_map(Some(1))?;
+            map.serialize_entry(ATTACHMENT_KEY, &index)?;
+            map.end()
+        }
+        None => data.serialize(serializer),
+    }
+}
+
+/// `#[serde(with = "attachment")]` for `Bytes` fields
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+    deserializer.deserialize_any(BinaryVisitor)
+}
+
+struct BinaryVisitor;
+
+impl<'de> Visitor<'de> for BinaryVisitor {
+    type Value = Bytes;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a byte array or an attachment reference")
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Bytes, E> {
+        Ok(Bytes::copy_from_slice(bytes))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Bytes, A::Error> {
+        let mut data = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element::<u8>()? {
+            data.push(byte);
+        }
+        Ok(Bytes::from(data))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Bytes, A::Error> {
+        let key: String = map
+            .next_key()?
+            .ok_or_else(|| de::Error::missing_field(ATTACHMENT_KEY))?;
+        if key != ATTACHMENT_KEY {
+            return Err(de::Error::unknown_field(&key, &[ATTACHMENT_KEY]));
+        }
+        let index: usize = map.next_value()?;
+
+        INCOMING
+            .with(|incoming| {
+                incoming
+                    .borrow()
+                    .as_ref()
+                    .and_then(|attachments| attachments.get(index).cloned())
+            })
+            .ok_or_else(|| de::Error::custom(format!("Missing attachment {}", index)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::codec::Json;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Chunk {
+        name: String,
+        #[serde(with = "super")]
+        data: Bytes,
+    }
+
+    fn chunk(data: &'static [u8]) -> Chunk {
+        Chunk {
+            name: "chunk".to_string(),
+            data: Bytes::from_static(data),
+        }
+    }
+
+    #[test]
+    fn test_attachments_round_trip() {
+        let value = vec![chunk(b"\x00\x01\x02"), chunk(b"abc")];
+
+        let mut json = BytesMut::new();
+        let attachments = encode_with_attachments(&Json, &value, &mut json).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&json).unwrap(),
+            r#"[{"name":"chunk","data":{"attachment":0}},{"name":"chunk","data":{"attachment":1}}]"#
+        );
+        assert_eq!(attachments, vec![&b"\x00\x01\x02"[..], &b"abc"[..]]);
+
+        let back: Vec<Chunk> = decode_with_attachments(&Json, &json, attachments).unwrap();
+        assert_eq!(back, value);
+    }
+
+    #[test]
+    fn test_inline_outside_channel() {
+        let json = serde_json::to_string(&chunk(b"ab")).unwrap();
+        assert_eq!(json, r#"{"name":"chunk","data":[97,98]}"#);
+        assert_eq!(serde_json::from_str::<Chunk>(&json).unwrap(), chunk(b"ab"));
+    }
+
+    #[test]
+    fn test_missing_attachment() {
+        let json = br#"{"name":"chunk","data":{"attachment":3}}"#;
+        assert!(decode_with_attachments::<_, Chunk>(&Json, json, Vec::new()).is_err());
+        assert!(serde_json::from_slice::<Chunk>(json).is_err());
+    }
+}
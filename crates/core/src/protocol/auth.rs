@@ -0,0 +1,377 @@
+//! # Session Authentication
+//!
+//! Shared-token authentication with per-frame replay protection for channels
+//! that are not otherwise secured (e.g. the remote server's TCP mode without TLS).
+//!
+//! ## Handshake
+//!
+//! ```text
+//! Client → Server: client_nonce (32 bytes)
+//! Server → Client: server_nonce (32 bytes) || server_proof (32 bytes)
+//! Client → Server: client_proof (32 bytes)
+//! ```
+//!
+//! Proofs are HMAC-SHA256 over both nonces keyed by the shared token, so neither
+//! side reveals the token and a recorded handshake cannot be reused: every
+//! session derives a fresh key from its own pair of nonces.
+//!
+//! ## Authenticated Frames
+//!
+//! ```text
+//! - 8 bytes: sequence number (big endian)
+//! - N bytes: payload
+//! - 32 bytes: HMAC-SHA256(session_key, direction || sequence || payload)
+//! ```
+//!
+//! Each direction keeps its own counter. A frame whose sequence number is not
+//! exactly the next expected one is rejected, so captured frames cannot be
+//! replayed, reordered, or reflected back to their sender. Only while a
+//! channel recovers from corruption may the sequence skip the frames it lost,
+//! at most [`MAX_SEQUENCE_GAP`] of them, and even then never go back.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::error::{AuthError, ProtocolError, Result};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Length of handshake nonces in bytes
+pub const NONCE_LEN: usize = 32;
+/// Length of handshake proofs and frame tags in bytes
+pub const TAG_LEN: usize = 32;
+/// Length of the sequence number prefix in bytes
+pub const SEQUENCE_LEN: usize = 8;
+/// Bytes added to every authenticated frame
+pub const FRAME_OVERHEAD: usize = SEQUENCE_LEN + TAG_LEN;
+/// Most frames a resynchronizing receiver takes as lost, so a frame cannot
+/// jump its sequence arbitrarily far ahead
+pub const MAX_SEQUENCE_GAP: u64 = 1 << 16;
+
+/// Handshake nonce
+pub type Nonce = [u8; NONCE_LEN];
+
+/// Side of the channel a party plays during authentication
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+impl Role {
+    fn label(self) -> &'static [u8] {
+        match self {
+            Role::Client => b"yuha client",
+            Role::Server => b"yuha server",
+        }
+    }
+
+    fn peer(self) -> Self {
+        match self {
+            Role::Client => Role::Server,
+            Role::Server => Role::Client,
+        }
+    }
+}
+
+/// Generate a fresh random handshake nonce
+pub fn generate_nonce() -> Nonce {
+    rand::random()
+}
+
+fn keyed_mac(key: &[u8]) -> HmacSha256 {
+    HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length")
+}
+
+fn handshake_mac(
+    token: &[u8],
+    label: &[u8],
+    client_nonce: &Nonce,
+    server_nonce: &Nonce,
+) -> HmacSha256 {
+    let mut mac = keyed_mac(token);
+    mac.update(label);
+    mac.update(client_nonce);
+    mac.update(server_nonce);
+    mac
+}
+
+/// Compute the proof a party sends to show it knows the shared token
+pub fn handshake_proof(
+    token: &[u8],
+    role: Role,
+    client_nonce: &Nonce,
+    server_nonce: &Nonce,
+) -> [u8; TAG_LEN] {
+    handshake_mac(token, role.label(), client_nonce, server_nonce)
+        .finalize()
+        .into_bytes()
+        .into()
+}
+
+/// Verify the proof received from the peer of `role`
+pub fn verify_handshake_proof(
+    token: &[u8],
+    role: Role,
+    client_nonce: &Nonce,
+    server_nonce: &Nonce,
+    proof: &[u8],
+) -> Result<()> {
+    handshake_mac(token, role.peer().label(), client_nonce, server_nonce)
+        .verify_slice(proof)
+        .map_err(|_| {
+            AuthError::InvalidCredentials {
+                reason: "Peer failed to prove knowledge of the auth token".to_string(),
+            }
+            .into()
+        })
+}
+
+/// Per-session state for sealing and opening authenticated frames
+#[derive(Debug, Clone)]
+pub struct SessionAuth {
+    key: [u8; TAG_LEN],
+    role: Role,
+    send_sequence: u64,
+    receive_sequence: u64,
+}
+
+impl SessionAuth {
+    /// Derive the session state from the shared token and both handshake nonces
+    pub fn new(token: &[u8], role: Role, client_nonce: &Nonce, server_nonce: &Nonce) -> Self {
+        let key = handshake_mac(token, b"yuha session", client_nonce, server_nonce)
+            .finalize()
+            .into_bytes()
+            .into();
+        Self {
+            key,
+            role,
+            send_sequence: 0,
+            receive_sequence: 0,
+        }
+    }
+
+    fn frame_mac(&self, role: Role, sequence: u64, payload: &[u8]) -> HmacSha256 {
+        let mut mac = keyed_mac(&self.key);
+        mac.update(role.label());
+        mac.update(&sequence.to_be_bytes());
+        mac.update(payload);
+        mac
+    }
+
+    /// Wrap an outgoing payload with the next sequence number and an authentication tag
+    pub fn seal(&mut self, payload: &[u8]) -> Bytes {
+        let sequence = self.send_sequence;
+        self.send_sequence += 1;
+
+        let tag = self.frame_mac(self.role, sequence, payload).finalize();
+        let mut frame = BytesMut::with_capacity(payload.len() + FRAME_OVERHEAD);
+        frame.put_u64(sequence);
+        frame.put_slice(payload);
+        frame.put_slice(&tag.into_bytes());
+        frame.freeze()
+    }
+
+    /// Verify an incoming frame and return its payload
+    pub fn open(&mut self, frame: Bytes) -> Result<Bytes> {
+        let (sequence, payload) = self.verify(frame)?;
+        if sequence != self.receive_sequence {
+            return Err(ProtocolError::ReplayDetected {
+                expected: self.receive_sequence,
+                actual: sequence,
+            }
+            .into());
+        }
+        self.receive_sequence += 1;
+        Ok(payload)
+    }
+
+    /// Verify an incoming frame that may follow lost ones
+    ///
+    /// Used while resynchronizing a corrupted channel: the sequence may skip
+    /// ahead, by at most [`MAX_SEQUENCE_GAP`] frames, but not go back, so
+    /// replays are still rejected.
+    pub fn open_after_gap(&mut self, frame: Bytes) -> Result<Bytes> {
+        let (sequence, payload) = self.verify(frame)?;
+        check_gap(self.receive_sequence, sequence)?;
+        self.receive_sequence = sequence + 1;
+        Ok(payload)
+    }
+
+    /// Check the tag of a frame and split off its sequence number
+    fn verify(&self, mut frame: Bytes) -> Result<(u64, Bytes)> {
+        if frame.len() < FRAME_OVERHEAD {
+            return Err(ProtocolError::IntegrityCheckFailed {
+                reason: format!("Authenticated frame too short: {} bytes", frame.len()),
+            }
+            .into());
+        }
+
+        let tag = frame.split_off(frame.len() - TAG_LEN);
+        let payload = frame.split_off(SEQUENCE_LEN);
+        let sequence = u64::from_be_bytes(frame[..].try_into().expect("sequence prefix length"));
+
+        self.frame_mac(self.role.peer(), sequence, &payload)
+            .verify_slice(&tag)
+            .map_err(|_| ProtocolError::IntegrityCheckFailed {
+                reason: format!("Invalid authentication tag on frame {}", sequence),
+            })?;
+        Ok((sequence, payload))
+    }
+}
+
+/// Check that a frame numbered `actual` may follow lost frames when
+/// `expected` was due
+pub(crate) fn check_gap(expected: u64, actual: u64) -> Result<()> {
+    if actual < expected {
+        return Err(ProtocolError::ReplayDetected { expected, actual }.into());
+    }
+    if actual - expected > MAX_SEQUENCE_GAP {
+        return Err(ProtocolError::IntegrityCheckFailed {
+            reason: format!(
+                "Frame {} skips more than {} frames after {}",
+                actual, MAX_SEQUENCE_GAP, expected
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::YuhaError;
+
+    const TOKEN: &[u8] = b"secret-token";
+
+    fn session_pair() -> (SessionAuth, SessionAuth) {
+        let client_nonce = generate_nonce();
+        let server_nonce = generate_nonce();
+        (
+            SessionAuth::new(TOKEN, Role::Client, &client_nonce, &server_nonce),
+            SessionAuth::new(TOKEN, Role::Server, &client_nonce, &server_nonce),
+        )
+    }
+
+    #[test]
+    fn test_handshake_proof_verification() {
+        let client_nonce = generate_nonce();
+        let server_nonce = generate_nonce();
+
+        let proof = handshake_proof(TOKEN, Role::Server, &client_nonce, &server_nonce);
+        assert!(
+            verify_handshake_proof(TOKEN, Role::Client, &client_nonce, &server_nonce, &proof)
+                .is_ok()
+        );
+
+        // A proof cannot be reflected back to the party that produced it
+        assert!(
+            verify_handshake_proof(TOKEN, Role::Server, &client_nonce, &server_nonce, &proof)
+                .is_err()
+        );
+
+        // A wrong token is rejected
+        assert!(
+            verify_handshake_proof(b"other", Role::Client, &client_nonce, &server_nonce, &proof)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_seal_open_roundtrip() {
+        let (mut client, mut server) = session_pair();
+
+        for message in [&b"first"[..], b"second", b""] {
+            let frame = client.seal(message);
+            assert_eq!(frame.len(), message.len() + FRAME_OVERHEAD);
+            assert_eq!(server.open(frame).unwrap(), Bytes::copy_from_slice(message));
+        }
+
+        let reply = server.seal(b"reply");
+        assert_eq!(client.open(reply).unwrap(), Bytes::from_static(b"reply"));
+    }
+
+    #[test]
+    fn test_replayed_frame_rejected() {
+        let (mut client, mut server) = session_pair();
+
+        let frame = client.seal(b"open-browser");
+        server.open(frame.clone()).unwrap();
+
+        let err = server.open(frame).unwrap_err();
+        assert!(matches!(
+            err,
+            YuhaError::Protocol(ProtocolError::ReplayDetected {
+                expected: 1,
+                actual: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn test_open_after_gap_skips_ahead_only() {
+        let (mut client, mut server) = session_pair();
+
+        let first = client.seal(b"lost");
+        let _lost = client.seal(b"lost too");
+        let next = client.seal(b"after the gap");
+        assert!(server.open(next.clone()).is_err());
+        assert_eq!(
+            server.open_after_gap(next).unwrap(),
+            Bytes::from_static(b"after the gap")
+        );
+
+        // Skipped frames cannot be delivered late
+        assert!(matches!(
+            server.open_after_gap(first).unwrap_err(),
+            YuhaError::Protocol(ProtocolError::ReplayDetected {
+                expected: 3,
+                actual: 0
+            })
+        ));
+        server.open(client.seal(b"in order")).unwrap();
+
+        // Nor may a frame skip too far ahead
+        client.send_sequence += MAX_SEQUENCE_GAP + 1;
+        assert!(matches!(
+            server
+                .open_after_gap(client.seal(b"far ahead"))
+                .unwrap_err(),
+            YuhaError::Protocol(ProtocolError::IntegrityCheckFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_reflected_frame_rejected() {
+        let (mut client, _server) = session_pair();
+
+        let frame = client.seal(b"ping");
+        let err = client.open(frame).unwrap_err();
+        assert!(matches!(
+            err,
+            YuhaError::Protocol(ProtocolError::IntegrityCheckFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_frame_from_other_session_rejected() {
+        let (mut client, _) = session_pair();
+        let (_, mut other_server) = session_pair();
+
+        let frame = client.seal(b"ping");
+        assert!(other_server.open(frame).is_err());
+    }
+
+    #[test]
+    fn test_tampered_frame_rejected() {
+        let (mut client, mut server) = session_pair();
+
+        let mut frame = BytesMut::from(&client.seal(b"payload")[..]);
+        frame[SEQUENCE_LEN] ^= 0x01;
+        assert!(server.open(frame.freeze()).is_err());
+
+        assert!(server.open(Bytes::from_static(b"short")).is_err());
+    }
+}
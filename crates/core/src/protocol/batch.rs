@@ -0,0 +1,130 @@
+//! # Streamed Responses
+//!
+//! Requests that can return many items stream them as a sequence of
+//! [`ProtocolResponse::Batch`] frames instead of one `Data` response, so the
+//! server never holds the whole result and the client sees the first items
+//! early. Every batch but the last has `more: true`; a stream ends with a
+//! batch whose `more` is false, or with an `Error` response if the producer
+//! fails part way.
+
+use super::{ProtocolResponse, ResponseItem};
+
+/// Encoded size budget of a single batch, well below the frame limit
+pub const MAX_BATCH_BYTES: usize = 32 * 1024;
+
+/// Groups response items into batches that each fit in one frame
+#[derive(Debug)]
+pub struct ResponseBatcher {
+    items: Vec<ResponseItem>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Default for ResponseBatcher {
+    fn default() -> Self {
+        Self::new(MAX_BATCH_BYTES)
+    }
+}
+
+impl ResponseBatcher {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            items: Vec::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    /// Add an item, returning the pending batch if the item does not fit in it
+    ///
+    /// An item larger than the budget is sent in a batch of its own.
+    pub fn push(&mut self, item: ResponseItem) -> serde_json::Result<Option<ProtocolResponse>> {
+        let item_size = serde_json::to_vec(&item)?.len() + 1;
+        let full = !self.items.is_empty() && self.size + item_size > self.max_size;
+        let batch = full.then(|| self.take(true));
+
+        self.size += item_size;
+        self.items.push(item);
+        Ok(batch)
+    }
+
+    /// The pending batch, if it holds any items, for items that should not
+    /// wait for the batch to fill up
+    pub fn flush(&mut self) -> Option<ProtocolResponse> {
+        (!self.items.is_empty()).then(|| self.take(true))
+    }
+
+    /// The final batch holding the remaining items
+    pub fn finish(mut self) -> ProtocolResponse {
+        self.take(false)
+    }
+
+    fn take(&mut self, more: bool) -> ProtocolResponse {
+        self.size = 0;
+        ProtocolResponse::Batch {
+            items: std::mem::take(&mut self.items),
+            more,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(i: usize) -> ResponseItem {
+        ResponseItem::FileEntry {
+            path: format!("/data/file-{:04}", i),
+            relative_path: format!("file-{:04}", i),
+            size: 4096,
+        }
+    }
+
+    fn batch_len(response: &ProtocolResponse) -> (usize, bool) {
+        match response {
+            ProtocolResponse::Batch { items, more } => (items.len(), *more),
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_batches_stay_within_budget() {
+        let item_size = serde_json::to_vec(&entry(0)).unwrap().len() + 1;
+        let mut batcher = ResponseBatcher::new(item_size * 10);
+
+        let mut batches: Vec<_> = (0..25)
+            .filter_map(|i| batcher.push(entry(i)).unwrap())
+            .collect();
+        batches.push(batcher.finish());
+
+        let lens: Vec<_> = batches.iter().map(batch_len).collect();
+        assert_eq!(lens, vec![(10, true), (10, true), (5, false)]);
+        for batch in &batches {
+            assert!(serde_json::to_vec(batch).unwrap().len() < item_size * 10 + 64);
+        }
+    }
+
+    #[test]
+    fn test_oversized_item_is_sent_alone() {
+        let mut batcher = ResponseBatcher::new(16);
+
+        assert!(batcher.push(entry(0)).unwrap().is_none());
+        let batch = batcher.push(entry(1)).unwrap().unwrap();
+        assert_eq!(batch_len(&batch), (1, true));
+        assert_eq!(batch_len(&batcher.finish()), (1, false));
+    }
+
+    #[test]
+    fn test_flush_sends_pending_items() {
+        let mut batcher = ResponseBatcher::default();
+        assert!(batcher.flush().is_none());
+        assert!(batcher.push(entry(0)).unwrap().is_none());
+        assert_eq!(batch_len(&batcher.flush().unwrap()), (1, true));
+        assert_eq!(batch_len(&batcher.finish()), (0, false));
+    }
+
+    #[test]
+    fn test_empty_stream() {
+        assert_eq!(batch_len(&ResponseBatcher::default().finish()), (0, false));
+    }
+}
@@ -55,6 +55,13 @@ impl ResponseBuffer {
         });
     }
 
+    pub fn add_credit(&mut self, connection_id: u32, bytes: u32) {
+        self.add_item(ResponseItem::Credit {
+            connection_id,
+            bytes,
+        });
+    }
+
     pub fn add_clipboard_content(&mut self, content: String) {
         self.add_item(ResponseItem::ClipboardContent { content });
     }
@@ -0,0 +1,448 @@
+//! # Request Construction
+//!
+//! Validating constructors for [`ProtocolRequest`] variants taking arguments,
+//! so library users get an error where a request is built instead of a
+//! failure reported by the remote. Requests with optional parts are built
+//! with [`ListFilesBuilder`] and [`LaunchAppBuilder`].
+//!
+//! ```rust
+//! use yuha_core::protocol::{ListQuery, ProtocolRequest};
+//!
+//! let forward = ProtocolRequest::start_port_forward(8080, "localhost", 80)?;
+//! let listing = ProtocolRequest::list_files(["/var/log"])
+//!     .query(ListQuery {
+//!         limit: Some(100),
+//!         ..Default::default()
+//!     })
+//!     .build()?;
+//! assert!(ProtocolRequest::open_browser("not a url").is_err());
+//! # Ok::<(), yuha_core::YuhaError>(())
+//! ```
+
+use bytes::Bytes;
+
+use super::request_response::{DisplayEnv, MAX_FILE_CHUNK_LEN, MAX_FILE_RANGE_LEN, Sandbox};
+use super::{ListQuery, ProtocolRequest};
+use crate::checksum::{self, HashAlgorithm};
+use crate::clipboard::{ClipboardFormat, ClipboardItem};
+use crate::error::{ProtocolError, Result};
+
+fn invalid(reason: impl Into<String>) -> crate::error::YuhaError {
+    ProtocolError::InvalidRequest {
+        reason: reason.into(),
+    }
+    .into()
+}
+
+fn check_port(port: u16) -> Result<u16> {
+    if port == 0 {
+        return Err(invalid("Port must be between 1 and 65535"));
+    }
+    Ok(port)
+}
+
+fn check_path(path: String) -> Result<String> {
+    if path.is_empty() {
+        return Err(invalid("Path must not be empty"));
+    }
+    Ok(path)
+}
+
+/// Whether `word` is a single non-empty command line word
+fn is_word(word: &str) -> bool {
+    !word.is_empty() && !word.contains(char::is_whitespace)
+}
+
+impl ProtocolRequest {
+    /// Forward `local_port` on the remote to `remote_host:remote_port`
+    pub fn start_port_forward(
+        local_port: u16,
+        remote_host: impl Into<String>,
+        remote_port: u16,
+    ) -> Result<Self> {
+        let remote_host = remote_host.into();
+        if !is_word(&remote_host) {
+            return Err(invalid(format!("Invalid remote host '{}'", remote_host)));
+        }
+        Ok(ProtocolRequest::StartPortForward {
+            local_port: check_port(local_port)?,
+            remote_host,
+            remote_port: check_port(remote_port)?,
+        })
+    }
+
+    /// Stop the forward of `local_port`
+    pub fn stop_port_forward(local_port: u16) -> Result<Self> {
+        Ok(ProtocolRequest::StopPortForward {
+            local_port: check_port(local_port)?,
+        })
+    }
+
+    /// Get clipboard content in the best of `accept`, most preferred first
+    pub fn get_clipboard_data(accept: Vec<ClipboardFormat>) -> Result<Self> {
+        if accept.is_empty() {
+            return Err(invalid("No accepted clipboard format"));
+        }
+        Ok(ProtocolRequest::GetClipboardData { accept })
+    }
+
+    /// Set clipboard content with one or more representations
+    pub fn set_clipboard_data(items: Vec<ClipboardItem>) -> Result<Self> {
+        if items.is_empty() {
+            return Err(invalid("No clipboard content to set"));
+        }
+        Ok(ProtocolRequest::SetClipboardData { items })
+    }
+
+    /// Open `url`, which must use a scheme the remote opens
+    pub fn open_browser(url: impl Into<String>) -> Result<Self> {
+        let url = url.into();
+        crate::browser::validate_url(&url)?;
+        Ok(ProtocolRequest::OpenBrowser { url })
+    }
+
+    /// Start building a `LaunchApp` request running `command`
+    pub fn launch_app<I, S>(command: I) -> LaunchAppBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        LaunchAppBuilder {
+            command: command.into_iter().map(Into::into).collect(),
+            display_env: DisplayEnv::default(),
+            sandbox: Sandbox::default(),
+        }
+    }
+
+    /// Open the remote `path` with its configured handler
+    pub fn open_path(path: impl Into<String>) -> Result<Self> {
+        Ok(ProtocolRequest::OpenPath {
+            path: check_path(path.into())?,
+        })
+    }
+
+    /// Start building a `ListFiles` request for `paths`
+    pub fn list_files<I, S>(paths: I) -> ListFilesBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        ListFilesBuilder {
+            paths: paths.into_iter().map(Into::into).collect(),
+            query: ListQuery::default(),
+        }
+    }
+
+    /// List the entries of the directory `path`
+    pub fn list_directory(path: impl Into<String>) -> Result<Self> {
+        Ok(ProtocolRequest::ListDirectory {
+            path: check_path(path.into())?,
+        })
+    }
+
+    /// Read `len` bytes of `path` from `offset`; `len` must be at most
+    /// [`MAX_FILE_CHUNK_LEN`]
+    pub fn read_file_chunk(path: impl Into<String>, offset: u64, len: u32) -> Result<Self> {
+        if !(1..=MAX_FILE_CHUNK_LEN).contains(&len) {
+            return Err(invalid(format!(
+                "Chunk length {} is not between 1 and {}",
+                len, MAX_FILE_CHUNK_LEN
+            )));
+        }
+        Ok(ProtocolRequest::ReadFileChunk {
+            path: check_path(path.into())?,
+            offset,
+            len,
+        })
+    }
+
+    /// Read up to `len` bytes of `path` from `offset`; `len` must be at most
+    /// [`MAX_FILE_RANGE_LEN`], and 0 only asks for the size of the file
+    pub fn read_file_range(path: impl Into<String>, offset: u64, len: u32) -> Result<Self> {
+        if len > MAX_FILE_RANGE_LEN {
+            return Err(invalid(format!(
+                "Range length {} exceeds {}",
+                len, MAX_FILE_RANGE_LEN
+            )));
+        }
+        Ok(ProtocolRequest::ReadFileRange {
+            path: check_path(path.into())?,
+            offset,
+            len,
+        })
+    }
+
+    /// Hash `path`, or the files under it, with `algo`
+    pub fn hash_path(path: impl Into<String>, algo: HashAlgorithm) -> Result<Self> {
+        Ok(ProtocolRequest::HashPath {
+            path: check_path(path.into())?,
+            algo,
+        })
+    }
+
+    /// Sum the sizes under `path` per directory, `depth` levels down
+    pub fn disk_usage(path: impl Into<String>, depth: u32) -> Result<Self> {
+        Ok(ProtocolRequest::DiskUsage {
+            path: check_path(path.into())?,
+            depth,
+        })
+    }
+
+    /// Remove `path`, moving it to the remote's trash when `trash` is set
+    pub fn remove_path(path: impl Into<String>, trash: bool) -> Result<Self> {
+        Ok(ProtocolRequest::RemovePath {
+            path: check_path(path.into())?,
+            trash,
+        })
+    }
+
+    /// Move `from` to `to`, trashing what `to` replaces when `trash` is set
+    pub fn move_path(from: impl Into<String>, to: impl Into<String>, trash: bool) -> Result<Self> {
+        Ok(ProtocolRequest::MovePath {
+            from: check_path(from.into())?,
+            to: check_path(to.into())?,
+            trash,
+        })
+    }
+
+    /// Restore the trash entry `id`, to `to` instead of its original path
+    /// when given
+    pub fn restore_path(id: impl Into<String>, to: Option<String>) -> Result<Self> {
+        let id = id.into();
+        if id.is_empty() {
+            return Err(invalid("Trash entry id must not be empty"));
+        }
+        Ok(ProtocolRequest::RestorePath {
+            id,
+            to: to.map(check_path).transpose()?,
+        })
+    }
+
+    /// Write `data` at `offset` of an upload of `path`, checksumming it; the
+    /// last chunk is refused if `path` no longer has `expected_digest`
+    pub fn write_file_chunk(
+        path: impl Into<String>,
+        offset: u64,
+        data: Bytes,
+        last: bool,
+        expected_digest: Option<String>,
+    ) -> Result<Self> {
+        Ok(ProtocolRequest::WriteFileChunk {
+            path: check_path(path.into())?,
+            offset,
+            crc32c: checksum::crc32c(&data),
+            data,
+            last,
+            expected_digest,
+        })
+    }
+
+    /// Probe `tools`, or the common ones when empty
+    pub fn probe_tools<I, S>(tools: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let tools: Vec<String> = tools.into_iter().map(Into::into).collect();
+        if let Some(tool) = tools
+            .iter()
+            .find(|tool| !is_word(tool) || tool.contains('/'))
+        {
+            return Err(invalid(format!("Invalid tool name '{}'", tool)));
+        }
+        Ok(ProtocolRequest::ProbeTools { tools })
+    }
+}
+
+/// Builder of a `ListFiles` request, from [`ProtocolRequest::list_files`]
+#[derive(Debug, Clone)]
+pub struct ListFilesBuilder {
+    paths: Vec<String>,
+    query: ListQuery,
+}
+
+impl ListFilesBuilder {
+    /// Also list `path`
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.paths.push(path.into());
+        self
+    }
+
+    /// Select the page and filter of the listing
+    pub fn query(mut self, query: ListQuery) -> Self {
+        self.query = query;
+        self
+    }
+
+    /// Build the request, failing without paths or with an empty one
+    pub fn build(self) -> Result<ProtocolRequest> {
+        if self.paths.is_empty() {
+            return Err(invalid("No paths to list"));
+        }
+        Ok(ProtocolRequest::ListFiles {
+            paths: self
+                .paths
+                .into_iter()
+                .map(check_path)
+                .collect::<Result<_>>()?,
+            query: self.query,
+        })
+    }
+}
+
+/// Builder of a `LaunchApp` request, from [`ProtocolRequest::launch_app`]
+#[derive(Debug, Clone)]
+pub struct LaunchAppBuilder {
+    command: Vec<String>,
+    display_env: DisplayEnv,
+    sandbox: Sandbox,
+}
+
+impl LaunchAppBuilder {
+    /// Connect to the X11 display, e.g. `:0`
+    pub fn display(mut self, display: impl Into<String>) -> Self {
+        self.display_env.display = Some(display.into());
+        self
+    }
+
+    /// Connect to the Wayland socket, e.g. `wayland-0`
+    pub fn wayland_display(mut self, socket: impl Into<String>) -> Self {
+        self.display_env.wayland_display = Some(socket.into());
+        self
+    }
+
+    /// Use `display_env`, replacing displays set before
+    pub fn display_env(mut self, display_env: DisplayEnv) -> Self {
+        self.display_env = display_env;
+        self
+    }
+
+    /// Run the application under the restrictions of `sandbox`
+    pub fn sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Build the request, failing without a program to run
+    pub fn build(self) -> Result<ProtocolRequest> {
+        if self
+            .command
+            .first()
+            .is_none_or(|program| program.is_empty())
+        {
+            return Err(invalid("No program to launch"));
+        }
+        Ok(ProtocolRequest::LaunchApp {
+            command: self.command,
+            display_env: self.display_env,
+            sandbox: self.sandbox,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_forward_validation() {
+        assert!(matches!(
+            ProtocolRequest::start_port_forward(8080, "localhost", 80),
+            Ok(ProtocolRequest::StartPortForward {
+                local_port: 8080,
+                remote_port: 80,
+                ..
+            })
+        ));
+        assert!(ProtocolRequest::start_port_forward(0, "localhost", 80).is_err());
+        assert!(ProtocolRequest::start_port_forward(8080, "localhost", 0).is_err());
+        assert!(ProtocolRequest::start_port_forward(8080, "", 80).is_err());
+        assert!(ProtocolRequest::start_port_forward(8080, "local host", 80).is_err());
+        assert!(ProtocolRequest::stop_port_forward(0).is_err());
+    }
+
+    #[test]
+    fn test_argument_validation() {
+        assert!(ProtocolRequest::open_browser("https://example.com").is_ok());
+        assert!(ProtocolRequest::open_browser("example.com").is_err());
+        assert!(ProtocolRequest::open_browser("javascript:alert(1)").is_err());
+
+        assert!(ProtocolRequest::open_path("").is_err());
+        assert!(ProtocolRequest::read_file_chunk("/etc/hosts", 0, 0).is_err());
+        assert!(ProtocolRequest::read_file_chunk("/etc/hosts", 0, MAX_FILE_CHUNK_LEN + 1).is_err());
+        assert!(ProtocolRequest::read_file_range("/etc/hosts", 0, 0).is_ok());
+        assert!(ProtocolRequest::read_file_range("/etc/hosts", 0, MAX_FILE_RANGE_LEN + 1).is_err());
+        assert!(ProtocolRequest::read_file_range("", 0, 1).is_err());
+        assert!(ProtocolRequest::hash_path("", HashAlgorithm::Blake3).is_err());
+        assert!(ProtocolRequest::remove_path("", true).is_err());
+        assert!(ProtocolRequest::disk_usage("", 1).is_err());
+        assert!(ProtocolRequest::move_path("/tmp/a", "", false).is_err());
+        assert!(ProtocolRequest::restore_path("", None).is_err());
+        assert!(ProtocolRequest::restore_path("1-a", Some(String::new())).is_err());
+        assert!(ProtocolRequest::get_clipboard_data(Vec::new()).is_err());
+        assert!(ProtocolRequest::set_clipboard_data(Vec::new()).is_err());
+
+        assert!(ProtocolRequest::probe_tools(Vec::<String>::new()).is_ok());
+        assert!(ProtocolRequest::probe_tools(["git", "cargo"]).is_ok());
+        assert!(ProtocolRequest::probe_tools(["rm -rf"]).is_err());
+        assert!(ProtocolRequest::probe_tools(["../git"]).is_err());
+    }
+
+    #[test]
+    fn test_write_file_chunk_checksums_data() {
+        let data = Bytes::from_static(b"content");
+        let Ok(ProtocolRequest::WriteFileChunk { crc32c, .. }) =
+            ProtocolRequest::write_file_chunk("/tmp/file", 0, data.clone(), true, None)
+        else {
+            panic!("expected a WriteFileChunk request");
+        };
+        assert_eq!(crc32c, checksum::crc32c(&data));
+    }
+
+    #[test]
+    fn test_builders() {
+        let request = ProtocolRequest::list_files(["/src"])
+            .path("/docs")
+            .query(ListQuery {
+                limit: Some(10),
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        let ProtocolRequest::ListFiles { paths, query } = request else {
+            panic!("expected a ListFiles request");
+        };
+        assert_eq!(paths, ["/src", "/docs"]);
+        assert_eq!(query.limit, Some(10));
+        assert!(
+            ProtocolRequest::list_files(Vec::<String>::new())
+                .build()
+                .is_err()
+        );
+        assert!(ProtocolRequest::list_files(["/src", ""]).build().is_err());
+
+        let request = ProtocolRequest::launch_app(["xterm", "-e", "top"])
+            .display(":1")
+            .sandbox(Sandbox {
+                read_only: true,
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        let ProtocolRequest::LaunchApp {
+            display_env,
+            sandbox,
+            ..
+        } = request
+        else {
+            panic!("expected a LaunchApp request");
+        };
+        assert_eq!(display_env.display.as_deref(), Some(":1"));
+        assert!(sandbox.read_only);
+        assert!(
+            ProtocolRequest::launch_app(Vec::<String>::new())
+                .build()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! # Codecs
+//!
+//! A [`MessageChannel`](crate::message_channel::MessageChannel) encodes its
+//! requests and responses with a [`Codec`], JSON by default. Both sides of a
+//! channel must use the same codec.
+//!
+//! - [`Json`]: readable on the wire and in captures
+//! - [`Cbor`]: compact binary encoding (`cbor` feature)
+//! - [`MessagePack`]: compact binary encoding (`msgpack` feature), which a
+//!   channel can also switch to after the handshake whatever its codec (see
+//!   [`MessageChannel::use_msgpack`](crate::message_channel::MessageChannel::use_msgpack));
+//!   its envelopes are marked, so a peer with another codec follows
+//!
+//! Messages carry `serde_json::Value` fields and binary fields that decode
+//! from either bytes or attachment references, so a codec must be
+//! self-describing; formats relying on the schema alone, like bincode or
+//! postcard, cannot decode them.
+
+use bytes::{BufMut, BytesMut};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fmt;
+
+use super::ExtensionId;
+
+/// Encoding of the messages sent over a channel
+pub trait Codec: Send + Sync {
+    type Error: fmt::Display;
+
+    /// Name used in logs
+    const NAME: &'static str;
+
+    fn encode<M: Serialize>(&self, message: &M) -> Result<Vec<u8>, Self::Error>;
+
+    /// Append the encoding of `message` to `out`, sparing a buffer of its own
+    fn encode_into<M: Serialize>(
+        &self,
+        message: &M,
+        out: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        out.extend_from_slice(&self.encode(message)?);
+        Ok(())
+    }
+
+    fn decode<M: DeserializeOwned>(&self, bytes: &[u8]) -> Result<M, Self::Error>;
+}
+
+/// Whether channels switch to MessagePack once `Hello` negotiated
+/// `negotiated`, which takes the `msgpack` feature
+#[cfg_attr(not(feature = "msgpack"), allow(unused_variables))]
+pub fn msgpack_negotiated(negotiated: &[ExtensionId]) -> bool {
+    #[cfg(feature = "msgpack")]
+    return negotiated.contains(&super::extension::MSGPACK.id);
+    #[cfg(not(feature = "msgpack"))]
+    false
+}
+
+/// First byte of envelopes encoded with [`MessagePack`], which no JSON or
+/// CBOR encoding of a message starts with
+pub(crate) const MSGPACK_MARKER: u8 = 0x06;
+
+/// JSON via `serde_json`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Json;
+
+impl Codec for Json {
+    type Error = serde_json::Error;
+
+    const NAME: &'static str = "json";
+
+    fn encode<M: Serialize>(&self, message: &M) -> Result<Vec<u8>, Self::Error> {
+        serde_json::to_vec(message)
+    }
+
+    fn encode_into<M: Serialize>(
+        &self,
+        message: &M,
+        out: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        serde_json::to_writer(out.writer(), message)
+    }
+
+    fn decode<M: DeserializeOwned>(&self, bytes: &[u8]) -> Result<M, Self::Error> {
+        serde_json::from_slice(bytes)
+    }
+}
+
+/// CBOR (RFC 8949) via `ciborium`, carrying bytes as bytes
+#[cfg(feature = "cbor")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    type Error = String;
+
+    const NAME: &'static str = "cbor";
+
+    fn encode<M: Serialize>(&self, message: &M) -> Result<Vec<u8>, Self::Error> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(message, &mut bytes).map_err(|e| e.to_string())?;
+        Ok(bytes)
+    }
+
+    fn encode_into<M: Serialize>(
+        &self,
+        message: &M,
+        out: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        ciborium::into_writer(message, out.writer()).map_err(|e| e.to_string())
+    }
+
+    fn decode<M: DeserializeOwned>(&self, bytes: &[u8]) -> Result<M, Self::Error> {
+        ciborium::from_reader(bytes).map_err(|e| e.to_string())
+    }
+}
+
+/// MessagePack via `rmp-serde`, carrying bytes as bytes and struct fields by
+/// name so messages stay self-describing
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    type Error = String;
+
+    const NAME: &'static str = "msgpack";
+
+    fn encode<M: Serialize>(&self, message: &M) -> Result<Vec<u8>, Self::Error> {
+        let mut out = vec![MSGPACK_MARKER];
+        rmp_serde::encode::write_named(&mut out, message).map_err(|e| e.to_string())?;
+        Ok(out)
+    }
+
+    fn encode_into<M: Serialize>(
+        &self,
+        message: &M,
+        out: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        out.put_u8(MSGPACK_MARKER);
+        rmp_serde::encode::write_named(&mut out.writer(), message).map_err(|e| e.to_string())
+    }
+
+    fn decode<M: DeserializeOwned>(&self, bytes: &[u8]) -> Result<M, Self::Error> {
+        match bytes.split_first() {
+            Some((&MSGPACK_MARKER, bytes)) => {
+                rmp_serde::from_slice(bytes).map_err(|e| e.to_string())
+            }
+            _ => Err("Not a MessagePack envelope".to_string()),
+        }
+    }
+}
@@ -0,0 +1,296 @@
+//! # Commands
+//!
+//! Commands are protocol features defined by a single trait with
+//! [`yuha_command`], instead of a request variant, a response item, a client
+//! method and a remote handler written across three crates. They travel as
+//! `ProtocolRequest::Command` with JSON arguments and are answered with a
+//! `CommandOutput` item.
+//!
+//! ```rust,ignore
+//! #[yuha_command]
+//! pub trait Notes {
+//!     /// Append a note, returning the number of notes
+//!     async fn add_note(&self, text: String) -> anyhow::Result<usize>;
+//! }
+//!
+//! // Remote: implement the trait on `NoteServer`, and register it with
+//! // `commands.register(NotesHandler(Arc::new(NoteServer::default())))`
+//! // Client: `client.add_note("text".to_string()).await?` via `NotesClient`
+//! ```
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use super::{ErrorCode, ProtocolRequest, ProtocolResponse, ResponseItem};
+use crate::error::{ProtocolError, Result};
+
+pub use yuha_macros::yuha_command;
+
+/// A command request, generated by [`yuha_command`] for each method
+pub trait Command: Serialize + Send + 'static {
+    /// Name the command is sent as, the method name
+    const NAME: &'static str;
+    /// What the command returns on success
+    type Output: DeserializeOwned + Send + 'static;
+
+    /// Protocol request carrying this command
+    fn request(&self) -> Result<ProtocolRequest> {
+        let args = serde_json::to_value(self).map_err(|e| ProtocolError::Serialization {
+            reason: e.to_string(),
+        })?;
+        Ok(ProtocolRequest::Command {
+            name: Self::NAME.to_string(),
+            args,
+        })
+    }
+
+    /// Decode the output from the items of the command's response
+    fn output(items: Vec<ResponseItem>) -> Result<Self::Output> {
+        let value = items
+            .into_iter()
+            .find_map(|item| match item {
+                ResponseItem::CommandOutput { value } => Some(value),
+                _ => None,
+            })
+            .ok_or_else(|| ProtocolError::InvalidFormat {
+                reason: format!("No output of command {}", Self::NAME),
+            })?;
+        serde_json::from_value(value).map_err(|e| {
+            ProtocolError::Deserialization {
+                reason: e.to_string(),
+            }
+            .into()
+        })
+    }
+}
+
+/// Sends commands and waits for their output, e.g. a connected client
+#[async_trait]
+pub trait CommandSender: Sync {
+    type Error;
+
+    /// Send `command` and return its output
+    async fn call<C: Command>(&self, command: C) -> std::result::Result<C::Output, Self::Error>;
+}
+
+/// Runs the commands of one command trait, e.g. the `NotesHandler`
+/// [`yuha_command`] generates for a trait `Notes`
+#[async_trait]
+pub trait CommandHandler: Send + Sync {
+    /// Run the command `name` with `args`, `None` if it is not one of this
+    /// handler's
+    async fn dispatch(&self, name: &str, args: serde_json::Value) -> Option<ProtocolResponse>;
+}
+
+/// Command handlers a server tries in turn for each `Command` request
+#[derive(Clone, Default)]
+pub struct CommandRegistry {
+    handlers: Vec<Arc<dyn CommandHandler>>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Try `handler` after the handlers registered before
+    pub fn register(&mut self, handler: impl CommandHandler + 'static) {
+        self.handlers.push(Arc::new(handler));
+    }
+
+    /// Response of the first handler running the command `name`, or an
+    /// `Unsupported` error if none does
+    pub async fn dispatch(&self, name: &str, args: serde_json::Value) -> ProtocolResponse {
+        for handler in &self.handlers {
+            if let Some(response) = handler.dispatch(name, args.clone()).await {
+                return response;
+            }
+        }
+        ProtocolResponse::error(ErrorCode::Unsupported, format!("Unknown command {}", name))
+            .with_detail("command", name)
+    }
+}
+
+/// Support of the code generated by [`yuha_command`]
+#[doc(hidden)]
+pub mod __private {
+    use std::fmt::Display;
+
+    use serde::Serialize;
+
+    use crate::protocol::{ErrorCode, ProtocolResponse, ResponseItem};
+
+    pub use async_trait;
+    pub use serde;
+    pub use serde_json;
+
+    /// Response to a command that returned `result`
+    pub fn respond<T: Serialize, E: Display>(result: Result<T, E>) -> ProtocolResponse {
+        let value = result
+            .map_err(|e| e.to_string())
+            .and_then(|output| serde_json::to_value(output).map_err(|e| e.to_string()));
+        match value {
+            Ok(value) => ProtocolResponse::Data {
+                items: vec![ResponseItem::CommandOutput { value }],
+                next: None,
+            },
+            Err(message) => ProtocolResponse::error(ErrorCode::Internal, message),
+        }
+    }
+
+    /// Response to a command whose arguments did not decode
+    pub fn invalid_args(name: &str, error: serde_json::Error) -> ProtocolResponse {
+        ProtocolResponse::error(
+            ErrorCode::InvalidArguments,
+            format!("Invalid arguments of command {}: {}", name, error),
+        )
+        .with_detail("command", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ProtocolResponse;
+    use std::sync::Mutex;
+
+    #[yuha_command]
+    trait Notes {
+        /// Append a note, returning the number of notes
+        async fn add_note(&self, text: String) -> std::result::Result<usize, String>;
+
+        /// Notes containing `filter`, at most `limit`
+        async fn find_notes(
+            &self,
+            filter: String,
+            limit: usize,
+        ) -> std::result::Result<Vec<String>, String>;
+    }
+
+    #[derive(Default)]
+    struct NoteServer(Mutex<Vec<String>>);
+
+    #[async_trait]
+    impl Notes for NoteServer {
+        async fn add_note(&self, text: String) -> std::result::Result<usize, String> {
+            if text.is_empty() {
+                return Err("Empty note".to_string());
+            }
+            let mut notes = self.0.lock().unwrap();
+            notes.push(text);
+            Ok(notes.len())
+        }
+
+        async fn find_notes(
+            &self,
+            filter: String,
+            limit: usize,
+        ) -> std::result::Result<Vec<String>, String> {
+            let notes = self.0.lock().unwrap();
+            Ok(notes
+                .iter()
+                .filter(|note| note.contains(&filter))
+                .take(limit)
+                .cloned()
+                .collect())
+        }
+    }
+
+    /// Sends commands straight to a server, through their wire encoding
+    struct Loopback(NoteServer);
+
+    #[async_trait]
+    impl CommandSender for Loopback {
+        type Error = String;
+
+        async fn call<C: Command>(&self, command: C) -> std::result::Result<C::Output, String> {
+            let ProtocolRequest::Command { name, args } =
+                command.request().map_err(|e| e.to_string())?
+            else {
+                unreachable!("commands are sent as Command requests");
+            };
+            match self.0.dispatch_command(&name, args).await {
+                Some(ProtocolResponse::Data { items, .. }) => {
+                    C::output(items).map_err(|e| e.to_string())
+                }
+                Some(ProtocolResponse::Error { message, .. }) => Err(message),
+                _ => Err(format!("Unknown command {}", name)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generated_client_and_dispatcher() {
+        let client = Loopback(NoteServer::default());
+        assert_eq!(client.add_note("buy milk".to_string()).await, Ok(1));
+        assert_eq!(client.add_note("call bob".to_string()).await, Ok(2));
+        assert_eq!(
+            client.add_note(String::new()).await,
+            Err("Empty note".to_string())
+        );
+        assert_eq!(
+            client.find_notes("milk".to_string(), 10).await,
+            Ok(vec!["buy milk".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_rejects_unknown_commands_and_arguments() {
+        let server = NoteServer::default();
+        assert!(
+            server
+                .dispatch_command("delete_note", serde_json::json!({}))
+                .await
+                .is_none()
+        );
+        assert!(matches!(
+            server
+                .dispatch_command("find_notes", serde_json::json!({ "filter": "x" }))
+                .await,
+            Some(ProtocolResponse::Error {
+                code: ErrorCode::InvalidArguments,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_registry_tries_handlers_in_turn() {
+        let mut commands = CommandRegistry::new();
+        commands.register(NotesHandler(Arc::new(NoteServer::default())));
+        let added = commands
+            .dispatch("add_note", serde_json::json!({ "text": "buy milk" }))
+            .await;
+        let ProtocolResponse::Data { items, .. } = added else {
+            panic!("expected the output, got {:?}", added);
+        };
+        assert_eq!(AddNote::output(items).unwrap(), 1);
+        assert!(matches!(
+            commands
+                .dispatch("delete_note", serde_json::json!({}))
+                .await,
+            ProtocolResponse::Error {
+                code: ErrorCode::Unsupported,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_request_encoding() {
+        let request = FindNotes {
+            filter: "milk".to_string(),
+            limit: 3,
+        }
+        .request()
+        .unwrap();
+        let ProtocolRequest::Command { name, args } = request else {
+            panic!("expected a Command request");
+        };
+        assert_eq!(name, "find_notes");
+        assert_eq!(args, serde_json::json!({ "filter": "milk", "limit": 3 }));
+    }
+}
@@ -0,0 +1,137 @@
+//! # Message Compression
+//!
+//! Each compression algorithm a build supports is registered as an
+//! extension adding no requests. The remote compresses what it sends with
+//! the preferred algorithm both sides negotiated, and a channel receiving a
+//! compressed message compresses with its algorithm from then on, so the
+//! client follows without a separate announcement.
+//!
+//! Only messages of at least a threshold size are compressed, and only when
+//! that makes them smaller, so small requests cost nothing.
+//!
+//! - [`Compression::Zstd`]: better ratio, for clipboard images and file
+//!   chunks (`zstd` feature)
+//! - [`Compression::Lz4`]: cheaper on slow CPUs (`lz4` feature)
+
+use super::extension::ExtensionId;
+
+/// Algorithm number carried by uncompressed payloads that merely look like
+/// compressed ones
+pub const STORED: u8 = 0;
+
+/// A compression algorithm for message payloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    #[cfg(feature = "zstd")]
+    Zstd,
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+/// Supported algorithms, most preferred first
+const PREFERENCE: &[Compression] = &[
+    #[cfg(feature = "zstd")]
+    Compression::Zstd,
+    #[cfg(feature = "lz4")]
+    Compression::Lz4,
+];
+
+impl Compression {
+    /// Number identifying the algorithm in compressed payloads
+    pub fn id(self) -> u8 {
+        match self {
+            #[cfg(feature = "zstd")]
+            Self::Zstd => 1,
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => 2,
+        }
+    }
+
+    /// Supported algorithm numbered `id`
+    pub fn from_id(id: u8) -> Option<Self> {
+        PREFERENCE
+            .iter()
+            .copied()
+            .find(|compression| compression.id() == id)
+    }
+
+    /// Extension negotiating the algorithm
+    pub fn extension(self) -> ExtensionId {
+        match self {
+            #[cfg(feature = "zstd")]
+            Self::Zstd => super::extension::COMPRESSION_ZSTD.id,
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => super::extension::COMPRESSION_LZ4.id,
+        }
+    }
+
+    /// Preferred algorithm among the `negotiated` extensions
+    pub fn preferred(negotiated: &[ExtensionId]) -> Option<Self> {
+        PREFERENCE
+            .iter()
+            .copied()
+            .find(|compression| negotiated.contains(&compression.extension()))
+    }
+
+    /// Compress `data` into a payload for [`decompress`](Self::decompress)
+    #[cfg_attr(not(any(feature = "zstd", feature = "lz4")), allow(unused_variables))]
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Self::Zstd => zstd::bulk::compress(data, 0).map_err(|e| e.to_string()),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+        }
+    }
+
+    /// Decompress `data`, failing when it expands beyond `max_len` bytes
+    #[cfg_attr(not(any(feature = "zstd", feature = "lz4")), allow(unused_variables))]
+    pub fn decompress(self, data: &[u8], max_len: usize) -> Result<Vec<u8>, String> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Self::Zstd => zstd::bulk::decompress(data, max_len).map_err(|e| e.to_string()),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => {
+                let len = data
+                    .get(..4)
+                    .map(|prefix| u32::from_le_bytes(prefix.try_into().unwrap()) as usize)
+                    .ok_or("Missing decompressed size")?;
+                if len > max_len {
+                    return Err(format!("Decompressed size {} exceeds {}", len, max_len));
+                }
+                lz4_flex::decompress_size_prepended(data).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::extension;
+
+    #[test]
+    fn test_round_trip() {
+        let data = b"clipboard ".repeat(1000);
+        for &compression in PREFERENCE {
+            let compressed = compression.compress(&data).unwrap();
+            assert!(compressed.len() < data.len() / 10);
+            assert_eq!(Compression::from_id(compression.id()), Some(compression));
+            assert_eq!(
+                compression.decompress(&compressed, data.len()).unwrap(),
+                data
+            );
+            assert!(compression.decompress(&compressed, 100).is_err());
+        }
+        assert_eq!(Compression::from_id(STORED), None);
+    }
+
+    #[test]
+    fn test_preferred() {
+        assert_eq!(Compression::preferred(&[]), None);
+        assert_eq!(
+            Compression::preferred(&extension::registered()),
+            PREFERENCE.first().copied()
+        );
+    }
+}
@@ -0,0 +1,352 @@
+//! # Protocol Extensions
+//!
+//! Optional request types are grouped into numbered extensions. Right after
+//! connecting, the client offers the extensions it knows in a `Hello`
+//! request and the remote answers with those it has enabled too; requests
+//! of an extension that was not negotiated are rejected. Experimental
+//! requests can thus be added, gated and dropped without touching the core
+//! protocol.
+//!
+//! Numbers below [`EXPERIMENTAL_BASE`] are assigned in [`REGISTRY`]. Numbers
+//! from it upwards are free for local experiments and never registered.
+
+use super::ProtocolRequest;
+
+/// Extension number exchanged in the `Hello` handshake
+pub type ExtensionId = u16;
+
+/// First extension number of the unregistered experimental namespace
+pub const EXPERIMENTAL_BASE: ExtensionId = 0x8000;
+
+/// A registered extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extension {
+    pub id: ExtensionId,
+    /// Name used on command lines and in logs
+    pub name: &'static str,
+    /// Kinds (see [`ProtocolRequest::kind`]) of the requests it adds
+    pub requests: &'static [&'static str],
+}
+
+/// Slow request log retrieval (`GetSlowLog`)
+pub const SLOW_LOG: Extension = Extension {
+    id: 1,
+    name: "slow-log",
+    requests: &["GetSlowLog"],
+};
+
+/// GUI application launch (`LaunchApp`)
+pub const APP_LAUNCH: Extension = Extension {
+    id: 2,
+    name: "app-launch",
+    requests: &["LaunchApp"],
+};
+
+/// Opening paths with their handlers (`OpenPath`)
+pub const OPEN_PATH: Extension = Extension {
+    id: 3,
+    name: "open-path",
+    requests: &["OpenPath"],
+};
+
+/// Remote toolchain probing (`ProbeTools`)
+pub const TOOL_PROBE: Extension = Extension {
+    id: 4,
+    name: "tool-probe",
+    requests: &["ProbeTools"],
+};
+
+/// Credits of forwarded data and streamed responses (`GrantCredit`,
+/// `GrantBatches`)
+pub const FLOW_CONTROL: Extension = Extension {
+    id: 6,
+    name: "flow-control",
+    requests: &["GrantCredit", "GrantBatches"],
+};
+
+/// Long frames of up to 16 MiB, sent by the remote once negotiated and by
+/// the client once it received one; adds no requests
+pub const LARGE_FRAMES: Extension = Extension {
+    id: 7,
+    name: "large-frames",
+    requests: &[],
+};
+
+/// zstd compression of large messages (see [`super::compression`]), only
+/// built with the `zstd` feature; adds no requests
+#[cfg(feature = "zstd")]
+pub const COMPRESSION_ZSTD: Extension = Extension {
+    id: 8,
+    name: "zstd",
+    requests: &[],
+};
+
+/// lz4 compression of large messages, only built with the `lz4` feature;
+/// adds no requests
+#[cfg(feature = "lz4")]
+pub const COMPRESSION_LZ4: Extension = Extension {
+    id: 9,
+    name: "lz4",
+    requests: &[],
+};
+
+/// CRC32C checksums of frames (see [`super::frame_checksum`]); adds no
+/// requests
+pub const FRAME_CRC32C: Extension = Extension {
+    id: 10,
+    name: "crc32c-frames",
+    requests: &[],
+};
+
+/// xxHash3 checksums of frames, only built with the `xxhash` feature; adds
+/// no requests
+#[cfg(feature = "xxhash")]
+pub const FRAME_XXHASH: Extension = Extension {
+    id: 11,
+    name: "xxhash-frames",
+    requests: &[],
+};
+
+/// Cancelling requests in flight with cancel control frames (see
+/// [`MessageChannel::send_cancel`](crate::message_channel::MessageChannel::send_cancel));
+/// adds no requests
+pub const CANCEL: Extension = Extension {
+    id: 12,
+    name: "cancel",
+    requests: &[],
+};
+
+/// Text copied on the remote pushed as `ClipboardContent` items of
+/// `PollData`, and the way the remote notices it reported by `Hello`; adds
+/// no requests
+pub const CLIPBOARD_WATCH: Extension = Extension {
+    id: 13,
+    name: "clipboard-watch",
+    requests: &[],
+};
+
+/// Closing channels with a close handshake (see
+/// [`MessageChannel::close`](crate::message_channel::MessageChannel::close));
+/// adds no requests
+pub const GRACEFUL_CLOSE: Extension = Extension {
+    id: 14,
+    name: "graceful-close",
+    requests: &[],
+};
+
+/// Reading arbitrary ranges of files along with their size
+/// (`ReadFileRange`)
+pub const FILE_RANGE: Extension = Extension {
+    id: 15,
+    name: "file-range",
+    requests: &["ReadFileRange"],
+};
+
+/// Hashing files for comparison (`HashPath`)
+pub const HASH_PATH: Extension = Extension {
+    id: 17,
+    name: "hash-path",
+    requests: &["HashPath"],
+};
+
+/// Removing and moving paths, optionally through the remote's trash
+/// (`RemovePath`, `MovePath`, `RestorePath`, `ListTrash`)
+pub const FILE_TRASH: Extension = Extension {
+    id: 18,
+    name: "file-trash",
+    requests: &["RemovePath", "MovePath", "RestorePath", "ListTrash"],
+};
+
+/// Per-directory sizes of remote trees (`DiskUsage`)
+pub const DISK_USAGE: Extension = Extension {
+    id: 19,
+    name: "disk-usage",
+    requests: &["DiskUsage"],
+};
+
+/// Connections accepted and rejected by the server (`GetAccessLog`)
+pub const ACCESS_LOG: Extension = Extension {
+    id: 20,
+    name: "access-log",
+    requests: &["GetAccessLog"],
+};
+
+/// Requests the remote sends the client (`ServerRequest`), answered with
+/// `ServerReply` (see [`super::server_request`])
+pub const SERVER_REQUESTS: Extension = Extension {
+    id: 21,
+    name: "server-requests",
+    requests: &["ServerReply"],
+};
+
+/// Events of subscribed topics pushed to the client (`Subscribe`,
+/// `Unsubscribe`, see [`super::topic`])
+pub const SUBSCRIPTIONS: Extension = Extension {
+    id: 22,
+    name: "subscriptions",
+    requests: &["Subscribe", "Unsubscribe"],
+};
+
+/// Background tasks of the session and its workspace (`ListTasks`,
+/// `CancelTask`)
+pub const TASKS: Extension = Extension {
+    id: 23,
+    name: "tasks",
+    requests: &["ListTasks", "CancelTask"],
+};
+
+/// Frames opened by a magic, so a corrupted channel finds the next frame
+/// and resets instead of failing (see
+/// [`MessageChannel::use_frame_magic`](crate::message_channel::MessageChannel::use_frame_magic)),
+/// used by the remote once negotiated and by the client once the remote
+/// announced them; adds no requests
+pub const RESYNC: Extension = Extension {
+    id: 24,
+    name: "resync",
+    requests: &[],
+};
+
+/// Payloads larger than a frame sent in fragments (see
+/// [`MessageChannel::use_fragments`](crate::message_channel::MessageChannel::use_fragments)),
+/// used by the remote once negotiated and by the client once the remote
+/// announced them; adds no requests
+pub const FRAGMENTS: Extension = Extension {
+    id: 25,
+    name: "fragments",
+    requests: &[],
+};
+
+/// Entries of a single directory (`ListDirectory`)
+pub const LIST_DIRECTORY: Extension = Extension {
+    id: 26,
+    name: "list-directory",
+    requests: &["ListDirectory"],
+};
+
+/// MessagePack encoding of messages once `Hello` is answered (see
+/// [`MessageChannel::use_msgpack`](crate::message_channel::MessageChannel::use_msgpack)),
+/// only built with the `msgpack` feature; adds no requests
+#[cfg(feature = "msgpack")]
+pub const MSGPACK: Extension = Extension {
+    id: 16,
+    name: "msgpack",
+    requests: &[],
+};
+
+/// Injected request faults for tests (`InjectFault`), only built with the
+/// `fault-injection` feature
+#[cfg(feature = "fault-injection")]
+pub const FAULT_INJECTION: Extension = Extension {
+    id: 5,
+    name: "fault-injection",
+    requests: &["InjectFault"],
+};
+
+/// Every known extension
+pub const REGISTRY: &[Extension] = &[
+    SLOW_LOG,
+    APP_LAUNCH,
+    OPEN_PATH,
+    TOOL_PROBE,
+    FLOW_CONTROL,
+    LARGE_FRAMES,
+    #[cfg(feature = "zstd")]
+    COMPRESSION_ZSTD,
+    #[cfg(feature = "lz4")]
+    COMPRESSION_LZ4,
+    FRAME_CRC32C,
+    #[cfg(feature = "xxhash")]
+    FRAME_XXHASH,
+    CANCEL,
+    CLIPBOARD_WATCH,
+    GRACEFUL_CLOSE,
+    FILE_RANGE,
+    HASH_PATH,
+    FILE_TRASH,
+    DISK_USAGE,
+    ACCESS_LOG,
+    SERVER_REQUESTS,
+    SUBSCRIPTIONS,
+    TASKS,
+    RESYNC,
+    FRAGMENTS,
+    LIST_DIRECTORY,
+    #[cfg(feature = "msgpack")]
+    MSGPACK,
+    #[cfg(feature = "fault-injection")]
+    FAULT_INJECTION,
+];
+
+/// Registered extension numbered `id`
+pub fn lookup(id: ExtensionId) -> Option<&'static Extension> {
+    REGISTRY.iter().find(|extension| extension.id == id)
+}
+
+/// Registered extension called `name`
+pub fn by_name(name: &str) -> Option<&'static Extension> {
+    REGISTRY.iter().find(|extension| extension.name == name)
+}
+
+/// Numbers of every registered extension
+pub fn registered() -> Vec<ExtensionId> {
+    REGISTRY.iter().map(|extension| extension.id).collect()
+}
+
+/// Extensions both offered by the client and enabled on the remote, ascending
+pub fn negotiate(offered: &[ExtensionId], enabled: &[ExtensionId]) -> Vec<ExtensionId> {
+    let mut negotiated: Vec<_> = offered
+        .iter()
+        .copied()
+        .filter(|id| enabled.contains(id))
+        .collect();
+    negotiated.sort_unstable();
+    negotiated.dedup();
+    negotiated
+}
+
+impl ProtocolRequest {
+    /// Extension this request belongs to, `None` for core requests
+    pub fn extension(&self) -> Option<&'static Extension> {
+        let kind = self.kind();
+        REGISTRY
+            .iter()
+            .find(|extension| extension.requests.contains(&kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_registry_is_consistent() {
+        let ids: HashSet<_> = REGISTRY.iter().map(|extension| extension.id).collect();
+        let names: HashSet<_> = REGISTRY.iter().map(|extension| extension.name).collect();
+        assert_eq!(ids.len(), REGISTRY.len());
+        assert_eq!(names.len(), REGISTRY.len());
+        assert!(
+            REGISTRY
+                .iter()
+                .all(|extension| extension.id < EXPERIMENTAL_BASE)
+        );
+
+        assert_eq!(lookup(SLOW_LOG.id), Some(&SLOW_LOG));
+        assert_eq!(by_name("slow-log"), Some(&SLOW_LOG));
+        assert_eq!(by_name("app-launch"), Some(&APP_LAUNCH));
+        assert_eq!(lookup(EXPERIMENTAL_BASE), None);
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate(&[3, 1, 1, 0x8001], &[1, 2, 3]), vec![1, 3]);
+        assert!(negotiate(&[], &registered()).is_empty());
+    }
+
+    #[test]
+    fn test_request_extension() {
+        assert_eq!(ProtocolRequest::GetSlowLog.extension(), Some(&SLOW_LOG));
+        assert_eq!(ProtocolRequest::ListTasks.extension(), Some(&TASKS));
+        assert_eq!(ProtocolRequest::PollData.extension(), None);
+    }
+}
@@ -0,0 +1,21 @@
+//! # Fault Injection
+//!
+//! Test-only protocol extension, built with the `fault-injection` feature.
+//! An `InjectFault` request makes the remote delay, drop or fail the next
+//! requests of a kind, so client timeout and retry handling can be
+//! exercised end-to-end against a real server. Production builds neither
+//! offer nor serve it.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// What happens to a request hit by an injected fault
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Fault {
+    /// Serve the request after `delay`
+    Delay { delay: Duration },
+    /// Never answer the request
+    Drop,
+    /// Answer with an error instead of serving the request
+    Error { message: String },
+}
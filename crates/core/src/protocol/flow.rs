@@ -0,0 +1,125 @@
+//! # Flow Control
+//!
+//! Forwarded connections share one protocol connection, so a consumer that
+//! stops reading one of them must not make the others wait behind its data
+//! nor let the remote buffer it without bound. With the `flow-control`
+//! extension, offered only by clients that grant credit back, each
+//! connection gets a [`Credit`] of [`INITIAL_CREDIT`] bytes in both
+//! directions:
+//!
+//! - The remote reads from the target only while the client has granted it
+//!   credit, and the client regains its credit with `GrantCredit` once it
+//!   consumed the polled data.
+//! - The client sends `PortForwardData` only within its credit, which the
+//!   remote returns through `Credit` items as the target accepts the data.
+//!
+//! Streamed responses (see [`super::batch`]) are held the same way, counted
+//! in batches: the remote sends a request at most [`INITIAL_BATCH_CREDIT`]
+//! `Batch` responses ahead of the client, which grants each one back with
+//! `GrantBatches` as it takes it. A walk over a huge tree then waits for a
+//! slow consumer instead of piling its results up in the client.
+
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+/// Bytes a connection may have in flight in each direction before any grant
+pub const INITIAL_CREDIT: usize = 256 * 1024;
+
+/// `Batch` responses a streamed request may have in flight before any grant
+pub const INITIAL_BATCH_CREDIT: usize = 8;
+
+/// Bytes a sender may still send on a connection, or batches on a stream
+#[derive(Debug)]
+pub struct Credit {
+    /// `None` when flow control is off
+    bytes: Mutex<Option<usize>>,
+    granted: Notify,
+}
+
+impl Credit {
+    pub fn new(bytes: usize) -> Self {
+        Self {
+            bytes: Mutex::new(Some(bytes)),
+            granted: Notify::new(),
+        }
+    }
+
+    /// Credit that never runs out, for peers without flow control
+    pub fn unlimited() -> Self {
+        Self {
+            bytes: Mutex::new(None),
+            granted: Notify::new(),
+        }
+    }
+
+    /// Wait until some credit is left and return it, without consuming it
+    pub async fn available(&self) -> usize {
+        loop {
+            let granted = self.granted.notified();
+            match *self.bytes.lock().unwrap() {
+                None => return usize::MAX,
+                Some(0) => {}
+                Some(bytes) => return bytes,
+            }
+            granted.await;
+        }
+    }
+
+    /// Take `bytes` from the credit, false if they exceed it
+    pub fn consume(&self, bytes: usize) -> bool {
+        match &mut *self.bytes.lock().unwrap() {
+            None => true,
+            Some(left) if *left >= bytes => {
+                *left -= bytes;
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    /// Give `bytes` back to the sender
+    pub fn grant(&self, bytes: usize) {
+        if let Some(left) = &mut *self.bytes.lock().unwrap() {
+            *left = left.saturating_add(bytes);
+        }
+        self.granted.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_consume_within_credit() {
+        let credit = Credit::new(10);
+        assert!(credit.consume(6));
+        assert!(!credit.consume(5));
+        assert!(credit.consume(4));
+        credit.grant(3);
+        assert!(credit.consume(3));
+
+        let unlimited = Credit::unlimited();
+        assert!(unlimited.consume(usize::MAX));
+    }
+
+    #[tokio::test]
+    async fn test_available_waits_for_grant() {
+        let credit = Arc::new(Credit::new(4));
+        assert_eq!(credit.available().await, 4);
+        assert!(credit.consume(4));
+
+        let waiter = tokio::spawn({
+            let credit = credit.clone();
+            async move { credit.available().await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+
+        credit.grant(7);
+        assert_eq!(waiter.await.unwrap(), 7);
+        assert_eq!(Credit::unlimited().available().await, usize::MAX);
+    }
+}
@@ -0,0 +1,113 @@
+//! # Frame Checksums
+//!
+//! Transports that may corrupt bytes (serial lines, flaky middleboxes) can
+//! have every frame carry a checksum of its payload. Each algorithm a build
+//! supports is registered as an extension adding no requests. The remote
+//! checksums what it sends with the preferred algorithm both sides
+//! negotiated, and a channel receiving a checksummed frame checksums with
+//! its algorithm from then on and requires checksums of the peer, so the
+//! client follows without a separate announcement.
+//!
+//! - [`FrameChecksum::Crc32c`]: hardware accelerated where the CPU allows
+//!   (see [`crate::checksum`])
+//! - [`FrameChecksum::Xxh3`]: 64-bit xxHash3, fast everywhere (`xxhash`
+//!   feature)
+
+use super::extension::ExtensionId;
+use crate::checksum;
+
+/// A checksum algorithm for frame payloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameChecksum {
+    Crc32c,
+    #[cfg(feature = "xxhash")]
+    Xxh3,
+}
+
+/// Supported algorithms, most preferred first
+const PREFERENCE: &[FrameChecksum] = &[
+    #[cfg(feature = "xxhash")]
+    FrameChecksum::Xxh3,
+    FrameChecksum::Crc32c,
+];
+
+impl FrameChecksum {
+    /// Number identifying the algorithm in frame headers; 1 marks frames
+    /// without a checksum
+    pub fn id(self) -> u8 {
+        match self {
+            Self::Crc32c => 2,
+            #[cfg(feature = "xxhash")]
+            Self::Xxh3 => 3,
+        }
+    }
+
+    /// Supported algorithm numbered `id`
+    pub fn from_id(id: u8) -> Option<Self> {
+        PREFERENCE
+            .iter()
+            .copied()
+            .find(|checksum| checksum.id() == id)
+    }
+
+    /// Extension negotiating the algorithm
+    pub fn extension(self) -> ExtensionId {
+        match self {
+            Self::Crc32c => super::extension::FRAME_CRC32C.id,
+            #[cfg(feature = "xxhash")]
+            Self::Xxh3 => super::extension::FRAME_XXHASH.id,
+        }
+    }
+
+    /// Preferred algorithm among the `negotiated` extensions
+    pub fn preferred(negotiated: &[ExtensionId]) -> Option<Self> {
+        PREFERENCE
+            .iter()
+            .copied()
+            .find(|checksum| negotiated.contains(&checksum.extension()))
+    }
+
+    /// Length in bytes of the checksum trailing each frame
+    pub fn trailer_len(self) -> usize {
+        match self {
+            Self::Crc32c => 4,
+            #[cfg(feature = "xxhash")]
+            Self::Xxh3 => 8,
+        }
+    }
+
+    /// Big endian checksum of `data`, [`trailer_len`](Self::trailer_len) bytes long
+    pub fn compute(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Crc32c => checksum::crc32c(data).to_be_bytes().to_vec(),
+            #[cfg(feature = "xxhash")]
+            Self::Xxh3 => xxhash_rust::xxh3::xxh3_64(data).to_be_bytes().to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::extension;
+
+    #[test]
+    fn test_checksums() {
+        for &checksum in PREFERENCE {
+            assert_eq!(FrameChecksum::from_id(checksum.id()), Some(checksum));
+            let sum = checksum.compute(b"payload");
+            assert_eq!(sum.len(), checksum.trailer_len());
+            assert_ne!(sum, checksum.compute(b"paylaod"));
+        }
+        assert_eq!(FrameChecksum::from_id(1), None);
+    }
+
+    #[test]
+    fn test_preferred() {
+        assert_eq!(FrameChecksum::preferred(&[]), None);
+        assert_eq!(
+            FrameChecksum::preferred(&extension::registered()),
+            PREFERENCE.first().copied()
+        );
+    }
+}
@@ -8,6 +8,26 @@
 //!
 //! - **Protocol**: Direct request-response communication between client and remote server
 //! - **Daemon Protocol**: Communication with local daemon for managing multiple sessions
+//! - **Session Authentication**: Token handshake and replay-protected frames for unsecured links
+//! - **Noise Encryption**: Encrypted frames and mutual authentication by static
+//!   keys for links in cleartext
+//! - **Binary Attachments**: Raw frames carrying binary fields outside the message envelope
+//! - **Codecs**: JSON, compact CBOR (`cbor` feature) or MessagePack (`msgpack`
+//!   feature, also negotiable at handshake) encoding of messages
+//! - **Compression**: zstd or lz4 compression of large messages, negotiated
+//!   as extensions (`zstd` and `lz4` features)
+//! - **Frame Checksums**: CRC32C or xxHash3 (`xxhash` feature) trailers
+//!   detecting corrupted frames, negotiated as extensions
+//! - **Extensions**: Numbered groups of optional requests negotiated after connecting
+//! - **Request Construction**: Validating constructors and builders of requests
+//! - **Commands**: Protocol features generated from one trait definition
+//! - **Flow Control**: Per-connection credits for forwarded data
+//! - **Server Requests**: Requests the remote sends the client, negotiated
+//!   as an extension
+//! - **Event Topics**: Events of the remote pushed to subscribed clients,
+//!   negotiated as an extension
+//! - **Fault Injection**: Delayed, dropped or failed requests for tests
+//!   (`fault-injection` feature)
 //!
 //! ## Design Philosophy
 //!
@@ -21,12 +41,23 @@
 //! ## Message Flow
 //!
 //! ```text
-//! Client → Server: Request (JSON over binary framing)
-//! Server → Client: Response (JSON over binary framing)
+//! Client → Server: Request (JSON or CBOR over binary framing)
+//! Server → Client: Response (JSON or CBOR over binary framing)
 //!
 //! For bidirectional data:
 //! Client → Server: PollData request (long polling)
 //! Server → Client: Response with buffered data items
+//!
+//! For requests returning many items:
+//! Server → Client: Batch { more: true } ... Batch { more: false }
+//!
+//! For requests of the remote (`server-requests` extension):
+//! Server → Client: ServerRequest, out of turn
+//! Client → Server: ServerReply echoing its id
+//!
+//! For subscribed topics (`subscriptions` extension):
+//! Client → Server: Subscribe { topic }
+//! Server → Client: Event, out of turn and unanswered
 //! ```
 //!
 //! ## Usage Example
@@ -38,10 +69,34 @@
 //! let response = protocol.send_request(ProtocolRequest::GetClipboard).await?;
 //! ```
 
+pub mod attachment;
+pub mod auth;
+pub mod batch;
 pub mod buffer;
+pub mod builder;
+pub mod codec;
+pub mod command;
+pub mod compression;
 pub mod daemon;
+pub mod extension;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+pub mod flow;
+pub mod frame_checksum;
+pub mod noise;
+pub mod query;
 pub mod request_response;
+pub mod server_request;
+pub mod topic;
 
 // Re-export main protocol types for convenient access
+pub use batch::ResponseBatcher;
 pub use buffer::ResponseBuffer;
-pub use request_response::{ProtocolRequest, ProtocolResponse, ResponseItem};
+pub use builder::{LaunchAppBuilder, ListFilesBuilder};
+pub use extension::{Extension, ExtensionId};
+pub use query::{Cursor, ListQuery};
+pub use request_response::{
+    CorrelationId, ErrorCode, ProtocolRequest, ProtocolResponse, ResponseItem,
+};
+pub use server_request::{ServerReply, ServerRequest, ServerRequestId};
+pub use topic::{Topic, TopicEvent};
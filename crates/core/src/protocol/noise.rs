@@ -0,0 +1,626 @@
+//! # Noise Encryption
+//!
+//! Confidentiality and mutual authentication for channels whose transport
+//! provides neither, e.g. direct TCP, using the Noise protocol
+//! (`Noise_XX_25519_ChaChaPoly_BLAKE2s`). Each side holds a static X25519
+//! key pair and accepts the peer only when its public key is trusted, so no
+//! secret is shared between the two.
+//!
+//! ## Handshake
+//!
+//! ```text
+//! Client → Server: e
+//! Server → Client: e, ee, s, es
+//! Client → Server: s, se
+//! ```
+//!
+//! Each handshake message is one frame. The client checks the server's key
+//! before sending its own, and the server checks the client's key before
+//! serving it.
+//!
+//! ## Encrypted Frames
+//!
+//! ```text
+//! - 8 bytes: sequence number (big endian), the nonce of the frame
+//! - N bytes: ChaCha20-Poly1305 encrypted payload
+//! - 16 bytes: authentication tag
+//! ```
+//!
+//! Sequence numbers follow the rules of [`crate::protocol::auth`]: a frame
+//! must carry exactly the next one, except that the sequence may skip ahead
+//! while a corrupted channel recovers. Each direction is keyed separately, so
+//! frames reflected back to their sender fail to decrypt.
+//!
+//! ## Keys
+//!
+//! A key file holds the private key as hex. Public keys are shown and
+//! configured as hex too.
+//!
+//! A key file may instead be sealed to a key the ssh-agent holds, e.g. a
+//! PKCS#11 token's, so that it is useless without the token:
+//!
+//! ```text
+//! sealed <challenge> <public key> <private key ^ derived secret>
+//! ```
+//!
+//! The secret is derived from the agent's signature of the random
+//! challenge, so only keys signing deterministically can seal: RSA and
+//! Ed25519, not ECDSA or FIDO2 (`sk-`) keys.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use snow::params::DHChoice;
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+use snow::{Builder, HandshakeState, StatelessTransportState};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use super::auth::{self, SEQUENCE_LEN};
+use crate::error::{AuthError, ProtocolError, Result, YuhaError};
+use crate::ssh_agent::AgentKey;
+
+/// Noise protocol name, fixing the handshake pattern and primitives
+pub const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+/// Length of private and public keys in bytes
+pub const KEY_LEN: usize = 32;
+/// Length of the authentication tag of an encrypted frame in bytes
+pub const TAG_LEN: usize = 16;
+/// Bytes added to every encrypted frame
+pub const FRAME_OVERHEAD: usize = SEQUENCE_LEN + TAG_LEN;
+/// Largest payload of an encrypted frame, bounded by the Noise message limit
+pub const MAX_PAYLOAD_LEN: usize = u16::MAX as usize - TAG_LEN;
+
+/// Hashed into every handshake so it cannot be taken for another protocol's
+const PROLOGUE: &[u8] = b"yuha noise 1";
+
+/// Largest handshake message: two keys, a tag and room to spare
+const MAX_HANDSHAKE_LEN: usize = 256;
+
+/// First word of a sealed key file
+const SEALED: &str = "sealed";
+/// Context of the secret a sealed key file is encrypted with
+const SEAL_CONTEXT: &str = "yuha 2026-10-17 noise key file seal";
+
+fn builder<'a>() -> Builder<'a> {
+    Builder::new(NOISE_PARAMS.parse().expect("valid Noise parameters")).prologue(PROLOGUE)
+}
+
+fn handshake_failed(reason: String) -> YuhaError {
+    AuthError::InvalidCredentials { reason }.into()
+}
+
+/// Static public key identifying a party
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NoisePublicKey([u8; KEY_LEN]);
+
+impl NoisePublicKey {
+    pub fn as_bytes(&self) -> &[u8; KEY_LEN] {
+        &self.0
+    }
+}
+
+impl From<[u8; KEY_LEN]> for NoisePublicKey {
+    fn from(key: [u8; KEY_LEN]) -> Self {
+        Self(key)
+    }
+}
+
+impl fmt::Display for NoisePublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+impl fmt::Debug for NoisePublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NoisePublicKey({})", self)
+    }
+}
+
+impl FromStr for NoisePublicKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        parse_key(s).map(Self)
+    }
+}
+
+impl Serialize for NoisePublicKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for NoisePublicKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Parse a key written as hex
+fn parse_key(s: &str) -> std::result::Result<[u8; KEY_LEN], String> {
+    let s = s.trim();
+    if s.len() != KEY_LEN * 2 || !s.is_ascii() {
+        return Err(format!("Expected {} hex digits", KEY_LEN * 2));
+    }
+    let mut key = [0u8; KEY_LEN];
+    for (byte, digits) in key.iter_mut().zip(s.as_bytes().chunks(2)) {
+        let digits = std::str::from_utf8(digits).expect("checked ASCII");
+        *byte = u8::from_str_radix(digits, 16).map_err(|_| format!("Invalid hex: {}", digits))?;
+    }
+    Ok(key)
+}
+
+/// Static key pair of a party
+#[derive(Clone)]
+pub struct NoiseKeypair {
+    private: [u8; KEY_LEN],
+    public: NoisePublicKey,
+}
+
+impl fmt::Debug for NoiseKeypair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NoiseKeypair")
+            .field("public", &self.public)
+            .finish_non_exhaustive()
+    }
+}
+
+impl NoiseKeypair {
+    /// Generate a fresh random key pair
+    pub fn generate() -> Self {
+        let keypair = builder()
+            .generate_keypair()
+            .expect("the default resolver generates X25519 keys");
+        let private = keypair.private.try_into().expect("X25519 key length");
+        Self::from_private(private)
+    }
+
+    /// Key pair of the private key `private`
+    pub fn from_private(private: [u8; KEY_LEN]) -> Self {
+        let mut dh = DefaultResolver
+            .resolve_dh(&DHChoice::Curve25519)
+            .expect("the default resolver supports X25519");
+        dh.set(&private);
+        let public = dh.pubkey().try_into().expect("X25519 key length");
+        Self {
+            private,
+            public: NoisePublicKey(public),
+        }
+    }
+
+    /// Read the key file at `path`
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let hex = std::fs::read_to_string(path)?;
+        let private = parse_key(&hex).map_err(|reason| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid key file {}: {}", path.display(), reason),
+            )
+        })?;
+        Ok(Self::from_private(private))
+    }
+
+    /// Read the key file at `path`, first generating it if there is none
+    ///
+    /// A generated file is only readable by its owner.
+    pub fn load_or_generate(path: &Path) -> io::Result<Self> {
+        if path.exists() {
+            return Self::load(path);
+        }
+        let keypair = Self::generate();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let hex = NoisePublicKey(keypair.private).to_string();
+        io::Write::write_all(&mut options.open(path)?, format!("{}\n", hex).as_bytes())?;
+        Ok(keypair)
+    }
+
+    /// Read the key file at `path` sealed to the agent's `key`, first
+    /// generating it if there is none or sealing it if it holds a plain key
+    ///
+    /// Unsealing, and sealing which signs twice to check the signatures
+    /// are deterministic, may wait for the token's PIN or a touch.
+    pub fn load_or_generate_sealed(path: &Path, key: &AgentKey) -> io::Result<Self> {
+        Self::load_or_generate_with(path, |data| key.sign(data))
+    }
+
+    fn load_or_generate_with(
+        path: &Path,
+        mut sign: impl FnMut(&[u8]) -> io::Result<Vec<u8>>,
+    ) -> io::Result<Self> {
+        let keypair = match std::fs::read_to_string(path) {
+            Ok(text) => match text.strip_prefix(SEALED) {
+                Some(fields) => return Self::unseal(fields, &mut sign, path),
+                None => Self::load(path)?,
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Self::generate(),
+            Err(e) => return Err(e),
+        };
+        let sealed = keypair.seal(&mut sign)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Replaces a plain key file only once the sealed one is complete
+        let partial = path.with_extension("sealing");
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        io::Write::write_all(&mut options.open(&partial)?, sealed.as_bytes())?;
+        std::fs::rename(&partial, path)?;
+        Ok(keypair)
+    }
+
+    /// Contents of a key file sealed with the signatures of `sign`
+    fn seal(&self, sign: &mut impl FnMut(&[u8]) -> io::Result<Vec<u8>>) -> io::Result<String> {
+        let challenge: [u8; KEY_LEN] = rand::random();
+        let signature = sign(&challenge)?;
+        if sign(&challenge)? != signature {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The key signs differently every time, so it cannot seal a key file; use an RSA or Ed25519 key",
+            ));
+        }
+        let secret = blake3::derive_key(SEAL_CONTEXT, &signature);
+        let sealed: [u8; KEY_LEN] = std::array::from_fn(|i| self.private[i] ^ secret[i]);
+        Ok(format!(
+            "{} {} {} {}\n",
+            SEALED,
+            NoisePublicKey(challenge),
+            self.public,
+            NoisePublicKey(sealed)
+        ))
+    }
+
+    /// Key pair of the sealed key file at `path` holding `fields`
+    fn unseal(
+        fields: &str,
+        sign: &mut impl FnMut(&[u8]) -> io::Result<Vec<u8>>,
+        path: &Path,
+    ) -> io::Result<Self> {
+        let invalid = |reason: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid key file {}: {}", path.display(), reason),
+            )
+        };
+        let fields = fields
+            .split_whitespace()
+            .map(parse_key)
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(invalid)?;
+        let [challenge, public, sealed] = fields[..] else {
+            return Err(invalid(
+                "Expected a challenge, a public and a sealed key".to_string(),
+            ));
+        };
+        let secret = blake3::derive_key(SEAL_CONTEXT, &sign(&challenge)?);
+        let keypair = Self::from_private(std::array::from_fn(|i| sealed[i] ^ secret[i]));
+        if keypair.public != NoisePublicKey(public) {
+            return Err(invalid(
+                "The agent's key does not unseal it, is it the key it was sealed to?".to_string(),
+            ));
+        }
+        Ok(keypair)
+    }
+
+    pub fn public(&self) -> NoisePublicKey {
+        self.public
+    }
+}
+
+/// Key pair of a party and the peer keys it accepts
+#[derive(Debug, Clone)]
+pub struct NoiseConfig {
+    pub keypair: NoiseKeypair,
+    pub trusted: Vec<NoisePublicKey>,
+}
+
+impl NoiseConfig {
+    pub fn new(keypair: NoiseKeypair, trusted: Vec<NoisePublicKey>) -> Self {
+        Self { keypair, trusted }
+    }
+}
+
+/// State of a handshake in progress
+pub struct NoiseHandshake {
+    state: HandshakeState,
+    trusted: Vec<NoisePublicKey>,
+    peer: Option<NoisePublicKey>,
+}
+
+impl NoiseHandshake {
+    /// Start the handshake as the connecting side, which sends first
+    pub fn initiator(config: &NoiseConfig) -> Result<Self> {
+        Self::new(config, true)
+    }
+
+    /// Start the handshake as the accepting side
+    pub fn responder(config: &NoiseConfig) -> Result<Self> {
+        Self::new(config, false)
+    }
+
+    fn new(config: &NoiseConfig, initiator: bool) -> Result<Self> {
+        let builder = builder().local_private_key(&config.keypair.private);
+        let state = if initiator {
+            builder.build_initiator()
+        } else {
+            builder.build_responder()
+        }
+        .map_err(|e| handshake_failed(format!("Failed to start Noise handshake: {}", e)))?;
+        Ok(Self {
+            state,
+            trusted: config.trusted.clone(),
+            peer: None,
+        })
+    }
+
+    /// Whether the handshake is complete
+    pub fn is_finished(&self) -> bool {
+        self.state.is_handshake_finished()
+    }
+
+    /// Next handshake message to send
+    pub fn write(&mut self) -> Result<Bytes> {
+        let mut message = vec![0u8; MAX_HANDSHAKE_LEN];
+        let len = self
+            .state
+            .write_message(&[], &mut message)
+            .map_err(|e| handshake_failed(format!("Noise handshake failed: {}", e)))?;
+        message.truncate(len);
+        Ok(message.into())
+    }
+
+    /// Process a handshake message received from the peer, rejecting it when
+    /// it reveals an untrusted key
+    pub fn read(&mut self, message: &[u8]) -> Result<()> {
+        let mut payload = vec![0u8; MAX_HANDSHAKE_LEN];
+        self.state
+            .read_message(message, &mut payload)
+            .map_err(|e| handshake_failed(format!("Noise handshake failed: {}", e)))?;
+
+        if self.peer.is_none()
+            && let Some(key) = self.state.get_remote_static()
+        {
+            let key = NoisePublicKey(key.try_into().expect("X25519 key length"));
+            if !self.trusted.contains(&key) {
+                return Err(handshake_failed(format!("Untrusted peer key {}", key)));
+            }
+            self.peer = Some(key);
+        }
+        Ok(())
+    }
+
+    /// Session of the finished handshake
+    pub fn finish(self) -> Result<NoiseSession> {
+        let peer = self
+            .peer
+            .ok_or_else(|| handshake_failed("Peer sent no static key".to_string()))?;
+        let transport = self
+            .state
+            .into_stateless_transport_mode()
+            .map_err(|e| handshake_failed(format!("Noise handshake incomplete: {}", e)))?;
+        Ok(NoiseSession {
+            transport: Arc::new(transport),
+            peer,
+            send_sequence: 0,
+            receive_sequence: 0,
+        })
+    }
+}
+
+/// Per-session state for encrypting and decrypting frames
+#[derive(Clone)]
+pub struct NoiseSession {
+    transport: Arc<StatelessTransportState>,
+    peer: NoisePublicKey,
+    send_sequence: u64,
+    receive_sequence: u64,
+}
+
+impl fmt::Debug for NoiseSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NoiseSession")
+            .field("peer", &self.peer)
+            .field("send_sequence", &self.send_sequence)
+            .field("receive_sequence", &self.receive_sequence)
+            .finish_non_exhaustive()
+    }
+}
+
+impl NoiseSession {
+    /// Static key the peer proved to hold
+    pub fn peer(&self) -> NoisePublicKey {
+        self.peer
+    }
+
+    /// Encrypt an outgoing payload of at most [`MAX_PAYLOAD_LEN`] bytes
+    /// under the next sequence number
+    pub fn seal(&mut self, payload: &[u8]) -> Bytes {
+        let sequence = self.send_sequence;
+        self.send_sequence += 1;
+
+        let mut frame = BytesMut::zeroed(payload.len() + FRAME_OVERHEAD);
+        (&mut frame[..SEQUENCE_LEN]).put_u64(sequence);
+        self.transport
+            .write_message(sequence, payload, &mut frame[SEQUENCE_LEN..])
+            .expect("payload within the Noise message limit");
+        frame.freeze()
+    }
+
+    /// Decrypt an incoming frame and return its payload
+    pub fn open(&mut self, frame: Bytes) -> Result<Bytes> {
+        let (sequence, payload) = self.decrypt(&frame)?;
+        if sequence != self.receive_sequence {
+            return Err(ProtocolError::ReplayDetected {
+                expected: self.receive_sequence,
+                actual: sequence,
+            }
+            .into());
+        }
+        self.receive_sequence += 1;
+        Ok(payload)
+    }
+
+    /// Decrypt an incoming frame that may follow lost ones
+    ///
+    /// Used while resynchronizing a corrupted channel: the sequence may skip
+    /// ahead, by at most [`MAX_SEQUENCE_GAP`](auth::MAX_SEQUENCE_GAP)
+    /// frames, but not go back, so replays are still rejected.
+    pub fn open_after_gap(&mut self, frame: Bytes) -> Result<Bytes> {
+        let (sequence, payload) = self.decrypt(&frame)?;
+        auth::check_gap(self.receive_sequence, sequence)?;
+        self.receive_sequence = sequence + 1;
+        Ok(payload)
+    }
+
+    fn decrypt(&self, frame: &[u8]) -> Result<(u64, Bytes)> {
+        if frame.len() < FRAME_OVERHEAD {
+            return Err(ProtocolError::IntegrityCheckFailed {
+                reason: format!("Encrypted frame too short: {} bytes", frame.len()),
+            }
+            .into());
+        }
+
+        let (sequence, ciphertext) = frame.split_at(SEQUENCE_LEN);
+        let sequence = u64::from_be_bytes(sequence.try_into().expect("sequence prefix length"));
+        let mut payload = vec![0u8; ciphertext.len()];
+        let len = self
+            .transport
+            .read_message(sequence, ciphertext, &mut payload)
+            .map_err(|_| ProtocolError::IntegrityCheckFailed {
+                reason: format!("Failed to decrypt frame {}", sequence),
+            })?;
+        payload.truncate(len);
+        Ok((sequence, payload.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake(
+        client: &NoiseConfig,
+        server: &NoiseConfig,
+    ) -> Result<(NoiseSession, NoiseSession)> {
+        let mut initiator = NoiseHandshake::initiator(client)?;
+        let mut responder = NoiseHandshake::responder(server)?;
+        responder.read(&initiator.write()?)?;
+        initiator.read(&responder.write()?)?;
+        responder.read(&initiator.write()?)?;
+        assert!(initiator.is_finished() && responder.is_finished());
+        Ok((initiator.finish()?, responder.finish()?))
+    }
+
+    fn configs() -> (NoiseConfig, NoiseConfig) {
+        let client = NoiseKeypair::generate();
+        let server = NoiseKeypair::generate();
+        (
+            NoiseConfig::new(client.clone(), vec![server.public()]),
+            NoiseConfig::new(server, vec![client.public()]),
+        )
+    }
+
+    #[test]
+    fn test_encrypted_roundtrip() {
+        let (client_config, server_config) = configs();
+        let (mut client, mut server) = handshake(&client_config, &server_config).unwrap();
+        assert_eq!(client.peer(), server_config.keypair.public());
+        assert_eq!(server.peer(), client_config.keypair.public());
+
+        let frame = client.seal(b"secret payload");
+        assert_eq!(frame.len(), b"secret payload".len() + FRAME_OVERHEAD);
+        assert!(!frame.windows(6).any(|window| window == b"secret"));
+        assert_eq!(
+            server.open(frame.clone()).unwrap(),
+            Bytes::from_static(b"secret payload")
+        );
+
+        // Replayed and reflected frames are rejected
+        assert!(server.open(frame.clone()).is_err());
+        assert!(client.open(frame).is_err());
+        let reply = server.seal(&[0u8; MAX_PAYLOAD_LEN]);
+        assert_eq!(client.open(reply).unwrap().len(), MAX_PAYLOAD_LEN);
+
+        let skipped = client.seal(b"lost");
+        let after = client.seal(b"after the gap");
+        assert!(server.open(after.clone()).is_err());
+        server.open_after_gap(after).unwrap();
+        assert!(server.open_after_gap(skipped).is_err());
+        client.send_sequence += auth::MAX_SEQUENCE_GAP + 1;
+        assert!(server.open_after_gap(client.seal(b"far ahead")).is_err());
+    }
+
+    #[test]
+    fn test_untrusted_keys_rejected() {
+        let (client_config, mut server_config) = configs();
+        server_config.trusted = vec![NoiseKeypair::generate().public()];
+        assert!(handshake(&client_config, &server_config).is_err());
+
+        let (mut client_config, server_config) = configs();
+        client_config.trusted.clear();
+        assert!(handshake(&client_config, &server_config).is_err());
+    }
+
+    #[test]
+    fn test_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keys/noise.key");
+        let generated = NoiseKeypair::load_or_generate(&path).unwrap();
+        let loaded = NoiseKeypair::load_or_generate(&path).unwrap();
+        assert_eq!(generated.public(), loaded.public());
+
+        let key = generated.public().to_string();
+        assert_eq!(key.parse::<NoisePublicKey>().unwrap(), generated.public());
+        assert!("abc".parse::<NoisePublicKey>().is_err());
+        std::fs::write(&path, "not a key").unwrap();
+        assert!(NoiseKeypair::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_sealed_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("noise.key");
+        let plain = NoiseKeypair::load_or_generate(&path).unwrap();
+        let sign = |data: &[u8]| Ok(blake3::hash(data).as_bytes().to_vec());
+
+        // A plain key file is sealed in place, keeping its key
+        let sealed = NoiseKeypair::load_or_generate_with(&path, sign).unwrap();
+        assert_eq!(sealed.public(), plain.public());
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("sealed "));
+        assert!(!contents.contains(&NoisePublicKey(plain.private).to_string()));
+        assert!(NoiseKeypair::load(&path).is_err());
+
+        let unsealed = NoiseKeypair::load_or_generate_with(&path, sign).unwrap();
+        assert_eq!(unsealed.public(), plain.public());
+        let other_key =
+            |data: &[u8]| Ok(blake3::hash(&[data, b"other"].concat()).as_bytes().to_vec());
+        assert!(NoiseKeypair::load_or_generate_with(&path, other_key).is_err());
+
+        // Signatures that differ every time cannot seal
+        let mut counter = 0u8;
+        let randomized = |data: &[u8]| {
+            counter += 1;
+            Ok([data, &[counter]].concat())
+        };
+        let fresh = dir.path().join("fresh.key");
+        assert!(NoiseKeypair::load_or_generate_with(&fresh, randomized).is_err());
+        assert!(!fresh.exists());
+        let generated = NoiseKeypair::load_or_generate_with(&fresh, sign).unwrap();
+        let loaded = NoiseKeypair::load_or_generate_with(&fresh, sign).unwrap();
+        assert_eq!(generated.public(), loaded.public());
+    }
+}
@@ -0,0 +1,197 @@
+//! # List Queries
+//!
+//! Paging and filtering for listing requests, applied on the remote so that
+//! clients on slow links only download the page they show.
+//!
+//! A listing answered with `Data` that was cut at the query's limit carries
+//! the [`Cursor`] of the next page; passing it back in
+//! [`ListQuery::cursor`] continues where the page ended, so clients can walk
+//! large listings page by page.
+
+use serde::{Deserialize, Serialize};
+
+/// Page and filter of a listing request
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListQuery {
+    /// Number of matching entries to skip
+    #[serde(default)]
+    pub offset: usize,
+    /// Maximum number of entries to return
+    #[serde(default)]
+    pub limit: Option<usize>,
+    /// Only return entries whose name contains this text
+    #[serde(default)]
+    pub filter: Option<String>,
+    /// Continue after the page this cursor was returned with, instead of
+    /// skipping `offset` entries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<Cursor>,
+}
+
+/// Opaque position in a listing, returned with a page to fetch the next one
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Cursor(String);
+
+impl Cursor {
+    /// Cursor of the page starting after `skipped` matching entries
+    pub(crate) fn at(skipped: usize) -> Self {
+        Cursor(skipped.to_string())
+    }
+
+    fn skipped(&self) -> Option<usize> {
+        self.0.parse().ok()
+    }
+}
+
+impl ListQuery {
+    /// Whether an entry named `name` passes the filter
+    pub fn matches(&self, name: &str) -> bool {
+        self.filter
+            .as_deref()
+            .is_none_or(|filter| name.contains(filter))
+    }
+
+    /// Start paging through matching entries
+    pub fn pager(&self) -> Pager<'_> {
+        // A cursor that was not returned by a pager starts past the end
+        let start = match &self.cursor {
+            Some(cursor) => cursor.skipped().unwrap_or(usize::MAX),
+            None => self.offset,
+        };
+        Pager {
+            query: self,
+            start,
+            skipped: 0,
+            taken: 0,
+            more: false,
+        }
+    }
+}
+
+/// Tracks a listing's position within a [`ListQuery`] page, for producers
+/// that generate entries lazily and should stop once the page is full
+#[derive(Debug)]
+pub struct Pager<'a> {
+    query: &'a ListQuery,
+    /// Matching entries before the page
+    start: usize,
+    skipped: usize,
+    taken: usize,
+    /// Whether a matching entry was offered past the full page
+    more: bool,
+}
+
+impl Pager<'_> {
+    /// Whether an entry named `name` belongs to the page
+    pub fn accept(&mut self, name: &str) -> bool {
+        if !self.query.matches(name) {
+            return false;
+        }
+        if self.is_full() {
+            self.more = true;
+            return false;
+        }
+        if self.skipped < self.start {
+            self.skipped += 1;
+            return false;
+        }
+        self.taken += 1;
+        true
+    }
+
+    /// Whether the page is complete, so no further entries can be accepted
+    pub fn is_full(&self) -> bool {
+        self.query.limit.is_some_and(|limit| self.taken >= limit)
+    }
+
+    /// Cursor of the next page, if a matching entry was offered after the
+    /// page was full
+    pub fn next_cursor(&self) -> Option<Cursor> {
+        self.more.then(|| Cursor::at(self.start + self.taken))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page<'a>(query: &ListQuery, names: &[&'a str]) -> Vec<&'a str> {
+        let mut pager = query.pager();
+        names
+            .iter()
+            .copied()
+            .filter(|name| pager.accept(name))
+            .collect()
+    }
+
+    #[test]
+    fn test_pager() {
+        let names = ["a.rs", "b.txt", "c.rs", "d.rs", "e.rs"];
+
+        assert_eq!(page(&ListQuery::default(), &names), names);
+        assert_eq!(
+            page(
+                &ListQuery {
+                    offset: 1,
+                    limit: Some(2),
+                    filter: Some(".rs".to_string()),
+                    ..Default::default()
+                },
+                &names
+            ),
+            vec!["c.rs", "d.rs"]
+        );
+        assert!(
+            page(
+                &ListQuery {
+                    offset: 10,
+                    ..Default::default()
+                },
+                &names
+            )
+            .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_pager_is_full() {
+        let query = ListQuery {
+            limit: Some(1),
+            ..Default::default()
+        };
+        let mut pager = query.pager();
+        assert!(!pager.is_full());
+        assert!(pager.accept("a"));
+        assert!(pager.is_full());
+        assert!(!pager.accept("b"));
+    }
+
+    #[test]
+    fn test_cursor_continues_pages() {
+        let names = ["a.rs", "b.txt", "c.rs", "d.rs", "e.rs"];
+        let mut query = ListQuery {
+            offset: 1,
+            limit: Some(2),
+            filter: Some(".rs".to_string()),
+            ..Default::default()
+        };
+
+        let mut pages = Vec::new();
+        loop {
+            let mut pager = query.pager();
+            let page: Vec<_> = names.iter().filter(|name| pager.accept(name)).collect();
+            pages.push(page);
+            let Some(cursor) = pager.next_cursor() else {
+                break;
+            };
+            query.cursor = Some(cursor);
+        }
+        // The last page ends the listing, so it has no next cursor
+        assert_eq!(pages, [vec![&"c.rs", &"d.rs"], vec![&"e.rs"]]);
+
+        // A cursor not returned by a pager yields nothing rather than restarting
+        query.cursor = Some(Cursor("garbage".to_string()));
+        assert!(page(&query, &names).is_empty());
+    }
+}
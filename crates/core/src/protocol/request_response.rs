@@ -10,16 +10,42 @@
 //!
 //! - **PollData**: Long polling for receiving server-side data
 //! - **Port Forwarding**: Start/stop port forwarding and data transfer
-//! - **Clipboard Operations**: Get/set clipboard content
+//! - **Clipboard Operations**: Get/set clipboard content, with format negotiation for rich content
 //! - **Browser Operations**: Open URLs in the default browser
+//! - **Applications**: Launch GUI applications on the remote desktop session, or
+//!   open a path with the application handling its extension
+//! - **File Operations**: List files, read them in chunks or ranges, write them
+//!   back and hash them for comparison
+//! - **Disk Usage**: Sizes of remote directory trees, computed on the remote
+//! - **Removing and Moving**: Remove or move remote paths, optionally keeping
+//!   what they destroy in the remote's trash to be restored later
+//! - **Listings**: File and port forward listings accept a [`ListQuery`] page and filter;
+//!   a `Data` page cut at the limit carries the [`Cursor`] of the next one
+//! - **Diagnostics**: Retrieve the server's slow request log, probe installed tools
+//! - **Tasks**: List and cancel the background tasks of the session
+//! - **Usage**: Resources used by the session's workspace, against its quotas
+//! - **Session State**: Snapshot forwards and topic subscriptions, and re-apply
+//!   them to another host or a new server
+//! - **Hello**: Negotiate optional [`super::extension`]s and select the
+//!   server's workspace right after connecting
+//! - **Commands**: Features defined by a trait, see [`super::command`]
 //!
 //! ## Response Format
 //!
 //! All responses follow a consistent format:
 //!
 //! - **Success**: Operation completed successfully
-//! - **Error**: Operation failed with error message
+//! - **Error**: Operation failed, with an [`ErrorCode`] to branch on, a
+//!   message and optional details
 //! - **Data**: Contains multiple data items from polling
+//! - **Batch**: One part of a streamed multi-item response (see [`super::batch`])
+//!
+//! ## Compatibility
+//!
+//! A request of a variant this build does not know, e.g. one added by a
+//! newer client, decodes as [`ProtocolRequest::Unknown`] instead of failing,
+//! and is answered with [`ProtocolResponse::Unsupported`] so the client can
+//! fall back.
 //!
 //! ## Usage Example
 //!
@@ -33,22 +59,60 @@
 //! let response = protocol.send_request(request).await?;
 //!
 //! match response {
-//!     ProtocolResponse::Data { items } => {
+//!     ProtocolResponse::Data { items, .. } => {
 //!         // Process clipboard data
 //!     }
-//!     ProtocolResponse::Error { message } => {
-//!         // Handle error
+//!     ProtocolResponse::Error { code, message, .. } => {
+//!         // Handle error, e.g. retry if `code.is_retriable()`
 //!     }
 //!     _ => {}
 //! }
 //! ```
 
 use bytes::Bytes;
-use serde::{Deserialize, Serialize};
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::time::{Duration, SystemTime};
+
+use super::server_request::{ServerReply, ServerRequest, ServerRequestId};
+use super::topic::{Topic, TopicEvent};
+use super::{Cursor, ExtensionId, ListQuery};
+use crate::access_log::AccessEntry;
+use crate::checksum::HashAlgorithm;
+use crate::clipboard::{ClipboardFormat, ClipboardItem};
+use crate::slow_log::SlowRequest;
+
+/// Largest file chunk a single `ReadFileChunk` returns, keeping the JSON-encoded
+/// response within one frame
+pub const MAX_FILE_CHUNK_LEN: u32 = 8 * 1024;
+
+/// Largest range a single `ReadFileRange` returns; longer responses are
+/// fragmented, so this only bounds what the remote holds in memory
+pub const MAX_FILE_RANGE_LEN: u32 = 1024 * 1024;
+
+/// Tools probed by a `ProbeTools` request that names none
+pub const COMMON_TOOLS: &[&str] = &["git", "docker", "python3", "rustc", "cargo"];
+
+/// Number a client gives a request, echoed by every response to it so
+/// responses can be matched while several requests are in flight
+pub type CorrelationId = u64;
 
 /// Protocol request types
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub enum ProtocolRequest {
+    /// Offer the extensions the client supports and select a workspace of
+    /// the server; answered with `Extensions`, and `BinaryHash` when the
+    /// server can hash its own binary
+    Hello {
+        extensions: Vec<ExtensionId>,
+        #[serde(default)]
+        workspace: Option<String>,
+    },
     PollData,
     StartPortForward {
         local_port: u16,
@@ -60,30 +124,1120 @@ pub enum ProtocolRequest {
     },
     PortForwardData {
         connection_id: u32,
+        #[serde(with = "super::attachment")]
         data: Bytes,
     },
+    /// Let the remote send `bytes` more of a connection's data, once the
+    /// client consumed what it polled (see [`super::flow`])
+    GrantCredit {
+        connection_id: u32,
+        bytes: u32,
+    },
+    /// Let the remote send `batches` more `Batch` responses to the request
+    /// `id`, once the client consumed what it received (see [`super::flow`])
+    GrantBatches {
+        id: CorrelationId,
+        batches: u32,
+    },
     GetClipboard,
     SetClipboard {
         content: String,
     },
+    /// Get clipboard content in the best of the accepted formats (most preferred first)
+    GetClipboardData {
+        accept: Vec<ClipboardFormat>,
+    },
+    /// Set clipboard content with one or more representations
+    SetClipboardData {
+        items: Vec<ClipboardItem>,
+    },
     OpenBrowser {
         url: String,
     },
+    /// Start a GUI application on the remote desktop session, detached from
+    /// the server; answered with `AppLaunched`
+    LaunchApp {
+        command: Vec<String>,
+        #[serde(default)]
+        display_env: DisplayEnv,
+        #[serde(default)]
+        sandbox: Sandbox,
+    },
+    /// Open a remote path with the handler configured for its extension on
+    /// the remote; answered with `AppLaunched`
+    OpenPath {
+        path: String,
+    },
+    /// List the files under the given paths, descending into directories;
+    /// answered with streamed `Batch` responses. The query filters on the
+    /// relative path and pages in path order.
+    ListFiles {
+        paths: Vec<String>,
+        #[serde(default)]
+        query: ListQuery,
+    },
+    /// List the entries of the directory `path` without descending into
+    /// them; answered with streamed `Batch` responses of `DirectoryEntry`
+    /// items in name order
+    ListDirectory {
+        path: String,
+    },
+    /// Read up to `len` bytes (capped at [`MAX_FILE_CHUNK_LEN`]) starting at `offset`
+    ReadFileChunk {
+        path: String,
+        offset: u64,
+        len: u32,
+    },
+    /// Read up to `len` bytes (capped at [`MAX_FILE_RANGE_LEN`]) starting at
+    /// `offset`, also reporting the current size of the file so clients can
+    /// tail a growing or truncated file
+    ReadFileRange {
+        path: String,
+        offset: u64,
+        len: u32,
+    },
+    /// Hash the file at `path`, or every regular file under it when it is a
+    /// directory; answered with streamed `Batch` responses of `FileHash`
+    /// items in path order
+    HashPath {
+        path: String,
+        #[serde(default)]
+        algo: HashAlgorithm,
+    },
+    /// Sum the sizes of the files under `path` per directory, down to
+    /// `depth` levels below it; answered with streamed `Batch` responses of
+    /// `DirectoryUsage` items, each directory after those below it, and
+    /// `DiskUsageProgress` items while a large tree is scanned
+    DiskUsage {
+        path: String,
+        #[serde(default)]
+        depth: u32,
+    },
+    /// Write `data` at `offset` of a staged copy of `path`, starting a new
+    /// copy at offset 0; the chunk marked `last` moves the copy over `path`.
+    /// `crc32c` covers `data`. With `expected_digest` set, the last chunk is
+    /// refused with `Conflict` unless `path` still has that BLAKE3 digest.
+    WriteFileChunk {
+        path: String,
+        offset: u64,
+        #[serde(with = "super::attachment")]
+        data: Bytes,
+        crc32c: u32,
+        last: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expected_digest: Option<String>,
+    },
+    /// Remove the file or directory tree at `path`; with `trash` set it is
+    /// moved to the remote's trash instead and answered with its `Trashed`
+    /// entry
+    RemovePath {
+        path: String,
+        #[serde(default)]
+        trash: bool,
+    },
+    /// Move `from` to `to`, replacing a file there; with `trash` set, what is
+    /// at `to` is moved to the remote's trash first and answered with its
+    /// `Trashed` entry
+    MovePath {
+        from: String,
+        to: String,
+        #[serde(default)]
+        trash: bool,
+    },
+    /// Move the trash entry `id` back to where it was trashed from, or to
+    /// `to`; fails when something is there already
+    RestorePath {
+        id: String,
+        #[serde(default)]
+        to: Option<String>,
+    },
+    /// List the entries of the remote's trash, oldest first; answered with
+    /// `Trashed` items
+    ListTrash,
+    /// Get the requests recorded in the server's slow log, newest first
+    GetSlowLog,
+    /// Get the connections recorded in the server's access log, newest
+    /// first; answered with `Access` items
+    GetAccessLog,
+    /// Locate tools on the remote's `PATH` (or [`COMMON_TOOLS`] when empty)
+    /// and report their versions; answered with one `Tool` per tool
+    ProbeTools {
+        #[serde(default)]
+        tools: Vec<String>,
+    },
+    /// List active port forwards by local port; the query filters on
+    /// `remote_host:remote_port`
+    ListPortForwards {
+        #[serde(default)]
+        query: ListQuery,
+    },
+    /// List the background tasks of this session; answered with one `Task`
+    /// per task
+    ListTasks,
+    /// Cancel a background task of this session
+    CancelTask {
+        id: TaskId,
+    },
+    /// Get the resources used by this session's workspace; answered with
+    /// `Usage`
+    GetUsage,
+    /// Snapshot this session's restorable state; answered with `SessionState`
+    ExportSessionState,
+    /// Re-apply a snapshot: start its forwards and subscribe to its topics
+    ImportSessionState {
+        state: SessionState,
+    },
+    /// Stop accepting connections on a forward's port, leaving those open
+    /// running, so another remote on the same host can take the port over
+    ReleasePortForward {
+        local_port: u16,
+    },
+    /// Run a command defined with [`super::command::yuha_command`]; answered
+    /// with `CommandOutput`
+    Command {
+        name: String,
+        args: serde_json::Value,
+    },
+    /// Answer the `ServerRequest` numbered `id`; answered with `Success`
+    ServerReply {
+        id: ServerRequestId,
+        reply: ServerReply,
+    },
+    /// Have the events of `topic` pushed as `Event` responses; answered with
+    /// `Success`
+    Subscribe {
+        topic: Topic,
+    },
+    /// Stop the events of `topic`; answered with `Success`
+    Unsubscribe {
+        topic: Topic,
+    },
+    /// Hit the next `count` requests of `kind` with `fault`
+    #[cfg(feature = "fault-injection")]
+    InjectFault {
+        kind: String,
+        fault: super::fault::Fault,
+        count: u32,
+    },
+    /// A request of a variant this build does not know, named
+    /// `request_name`; answered with `Unsupported`
+    #[serde(skip)]
+    Unknown {
+        request_name: String,
+    },
+}
+
+impl ProtocolRequest {
+    /// Variant name, used to label metrics and logs
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProtocolRequest::Hello { .. } => "Hello",
+            ProtocolRequest::PollData => "PollData",
+            ProtocolRequest::StartPortForward { .. } => "StartPortForward",
+            ProtocolRequest::StopPortForward { .. } => "StopPortForward",
+            ProtocolRequest::PortForwardData { .. } => "PortForwardData",
+            ProtocolRequest::GrantCredit { .. } => "GrantCredit",
+            ProtocolRequest::GrantBatches { .. } => "GrantBatches",
+            ProtocolRequest::GetClipboard => "GetClipboard",
+            ProtocolRequest::SetClipboard { .. } => "SetClipboard",
+            ProtocolRequest::GetClipboardData { .. } => "GetClipboardData",
+            ProtocolRequest::SetClipboardData { .. } => "SetClipboardData",
+            ProtocolRequest::OpenBrowser { .. } => "OpenBrowser",
+            ProtocolRequest::LaunchApp { .. } => "LaunchApp",
+            ProtocolRequest::OpenPath { .. } => "OpenPath",
+            ProtocolRequest::ListFiles { .. } => "ListFiles",
+            ProtocolRequest::ListDirectory { .. } => "ListDirectory",
+            ProtocolRequest::ReadFileChunk { .. } => "ReadFileChunk",
+            ProtocolRequest::ReadFileRange { .. } => "ReadFileRange",
+            ProtocolRequest::HashPath { .. } => "HashPath",
+            ProtocolRequest::DiskUsage { .. } => "DiskUsage",
+            ProtocolRequest::WriteFileChunk { .. } => "WriteFileChunk",
+            ProtocolRequest::RemovePath { .. } => "RemovePath",
+            ProtocolRequest::MovePath { .. } => "MovePath",
+            ProtocolRequest::RestorePath { .. } => "RestorePath",
+            ProtocolRequest::ListTrash => "ListTrash",
+            ProtocolRequest::GetSlowLog => "GetSlowLog",
+            ProtocolRequest::GetAccessLog => "GetAccessLog",
+            ProtocolRequest::ProbeTools { .. } => "ProbeTools",
+            ProtocolRequest::ListPortForwards { .. } => "ListPortForwards",
+            ProtocolRequest::ListTasks => "ListTasks",
+            ProtocolRequest::CancelTask { .. } => "CancelTask",
+            ProtocolRequest::GetUsage => "GetUsage",
+            ProtocolRequest::ExportSessionState => "ExportSessionState",
+            ProtocolRequest::ImportSessionState { .. } => "ImportSessionState",
+            ProtocolRequest::ReleasePortForward { .. } => "ReleasePortForward",
+            ProtocolRequest::Command { .. } => "Command",
+            ProtocolRequest::ServerReply { .. } => "ServerReply",
+            ProtocolRequest::Subscribe { .. } => "Subscribe",
+            ProtocolRequest::Unsubscribe { .. } => "Unsubscribe",
+            #[cfg(feature = "fault-injection")]
+            ProtocolRequest::InjectFault { .. } => "InjectFault",
+            ProtocolRequest::Unknown { .. } => "Unknown",
+        }
+    }
+
+    /// Whether the request is an idempotent read whose response can be reused
+    pub fn is_cacheable(&self) -> bool {
+        matches!(
+            self,
+            ProtocolRequest::GetClipboard
+                | ProtocolRequest::GetClipboardData { .. }
+                | ProtocolRequest::GetSlowLog
+                | ProtocolRequest::ProbeTools { .. }
+                | ProtocolRequest::ListPortForwards { .. }
+        )
+    }
+
+    /// Whether the request moves port forward data, which clients and
+    /// remotes keep apart from control requests so a stalled forward never
+    /// holds those up
+    pub fn is_data(&self) -> bool {
+        matches!(
+            self,
+            ProtocolRequest::PollData
+                | ProtocolRequest::PortForwardData { .. }
+                | ProtocolRequest::GrantCredit { .. }
+        )
+    }
+
+    /// Kinds of the reads whose responses this request may change
+    pub fn invalidated_kinds(&self) -> &'static [&'static str] {
+        match self {
+            ProtocolRequest::SetClipboard { .. } | ProtocolRequest::SetClipboardData { .. } => {
+                &["GetClipboard", "GetClipboardData"]
+            }
+            ProtocolRequest::StartPortForward { .. }
+            | ProtocolRequest::StopPortForward { .. }
+            | ProtocolRequest::ReleasePortForward { .. }
+            | ProtocolRequest::CancelTask { .. } => &["ListPortForwards"],
+            ProtocolRequest::ImportSessionState { .. } => &["ListPortForwards"],
+            _ => &[],
+        }
+    }
+
+    /// Remote paths the request reads, writes or opens
+    pub fn paths(&self) -> Vec<&str> {
+        match self {
+            ProtocolRequest::ListFiles { paths, .. } => paths.iter().map(String::as_str).collect(),
+            ProtocolRequest::ListDirectory { path }
+            | ProtocolRequest::ReadFileChunk { path, .. }
+            | ProtocolRequest::ReadFileRange { path, .. }
+            | ProtocolRequest::HashPath { path, .. }
+            | ProtocolRequest::DiskUsage { path, .. }
+            | ProtocolRequest::WriteFileChunk { path, .. }
+            | ProtocolRequest::OpenPath { path }
+            | ProtocolRequest::RemovePath { path, .. } => vec![path],
+            ProtocolRequest::MovePath { from, to, .. } => vec![from, to],
+            ProtocolRequest::RestorePath { to, .. } => to.iter().map(String::as_str).collect(),
+            // Listed one by one so a new variant is not let past the
+            // workspace check until its paths are decided
+            ProtocolRequest::Hello { .. }
+            | ProtocolRequest::PollData
+            | ProtocolRequest::StartPortForward { .. }
+            | ProtocolRequest::StopPortForward { .. }
+            | ProtocolRequest::PortForwardData { .. }
+            | ProtocolRequest::GrantCredit { .. }
+            | ProtocolRequest::GrantBatches { .. }
+            | ProtocolRequest::GetClipboard
+            | ProtocolRequest::SetClipboard { .. }
+            | ProtocolRequest::GetClipboardData { .. }
+            | ProtocolRequest::SetClipboardData { .. }
+            | ProtocolRequest::OpenBrowser { .. }
+            | ProtocolRequest::LaunchApp { .. }
+            | ProtocolRequest::ListTrash
+            | ProtocolRequest::GetSlowLog
+            | ProtocolRequest::GetAccessLog
+            | ProtocolRequest::ProbeTools { .. }
+            | ProtocolRequest::ListPortForwards { .. }
+            | ProtocolRequest::ListTasks
+            | ProtocolRequest::CancelTask { .. }
+            | ProtocolRequest::GetUsage
+            | ProtocolRequest::ExportSessionState
+            | ProtocolRequest::ImportSessionState { .. }
+            | ProtocolRequest::ReleasePortForward { .. }
+            | ProtocolRequest::Command { .. }
+            | ProtocolRequest::ServerReply { .. }
+            | ProtocolRequest::Subscribe { .. }
+            | ProtocolRequest::Unsubscribe { .. }
+            | ProtocolRequest::Unknown { .. } => Vec::new(),
+            #[cfg(feature = "fault-injection")]
+            ProtocolRequest::InjectFault { .. } => Vec::new(),
+        }
+    }
+}
+
+impl Serialize for ProtocolRequest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            // As a unit variant, which decodes back to `Unknown`
+            ProtocolRequest::Unknown { request_name } => serializer.serialize_str(request_name),
+            request => ProtocolRequest::serialize(request, serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ProtocolRequest {
+    /// Decode a request, as `Unknown` when its variant is not known
+    ///
+    /// Codecs are self-describing, so the variant name is read ahead of the
+    /// derived decoding, which then reads the variant's fields.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(RequestVisitor)
+    }
+}
+
+struct RequestVisitor;
+
+impl<'de> Visitor<'de> for RequestVisitor {
+    type Value = ProtocolRequest;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a request variant")
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<Self::Value, E> {
+        let unknown = Cell::new(false);
+        let variant = Variant::<NoFields<'de, E>> {
+            name,
+            fields: None,
+            unknown: &unknown,
+        };
+        match ProtocolRequest::deserialize(variant) {
+            Err(_) if unknown.get() => Ok(ProtocolRequest::Unknown {
+                request_name: name.to_string(),
+            }),
+            decoded => decoded,
+        }
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let Some(name) = map.next_key::<String>()? else {
+            return Err(de::Error::invalid_length(0, &"a map with a single key"));
+        };
+        let unknown = Cell::new(false);
+        let variant = Variant {
+            name: &name,
+            fields: Some(&mut map),
+            unknown: &unknown,
+        };
+        let request = match ProtocolRequest::deserialize(variant) {
+            Err(_) if unknown.get() => {
+                map.next_value::<IgnoredAny>()?;
+                ProtocolRequest::Unknown { request_name: name }
+            }
+            decoded => decoded?,
+        };
+        if map.next_key::<IgnoredAny>()?.is_some() {
+            return Err(de::Error::invalid_length(2, &"a map with a single key"));
+        }
+        Ok(request)
+    }
+}
+
+/// Variant named `name` whose fields, if any, are the value in `fields`,
+/// handed to the derived decoding; `unknown` is set when it does not know
+/// the name
+struct Variant<'a, A> {
+    name: &'a str,
+    fields: Option<&'a mut A>,
+    unknown: &'a Cell<bool>,
+}
+
+impl<'de, A: MapAccess<'de>> Deserializer<'de> for Variant<'_, A> {
+    type Error = A::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, A::Error> {
+        visitor.visit_enum(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'de, A: MapAccess<'de>> de::EnumAccess<'de> for Variant<'_, A> {
+    type Error = A::Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), A::Error> {
+        let name = de::value::StrDeserializer::<A::Error>::new(self.name);
+        // Only an unknown name fails to decode as a variant identifier
+        let variant = seed
+            .deserialize(name)
+            .inspect_err(|_| self.unknown.set(true))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de, A: MapAccess<'de>> de::VariantAccess<'de> for Variant<'_, A> {
+    type Error = A::Error;
+
+    fn unit_variant(self) -> Result<(), A::Error> {
+        match self.fields {
+            Some(map) => map.next_value(),
+            None => Ok(()),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, A::Error> {
+        match self.fields {
+            Some(map) => map.next_value_seed(seed),
+            None => Err(de::Error::invalid_type(
+                de::Unexpected::UnitVariant,
+                &"newtype variant",
+            )),
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, A::Error> {
+        self.fields(Fields {
+            visitor,
+            shape: Shape::Tuple(len),
+        })
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, A::Error> {
+        self.fields(Fields {
+            visitor,
+            shape: Shape::Struct(fields),
+        })
+    }
+}
+
+impl<'de, A: MapAccess<'de>> Variant<'_, A> {
+    fn fields<V: Visitor<'de>>(self, fields: Fields<V>) -> Result<V::Value, A::Error> {
+        match self.fields {
+            Some(map) => map.next_value_seed(fields),
+            None => Err(de::Error::invalid_type(
+                de::Unexpected::UnitVariant,
+                &"a variant with fields",
+            )),
+        }
+    }
+}
+
+/// Fields of a variant given as a bare name
+type NoFields<'de, E> = de::value::MapDeserializer<'de, std::iter::Empty<((), ())>, E>;
+
+/// Fields of a tuple or struct variant, decoded from the variant's value
+struct Fields<V> {
+    visitor: V,
+    shape: Shape,
+}
+
+enum Shape {
+    Tuple(usize),
+    Struct(&'static [&'static str]),
+}
+
+impl<'de, V: Visitor<'de>> DeserializeSeed<'de> for Fields<V> {
+    type Value = V::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<V::Value, D::Error> {
+        match self.shape {
+            Shape::Tuple(len) => deserializer.deserialize_tuple(len, self.visitor),
+            Shape::Struct(fields) => deserializer.deserialize_struct("", fields, self.visitor),
+        }
+    }
 }
 
 /// Protocol response types
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ProtocolResponse {
-    Data { items: Vec<ResponseItem> },
+    Data {
+        items: Vec<ResponseItem>,
+        /// Cursor of the next page of a listing cut at its query's limit
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next: Option<Cursor>,
+    },
+    /// Part of a streamed response; further batches follow while `more` is set
+    Batch {
+        items: Vec<ResponseItem>,
+        more: bool,
+    },
     Success,
-    Error { message: String },
+    Error {
+        message: String,
+        /// Kind of failure; `Internal` from remotes predating codes
+        #[serde(default)]
+        code: ErrorCode,
+        /// Facts about the failure, e.g. the `path` that was not found
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        details: BTreeMap<String, String>,
+    },
+    /// The request is of a variant the remote does not know, e.g. one newer
+    /// than the remote
+    Unsupported {
+        request_name: String,
+    },
+    /// A request of the remote, sent out of turn rather than answering one
+    /// (see [`super::server_request`])
+    ServerRequest {
+        id: ServerRequestId,
+        request: ServerRequest,
+    },
+    /// An event of a subscribed topic, sent out of turn and never answered
+    /// (see [`super::topic`])
+    Event {
+        event: TopicEvent,
+    },
+}
+
+impl ProtocolResponse {
+    /// `Error` response of `code`
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        ProtocolResponse::Error {
+            message: message.into(),
+            code,
+            details: BTreeMap::new(),
+        }
+    }
+
+    /// `Error` response to `error` while doing `what`, e.g. "Failed to read
+    /// file", coded by the I/O error causing it if any
+    pub fn failure(what: &str, error: &anyhow::Error) -> Self {
+        Self::error(ErrorCode::of(error), format!("{}: {:#}", what, error))
+    }
+
+    /// Add the detail `key` to an `Error` response; other responses are
+    /// returned as they are
+    pub fn with_detail(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        if let ProtocolResponse::Error { details, .. } = &mut self {
+            details.insert(key.into(), value.into());
+        }
+        self
+    }
+}
+
+/// Kind of failure of an `Error` response, for clients and retry policies
+/// to branch on rather than matching messages
+///
+/// Codes added later decode as `Internal` on older peers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ErrorCode {
+    /// A path, task or other named thing does not exist
+    NotFound,
+    /// Refused by the filesystem, a workspace, a quota or a policy
+    PermissionDenied,
+    /// The request, or an extension or command it needs, is not supported
+    Unsupported,
+    /// The request's arguments are malformed, e.g. those of a command; the
+    /// client's mistake rather than a fault of the remote
+    InvalidArguments,
+    /// The remote is busy, e.g. over a rate limit; retrying later may succeed
+    Busy,
+    /// The target changed since the client read it, e.g. a file edited on
+    /// both sides
+    Conflict,
+    /// The client cancelled the request
+    Cancelled,
+    /// Any other failure
+    #[default]
+    #[serde(other)]
+    Internal,
+}
+
+impl ErrorCode {
+    /// Whether the same request may succeed when retried later
+    pub fn is_retriable(self) -> bool {
+        self == ErrorCode::Busy
+    }
+
+    /// Code of an I/O error of `kind`
+    pub fn from_io(kind: io::ErrorKind) -> Self {
+        match kind {
+            io::ErrorKind::NotFound => ErrorCode::NotFound,
+            io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem => {
+                ErrorCode::PermissionDenied
+            }
+            io::ErrorKind::Unsupported => ErrorCode::Unsupported,
+            io::ErrorKind::WouldBlock
+            | io::ErrorKind::ResourceBusy
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::Interrupted => ErrorCode::Busy,
+            _ => ErrorCode::Internal,
+        }
+    }
+
+    /// Code of the first I/O error in the chain of `error`, `Internal`
+    /// without one
+    pub fn of(error: &anyhow::Error) -> Self {
+        error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<io::Error>())
+            .map_or(ErrorCode::Internal, |e| Self::from_io(e.kind()))
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
 }
 
 /// Response data items for the simple protocol
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ResponseItem {
-    PortForwardData { connection_id: u32, data: Bytes },
-    NewConnection { connection_id: u32, local_port: u16 },
-    CloseConnection { connection_id: u32 },
-    ClipboardContent { content: String },
+    PortForwardData {
+        connection_id: u32,
+        #[serde(with = "super::attachment")]
+        data: Bytes,
+    },
+    NewConnection {
+        connection_id: u32,
+        local_port: u16,
+    },
+    CloseConnection {
+        connection_id: u32,
+    },
+    /// The target accepted `bytes` of a connection's data, which the client
+    /// may send again (see [`super::flow`])
+    Credit {
+        connection_id: u32,
+        bytes: u32,
+    },
+    ClipboardContent {
+        content: String,
+    },
+    ClipboardData {
+        item: ClipboardItem,
+    },
+    /// A regular file found by `ListFiles`; `relative_path` keeps the name of the
+    /// requested entry so directory structure can be recreated
+    FileEntry {
+        path: String,
+        relative_path: String,
+        size: u64,
+    },
+    /// An entry of a directory listed by `ListDirectory`; `dir` is set for
+    /// directories and links to them
+    DirectoryEntry {
+        name: String,
+        dir: bool,
+    },
+    /// A chunk read by `ReadFileChunk`; `crc32c` covers `data`
+    FileChunk {
+        path: String,
+        offset: u64,
+        #[serde(with = "super::attachment")]
+        data: Bytes,
+        crc32c: u32,
+        eof: bool,
+    },
+    /// A range read by `ReadFileRange`; `crc32c` covers `data` and `size` is
+    /// the size of the file when it was read
+    FileRange {
+        path: String,
+        offset: u64,
+        #[serde(with = "super::attachment")]
+        data: Bytes,
+        crc32c: u32,
+        size: u64,
+    },
+    /// A file hashed by `HashPath`; `relative_path` is relative to the
+    /// hashed path, and empty when that is the file itself
+    FileHash {
+        path: String,
+        relative_path: String,
+        size: u64,
+        hash: String,
+    },
+    /// Size of a directory tree scanned by `DiskUsage`
+    DirectoryUsage {
+        usage: DirectoryUsage,
+    },
+    /// How much of the tree a `DiskUsage` request scanned so far
+    DiskUsageProgress {
+        entries: u64,
+        size: u64,
+    },
+    /// A path moved to the remote's trash
+    Trashed {
+        entry: TrashEntry,
+    },
+    SlowRequest {
+        request: SlowRequest,
+    },
+    /// A connection recorded in the access log
+    Access {
+        entry: AccessEntry,
+    },
+    PortForward {
+        forward: PortForwardEntry,
+    },
+    /// Extensions negotiated by `Hello`
+    Extensions {
+        extensions: Vec<ExtensionId>,
+    },
+    /// BLAKE3 hash of the running server binary, reported by `Hello`
+    BinaryHash {
+        hash: String,
+    },
+    /// How the remote notices clipboard changes on its side, reported by
+    /// `Hello` with the `clipboard-watch` extension
+    ClipboardWatch {
+        watch: ClipboardWatch,
+    },
+    /// Process started by `LaunchApp` or `OpenPath`
+    AppLaunched {
+        pid: u32,
+    },
+    /// A tool probed by `ProbeTools`
+    Tool {
+        tool: ToolInfo,
+    },
+    /// A background task listed by `ListTasks`
+    Task {
+        task: TaskInfo,
+    },
+    /// Answer of `GetUsage`
+    Usage {
+        usage: Usage,
+    },
+    /// Snapshot taken by `ExportSessionState`
+    SessionState {
+        state: SessionState,
+    },
+    /// Return value of a `Command`
+    CommandOutput {
+        value: serde_json::Value,
+    },
+}
+
+/// How the remote notices text copied on its side
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClipboardWatch {
+    /// Copies are only taken in when the client reads the clipboard
+    OnRead,
+    /// The source is polled every `min_interval_ms` after activity, backing
+    /// off to `max_interval_ms` while idle, and spends at most
+    /// `max_cpu_percent` of the time on reads
+    AdaptivePolling {
+        min_interval_ms: u64,
+        max_interval_ms: u64,
+        max_cpu_percent: u8,
+    },
+}
+
+/// Restorable settings of a session; data such as the clipboard content
+/// stays with the host
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionState {
+    #[serde(default)]
+    pub forwards: Vec<PortForwardEntry>,
+    /// Topics the client subscribed to
+    #[serde(default)]
+    pub topics: Vec<Topic>,
+}
+
+impl SessionState {
+    /// Forwards of the snapshot that are not among `active` as they are
+    pub fn missing_forwards<'a>(
+        &'a self,
+        active: &'a [PortForwardEntry],
+    ) -> impl Iterator<Item = &'a PortForwardEntry> {
+        self.forwards
+            .iter()
+            .filter(|forward| !active.contains(forward))
+    }
+}
+
+/// Identifier of a background task, unique within its session
+pub type TaskId = u64;
+
+/// A background task running on the remote for a session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub kind: TaskKind,
+    /// Peer of the session that started the task
+    pub session: String,
+    pub started_at: SystemTime,
+}
+
+/// Size of the files in a directory tree
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryUsage {
+    pub path: String,
+    /// Levels below the scanned path, 0 for the path itself
+    pub depth: u32,
+    /// Apparent size of the regular files in the tree, in bytes
+    pub size: u64,
+    pub files: u64,
+}
+
+/// A path kept in the remote's trash until restored or expired
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrashEntry {
+    /// Names the entry for `RestorePath`
+    pub id: String,
+    /// Where the path was trashed from
+    pub original_path: String,
+    pub trashed_at: SystemTime,
+}
+
+/// What a background task does
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TaskKind {
+    /// Accepts connections for a port forward
+    PortForward {
+        local_port: u16,
+        remote_host: String,
+        remote_port: u16,
+    },
+    /// Relays one connection accepted by a port forward
+    Connection { connection_id: u32, local_port: u16 },
+}
+
+impl TaskKind {
+    /// Port of the forward the task belongs to
+    pub fn local_port(&self) -> u16 {
+        match self {
+            TaskKind::PortForward { local_port, .. } | TaskKind::Connection { local_port, .. } => {
+                *local_port
+            }
+        }
+    }
+}
+
+/// Resources used by the sessions of a client since the server started, or
+/// in the current window of a server whose usage starts over
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    /// Identity of the client the usage is of, e.g. its Noise key; `None`
+    /// for the usage shared by clients without one
+    pub client: Option<String>,
+    /// Request and response bytes exchanged, forwarded data included
+    pub bytes: u64,
+    /// Port forwards started
+    pub forwards: u64,
+    /// CPU time used by applications started with `LaunchApp` or `OpenPath`
+    /// and the processes they started
+    pub exec_cpu: Duration,
+    /// Time until the usage starts over; `None` when it never does
+    #[serde(default)]
+    pub resets_in: Option<Duration>,
+    /// Limits beyond which the server refuses requests
+    pub quota: Quota,
+}
+
+/// Limits of a [`Usage`]; `None` is unlimited
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quota {
+    pub bytes: Option<u64>,
+    pub forwards: Option<u64>,
+    pub exec_cpu: Option<Duration>,
+}
+
+/// Where a tool is installed on the remote and which version it reports;
+/// both are `None` when it is missing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolInfo {
+    pub name: String,
+    pub path: Option<String>,
+    /// First line printed by `--version`
+    pub version: Option<String>,
+}
+
+/// Display server a launched application connects to; unset fields are
+/// detected from the remote desktop session
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisplayEnv {
+    /// X11 display, e.g. `:0`
+    pub display: Option<String>,
+    /// Wayland socket name, e.g. `wayland-0`
+    pub wayland_display: Option<String>,
+}
+
+/// Restrictions a launched application runs under; the remote refuses to
+/// launch when it cannot apply them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sandbox {
+    /// Run as this user instead of the server's, which must be root
+    pub user: Option<String>,
+    /// CPU time limit in percent of one core
+    pub cpu_percent: Option<u32>,
+    /// Memory limit in bytes
+    pub memory_bytes: Option<u64>,
+    /// See the filesystem read-only, with a private `/tmp`
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+impl Sandbox {
+    /// Whether the application runs like the server itself
+    pub fn is_unrestricted(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The stricter of each restriction of `self` and `floor`, with the
+    /// user of `floor` winning
+    pub fn within(self, floor: &Sandbox) -> Sandbox {
+        Sandbox {
+            user: floor.user.clone().or(self.user),
+            cpu_percent: lower(self.cpu_percent, floor.cpu_percent),
+            memory_bytes: lower(self.memory_bytes, floor.memory_bytes),
+            read_only: self.read_only || floor.read_only,
+        }
+    }
+}
+
+/// The lower of two optional limits
+fn lower<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// A port forward started with `StartPortForward`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortForwardEntry {
+    pub local_port: u16,
+    pub remote_host: String,
+    pub remote_port: u16,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forward(local_port: u16, remote_port: u16) -> PortForwardEntry {
+        PortForwardEntry {
+            local_port,
+            remote_host: "localhost".to_string(),
+            remote_port,
+        }
+    }
+
+    #[test]
+    fn test_sandbox_within_floor() {
+        let requested = Sandbox {
+            user: Some("alice".to_string()),
+            cpu_percent: Some(20),
+            memory_bytes: None,
+            read_only: false,
+        };
+        let floor = Sandbox {
+            user: Some("guest".to_string()),
+            cpu_percent: Some(50),
+            memory_bytes: Some(1 << 30),
+            read_only: true,
+        };
+        assert_eq!(
+            requested.clone().within(&floor),
+            Sandbox {
+                cpu_percent: Some(20),
+                ..floor.clone()
+            }
+        );
+        assert_eq!(requested.clone().within(&Sandbox::default()), requested);
+        assert!(Sandbox::default().is_unrestricted());
+        assert!(!floor.is_unrestricted());
+    }
+
+    #[test]
+    fn test_missing_forwards() {
+        let state = SessionState {
+            forwards: vec![forward(8080, 80), forward(9090, 90), forward(5432, 5432)],
+            topics: Vec::new(),
+        };
+        // 9090 is active with another target, so it is still missing
+        let active = [forward(8080, 80), forward(9090, 91)];
+        assert_eq!(
+            state.missing_forwards(&active).collect::<Vec<_>>(),
+            vec![&forward(9090, 90), &forward(5432, 5432)]
+        );
+    }
+
+    #[test]
+    fn test_paths() {
+        let request = ProtocolRequest::ListFiles {
+            paths: vec!["/src".to_string(), "/docs".to_string()],
+            query: Default::default(),
+        };
+        assert_eq!(request.paths(), ["/src", "/docs"]);
+        let request = ProtocolRequest::OpenPath {
+            path: "/src/main.rs".to_string(),
+        };
+        assert_eq!(request.paths(), ["/src/main.rs"]);
+        assert!(ProtocolRequest::GetClipboard.paths().is_empty());
+    }
+
+    #[test]
+    fn test_unknown_requests() {
+        let decode = |json: &str| serde_json::from_str::<ProtocolRequest>(json);
+        let name = |json: &str| match decode(json).unwrap() {
+            ProtocolRequest::Unknown { request_name } => request_name,
+            other => panic!("Decoded {:?}", other),
+        };
+        assert_eq!(name(r#""FutureRequest""#), "FutureRequest");
+        assert_eq!(
+            name(r#"{"FutureRequest":{"path":"/src","depth":[1,2]}}"#),
+            "FutureRequest"
+        );
+        let json = serde_json::to_string(&ProtocolRequest::Unknown {
+            request_name: "FutureRequest".to_string(),
+        })
+        .unwrap();
+        assert_eq!(name(&json), "FutureRequest");
+
+        // Known variants decode as before, and malformed ones are still errors
+        assert!(matches!(
+            decode(r#""GetClipboard""#).unwrap(),
+            ProtocolRequest::GetClipboard
+        ));
+        let json = serde_json::to_string(&ProtocolRequest::OpenPath {
+            path: "/src/main.rs".to_string(),
+        })
+        .unwrap();
+        assert!(matches!(
+            decode(&json).unwrap(),
+            ProtocolRequest::OpenPath { path } if path == "/src/main.rs"
+        ));
+        assert!(decode(r#"{"OpenPath":{"path":7}}"#).is_err());
+        assert!(decode(r#"{"OpenPath":{}}"#).is_err());
+        assert!(decode(r#"{"FutureRequest":null,"OpenPath":null}"#).is_err());
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let missing = anyhow::Error::new(io::Error::from(io::ErrorKind::NotFound))
+            .context("Failed to open /data/a.txt");
+        let response = ProtocolResponse::failure("Failed to read file", &missing)
+            .with_detail("path", "/data/a.txt");
+        let json = serde_json::to_string(&response).unwrap();
+        match serde_json::from_str(&json).unwrap() {
+            ProtocolResponse::Error {
+                message,
+                code,
+                details,
+            } => {
+                assert_eq!(code, ErrorCode::NotFound);
+                assert!(message.starts_with("Failed to read file: Failed to open /data/a.txt"));
+                assert_eq!(details["path"], "/data/a.txt");
+            }
+            other => panic!("Decoded {:?}", other),
+        }
+        assert_eq!(
+            ErrorCode::of(&anyhow::anyhow!("no cause")),
+            ErrorCode::Internal
+        );
+
+        // Errors of older remotes, and codes newer than this build, decode
+        let old: ProtocolResponse = serde_json::from_str(r#"{"Error":{"message":"x"}}"#).unwrap();
+        assert!(matches!(
+            old,
+            ProtocolResponse::Error { code: ErrorCode::Internal, ref details, .. } if details.is_empty()
+        ));
+        let newer: ErrorCode = serde_json::from_str(r#""QuotaExceeded""#).unwrap();
+        assert_eq!(newer, ErrorCode::Internal);
+        assert!(ErrorCode::Busy.is_retriable() && !ErrorCode::NotFound.is_retriable());
+    }
 }
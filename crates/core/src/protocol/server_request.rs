@@ -0,0 +1,51 @@
+//! # Server Requests
+//!
+//! With the `server-requests` extension, the remote may ask the client
+//! something instead of only answering it, e.g. to open a URL on the
+//! client's host. The remote sends a `ServerRequest` response out of turn,
+//! between its answers to the client's requests or while a `PollData` long
+//! poll waits, numbered with a [`ServerRequestId`] of the remote's own. The
+//! client answers with a `ServerReply` request echoing that id, which the
+//! remote acknowledges with `Success`.
+//!
+//! ```text
+//! Server → Client: ServerRequest { id: 1, request: OpenUrl { url } }
+//! Client → Server: ServerReply { id: 1, reply: Done }
+//! Server → Client: Success
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+/// Number the remote gives a request it sends, echoed by the client's reply
+pub type ServerRequestId = u64;
+
+/// Something the remote asks of the client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ServerRequest {
+    /// Open `url` in a browser on the client's host
+    OpenUrl { url: String },
+    /// The remote clipboard now holds `content`
+    ClipboardChanged { content: String },
+}
+
+impl ServerRequest {
+    /// Variant name, used in logs
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ServerRequest::OpenUrl { .. } => "OpenUrl",
+            ServerRequest::ClipboardChanged { .. } => "ClipboardChanged",
+        }
+    }
+}
+
+/// The client's answer to a [`ServerRequest`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ServerReply {
+    Done,
+    /// No handler of the client took the request, or the client does not
+    /// know its variant
+    Unhandled,
+    Failed {
+        message: String,
+    },
+}
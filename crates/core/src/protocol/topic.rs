@@ -0,0 +1,79 @@
+//! # Event Topics
+//!
+//! With the `subscriptions` extension, a client subscribes to topics of the
+//! remote with `Subscribe` and is then pushed an `Event` response out of
+//! turn for each event of those topics, between the remote's answers to its
+//! requests or while a `PollData` long poll waits. Events carry no
+//! correlation id and are never answered. `Unsubscribe` stops them.
+//!
+//! ```text
+//! Client → Server: Subscribe { topic: Clipboard }
+//! Server → Client: Success
+//! Server → Client: Event { event: ClipboardChanged { text } }
+//! ```
+//!
+//! Events report what sessions of the remote do, e.g. a file uploaded by
+//! another client, not changes made on the remote behind yuha's back.
+
+use serde::{Deserialize, Serialize};
+
+/// Group of events a client may subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Topic {
+    /// Content set on the clipboard
+    Clipboard,
+    /// Connections to port forwards opened and closed
+    PortActivity,
+    /// Files written, removed, moved and restored
+    Files,
+}
+
+/// Something that happened on the remote
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TopicEvent {
+    /// The clipboard was set, to `text` if it holds text
+    ClipboardChanged {
+        text: Option<String>,
+    },
+    /// A connection to the port forward on `local_port` was accepted
+    ConnectionOpened {
+        connection_id: u32,
+        local_port: u16,
+    },
+    ConnectionClosed {
+        connection_id: u32,
+    },
+    /// The last chunk of an upload of `bytes` was written
+    FileWritten {
+        path: String,
+        bytes: u64,
+    },
+    /// Removed, or moved to the trash
+    PathRemoved {
+        path: String,
+    },
+    PathMoved {
+        from: String,
+        to: String,
+    },
+    /// Restored from the trash
+    PathRestored {
+        path: String,
+    },
+}
+
+impl TopicEvent {
+    /// The topic the event is published on
+    pub fn topic(&self) -> Topic {
+        match self {
+            TopicEvent::ClipboardChanged { .. } => Topic::Clipboard,
+            TopicEvent::ConnectionOpened { .. } | TopicEvent::ConnectionClosed { .. } => {
+                Topic::PortActivity
+            }
+            TopicEvent::FileWritten { .. }
+            | TopicEvent::PathRemoved { .. }
+            | TopicEvent::PathMoved { .. }
+            | TopicEvent::PathRestored { .. } => Topic::Files,
+        }
+    }
+}
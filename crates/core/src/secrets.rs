@@ -0,0 +1,381 @@
+//! # Encrypted Config Values
+//!
+//! Passwords, tokens and other strings in the config file can be stored
+//! encrypted to an age recipient, as the ASCII armored output of
+//! `age -a -r <recipient>`, so configs are safe to commit to dotfile
+//! repositories. [`YuhaConfig::load_from_file`](crate::YuhaConfig::load_from_file)
+//! decrypts them with the identities named by the `[secrets]` section:
+//!
+//! - `identity_files`: age identity files, relative to the config file
+//! - `keychain = true`: an identity stored in the OS keychain under service
+//!   `yuha`, user `age-identity` (`keychain` feature)
+//!
+//! Decrypting needs the `encrypted-config` feature. `yuha config show`
+//! redacts the decrypted values, and a config saved after loading writes
+//! them back encrypted as they were; a value changed meanwhile is refused
+//! until it is encrypted anew.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, YuhaError};
+
+/// First line of an armored age ciphertext
+const ARMOR_BEGIN: &str = "-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// Keychain entry holding an age identity
+#[cfg(feature = "keychain")]
+const KEYCHAIN_ENTRY: (&str, &str) = ("yuha", "age-identity");
+
+/// Where the identities decrypting config values are found
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SecretsConfig {
+    /// age identity files
+    #[serde(default)]
+    pub identity_files: Vec<PathBuf>,
+    /// Read an identity from the OS keychain
+    #[serde(default)]
+    pub keychain: bool,
+}
+
+/// Whether `value` is an armored age ciphertext
+pub fn is_encrypted(value: &str) -> bool {
+    value.trim_start().starts_with(ARMOR_BEGIN)
+}
+
+/// Placeholder shown instead of a decrypted value
+pub const REDACTED: &str = "<encrypted>";
+
+/// Step from a table or array to one of its values
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Key {
+    Name(String),
+    Index(usize),
+}
+
+/// A value decrypted at load time, with where it was found
+#[derive(Clone)]
+struct Decrypted {
+    path: Vec<Key>,
+    plain: String,
+    ciphertext: String,
+}
+
+/// Values a config decrypted at load time, to hide them again for display
+/// and to save them in their encrypted form
+#[derive(Clone, Default)]
+pub struct DecryptedValues {
+    values: Vec<Decrypted>,
+}
+
+impl DecryptedValues {
+    /// Replace the decrypted values in `table`, a serialized config, by
+    /// [`REDACTED`]
+    pub fn redact(&self, table: &mut toml::Table) {
+        for value in &self.values {
+            if let Some(slot) = value_mut(table, &value.path) {
+                *slot = toml::Value::String(REDACTED.to_string());
+            }
+        }
+    }
+
+    /// Put the ciphertexts back in place of the decrypted values in `table`,
+    /// a serialized config
+    ///
+    /// A value changed since it was decrypted is refused rather than written
+    /// in plain text; it must be encrypted anew.
+    pub fn seal(&self, table: &mut toml::Table) -> Result<()> {
+        for value in &self.values {
+            let Some(slot) = value_mut(table, &value.path) else {
+                continue;
+            };
+            match slot.as_str() {
+                Some(text) if text == value.plain => {
+                    *slot = toml::Value::String(value.ciphertext.clone());
+                }
+                Some(text) if is_encrypted(text) => {}
+                _ => {
+                    return Err(YuhaError::config(format!(
+                        "Refusing to save the changed encrypted value {} in plain text; \
+                         encrypt it with age first",
+                        display_path(&value.path)
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for DecryptedValues {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.values.iter().map(|value| display_path(&value.path)))
+            .finish()
+    }
+}
+
+/// Decrypt the encrypted strings anywhere in the config `table` loaded from
+/// a file in `dir`, returning what was decrypted
+pub fn decrypt_values(table: &mut toml::Table, dir: &Path) -> Result<DecryptedValues> {
+    let mut encrypted = Vec::new();
+    collect_encrypted(table, &mut Vec::new(), &mut encrypted);
+    if encrypted.is_empty() {
+        return Ok(DecryptedValues::default());
+    }
+
+    let secrets: SecretsConfig = match table.get("secrets") {
+        Some(section) => section
+            .clone()
+            .try_into()
+            .map_err(|e| YuhaError::config(format!("Invalid secrets section: {}", e)))?,
+        None => SecretsConfig::default(),
+    };
+    let ciphertexts: Vec<_> = encrypted.iter().map(|(_, text)| text.clone()).collect();
+    let decrypted = decrypt_all(&secrets, dir, &ciphertexts)?;
+
+    let mut values = Vec::new();
+    for ((path, ciphertext), plain) in encrypted.into_iter().zip(decrypted) {
+        if let Some(slot) = value_mut(table, &path) {
+            *slot = toml::Value::String(plain.clone());
+        }
+        values.push(Decrypted {
+            path,
+            plain,
+            ciphertext,
+        });
+    }
+    Ok(DecryptedValues { values })
+}
+
+/// Collect the encrypted strings of `table`, found at `path`, with their
+/// paths
+fn collect_encrypted(
+    table: &toml::Table,
+    path: &mut Vec<Key>,
+    encrypted: &mut Vec<(Vec<Key>, String)>,
+) {
+    for (name, value) in table {
+        path.push(Key::Name(name.clone()));
+        collect_value(value, path, encrypted);
+        path.pop();
+    }
+}
+
+fn collect_value(
+    value: &toml::Value,
+    path: &mut Vec<Key>,
+    encrypted: &mut Vec<(Vec<Key>, String)>,
+) {
+    match value {
+        toml::Value::String(text) if is_encrypted(text) => {
+            encrypted.push((path.clone(), text.clone()))
+        }
+        toml::Value::Table(table) => collect_encrypted(table, path, encrypted),
+        toml::Value::Array(values) => {
+            for (index, value) in values.iter().enumerate() {
+                path.push(Key::Index(index));
+                collect_value(value, path, encrypted);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The value of `table` at `path`, if there is one
+fn value_mut<'a>(table: &'a mut toml::Table, path: &[Key]) -> Option<&'a mut toml::Value> {
+    let (Key::Name(first), rest) = path.split_first()? else {
+        return None;
+    };
+    let mut value = table.get_mut(first)?;
+    for key in rest {
+        value = match (key, value) {
+            (Key::Name(name), toml::Value::Table(table)) => table.get_mut(name)?,
+            (Key::Index(index), toml::Value::Array(values)) => values.get_mut(*index)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// `path` the way a TOML key is written, e.g. `profiles.web.ssh.password`
+fn display_path(path: &[Key]) -> String {
+    let mut text = String::new();
+    for key in path {
+        match key {
+            Key::Name(name) if text.is_empty() => text.push_str(name),
+            Key::Name(name) => {
+                text.push('.');
+                text.push_str(name);
+            }
+            Key::Index(index) => text.push_str(&format!("[{}]", index)),
+        }
+    }
+    text
+}
+
+#[cfg(feature = "encrypted-config")]
+fn decrypt_all(secrets: &SecretsConfig, dir: &Path, encrypted: &[String]) -> Result<Vec<String>> {
+    use std::io::Read;
+
+    let identities = identities(secrets, dir)?;
+    if identities.is_empty() {
+        return Err(YuhaError::config(
+            "Config holds encrypted values but the secrets section names no identity",
+        ));
+    }
+
+    encrypted
+        .iter()
+        .map(|ciphertext| {
+            let armored = age::armor::ArmoredReader::new(ciphertext.trim().as_bytes());
+            let decryptor = age::Decryptor::new(armored).map_err(|e| {
+                YuhaError::config(format!("Malformed encrypted config value: {}", e))
+            })?;
+            let mut reader = decryptor
+                .decrypt(identities.iter().map(|identity| identity.as_ref()))
+                .map_err(|e| YuhaError::config(format!("Failed to decrypt config value: {}", e)))?;
+            let mut plain = String::new();
+            reader
+                .read_to_string(&mut plain)
+                .map_err(|e| YuhaError::config(format!("Failed to decrypt config value: {}", e)))?;
+            Ok(plain)
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "encrypted-config"))]
+fn decrypt_all(
+    _secrets: &SecretsConfig,
+    _dir: &Path,
+    _encrypted: &[String],
+) -> Result<Vec<String>> {
+    Err(YuhaError::config(
+        "Config holds encrypted values but yuha was built without the encrypted-config feature",
+    ))
+}
+
+#[cfg(feature = "encrypted-config")]
+fn identities(secrets: &SecretsConfig, dir: &Path) -> Result<Vec<Box<dyn age::Identity>>> {
+    let mut identities = Vec::new();
+    for file in &secrets.identity_files {
+        let path = dir.join(file);
+        let file =
+            age::IdentityFile::from_file(path.to_string_lossy().into_owned()).map_err(|e| {
+                YuhaError::config(format!(
+                    "Failed to read identity file {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+        identities.extend(file.into_identities().map_err(|e| {
+            YuhaError::config(format!("Invalid identity file {}: {}", path.display(), e))
+        })?);
+    }
+
+    if secrets.keychain {
+        identities.extend(keychain_identities()?);
+    }
+    Ok(identities)
+}
+
+#[cfg(feature = "keychain")]
+fn keychain_identities() -> Result<Vec<Box<dyn age::Identity>>> {
+    let (service, user) = KEYCHAIN_ENTRY;
+    let identity = keyring::Entry::new(service, user)
+        .and_then(|entry| entry.get_password())
+        .map_err(|e| {
+            YuhaError::config(format!("Failed to read identity from the keychain: {}", e))
+        })?;
+    age::IdentityFile::from_buffer(identity.as_bytes())
+        .map_err(|e| YuhaError::config(format!("Invalid identity in the keychain: {}", e)))?
+        .into_identities()
+        .map_err(|e| YuhaError::config(format!("Invalid identity in the keychain: {}", e)))
+}
+
+#[cfg(all(feature = "encrypted-config", not(feature = "keychain")))]
+fn keychain_identities() -> Result<Vec<Box<dyn age::Identity>>> {
+    Err(YuhaError::config(
+        "Config reads an identity from the keychain but yuha was built without the keychain feature",
+    ))
+}
+
+#[cfg(all(test, feature = "encrypted-config"))]
+mod tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+
+    #[test]
+    fn test_decrypt_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let identity = age::x25519::Identity::generate();
+        std::fs::write(
+            dir.path().join("identity.txt"),
+            identity.to_string().expose_secret(),
+        )
+        .unwrap();
+        let encrypt =
+            |plain: &str| age::encrypt_and_armor(&identity.to_public(), plain.as_bytes()).unwrap();
+
+        let mut table: toml::Table = toml::from_str(&format!(
+            "[secrets]\nidentity_files = [\"identity.txt\"]\n[profiles.web.ssh]\npassword = '''{}'''\ntokens = ['''{}''', \"plain\"]\n",
+            encrypt("hunter2"),
+            encrypt("t0ken"),
+        ))
+        .unwrap();
+        decrypt_values(&mut table, dir.path()).unwrap();
+        let ssh = &table["profiles"]["web"]["ssh"];
+        assert_eq!(ssh["password"].as_str(), Some("hunter2"));
+        assert_eq!(ssh["tokens"][0].as_str(), Some("t0ken"));
+        assert_eq!(ssh["tokens"][1].as_str(), Some("plain"));
+
+        // Encrypted to someone else
+        let other = age::x25519::Identity::generate();
+        let ciphertext = age::encrypt_and_armor(&other.to_public(), b"x").unwrap();
+        let mut table: toml::Table = toml::from_str(&format!(
+            "[secrets]\nidentity_files = [\"identity.txt\"]\ntoken = '''{}'''\n",
+            ciphertext
+        ))
+        .unwrap();
+        assert!(decrypt_values(&mut table, dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_seal_and_redact() {
+        let dir = tempfile::tempdir().unwrap();
+        let identity = age::x25519::Identity::generate();
+        std::fs::write(
+            dir.path().join("identity.txt"),
+            identity.to_string().expose_secret(),
+        )
+        .unwrap();
+        let ciphertext = age::encrypt_and_armor(&identity.to_public(), b"hunter2").unwrap();
+        let mut table: toml::Table = toml::from_str(&format!(
+            "[secrets]\nidentity_files = [\"identity.txt\"]\n[profiles.web.ssh]\npassword = '''{}'''\n",
+            ciphertext
+        ))
+        .unwrap();
+        let decrypted = decrypt_values(&mut table, dir.path()).unwrap();
+        assert!(!format!("{:?}", decrypted).contains("hunter2"));
+
+        let mut shown = table.clone();
+        decrypted.redact(&mut shown);
+        assert_eq!(
+            shown["profiles"]["web"]["ssh"]["password"].as_str(),
+            Some(REDACTED)
+        );
+
+        let mut saved = table.clone();
+        decrypted.seal(&mut saved).unwrap();
+        assert_eq!(
+            saved["profiles"]["web"]["ssh"]["password"].as_str(),
+            Some(ciphertext.as_str())
+        );
+
+        let mut changed = table;
+        changed["profiles"]["web"]["ssh"]["password"] = toml::Value::from("changed");
+        assert!(decrypted.seal(&mut changed).is_err());
+    }
+}
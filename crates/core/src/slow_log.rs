@@ -0,0 +1,156 @@
+//! # Slow Request Log
+//!
+//! Keeps the most recent requests that took longer or carried more data than
+//! the configured thresholds, so performance problems seen in the field can be
+//! inspected after the fact via `ProtocolRequest::GetSlowLog`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+/// Thresholds and capacity of a [`SlowLog`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlowLogConfig {
+    /// Requests taking at least this long are logged
+    pub duration_threshold: Duration,
+    /// Requests whose request or response payload reaches this many bytes are logged
+    pub payload_threshold: usize,
+    /// Maximum number of entries kept
+    pub capacity: usize,
+}
+
+impl Default for SlowLogConfig {
+    fn default() -> Self {
+        Self {
+            duration_threshold: Duration::from_secs(1),
+            payload_threshold: 32 * 1024,
+            capacity: 100,
+        }
+    }
+}
+
+/// A request that exceeded a slow log threshold
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlowRequest {
+    /// Request variant name, e.g. `ReadFileChunk`
+    pub request_type: String,
+    /// Peer that sent the request
+    pub peer: String,
+    /// Encoded request payload size in bytes
+    pub request_size: usize,
+    /// Encoded response payload size in bytes
+    pub response_size: usize,
+    pub duration: Duration,
+    pub completed_at: SystemTime,
+}
+
+/// Ring buffer of requests exceeding the configured thresholds
+#[derive(Debug)]
+pub struct SlowLog {
+    config: SlowLogConfig,
+    entries: VecDeque<SlowRequest>,
+}
+
+impl SlowLog {
+    pub fn new(config: SlowLogConfig) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(config.capacity.min(1024)),
+            config,
+        }
+    }
+
+    /// Record `request` if it exceeds a threshold, returning whether it was logged
+    ///
+    /// Long polls wait for data by design, so only their payload sizes are checked.
+    pub fn observe(&mut self, request: SlowRequest, long_poll: bool) -> bool {
+        let slow = !long_poll && request.duration >= self.config.duration_threshold;
+        let large =
+            request.request_size.max(request.response_size) >= self.config.payload_threshold;
+        if !(slow || large) || self.config.capacity == 0 {
+            return false;
+        }
+
+        warn!(
+            "Slow request {} from {}: {:?}, request {} bytes, response {} bytes",
+            request.request_type,
+            request.peer,
+            request.duration,
+            request.request_size,
+            request.response_size
+        );
+
+        if self.entries.len() == self.config.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(request);
+        true
+    }
+
+    /// Logged requests, newest first
+    pub fn entries(&self) -> Vec<SlowRequest> {
+        self.entries.iter().rev().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(request_type: &str, size: usize, duration: Duration) -> SlowRequest {
+        SlowRequest {
+            request_type: request_type.to_string(),
+            peer: "stdio".to_string(),
+            request_size: 16,
+            response_size: size,
+            duration,
+            completed_at: SystemTime::now(),
+        }
+    }
+
+    fn slow_log(capacity: usize) -> SlowLog {
+        SlowLog::new(SlowLogConfig {
+            duration_threshold: Duration::from_millis(100),
+            payload_threshold: 1024,
+            capacity,
+        })
+    }
+
+    #[test]
+    fn test_observe_thresholds() {
+        let mut log = slow_log(10);
+
+        assert!(!log.observe(request("GetClipboard", 10, Duration::from_millis(5)), false));
+        assert!(log.observe(
+            request("OpenBrowser", 10, Duration::from_millis(150)),
+            false
+        ));
+        assert!(log.observe(request("ReadFileChunk", 4096, Duration::ZERO), false));
+
+        // Long polls are only logged for their payload size
+        assert!(!log.observe(request("PollData", 10, Duration::from_secs(5)), true));
+        assert!(log.observe(request("PollData", 2048, Duration::from_secs(5)), true));
+
+        let types: Vec<_> = log
+            .entries()
+            .into_iter()
+            .map(|entry| entry.request_type)
+            .collect();
+        assert_eq!(types, vec!["PollData", "ReadFileChunk", "OpenBrowser"]);
+    }
+
+    #[test]
+    fn test_capacity_evicts_oldest() {
+        let mut log = slow_log(2);
+        for request_type in ["A", "B", "C"] {
+            log.observe(request(request_type, 4096, Duration::ZERO), false);
+        }
+
+        let types: Vec<_> = log
+            .entries()
+            .into_iter()
+            .map(|entry| entry.request_type)
+            .collect();
+        assert_eq!(types, vec!["C", "B"]);
+    }
+}
@@ -0,0 +1,242 @@
+//! # ssh-agent Signatures
+//!
+//! Keys the user's ssh-agent holds, named by their OpenSSH public key, e.g.
+//! `ssh-rsa AAAA... comment`, and signatures made with them. Keys of a
+//! PKCS#11 token are held once the token is loaded into the agent
+//! (`ssh-add -s <provider>`), so a secret derived from their signatures
+//! never leaves the token; signing may wait for the PIN or a touch.
+
+use base64::Engine;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+/// `SSH_AGENTC_SIGN_REQUEST`
+const SIGN_REQUEST: u8 = 13;
+/// `SSH_AGENT_SIGN_RESPONSE`
+const SIGN_RESPONSE: u8 = 14;
+/// `SSH_AGENT_RSA_SHA2_256`, as RSA keys would otherwise sign with SHA-1
+const RSA_SHA2_256: u32 = 2;
+/// Largest agent reply accepted
+const MAX_REPLY_LEN: usize = 256 * 1024;
+
+/// A key the ssh-agent signs with
+#[derive(Clone, PartialEq, Eq)]
+pub struct AgentKey {
+    algorithm: String,
+    blob: Vec<u8>,
+}
+
+impl AgentKey {
+    pub fn algorithm(&self) -> &str {
+        &self.algorithm
+    }
+
+    /// Have the agent sign `data`, returning the signature blob
+    pub fn sign(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        #[cfg(unix)]
+        {
+            let socket = std::env::var_os("SSH_AUTH_SOCK").ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "SSH_AUTH_SOCK is not set")
+            })?;
+            let mut agent = std::os::unix::net::UnixStream::connect(socket)?;
+            self.sign_with(&mut agent, data)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = data;
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "ssh-agent signatures need a Unix socket agent",
+            ))
+        }
+    }
+
+    /// Sign `data` through the agent connection `agent`
+    fn sign_with(&self, agent: &mut (impl Read + Write), data: &[u8]) -> io::Result<Vec<u8>> {
+        let flags = if self.algorithm == "ssh-rsa" {
+            RSA_SHA2_256
+        } else {
+            0
+        };
+        let mut request = vec![SIGN_REQUEST];
+        put_string(&mut request, &self.blob);
+        put_string(&mut request, data);
+        request.extend_from_slice(&flags.to_be_bytes());
+        agent.write_all(&(request.len() as u32).to_be_bytes())?;
+        agent.write_all(&request)?;
+
+        let mut len = [0u8; 4];
+        agent.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_REPLY_LEN {
+            return Err(invalid("oversized ssh-agent reply"));
+        }
+        let mut reply = vec![0u8; len];
+        agent.read_exact(&mut reply)?;
+        match reply.split_first() {
+            Some((&SIGN_RESPONSE, mut rest)) => Ok(take_string(&mut rest)?.to_vec()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "The ssh-agent refused to sign with the {} key",
+                    self.algorithm
+                ),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AgentKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let blob = base64::engine::general_purpose::STANDARD.encode(&self.blob);
+        write!(f, "{} {}", self.algorithm, blob)
+    }
+}
+
+impl fmt::Debug for AgentKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AgentKey({})", self)
+    }
+}
+
+impl FromStr for AgentKey {
+    type Err = String;
+
+    /// Parse an OpenSSH public key line, ignoring its comment
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split_whitespace();
+        let (Some(algorithm), Some(blob)) = (fields.next(), fields.next()) else {
+            return Err("Expected an OpenSSH public key, e.g. `ssh-rsa AAAA...`".to_string());
+        };
+        let blob = base64::engine::general_purpose::STANDARD
+            .decode(blob)
+            .map_err(|e| format!("Invalid public key: {}", e))?;
+        let mut rest = blob.as_slice();
+        let named = take_string(&mut rest).map_err(|e| e.to_string())?;
+        if named != algorithm.as_bytes() {
+            return Err(format!("Public key is not of type {}", algorithm));
+        }
+        Ok(Self {
+            algorithm: algorithm.to_string(),
+            blob,
+        })
+    }
+}
+
+impl Serialize for AgentKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for AgentKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+fn put_string(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buf.extend_from_slice(data);
+}
+
+fn take_string<'a>(buf: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    let (len, rest) = buf
+        .split_first_chunk::<4>()
+        .ok_or_else(|| invalid("truncated string"))?;
+    let len = u32::from_be_bytes(*len) as usize;
+    if rest.len() < len {
+        return Err(invalid("truncated string"));
+    }
+    let (string, rest) = rest.split_at(len);
+    *buf = rest;
+    Ok(string)
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ed25519() -> AgentKey {
+        let mut blob = Vec::new();
+        put_string(&mut blob, b"ssh-ed25519");
+        put_string(&mut blob, &[1; 32]);
+        AgentKey {
+            algorithm: "ssh-ed25519".to_string(),
+            blob,
+        }
+    }
+
+    /// One agent exchange: the request received and the reply to give
+    struct FakeAgent {
+        request: Vec<u8>,
+        reply: io::Cursor<Vec<u8>>,
+    }
+
+    impl Read for FakeAgent {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reply.read(buf)
+        }
+    }
+
+    impl Write for FakeAgent {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.request.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn reply(message: &[u8]) -> io::Cursor<Vec<u8>> {
+        let mut reply = (message.len() as u32).to_be_bytes().to_vec();
+        reply.extend_from_slice(message);
+        io::Cursor::new(reply)
+    }
+
+    #[test]
+    fn test_public_key_line() {
+        let key = ed25519();
+        let line = format!("{} user@host", key);
+        assert_eq!(line.parse::<AgentKey>().unwrap(), key);
+        assert!("ssh-ed25519".parse::<AgentKey>().is_err());
+        assert!("ssh-rsa AAAA!".parse::<AgentKey>().is_err());
+        let mislabeled = key.to_string().replace("ssh-ed25519", "ssh-rsa");
+        assert!(mislabeled.parse::<AgentKey>().is_err());
+    }
+
+    #[test]
+    fn test_sign_request() {
+        let key = ed25519();
+        let mut response = vec![SIGN_RESPONSE];
+        put_string(&mut response, b"signature");
+        let mut agent = FakeAgent {
+            request: Vec::new(),
+            reply: reply(&response),
+        };
+        assert_eq!(key.sign_with(&mut agent, b"data").unwrap(), b"signature");
+
+        let mut expected = vec![SIGN_REQUEST];
+        put_string(&mut expected, &key.blob);
+        put_string(&mut expected, b"data");
+        expected.extend_from_slice(&0u32.to_be_bytes());
+        assert_eq!(agent.request[4..], expected);
+
+        // SSH_AGENT_FAILURE
+        let mut agent = FakeAgent {
+            request: Vec::new(),
+            reply: reply(&[5]),
+        };
+        let error = key.sign_with(&mut agent, b"data").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+    }
+}
@@ -1,5 +1,6 @@
 //! Tests for transport configuration and factory
 
+use crate::protocol::noise::NoiseKeypair;
 use crate::transport::*;
 use std::path::PathBuf;
 
@@ -45,9 +46,14 @@ fn test_ssh_transport_config() {
         username: "user".to_string(),
         password: Some("pass".to_string()),
         key_path: None,
+        cert_path: None,
+        cert_principal: None,
+        pkcs11: None,
         auto_upload_binary: false,
         timeout: 30,
-        keepalive: 60,
+        socket: None,
+        keepalive: None,
+        algorithms: SshAlgorithms::default(),
     };
 
     assert_eq!(ssh_config.host, "example.com");
@@ -79,6 +85,10 @@ fn test_tcp_transport_config() {
         port: 9999,
         timeout: 30,
         tls: None,
+        auth_token: None,
+        noise: None,
+        socket: None,
+        knock: None,
     };
 
     assert_eq!(tcp_config.host, "localhost");
@@ -94,12 +104,14 @@ fn test_wsl_transport_config() {
         user: Some("user".to_string()),
         binary_path: Some(PathBuf::from("yuha-remote")),
         working_dir: Some(PathBuf::from("/home/user")),
+        expose_ports: true,
     };
 
     assert_eq!(wsl_config.distribution, Some("Ubuntu".to_string()));
     assert_eq!(wsl_config.user, Some("user".to_string()));
     assert_eq!(wsl_config.binary_path, Some(PathBuf::from("yuha-remote")));
     assert_eq!(wsl_config.working_dir, Some(PathBuf::from("/home/user")));
+    assert!(wsl_config.expose_ports);
 }
 
 #[test]
@@ -138,6 +150,112 @@ fn test_transport_builder() {
     assert!(tcp_config.tcp.is_some());
 }
 
+#[test]
+fn test_tcp_builder_auth_token() {
+    let tcp_config = TransportBuilder::tcp()
+        .host("localhost")
+        .port(9999)
+        .auth_token("secret")
+        .build()
+        .unwrap();
+
+    assert_eq!(
+        tcp_config.tcp.unwrap().auth_token,
+        Some("secret".to_string())
+    );
+}
+
+#[test]
+fn test_tcp_builder_noise() {
+    let remote_key = NoiseKeypair::generate().public();
+    let tcp_config = TransportBuilder::tcp()
+        .host("localhost")
+        .port(9999)
+        .noise("/tmp/yuha-noise.key", remote_key)
+        .build()
+        .unwrap();
+
+    let noise = tcp_config.tcp.unwrap().noise.unwrap();
+    assert_eq!(noise.remote_key, remote_key);
+
+    // Keys are configured as hex
+    let parsed: NoiseSettings = toml::from_str(&format!(
+        "key_file = \"/tmp/yuha-noise.key\"\nremote_key = \"{}\"",
+        remote_key
+    ))
+    .unwrap();
+    assert_eq!(parsed, noise);
+
+    // Sealed to an agent key written as an OpenSSH public key
+    let agent_key =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+    let parsed: NoiseSettings = toml::from_str(&format!(
+        "key_file = \"/tmp/yuha-noise.key\"\nremote_key = \"{}\"\nsealed_to = \"{} token\"",
+        remote_key, agent_key
+    ))
+    .unwrap();
+    let sealed_to = parsed.sealed_to.unwrap();
+    assert_eq!(sealed_to.algorithm(), "ssh-ed25519");
+    assert_eq!(sealed_to.to_string(), agent_key);
+    let tcp_config = TransportBuilder::tcp()
+        .host("localhost")
+        .port(9999)
+        .noise("/tmp/yuha-noise.key", remote_key)
+        .seal_noise_key(sealed_to.clone())
+        .build()
+        .unwrap();
+    assert_eq!(
+        tcp_config.tcp.unwrap().noise.unwrap().sealed_to,
+        Some(sealed_to)
+    );
+}
+
+#[test]
+fn test_tcp_knock() {
+    let tcp: TcpConfig = toml::from_str(
+        r#"
+        host = "example.com"
+        port = 9999
+
+        [knock]
+        sequence = [{ port = 7000 }, { port = 8000, protocol = "udp" }]
+        "#,
+    )
+    .unwrap();
+    let knock = tcp.knock.unwrap();
+    assert_eq!(
+        knock.sequence,
+        [
+            KnockStep {
+                port: 7000,
+                protocol: KnockProtocol::Tcp,
+            },
+            KnockStep {
+                port: 8000,
+                protocol: KnockProtocol::Udp,
+            },
+        ]
+    );
+    assert_eq!(knock.delay_ms, 100);
+    assert!(knock.command.is_none());
+
+    let builder = |knock| {
+        TransportBuilder::tcp()
+            .host("example.com")
+            .port(9999)
+            .knock(knock)
+    };
+    assert!(builder(knock.clone()).build().is_ok());
+    let command = KnockConfig {
+        command: Some(Vec::new()),
+        ..knock.clone()
+    };
+    assert!(builder(command).build().is_err());
+    let mut zero = knock;
+    zero.sequence[0].port = 0;
+    assert!(builder(zero).build().is_err());
+}
+
 #[test]
 fn test_transport_config_validation() {
     // Test valid SSH config
@@ -149,9 +267,14 @@ fn test_transport_config_validation() {
             username: "user".to_string(),
             password: Some("pass".to_string()),
             key_path: None,
+            cert_path: None,
+            cert_principal: None,
+            pkcs11: None,
             auto_upload_binary: false,
             timeout: 30,
-            keepalive: 60,
+            socket: None,
+            keepalive: None,
+            algorithms: SshAlgorithms::default(),
         }),
         local: None,
         tcp: None,
@@ -174,6 +297,10 @@ fn test_transport_config_validation() {
             port: 9999,
             timeout: 30,
             tls: None,
+            auth_token: None,
+            noise: None,
+            socket: None,
+            knock: None,
         }),
         wsl: None,
         general: GeneralConfig::default(),
@@ -195,9 +322,14 @@ fn test_config_serialization() {
             username: "user".to_string(),
             password: None,
             key_path: Some(PathBuf::from("/path/to/key")),
+            cert_path: None,
+            cert_principal: None,
+            pkcs11: None,
             auto_upload_binary: true,
             timeout: 30,
-            keepalive: 60,
+            socket: None,
+            keepalive: None,
+            algorithms: SshAlgorithms::default(),
         }),
         local: None,
         tcp: None,
@@ -216,3 +348,88 @@ fn test_config_serialization() {
         deserialized.ssh.as_ref().unwrap().host
     );
 }
+
+#[test]
+fn test_socket_options_defaults() {
+    let tcp: TcpConfig = toml::from_str(
+        r#"
+        host = "example.com"
+        port = 9999
+
+        [socket]
+        recv_buffer = 4194304
+        bind_address = "10.0.0.2"
+        "#,
+    )
+    .unwrap();
+    let socket = tcp.socket.unwrap();
+    assert_eq!(
+        socket,
+        SocketOptions {
+            recv_buffer: Some(4 * 1024 * 1024),
+            bind_address: Some("10.0.0.2".parse().unwrap()),
+            ..SocketOptions::default()
+        }
+    );
+    assert_eq!(socket.keepalive, 60);
+    assert!(socket.nodelay);
+
+    let config = TransportBuilder::ssh()
+        .host("example.com")
+        .username("user")
+        .password("pass")
+        .keepalive(0)
+        .build()
+        .unwrap();
+    assert_eq!(config.ssh.unwrap().socket.unwrap().keepalive, 0);
+}
+
+#[test]
+fn test_ssh_legacy_keepalive() {
+    let ssh: SshConfig = toml::from_str(
+        r#"
+        host = "example.com"
+        username = "user"
+        keepalive = 15
+        "#,
+    )
+    .unwrap();
+    let defaults = SocketOptions {
+        nodelay: false,
+        ..SocketOptions::default()
+    };
+    let socket = ssh.socket_options(&defaults);
+    assert_eq!(socket.keepalive, 15);
+    assert!(!socket.nodelay);
+
+    // Socket options configured since take precedence
+    let ssh = SshConfig {
+        socket: Some(SocketOptions::default()),
+        ..ssh
+    };
+    assert_eq!(ssh.socket_options(&defaults), SocketOptions::default());
+}
+
+#[test]
+fn test_ssh_certificate_requires_key() {
+    let builder = || {
+        TransportBuilder::ssh()
+            .host("example.com")
+            .username("user")
+            .password("pass")
+            .cert_file("/path/to/key-cert.pub")
+    };
+    assert!(builder().build().is_err());
+    assert!(builder().key_file("/path/to/key").build().is_ok());
+}
+
+#[test]
+fn test_ssh_pkcs11_authentication() {
+    let builder = || TransportBuilder::ssh().host("example.com").username("user");
+    assert!(builder().build().is_err());
+    let config = builder().pkcs11("/usr/lib/libykcs11.so").build().unwrap();
+    assert_eq!(
+        config.ssh.unwrap().pkcs11.as_deref(),
+        Some("/usr/lib/libykcs11.so")
+    );
+}
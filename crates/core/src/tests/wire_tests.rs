@@ -0,0 +1,1057 @@
+//! Wire format golden tests
+//!
+//! Every request, response and response item variant is encoded through a
+//! [`MessageChannel`] and compared frame by frame with the fixtures in
+//! `src/tests/golden`, and the recorded frames are decoded again, so an
+//! accidental wire format change fails here instead of between a client and
+//! a remote built from different revisions.
+//!
+//! Fixtures are kept per wire revision, in `golden/v<N>`, and never rewritten
+//! once recorded. The current code must encode exactly what the newest
+//! revision, [`REVISION`], recorded, and decode what every revision recorded:
+//! the requests of earlier clients as the remote does and the responses of
+//! earlier remotes as the client does.
+//!
+//! `session.golden` is an authenticated session recorded with fixed nonces,
+//! and `session-without-extensions.golden` one with a remote predating
+//! extension negotiation, answering `Hello` as unsupported. Each is replayed in both directions: the current server code must accept the
+//! recorded client's handshake and frames, and the current client code the
+//! recorded server's. Live handshakes use fresh nonces, so the replay runs
+//! against the handshake primitives with the recorded nonces; the remote's
+//! tests replay the recorded clients' messages against a live server.
+//!
+//! Fixtures list one frame payload per line under a `[case]` header:
+//!
+//! ```text
+//! attachment 00ff        attachment frame, bytes after the marker in hex
+//! json {"PollData":..}   JSON envelope
+//! raw 1111..             handshake bytes in hex
+//! sealed 0 <tag> json .. authenticated frame: sequence, tag in hex, payload
+//! ```
+//!
+//! Cases of new requests, responses and items are added to the newest
+//! revision with `YUHA_UPDATE_GOLDEN=1 cargo test -p yuha-core wire_tests`,
+//! which leaves recorded cases alone. Changing the encoding of a recorded
+//! case takes a new revision: bump [`REVISION`] and run the same command to
+//! record it in full.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, duplex};
+
+use crate::access_log::{AccessEntry, AccessOutcome};
+use crate::checksum::HashAlgorithm;
+use crate::clipboard::{ClipboardFormat, ClipboardItem};
+use crate::message_channel::{FRAME_MAGIC, HEADER_LEN, MessageChannel};
+use crate::protocol::attachment::{ATTACHMENT_MARKER, BinaryEncoding};
+use crate::protocol::auth::{self, NONCE_LEN, Nonce, Role, SEQUENCE_LEN, SessionAuth, TAG_LEN};
+use crate::protocol::extension::EXPERIMENTAL_BASE;
+use crate::protocol::request_response::{
+    ClipboardWatch, DirectoryUsage, DisplayEnv, PortForwardEntry, Quota, Sandbox, SessionState,
+    TaskInfo, TaskKind, ToolInfo, TrashEntry, Usage,
+};
+use crate::protocol::{
+    Cursor, ErrorCode, ListQuery, ProtocolRequest, ProtocolResponse, ResponseItem, ServerReply,
+    ServerRequest, Topic, TopicEvent,
+};
+use crate::slow_log::SlowRequest;
+
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/golden");
+const UPDATE_ENV: &str = "YUHA_UPDATE_GOLDEN";
+
+/// Wire revision the current code records; fixtures of earlier ones must
+/// still decode
+const REVISION: u32 = 9;
+
+const TOKEN: &[u8] = b"golden-token";
+const CLIENT_NONCE: Nonce = [0x11; NONCE_LEN];
+const SERVER_NONCE: Nonce = [0x22; NONCE_LEN];
+
+#[derive(Debug)]
+enum Message {
+    Request(ProtocolRequest),
+    Response(ProtocolResponse),
+}
+
+impl Message {
+    async fn send<T: AsyncRead + AsyncWrite + Unpin>(&self, channel: &mut MessageChannel<T>) {
+        match self {
+            Message::Request(request) => channel.send_request(request).await.unwrap(),
+            Message::Response(response) => channel.send_response(response).await.unwrap(),
+        }
+    }
+
+    /// Frame payloads of this message as written by a channel
+    async fn encode(&self, encoding: BinaryEncoding) -> Vec<Bytes> {
+        let (sender, mut receiver) = duplex(1 << 20);
+        let mut channel = MessageChannel::new_with_stream(sender)
+            .with_frame_magic()
+            .with_binary_encoding(encoding);
+        self.send(&mut channel).await;
+        drop(channel);
+
+        let mut wire = Vec::new();
+        receiver.read_to_end(&mut wire).await.unwrap();
+        split_frames(&wire)
+    }
+
+    /// Decode `frames` as a message of the same kind as this one
+    async fn decode(&self, frames: &[Bytes]) -> crate::Result<Message> {
+        decode(matches!(self, Message::Request(_)), frames).await
+    }
+
+    /// Name of the case this message would be recorded under
+    fn case_name(&self) -> String {
+        match self {
+            Message::Request(request) => request.kind().to_string(),
+            Message::Response(ProtocolResponse::Data { items, .. }) => match items.first() {
+                Some(item) => format!("Data.{}", item_name(item)),
+                None => "Data".to_string(),
+            },
+            Message::Response(response) => {
+                let debug = format!("{:?}", response);
+                debug
+                    .split([' ', '(', '{'])
+                    .next()
+                    .unwrap_or_default()
+                    .to_string()
+            }
+        }
+    }
+}
+
+/// Decode `frames` as a request, as the remote does, or as a response, as
+/// the client does
+async fn decode(request: bool, frames: &[Bytes]) -> crate::Result<Message> {
+    let (mut sender, receiver) = duplex(1 << 20);
+    for frame in frames {
+        sender.write_all(&FRAME_MAGIC).await.unwrap();
+        sender.write_u16(frame.len() as u16).await.unwrap();
+        sender.write_all(frame).await.unwrap();
+    }
+    drop(sender);
+    let mut channel = MessageChannel::new_with_stream(receiver).with_frame_magic();
+    Ok(if request {
+        Message::Request(channel.receive_request().await?)
+    } else {
+        Message::Response(channel.receive_response().await?)
+    })
+}
+
+struct Case {
+    name: String,
+    message: Message,
+    encoding: BinaryEncoding,
+}
+
+impl Case {
+    fn request(request: ProtocolRequest) -> Self {
+        Self {
+            name: request.kind().to_string(),
+            message: Message::Request(request),
+            encoding: BinaryEncoding::Attachment,
+        }
+    }
+
+    fn response(name: &str, response: ProtocolResponse) -> Self {
+        Self {
+            name: name.to_string(),
+            message: Message::Response(response),
+            encoding: BinaryEncoding::Attachment,
+        }
+    }
+
+    fn data(item: ResponseItem) -> Self {
+        Self::response(
+            &format!("Data.{}", item_name(&item)),
+            ProtocolResponse::Data {
+                items: vec![item],
+                next: None,
+            },
+        )
+    }
+
+    fn inline(mut self) -> Self {
+        self.name.push_str(".inline");
+        self.encoding = BinaryEncoding::Inline;
+        self
+    }
+}
+
+fn item_name(item: &ResponseItem) -> &'static str {
+    match item {
+        ResponseItem::PortForwardData { .. } => "PortForwardData",
+        ResponseItem::NewConnection { .. } => "NewConnection",
+        ResponseItem::CloseConnection { .. } => "CloseConnection",
+        ResponseItem::Credit { .. } => "Credit",
+        ResponseItem::ClipboardContent { .. } => "ClipboardContent",
+        ResponseItem::ClipboardData { .. } => "ClipboardData",
+        ResponseItem::FileEntry { .. } => "FileEntry",
+        ResponseItem::DirectoryEntry { .. } => "DirectoryEntry",
+        ResponseItem::FileChunk { .. } => "FileChunk",
+        ResponseItem::FileRange { .. } => "FileRange",
+        ResponseItem::FileHash { .. } => "FileHash",
+        ResponseItem::DirectoryUsage { .. } => "DirectoryUsage",
+        ResponseItem::DiskUsageProgress { .. } => "DiskUsageProgress",
+        ResponseItem::Trashed { .. } => "Trashed",
+        ResponseItem::SlowRequest { .. } => "SlowRequest",
+        ResponseItem::Access { .. } => "Access",
+        ResponseItem::PortForward { .. } => "PortForward",
+        ResponseItem::Extensions { .. } => "Extensions",
+        ResponseItem::BinaryHash { .. } => "BinaryHash",
+        ResponseItem::ClipboardWatch { .. } => "ClipboardWatch",
+        ResponseItem::AppLaunched { .. } => "AppLaunched",
+        ResponseItem::Tool { .. } => "Tool",
+        ResponseItem::Task { .. } => "Task",
+        ResponseItem::Usage { .. } => "Usage",
+        ResponseItem::SessionState { .. } => "SessionState",
+        ResponseItem::CommandOutput { .. } => "CommandOutput",
+    }
+}
+
+fn file_entry(name: &str) -> ResponseItem {
+    ResponseItem::FileEntry {
+        path: format!("/data/{}", name),
+        relative_path: name.to_string(),
+        size: 1024,
+    }
+}
+
+fn file_chunk() -> ResponseItem {
+    ResponseItem::FileChunk {
+        path: "/data/a.txt".to_string(),
+        offset: 8192,
+        data: Bytes::from_static(b"\x00\x01chunk"),
+        crc32c: 0x1234_5678,
+        eof: true,
+    }
+}
+
+fn clipboard_items() -> Vec<ClipboardItem> {
+    vec![
+        ClipboardItem::text("hello world"),
+        ClipboardItem::new(ClipboardFormat::Png, &b"\x89PNG"[..]),
+    ]
+}
+
+fn session_state() -> SessionState {
+    SessionState {
+        forwards: vec![PortForwardEntry {
+            local_port: 8080,
+            remote_host: "localhost".to_string(),
+            remote_port: 80,
+        }],
+        topics: vec![Topic::Clipboard, Topic::Files],
+    }
+}
+
+fn write_file_chunk() -> ProtocolRequest {
+    ProtocolRequest::WriteFileChunk {
+        path: "/data/a.txt".to_string(),
+        offset: 8192,
+        data: Bytes::from_static(b"\x00\x01chunk"),
+        crc32c: 0x1234_5678,
+        last: true,
+        expected_digest: Some("af1349b9".to_string()),
+    }
+}
+
+fn port_forward_data() -> ProtocolRequest {
+    ProtocolRequest::PortForwardData {
+        connection_id: 7,
+        data: Bytes::from_static(b"\x00\xffdata"),
+    }
+}
+
+/// One case per request variant, plus inline variants of binary requests
+fn request_cases() -> Vec<Case> {
+    vec![
+        Case::request(ProtocolRequest::Hello {
+            extensions: vec![1, EXPERIMENTAL_BASE],
+            workspace: Some("frontend".to_string()),
+        }),
+        Case::request(ProtocolRequest::PollData),
+        Case::request(ProtocolRequest::StartPortForward {
+            local_port: 8080,
+            remote_host: "localhost".to_string(),
+            remote_port: 80,
+        }),
+        Case::request(ProtocolRequest::StopPortForward { local_port: 8080 }),
+        Case::request(ProtocolRequest::ReleasePortForward { local_port: 8080 }),
+        Case::request(port_forward_data()),
+        Case::request(port_forward_data()).inline(),
+        Case::request(ProtocolRequest::GrantCredit {
+            connection_id: 7,
+            bytes: 65536,
+        }),
+        Case::request(ProtocolRequest::GrantBatches { id: 7, batches: 1 }),
+        Case::request(ProtocolRequest::GetClipboard),
+        Case::request(ProtocolRequest::SetClipboard {
+            content: "hello world".to_string(),
+        }),
+        Case::request(ProtocolRequest::GetClipboardData {
+            accept: vec![ClipboardFormat::Html, ClipboardFormat::Text],
+        }),
+        Case::request(ProtocolRequest::SetClipboardData {
+            items: clipboard_items(),
+        }),
+        Case::request(ProtocolRequest::SetClipboardData {
+            items: clipboard_items(),
+        })
+        .inline(),
+        Case::request(ProtocolRequest::OpenBrowser {
+            url: "https://example.com/".to_string(),
+        }),
+        Case::request(ProtocolRequest::LaunchApp {
+            command: vec!["code".to_string(), "/data/a.txt".to_string()],
+            display_env: DisplayEnv {
+                display: Some(":0".to_string()),
+                wayland_display: None,
+            },
+            sandbox: Sandbox {
+                user: Some("guest".to_string()),
+                cpu_percent: Some(50),
+                memory_bytes: Some(1 << 30),
+                read_only: true,
+            },
+        }),
+        Case::request(ProtocolRequest::OpenPath {
+            path: "/data/a.txt".to_string(),
+        }),
+        Case::request(ProtocolRequest::ListFiles {
+            paths: vec!["/data".to_string()],
+            query: ListQuery {
+                offset: 10,
+                limit: Some(20),
+                filter: Some(".rs".to_string()),
+                ..Default::default()
+            },
+        }),
+        Case::request(ProtocolRequest::ListDirectory {
+            path: "/data".to_string(),
+        }),
+        Case::request(ProtocolRequest::ReadFileChunk {
+            path: "/data/a.txt".to_string(),
+            offset: 8192,
+            len: 4096,
+        }),
+        Case::request(ProtocolRequest::ReadFileRange {
+            path: "/var/log/syslog".to_string(),
+            offset: 65536,
+            len: 4096,
+        }),
+        Case::request(ProtocolRequest::HashPath {
+            path: "/data".to_string(),
+            algo: HashAlgorithm::Crc32c,
+        }),
+        Case::request(ProtocolRequest::DiskUsage {
+            path: "/data".to_string(),
+            depth: 2,
+        }),
+        Case::request(ProtocolRequest::RemovePath {
+            path: "/data/a.txt".to_string(),
+            trash: true,
+        }),
+        Case::request(ProtocolRequest::MovePath {
+            from: "/data/a.txt".to_string(),
+            to: "/data/b.txt".to_string(),
+            trash: false,
+        }),
+        Case::request(ProtocolRequest::RestorePath {
+            id: "1700000000000-1".to_string(),
+            to: Some("/data/c.txt".to_string()),
+        }),
+        Case::request(ProtocolRequest::ListTrash),
+        Case::request(write_file_chunk()),
+        Case::request(write_file_chunk()).inline(),
+        Case::request(ProtocolRequest::GetSlowLog),
+        Case::request(ProtocolRequest::GetAccessLog),
+        Case::request(ProtocolRequest::ProbeTools {
+            tools: vec!["git".to_string()],
+        }),
+        Case::request(ProtocolRequest::ListPortForwards {
+            query: ListQuery {
+                limit: Some(2),
+                cursor: Some(Cursor::at(2)),
+                ..Default::default()
+            },
+        }),
+        Case::request(ProtocolRequest::ListTasks),
+        Case::request(ProtocolRequest::CancelTask { id: 3 }),
+        Case::request(ProtocolRequest::GetUsage),
+        Case::request(ProtocolRequest::ExportSessionState),
+        Case::request(ProtocolRequest::ImportSessionState {
+            state: session_state(),
+        }),
+        Case::request(ProtocolRequest::Command {
+            name: "find_notes".to_string(),
+            args: serde_json::json!({ "filter": "milk", "limit": 3 }),
+        }),
+        Case::request(ProtocolRequest::ServerReply {
+            id: 1,
+            reply: ServerReply::Failed {
+                message: "No browser".to_string(),
+            },
+        }),
+        Case::request(ProtocolRequest::Subscribe {
+            topic: Topic::Files,
+        }),
+        Case::request(ProtocolRequest::Unsubscribe {
+            topic: Topic::PortActivity,
+        }),
+    ]
+}
+
+/// One case per response and response item variant, plus inline variants
+/// of binary items
+fn response_cases() -> Vec<Case> {
+    vec![
+        Case::response("Success", ProtocolResponse::Success),
+        Case::response(
+            "Error",
+            ProtocolResponse::error(ErrorCode::PermissionDenied, "Permission denied"),
+        ),
+        Case::response(
+            "Error.details",
+            ProtocolResponse::error(ErrorCode::NotFound, "No such file: notes.txt")
+                .with_detail("path", "notes.txt"),
+        ),
+        Case::response(
+            "Unsupported",
+            ProtocolResponse::Unsupported {
+                request_name: "FutureRequest".to_string(),
+            },
+        ),
+        Case::response(
+            "ServerRequest",
+            ProtocolResponse::ServerRequest {
+                id: 1,
+                request: ServerRequest::OpenUrl {
+                    url: "https://example.com".to_string(),
+                },
+            },
+        ),
+        Case::response(
+            "Event",
+            ProtocolResponse::Event {
+                event: TopicEvent::PathMoved {
+                    from: "/data/a.txt".to_string(),
+                    to: "/data/b.txt".to_string(),
+                },
+            },
+        ),
+        Case::response(
+            "Data.next",
+            ProtocolResponse::Data {
+                items: vec![file_entry("a.txt"), file_entry("b.txt")],
+                next: Some(Cursor::at(2)),
+            },
+        ),
+        Case::response(
+            "Batch",
+            ProtocolResponse::Batch {
+                items: vec![file_entry("a.txt"), file_entry("b.txt")],
+                more: true,
+            },
+        ),
+        Case::data(ResponseItem::PortForwardData {
+            connection_id: 7,
+            data: Bytes::from_static(b"\x00\xffdata"),
+        }),
+        Case::data(ResponseItem::NewConnection {
+            connection_id: 7,
+            local_port: 8080,
+        }),
+        Case::data(ResponseItem::CloseConnection { connection_id: 7 }),
+        Case::data(ResponseItem::Credit {
+            connection_id: 7,
+            bytes: 65536,
+        }),
+        Case::data(ResponseItem::ClipboardContent {
+            content: "hello world".to_string(),
+        }),
+        Case::data(ResponseItem::ClipboardData {
+            item: ClipboardItem::new(ClipboardFormat::Html, &b"<b>hi</b>"[..]),
+        }),
+        Case::data(file_entry("a.txt")),
+        Case::data(ResponseItem::DirectoryEntry {
+            name: "logs".to_string(),
+            dir: true,
+        }),
+        Case::data(file_chunk()),
+        Case::data(file_chunk()).inline(),
+        Case::data(ResponseItem::FileRange {
+            path: "/var/log/syslog".to_string(),
+            offset: 65536,
+            data: Bytes::from_static(b"line\n"),
+            crc32c: 0x1234_5678,
+            size: 65541,
+        }),
+        Case::data(ResponseItem::FileHash {
+            path: "/data/a.txt".to_string(),
+            relative_path: "a.txt".to_string(),
+            size: 1024,
+            hash: "e3069283".to_string(),
+        }),
+        Case::data(ResponseItem::DirectoryUsage {
+            usage: DirectoryUsage {
+                path: "/data/logs".to_string(),
+                depth: 1,
+                size: 1 << 30,
+                files: 1200,
+            },
+        }),
+        Case::data(ResponseItem::DiskUsageProgress {
+            entries: 100_000,
+            size: 1 << 32,
+        }),
+        Case::data(ResponseItem::Trashed {
+            entry: TrashEntry {
+                id: "1700000000000-1".to_string(),
+                original_path: "/data/a.txt".to_string(),
+                trashed_at: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            },
+        }),
+        Case::data(ResponseItem::SlowRequest {
+            request: SlowRequest {
+                request_type: "ReadFileChunk".to_string(),
+                peer: "127.0.0.1:50000".to_string(),
+                request_size: 64,
+                response_size: 8192,
+                duration: Duration::from_millis(1500),
+                completed_at: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            },
+        }),
+        Case::data(ResponseItem::Access {
+            entry: AccessEntry {
+                source: "192.0.2.7:50000".parse().unwrap(),
+                outcome: AccessOutcome::Rejected {
+                    reason: "Authentication failed".to_string(),
+                },
+                connected_at: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+                duration: Duration::from_millis(20),
+                client_key: None,
+            },
+        }),
+        Case::data(ResponseItem::PortForward {
+            forward: PortForwardEntry {
+                local_port: 8080,
+                remote_host: "localhost".to_string(),
+                remote_port: 80,
+            },
+        }),
+        Case::data(ResponseItem::Extensions {
+            extensions: vec![1],
+        }),
+        Case::data(ResponseItem::BinaryHash {
+            hash: "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262".to_string(),
+        }),
+        Case::data(ResponseItem::ClipboardWatch {
+            watch: ClipboardWatch::AdaptivePolling {
+                min_interval_ms: 250,
+                max_interval_ms: 5000,
+                max_cpu_percent: 2,
+            },
+        }),
+        Case::data(ResponseItem::AppLaunched { pid: 4242 }),
+        Case::data(ResponseItem::Tool {
+            tool: ToolInfo {
+                name: "git".to_string(),
+                path: Some("/usr/bin/git".to_string()),
+                version: Some("git version 2.43.0".to_string()),
+            },
+        }),
+        Case::data(ResponseItem::Task {
+            task: TaskInfo {
+                id: 3,
+                kind: TaskKind::Connection {
+                    connection_id: 7,
+                    local_port: 8080,
+                },
+                session: "127.0.0.1:50000".to_string(),
+                started_at: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            },
+        }),
+        Case::data(ResponseItem::Usage {
+            usage: Usage {
+                client: Some("client-key".to_string()),
+                bytes: 1 << 20,
+                forwards: 2,
+                exec_cpu: Duration::from_millis(1500),
+                resets_in: Some(Duration::from_secs(600)),
+                quota: Quota {
+                    bytes: Some(1 << 30),
+                    forwards: None,
+                    exec_cpu: Some(Duration::from_secs(60)),
+                },
+            },
+        }),
+        Case::data(ResponseItem::SessionState {
+            state: session_state(),
+        }),
+        Case::data(ResponseItem::CommandOutput {
+            value: serde_json::json!(["buy milk"]),
+        }),
+    ]
+}
+
+/// Messages of the recorded session, `true` for those sent by the client
+fn session_messages() -> Vec<(bool, Message)> {
+    vec![
+        (
+            true,
+            Message::Request(ProtocolRequest::Hello {
+                extensions: vec![1],
+                workspace: None,
+            }),
+        ),
+        (
+            false,
+            Message::Response(ProtocolResponse::Data {
+                items: vec![ResponseItem::Extensions {
+                    extensions: vec![1],
+                }],
+                next: None,
+            }),
+        ),
+        (
+            true,
+            Message::Request(ProtocolRequest::ListFiles {
+                paths: vec!["/data".to_string()],
+                query: ListQuery::default(),
+            }),
+        ),
+        (
+            false,
+            Message::Response(ProtocolResponse::Batch {
+                items: vec![file_entry("a.txt")],
+                more: true,
+            }),
+        ),
+        (
+            false,
+            Message::Response(ProtocolResponse::Batch {
+                items: vec![file_entry("b.txt")],
+                more: false,
+            }),
+        ),
+        (
+            true,
+            Message::Request(ProtocolRequest::ReadFileChunk {
+                path: "/data/a.txt".to_string(),
+                offset: 8192,
+                len: 4096,
+            }),
+        ),
+        (
+            false,
+            Message::Response(ProtocolResponse::Data {
+                items: vec![file_chunk()],
+                next: None,
+            }),
+        ),
+        (
+            true,
+            Message::Request(ProtocolRequest::SetClipboardData {
+                items: clipboard_items(),
+            }),
+        ),
+        (false, Message::Response(ProtocolResponse::Success)),
+    ]
+}
+
+/// Messages of the session with a remote that does not know `Hello`, which
+/// the client continues without extensions
+fn session_without_extensions_messages() -> Vec<(bool, Message)> {
+    vec![
+        (
+            true,
+            Message::Request(ProtocolRequest::Hello {
+                extensions: vec![1],
+                workspace: None,
+            }),
+        ),
+        (
+            false,
+            Message::Response(ProtocolResponse::Unsupported {
+                request_name: "Hello".to_string(),
+            }),
+        ),
+        (
+            true,
+            Message::Request(ProtocolRequest::ListFiles {
+                paths: vec!["/data".to_string()],
+                query: ListQuery::default(),
+            }),
+        ),
+        (
+            false,
+            Message::Response(ProtocolResponse::Batch {
+                items: vec![file_entry("a.txt")],
+                more: false,
+            }),
+        ),
+    ]
+}
+
+/// Recorded sessions and their messages
+fn sessions() -> [(&'static str, Vec<(bool, Message)>); 2] {
+    [
+        ("session.golden", session_messages()),
+        (
+            "session-without-extensions.golden",
+            session_without_extensions_messages(),
+        ),
+    ]
+}
+
+fn split_frames(mut wire: &[u8]) -> Vec<Bytes> {
+    let mut frames = Vec::new();
+    while !wire.is_empty() {
+        assert_eq!(wire[..2], FRAME_MAGIC);
+        let len = u16::from_be_bytes([wire[2], wire[3]]) as usize;
+        frames.push(Bytes::copy_from_slice(&wire[HEADER_LEN..HEADER_LEN + len]));
+        wire = &wire[HEADER_LEN + len..];
+    }
+    frames
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).expect("Invalid hex in golden file"))
+        .collect()
+}
+
+fn render(frame: &[u8]) -> String {
+    match frame.split_first() {
+        Some((&ATTACHMENT_MARKER, data)) => format!("attachment {}", to_hex(data)),
+        _ => match std::str::from_utf8(frame) {
+            Ok(json) if json.starts_with(['{', '"']) => format!("json {}", json),
+            _ => format!("raw {}", to_hex(frame)),
+        },
+    }
+}
+
+fn render_sealed(frame: &[u8]) -> String {
+    let (sequence, rest) = frame.split_at(SEQUENCE_LEN);
+    let (payload, tag) = rest.split_at(rest.len() - TAG_LEN);
+    format!(
+        "sealed {} {} {}",
+        u64::from_be_bytes(sequence.try_into().unwrap()),
+        to_hex(tag),
+        render(payload)
+    )
+}
+
+fn parse(line: &str) -> Bytes {
+    let (kind, rest) = line
+        .split_once(' ')
+        .unwrap_or_else(|| panic!("Invalid golden frame: {}", line));
+    let mut frame = BytesMut::new();
+    match kind {
+        "attachment" => {
+            frame.put_u8(ATTACHMENT_MARKER);
+            frame.put_slice(&from_hex(rest));
+        }
+        "json" => frame.put_slice(rest.as_bytes()),
+        "raw" => frame.put_slice(&from_hex(rest)),
+        "sealed" => {
+            let mut parts = rest.splitn(3, ' ');
+            let mut next = || parts.next().expect("Truncated sealed frame");
+            let sequence: u64 = next().parse().expect("Invalid sealed frame sequence");
+            let tag = from_hex(next());
+            frame.put_u64(sequence);
+            frame.put_slice(&parse(next()));
+            frame.put_slice(&tag);
+        }
+        other => panic!("Unknown golden frame kind: {}", other),
+    }
+    frame.freeze()
+}
+
+fn revision_dir(revision: u32) -> String {
+    format!("{}/v{}", GOLDEN_DIR, revision)
+}
+
+/// Cases of a fixture file and their frame lines
+fn read_fixture(path: &str) -> BTreeMap<String, Vec<String>> {
+    let text = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        panic!(
+            "Failed to read {}: {} (set {} to create it)",
+            path, e, UPDATE_ENV
+        )
+    });
+    let mut recorded = BTreeMap::new();
+    let mut lines: Option<&mut Vec<String>> = None;
+    for line in text.lines().filter(|line| !line.is_empty()) {
+        match line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            Some(name) => lines = Some(recorded.entry(name.to_string()).or_default()),
+            None => lines
+                .as_mut()
+                .expect("Golden frame outside a case")
+                .push(line.to_string()),
+        }
+    }
+    recorded
+}
+
+/// Compare `actual` frame lines with the newest revision's golden file and
+/// return the recorded ones
+///
+/// With `YUHA_UPDATE_GOLDEN` set, cases missing from the file are recorded
+/// from `actual` first; recorded cases are never rewritten.
+fn golden(file: &str, actual: &BTreeMap<String, Vec<String>>) -> BTreeMap<String, Vec<String>> {
+    let path = format!("{}/{}", revision_dir(REVISION), file);
+    if std::env::var_os(UPDATE_ENV).is_some() {
+        let mut recorded = if std::path::Path::new(&path).exists() {
+            read_fixture(&path)
+        } else {
+            BTreeMap::new()
+        };
+        let missing: Vec<_> = actual
+            .iter()
+            .filter(|(name, _)| !recorded.contains_key(*name))
+            .collect();
+        if !missing.is_empty() {
+            for (name, lines) in missing {
+                recorded.insert(name.clone(), lines.clone());
+            }
+            let text = recorded
+                .iter()
+                .map(|(name, lines)| format!("[{}]\n{}\n", name, lines.join("\n")))
+                .collect::<Vec<_>>()
+                .join("\n");
+            std::fs::create_dir_all(revision_dir(REVISION)).unwrap();
+            std::fs::write(&path, text).unwrap();
+        }
+    }
+
+    let recorded = read_fixture(&path);
+    for (name, lines) in actual {
+        assert_eq!(
+            recorded.get(name),
+            Some(lines),
+            "Wire format of {} changed from revision {} (see {}); record a new revision",
+            name,
+            REVISION,
+            path
+        );
+    }
+    assert_eq!(
+        recorded.keys().collect::<Vec<_>>(),
+        actual.keys().collect::<Vec<_>>(),
+        "Stale cases in {}",
+        path
+    );
+    recorded
+}
+
+async fn check_cases(file: &str, cases: Vec<Case>) {
+    let mut actual = BTreeMap::new();
+    for case in &cases {
+        let frames = case.message.encode(case.encoding).await;
+        let lines = frames.iter().map(|frame| render(frame)).collect();
+        assert!(
+            actual.insert(case.name.clone(), lines).is_none(),
+            "Duplicate case {}",
+            case.name
+        );
+    }
+
+    let recorded = golden(file, &actual);
+    for case in &cases {
+        let frames: Vec<_> = recorded[&case.name]
+            .iter()
+            .map(|line| parse(line))
+            .collect();
+        let decoded = case.message.decode(&frames).await.unwrap();
+        assert_eq!(
+            format!("{:?}", decoded),
+            format!("{:?}", case.message),
+            "Recorded {} decodes differently",
+            case.name
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_request_golden_frames() {
+    check_cases("requests.golden", request_cases()).await;
+}
+
+#[tokio::test]
+async fn test_response_golden_frames() {
+    check_cases("responses.golden", response_cases()).await;
+}
+
+/// Every case survives MessagePack, which has no fixtures of its own as
+/// its encoding follows from the same serde representation
+#[cfg(feature = "msgpack")]
+#[tokio::test]
+async fn test_cases_round_trip_msgpack() {
+    for case in request_cases().into_iter().chain(response_cases()) {
+        let (sender, receiver) = duplex(1 << 20);
+        let mut sending = MessageChannel::new_with_stream(sender)
+            .with_frame_magic()
+            .with_binary_encoding(case.encoding);
+        sending.use_msgpack();
+        case.message.send(&mut sending).await;
+        drop(sending);
+
+        let mut channel = MessageChannel::new_with_stream(receiver).with_frame_magic();
+        let decoded = match case.message {
+            Message::Request(_) => Message::Request(channel.receive_request().await.unwrap()),
+            Message::Response(_) => Message::Response(channel.receive_response().await.unwrap()),
+        };
+        assert_eq!(
+            format!("{:?}", decoded),
+            format!("{:?}", case.message),
+            "{} decodes differently from MessagePack",
+            case.name
+        );
+    }
+}
+
+/// Record a session of `messages`: handshake, then every message sealed by
+/// its sender
+async fn record_session(messages: &[(bool, Message)]) -> Vec<String> {
+    let server_proof = auth::handshake_proof(TOKEN, Role::Server, &CLIENT_NONCE, &SERVER_NONCE);
+    let client_proof = auth::handshake_proof(TOKEN, Role::Client, &CLIENT_NONCE, &SERVER_NONCE);
+    let mut lines = vec![
+        format!("> {}", render(&CLIENT_NONCE)),
+        format!("< {}", render(&[SERVER_NONCE, server_proof].concat())),
+        format!("> {}", render(&client_proof)),
+    ];
+
+    let mut client = SessionAuth::new(TOKEN, Role::Client, &CLIENT_NONCE, &SERVER_NONCE);
+    let mut server = SessionAuth::new(TOKEN, Role::Server, &CLIENT_NONCE, &SERVER_NONCE);
+    for (from_client, message) in messages {
+        let (direction, auth) = if *from_client {
+            (">", &mut client)
+        } else {
+            ("<", &mut server)
+        };
+        for frame in message.encode(BinaryEncoding::Attachment).await {
+            lines.push(format!(
+                "{} {}",
+                direction,
+                render_sealed(&auth.seal(&frame))
+            ));
+        }
+    }
+    lines
+}
+
+/// Decode a recorded session, checking its handshake with the current auth
+/// code, into its messages and whether each came from the client
+async fn replay_session(lines: &[String]) -> Vec<(bool, Message)> {
+    let mut frames = lines.iter().map(|line| {
+        let (direction, frame) = line.split_once(' ').expect("Missing frame direction");
+        (direction == ">", parse(frame))
+    });
+    let mut handshake = || frames.next().expect("Truncated handshake").1;
+    let (hello, challenge, client_proof) = (handshake(), handshake(), handshake());
+    let client_nonce: Nonce = hello[..].try_into().unwrap();
+    let server_nonce: Nonce = challenge[..NONCE_LEN].try_into().unwrap();
+
+    // The current server accepts the recorded client and vice versa
+    auth::verify_handshake_proof(
+        TOKEN,
+        Role::Server,
+        &client_nonce,
+        &server_nonce,
+        &client_proof,
+    )
+    .unwrap();
+    auth::verify_handshake_proof(
+        TOKEN,
+        Role::Client,
+        &client_nonce,
+        &server_nonce,
+        &challenge[NONCE_LEN..],
+    )
+    .unwrap();
+
+    let mut client = SessionAuth::new(TOKEN, Role::Client, &client_nonce, &server_nonce);
+    let mut server = SessionAuth::new(TOKEN, Role::Server, &client_nonce, &server_nonce);
+    let mut messages = Vec::new();
+    let mut payloads: Vec<Bytes> = Vec::new();
+    for (from_client, frame) in frames {
+        let auth = if from_client {
+            &mut server
+        } else {
+            &mut client
+        };
+        let payload = auth.open(frame).unwrap();
+        let complete = payload[0] != ATTACHMENT_MARKER;
+        payloads.push(payload);
+        if complete {
+            let message = decode(from_client, &std::mem::take(&mut payloads))
+                .await
+                .unwrap();
+            messages.push((from_client, message));
+        }
+    }
+    assert!(payloads.is_empty(), "Session ends inside a message");
+    messages
+}
+
+#[tokio::test]
+async fn test_recorded_sessions_replay() {
+    for (file, expected) in sessions() {
+        let actual = BTreeMap::from([("session".to_string(), record_session(&expected).await)]);
+        let recorded = golden(file, &actual);
+
+        let replayed = replay_session(&recorded["session"]).await;
+        assert_eq!(replayed.len(), expected.len(), "{} length differs", file);
+        for ((from_client, decoded), (sender, message)) in replayed.iter().zip(&expected) {
+            assert_eq!(from_client, sender, "Frame from the wrong side in {}", file);
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", message));
+        }
+    }
+}
+
+/// Requests of every earlier client revision decode on the current remote,
+/// and responses of every earlier remote revision on the current client
+#[tokio::test]
+async fn test_earlier_revisions_decode() {
+    for revision in 1..REVISION {
+        let dir = revision_dir(revision);
+        for (file, request) in [("requests.golden", true), ("responses.golden", false)] {
+            for (name, lines) in read_fixture(&format!("{}/{}", dir, file)) {
+                let frames: Vec<_> = lines.iter().map(|line| parse(line)).collect();
+                let decoded = decode(request, &frames).await.unwrap_or_else(|e| {
+                    panic!("Revision {} {} no longer decodes: {}", revision, name, e)
+                });
+                assert_eq!(
+                    name.split('.').next(),
+                    decoded.case_name().split('.').next(),
+                    "Revision {} {} decodes as another message",
+                    revision,
+                    name
+                );
+            }
+        }
+
+        let session = read_fixture(&format!("{}/session.golden", dir));
+        let replayed = replay_session(&session["session"]).await;
+        assert!(
+            !replayed.is_empty(),
+            "Revision {} session is empty",
+            revision
+        );
+    }
+}
@@ -1,10 +1,12 @@
 //! Builder pattern implementation for transport configuration
 
 use super::{
-    GeneralConfig, LocalConfig, SshConfig, TcpConfig, TlsConfig, TransportConfig, TransportType,
-    WslConfig,
+    GeneralConfig, KnockConfig, LocalConfig, NoiseSettings, SocketOptions, SshAlgorithms,
+    SshConfig, TcpConfig, TlsConfig, TransportConfig, TransportType, WslConfig,
 };
 use crate::error::Result;
+use crate::protocol::noise::NoisePublicKey;
+use crate::ssh_agent::AgentKey;
 use std::path::PathBuf;
 
 /// Builder for creating transport configurations using the builder pattern
@@ -94,9 +96,14 @@ impl SshTransportBuilder {
                 username: String::new(),
                 password: None,
                 key_path: None,
+                cert_path: None,
+                cert_principal: None,
+                pkcs11: None,
                 auto_upload_binary: false,
                 timeout: 30,
-                keepalive: 60,
+                socket: None,
+                keepalive: None,
+                algorithms: SshAlgorithms::default(),
             },
             general: GeneralConfig::default(),
         }
@@ -132,6 +139,25 @@ impl SshTransportBuilder {
         self
     }
 
+    /// Set the OpenSSH certificate of the key
+    pub fn cert_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config.cert_path = Some(path.into());
+        self
+    }
+
+    /// Set the principal the certificate must be valid for, when it is not
+    /// the user name
+    pub fn cert_principal<S: Into<String>>(mut self, principal: S) -> Self {
+        self.config.cert_principal = Some(principal.into());
+        self
+    }
+
+    /// Set the PKCS#11 provider of token-backed keys
+    pub fn pkcs11<S: Into<String>>(mut self, provider: S) -> Self {
+        self.config.pkcs11 = Some(provider.into());
+        self
+    }
+
     /// Enable automatic binary upload
     pub fn auto_upload_binary(mut self) -> Self {
         self.config.auto_upload_binary = true;
@@ -144,9 +170,24 @@ impl SshTransportBuilder {
         self
     }
 
-    /// Set keepalive interval
+    /// Set the idle time before keep-alive probes, `0` to send none
     pub fn keepalive(mut self, seconds: u64) -> Self {
-        self.config.keepalive = seconds;
+        self.config
+            .socket
+            .get_or_insert_with(SocketOptions::default)
+            .keepalive = seconds;
+        self
+    }
+
+    /// Set the TCP socket options
+    pub fn socket(mut self, options: SocketOptions) -> Self {
+        self.config.socket = Some(options);
+        self
+    }
+
+    /// Set the algorithm preferences and compression
+    pub fn algorithms(mut self, algorithms: SshAlgorithms) -> Self {
+        self.config.algorithms = algorithms;
         self
     }
 
@@ -256,6 +297,10 @@ impl TcpTransportBuilder {
                 port: 0,
                 timeout: 30,
                 tls: None,
+                auth_token: None,
+                noise: None,
+                socket: None,
+                knock: None,
             },
             general: GeneralConfig::default(),
         }
@@ -279,6 +324,43 @@ impl TcpTransportBuilder {
         self
     }
 
+    /// Set the shared token used to authenticate the session
+    pub fn auth_token<S: Into<String>>(mut self, token: S) -> Self {
+        self.config.auth_token = Some(token.into());
+        self
+    }
+
+    /// Encrypt the session with Noise, using the key in `key_file` and
+    /// trusting only a remote holding `remote_key`
+    pub fn noise<P: Into<PathBuf>>(mut self, key_file: P, remote_key: NoisePublicKey) -> Self {
+        self.config.noise = Some(NoiseSettings {
+            key_file: key_file.into(),
+            remote_key,
+            sealed_to: None,
+        });
+        self
+    }
+
+    /// Seal the Noise key file to the ssh-agent's `key`
+    pub fn seal_noise_key(mut self, key: AgentKey) -> Self {
+        if let Some(noise) = &mut self.config.noise {
+            noise.sealed_to = Some(key);
+        }
+        self
+    }
+
+    /// Set the TCP socket options
+    pub fn socket(mut self, options: SocketOptions) -> Self {
+        self.config.socket = Some(options);
+        self
+    }
+
+    /// Knock before connecting
+    pub fn knock(mut self, knock: KnockConfig) -> Self {
+        self.config.knock = Some(knock);
+        self
+    }
+
     /// Enable TLS
     pub fn with_tls(self) -> TlsBuilder {
         TlsBuilder::new(self)
@@ -374,6 +456,7 @@ impl WslTransportBuilder {
                 user: None,
                 binary_path: None,
                 working_dir: None,
+                expose_ports: false,
             },
             general: GeneralConfig::default(),
         }
@@ -403,6 +486,12 @@ impl WslTransportBuilder {
         self
     }
 
+    /// Expose forwarded ports to Windows applications
+    pub fn expose_ports(mut self, expose: bool) -> Self {
+        self.config.expose_ports = expose;
+        self
+    }
+
     /// Add environment variable
     pub fn with_env_var<K, V>(mut self, key: K, value: V) -> Self
     where
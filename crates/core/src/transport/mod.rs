@@ -7,9 +7,9 @@
 //! ## Supported Transport Types
 //!
 //! - **SSH Transport**: Connect to remote server via SSH with automatic binary upload
-//!   - Supports key-based and password authentication
+//!   - Supports key-based, certificate and password authentication
 //!   - Automatic detection and upload of yuha-remote binary
-//!   - Configurable connection parameters (timeout, keepalive)
+//!   - Configurable connection parameters (timeout, socket options)
 //!
 //! - **Local Transport**: Spawn local process and communicate via stdin/stdout
 //!   - Useful for development and testing
@@ -17,7 +17,9 @@
 //!
 //! - **TCP Transport**: Direct TCP connection to running daemon
 //!   - Optional TLS encryption support
-//!   - Configurable connection timeouts
+//!   - Optional Noise encryption inside the message channel
+//!   - Optional port knocking or single packet authorization before connecting
+//!   - Configurable connection timeouts and socket options
 //!
 //! - **WSL Transport**: Windows Subsystem for Linux integration
 //!   - Platform-specific transport for Windows users
@@ -42,10 +44,15 @@
 //!     .build()?;
 //! ```
 
+use crate::child_env::ChildEnv;
 use crate::error::{Result, TransportError};
+use crate::message_channel::{KeepaliveConfig, ReadBufferConfig};
+use crate::protocol::noise::NoisePublicKey;
+use crate::ssh_agent::AgentKey;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::path::PathBuf;
 use tokio::io::{AsyncRead, AsyncWrite};
 
@@ -112,15 +119,64 @@ pub struct SshConfig {
     pub password: Option<String>,
     /// Private key file path
     pub key_path: Option<PathBuf>,
+    /// OpenSSH certificate of the private key, signed by a user CA; defaults
+    /// to `<key_path>-cert.pub` when that exists
+    pub cert_path: Option<PathBuf>,
+    /// Principal the certificate must be valid for, e.g. a role the CA
+    /// issues that the server maps to `username` with an
+    /// `AuthorizedPrincipalsFile`; defaults to `username`
+    pub cert_principal: Option<String>,
+    /// PKCS#11 provider library whose token keys the ssh-agent signs with,
+    /// e.g. a YubiKey's PIV slots through `libykcs11.so`; `key_path` then
+    /// names the public key of the one token key to use, if not any
+    pub pkcs11: Option<String>,
     /// Auto-upload binary if not present
     #[serde(default)]
     pub auto_upload_binary: bool,
     /// Connection timeout in seconds
     #[serde(default = "default_timeout")]
     pub timeout: u64,
-    /// Keep-alive interval in seconds
-    #[serde(default = "default_keepalive")]
-    pub keepalive: u64,
+    /// Tuning of the TCP connection to the SSH server, the client's defaults
+    /// when unset
+    #[serde(default)]
+    pub socket: Option<SocketOptions>,
+    /// Seconds before keep-alive probes, as configured before
+    /// `socket.keepalive` replaced it; applies when `socket` is unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keepalive: Option<u64>,
+    /// Algorithm preferences and compression
+    #[serde(default)]
+    pub algorithms: SshAlgorithms,
+}
+
+impl SshConfig {
+    /// Socket options to connect with: those configured, or else `defaults`
+    /// with the legacy `keepalive` applied
+    pub fn socket_options(&self, defaults: &SocketOptions) -> SocketOptions {
+        self.socket.clone().unwrap_or_else(|| SocketOptions {
+            keepalive: self.keepalive.unwrap_or(defaults.keepalive),
+            ..defaults.clone()
+        })
+    }
+}
+
+/// SSH algorithm preferences, most preferred first
+///
+/// Empty lists keep the client's defaults. Names are those of the SSH
+/// protocol, e.g. `aes256-gcm@openssh.com`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SshAlgorithms {
+    /// Key exchange algorithms
+    #[serde(default)]
+    pub kex: Vec<String>,
+    #[serde(default)]
+    pub ciphers: Vec<String>,
+    #[serde(default)]
+    pub macs: Vec<String>,
+    /// Prefer zlib compression, which pays off on slow links but costs CPU
+    /// on both ends
+    #[serde(default)]
+    pub compression: bool,
 }
 
 /// Local process transport configuration
@@ -147,6 +203,92 @@ pub struct TcpConfig {
     pub timeout: u64,
     /// TLS configuration
     pub tls: Option<TlsConfig>,
+    /// Shared token for authenticating the session when TLS is not in use
+    #[serde(default)]
+    pub auth_token: Option<String>,
+    /// Noise encryption of the session when TLS is not in use
+    #[serde(default)]
+    pub noise: Option<NoiseSettings>,
+    /// Tuning of the TCP connection, the client's defaults when unset
+    #[serde(default)]
+    pub socket: Option<SocketOptions>,
+    /// Knocks opening the port before connecting, for daemons behind port
+    /// knocking or single packet authorization
+    #[serde(default)]
+    pub knock: Option<KnockConfig>,
+}
+
+/// TCP socket tuning, as high-latency links and large transfers call for
+/// other settings than a LAN
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SocketOptions {
+    /// Seconds a connection idles before keep-alive probes are sent, `0` to
+    /// send none
+    #[serde(default = "default_keepalive")]
+    pub keepalive: u64,
+    /// Send small writes right away instead of coalescing them (`TCP_NODELAY`)
+    #[serde(default = "default_nodelay")]
+    pub nodelay: bool,
+    /// Send buffer size in bytes (`SO_SNDBUF`), the system's when unset
+    #[serde(default)]
+    pub send_buffer: Option<u32>,
+    /// Receive buffer size in bytes (`SO_RCVBUF`), the system's when unset
+    #[serde(default)]
+    pub recv_buffer: Option<u32>,
+    /// Local address to connect from, e.g. to pick a network interface
+    #[serde(default)]
+    pub bind_address: Option<IpAddr>,
+}
+
+/// Noise encryption of a TCP session (see [`crate::protocol::noise`])
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NoiseSettings {
+    /// Private key file of this side, generated on first use
+    pub key_file: PathBuf,
+    /// Public key the remote must hold
+    pub remote_key: NoisePublicKey,
+    /// ssh-agent key, e.g. a PKCS#11 token's, the key file is sealed to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed_to: Option<AgentKey>,
+}
+
+/// Steps taken before a TCP connection, for firewalls that open the port
+/// only to hosts that knocked first
+///
+/// The command runs first, then the ports of `sequence` are knocked on in
+/// order, each step `delay_ms` after the previous one, and the connection
+/// follows `delay_ms` after the last step.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KnockConfig {
+    /// Ports knocked on, in order
+    #[serde(default)]
+    pub sequence: Vec<KnockStep>,
+    /// Command sending a single packet authorization, e.g.
+    /// `["fwknop", "-n", "server"]`; the connection fails if it does
+    #[serde(default)]
+    pub command: Option<Vec<String>>,
+    /// Milliseconds between steps and before connecting
+    #[serde(default = "default_knock_delay")]
+    pub delay_ms: u64,
+}
+
+/// A knock on a port of the host connected to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KnockStep {
+    pub port: u16,
+    #[serde(default)]
+    pub protocol: KnockProtocol,
+}
+
+/// How a port is knocked on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KnockProtocol {
+    /// A connection attempt, given up on after the knock delay
+    #[default]
+    Tcp,
+    /// An empty datagram
+    Udp,
 }
 
 /// TLS configuration for TCP transport
@@ -177,6 +319,9 @@ pub struct WslConfig {
     pub binary_path: Option<PathBuf>,
     /// Working directory in WSL
     pub working_dir: Option<PathBuf>,
+    /// Add Windows port proxy rules so forwarded ports are reachable via localhost
+    #[serde(default)]
+    pub expose_ports: bool,
 }
 
 /// General configuration that applies to all transports
@@ -193,6 +338,15 @@ pub struct GeneralConfig {
     pub env_vars: HashMap<String, String>,
     /// Remote binary path override
     pub remote_binary_path: Option<PathBuf>,
+    /// Read buffer sizing for the message channel
+    #[serde(default)]
+    pub read_buffer: ReadBufferConfig,
+    /// Dead-peer detection for the message channel
+    #[serde(default)]
+    pub keepalive: KeepaliveConfig,
+    /// Environment of locally spawned remote processes
+    #[serde(default)]
+    pub child_env: ChildEnv,
 }
 
 /// Transport metadata for introspection
@@ -224,6 +378,12 @@ fn default_retry_delay() -> u64 {
 fn default_tls_verify() -> bool {
     true
 }
+fn default_nodelay() -> bool {
+    true
+}
+fn default_knock_delay() -> u64 {
+    100
+}
 
 impl Default for TransportConfig {
     fn default() -> Self {
@@ -238,6 +398,18 @@ impl Default for TransportConfig {
     }
 }
 
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            keepalive: default_keepalive(),
+            nodelay: default_nodelay(),
+            send_buffer: None,
+            recv_buffer: None,
+            bind_address: None,
+        }
+    }
+}
+
 impl Default for LocalConfig {
     fn default() -> Self {
         Self {
@@ -255,6 +427,9 @@ impl Default for GeneralConfig {
             retry_delay: default_retry_delay(),
             env_vars: HashMap::new(),
             remote_binary_path: None,
+            read_buffer: ReadBufferConfig::default(),
+            keepalive: KeepaliveConfig::default(),
+            child_env: ChildEnv::default(),
         }
     }
 }
@@ -340,9 +515,16 @@ impl TransportConfig {
                     .into());
                 }
 
-                if ssh.password.is_none() && ssh.key_path.is_none() {
+                if ssh.cert_path.is_some() && ssh.key_path.is_none() {
+                    return Err(TransportError::ConfigurationError {
+                        reason: "SSH certificate requires the key it certifies".to_string(),
+                    }
+                    .into());
+                }
+
+                if ssh.password.is_none() && ssh.key_path.is_none() && ssh.pkcs11.is_none() {
                     return Err(TransportError::ConfigurationError {
-                        reason: "SSH transport requires either password or key authentication"
+                        reason: "SSH transport requires password, key or PKCS#11 authentication"
                             .to_string(),
                     }
                     .into());
@@ -384,6 +566,25 @@ impl TransportConfig {
                     }
                     .into());
                 }
+
+                if let Some(knock) = &tcp.knock {
+                    if knock.sequence.iter().any(|step| step.port == 0) {
+                        return Err(TransportError::ConfigurationError {
+                            reason: "Knock port cannot be 0".to_string(),
+                        }
+                        .into());
+                    }
+                    if knock
+                        .command
+                        .as_ref()
+                        .is_some_and(|command| command.is_empty())
+                    {
+                        return Err(TransportError::ConfigurationError {
+                            reason: "Knock command cannot be empty".to_string(),
+                        }
+                        .into());
+                    }
+                }
             }
             TransportType::Wsl => {
                 if !cfg!(windows) {
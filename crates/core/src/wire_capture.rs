@@ -0,0 +1,310 @@
+//! # Wire Capture
+//!
+//! Records the frames a [`MessageChannel`](crate::message_channel::MessageChannel)
+//! sends and receives, with their direction and timing, so protocol issues
+//! can be looked into without packet captures of the transport underneath.
+//! A recorder is attached with
+//! [`with_recorder`](crate::message_channel::MessageChannel::with_recorder);
+//! frames are recorded as they are on the wire, so after authentication
+//! their payloads are sealed.
+//!
+//! A capture is read back with [`CaptureReader`], and [`Replay`] feeds the
+//! frames of one direction through the decode path of another channel:
+//!
+//! ```rust
+//! use yuha_core::message_channel::MessageChannel;
+//! use yuha_core::wire_capture::{CaptureReader, Direction, Replay};
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let records = CaptureReader::open("session.ycap")?.collect::<Result<Vec<_>, _>>()?;
+//! let mut channel = MessageChannel::new_with_stream(Replay::new(records, Direction::Received));
+//! let response = channel.receive_response().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ## Format
+//!
+//! A capture starts with [`CAPTURE_MAGIC`] and the wall clock time the
+//! recording started at, in microseconds since the Unix epoch. Each record
+//! follows as its direction (0 sent, 1 received), microseconds since the
+//! start, frame length and frame bytes; numbers are big endian.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::warn;
+
+/// Bytes opening every capture, ending in the format version
+pub const CAPTURE_MAGIC: [u8; 8] = *b"YUHACAP\x01";
+
+/// Largest frame a capture holds; a longer recorded length is corruption
+const MAX_RECORD_LEN: usize = 64 * 1024 * 1024;
+
+/// Which way a frame went, seen from the recording channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    fn byte(self) -> u8 {
+        match self {
+            Direction::Sent => 0,
+            Direction::Received => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            0 => Ok(Direction::Sent),
+            1 => Ok(Direction::Received),
+            other => Err(invalid_data(format!("Unknown direction {}", other))),
+        }
+    }
+}
+
+/// A recorded frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub direction: Direction,
+    /// Time since the recording started
+    pub elapsed: Duration,
+    /// The whole frame: header, payload and checksum trailer
+    pub frame: Bytes,
+}
+
+/// Writes the frames of a channel to a capture
+///
+/// Clones record to the same capture. A failed write is logged and ends
+/// the recording rather than the traffic recorded.
+#[derive(Clone)]
+pub struct WireRecorder {
+    started: Instant,
+    output: Arc<Mutex<Option<Box<dyn Write + Send>>>>,
+}
+
+impl std::fmt::Debug for WireRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WireRecorder")
+            .field("started", &self.started)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WireRecorder {
+    /// Record to a new capture file at `path`, replacing any file there
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+
+    /// Record to `output`, starting with the capture header
+    pub fn new(mut output: impl Write + Send + 'static) -> io::Result<Self> {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        output.write_all(&CAPTURE_MAGIC)?;
+        output.write_all(&(since_epoch.as_micros() as u64).to_be_bytes())?;
+        output.flush()?;
+        Ok(Self {
+            started: Instant::now(),
+            output: Arc::new(Mutex::new(Some(Box::new(output)))),
+        })
+    }
+
+    /// Record a frame made of `parts` concatenated
+    pub fn record(&self, direction: Direction, parts: &[&[u8]]) {
+        let mut output = self.output.lock().unwrap();
+        let Some(writer) = output.as_mut() else {
+            return;
+        };
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        let mut header = BytesMut::with_capacity(13);
+        header.put_u8(direction.byte());
+        header.put_u64(self.started.elapsed().as_micros() as u64);
+        header.put_u32(len as u32);
+
+        let written = std::iter::once(&header[..])
+            .chain(parts.iter().copied())
+            .try_for_each(|part| writer.write_all(part))
+            .and_then(|_| writer.flush());
+        if let Err(e) = written {
+            warn!("Failed to record a frame, stopping the capture: {}", e);
+            *output = None;
+        }
+    }
+}
+
+/// Reads the records of a capture in order
+pub struct CaptureReader<R> {
+    input: R,
+    /// Wall clock time the recording started at
+    pub started: SystemTime,
+}
+
+impl CaptureReader<BufReader<File>> {
+    /// Read the capture file at `path`
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Read a capture from `input`, checking its header
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut magic = [0u8; CAPTURE_MAGIC.len()];
+        input.read_exact(&mut magic)?;
+        if magic != CAPTURE_MAGIC {
+            return Err(invalid_data("Not a wire capture".to_string()));
+        }
+        let mut micros = [0u8; 8];
+        input.read_exact(&mut micros)?;
+        let started = UNIX_EPOCH + Duration::from_micros(u64::from_be_bytes(micros));
+        Ok(Self { input, started })
+    }
+
+    /// The next record, `None` at the end of the capture
+    pub fn next_record(&mut self) -> io::Result<Option<Record>> {
+        let mut header = [0u8; 13];
+        match self.input.read_exact(&mut header[..1]) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        self.input.read_exact(&mut header[1..])?;
+
+        let direction = Direction::from_byte(header[0])?;
+        let micros = u64::from_be_bytes(header[1..9].try_into().unwrap());
+        let len = u32::from_be_bytes(header[9..].try_into().unwrap()) as usize;
+        if len > MAX_RECORD_LEN {
+            return Err(invalid_data(format!("Record of {} bytes", len)));
+        }
+        let mut frame = vec![0u8; len];
+        self.input.read_exact(&mut frame)?;
+        Ok(Some(Record {
+            direction,
+            elapsed: Duration::from_micros(micros),
+            frame: Bytes::from(frame),
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// Stream yielding the recorded frames of one direction and discarding
+/// what is written to it, e.g. the pongs a replayed ping asks for
+///
+/// The stream ends after the last frame, so a channel reading from it
+/// reports the channel closed.
+#[derive(Debug)]
+pub struct Replay {
+    data: Bytes,
+}
+
+impl Replay {
+    /// Replay the frames of `records` that went in `direction`
+    pub fn new(records: impl IntoIterator<Item = Record>, direction: Direction) -> Self {
+        let mut data = BytesMut::new();
+        for record in records {
+            if record.direction == direction {
+                data.extend_from_slice(&record.frame);
+            }
+        }
+        Self {
+            data: data.freeze(),
+        }
+    }
+}
+
+impl AsyncRead for Replay {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let len = buf.remaining().min(self.data.len());
+        let chunk = self.data.split_to(len);
+        buf.put_slice(&chunk);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Replay {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn invalid_data(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Capture kept in memory
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_capture_round_trip() {
+        let capture = Shared::default();
+        let recorder = WireRecorder::new(capture.clone()).unwrap();
+        recorder.record(Direction::Sent, &[b"\xd9\x1e", b"\x00\x02", b"hi"]);
+        recorder.clone().record(Direction::Received, &[b"frame"]);
+
+        let bytes = capture.0.lock().unwrap().clone();
+        let records: Vec<_> = CaptureReader::new(&bytes[..])
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].direction, Direction::Sent);
+        assert_eq!(&records[0].frame[..], b"\xd9\x1e\x00\x02hi");
+        assert_eq!(records[1].direction, Direction::Received);
+        assert!(records[1].elapsed >= records[0].elapsed);
+
+        // A capture cut off inside a record is an error, not its end
+        let mut cut = CaptureReader::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(cut.next_record().unwrap().is_some());
+        assert!(cut.next_record().is_err());
+        assert!(CaptureReader::new(&b"not a capture"[..]).is_err());
+    }
+}
@@ -0,0 +1,37 @@
+//! A transport of its own wrapping the local one, counting session traffic
+//!
+//! ```text
+//! cargo run -p yuha-examples --example custom_transport
+//! ```
+
+use yuha_core::protocol::ListQuery;
+use yuha_examples::harness::Harness;
+use yuha_examples::metered::MeteredTransport;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut meter = None;
+    let harness = Harness::start_with(|transport| {
+        let transport = MeteredTransport::new(transport);
+        meter = Some(transport.meter());
+        transport
+    })
+    .await?;
+    let meter = meter.expect("the transport was made");
+    println!(
+        "Handshake: {} bytes sent, {} received",
+        meter.sent(),
+        meter.received()
+    );
+
+    let client = harness.client();
+    for _ in 0..10 {
+        client.list_port_forwards(ListQuery::default()).await?;
+    }
+    println!(
+        "After 10 requests: {} bytes sent, {} received",
+        meter.sent(),
+        meter.received()
+    );
+    Ok(())
+}
@@ -0,0 +1,42 @@
+//! A UI frame loop driving a client that runs on a thread of its own
+//!
+//! ```text
+//! cargo run -p yuha-examples --example embed_gui [DIR]
+//! ```
+
+use std::time::Duration;
+use yuha_client::testing;
+use yuha_examples::gui::{Backend, Request, Update};
+
+fn main() -> anyhow::Result<()> {
+    let dir = std::env::args().nth(1).unwrap_or_else(|| ".".to_string());
+    let backend = Backend::spawn(|| testing::connect_local(testing::remote_binary()))?;
+
+    // A toolkit would call the body of this loop on every frame
+    let mut pending = true;
+    let mut frames = 0;
+    while pending {
+        for update in backend.poll() {
+            match update {
+                Update::Connected => backend.send(Request::ListFiles(dir.clone())),
+                Update::Files(files) => {
+                    println!(
+                        "{} files under {} after {} frames:",
+                        files.len(),
+                        dir,
+                        frames
+                    );
+                    for file in files.iter().take(20) {
+                        println!("  {}", file);
+                    }
+                    pending = false;
+                }
+                Update::Failed(error) => anyhow::bail!(error),
+                update => println!("{:?}", update),
+            }
+        }
+        frames += 1;
+        std::thread::sleep(Duration::from_millis(16));
+    }
+    Ok(())
+}
@@ -0,0 +1,42 @@
+//! Awaiting the events of a port forward while using the same session
+//!
+//! ```text
+//! cargo run -p yuha-examples --example event_subscription
+//! ```
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use yuha_client::testing;
+use yuha_examples::harness::{Harness, echo_server};
+use yuha_examples::subscription::{SessionEvent, subscribe};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let harness = Harness::start().await?;
+    let client = harness.client();
+    let port = testing::free_port()?;
+    let echo = echo_server().await?;
+    client
+        .start_port_forward(port, "127.0.0.1".to_string(), echo)
+        .await?;
+    println!("Forwarding port {} to the echo server on {}", port, echo);
+    let mut events = subscribe(client.clone());
+
+    tokio::spawn(async move {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await?;
+        for line in ["hello\n", "world\n"] {
+            stream.write_all(line.as_bytes()).await?;
+            let mut echoed = vec![0u8; line.len()];
+            stream.read_exact(&mut echoed).await?;
+        }
+        anyhow::Ok(())
+    });
+
+    while let Some(event) = events.next().await {
+        println!("{:?}", event);
+        if let SessionEvent::Closed { .. } = event {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,29 @@
+//! Pushing a local tree to a remote one and verifying the result
+//!
+//! ```text
+//! cargo run -p yuha-examples --example file_sync
+//! ```
+
+use yuha_examples::harness::Harness;
+use yuha_examples::sync;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let harness = Harness::start().await?;
+    harness.write("local/README.md", "# Notes\n")?;
+    harness.write("local/src/main.rs", "fn main() {}\n")?;
+    harness.write("remote/src/main.rs", "fn main() { todo!() }\n")?;
+    harness.write("remote/stale.txt", "left alone\n")?;
+    let local = harness.dir().join("local");
+    let remote = harness.dir().join("remote").display().to_string();
+
+    for difference in sync::push(harness.client(), &local, &remote).await? {
+        println!("{}", difference);
+    }
+    let left = harness
+        .client()
+        .compare(&local, &remote, Default::default())
+        .await?;
+    println!("{} differences left after the push", left.len());
+    Ok(())
+}
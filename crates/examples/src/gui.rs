@@ -0,0 +1,163 @@
+//! A client embedded in a GUI
+//!
+//! UI toolkits own the main thread and redraw from a frame loop that must
+//! not block. [`Backend`] runs the client on a thread with a runtime of its
+//! own: the UI queues [`Request`]s with [`Backend::send`] and picks up the
+//! [`Update`]s answering them with [`Backend::poll`] on each frame. Requests
+//! are answered one at a time, in order.
+
+use std::future::Future;
+use std::io;
+use std::sync::mpsc as std_mpsc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use yuha_client::transport::Transport;
+use yuha_client::{Client, ClientError};
+use yuha_core::protocol::ListQuery;
+
+/// Something the UI asks of the remote
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// The text on the remote clipboard
+    Clipboard,
+    /// Put text on the remote clipboard
+    SetClipboard(String),
+    /// The paths of the files under a remote directory
+    ListFiles(String),
+}
+
+/// Something for the UI to show
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Update {
+    /// The session is up and requests are answered
+    Connected,
+    Clipboard(String),
+    ClipboardSet,
+    Files(Vec<String>),
+    /// Connecting or a request failed
+    Failed(String),
+}
+
+/// A client on a thread of its own, driven without blocking
+pub struct Backend {
+    requests: Option<mpsc::UnboundedSender<Request>>,
+    updates: std_mpsc::Receiver<Update>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Backend {
+    /// Start a thread connecting with `connect`, then answering requests
+    /// until the backend is dropped
+    pub fn spawn<T, F, Fut>(connect: F) -> io::Result<Self>
+    where
+        T: Transport + 'static,
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Client<T>, ClientError>>,
+    {
+        let (request_sender, mut requests) = mpsc::unbounded_channel();
+        let (update_sender, updates) = std_mpsc::channel();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let thread = std::thread::Builder::new()
+            .name("yuha-client".to_string())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    let client = match connect().await {
+                        Ok(client) => client,
+                        Err(e) => {
+                            let _ = update_sender.send(Update::Failed(e.to_string()));
+                            return;
+                        }
+                    };
+                    let _ = update_sender.send(Update::Connected);
+                    while let Some(request) = requests.recv().await {
+                        let update = answer(&client, request)
+                            .await
+                            .unwrap_or_else(|e| Update::Failed(e.to_string()));
+                        if update_sender.send(update).is_err() {
+                            return;
+                        }
+                    }
+                })
+            })?;
+        Ok(Self {
+            requests: Some(request_sender),
+            updates,
+            thread: Some(thread),
+        })
+    }
+
+    /// Queue `request`, answered by an update
+    pub fn send(&self, request: Request) {
+        if let Some(requests) = &self.requests {
+            // The thread only stops early when connecting failed, which
+            // was reported by an update already
+            let _ = requests.send(request);
+        }
+    }
+
+    /// Updates that arrived since the last call, without blocking
+    pub fn poll(&self) -> Vec<Update> {
+        self.updates.try_iter().collect()
+    }
+
+    /// Wait up to `timeout` for the next update
+    pub fn wait(&self, timeout: Duration) -> Option<Update> {
+        self.updates.recv_timeout(timeout).ok()
+    }
+}
+
+impl Drop for Backend {
+    fn drop(&mut self) {
+        // Closing the requests ends the thread after the request in flight
+        self.requests.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+async fn answer<T: Transport>(client: &Client<T>, request: Request) -> Result<Update, ClientError> {
+    let update = match request {
+        Request::Clipboard => Update::Clipboard(client.get_clipboard().await?),
+        Request::SetClipboard(text) => {
+            client.set_clipboard(text).await?;
+            Update::ClipboardSet
+        }
+        Request::ListFiles(path) => {
+            let files = client.list_files(vec![path], ListQuery::default()).await?;
+            Update::Files(files.into_iter().map(|file| file.relative_path).collect())
+        }
+    };
+    Ok(update)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yuha_client::testing;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn test_backend_answers_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "notes").unwrap();
+        let backend = Backend::spawn(|| testing::connect_local(testing::remote_binary())).unwrap();
+        assert_eq!(backend.wait(TIMEOUT), Some(Update::Connected));
+
+        let path = dir.path().to_str().unwrap().to_string();
+        backend.send(Request::ListFiles(path));
+        backend.send(Request::ListFiles(
+            dir.path().join("missing").display().to_string(),
+        ));
+        let Some(Update::Files(files)) = backend.wait(TIMEOUT) else {
+            panic!("Expected the files first");
+        };
+        assert!(files.iter().any(|file| file.ends_with("notes.txt")));
+        assert!(matches!(backend.wait(TIMEOUT), Some(Update::Failed(_))));
+        assert!(backend.poll().is_empty());
+    }
+}
@@ -0,0 +1,82 @@
+//! Isolated local remotes for the examples and their tests
+//!
+//! A [`Harness`] starts the `yuha-remote` built along with the client
+//! library through [`yuha_client::testing`], so harnesses run side by side,
+//! and owns a scratch directory removed with it. The remote runs on this
+//! host, so paths in the scratch directory are valid on both sides.
+
+use anyhow::Result;
+use std::path::Path;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use yuha_client::Client;
+use yuha_client::testing;
+use yuha_client::transport::{LocalTransport, Transport, TransportConfig};
+
+/// A connected local remote and a scratch directory
+pub struct Harness<T: Transport = LocalTransport> {
+    client: Client<T>,
+    dir: TempDir,
+}
+
+impl Harness {
+    pub async fn start() -> Result<Self> {
+        Self::start_with(|transport| transport).await
+    }
+}
+
+impl<T: Transport> Harness<T> {
+    /// Start a remote over the transport `wrap` makes of the local one
+    pub async fn start_with(wrap: impl FnOnce(LocalTransport) -> T) -> Result<Self> {
+        let transport =
+            testing::local_transport(testing::remote_binary(), TransportConfig::default());
+        let mut client = Client::new(wrap(transport));
+        client.connect().await?;
+        Ok(Self {
+            client,
+            dir: tempfile::tempdir()?,
+        })
+    }
+
+    pub fn client(&self) -> &Client<T> {
+        &self.client
+    }
+
+    /// Scratch directory, removed with the harness
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Write `content` to `relative_path` in the scratch directory, creating
+    /// its parents
+    pub fn write(&self, relative_path: &str, content: &str) -> Result<()> {
+        let path = self.dir().join(relative_path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+/// Start a loopback TCP server echoing what it receives, returning its port
+///
+/// The server runs until the runtime shuts down.
+pub async fn echo_server() -> Result<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buf = [0u8; 4096];
+                while let Ok(len @ 1..) = stream.read(&mut buf).await {
+                    if stream.write_all(&buf[..len]).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    Ok(port)
+}
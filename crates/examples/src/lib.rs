@@ -0,0 +1,26 @@
+//! # Yuha Examples
+//!
+//! End-to-end scenarios embedding the client library, each run by a program
+//! in `examples/` and tested against a real local remote, so the public API
+//! keeps covering what embedders need:
+//!
+//! - **GUI Embedding** ([`gui`]): a client on a runtime thread of its own,
+//!   driven from a UI frame loop that never blocks (`embed_gui`)
+//! - **Custom Transports** ([`metered`]): a `Transport` wrapping another one,
+//!   counting the bytes of its sessions (`custom_transport`)
+//! - **Event Subscription** ([`subscription`]): connections and data of port
+//!   forwards and copied text as a channel of events (`event_subscription`)
+//! - **File Sync** ([`sync`]): pushing the files of a local tree that differ
+//!   from a remote one (`file_sync`)
+//! - **Harness** ([`harness`]): isolated local remotes with scratch
+//!   directories, for the examples and their tests
+//!
+//! ```text
+//! cargo run -p yuha-examples --example file_sync
+//! ```
+
+pub mod gui;
+pub mod harness;
+pub mod metered;
+pub mod subscription;
+pub mod sync;
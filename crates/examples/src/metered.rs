@@ -0,0 +1,177 @@
+//! A custom transport counting the bytes of its sessions
+//!
+//! [`MeteredTransport`] wraps any other [`Transport`], connecting through it
+//! and counting what passes its stream in a shared [`Meter`]. Everything
+//! else a transport reports, from the auth token to the port forward
+//! callbacks, is the wrapped one's.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use yuha_client::transport::Transport;
+use yuha_core::message_channel::{KeepaliveConfig, ReadBufferConfig};
+use yuha_core::protocol::noise::NoiseConfig;
+
+/// Bytes sent and received by the sessions of a transport
+#[derive(Debug, Default)]
+pub struct Meter {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+impl Meter {
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+}
+
+/// Transport counting the bytes of the sessions of the one it wraps
+pub struct MeteredTransport<T> {
+    inner: T,
+    meter: Arc<Meter>,
+}
+
+impl<T: Transport> MeteredTransport<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            meter: Arc::default(),
+        }
+    }
+
+    /// Counts of every session connected through this transport
+    pub fn meter(&self) -> Arc<Meter> {
+        self.meter.clone()
+    }
+}
+
+#[async_trait]
+impl<T: Transport> Transport for MeteredTransport<T> {
+    type Stream = MeteredStream<T::Stream>;
+
+    async fn connect(&self) -> Result<Self::Stream> {
+        Ok(MeteredStream {
+            inner: self.inner.connect().await?,
+            meter: self.meter.clone(),
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        "metered"
+    }
+
+    fn auth_token(&self) -> Option<&str> {
+        self.inner.auth_token()
+    }
+
+    fn noise(&self) -> Option<&NoiseConfig> {
+        self.inner.noise()
+    }
+
+    fn remote_binary(&self) -> Option<&Path> {
+        self.inner.remote_binary()
+    }
+
+    fn read_buffer(&self) -> ReadBufferConfig {
+        self.inner.read_buffer()
+    }
+
+    fn keepalive(&self) -> KeepaliveConfig {
+        self.inner.keepalive()
+    }
+
+    async fn forward_started(&self, local_port: u16) -> Result<()> {
+        self.inner.forward_started(local_port).await
+    }
+
+    async fn forward_stopped(&self, local_port: u16) -> Result<()> {
+        self.inner.forward_stopped(local_port).await
+    }
+}
+
+/// Stream of a [`MeteredTransport`] session
+pub struct MeteredStream<S> {
+    inner: S,
+    meter: Arc<Meter>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for MeteredStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - filled;
+        self.meter
+            .received
+            .fetch_add(read as u64, Ordering::Relaxed);
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for MeteredStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = &poll {
+            self.meter
+                .sent
+                .fetch_add(*written as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::Harness;
+    use yuha_core::protocol::ListQuery;
+
+    #[tokio::test]
+    async fn test_meter_counts_session() {
+        let mut meter = None;
+        let harness = Harness::start_with(|transport| {
+            let transport = MeteredTransport::new(transport);
+            meter = Some(transport.meter());
+            transport
+        })
+        .await
+        .unwrap();
+        let meter = meter.unwrap();
+        // The handshake went both ways
+        let (sent, received) = (meter.sent(), meter.received());
+        assert!(sent > 0 && received > 0);
+
+        harness
+            .client()
+            .list_port_forwards(ListQuery::default())
+            .await
+            .unwrap();
+        assert!(meter.sent() > sent);
+        assert!(meter.received() > received);
+    }
+}
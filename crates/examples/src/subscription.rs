@@ -0,0 +1,147 @@
+//! Events of a session as a channel
+//!
+//! [`subscribe`] polls the remote in a task of its own and turns what it
+//! pushes into [`SessionEvent`]s, so an application awaits events while
+//! still sending requests over clones of the same client. Data of port
+//! forwards is acknowledged as it is received, as the polling loop of the
+//! client does.
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use yuha_client::transport::Transport;
+use yuha_client::{Client, ClientError};
+use yuha_core::protocol::ResponseItem;
+
+/// Something the remote pushed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// A connection to the port forward on `local_port` was accepted
+    Connected { connection_id: u32, local_port: u16 },
+    /// Data of a forwarded connection arrived
+    Data { connection_id: u32, len: usize },
+    /// A forwarded connection was closed
+    Closed { connection_id: u32 },
+    /// Text was copied on the remote (`clipboard-watch` extension)
+    Copied { text: String },
+}
+
+impl SessionEvent {
+    fn from_item(item: ResponseItem) -> Option<Self> {
+        let event = match item {
+            ResponseItem::NewConnection {
+                connection_id,
+                local_port,
+            } => SessionEvent::Connected {
+                connection_id,
+                local_port,
+            },
+            ResponseItem::PortForwardData {
+                connection_id,
+                data,
+            } => SessionEvent::Data {
+                connection_id,
+                len: data.len(),
+            },
+            ResponseItem::CloseConnection { connection_id } => {
+                SessionEvent::Closed { connection_id }
+            }
+            ResponseItem::ClipboardContent { content } => SessionEvent::Copied { text: content },
+            _ => return None,
+        };
+        Some(event)
+    }
+}
+
+/// Events of a session, polled until dropped
+pub struct Subscription {
+    events: mpsc::UnboundedReceiver<SessionEvent>,
+    poller: JoinHandle<Result<(), ClientError>>,
+}
+
+impl Subscription {
+    /// The next event, or `None` once polling failed
+    pub async fn next(&mut self) -> Option<SessionEvent> {
+        self.events.recv().await
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.poller.abort();
+    }
+}
+
+/// Subscribe to the events of the session of `client`
+pub fn subscribe<T: Transport + 'static>(client: Client<T>) -> Subscription {
+    let (sender, events) = mpsc::unbounded_channel();
+    let poller = tokio::spawn(async move {
+        client
+            .start_polling_loop(move |item| match SessionEvent::from_item(item) {
+                Some(event) => sender.send(event).is_ok(),
+                None => true,
+            })
+            .await
+    });
+    Subscription { events, poller }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Harness, echo_server};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::time::timeout;
+    use yuha_client::testing;
+
+    #[tokio::test]
+    async fn test_forwarded_connection_events() {
+        let harness = Harness::start().await.unwrap();
+        let client = harness.client();
+        let port = testing::free_port().unwrap();
+        let echo = echo_server().await.unwrap();
+        client
+            .start_port_forward(port, "127.0.0.1".to_string(), echo)
+            .await
+            .unwrap();
+        let mut events = subscribe(client.clone());
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut echoed = [0u8; 4];
+        stream.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"ping");
+        drop(stream);
+
+        let mut seen = Vec::new();
+        timeout(Duration::from_secs(10), async {
+            while let Some(event) = events.next().await {
+                let closed = matches!(event, SessionEvent::Closed { .. });
+                seen.push(event);
+                if closed {
+                    break;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        let SessionEvent::Connected {
+            connection_id,
+            local_port,
+        } = seen[0]
+        else {
+            panic!("Expected a connection first, got {:?}", seen);
+        };
+        assert_eq!(local_port, port);
+        let data: usize = seen
+            .iter()
+            .filter_map(|event| match event {
+                SessionEvent::Data { len, .. } => Some(len),
+                _ => None,
+            })
+            .sum();
+        assert!(data > 0);
+        assert_eq!(seen.last(), Some(&SessionEvent::Closed { connection_id }));
+    }
+}
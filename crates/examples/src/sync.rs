@@ -0,0 +1,77 @@
+//! Pushing a local tree to a remote one
+//!
+//! [`push`] compares both trees by their hashes and uploads the local files
+//! that are missing or differ on the remote, leaving files only present on
+//! the remote alone. Directories are not created, so a file in a directory
+//! missing on the remote fails its upload.
+
+use std::path::Path;
+use yuha_client::compare::Difference;
+use yuha_client::transport::Transport;
+use yuha_client::{Client, ClientError};
+use yuha_core::checksum::HashAlgorithm;
+
+/// Upload the files of the local tree `local` differing from the remote
+/// tree `remote`, returning the differences found before
+pub async fn push<T: Transport>(
+    client: &Client<T>,
+    local: &Path,
+    remote: &str,
+) -> Result<Vec<Difference>, ClientError> {
+    let differences = client
+        .compare(local, remote, HashAlgorithm::default())
+        .await?;
+    for difference in &differences {
+        let relative_path = match difference {
+            Difference::Changed { relative_path, .. } | Difference::OnlyLocal { relative_path } => {
+                relative_path
+            }
+            Difference::OnlyRemote { .. } => continue,
+        };
+        // An empty relative path is the compared file itself
+        let (src, dest) = match relative_path.as_str() {
+            "" => (local.to_path_buf(), remote.to_string()),
+            path => (
+                local.join(path),
+                format!("{}/{}", remote.trim_end_matches('/'), path),
+            ),
+        };
+        client.upload_file(&src, &dest).await?;
+    }
+    Ok(differences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::Harness;
+
+    #[tokio::test]
+    async fn test_push_uploads_differences() {
+        let harness = Harness::start().await.unwrap();
+        harness.write("local/same.txt", "same").unwrap();
+        harness.write("local/docs/changed.md", "new").unwrap();
+        harness.write("local/added.txt", "added").unwrap();
+        harness.write("remote/same.txt", "same").unwrap();
+        harness.write("remote/docs/changed.md", "old").unwrap();
+        harness.write("remote/extra.txt", "extra").unwrap();
+        let local = harness.dir().join("local");
+        let remote = harness.dir().join("remote");
+        let remote = remote.to_str().unwrap();
+
+        let pushed = push(harness.client(), &local, remote).await.unwrap();
+        let paths: Vec<_> = pushed.iter().map(Difference::relative_path).collect();
+        assert_eq!(paths, ["added.txt", "docs/changed.md", "extra.txt"]);
+        let changed = std::fs::read_to_string(harness.dir().join("remote/docs/changed.md"));
+        assert_eq!(changed.unwrap(), "new");
+
+        // Only the file missing locally is left
+        let left = push(harness.client(), &local, remote).await.unwrap();
+        assert_eq!(
+            left,
+            [Difference::OnlyRemote {
+                relative_path: "extra.txt".to_string()
+            }]
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! # Yuha Macros
+//!
+//! Procedural macros of the Yuha protocol, re-exported by `yuha_core`.
+//!
+//! [`macro@yuha_command`] turns one trait definition into everything a new
+//! protocol command needs on both sides, so a feature is added by writing
+//! the trait and implementing it on the remote.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    Error, FnArg, GenericArgument, Ident, ItemTrait, Pat, PathArguments, ReturnType, TraitItem,
+    TraitItemFn, Type, parse_macro_input,
+};
+
+/// Define protocol commands with a trait of async methods
+///
+/// Each method takes `&self` and owned, serializable arguments and returns a
+/// `Result` whose error is displayed to the client. For a trait `Notes` with
+/// a method `add_note`, the macro generates:
+///
+/// - an `AddNote` struct of the arguments, the command's request
+///   (`ProtocolRequest::Command` named `add_note`), implementing `Command`
+/// - a `dispatch_command` method on `Notes`, running the method a `Command`
+///   request names
+/// - a `NotesHandler` wrapping an `Arc` of an implementation, the
+///   `CommandHandler` the remote registers to serve the commands
+/// - a `NotesClient` trait, implemented for every `CommandSender` such as
+///   the client, with an `add_note` method sending the command
+///
+/// The trait is made an `async_trait`, so implementations need the
+/// `#[async_trait]` attribute too.
+///
+/// ```rust,ignore
+/// #[yuha_command]
+/// pub trait Notes {
+///     /// Append a note, returning the number of notes
+///     async fn add_note(&self, text: String) -> anyhow::Result<usize>;
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn yuha_command(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return Error::new(Span::call_site(), "yuha_command takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let item = parse_macro_input!(item as ItemTrait);
+    expand(item)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// A trait method defining a command
+struct Command<'a> {
+    method: &'a TraitItemFn,
+    /// Argument struct, the method name in upper camel case
+    request: Ident,
+    args: Vec<(&'a Ident, &'a Type)>,
+    output: &'a Type,
+}
+
+impl<'a> Command<'a> {
+    fn parse(method: &'a TraitItemFn) -> syn::Result<Self> {
+        let sig = &method.sig;
+        if sig.asyncness.is_none() {
+            return Err(Error::new_spanned(sig, "commands must be async"));
+        }
+        if !sig.generics.params.is_empty() {
+            return Err(Error::new_spanned(
+                &sig.generics,
+                "commands cannot be generic",
+            ));
+        }
+
+        let mut inputs = sig.inputs.iter();
+        match inputs.next() {
+            Some(FnArg::Receiver(receiver))
+                if receiver.reference.is_some() && receiver.mutability.is_none() => {}
+            _ => return Err(Error::new_spanned(sig, "commands must take `&self`")),
+        }
+        let args = inputs
+            .map(|input| match input {
+                FnArg::Typed(arg) => match &*arg.pat {
+                    Pat::Ident(pat) => Ok((&pat.ident, &*arg.ty)),
+                    pat => Err(Error::new_spanned(pat, "arguments must be plain names")),
+                },
+                FnArg::Receiver(receiver) => Err(Error::new_spanned(receiver, "unexpected self")),
+            })
+            .collect::<syn::Result<_>>()?;
+
+        Ok(Self {
+            method,
+            request: Ident::new(&upper_camel_case(&sig.ident.to_string()), sig.ident.span()),
+            args,
+            output: ok_type(&sig.output)?,
+        })
+    }
+}
+
+/// `T` of a `Result<T, E>` or `Result<T>` return type
+fn ok_type(output: &ReturnType) -> syn::Result<&Type> {
+    let error = || Error::new_spanned(output, "commands must return a `Result`");
+    let ReturnType::Type(_, ty) = output else {
+        return Err(error());
+    };
+    let Type::Path(path) = &**ty else {
+        return Err(error());
+    };
+    let segment = path.path.segments.last().ok_or_else(error)?;
+    if segment.ident != "Result" {
+        return Err(error());
+    }
+    let PathArguments::AngleBracketed(generics) = &segment.arguments else {
+        return Err(error());
+    };
+    match generics.args.first() {
+        Some(GenericArgument::Type(ty)) => Ok(ty),
+        _ => Err(error()),
+    }
+}
+
+fn upper_camel_case(name: &str) -> String {
+    name.split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn expand(mut item: ItemTrait) -> syn::Result<TokenStream2> {
+    let private = quote!(::yuha_core::protocol::command::__private);
+    let command_path = quote!(::yuha_core::protocol::command);
+    let protocol = quote!(::yuha_core::protocol);
+
+    let methods: Vec<TraitItemFn> = item
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            TraitItem::Fn(method) => Some(method.clone()),
+            _ => None,
+        })
+        .collect();
+    let commands = methods
+        .iter()
+        .map(Command::parse)
+        .collect::<syn::Result<Vec<_>>>()?;
+    if commands.is_empty() {
+        return Err(Error::new_spanned(
+            &item.ident,
+            "a command trait needs at least one command",
+        ));
+    }
+
+    let vis = &item.vis;
+    let name = &item.ident;
+    let client = format_ident!("{}Client", name);
+    let handler = format_ident!("{}Handler", name);
+
+    let mut requests = Vec::new();
+    let mut arms = Vec::new();
+    let mut client_methods = Vec::new();
+    for command in &commands {
+        let Command {
+            method,
+            request,
+            args,
+            output,
+        } = command;
+        let ident = &method.sig.ident;
+        let command_name = ident.to_string();
+        let docs = method
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("doc"));
+        let arg_names: Vec<_> = args.iter().map(|(name, _)| name).collect();
+        let arg_types: Vec<_> = args.iter().map(|(_, ty)| ty).collect();
+        let request_doc = format!("Request of [`{}::{}`]", name, ident);
+
+        requests.push(quote! {
+            #[doc = #request_doc]
+            #[derive(Debug, #private::serde::Serialize, #private::serde::Deserialize)]
+            #[serde(crate = "::yuha_core::protocol::command::__private::serde")]
+            #vis struct #request {
+                #(pub #arg_names: #arg_types,)*
+            }
+
+            impl #command_path::Command for #request {
+                const NAME: &'static str = #command_name;
+                type Output = #output;
+            }
+        });
+        arms.push(quote! {
+            #command_name => Some(match #private::serde_json::from_value::<#request>(args) {
+                Ok(#request { #(#arg_names),* }) => {
+                    #private::respond(self.#ident(#(#arg_names),*).await)
+                }
+                Err(e) => #private::invalid_args(name, e),
+            }),
+        });
+        client_methods.push(quote! {
+            #(#docs)*
+            async fn #ident(&self, #(#arg_names: #arg_types),*) -> ::std::result::Result<#output, Self::Error> {
+                self.call(#request { #(#arg_names),* }).await
+            }
+        });
+    }
+
+    item.items.push(syn::parse_quote! {
+        /// Run the command `name` with `args` if it is one of this trait's
+        async fn dispatch_command(
+            &self,
+            name: &str,
+            args: #private::serde_json::Value,
+        ) -> ::std::option::Option<#protocol::ProtocolResponse> {
+            match name {
+                #(#arms)*
+                _ => None,
+            }
+        }
+    });
+    let client_doc = format!("Client side of [`{}`], sending its commands", name);
+    let handler_doc = format!(
+        "Serves the commands of [`{}`] with the implementation it holds",
+        name
+    );
+
+    Ok(quote! {
+        #[#private::async_trait::async_trait]
+        #item
+
+        #(#requests)*
+
+        #[doc = #client_doc]
+        #[#private::async_trait::async_trait]
+        #vis trait #client: #command_path::CommandSender {
+            #(#client_methods)*
+        }
+
+        impl<S: #command_path::CommandSender + ?Sized> #client for S {}
+
+        #[doc = #handler_doc]
+        #vis struct #handler<T: ?Sized>(pub ::std::sync::Arc<T>);
+
+        #[#private::async_trait::async_trait]
+        impl<T: #name + Send + Sync + ?Sized + 'static> #command_path::CommandHandler for #handler<T> {
+            async fn dispatch(
+                &self,
+                name: &str,
+                args: #private::serde_json::Value,
+            ) -> ::std::option::Option<#protocol::ProtocolResponse> {
+                self.0.dispatch_command(name, args).await
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upper_camel_case() {
+        assert_eq!(upper_camel_case("add_note"), "AddNote");
+        assert_eq!(upper_camel_case("list"), "List");
+        assert_eq!(upper_camel_case("_get__slow_log"), "GetSlowLog");
+    }
+
+    #[test]
+    fn test_commands_must_return_result() {
+        let item: ItemTrait = syn::parse_quote! {
+            trait Notes {
+                async fn count(&self) -> usize;
+            }
+        };
+        let error = expand(item).unwrap_err();
+        assert!(error.to_string().contains("must return a `Result`"));
+    }
+}
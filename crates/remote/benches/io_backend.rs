@@ -0,0 +1,152 @@
+//! Compares the epoll and io_uring backends for file reads and port
+//! forwarding relays.
+//!
+//! Run with `cargo bench -p yuha-remote --features io-uring` to include the
+//! io_uring backend; without the feature only the epoll path is measured.
+
+use bytes::Bytes;
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio::sync::{RwLock, mpsc};
+use yuha_core::protocol::ResponseBuffer;
+use yuha_core::protocol::request_response::MAX_FILE_CHUNK_LEN;
+use yuha_remote::{files, forward};
+
+const FILE_LEN: usize = 4 * 1024 * 1024;
+const RELAY_LEN: usize = 16 * 1024 * 1024;
+
+fn file_read(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.bin");
+    std::fs::write(&path, vec![0xa5; FILE_LEN]).unwrap();
+    let path = path.to_string_lossy().into_owned();
+    let chunk = MAX_FILE_CHUNK_LEN as usize;
+
+    let mut group = c.benchmark_group("file_read");
+    group.throughput(Throughput::Bytes(FILE_LEN as u64));
+
+    group.bench_function(BenchmarkId::new("epoll", chunk), |b| {
+        b.to_async(&runtime).iter(|| async {
+            for offset in (0..FILE_LEN).step_by(chunk) {
+                files::epoll_read_at(&path, offset as u64, chunk)
+                    .await
+                    .unwrap();
+            }
+        })
+    });
+
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    if let Some(uring) = yuha_remote::uring::runtime() {
+        group.bench_function(BenchmarkId::new("io_uring", chunk), |b| {
+            b.to_async(&runtime).iter(|| async {
+                for offset in (0..FILE_LEN).step_by(chunk) {
+                    uring
+                        .read_at(path.clone().into(), offset as u64, chunk)
+                        .await
+                        .unwrap();
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+/// Connected `(accepted, connecting)` stream pair
+async fn stream_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let connecting = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (accepted, _) = listener.accept().await.unwrap();
+    (accepted, connecting)
+}
+
+/// Push [`RELAY_LEN`] bytes from the client through a relay to the target
+async fn relay_through<F, Fut>(relay: F)
+where
+    F: FnOnce(
+        TcpStream,
+        TcpStream,
+        Arc<RwLock<ResponseBuffer>>,
+        mpsc::UnboundedReceiver<Bytes>,
+    ) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let (client_side, mut client) = stream_pair().await;
+    let (mut target, target_side) = stream_pair().await;
+    let (_data_tx, data_rx) = mpsc::unbounded_channel();
+    let relay = tokio::spawn(relay(
+        client_side,
+        target_side,
+        Arc::new(RwLock::new(ResponseBuffer::new())),
+        data_rx,
+    ));
+
+    let writer = tokio::spawn(async move {
+        let data = vec![0x5a; 64 * 1024];
+        for _ in 0..RELAY_LEN / data.len() {
+            client.write_all(&data).await.unwrap();
+        }
+    });
+    let mut buf = vec![0; 64 * 1024];
+    let mut received = 0;
+    while received < RELAY_LEN {
+        received += target.read(&mut buf).await.unwrap();
+    }
+
+    writer.await.unwrap();
+    relay.await.unwrap();
+}
+
+fn port_forward(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("port_forward");
+    group.throughput(Throughput::Bytes(RELAY_LEN as u64));
+
+    group.bench_function("epoll", |b| {
+        b.to_async(&runtime).iter(|| {
+            relay_through(|client, target, response_buffer, data_rx| {
+                forward::epoll_relay(
+                    client,
+                    target,
+                    0,
+                    response_buffer,
+                    data_rx,
+                    Arc::new(forward::Flow::new(false)),
+                )
+            })
+        })
+    });
+
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    if let Some(uring) = yuha_remote::uring::runtime() {
+        group.bench_function("io_uring", |b| {
+            b.to_async(&runtime).iter(|| {
+                relay_through(|client, target, response_buffer, data_rx| async move {
+                    uring
+                        .relay(
+                            client.into_std().unwrap(),
+                            target.into_std().unwrap(),
+                            0,
+                            response_buffer,
+                            data_rx,
+                            Arc::new(forward::Flow::new(false)),
+                        )
+                        .await
+                        .unwrap()
+                })
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, file_read, port_forward);
+criterion_main!(benches);
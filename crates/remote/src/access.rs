@@ -0,0 +1,344 @@
+//! Access log of TCP connections and alerts on repeated rejections
+//!
+//! [`AccessMonitor`] records every TCP connection in the server's
+//! [`AccessLog`]: a rejected one when its authentication fails, an accepted
+//! one when its session ends. Given [`FailureAlerts`], it also posts to a
+//! webhook once a source is rejected `threshold` times within `window`,
+//! so a daemon being probed is noticed before its log is read. Given an
+//! [`EventBus`], it publishes the connections and rejections as events.
+
+use crate::events::{Event, EventBus};
+use anyhow::{Context, Result, bail};
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tracing::{info, warn};
+use yuha_core::access_log::{AccessEntry, AccessLog, AccessOutcome};
+
+/// Time a webhook is given to answer
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Records the connections of a server, alerting on repeated rejections
+#[derive(Debug)]
+pub struct AccessMonitor {
+    log: Arc<AccessLog>,
+    alerts: Option<FailureAlerts>,
+    events: EventBus,
+}
+
+impl AccessMonitor {
+    pub fn new(log: AccessLog) -> Self {
+        Self {
+            log: Arc::new(log),
+            alerts: None,
+            events: EventBus::default(),
+        }
+    }
+
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    pub fn with_alerts(mut self, alerts: FailureAlerts) -> Self {
+        self.alerts = Some(alerts);
+        self
+    }
+
+    pub fn log(&self) -> &Arc<AccessLog> {
+        &self.log
+    }
+
+    /// Record a connection from `source` closed before being served
+    pub fn rejected(&self, source: SocketAddr, connected_at: SystemTime, reason: String) {
+        warn!("Rejected connection from {}: {}", source, reason);
+        self.events.publish(Event::AuthFailure {
+            peer: source.to_string(),
+            reason: reason.clone(),
+        });
+        let entry = AccessEntry {
+            source,
+            outcome: AccessOutcome::Rejected { reason },
+            connected_at,
+            duration: connected_at.elapsed().unwrap_or_default(),
+            client_key: None,
+        };
+        if let Some(alerts) = &self.alerts {
+            alerts.rejected(&entry, Instant::now());
+        }
+        self.log.record(entry);
+    }
+
+    /// Record the accepted connection from `source` once the returned
+    /// guard is dropped at the end of its session
+    pub fn accepted(
+        &self,
+        source: SocketAddr,
+        connected_at: SystemTime,
+        client_key: Option<String>,
+    ) -> AcceptedConnection {
+        info!("Accepted connection from {}", source);
+        self.events.publish(Event::SessionConnected {
+            peer: source.to_string(),
+            client_key: client_key.clone(),
+        });
+        AcceptedConnection {
+            log: self.log.clone(),
+            events: self.events.clone(),
+            source,
+            connected_at,
+            client_key,
+        }
+    }
+}
+
+/// A served connection, recorded in the access log when dropped
+#[derive(Debug)]
+pub struct AcceptedConnection {
+    log: Arc<AccessLog>,
+    events: EventBus,
+    source: SocketAddr,
+    connected_at: SystemTime,
+    client_key: Option<String>,
+}
+
+impl Drop for AcceptedConnection {
+    fn drop(&mut self) {
+        let duration = self.connected_at.elapsed().unwrap_or_default();
+        self.events.publish(Event::SessionDisconnected {
+            peer: self.source.to_string(),
+            duration_secs: duration.as_secs(),
+        });
+        self.log.record(AccessEntry {
+            source: self.source,
+            outcome: AccessOutcome::Accepted,
+            connected_at: self.connected_at,
+            duration,
+            client_key: self.client_key.take(),
+        });
+    }
+}
+
+/// Webhook alerts on sources rejected `threshold` times within `window`
+///
+/// A source's count starts over after an alert, so a source that keeps
+/// failing raises an alert every `threshold` rejections.
+#[derive(Debug)]
+pub struct FailureAlerts {
+    webhook: Webhook,
+    threshold: usize,
+    window: Duration,
+    failures: Mutex<HashMap<IpAddr, VecDeque<Instant>>>,
+}
+
+impl FailureAlerts {
+    pub fn new(webhook: Webhook, threshold: usize, window: Duration) -> Self {
+        Self {
+            webhook,
+            threshold: threshold.max(1),
+            window,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a rejection of `source` at `now`, returning whether it reaches
+    /// the threshold
+    pub fn observe(&self, source: IpAddr, now: Instant) -> bool {
+        let mut failures = self.failures.lock().unwrap();
+        let window = self.window;
+        let recent = |at: &Instant| now.saturating_duration_since(*at) < window;
+        failures.retain(|_, times| times.back().is_some_and(recent));
+
+        let times = failures.entry(source).or_default();
+        while times.front().is_some_and(|at| !recent(at)) {
+            times.pop_front();
+        }
+        times.push_back(now);
+        if times.len() < self.threshold {
+            return false;
+        }
+        failures.remove(&source);
+        true
+    }
+
+    /// Count the rejection `entry`, alerting in the background at the
+    /// threshold
+    fn rejected(&self, entry: &AccessEntry, now: Instant) {
+        if !self.observe(entry.source.ip(), now) {
+            return;
+        }
+        let AccessOutcome::Rejected { reason } = &entry.outcome else {
+            return;
+        };
+        let body = json!({
+            "event": "auth_failures",
+            "source": entry.source.ip(),
+            "failures": self.threshold,
+            "window_secs": self.window.as_secs(),
+            "last_reason": reason,
+            "text": format!(
+                "yuha-remote rejected {} {} times within {}s",
+                entry.source.ip(),
+                self.threshold,
+                self.window.as_secs()
+            ),
+        });
+        let webhook = self.webhook.clone();
+        tokio::spawn(async move {
+            if let Err(e) = webhook.post(&body).await {
+                warn!("Failed to post the authentication failure alert: {:#}", e);
+            }
+        });
+    }
+}
+
+/// Plain HTTP endpoint receiving JSON alerts
+///
+/// Only `http://` URLs are supported; an HTTPS endpoint is reached through
+/// a local relay terminating TLS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Webhook {
+    pub fn parse(url: &str) -> Result<Self> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            bail!("Webhook URL must start with http://: {}", url);
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        // IPv6 addresses are bracketed, as their colons are not ports
+        let port_start = match authority.rfind(']') {
+            Some(end) => authority[end..].find(':').map(|index| end + index),
+            None => authority.rfind(':'),
+        };
+        let (host, port) = match port_start {
+            Some(index) => (
+                &authority[..index],
+                authority[index + 1..]
+                    .parse()
+                    .with_context(|| format!("Invalid port in webhook URL {}", url))?,
+            ),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            bail!("Missing host in webhook URL {}", url);
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// POST `body`, failing unless the endpoint answers with a 2xx status
+    pub async fn post(&self, body: &serde_json::Value) -> Result<()> {
+        let body = serde_json::to_vec(body)?;
+        let host = self.host.trim_start_matches('[').trim_end_matches(']');
+        let exchange = async {
+            let mut stream = TcpStream::connect((host, self.port)).await?;
+            let mut request = format!(
+                "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                self.path,
+                self.host,
+                body.len()
+            )
+            .into_bytes();
+            request.extend_from_slice(&body);
+            stream.write_all(&request).await?;
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await?;
+            anyhow::Ok(response)
+        };
+        let response = timeout(WEBHOOK_TIMEOUT, exchange)
+            .await
+            .context("Webhook timed out")??;
+
+        let status_line = response.split(|&b| b == b'\n').next().unwrap_or_default();
+        let status = String::from_utf8_lossy(status_line);
+        let code = status.split_whitespace().nth(1).unwrap_or_default();
+        if !code.starts_with('2') {
+            bail!("Webhook answered {}", status.trim());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_webhook_parse() {
+        let webhook = Webhook::parse("http://alerts.internal:8080/hooks/yuha").unwrap();
+        assert_eq!(webhook.host, "alerts.internal");
+        assert_eq!(webhook.port, 8080);
+        assert_eq!(webhook.path, "/hooks/yuha");
+        let webhook = Webhook::parse("http://[::1]").unwrap();
+        assert_eq!((webhook.host.as_str(), webhook.port), ("[::1]", 80));
+        assert_eq!(webhook.path, "/");
+        assert!(Webhook::parse("https://alerts.internal").is_err());
+        assert!(Webhook::parse("http://alerts.internal:http").is_err());
+    }
+
+    #[test]
+    fn test_failure_threshold() {
+        let webhook = Webhook::parse("http://127.0.0.1:9").unwrap();
+        let alerts = FailureAlerts::new(webhook, 3, Duration::from_secs(60));
+        let attacker: IpAddr = "192.0.2.7".parse().unwrap();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert!(!alerts.observe(attacker, at(0)));
+        assert!(!alerts.observe(attacker, at(1)));
+        // Another source counts separately
+        assert!(!alerts.observe("10.0.0.1".parse().unwrap(), at(1)));
+        assert!(alerts.observe(attacker, at(2)));
+
+        // The count starts over, and failures outside the window expire
+        assert!(!alerts.observe(attacker, at(3)));
+        assert!(!alerts.observe(attacker, at(4)));
+        assert!(!alerts.observe(attacker, at(100)));
+    }
+
+    #[tokio::test]
+    async fn test_webhook_post() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/alert", listener.local_addr().unwrap());
+        let endpoint = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"}") {
+                let mut buf = [0u8; 1024];
+                let len = stream.read(&mut buf).await.unwrap();
+                assert!(len > 0);
+                request.extend_from_slice(&buf[..len]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        Webhook::parse(&url)
+            .unwrap()
+            .post(&json!({ "event": "test" }))
+            .await
+            .unwrap();
+        let request = endpoint.await.unwrap();
+        assert!(request.starts_with("POST /alert HTTP/1.1\r\n"));
+        assert!(request.ends_with(r#"{"event":"test"}"#));
+    }
+}
@@ -0,0 +1,403 @@
+//! GUI application launch
+//!
+//! Starts applications on the desktop session of the remote host, e.g. to
+//! open a file in the remote IDE with the handler for its extension. The
+//! server itself usually runs without a display (over SSH or as a daemon),
+//! so the display variables are taken from the request, the server's
+//! environment, or the display sockets of the session, in that order.
+
+use anyhow::{Context, Result, bail};
+#[cfg(target_os = "linux")]
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tracing::{debug, info};
+use yuha_core::child_env::ChildEnv;
+use yuha_core::open::OpenHandlers;
+use yuha_core::protocol::request_response::{DisplayEnv, Sandbox};
+
+use crate::sandbox;
+
+/// Where a desktop session's display sockets live
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// Directory of the X11 sockets (`X0`, `X1`, ...)
+    pub x11_dir: PathBuf,
+    /// `XDG_RUNTIME_DIR` holding the Wayland sockets
+    pub runtime_dir: Option<PathBuf>,
+    /// Display variables inherited by the server
+    pub inherited: DisplayEnv,
+}
+
+impl Session {
+    /// Session of the user running the server
+    pub fn current() -> Self {
+        let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .or_else(user_runtime_dir);
+        Self {
+            x11_dir: PathBuf::from("/tmp/.X11-unix"),
+            runtime_dir,
+            inherited: DisplayEnv {
+                display: std::env::var("DISPLAY").ok(),
+                wayland_display: std::env::var("WAYLAND_DISPLAY").ok(),
+            },
+        }
+    }
+
+    /// Environment variables pointing an application at the display
+    ///
+    /// A request naming either display is used as is; otherwise both are
+    /// detected, and it is an error when neither is found.
+    pub fn display_vars(&self, requested: &DisplayEnv) -> Result<Vec<(&'static str, String)>> {
+        let env = if requested.display.is_some() || requested.wayland_display.is_some() {
+            requested.clone()
+        } else {
+            self.detect()
+        };
+
+        let mut vars = Vec::new();
+        if let Some(display) = env.display {
+            vars.push(("DISPLAY", display));
+        }
+        if let Some(wayland_display) = env.wayland_display {
+            if let Some(runtime_dir) = &self.runtime_dir {
+                vars.push((
+                    "XDG_RUNTIME_DIR",
+                    runtime_dir.to_string_lossy().into_owned(),
+                ));
+            }
+            vars.push(("WAYLAND_DISPLAY", wayland_display));
+        }
+        if vars.is_empty() {
+            bail!("No display server found on the remote desktop session");
+        }
+        Ok(vars)
+    }
+
+    fn detect(&self) -> DisplayEnv {
+        DisplayEnv {
+            display: self
+                .inherited
+                .display
+                .clone()
+                .or_else(|| first_socket(&self.x11_dir, "X").map(|number| format!(":{}", number))),
+            wayland_display: self.inherited.wayland_display.clone().or_else(|| {
+                let runtime_dir = self.runtime_dir.as_deref()?;
+                first_socket(runtime_dir, "wayland-").map(|number| format!("wayland-{}", number))
+            }),
+        }
+    }
+}
+
+/// Lowest display number of the sockets named `<prefix><number>` in `dir`
+fn first_socket(dir: &Path, prefix: &str) -> Option<u32> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name();
+            name.to_str()?.strip_prefix(prefix)?.parse().ok()
+        })
+        .min()
+}
+
+/// `/run/user/<uid>` of the user running the server
+fn user_runtime_dir() -> Option<PathBuf> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let uid = std::fs::metadata("/proc/self").ok()?.uid();
+        let dir = PathBuf::from(format!("/run/user/{}", uid));
+        dir.is_dir().then_some(dir)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+/// How often the CPU time of launched applications is sampled
+const CPU_SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Parent and CPU ticks of process `pid`: its own and those of the children
+/// it reaped
+#[cfg(target_os = "linux")]
+fn proc_stat(pid: u32) -> Option<(u32, u64)> {
+    // The parent, then the user and system time of every thread and of the
+    // reaped children follow the parenthesized command name
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let ticks = fields
+        .get(11..15)?
+        .iter()
+        .map(|field| field.parse::<u64>().ok())
+        .sum::<Option<u64>>()?;
+    Some((fields.get(1)?.parse().ok()?, ticks))
+}
+
+/// CPU time process `pid` and its descendants used so far, where the
+/// platform reports it
+///
+/// Descendants that outlive their parent are no longer counted.
+fn cpu_time(pid: u32) -> Option<Duration> {
+    #[cfg(target_os = "linux")]
+    {
+        // Ticks of 1/100 s, the kernel's fixed USER_HZ
+        const TICK: Duration = Duration::from_millis(10);
+        let (_, mut ticks) = proc_stat(pid)?;
+
+        let mut children: HashMap<u32, Vec<(u32, u64)>> = HashMap::new();
+        for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+            let Some(child) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse().ok())
+            else {
+                continue;
+            };
+            if let Some((parent, child_ticks)) = proc_stat(child) {
+                children
+                    .entry(parent)
+                    .or_default()
+                    .push((child, child_ticks));
+            }
+        }
+        let mut pending = vec![pid];
+        while let Some(parent) = pending.pop() {
+            for &(child, child_ticks) in children.get(&parent).into_iter().flatten() {
+                ticks += child_ticks;
+                pending.push(child);
+            }
+        }
+        Some(TICK * u32::try_from(ticks).unwrap_or(u32::MAX))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = pid;
+        None
+    }
+}
+
+/// Start `command` on the display of `session` under `sandbox` and return
+/// its process id
+///
+/// The application is detached from the server: it gets no stdio and keeps
+/// running when the session ends. It starts with the variables of `env`
+/// and the display, not the server's whole environment. The CPU time it
+/// uses is passed to `record_cpu` as it accrues.
+pub fn launch(
+    command: &[String],
+    display_env: &DisplayEnv,
+    sandbox: &Sandbox,
+    env: &ChildEnv,
+    session: &Session,
+    record_cpu: impl Fn(Duration) + Send + 'static,
+) -> Result<u32> {
+    let Some(program) = command.first() else {
+        bail!("No command to launch");
+    };
+
+    let mut cmd = sandbox::command(sandbox, command, env)?;
+    cmd.envs(session.display_vars(display_env)?)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    #[cfg(unix)]
+    cmd.process_group(0);
+
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("Failed to launch {}", program))?;
+    let pid = child.id().unwrap_or_default();
+    info!("Launched {} (pid {})", program, pid);
+
+    // Account for its CPU time until it exits, then reap it
+    tokio::spawn(async move {
+        let mut sample = tokio::time::interval(CPU_SAMPLE_INTERVAL);
+        let mut used = Duration::ZERO;
+        let status = loop {
+            tokio::select! {
+                status = child.wait() => break status,
+                _ = sample.tick() => {
+                    if let Some(cpu) = cpu_time(pid) {
+                        record_cpu(cpu.saturating_sub(used));
+                        used = used.max(cpu);
+                    }
+                }
+            }
+        };
+        debug!("Launched process {} exited: {:?}", pid, status);
+    });
+    Ok(pid)
+}
+
+/// Open `path` with its handler on the display of the current session
+/// under `sandbox` with `env`, passing the CPU time of the handler to
+/// `record_cpu`
+pub async fn open(
+    path: &str,
+    handlers: &OpenHandlers,
+    sandbox: &Sandbox,
+    env: &ChildEnv,
+    record_cpu: impl Fn(Duration) + Send + 'static,
+) -> Result<u32> {
+    tokio::fs::metadata(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path))?;
+    launch(
+        &handlers.command(path),
+        &DisplayEnv::default(),
+        sandbox,
+        env,
+        &Session::current(),
+        record_cpu,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn session(x11_dir: &Path, runtime_dir: &Path) -> Session {
+        Session {
+            x11_dir: x11_dir.to_path_buf(),
+            runtime_dir: Some(runtime_dir.to_path_buf()),
+            inherited: DisplayEnv::default(),
+        }
+    }
+
+    #[test]
+    fn test_display_vars_detected_from_sockets() {
+        let x11 = tempdir().unwrap();
+        let runtime = tempdir().unwrap();
+        for name in ["X1", "X0", "X0-lock"] {
+            std::fs::write(x11.path().join(name), b"").unwrap();
+        }
+        std::fs::write(runtime.path().join("wayland-1"), b"").unwrap();
+
+        let vars = session(x11.path(), runtime.path())
+            .display_vars(&DisplayEnv::default())
+            .unwrap();
+        assert_eq!(
+            vars,
+            vec![
+                ("DISPLAY", ":0".to_string()),
+                (
+                    "XDG_RUNTIME_DIR",
+                    runtime.path().to_string_lossy().into_owned()
+                ),
+                ("WAYLAND_DISPLAY", "wayland-1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_display_vars_prefer_request_then_inherited() {
+        let x11 = tempdir().unwrap();
+        let runtime = tempdir().unwrap();
+        std::fs::write(x11.path().join("X0"), b"").unwrap();
+        let mut session = session(x11.path(), runtime.path());
+        session.inherited.display = Some(":5".to_string());
+
+        assert_eq!(
+            session.display_vars(&DisplayEnv::default()).unwrap(),
+            vec![("DISPLAY", ":5".to_string())]
+        );
+        let requested = DisplayEnv {
+            display: Some(":2".to_string()),
+            wayland_display: None,
+        };
+        assert_eq!(
+            session.display_vars(&requested).unwrap(),
+            vec![("DISPLAY", ":2".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_display_vars_without_display() {
+        let empty = tempdir().unwrap();
+        let session = session(empty.path(), empty.path());
+        assert!(session.display_vars(&DisplayEnv::default()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_launch() {
+        let empty = tempdir().unwrap();
+        let session = session(empty.path(), empty.path());
+        let display_env = DisplayEnv {
+            display: Some(":0".to_string()),
+            wayland_display: None,
+        };
+
+        let sandbox = Sandbox::default();
+        let env = ChildEnv::default();
+        assert!(launch(&[], &display_env, &sandbox, &env, &session, |_| {}).is_err());
+        let pid = launch(
+            &["true".to_string()],
+            &display_env,
+            &sandbox,
+            &env,
+            &session,
+            |_| {},
+        )
+        .unwrap();
+        assert!(pid > 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_cpu_time() {
+        let pid = std::process::id();
+        let spent = cpu_time(pid).unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while cpu_time(pid).unwrap() == spent {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "CPU time not advancing"
+            );
+        }
+        assert!(cpu_time(u32::MAX).is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_cpu_time_of_descendants() {
+        // The shell only waits while its child spins
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "timeout 5 sh -c 'while :; do :; done'; true"])
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while cpu_time(pid).unwrap_or_default() < Duration::from_millis(50) {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "CPU time of descendants not counted"
+            );
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        // Counted although the shell itself used next to none
+        assert!(proc_stat(pid).unwrap().1 < 5);
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_open_missing_path() {
+        let dir = tempdir().unwrap();
+        let missing = dir.path().join("missing.txt");
+        let error = open(
+            &missing.to_string_lossy(),
+            &OpenHandlers::default(),
+            &Sandbox::default(),
+            &ChildEnv::default(),
+            |_| {},
+        )
+        .await
+        .unwrap_err();
+        assert!(format!("{:#}", error).contains("missing.txt"));
+    }
+}
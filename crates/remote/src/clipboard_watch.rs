@@ -0,0 +1,280 @@
+//! # Clipboard Watching
+//!
+//! Text copied on the remote, so far into tmux paste buffers, is pushed to
+//! clients that negotiated the `clipboard-watch` extension as
+//! `ClipboardContent` items of their next `PollData`. tmux does not announce
+//! buffer changes, so the buffers are polled, adaptively rather than at a
+//! fixed rate:
+//!
+//! - every `min_interval` right after a change or a clipboard request
+//! - doubling towards `max_interval` while nothing changes
+//! - stretched further whenever reads would otherwise take more than
+//!   `max_cpu_percent` of the time
+//!
+//! Nothing is polled while no session listens. Clients learn the mode in
+//! effect from the `ClipboardWatch` item of the `Hello` response.
+
+use anyhow::Result;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::{Notify, broadcast};
+use tokio::time::Instant;
+use tracing::debug;
+
+use yuha_core::protocol::request_response::ClipboardWatch;
+
+use crate::tmux::TmuxBuffers;
+
+/// Changes kept for a session that has not taken them yet
+const CHANGES_KEPT: usize = 16;
+
+/// Where watched text is copied to
+pub trait ClipboardSource: Send + Sync + 'static {
+    /// Text copied since the last call, if any
+    fn changed(&self) -> impl Future<Output = Result<Option<String>>> + Send;
+}
+
+impl ClipboardSource for TmuxBuffers {
+    fn changed(&self) -> impl Future<Output = Result<Option<String>>> + Send {
+        self.copied()
+    }
+}
+
+/// Polling intervals and CPU cap of a watcher
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PollConfig {
+    /// Interval right after activity
+    pub min_interval: Duration,
+    /// Interval once idle
+    pub max_interval: Duration,
+    /// Largest share of the time spent reading the source, in percent
+    pub max_cpu_percent: u8,
+}
+
+impl Default for PollConfig {
+    fn default() -> Self {
+        Self {
+            min_interval: Duration::from_millis(250),
+            max_interval: Duration::from_secs(5),
+            max_cpu_percent: 2,
+        }
+    }
+}
+
+/// Interval between polls, adapted to activity and the cost of a poll
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    config: PollConfig,
+    interval: Duration,
+}
+
+impl Backoff {
+    pub fn new(config: PollConfig) -> Self {
+        Self {
+            config,
+            interval: config.min_interval,
+        }
+    }
+
+    /// Poll quickly again
+    pub fn reset(&mut self) {
+        self.interval = self.config.min_interval;
+    }
+
+    /// Take in whether a poll found a change
+    pub fn record(&mut self, changed: bool) {
+        if changed {
+            self.reset();
+        } else {
+            self.interval = (self.interval * 2).min(self.config.max_interval);
+        }
+    }
+
+    /// Delay before the next poll after one that took `spent`
+    pub fn delay(&self, spent: Duration) -> Duration {
+        // spent / (spent + delay) stays within the cap
+        let percent = u32::from(self.config.max_cpu_percent.clamp(1, 100));
+        self.interval.max(spent * (100 - percent) / percent)
+    }
+}
+
+/// Polls a clipboard source while sessions listen, broadcasting its changes
+pub struct ClipboardWatcher<S = TmuxBuffers> {
+    source: Arc<S>,
+    config: PollConfig,
+    changes: broadcast::Sender<String>,
+    /// Cuts the wait for the next poll short
+    wake: Notify,
+    /// Clipboard used since the poll loop last looked
+    active: AtomicBool,
+}
+
+impl<S: ClipboardSource> ClipboardWatcher<S> {
+    /// Watch `source` from a background task, which ends soon after the
+    /// watcher is dropped
+    pub fn spawn(source: Arc<S>, config: PollConfig) -> Arc<Self> {
+        let watcher = Arc::new(Self {
+            source,
+            config,
+            changes: broadcast::channel(CHANGES_KEPT).0,
+            wake: Notify::new(),
+            active: AtomicBool::new(false),
+        });
+        tokio::spawn(poll(Arc::downgrade(&watcher)));
+        watcher
+    }
+
+    /// Mode reported to clients
+    pub fn mode(&self) -> ClipboardWatch {
+        ClipboardWatch::AdaptivePolling {
+            min_interval_ms: self.config.min_interval.as_millis() as u64,
+            max_interval_ms: self.config.max_interval.as_millis() as u64,
+            max_cpu_percent: self.config.max_cpu_percent,
+        }
+    }
+
+    /// Changes found from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        let changes = self.changes.subscribe();
+        self.wake.notify_one();
+        changes
+    }
+
+    /// Note that the clipboard is in use, speeding polling up
+    pub fn activity(&self) {
+        self.active.store(true, Ordering::Relaxed);
+        self.wake.notify_one();
+    }
+
+    /// Read the source now, broadcasting a change
+    pub async fn check(&self) -> Result<Option<String>> {
+        let changed = self.source.changed().await?;
+        if let Some(text) = &changed {
+            // Having no listener is fine
+            let _ = self.changes.send(text.clone());
+        }
+        Ok(changed)
+    }
+}
+
+async fn poll<S: ClipboardSource>(watcher: Weak<ClipboardWatcher<S>>) {
+    let Some(config) = watcher.upgrade().map(|watcher| watcher.config) else {
+        return;
+    };
+    let mut backoff = Backoff::new(config);
+    let mut last_end = Instant::now();
+    let mut last_spent = Duration::ZERO;
+    let mut next_poll = last_end;
+    loop {
+        let Some(watcher) = watcher.upgrade() else {
+            return;
+        };
+        if watcher.active.swap(false, Ordering::Relaxed) {
+            backoff.reset();
+            next_poll = next_poll.min(last_end + backoff.delay(last_spent));
+        }
+        let listening = watcher.changes.receiver_count() > 0;
+        if !listening || Instant::now() < next_poll {
+            let until = match listening {
+                true => next_poll,
+                false => Instant::now() + config.max_interval,
+            };
+            let _ = tokio::time::timeout_at(until, watcher.wake.notified()).await;
+            continue;
+        }
+
+        let started = Instant::now();
+        let changed = match watcher.check().await {
+            Ok(changed) => changed.is_some(),
+            // Backs off like an idle source, e.g. while no tmux server runs
+            Err(e) => {
+                debug!("Failed to poll the clipboard: {:#}", e);
+                false
+            }
+        };
+        last_end = Instant::now();
+        last_spent = last_end - started;
+        backoff.record(changed);
+        next_poll = last_end + backoff.delay(last_spent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_backoff() {
+        let config = PollConfig {
+            min_interval: Duration::from_millis(100),
+            max_interval: Duration::from_millis(500),
+            max_cpu_percent: 10,
+        };
+        let mut backoff = Backoff::new(config);
+        let delays: Vec<_> = (0..4)
+            .map(|_| {
+                backoff.record(false);
+                backoff.delay(Duration::ZERO).as_millis()
+            })
+            .collect();
+        assert_eq!(delays, [200, 400, 500, 500]);
+        backoff.record(true);
+        assert_eq!(backoff.delay(Duration::ZERO), config.min_interval);
+        // A poll taking 100ms waits 900ms to stay within 10%
+        assert_eq!(
+            backoff.delay(Duration::from_millis(100)),
+            Duration::from_millis(900)
+        );
+    }
+
+    /// Source handing out queued copies, counting reads
+    #[derive(Default)]
+    struct Copies {
+        queued: Mutex<Vec<String>>,
+        reads: Mutex<usize>,
+    }
+
+    impl ClipboardSource for Copies {
+        async fn changed(&self) -> Result<Option<String>> {
+            *self.reads.lock().unwrap() += 1;
+            Ok(self.queued.lock().unwrap().pop())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watcher_polls_while_listened_to() {
+        let config = PollConfig {
+            min_interval: Duration::from_millis(5),
+            max_interval: Duration::from_millis(40),
+            max_cpu_percent: 50,
+        };
+        let source = Arc::new(Copies::default());
+        let watcher = ClipboardWatcher::spawn(source.clone(), config);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(*source.reads.lock().unwrap(), 0);
+
+        let mut changes = watcher.subscribe();
+        source.queued.lock().unwrap().push("copied".to_string());
+        let copied = tokio::time::timeout(Duration::from_secs(5), changes.recv()).await;
+        assert_eq!(copied.unwrap().unwrap(), "copied");
+
+        // Idle polling backs off to the slow interval
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let reads = *source.reads.lock().unwrap();
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        let idle_reads = *source.reads.lock().unwrap() - reads;
+        assert!(idle_reads <= 12, "{} reads while idle", idle_reads);
+        assert_eq!(
+            watcher.mode(),
+            ClipboardWatch::AdaptivePolling {
+                min_interval_ms: 5,
+                max_interval_ms: 40,
+                max_cpu_percent: 50,
+            }
+        );
+    }
+}
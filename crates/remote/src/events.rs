@@ -0,0 +1,312 @@
+//! Events of the server and hooks notified of them
+//!
+//! The server publishes what happens to its sessions on an [`EventBus`]:
+//! connections accepted and closed, authentication failures and completed
+//! uploads. Each [`EventHook`] subscribes to the bus and delivers the kinds
+//! of events it selected to its [`EventSink`], a webhook or a command, for
+//! chat notifications and automation.
+//!
+//! Events are delivered as a JSON object with an `event` field naming the
+//! kind and a human readable `text`, e.g.
+//!
+//! ```text
+//! {"event":"auth-failure","peer":"192.0.2.7:51234","reason":"Authentication failed","text":"..."}
+//! ```
+
+use crate::access::Webhook;
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tracing::{debug, warn};
+
+/// Events kept for hooks that fall behind
+const BUS_CAPACITY: usize = 256;
+
+/// Time a hook command is given to finish
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something that happened to the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    /// A TCP client authenticated and its session started
+    SessionConnected {
+        peer: String,
+        client_key: Option<String>,
+    },
+    /// The session of an accepted TCP client ended
+    SessionDisconnected { peer: String, duration_secs: u64 },
+    /// A TCP client was closed before being served
+    AuthFailure { peer: String, reason: String },
+    /// The last chunk of an upload was written
+    TransferCompleted {
+        peer: String,
+        path: String,
+        bytes: u64,
+    },
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::SessionConnected { .. } => EventKind::SessionConnected,
+            Event::SessionDisconnected { .. } => EventKind::SessionDisconnected,
+            Event::AuthFailure { .. } => EventKind::AuthFailure,
+            Event::TransferCompleted { .. } => EventKind::TransferCompleted,
+        }
+    }
+
+    /// One line describing the event, for chat messages
+    pub fn text(&self) -> String {
+        match self {
+            Event::SessionConnected { peer, .. } => format!("yuha-remote accepted {}", peer),
+            Event::SessionDisconnected {
+                peer,
+                duration_secs,
+            } => format!(
+                "yuha-remote session of {} ended after {}s",
+                peer, duration_secs
+            ),
+            Event::AuthFailure { peer, reason } => {
+                format!("yuha-remote rejected {}: {}", peer, reason)
+            }
+            Event::TransferCompleted { peer, path, bytes } => {
+                format!(
+                    "yuha-remote received {} ({} bytes) from {}",
+                    path, bytes, peer
+                )
+            }
+        }
+    }
+
+    /// The event as delivered to sinks
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut value = serde_json::to_value(self).expect("events serialize to JSON");
+        value["text"] = self.text().into();
+        value
+    }
+}
+
+/// Kinds of events a hook may select
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum EventKind {
+    SessionConnected,
+    SessionDisconnected,
+    AuthFailure,
+    TransferCompleted,
+}
+
+impl EventKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::SessionConnected => "session-connected",
+            EventKind::SessionDisconnected => "session-disconnected",
+            EventKind::AuthFailure => "auth-failure",
+            EventKind::TransferCompleted => "transfer-completed",
+        }
+    }
+}
+
+/// Broadcast of the server's events, shared by its sessions
+///
+/// Publishing never blocks; without hooks, events are dropped.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(BUS_CAPACITY).0,
+        }
+    }
+}
+
+impl EventBus {
+    pub fn publish(&self, event: Event) {
+        debug!("Event {:?}", event);
+        // Fails only without subscribers
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+/// Where a hook delivers events
+#[derive(Debug, Clone)]
+pub enum EventSink {
+    /// POST the event to a plain HTTP endpoint
+    Webhook(Webhook),
+    /// Run a command with the event on its stdin and its kind in
+    /// `YUHA_EVENT`
+    Command(Vec<String>),
+}
+
+impl EventSink {
+    /// Command sink from a `COMMAND ARGS...` specification
+    ///
+    /// The command is split on whitespace; quoting is not supported.
+    pub fn command(spec: &str) -> Result<Self> {
+        let command: Vec<String> = spec.split_whitespace().map(str::to_string).collect();
+        if command.is_empty() {
+            bail!("Empty event command");
+        }
+        Ok(EventSink::Command(command))
+    }
+
+    pub async fn deliver(&self, event: &Event) -> Result<()> {
+        match self {
+            EventSink::Webhook(webhook) => webhook.post(&event.to_json()).await,
+            EventSink::Command(command) => run_command(command, event).await,
+        }
+    }
+}
+
+/// A sink notified of the events of selected kinds
+#[derive(Debug, Clone)]
+pub struct EventHook {
+    sink: EventSink,
+    kinds: Vec<EventKind>,
+}
+
+impl EventHook {
+    /// Hook notified of every event
+    pub fn new(sink: EventSink) -> Self {
+        Self {
+            sink,
+            kinds: Vec::new(),
+        }
+    }
+
+    /// Only notify of events of `kinds`, or of every event if empty
+    pub fn with_kinds(mut self, kinds: Vec<EventKind>) -> Self {
+        self.kinds = kinds;
+        self
+    }
+
+    pub fn wants(&self, kind: EventKind) -> bool {
+        self.kinds.is_empty() || self.kinds.contains(&kind)
+    }
+
+    /// Deliver the selected events of `bus` in the background, one at a
+    /// time and in order
+    pub fn spawn(self, bus: &EventBus) -> JoinHandle<()> {
+        let mut events = bus.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("Event hook fell behind, {} events not delivered", missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                if !self.wants(event.kind()) {
+                    continue;
+                }
+                if let Err(e) = self.sink.deliver(&event).await {
+                    warn!(
+                        "Failed to deliver the {} event: {:#}",
+                        event.kind().name(),
+                        e
+                    );
+                }
+            }
+        })
+    }
+}
+
+/// Run `command` with `event` as JSON on its stdin, failing unless it succeeds
+async fn run_command(command: &[String], event: &Event) -> Result<()> {
+    let (program, args) = command.split_first().context("Empty event command")?;
+    let mut child = Command::new(program)
+        .args(args)
+        .env("YUHA_EVENT", event.kind().name())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to run event command {}", program))?;
+    let mut stdin = child.stdin.take().context("Event command has no stdin")?;
+    let body = serde_json::to_vec(&event.to_json())?;
+    let status = timeout(COMMAND_TIMEOUT, async {
+        // A command ignoring the event may close its stdin early
+        let _ = stdin.write_all(&body).await;
+        drop(stdin);
+        child.wait().await
+    })
+    .await
+    .with_context(|| format!("Event command {} timed out", program))??;
+    if !status.success() {
+        bail!("Event command {} failed with {}", program, status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_json() {
+        let event = Event::TransferCompleted {
+            peer: "192.0.2.7:51234".to_string(),
+            path: "notes.txt".to_string(),
+            bytes: 42,
+        };
+        let json = event.to_json();
+        assert_eq!(json["event"], "transfer-completed");
+        assert_eq!(json["event"], event.kind().name());
+        assert_eq!(json["path"], "notes.txt");
+        assert_eq!(json["bytes"], 42);
+        assert!(json["text"].as_str().unwrap().contains("notes.txt"));
+        assert!(EventSink::command("  ").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_hook_delivers_selected_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("events.json");
+        let sink = EventSink::command(&format!("tee {}", out.display())).unwrap();
+        let bus = EventBus::default();
+        let hook = EventHook::new(sink)
+            .with_kinds(vec![EventKind::AuthFailure])
+            .spawn(&bus);
+
+        bus.publish(Event::SessionConnected {
+            peer: "10.0.0.1:4000".to_string(),
+            client_key: None,
+        });
+        bus.publish(Event::AuthFailure {
+            peer: "192.0.2.7:51234".to_string(),
+            reason: "Authentication failed".to_string(),
+        });
+
+        let delivered = timeout(Duration::from_secs(5), async {
+            loop {
+                if let Ok(body) = tokio::fs::read(&out).await
+                    && let Ok(json) = serde_json::from_slice::<serde_json::Value>(&body)
+                {
+                    return json;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(delivered["event"], "auth-failure");
+        assert_eq!(delivered["peer"], "192.0.2.7:51234");
+        hook.abort();
+    }
+}
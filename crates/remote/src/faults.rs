@@ -0,0 +1,70 @@
+//! Injected faults
+//!
+//! With the `fault-injection` feature, clients can arm [`Faults`] through
+//! `InjectFault` requests; the server then delays, drops or fails the next
+//! requests of the given kind before they reach the middleware.
+
+use std::sync::Mutex;
+use yuha_core::protocol::fault::Fault;
+
+struct Rule {
+    kind: String,
+    fault: Fault,
+    remaining: u32,
+}
+
+/// Faults armed on a session, applied in the order they were armed
+#[derive(Default)]
+pub struct Faults {
+    rules: Mutex<Vec<Rule>>,
+}
+
+impl Faults {
+    /// Hit the next `count` requests of `kind` with `fault`
+    pub fn arm(&self, kind: String, fault: Fault, count: u32) {
+        if count > 0 {
+            self.rules.lock().unwrap().push(Rule {
+                kind,
+                fault,
+                remaining: count,
+            });
+        }
+    }
+
+    /// The fault hitting a request of `kind`, if one is armed
+    pub fn take(&self, kind: &str) -> Option<Fault> {
+        let mut rules = self.rules.lock().unwrap();
+        let index = rules.iter().position(|rule| rule.kind == kind)?;
+        let rule = &mut rules[index];
+        rule.remaining -= 1;
+        if rule.remaining > 0 {
+            Some(rule.fault.clone())
+        } else {
+            Some(rules.remove(index).fault)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_faults_run_out() {
+        let faults = Faults::default();
+        let delay = Fault::Delay {
+            delay: Duration::from_millis(10),
+        };
+        faults.arm("GetClipboard".to_string(), Fault::Drop, 2);
+        faults.arm("GetClipboard".to_string(), delay.clone(), 1);
+        faults.arm("ListTasks".to_string(), Fault::Drop, 0);
+
+        assert_eq!(faults.take("SetClipboard"), None);
+        assert_eq!(faults.take("GetClipboard"), Some(Fault::Drop));
+        assert_eq!(faults.take("GetClipboard"), Some(Fault::Drop));
+        assert_eq!(faults.take("GetClipboard"), Some(delay));
+        assert_eq!(faults.take("GetClipboard"), None);
+        assert_eq!(faults.take("ListTasks"), None);
+    }
+}
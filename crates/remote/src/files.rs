@@ -0,0 +1,812 @@
+//! File access for clients
+//!
+//! Lists and reads files on the remote host so clients can transfer them,
+//! e.g. when a file list copied on the remote is pasted locally, writes
+//! files clients upload, e.g. after editing them locally, hashes files
+//! so clients can compare them with their own, sums their sizes per
+//! directory, and removes and moves them.
+
+use anyhow::{Context, Result, anyhow};
+use bytes::Bytes;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tracing::debug;
+use yuha_core::checksum::{self, HashAlgorithm};
+use yuha_core::protocol::request_response::{
+    DirectoryUsage, MAX_FILE_CHUNK_LEN, MAX_FILE_RANGE_LEN,
+};
+use yuha_core::protocol::{ListQuery, ResponseItem};
+
+/// List the regular files under the given paths that fall in `query`'s page,
+/// sending a `FileEntry` for each to `entries` as it is found
+///
+/// Directories are walked recursively in path order, so pages are stable;
+/// symbolic links to directories are not followed so cyclic links cannot
+/// cause endless walks. The walk stops as soon as the page is full.
+pub async fn list_files(
+    paths: &[String],
+    query: &ListQuery,
+    entries: &mpsc::Sender<ResponseItem>,
+) -> Result<()> {
+    let mut pager = query.pager();
+
+    for root in paths {
+        let root = PathBuf::from(root);
+        let base = root.parent().map(Path::to_path_buf).unwrap_or_default();
+        let mut pending = vec![root];
+
+        while let Some(path) = pending.pop() {
+            if pager.is_full() {
+                return Ok(());
+            }
+
+            let metadata = tokio::fs::symlink_metadata(&path)
+                .await
+                .with_context(|| format!("Failed to stat {}", path.display()))?;
+
+            if metadata.is_dir() {
+                let mut dir = tokio::fs::read_dir(&path)
+                    .await
+                    .with_context(|| format!("Failed to read directory {}", path.display()))?;
+                let mut children = Vec::new();
+                while let Some(entry) = dir.next_entry().await? {
+                    children.push(entry.path());
+                }
+                // Pushed in reverse so they are popped in path order
+                children.sort_by(|a, b| b.cmp(a));
+                pending.extend(children);
+                continue;
+            }
+
+            let metadata = if metadata.file_type().is_symlink() {
+                tokio::fs::metadata(&path)
+                    .await
+                    .with_context(|| format!("Failed to resolve link {}", path.display()))?
+            } else {
+                metadata
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+
+            let relative_path = path
+                .strip_prefix(&base)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .into_owned();
+            if !pager.accept(&relative_path) {
+                continue;
+            }
+            let entry = ResponseItem::FileEntry {
+                path: path.to_string_lossy().into_owned(),
+                relative_path,
+                size: metadata.len(),
+            };
+            entries
+                .send(entry)
+                .await
+                .context("File listing was abandoned")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// List the entries of the directory `path` in name order, sending a
+/// `DirectoryEntry` for each to `entries`
+///
+/// Entries are not descended into; a symbolic link counts as a directory
+/// when it points to one.
+pub async fn list_directory(path: &str, entries: &mpsc::Sender<ResponseItem>) -> Result<()> {
+    let mut dir = tokio::fs::read_dir(path)
+        .await
+        .with_context(|| format!("Failed to read directory {}", path))?;
+    let mut listed = Vec::new();
+    while let Some(entry) = dir.next_entry().await? {
+        let is_dir = tokio::fs::metadata(entry.path())
+            .await
+            .is_ok_and(|metadata| metadata.is_dir());
+        listed.push((entry.file_name().to_string_lossy().into_owned(), is_dir));
+    }
+    listed.sort();
+
+    for (name, dir) in listed {
+        entries
+            .send(ResponseItem::DirectoryEntry { name, dir })
+            .await
+            .context("Directory listing was abandoned")?;
+    }
+    Ok(())
+}
+
+/// Hash the file at `root`, or every regular file under it, sending a
+/// `FileHash` for each to `hashes` as it is hashed
+///
+/// Files are found as by [`list_files`], so in path order.
+pub async fn hash_path(
+    root: &str,
+    algo: HashAlgorithm,
+    hashes: &mpsc::Sender<ResponseItem>,
+) -> Result<()> {
+    // Files found ahead of the one being hashed
+    const LISTED_AHEAD: usize = 64;
+
+    let (entries_tx, mut entries_rx) = mpsc::channel(LISTED_AHEAD);
+    let paths = vec![root.to_string()];
+    let listing = async move { list_files(&paths, &ListQuery::default(), &entries_tx).await };
+    let hashing = async {
+        while let Some(entry) = entries_rx.recv().await {
+            let ResponseItem::FileEntry { path, size, .. } = entry else {
+                continue;
+            };
+            let file = PathBuf::from(&path);
+            let hash = tokio::task::spawn_blocking(move || algo.hash_file(&file))
+                .await?
+                .with_context(|| format!("Failed to hash {}", path))?;
+            let relative_path = Path::new(&path)
+                .strip_prefix(root)
+                .map(|relative| relative.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let item = ResponseItem::FileHash {
+                path,
+                relative_path,
+                size,
+                hash,
+            };
+            hashes.send(item).await.context("Hashing was abandoned")?;
+        }
+        Ok(())
+    };
+    // A failed hash abandons the listing, so its own error comes first
+    let (listed, hashed) = tokio::join!(listing, hashing);
+    hashed.and(listed)
+}
+
+/// Interval between the `DiskUsageProgress` items of a scan
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sum the sizes of the regular files under `root` per directory, sending a
+/// `DirectoryUsage` for each directory down to `depth` levels below `root`
+/// to `items`, after those below it
+///
+/// Symbolic links are not followed and directories that cannot be read
+/// are skipped, as with `du`; a `DiskUsageProgress` is sent every
+/// [`PROGRESS_INTERVAL`] of a long scan. A file `root` is reported as a
+/// tree of one file.
+pub async fn disk_usage(root: &str, depth: u32, items: &mpsc::Sender<ResponseItem>) -> Result<()> {
+    let root = PathBuf::from(root);
+    let mut scan = UsageScan {
+        max_depth: depth,
+        items: items.clone(),
+        entries: 0,
+        size: 0,
+        last_progress: Instant::now(),
+    };
+    tokio::task::spawn_blocking(move || {
+        let metadata = std::fs::symlink_metadata(&root)
+            .with_context(|| format!("Failed to stat {}", root.display()))?;
+        if metadata.is_dir() {
+            scan.directory(&root, 0).map(|_| ())
+        } else {
+            scan.send(ResponseItem::DirectoryUsage {
+                usage: DirectoryUsage {
+                    path: root.to_string_lossy().into_owned(),
+                    depth: 0,
+                    size: metadata.len(),
+                    files: 1,
+                },
+            })
+        }
+    })
+    .await?
+}
+
+/// State of a `DiskUsage` scan
+struct UsageScan {
+    max_depth: u32,
+    items: mpsc::Sender<ResponseItem>,
+    /// Entries and bytes seen so far
+    entries: u64,
+    size: u64,
+    last_progress: Instant,
+}
+
+impl UsageScan {
+    /// Size and file count of the tree at `dir`, `depth` levels below the
+    /// root, reporting it when within the depth limit
+    fn directory(&mut self, dir: &Path, depth: u32) -> Result<(u64, u64)> {
+        let (mut size, mut files) = (0, 0);
+        match std::fs::read_dir(dir) {
+            Ok(entries) => {
+                for entry in entries.flatten() {
+                    self.entries += 1;
+                    // Not following symbolic links
+                    let Ok(metadata) = entry.metadata() else {
+                        continue;
+                    };
+                    if metadata.is_dir() {
+                        let (dir_size, dir_files) = self.directory(&entry.path(), depth + 1)?;
+                        size += dir_size;
+                        files += dir_files;
+                    } else if metadata.is_file() {
+                        size += metadata.len();
+                        files += 1;
+                        self.size += metadata.len();
+                    }
+                    self.report_progress()?;
+                }
+            }
+            Err(e) if depth == 0 => {
+                return Err(e).with_context(|| format!("Failed to read {}", dir.display()));
+            }
+            Err(e) => debug!("Skipping {}: {}", dir.display(), e),
+        }
+        if depth <= self.max_depth {
+            self.send(ResponseItem::DirectoryUsage {
+                usage: DirectoryUsage {
+                    path: dir.to_string_lossy().into_owned(),
+                    depth,
+                    size,
+                    files,
+                },
+            })?;
+        }
+        Ok((size, files))
+    }
+
+    fn report_progress(&mut self) -> Result<()> {
+        if self.last_progress.elapsed() < PROGRESS_INTERVAL {
+            return Ok(());
+        }
+        self.last_progress = Instant::now();
+        self.send(ResponseItem::DiskUsageProgress {
+            entries: self.entries,
+            size: self.size,
+        })
+    }
+
+    fn send(&self, item: ResponseItem) -> Result<()> {
+        self.items
+            .blocking_send(item)
+            .map_err(|_| anyhow!("Disk usage scan was abandoned"))
+    }
+}
+
+/// Read a chunk of a file, capped at [`MAX_FILE_CHUNK_LEN`] bytes
+pub async fn read_chunk(path: &str, offset: u64, len: u32) -> Result<ResponseItem> {
+    let len = len.min(MAX_FILE_CHUNK_LEN) as usize;
+    let (data, size) = read_at(path, offset, len).await?;
+
+    let eof = offset + data.len() as u64 >= size;
+    Ok(ResponseItem::FileChunk {
+        path: path.to_string(),
+        offset,
+        crc32c: checksum::crc32c(&data),
+        data: Bytes::from(data),
+        eof,
+    })
+}
+
+/// Read a range of a file, capped at [`MAX_FILE_RANGE_LEN`] bytes, along
+/// with the size of the file
+///
+/// A range past the end reads nothing, so followers of a truncated file
+/// learn its new size instead of failing.
+pub async fn read_range(path: &str, offset: u64, len: u32) -> Result<ResponseItem> {
+    let len = len.min(MAX_FILE_RANGE_LEN) as usize;
+    let (data, size) = read_at(path, offset, len).await?;
+
+    Ok(ResponseItem::FileRange {
+        path: path.to_string(),
+        offset,
+        crc32c: checksum::crc32c(&data),
+        data: Bytes::from(data),
+        size,
+    })
+}
+
+/// Suffix of the copy uploads are written to before replacing the file
+const UPLOAD_SUFFIX: &str = ".yuha-upload";
+
+/// An upload refused because its target changed since the client read it
+#[derive(Debug)]
+pub struct Conflict {
+    pub path: String,
+}
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} changed since it was read", self.path)
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+/// Write a chunk of an upload of `path`
+///
+/// Chunks go to a copy next to `path`, so readers never see a partial file;
+/// the `last` chunk moves the copy over `path`, keeping its permissions.
+/// With `expected_digest`, the last chunk fails with [`Conflict`] and keeps
+/// `path` unless it still has that digest.
+pub async fn write_chunk(
+    path: &str,
+    offset: u64,
+    data: &[u8],
+    crc32c: u32,
+    last: bool,
+    expected_digest: Option<&str>,
+) -> Result<()> {
+    if checksum::crc32c(data) != crc32c {
+        anyhow::bail!("Checksum mismatch in {} at offset {}", path, offset);
+    }
+
+    let staged = format!("{}{}", path, UPLOAD_SUFFIX);
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create(offset == 0)
+        .truncate(offset == 0)
+        .open(&staged)
+        .await
+        .with_context(|| format!("Failed to open {}", staged))?;
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+    file.write_all(data).await?;
+    if !last {
+        return Ok(());
+    }
+
+    file.sync_all().await?;
+    if let Some(expected) = expected_digest {
+        let target = PathBuf::from(path);
+        let current = tokio::task::spawn_blocking(move || checksum::digest_file(&target)).await?;
+        if current.ok().as_deref() != Some(expected) {
+            let _ = tokio::fs::remove_file(&staged).await;
+            return Err(Conflict {
+                path: path.to_string(),
+            }
+            .into());
+        }
+    }
+    if let Ok(metadata) = tokio::fs::metadata(path).await {
+        tokio::fs::set_permissions(&staged, metadata.permissions()).await?;
+    }
+    tokio::fs::rename(&staged, path)
+        .await
+        .with_context(|| format!("Failed to replace {}", path))
+}
+
+/// Remove the file, symbolic link or directory tree at `path`
+pub async fn remove_path(path: &str) -> Result<()> {
+    let metadata = tokio::fs::symlink_metadata(path)
+        .await
+        .with_context(|| format!("Failed to stat {}", path))?;
+    let removed = if metadata.is_dir() {
+        tokio::fs::remove_dir_all(path).await
+    } else {
+        tokio::fs::remove_file(path).await
+    };
+    removed.with_context(|| format!("Failed to remove {}", path))
+}
+
+/// Move `from` to `to`, replacing a file there, blocking
+///
+/// A move to another filesystem copies the tree and then removes `from`.
+pub fn move_tree(from: &Path, to: &Path) -> Result<()> {
+    match std::fs::rename(from, to) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {}
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to move {} to {}", from.display(), to.display()));
+        }
+    }
+    copy_tree(from, to)
+        .with_context(|| format!("Failed to copy {} to {}", from.display(), to.display()))?;
+    let metadata = std::fs::symlink_metadata(from)?;
+    if metadata.is_dir() {
+        std::fs::remove_dir_all(from)?;
+    } else {
+        std::fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// Copy `from` to `to` keeping symbolic links and permissions
+fn copy_tree(from: &Path, to: &Path) -> std::io::Result<()> {
+    let metadata = std::fs::symlink_metadata(from)?;
+    if metadata.file_type().is_symlink() {
+        #[cfg(unix)]
+        return std::os::unix::fs::symlink(std::fs::read_link(from)?, to);
+        #[cfg(not(unix))]
+        return std::fs::copy(from, to).map(|_| ());
+    }
+    if !metadata.is_dir() {
+        return std::fs::copy(from, to).map(|_| ());
+    }
+    std::fs::create_dir(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        copy_tree(&entry.path(), &to.join(entry.file_name()))?;
+    }
+    std::fs::set_permissions(to, metadata.permissions())
+}
+
+/// Read up to `len` bytes at `offset`, returning the data and the file size
+///
+/// Uses the io_uring backend when it is built in and supported by the kernel.
+async fn read_at(path: &str, offset: u64, len: usize) -> Result<(Vec<u8>, u64)> {
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    if let Some(uring) = crate::uring::runtime() {
+        return uring.read_at(PathBuf::from(path), offset, len).await;
+    }
+
+    epoll_read_at(path, offset, len).await
+}
+
+/// Read up to `len` bytes at `offset` through `tokio::fs`
+pub async fn epoll_read_at(path: &str, offset: u64, len: usize) -> Result<(Vec<u8>, u64)> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path))?;
+    let size = file.metadata().await?.len();
+
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+    let mut data = Vec::with_capacity(len);
+    file.take(len as u64).read_to_end(&mut data).await?;
+    Ok((data, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    /// Run a listing to completion; test trees fit in the channel
+    async fn collect_files(paths: &[String], query: &ListQuery) -> Result<Vec<ResponseItem>> {
+        let (entries_tx, mut entries_rx) = mpsc::channel(64);
+        list_files(paths, query, &entries_tx).await?;
+        drop(entries_tx);
+
+        let mut entries = Vec::new();
+        while let Some(entry) = entries_rx.recv().await {
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    #[tokio::test]
+    async fn test_list_files_walks_directories() {
+        let dir = tempdir().unwrap();
+        let folder = dir.path().join("folder");
+        std::fs::create_dir_all(folder.join("nested")).unwrap();
+        std::fs::write(folder.join("a.txt"), b"aaa").unwrap();
+        std::fs::write(folder.join("nested/b.txt"), b"bb").unwrap();
+        let single = dir.path().join("single.txt");
+        std::fs::write(&single, b"s").unwrap();
+
+        let paths = vec![
+            folder.to_string_lossy().into_owned(),
+            single.to_string_lossy().into_owned(),
+        ];
+        let mut entries: Vec<_> = collect_files(&paths, &ListQuery::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| match item {
+                ResponseItem::FileEntry {
+                    relative_path,
+                    size,
+                    ..
+                } => (relative_path, size),
+                other => panic!("unexpected item {:?}", other),
+            })
+            .collect();
+        entries.sort();
+
+        assert_eq!(
+            entries,
+            vec![
+                ("folder/a.txt".to_string(), 3),
+                ("folder/nested/b.txt".to_string(), 2),
+                ("single.txt".to_string(), 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_directory_does_not_descend() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("logs/old")).unwrap();
+        std::fs::write(dir.path().join("logs/old/a.log"), b"a").unwrap();
+        std::fs::write(dir.path().join("b.txt"), b"b").unwrap();
+
+        let (entries_tx, mut entries_rx) = mpsc::channel(64);
+        list_directory(&dir.path().to_string_lossy(), &entries_tx)
+            .await
+            .unwrap();
+        drop(entries_tx);
+        let mut entries = Vec::new();
+        while let Some(ResponseItem::DirectoryEntry { name, dir }) = entries_rx.recv().await {
+            entries.push((name, dir));
+        }
+
+        assert_eq!(
+            entries,
+            vec![("b.txt".to_string(), false), ("logs".to_string(), true)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_files_pages_in_path_order() {
+        let dir = tempdir().unwrap();
+        for name in ["e.rs", "b.txt", "a.rs", "d.rs", "c.rs"] {
+            std::fs::write(dir.path().join(name), b"x").unwrap();
+        }
+        let query = ListQuery {
+            offset: 1,
+            limit: Some(2),
+            filter: Some(".rs".to_string()),
+            ..Default::default()
+        };
+
+        let names: Vec<_> = collect_files(&[dir.path().to_string_lossy().into_owned()], &query)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| match item {
+                ResponseItem::FileEntry { path, .. } => {
+                    path.rsplit('/').next().unwrap().to_string()
+                }
+                other => panic!("unexpected item {:?}", other),
+            })
+            .collect();
+
+        assert_eq!(names, vec!["c.rs", "d.rs"]);
+    }
+
+    #[tokio::test]
+    async fn test_list_files_missing_path() {
+        assert!(
+            collect_files(&["/nonexistent/yuha".to_string()], &ListQuery::default())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_chunk() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, b"old").unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        write_chunk(&path, 0, b"new ", checksum::crc32c(b"new "), false, None)
+            .await
+            .unwrap();
+        // Not replaced before the last chunk
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+        assert!(
+            write_chunk(&path, 4, b"text", 0, true, None).await.is_err(),
+            "corrupt chunk accepted"
+        );
+        write_chunk(&path, 4, b"text", checksum::crc32c(b"text"), true, None)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"new text");
+        assert!(!Path::new(&format!("{}{}", path, UPLOAD_SUFFIX)).exists());
+    }
+
+    #[tokio::test]
+    async fn test_write_chunk_refuses_changed_target() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, b"read").unwrap();
+        let path = path.to_string_lossy().into_owned();
+        let read = checksum::digest(b"read");
+
+        // Changed by someone else after the client read it
+        std::fs::write(&path, b"theirs").unwrap();
+        let crc = checksum::crc32c(b"mine");
+        let error = write_chunk(&path, 0, b"mine", crc, true, Some(&read))
+            .await
+            .unwrap_err();
+        assert!(error.is::<Conflict>());
+        assert_eq!(std::fs::read(&path).unwrap(), b"theirs");
+        assert!(!Path::new(&format!("{}{}", path, UPLOAD_SUFFIX)).exists());
+
+        let theirs = checksum::digest(b"theirs");
+        write_chunk(&path, 0, b"mine", crc, true, Some(&theirs))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"mine");
+    }
+
+    #[tokio::test]
+    async fn test_read_chunk() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        let content: Vec<u8> = (0..(MAX_FILE_CHUNK_LEN + 10))
+            .map(|i| (i % 251) as u8)
+            .collect();
+        std::fs::write(&path, &content).unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        match read_chunk(&path, 0, u32::MAX).await.unwrap() {
+            ResponseItem::FileChunk {
+                data, crc32c, eof, ..
+            } => {
+                assert_eq!(data.len(), MAX_FILE_CHUNK_LEN as usize);
+                assert_eq!(crc32c, checksum::crc32c(&data));
+                assert!(!eof);
+            }
+            other => panic!("unexpected item {:?}", other),
+        }
+
+        match read_chunk(&path, MAX_FILE_CHUNK_LEN as u64, 100)
+            .await
+            .unwrap()
+        {
+            ResponseItem::FileChunk { data, eof, .. } => {
+                assert_eq!(&data[..], &content[MAX_FILE_CHUNK_LEN as usize..]);
+                assert!(eof);
+            }
+            other => panic!("unexpected item {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hash_path() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("tree");
+        std::fs::create_dir_all(root.join("nested")).unwrap();
+        std::fs::write(root.join("a.txt"), b"aaa").unwrap();
+        std::fs::write(root.join("nested/b.txt"), b"bb").unwrap();
+        let root = root.to_string_lossy().into_owned();
+
+        let (hashes_tx, mut hashes_rx) = mpsc::channel(8);
+        hash_path(&root, HashAlgorithm::Crc32c, &hashes_tx)
+            .await
+            .unwrap();
+        drop(hashes_tx);
+        let mut hashed = Vec::new();
+        while let Some(item) = hashes_rx.recv().await {
+            match item {
+                ResponseItem::FileHash {
+                    relative_path,
+                    size,
+                    hash,
+                    ..
+                } => hashed.push((relative_path, size, hash)),
+                other => panic!("unexpected item {:?}", other),
+            }
+        }
+        assert_eq!(
+            hashed,
+            [
+                ("a.txt".to_string(), 3, HashAlgorithm::Crc32c.hash(b"aaa")),
+                (
+                    "nested/b.txt".to_string(),
+                    2,
+                    HashAlgorithm::Crc32c.hash(b"bb")
+                ),
+            ]
+        );
+
+        // A file is hashed itself
+        let (hashes_tx, mut hashes_rx) = mpsc::channel(8);
+        let file = format!("{}/a.txt", root);
+        hash_path(&file, HashAlgorithm::Blake3, &hashes_tx)
+            .await
+            .unwrap();
+        assert!(matches!(
+            hashes_rx.recv().await,
+            Some(ResponseItem::FileHash { relative_path, .. }) if relative_path.is_empty()
+        ));
+        assert!(
+            hash_path("/nonexistent/yuha", HashAlgorithm::Blake3, &hashes_tx)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_range() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        std::fs::write(&path, b"first\nsecond\n").unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        match read_range(&path, 6, 100).await.unwrap() {
+            ResponseItem::FileRange {
+                data, crc32c, size, ..
+            } => {
+                assert_eq!(&data[..], b"second\n");
+                assert_eq!(crc32c, checksum::crc32c(&data));
+                assert_eq!(size, 13);
+            }
+            other => panic!("unexpected item {:?}", other),
+        }
+
+        // Past the end, as after truncation
+        match read_range(&path, 100, 100).await.unwrap() {
+            ResponseItem::FileRange { data, size, .. } => {
+                assert!(data.is_empty());
+                assert_eq!(size, 13);
+            }
+            other => panic!("unexpected item {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_remove_and_copy_tree() {
+        let dir = tempdir().unwrap();
+        let tree = dir.path().join("tree");
+        std::fs::create_dir_all(tree.join("sub")).unwrap();
+        std::fs::write(tree.join("sub/a.txt"), b"a").unwrap();
+
+        // As a move across filesystems does
+        let copy = dir.path().join("copy");
+        copy_tree(&tree, &copy).unwrap();
+        assert_eq!(std::fs::read(copy.join("sub/a.txt")).unwrap(), b"a");
+
+        remove_path(&tree.to_string_lossy()).await.unwrap();
+        assert!(!tree.exists());
+        remove_path(&copy.join("sub/a.txt").to_string_lossy())
+            .await
+            .unwrap();
+        assert!(!copy.join("sub/a.txt").exists());
+        assert!(remove_path(&tree.to_string_lossy()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_disk_usage() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("tree");
+        std::fs::create_dir_all(root.join("a/deep")).unwrap();
+        std::fs::create_dir_all(root.join("b")).unwrap();
+        std::fs::write(root.join("top.txt"), b"1234").unwrap();
+        std::fs::write(root.join("a/one.txt"), b"12").unwrap();
+        std::fs::write(root.join("a/deep/two.txt"), b"123").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.join("a"), root.join("b/link")).unwrap();
+        let root = root.to_string_lossy().into_owned();
+
+        let (items_tx, mut items_rx) = mpsc::channel(8);
+        disk_usage(&root, 1, &items_tx).await.unwrap();
+        drop(items_tx);
+        let mut usages = Vec::new();
+        while let Some(item) = items_rx.recv().await {
+            if let ResponseItem::DirectoryUsage { usage } = item {
+                let relative = usage.path.strip_prefix(&root).unwrap().to_string();
+                usages.push((relative, usage.depth, usage.size, usage.files));
+            }
+        }
+        // Directories below the depth limit count, but are not reported
+        assert_eq!(usages.last().unwrap(), &(String::new(), 0, 9, 3));
+        usages.sort();
+        assert_eq!(
+            usages,
+            [
+                (String::new(), 0, 9, 3),
+                ("/a".to_string(), 1, 5, 2),
+                ("/b".to_string(), 1, 0, 0),
+            ]
+        );
+
+        let (items_tx, mut items_rx) = mpsc::channel(8);
+        disk_usage(&format!("{}/top.txt", root), 3, &items_tx)
+            .await
+            .unwrap();
+        match items_rx.recv().await.unwrap() {
+            ResponseItem::DirectoryUsage { usage } => assert_eq!((usage.size, usage.files), (4, 1)),
+            other => panic!("unexpected item {:?}", other),
+        }
+        assert!(
+            disk_usage(&format!("{}/missing", root), 0, &items_tx)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! Port forwarding relays
+//!
+//! Copies data between an accepted client connection and the forwarding
+//! target. Target data is also queued in the [`ResponseBuffer`] for clients
+//! that poll, and data received from the client over the message channel is
+//! written to the target. Both directions of the latter stay within the
+//! connection's [`Flow`] credits.
+
+use bytes::Bytes;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{RwLock, mpsc};
+use tracing::error;
+use yuha_core::protocol::ResponseBuffer;
+use yuha_core::protocol::flow::{Credit, INITIAL_CREDIT};
+
+/// Relay buffer size per direction
+pub const RELAY_BUF_LEN: usize = 16 * 1024;
+
+/// Credits of a relayed connection (see [`yuha_core::protocol::flow`])
+pub struct Flow {
+    /// Target data the client is ready to poll
+    pub outbound: Credit,
+    /// Client data the target is ready to accept
+    pub inbound: Credit,
+    /// Whether the client negotiated flow control and expects `Credit` items
+    pub enabled: bool,
+}
+
+impl Flow {
+    pub fn new(enabled: bool) -> Self {
+        let credit = || {
+            if enabled {
+                Credit::new(INITIAL_CREDIT)
+            } else {
+                Credit::unlimited()
+            }
+        };
+        Self {
+            outbound: credit(),
+            inbound: credit(),
+            enabled,
+        }
+    }
+
+    /// Give the client back the credit of `bytes` written to the target
+    pub async fn written(
+        &self,
+        connection_id: u32,
+        bytes: usize,
+        response_buffer: &RwLock<ResponseBuffer>,
+    ) {
+        if self.enabled {
+            self.inbound.grant(bytes);
+            response_buffer
+                .write()
+                .await
+                .add_credit(connection_id, bytes as u32);
+        }
+    }
+}
+
+/// A relayed connection, as reached by the requests of its session
+pub struct RelayHandle {
+    /// Client data to write to the target
+    pub data: mpsc::UnboundedSender<Bytes>,
+    pub flow: Arc<Flow>,
+}
+
+/// Relay a connection until either side closes
+///
+/// Uses the io_uring backend when it is built in and supported by the kernel,
+/// and the epoll based tokio runtime otherwise.
+pub async fn relay(
+    client_stream: TcpStream,
+    target_stream: TcpStream,
+    connection_id: u32,
+    response_buffer: Arc<RwLock<ResponseBuffer>>,
+    data_rx: mpsc::UnboundedReceiver<Bytes>,
+    flow: Arc<Flow>,
+) {
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    if let Some(uring) = crate::uring::runtime() {
+        let streams = client_stream
+            .into_std()
+            .and_then(|client| Ok((client, target_stream.into_std()?)));
+        let result = match streams {
+            Ok((client, target)) => {
+                uring
+                    .relay(
+                        client,
+                        target,
+                        connection_id,
+                        response_buffer,
+                        data_rx,
+                        flow,
+                    )
+                    .await
+            }
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            error!(
+                "io_uring relay failed for connection {}: {}",
+                connection_id, e
+            );
+        }
+        return;
+    }
+
+    epoll_relay(
+        client_stream,
+        target_stream,
+        connection_id,
+        response_buffer,
+        data_rx,
+        flow,
+    )
+    .await
+}
+
+/// Relay a connection on the epoll based tokio runtime
+pub async fn epoll_relay(
+    mut client_stream: TcpStream,
+    mut target_stream: TcpStream,
+    connection_id: u32,
+    response_buffer: Arc<RwLock<ResponseBuffer>>,
+    mut data_rx: mpsc::UnboundedReceiver<Bytes>,
+    flow: Arc<Flow>,
+) {
+    let mut client_buf = vec![0; RELAY_BUF_LEN];
+    let mut target_buf = vec![0; RELAY_BUF_LEN];
+
+    loop {
+        tokio::select! {
+            // Read from client and write to target
+            result = client_stream.read(&mut client_buf) => {
+                match result {
+                    Ok(0) => {
+                        break;
+                    }
+                    Ok(n) => {
+                        if let Err(e) = target_stream.write_all(&client_buf[..n]).await {
+                            error!("Error writing to target for connection {}: {}", connection_id, e);
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        error!("Error reading from client for connection {}: {}", connection_id, e);
+                        break;
+                    }
+                }
+            }
+            // Read from target while the client has credit, and write to client
+            result = async {
+                let window = flow.outbound.available().await.min(target_buf.len());
+                target_stream.read(&mut target_buf[..window]).await
+            } => {
+                match result {
+                    Ok(0) => {
+                        break;
+                    }
+                    Ok(n) => {
+                        flow.outbound.consume(n);
+                        if let Err(e) = client_stream.write_all(&target_buf[..n]).await {
+                            error!("Error writing to client for connection {}: {}", connection_id, e);
+                            break;
+                        }
+                        // Also buffer data for client polling
+                        {
+                            let mut buffer = response_buffer.write().await;
+                            buffer.add_port_forward_data(connection_id, Bytes::copy_from_slice(&target_buf[..n]));
+                        }
+                    }
+                    Err(e) => {
+                        error!("Error reading from target for connection {}: {}", connection_id, e);
+                        break;
+                    }
+                }
+            }
+            // Handle data from client via request
+            data = data_rx.recv() => {
+                match data {
+                    Some(data) => {
+                        if let Err(e) = target_stream.write_all(&data).await {
+                            error!("Error writing client data to target for connection {}: {}", connection_id, e);
+                            break;
+                        }
+                        flow.written(connection_id, data.len(), &response_buffer).await;
+                    }
+                    None => {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use yuha_core::protocol::ResponseItem;
+    use yuha_core::protocol::buffer::ProtocolBuffer;
+
+    async fn pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let connecting = TcpStream::connect(listener.local_addr().unwrap());
+        let (accepted, connected) = tokio::join!(listener.accept(), connecting);
+        (accepted.unwrap().0, connected.unwrap())
+    }
+
+    fn polled(items: &[ResponseItem]) -> usize {
+        items
+            .iter()
+            .map(|item| match item {
+                ResponseItem::PortForwardData { data, .. } => data.len(),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn test_relay_stays_within_credit() {
+        let (client_side, mut client) = pair().await;
+        let (target_side, mut target) = pair().await;
+        let response_buffer = Arc::new(RwLock::new(ResponseBuffer::new()));
+        let flow = Arc::new(Flow {
+            outbound: Credit::new(4),
+            inbound: Credit::new(INITIAL_CREDIT),
+            enabled: true,
+        });
+        let (data_tx, data_rx) = mpsc::unbounded_channel();
+        tokio::spawn(epoll_relay(
+            client_side,
+            target_side,
+            7,
+            response_buffer.clone(),
+            data_rx,
+            flow.clone(),
+        ));
+
+        target.write_all(b"0123456789").await.unwrap();
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(polled(&response_buffer.write().await.take_items()), 4);
+
+        flow.outbound.grant(6);
+        let mut rest = [0; 6];
+        client.read_exact(&mut rest).await.unwrap();
+        assert_eq!(&rest, b"456789");
+
+        // Client data written to the target returns its credit
+        assert!(flow.inbound.consume(5));
+        data_tx.send(Bytes::from_static(b"hello")).unwrap();
+        let mut injected = [0; 5];
+        target.read_exact(&mut injected).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let items = response_buffer.write().await.take_items();
+        assert!(items.iter().any(|item| matches!(
+            item,
+            ResponseItem::Credit {
+                connection_id: 7,
+                bytes: 5
+            }
+        )));
+    }
+}
@@ -10,8 +10,10 @@ use std::sync::Arc;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{UnixListener, UnixStream};
 use tokio::sync::{RwLock, mpsc};
-use tracing::{error, info};
-use yuha_core::protocol::ResponseBuffer;
+use tracing::{debug, error, info};
+use yuha_core::protocol::{ResponseBuffer, ServerReply, ServerRequest};
+
+use crate::server_requests::ServerRequests;
 
 /// IPC command that can be sent from shell to remote process
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,6 +54,7 @@ pub struct IpcServer {
     socket_path: PathBuf,
     response_buffer: Arc<RwLock<ResponseBuffer>>,
     client_sender: Option<mpsc::UnboundedSender<String>>,
+    server_requests: Option<ServerRequests>,
     uptime_start: std::time::Instant,
 }
 
@@ -62,6 +65,7 @@ impl IpcServer {
             socket_path,
             response_buffer,
             client_sender: None,
+            server_requests: None,
             uptime_start: std::time::Instant::now(),
         }
     }
@@ -71,6 +75,12 @@ impl IpcServer {
         self.client_sender = Some(sender);
     }
 
+    /// Ask the client of the session through `server_requests` to open
+    /// URLs, and tell it of clipboard changes
+    pub fn set_server_requests(&mut self, server_requests: ServerRequests) {
+        self.server_requests = Some(server_requests);
+    }
+
     /// Start the IPC server
     pub async fn start(&self) -> Result<()> {
         // Remove existing socket if it exists
@@ -91,6 +101,7 @@ impl IpcServer {
                 Ok((stream, _)) => {
                     let response_buffer = self.response_buffer.clone();
                     let client_sender = self.client_sender.clone();
+                    let server_requests = self.server_requests.clone();
                     let uptime_start = self.uptime_start;
 
                     tokio::spawn(async move {
@@ -98,6 +109,7 @@ impl IpcServer {
                             stream,
                             response_buffer,
                             client_sender,
+                            server_requests,
                             uptime_start,
                         )
                         .await
@@ -121,6 +133,7 @@ impl IpcServer {
         stream: UnixStream,
         response_buffer: Arc<RwLock<ResponseBuffer>>,
         client_sender: Option<mpsc::UnboundedSender<String>>,
+        server_requests: Option<ServerRequests>,
         uptime_start: std::time::Instant,
     ) -> Result<()> {
         let (reader, mut writer) = stream.into_split();
@@ -147,6 +160,7 @@ impl IpcServer {
                         command,
                         &response_buffer,
                         &client_sender,
+                        &server_requests,
                         uptime_start,
                     )
                     .await;
@@ -168,12 +182,13 @@ impl IpcServer {
         command: IpcCommand,
         _response_buffer: &Arc<RwLock<ResponseBuffer>>,
         client_sender: &Option<mpsc::UnboundedSender<String>>,
+        server_requests: &Option<ServerRequests>,
         uptime_start: std::time::Instant,
     ) -> IpcResponse {
         match command {
             IpcCommand::GetClipboard => match yuha_core::clipboard::get_clipboard() {
                 Ok(content) => IpcResponse::Success {
-                    data: Some(content),
+                    data: Some(content.unwrap_or_default()),
                 },
                 Err(e) => IpcResponse::Error {
                     message: format!("Failed to get clipboard: {}", e),
@@ -181,18 +196,20 @@ impl IpcServer {
             },
             IpcCommand::SetClipboard { content } => {
                 match yuha_core::clipboard::set_clipboard(&content) {
-                    Ok(()) => IpcResponse::Success { data: None },
+                    Ok(()) => {
+                        if let Some(server_requests) = server_requests {
+                            // No client to tell is fine
+                            let _ =
+                                server_requests.notify(ServerRequest::ClipboardChanged { content });
+                        }
+                        IpcResponse::Success { data: None }
+                    }
                     Err(e) => IpcResponse::Error {
                         message: format!("Failed to set clipboard: {}", e),
                     },
                 }
             }
-            IpcCommand::OpenBrowser { url } => match yuha_core::browser::open_url(&url).await {
-                Ok(()) => IpcResponse::Success { data: None },
-                Err(e) => IpcResponse::Error {
-                    message: format!("Failed to open browser: {}", e),
-                },
-            },
+            IpcCommand::OpenBrowser { url } => Self::open_browser(url, server_requests).await,
             IpcCommand::SendToClient { message } => {
                 if let Some(sender) = client_sender {
                     match sender.send(message) {
@@ -218,6 +235,30 @@ impl IpcServer {
         }
     }
 
+    /// Open `url` on the client's host, or on this host when no client
+    /// takes the request
+    async fn open_browser(url: String, server_requests: &Option<ServerRequests>) -> IpcResponse {
+        if let Some(server_requests) = server_requests {
+            let request = ServerRequest::OpenUrl { url: url.clone() };
+            match server_requests.request(request).await {
+                Ok(ServerReply::Done) => return IpcResponse::Success { data: None },
+                Ok(ServerReply::Failed { message }) => {
+                    return IpcResponse::Error {
+                        message: format!("Client failed to open browser: {}", message),
+                    };
+                }
+                Ok(ServerReply::Unhandled) => debug!("Client did not open {}", url),
+                Err(e) => debug!("Client did not open {}: {:#}", url, e),
+            }
+        }
+        match yuha_core::browser::open_url(&url).await {
+            Ok(()) => IpcResponse::Success { data: None },
+            Err(e) => IpcResponse::Error {
+                message: format!("Failed to open browser: {}", e),
+            },
+        }
+    }
+
     /// Send an IPC response
     async fn send_response(
         writer: &mut tokio::net::unix::OwnedWriteHalf,
@@ -8,11 +8,42 @@
 //!
 //! The remote server operates in a simple request-response mode, processing
 //! incoming requests from clients and sending back responses. It uses a
-//! polling-based approach for pseudo-bidirectional communication.
+//! polling-based approach for pseudo-bidirectional communication; clients
+//! negotiating `server-requests` are also sent requests of the server.
 //!
 //! ## Key Components
 //!
+//! - **Access Module**: Access log of TCP connections and webhook alerts on
+//!   repeated rejections
+//! - **Apps Module**: GUI application launch on the remote desktop session
+//! - **Clipboard Watch Module**: Adaptive polling of tmux buffers, pushing
+//!   copies to clients
+//! - **Events Module**: Session, authentication and upload events delivered
+//!   to webhooks and commands
+//! - **Faults Module**: Injected request faults for client tests
+//!   (`fault-injection` feature)
 //! - **IPC Module**: Inter-process communication for daemon mode
+//! - **Files Module**: File listing, chunked reads and writes, removing and
+//!   moving
+//! - **Forward Module**: Relays for port forwarded connections
+//! - **Middleware Module**: Policies wrapped around the request dispatcher
+//! - **Sandbox Module**: User switching, resource limits and read-only
+//!   filesystems of launched applications
+//! - **Server Requests Module**: Requests sent to the client of a session,
+//!   such as opening a URL on the client's host
+//! - **Stdio Module**: Protocol stream of stdio mode, guarded from stray
+//!   output
+//! - **Tasks Module**: Tracking and cancellation of a session's background tasks
+//! - **Tmux Module**: Clipboard mirrored to tmux paste buffers
+//! - **Tools Module**: Toolchain probing for client integrations and diagnostics
+//! - **Topics Module**: Clipboard, port forward and file events pushed to
+//!   subscribed clients
+//! - **Trash Module**: Removed and replaced files kept for restoring
+//! - **Usage Module**: Resource accounting and quotas
+//! - **Workspace Module**: Isolated clipboards, forwards and path roots
+//!   selected at handshake
+//! - **Uring Module**: io_uring backend for file reads and relays (Linux,
+//!   `io-uring` feature)
 //! - **Request Processing**: Handles various client request types
 //! - **System Integration**: Interfaces with local system resources
 //!
@@ -22,7 +53,28 @@
 //! - **Stdio Mode**: Communicate over stdin/stdout (default for SSH)
 //! - **Daemon Mode**: Run as background service with IPC communication
 
+pub mod access;
+pub mod apps;
+pub mod clipboard_watch;
+pub mod events;
+#[cfg(feature = "fault-injection")]
+pub mod faults;
+pub mod files;
+pub mod forward;
 pub mod ipc;
+pub mod middleware;
+pub mod sandbox;
+pub mod server_requests;
+pub mod stdio;
+pub mod tasks;
+pub mod tmux;
+pub mod tools;
+pub mod topics;
+pub mod trash;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub mod uring;
+pub mod usage;
+pub mod workspace;
 
 /// Remote implementation
 pub mod remote {
@@ -1,80 +1,303 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use bytes::Bytes;
 use clap::{Parser, Subcommand};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::io::Write;
-use std::path::PathBuf;
-use std::pin::Pin;
-use std::sync::Arc;
-use std::task::{Context, Poll};
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf, Stdin, Stdout};
-use tokio::sync::{RwLock, mpsc};
-use tracing::{error, info, warn};
-
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::{RwLock, broadcast, mpsc};
+use tokio::task::AbortHandle;
+use tracing::{debug, error, info, warn};
+
+use yuha_core::access_log::{self, AccessLog};
+use yuha_core::checksum::{self, HashAlgorithm};
+use yuha_core::child_env::ChildEnv;
+use yuha_core::clipboard::{self, ClipboardFormat, ClipboardItem, ClipboardStore};
+use yuha_core::error::{ProtocolError, YuhaError};
 use yuha_core::message_channel::MessageChannel;
+use yuha_core::open::OpenHandlers;
+use yuha_core::protocol::attachment::BinaryEncoding;
 use yuha_core::protocol::buffer::ProtocolBuffer;
-use yuha_core::protocol::{ProtocolRequest, ProtocolResponse, ResponseBuffer};
-use yuha_core::{browser, clipboard};
+use yuha_core::protocol::codec;
+use yuha_core::protocol::command::CommandRegistry;
+use yuha_core::protocol::compression::Compression;
+use yuha_core::protocol::extension::{self, ExtensionId};
+#[cfg(feature = "fault-injection")]
+use yuha_core::protocol::fault::Fault;
+use yuha_core::protocol::flow::{Credit, INITIAL_BATCH_CREDIT};
+use yuha_core::protocol::frame_checksum::FrameChecksum;
+use yuha_core::protocol::noise::{NoiseConfig, NoiseKeypair, NoisePublicKey};
+use yuha_core::protocol::request_response::{
+    ClipboardWatch, PortForwardEntry, Quota, Sandbox, SessionState, TaskId, TaskInfo, TaskKind,
+    Usage,
+};
+use yuha_core::protocol::{
+    CorrelationId, ErrorCode, ListQuery, ProtocolRequest, ProtocolResponse, ResponseBatcher,
+    ResponseBuffer, ResponseItem, ServerReply, ServerRequest, ServerRequestId, Topic, TopicEvent,
+};
+use yuha_core::slow_log::{SlowLog, SlowLogConfig, SlowRequest};
+use yuha_core::ssh_agent::AgentKey;
+use yuha_core::{METRICS, browser};
+use yuha_remote::access::{AcceptedConnection, AccessMonitor, FailureAlerts, Webhook};
+use yuha_remote::clipboard_watch::{ClipboardWatcher, PollConfig};
+use yuha_remote::events::{Event, EventBus, EventHook, EventKind, EventSink};
+#[cfg(feature = "fault-injection")]
+use yuha_remote::faults::Faults;
 use yuha_remote::ipc::{IpcClient, IpcCommand, IpcResponse, get_default_ipc_socket_path};
-
-/// A stream that combines stdin and stdout for bidirectional communication
-pub struct StdioStream {
-    stdin: Stdin,
-    stdout: Stdout,
-}
-
-impl StdioStream {
-    pub fn new(stdin: Stdin, stdout: Stdout) -> Self {
-        Self { stdin, stdout }
-    }
-}
-
-impl AsyncRead for StdioStream {
-    fn poll_read(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &mut ReadBuf<'_>,
-    ) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut self.stdin).poll_read(cx, buf)
-    }
-}
-
-impl AsyncWrite for StdioStream {
-    fn poll_write(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &[u8],
-    ) -> Poll<std::io::Result<usize>> {
-        Pin::new(&mut self.stdout).poll_write(cx, buf)
-    }
-
-    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut self.stdout).poll_flush(cx)
-    }
-
-    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut self.stdout).poll_shutdown(cx)
-    }
-}
+use yuha_remote::middleware::{
+    Audit, Authorization, CommandAllowList, Middleware, MiddlewareChain, RateLimit, RequestContext,
+};
+use yuha_remote::server_requests::{self, Outbox};
+use yuha_remote::tasks::TaskRegistry;
+use yuha_remote::tmux::TmuxBuffers;
+use yuha_remote::topics::{Publisher, Subscriber, TopicBus};
+use yuha_remote::trash::{self, Trash};
+use yuha_remote::usage::{self, Meter, Meters};
+use yuha_remote::workspace::{Workspace, Workspaces};
+use yuha_remote::{apps, files, forward, stdio, tools};
+
+/// Items a file system walk finds ahead of the client; bounds memory on huge
+/// trees
+const BATCH_QUEUE_LEN: usize = 256;
+
+/// How long a TCP client has to complete the encryption and authentication
+/// handshakes, so silent connections do not hold a session open
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Simplified remote server using request-response protocol
 pub struct RemoteServer<T> {
     message_channel: MessageChannel<T>,
     response_buffer: Arc<RwLock<ResponseBuffer>>,
-    active_connections: Arc<RwLock<HashMap<u32, mpsc::UnboundedSender<Bytes>>>>,
+    active_connections: Arc<RwLock<HashMap<u32, forward::RelayHandle>>>,
     next_connection_id: Arc<RwLock<u32>>,
+    peer: String,
+    slow_log: SlowLog,
+    /// Bytes of `Batch` responses sent for the current request
+    streamed_len: usize,
+    /// Correlation id of the current request, echoed by its responses
+    correlation: Option<CorrelationId>,
+    /// Whether the client cancelled the current request
+    cancelled: bool,
+    /// Extensions this server may negotiate
+    extensions: Vec<ExtensionId>,
+    /// Extensions negotiated by the client's `Hello`
+    negotiated: Vec<ExtensionId>,
+    /// Requests that arrived while another one was served, served next in
+    /// order
+    queued_requests: VecDeque<yuha_core::Result<(Option<CorrelationId>, ProtocolRequest)>>,
+    /// Applications opening paths for `OpenPath`
+    open_handlers: OpenHandlers,
+    /// Restrictions every launched application runs under at least
+    launch_sandbox: Sandbox,
+    /// Environment launched applications start with
+    launch_env: ChildEnv,
+    /// tmux paste buffers the clipboard is mirrored to
+    tmux: Option<Arc<TmuxBuffers>>,
+    /// Watcher of the tmux buffers, pushing copies to clients
+    clipboard_watcher: Option<Arc<ClipboardWatcher>>,
+    /// Task pushing watched copies to this session's client
+    clipboard_pushes: Option<AbortHandle>,
+    /// Policies wrapped around `handle_request`
+    middleware: MiddlewareChain,
+    /// Key the client authenticated with
+    identity: Option<String>,
+    /// Background tasks of this session, or of its workspace; those of this
+    /// session are cancelled when it ends
+    tasks: TaskRegistry,
+    /// Workspaces a client may select
+    workspaces: Workspaces,
+    /// Workspace selected by the client's `Hello`
+    workspace: Option<Arc<Workspace>>,
+    /// Resources used by the server's clients
+    meters: Arc<Meters>,
+    /// Resources used by this client, its identity's meter in `meters`
+    meter: Arc<Meter>,
+    /// Limits of the resources each client uses
+    quota: Quota,
+    /// Where removed and replaced paths are kept when asked for
+    trash: Arc<Trash>,
+    /// Connections to the server, shared by its sessions
+    access_log: Arc<AccessLog>,
+    /// Where completed uploads are published
+    events: EventBus,
+    /// Requests to send this session's client
+    server_requests: Option<Outbox>,
+    /// Where events of topics are published, shared by the server's sessions
+    topics: TopicBus,
+    /// Events of the topics the client subscribed to
+    subscriber: Option<Subscriber>,
+    /// Handlers of `Command` requests, tried in turn
+    commands: CommandRegistry,
+    /// Faults armed by the client's `InjectFault` requests
+    #[cfg(feature = "fault-injection")]
+    faults: Faults,
+}
+
+impl<T> Drop for RemoteServer<T> {
+    fn drop(&mut self) {
+        self.tasks.cancel_where(|task| task.session == self.peer);
+        if let Some(pushes) = &self.clipboard_pushes {
+            pushes.abort();
+        }
+    }
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> RemoteServer<T> {
-    pub fn new(message_channel: MessageChannel<T>) -> Self {
+    pub fn new(message_channel: MessageChannel<T>, peer: String, slow_log: SlowLogConfig) -> Self {
         Self {
             message_channel,
             response_buffer: Arc::new(RwLock::new(ResponseBuffer::new())),
             active_connections: Arc::new(RwLock::new(HashMap::new())),
             next_connection_id: Arc::new(RwLock::new(1)),
+            tasks: TaskRegistry::new(&peer),
+            peer,
+            slow_log: SlowLog::new(slow_log),
+            streamed_len: 0,
+            extensions: extension::registered(),
+            negotiated: Vec::new(),
+            queued_requests: VecDeque::new(),
+            correlation: None,
+            cancelled: false,
+            open_handlers: OpenHandlers::default(),
+            launch_sandbox: Sandbox::default(),
+            launch_env: ChildEnv::default(),
+            tmux: None,
+            clipboard_watcher: None,
+            clipboard_pushes: None,
+            middleware: MiddlewareChain::standard(),
+            identity: None,
+            workspaces: Workspaces::default(),
+            workspace: None,
+            meters: Arc::default(),
+            meter: Arc::default(),
+            quota: Quota::default(),
+            trash: Arc::new(Trash::new(
+                Trash::default_dir(),
+                Some(trash::DEFAULT_RETENTION),
+            )),
+            access_log: Arc::new(AccessLog::new(0)),
+            events: EventBus::default(),
+            server_requests: None,
+            topics: TopicBus::default(),
+            subscriber: None,
+            commands: CommandRegistry::default(),
+            #[cfg(feature = "fault-injection")]
+            faults: Faults::default(),
         }
     }
 
+    /// Account usage on the meter of the client's identity in `meters`,
+    /// shared by the server's sessions
+    pub fn with_meters(mut self, meters: Arc<Meters>) -> Self {
+        self.meter = meters.of(self.identity.as_deref());
+        self.meters = meters;
+        self
+    }
+
+    /// Refuse to use resources beyond `quota`
+    pub fn with_quota(mut self, quota: Quota) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Keep removed and replaced paths in `trash`
+    pub fn with_trash(mut self, trash: Arc<Trash>) -> Self {
+        self.trash = trash;
+        self
+    }
+
+    /// Answer `GetAccessLog` from `access_log`
+    pub fn with_access_log(mut self, access_log: Arc<AccessLog>) -> Self {
+        self.access_log = access_log;
+        self
+    }
+
+    /// Publish completed uploads on `events`
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Host `workspaces`, one of which clients must select
+    pub fn with_workspaces(mut self, workspaces: Workspaces) -> Self {
+        self.workspaces = workspaces;
+        self
+    }
+
+    /// Only negotiate the given extensions instead of every registered one
+    pub fn with_extensions(mut self, extensions: Vec<ExtensionId>) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Open paths with `handlers` instead of the platform's default opener
+    pub fn with_open_handlers(mut self, handlers: OpenHandlers) -> Self {
+        self.open_handlers = handlers;
+        self
+    }
+
+    /// Launch applications under at least the restrictions of `sandbox`
+    pub fn with_launch_sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.launch_sandbox = sandbox;
+        self
+    }
+
+    /// Start launched applications with `env` instead of the default
+    /// allow-listed environment
+    pub fn with_launch_env(mut self, env: ChildEnv) -> Self {
+        self.launch_env = env;
+        self
+    }
+
+    /// Mirror the clipboard to the paste buffers of `tmux`
+    pub fn with_tmux_buffers(mut self, tmux: Arc<TmuxBuffers>) -> Self {
+        self.tmux = Some(tmux);
+        self
+    }
+
+    /// Push text copied in the tmux buffers `watcher` polls to clients
+    /// negotiating `clipboard-watch`
+    pub fn with_clipboard_watcher(mut self, watcher: Arc<ClipboardWatcher>) -> Self {
+        self.clipboard_watcher = Some(watcher);
+        self
+    }
+
+    /// Send the requests taken from `outbox` to clients negotiating
+    /// `server-requests`
+    pub fn with_server_requests(mut self, outbox: Outbox) -> Self {
+        self.server_requests = Some(outbox);
+        self
+    }
+
+    /// Publish events on `topics`, shared with the server's other sessions
+    pub fn with_topics(mut self, topics: TopicBus) -> Self {
+        self.topics = topics;
+        self
+    }
+
+    /// Serve `Command` requests with the handlers of `commands`
+    pub fn with_commands(mut self, commands: CommandRegistry) -> Self {
+        self.commands = commands;
+        self
+    }
+
+    /// Serve a client authenticated with `identity`, e.g. its Noise key
+    pub fn with_identity(mut self, identity: Option<String>) -> Self {
+        self.meter = self.meters.of(identity.as_deref());
+        self.identity = identity;
+        self
+    }
+
+    /// Add `middleware` inside the standard chain
+    pub fn with_middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware = std::mem::take(&mut self.middleware).with(middleware);
+        self
+    }
+
     pub fn get_response_buffer(&self) -> Arc<RwLock<ResponseBuffer>> {
         self.response_buffer.clone()
     }
@@ -84,15 +307,23 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> RemoteServer<T> {
         info!("Remote server starting with request-response protocol");
 
         loop {
-            match self.message_channel.receive_request().await {
-                Ok(request) => {
-                    let response = self.handle_request(request).await;
-
-                    if let Err(e) = self.message_channel.send_response(&response).await {
+            match self.next_request().await {
+                Ok((id, request)) => {
+                    if let Err(e) = self.serve_request(id, request).await {
                         error!("Failed to send response: {}", e);
                         break;
                     }
                 }
+                Err(YuhaError::Protocol(ProtocolError::ChannelReset)) => {
+                    warn!("Channel reset, requests in flight were lost");
+                }
+                Err(YuhaError::Protocol(ProtocolError::RequestCancelled { id })) => {
+                    debug!("Request {} finished before its cancellation", id);
+                }
+                Err(YuhaError::Protocol(ProtocolError::ClosedByPeer)) => {
+                    info!("Client closed the session");
+                    break;
+                }
                 Err(e) => {
                     error!("Error receiving request: {}", e);
                     break;
@@ -123,16 +354,24 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> RemoteServer<T> {
         loop {
             tokio::select! {
                 // Handle client requests
-                request_result = self.message_channel.receive_request() => {
+                request_result = self.next_request() => {
                     match request_result {
-                        Ok(request) => {
-                            let response = self.handle_request(request).await;
-
-                            if let Err(e) = self.message_channel.send_response(&response).await {
+                        Ok((id, request)) => {
+                            if let Err(e) = self.serve_request(id, request).await {
                                 error!("Failed to send response: {}", e);
                                 break;
                             }
                         }
+                        Err(YuhaError::Protocol(ProtocolError::ChannelReset)) => {
+                            warn!("Channel reset, requests in flight were lost");
+                        }
+                        Err(YuhaError::Protocol(ProtocolError::RequestCancelled { id })) => {
+                            debug!("Request {} finished before its cancellation", id);
+                        }
+                        Err(YuhaError::Protocol(ProtocolError::ClosedByPeer)) => {
+                            info!("Client closed the session");
+                            break;
+                        }
                         Err(e) => {
                             error!("Error receiving request: {}", e);
                             break;
@@ -149,9 +388,355 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> RemoteServer<T> {
         Ok(())
     }
 
+    /// Receive the next request, starting with those that arrived while
+    /// another one was served, and send server requests while waiting
+    async fn next_request(
+        &mut self,
+    ) -> yuha_core::Result<(Option<CorrelationId>, ProtocolRequest)> {
+        if let Some(request) = self.queued_requests.pop_front() {
+            return request;
+        }
+        loop {
+            // Both are cancel safe
+            tokio::select! {
+                received = self.message_channel.receive_request_with_id() => return received,
+                (id, request) = next_server_request(&mut self.server_requests) => {
+                    self.send_server_request(id, request).await?;
+                }
+                event = next_event(&mut self.subscriber) => self.send_event(event).await?,
+            }
+        }
+    }
+
+    /// Push an event of a subscribed topic to the client
+    async fn send_event(&mut self, event: TopicEvent) -> yuha_core::Result<()> {
+        debug!("Pushing {:?}", event);
+        self.message_channel
+            .send_response_with_id(None, &ProtocolResponse::Event { event })
+            .await
+    }
+
+    /// Handle publishing the events of this session's workspace
+    fn publisher(&self) -> Publisher {
+        self.topics
+            .publisher(self.workspace.as_ref().map(|workspace| workspace.name()))
+    }
+
+    /// Send a request to the client, or answer it `Unhandled` when the
+    /// client did not negotiate `server-requests`
+    async fn send_server_request(
+        &mut self,
+        id: ServerRequestId,
+        request: ServerRequest,
+    ) -> yuha_core::Result<()> {
+        if !self.negotiated.contains(&extension::SERVER_REQUESTS.id) {
+            debug!("Client cannot take server request {}", request.kind());
+            if let Some(outbox) = &mut self.server_requests {
+                outbox.reply(id, ServerReply::Unhandled);
+            }
+            return Ok(());
+        }
+        debug!("Sending server request {} ({})", id, request.kind());
+        self.message_channel
+            .send_response_with_id(None, &ProtocolResponse::ServerRequest { id, request })
+            .await
+    }
+
+    /// Whether to keep receiving while serving a request; not once a
+    /// receive failed, as its error waits in the queue
+    fn reading_ahead(&self) -> bool {
+        self.queued_requests
+            .back()
+            .is_none_or(|request| request.is_ok())
+    }
+
+    /// Take in what arrived while serving a request: queue a request to be
+    /// served next, or apply a cancellation, answering a queued request it
+    /// hits right away
+    ///
+    /// Returns whether the request being served was cancelled.
+    async fn receive_ahead(
+        &mut self,
+        received: yuha_core::Result<(Option<CorrelationId>, ProtocolRequest)>,
+    ) -> bool {
+        let id = match received {
+            Err(YuhaError::Protocol(ProtocolError::RequestCancelled { id })) => id,
+            received => {
+                self.queued_requests.push_back(received);
+                return false;
+            }
+        };
+        if self.correlation == Some(id) {
+            info!("Request {} cancelled by the client", id);
+            self.cancelled = true;
+            return true;
+        }
+        let queued = self
+            .queued_requests
+            .iter()
+            .position(|request| matches!(request, Ok((Some(queued), _)) if *queued == id));
+        match queued {
+            Some(position) => {
+                info!("Queued request {} cancelled by the client", id);
+                self.queued_requests.remove(position);
+                // A broken channel fails the response to the request served
+                if let Err(e) = self
+                    .message_channel
+                    .send_response_with_id(Some(id), &cancelled())
+                    .await
+                {
+                    error!("Failed to answer cancelled request {}: {}", id, e);
+                }
+            }
+            None => debug!("Request {} finished before its cancellation", id),
+        }
+        false
+    }
+
+    /// Handle a request, send its response, and record its size and duration
+    async fn serve_request(
+        &mut self,
+        id: Option<CorrelationId>,
+        request: ProtocolRequest,
+    ) -> Result<()> {
+        self.correlation = id;
+        self.cancelled = false;
+        let request_type = request.kind();
+        let long_poll = matches!(request, ProtocolRequest::PollData);
+        let request_size = self.message_channel.last_received_len();
+        let started = Instant::now();
+        self.streamed_len = 0;
+
+        #[cfg(feature = "fault-injection")]
+        match self.faults.take(request_type) {
+            Some(Fault::Delay { delay }) => tokio::time::sleep(delay).await,
+            Some(Fault::Drop) => {
+                warn!("Dropping {} request by an injected fault", request_type);
+                return Ok(());
+            }
+            Some(Fault::Error { message }) => {
+                return self
+                    .message_channel
+                    .send_response_with_id(
+                        id,
+                        &ProtocolResponse::error(ErrorCode::Internal, message),
+                    )
+                    .await
+                    .map_err(Into::into);
+            }
+            None => {}
+        }
+
+        let (entered, answered) = self
+            .middleware
+            .before(&self.context(request_type), &request);
+        let mut response = match answered {
+            Some(response) => response,
+            None => self.handle_request(request).await,
+        };
+        self.middleware.after(
+            entered,
+            &self.context(request_type),
+            &mut response,
+            started.elapsed(),
+        );
+        self.message_channel
+            .send_response_with_id(id, &response)
+            .await?;
+
+        let response_size = self.streamed_len + self.message_channel.last_sent_len();
+        self.meter.add_bytes((request_size + response_size) as u64);
+        self.record_request(
+            request_type,
+            request_size,
+            response_size,
+            started.elapsed(),
+            long_poll,
+        );
+        Ok(())
+    }
+
+    /// Record payload size metrics and log the request if it is slow or large
+    fn record_request(
+        &mut self,
+        request_type: &'static str,
+        request_size: usize,
+        response_size: usize,
+        duration: Duration,
+        long_poll: bool,
+    ) {
+        let labels = HashMap::from([("type".to_string(), request_type.to_string())]);
+        METRICS.record_histogram("request_bytes", request_size as f64, labels.clone());
+        METRICS.record_histogram("response_bytes", response_size as f64, labels);
+        METRICS.gauge(
+            "buffer_pool_hit_rate",
+            self.message_channel.buffer_pool_stats().hit_rate(),
+            HashMap::new(),
+        );
+
+        self.slow_log.observe(
+            SlowRequest {
+                request_type: request_type.to_string(),
+                peer: self.peer.clone(),
+                request_size,
+                response_size,
+                duration,
+                completed_at: SystemTime::now(),
+            },
+            long_poll,
+        );
+    }
+
+    fn context(&self, kind: &'static str) -> RequestContext<'_> {
+        RequestContext {
+            peer: &self.peer,
+            identity: self.identity.as_deref(),
+            kind,
+            negotiated: &self.negotiated,
+        }
+    }
+
+    /// Select the workspace named in a `Hello`, which cannot change later
+    fn select_workspace(&mut self, name: Option<&str>) -> Result<()> {
+        let selected = self.workspaces.select(name)?;
+        if let Some(current) = &self.workspace {
+            if selected.is_none_or(|selected| selected.name() != current.name()) {
+                bail!(
+                    "Workspace {} cannot change during a session",
+                    current.name()
+                );
+            }
+            return Ok(());
+        }
+        if let Some(workspace) = &selected {
+            info!(
+                "Session {} selected workspace {}",
+                self.peer,
+                workspace.name()
+            );
+            self.tasks = workspace.tasks(&self.peer);
+        }
+        self.workspace = selected;
+        Ok(())
+    }
+
+    /// Response refusing `request` when the workspace rules or the quotas do
+    fn refusal(&self, request: &ProtocolRequest) -> Option<ProtocolResponse> {
+        let refusal = match &self.workspace {
+            Some(workspace) => request
+                .paths()
+                .into_iter()
+                .find_map(|path| workspace.check_path(path).err())
+                .map(|e| format!("{:#}", e)),
+            None if !self.workspaces.is_empty()
+                && !matches!(request, ProtocolRequest::Hello { .. }) =>
+            {
+                Some("Select a workspace with Hello first".to_string())
+            }
+            None => None,
+        };
+        refusal
+            .or_else(|| {
+                usage::check(&self.usage(), request)
+                    .err()
+                    .map(|e| e.to_string())
+            })
+            .map(|message| ProtocolResponse::error(ErrorCode::PermissionDenied, message))
+    }
+
+    fn usage(&self) -> Usage {
+        self.meter.usage(self.identity.as_deref(), &self.quota)
+    }
+
+    /// Callback adding CPU time of launched applications to the meter
+    fn record_cpu(&self) -> impl Fn(Duration) + Send + 'static {
+        let meter = self.meter.clone();
+        move |cpu| meter.add_exec_cpu(cpu)
+    }
+
+    /// Clipboard of the selected workspace, or of this host
+    fn clipboard(&self) -> &ClipboardStore {
+        self.workspace
+            .as_ref()
+            .map_or(clipboard::system(), |workspace| workspace.clipboard())
+    }
+
+    /// Forwards in this session's namespace, by port
+    fn forwards(&self) -> Vec<PortForwardEntry> {
+        let mut forwards: Vec<_> = self
+            .tasks
+            .list()
+            .into_iter()
+            .filter_map(|task| match task.kind {
+                TaskKind::PortForward {
+                    local_port,
+                    remote_host,
+                    remote_port,
+                } => Some(PortForwardEntry {
+                    local_port,
+                    remote_host,
+                    remote_port,
+                }),
+                TaskKind::Connection { .. } => None,
+            })
+            .collect();
+        forwards.sort_by_key(|forward| forward.local_port);
+        forwards
+    }
+
     /// Handle a single request
     async fn handle_request(&mut self, request: ProtocolRequest) -> ProtocolResponse {
+        if let Some(refusal) = self.refusal(&request) {
+            return refusal;
+        }
         match request {
+            ProtocolRequest::Hello {
+                extensions,
+                workspace,
+            } => {
+                if let Err(e) = self.select_workspace(workspace.as_deref()) {
+                    return ProtocolResponse::error(ErrorCode::PermissionDenied, e.to_string());
+                }
+                self.negotiated = extension::negotiate(&extensions, &self.extensions);
+                info!("Negotiated extensions {:?}", self.negotiated);
+                if self.negotiated.contains(&extension::RESYNC.id) {
+                    self.message_channel.use_frame_magic();
+                }
+                if self.negotiated.contains(&extension::LARGE_FRAMES.id) {
+                    self.message_channel.use_long_frames();
+                }
+                if self.negotiated.contains(&extension::FRAGMENTS.id) {
+                    self.message_channel.use_fragments();
+                }
+                if let Some(compression) = Compression::preferred(&self.negotiated) {
+                    self.message_channel.use_compression(compression);
+                }
+                if let Some(checksum) = FrameChecksum::preferred(&self.negotiated) {
+                    self.message_channel.use_frame_checksum(checksum);
+                }
+                if codec::msgpack_negotiated(&self.negotiated) {
+                    self.message_channel.use_msgpack();
+                }
+                let extensions = ResponseItem::Extensions {
+                    extensions: self.negotiated.clone(),
+                };
+                let hash = binary_hash().map(|hash| ResponseItem::BinaryHash {
+                    hash: hash.to_string(),
+                });
+                let watch = self
+                    .negotiated
+                    .contains(&extension::CLIPBOARD_WATCH.id)
+                    .then(|| ResponseItem::ClipboardWatch {
+                        watch: self.watch_clipboard(),
+                    });
+                ProtocolResponse::Data {
+                    items: std::iter::once(extensions)
+                        .chain(hash)
+                        .chain(watch)
+                        .collect(),
+                    next: None,
+                }
+            }
             ProtocolRequest::PollData => {
                 let mut buffer = self.response_buffer.write().await;
                 let items = buffer.take_items();
@@ -159,11 +744,14 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> RemoteServer<T> {
                     // Long polling: wait for data
                     drop(buffer);
                     self.wait_for_data().await;
+                    if self.cancelled {
+                        return cancelled();
+                    }
                     let mut buffer = self.response_buffer.write().await;
                     let items = buffer.take_items();
-                    ProtocolResponse::Data { items }
+                    ProtocolResponse::Data { items, next: None }
                 } else {
-                    ProtocolResponse::Data { items }
+                    ProtocolResponse::Data { items, next: None }
                 }
             }
             ProtocolRequest::StartPortForward {
@@ -177,18 +765,231 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> RemoteServer<T> {
             ProtocolRequest::StopPortForward { local_port } => {
                 self.stop_port_forward(local_port).await
             }
+            ProtocolRequest::ReleasePortForward { local_port } => {
+                self.release_port_forward(local_port).await
+            }
             ProtocolRequest::PortForwardData {
                 connection_id,
                 data,
             } => self.forward_data(connection_id, data).await,
+            ProtocolRequest::GrantCredit {
+                connection_id,
+                bytes,
+            } => self.grant_credit(connection_id, bytes).await,
+            // Grants for a stream that ended meanwhile
+            ProtocolRequest::GrantBatches { .. } => ProtocolResponse::Success,
             ProtocolRequest::GetClipboard => self.get_clipboard().await,
             ProtocolRequest::SetClipboard { content } => self.set_clipboard(content).await,
+            ProtocolRequest::GetClipboardData { accept } => self.get_clipboard_data(&accept).await,
+            ProtocolRequest::SetClipboardData { items } => self.set_clipboard_data(items).await,
             ProtocolRequest::OpenBrowser { url } => self.open_browser(url).await,
+            ProtocolRequest::LaunchApp {
+                command,
+                display_env,
+                sandbox,
+            } => {
+                let sandbox = sandbox.within(&self.launch_sandbox);
+                let env = self.launch_env.clone();
+                let record_cpu = self.record_cpu();
+                self.cancellable(async move {
+                    apps::launch(
+                        &command,
+                        &display_env,
+                        &sandbox,
+                        &env,
+                        &apps::Session::current(),
+                        record_cpu,
+                    )
+                })
+                .await
+                .map_or_else(cancelled, launched)
+            }
+            ProtocolRequest::OpenPath { path } => {
+                let handlers = self.open_handlers.clone();
+                let sandbox = self.launch_sandbox.clone();
+                let env = self.launch_env.clone();
+                let record_cpu = self.record_cpu();
+                self.cancellable(async move {
+                    apps::open(&path, &handlers, &sandbox, &env, record_cpu).await
+                })
+                .await
+                .map_or_else(cancelled, launched)
+            }
+            ProtocolRequest::ListFiles { paths, query } => self.list_files(paths, query).await,
+            ProtocolRequest::ListDirectory { path } => self.list_directory(path).await,
+            ProtocolRequest::HashPath { path, algo } => self.hash_path(path, algo).await,
+            ProtocolRequest::DiskUsage { path, depth } => self.disk_usage(path, depth).await,
+            ProtocolRequest::ReadFileChunk { path, offset, len } => {
+                match self
+                    .cancellable(async move { files::read_chunk(&path, offset, len).await })
+                    .await
+                {
+                    Some(Ok(item)) => ProtocolResponse::Data {
+                        items: vec![item],
+                        next: None,
+                    },
+                    Some(Err(e)) => ProtocolResponse::failure("Failed to read file", &e),
+                    None => cancelled(),
+                }
+            }
+            ProtocolRequest::ReadFileRange { path, offset, len } => {
+                match self
+                    .cancellable(async move { files::read_range(&path, offset, len).await })
+                    .await
+                {
+                    Some(Ok(item)) => ProtocolResponse::Data {
+                        items: vec![item],
+                        next: None,
+                    },
+                    Some(Err(e)) => ProtocolResponse::failure("Failed to read file", &e),
+                    None => cancelled(),
+                }
+            }
+            ProtocolRequest::WriteFileChunk {
+                path,
+                offset,
+                data,
+                crc32c,
+                last,
+                expected_digest,
+            } => {
+                let target = path.clone();
+                let written = self
+                    .cancellable(async move {
+                        files::write_chunk(
+                            &target,
+                            offset,
+                            &data,
+                            crc32c,
+                            last,
+                            expected_digest.as_deref(),
+                        )
+                        .await
+                        .map(|()| offset + data.len() as u64)
+                    })
+                    .await;
+                match written {
+                    None => cancelled(),
+                    Some(Ok(bytes)) => {
+                        if last {
+                            self.publisher().publish(TopicEvent::FileWritten {
+                                path: path.clone(),
+                                bytes,
+                            });
+                            self.events.publish(Event::TransferCompleted {
+                                peer: self.peer.clone(),
+                                bytes,
+                                path,
+                            });
+                        }
+                        ProtocolResponse::Success
+                    }
+                    Some(Err(e)) if e.is::<files::Conflict>() => {
+                        ProtocolResponse::error(ErrorCode::Conflict, format!("{:#}", e))
+                    }
+                    Some(Err(e)) => ProtocolResponse::failure("Failed to write file", &e),
+                }
+            }
+            ProtocolRequest::RemovePath { path, trash } => self.remove_path(path, trash).await,
+            ProtocolRequest::MovePath { from, to, trash } => self.move_path(from, to, trash).await,
+            ProtocolRequest::RestorePath { id, to } => self.restore_path(id, to).await,
+            ProtocolRequest::ListTrash => self.list_trash().await,
+            ProtocolRequest::ListPortForwards { query } => self.list_port_forwards(&query).await,
+            ProtocolRequest::ProbeTools { tools } => ProtocolResponse::Data {
+                items: tools::probe(&tools)
+                    .await
+                    .into_iter()
+                    .map(|tool| ResponseItem::Tool { tool })
+                    .collect(),
+                next: None,
+            },
+            ProtocolRequest::ListTasks => ProtocolResponse::Data {
+                items: self
+                    .tasks
+                    .list()
+                    .into_iter()
+                    .map(|task| ResponseItem::Task { task })
+                    .collect(),
+                next: None,
+            },
+            ProtocolRequest::CancelTask { id } => self.cancel_task(id).await,
+            ProtocolRequest::GetUsage => ProtocolResponse::Data {
+                items: vec![ResponseItem::Usage {
+                    usage: self.usage(),
+                }],
+                next: None,
+            },
+            ProtocolRequest::ExportSessionState => self.export_session_state().await,
+            ProtocolRequest::ImportSessionState { state } => self.import_session_state(state).await,
+            ProtocolRequest::Command { name, args } => self.commands.dispatch(&name, args).await,
+            ProtocolRequest::GetSlowLog => ProtocolResponse::Data {
+                items: self
+                    .slow_log
+                    .entries()
+                    .into_iter()
+                    .map(|request| ResponseItem::SlowRequest { request })
+                    .collect(),
+                next: None,
+            },
+            ProtocolRequest::GetAccessLog => ProtocolResponse::Data {
+                items: self
+                    .access_log
+                    .entries()
+                    .into_iter()
+                    .map(|entry| ResponseItem::Access { entry })
+                    .collect(),
+                next: None,
+            },
+            ProtocolRequest::ServerReply { id, reply } => {
+                let replied = self
+                    .server_requests
+                    .as_mut()
+                    .is_some_and(|outbox| outbox.reply(id, reply));
+                if replied {
+                    ProtocolResponse::Success
+                } else {
+                    ProtocolResponse::error(
+                        ErrorCode::NotFound,
+                        format!("No server request {} awaits a reply", id),
+                    )
+                    .with_detail("id", id.to_string())
+                }
+            }
+            ProtocolRequest::Subscribe { topic } => {
+                self.subscribe(topic);
+                ProtocolResponse::Success
+            }
+            ProtocolRequest::Unsubscribe { topic } => {
+                if let Some(subscriber) = &mut self.subscriber {
+                    subscriber.unsubscribe(topic);
+                }
+                ProtocolResponse::Success
+            }
+            #[cfg(feature = "fault-injection")]
+            ProtocolRequest::InjectFault { kind, fault, count } => {
+                info!(
+                    "Injecting {:?} into the next {} {} requests",
+                    fault, count, kind
+                );
+                self.faults.arm(kind, fault, count);
+                ProtocolResponse::Success
+            }
+            ProtocolRequest::Unknown { request_name } => {
+                debug!("Unsupported request {}", request_name);
+                ProtocolResponse::Unsupported { request_name }
+            }
         }
     }
 
     /// Wait for data with timeout for long polling
-    async fn wait_for_data(&self) {
+    ///
+    /// A control request arriving meanwhile is answered while the poll keeps
+    /// waiting, so control never stalls behind port forward data; the data
+    /// itself is paced by the credit of `flow-control`. Clients answered in
+    /// order cannot take a reply overtaking the poll, so without correlation
+    /// ids, like for data requests and cancellations, the request ends the
+    /// wait early and is queued.
+    async fn wait_for_data(&mut self) {
         // Long polling: wait up to 5 seconds for data
         // This balances low latency with reasonable timeout behavior
         const LONG_POLL_TIMEOUT: tokio::time::Duration = tokio::time::Duration::from_secs(5);
@@ -203,12 +1004,304 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> RemoteServer<T> {
                     return;
                 }
             }
-            // Sleep briefly to avoid excessive lock contention
-            tokio::time::sleep(POLL_INTERVAL).await;
+            // Sleep briefly to avoid excessive lock contention; receiving is
+            // cancel safe, so the sleep winning loses nothing
+            tokio::select! {
+                received = self.message_channel.receive_request_with_id() => match received {
+                    Ok((Some(id), request)) if self.correlation.is_some() && !request.is_data() => {
+                        if let Err(e) = self.serve_beside_poll(id, request).await {
+                            error!("Failed to send response: {}", e);
+                            return;
+                        }
+                    }
+                    received => {
+                        self.receive_ahead(received).await;
+                        return;
+                    }
+                },
+                (id, request) = next_server_request(&mut self.server_requests) => {
+                    if let Err(e) = self.send_server_request(id, request).await {
+                        error!("Failed to send server request {}: {}", id, e);
+                        return;
+                    }
+                }
+                event = next_event(&mut self.subscriber) => {
+                    if let Err(e) = self.send_event(event).await {
+                        error!("Failed to push event: {}", e);
+                        return;
+                    }
+                }
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
         }
         // Timeout is normal for long polling - client will retry
     }
 
+    /// Serve a control request while a long poll waits, restoring the poll's
+    /// state afterwards
+    async fn serve_beside_poll(
+        &mut self,
+        id: CorrelationId,
+        request: ProtocolRequest,
+    ) -> Result<()> {
+        let poll = (self.correlation, self.cancelled, self.streamed_len);
+        let served = Box::pin(self.serve_request(Some(id), request)).await;
+        (self.correlation, self.cancelled, self.streamed_len) = poll;
+        served
+    }
+
+    /// List files, streaming batches to the client while the walk continues
+    async fn list_files(&mut self, paths: Vec<String>, query: ListQuery) -> ProtocolResponse {
+        let (entries_tx, entries_rx) = mpsc::channel(BATCH_QUEUE_LEN);
+        let walk =
+            tokio::spawn(async move { files::list_files(&paths, &query, &entries_tx).await });
+        self.stream_walk(entries_rx, walk, "list files").await
+    }
+
+    async fn list_directory(&mut self, path: String) -> ProtocolResponse {
+        let (entries_tx, entries_rx) = mpsc::channel(BATCH_QUEUE_LEN);
+        let walk = tokio::spawn(async move { files::list_directory(&path, &entries_tx).await });
+        self.stream_walk(entries_rx, walk, "list the directory")
+            .await
+    }
+
+    async fn hash_path(&mut self, path: String, algo: HashAlgorithm) -> ProtocolResponse {
+        let (hashes_tx, hashes_rx) = mpsc::channel(BATCH_QUEUE_LEN);
+        let walk = tokio::spawn(async move { files::hash_path(&path, algo, &hashes_tx).await });
+        self.stream_walk(hashes_rx, walk, "hash files").await
+    }
+
+    async fn disk_usage(&mut self, path: String, depth: u32) -> ProtocolResponse {
+        let (items_tx, items_rx) = mpsc::channel(BATCH_QUEUE_LEN);
+        let walk = tokio::spawn(async move { files::disk_usage(&path, depth, &items_tx).await });
+        self.stream_walk(items_rx, walk, "scan disk usage").await
+    }
+
+    /// Remove `path`, into the trash if asked to
+    async fn remove_path(&self, path: String, trash: bool) -> ProtocolResponse {
+        let removed = TopicEvent::PathRemoved { path: path.clone() };
+        if !trash {
+            return match files::remove_path(&path).await {
+                Ok(()) => {
+                    self.publisher().publish(removed);
+                    ProtocolResponse::Success
+                }
+                Err(e) => ProtocolResponse::failure("Failed to remove", &e),
+            };
+        }
+        let trashed = self.in_trash(move |trash| trash.trash(&path)).await;
+        if trashed.is_ok() {
+            self.publisher().publish(removed);
+        }
+        match trashed {
+            Ok(entry) => ProtocolResponse::Data {
+                items: vec![ResponseItem::Trashed { entry }],
+                next: None,
+            },
+            Err(e) => ProtocolResponse::failure("Failed to trash", &e),
+        }
+    }
+
+    /// Move `from` to `to`, trashing what it replaces if asked to
+    async fn move_path(&self, from: String, to: String, trash: bool) -> ProtocolResponse {
+        let replaced = trash && tokio::fs::symlink_metadata(&to).await.is_ok();
+        let event = TopicEvent::PathMoved {
+            from: from.clone(),
+            to: to.clone(),
+        };
+        let moved = self
+            .in_trash(move |trash| {
+                let entry = replaced.then(|| trash.trash(&to)).transpose()?;
+                files::move_tree(Path::new(&from), Path::new(&to))?;
+                Ok(entry)
+            })
+            .await;
+        if moved.is_ok() {
+            self.publisher().publish(event);
+        }
+        match moved {
+            Ok(Some(entry)) => ProtocolResponse::Data {
+                items: vec![ResponseItem::Trashed { entry }],
+                next: None,
+            },
+            Ok(None) => ProtocolResponse::Success,
+            Err(e) => ProtocolResponse::failure("Failed to move", &e),
+        }
+    }
+
+    /// Restore a trash entry, which a workspace session may only do inside
+    /// the workspace
+    async fn restore_path(&self, id: String, to: Option<String>) -> ProtocolResponse {
+        let workspace = self.workspace.clone();
+        let restored = self
+            .in_trash(move |trash| {
+                if let Some(workspace) = &workspace
+                    && to.is_none()
+                {
+                    workspace.check_path(&trash.entry(&id)?.original_path)?;
+                }
+                let entry = trash.restore(&id, to.as_deref())?;
+                Ok(to.unwrap_or(entry.original_path))
+            })
+            .await;
+        match restored {
+            Ok(path) => {
+                self.publisher().publish(TopicEvent::PathRestored { path });
+                ProtocolResponse::Success
+            }
+            Err(e) => ProtocolResponse::failure("Failed to restore", &e),
+        }
+    }
+
+    /// List the trash entries, of the workspace in a workspace session
+    async fn list_trash(&self) -> ProtocolResponse {
+        let workspace = self.workspace.clone();
+        let listed = self
+            .in_trash(move |trash| {
+                let mut entries = trash.list()?;
+                if let Some(workspace) = &workspace {
+                    entries.retain(|entry| workspace.check_path(&entry.original_path).is_ok());
+                }
+                Ok(entries)
+            })
+            .await;
+        match listed {
+            Ok(entries) => ProtocolResponse::Data {
+                items: entries
+                    .into_iter()
+                    .map(|entry| ResponseItem::Trashed { entry })
+                    .collect(),
+                next: None,
+            },
+            Err(e) => ProtocolResponse::failure("Failed to list the trash", &e),
+        }
+    }
+
+    /// Run `f` on the trash on a blocking thread
+    async fn in_trash<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Trash) -> Result<R> + Send + 'static,
+    ) -> Result<R> {
+        let trash = self.trash.clone();
+        tokio::task::spawn_blocking(move || f(&trash)).await?
+    }
+
+    /// Stream the items a walk of the file system finds in `Batch` responses,
+    /// aborting the walk once the request is cancelled
+    async fn stream_walk(
+        &mut self,
+        items: mpsc::Receiver<ResponseItem>,
+        walk: tokio::task::JoinHandle<Result<()>>,
+        what: &str,
+    ) -> ProtocolResponse {
+        let streamed = self.stream_batches(items).await;
+        if self.cancelled {
+            walk.abort();
+            return cancelled();
+        }
+        match (streamed, walk.await) {
+            (Ok(batcher), Ok(Ok(()))) => batcher.finish(),
+            (Err(e), _) => ProtocolResponse::failure("Failed to stream the results", &e),
+            (_, Ok(Err(e))) => ProtocolResponse::failure(&format!("Failed to {}", what), &e),
+            (_, Err(e)) => ProtocolResponse::error(
+                ErrorCode::Internal,
+                format!("Task to {} failed: {}", what, e),
+            ),
+        }
+    }
+
+    /// Run `work` as a task, queueing requests arriving meanwhile, and abort
+    /// it once the request is cancelled
+    ///
+    /// Returns `None` when cancelled. Work must not be left half done by an
+    /// abort at any await point.
+    async fn cancellable<R: Send + 'static>(
+        &mut self,
+        work: impl Future<Output = Result<R>> + Send + 'static,
+    ) -> Option<Result<R>> {
+        let mut task = tokio::spawn(work);
+        loop {
+            tokio::select! {
+                done = &mut task => {
+                    return Some(done.context("The task serving the request failed").and_then(|r| r));
+                }
+                received = self.message_channel.receive_request_with_id(),
+                    if self.reading_ahead() =>
+                {
+                    if self.receive_ahead(received).await {
+                        task.abort();
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send items in `Batch` responses as they arrive, returning the batcher
+    /// holding the items for the final batch
+    ///
+    /// With `flow-control`, batches go out only within the credit the client
+    /// granted for this request, and the walk waits meanwhile. Requests
+    /// arriving meanwhile are queued; a cancellation of this one stops the
+    /// stream.
+    async fn stream_batches(
+        &mut self,
+        mut items: mpsc::Receiver<ResponseItem>,
+    ) -> Result<ResponseBatcher> {
+        let credit = if self.negotiated.contains(&extension::FLOW_CONTROL.id) {
+            Credit::new(INITIAL_BATCH_CREDIT)
+        } else {
+            Credit::unlimited()
+        };
+        let mut batcher = ResponseBatcher::default();
+        let mut waiting = VecDeque::new();
+        loop {
+            // The batches the credit allows go out with one write
+            self.message_channel.cork();
+            while !waiting.is_empty() && credit.consume(1) {
+                if let Some(batch) = waiting.pop_front() {
+                    self.message_channel
+                        .send_response_with_id(self.correlation, &batch)
+                        .await?;
+                    self.streamed_len += self.message_channel.last_sent_len();
+                }
+            }
+            self.message_channel.uncork().await?;
+            tokio::select! {
+                item = items.recv(), if waiting.is_empty() => {
+                    let Some(item) = item else { break };
+                    // Progress is stale once the batch fills up, so it goes
+                    // out at once
+                    let urgent = matches!(item, ResponseItem::DiskUsageProgress { .. });
+                    let full = batcher.push(item)?;
+                    let flushed = if urgent { batcher.flush() } else { None };
+                    waiting.extend(full.into_iter().chain(flushed));
+                }
+                received = self.message_channel.receive_request_with_id(),
+                    if self.reading_ahead() =>
+                {
+                    match received {
+                        Ok((grant, ProtocolRequest::GrantBatches { id, batches }))
+                            if self.correlation == Some(id) =>
+                        {
+                            credit.grant(batches as usize);
+                            self.message_channel
+                                .send_response_with_id(grant, &ProtocolResponse::Success)
+                                .await?;
+                        }
+                        received => {
+                            if self.receive_ahead(received).await {
+                                break;
+                            }
+                        }
+                    }
+                }
+                else => bail!("The connection failed while the stream waited for credit"),
+            }
+        }
+        Ok(batcher)
+    }
+
     /// Start port forwarding
     async fn start_port_forward(
         &self,
@@ -221,16 +1314,29 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> RemoteServer<T> {
             local_port, remote_host, remote_port
         );
 
+        if let Err(e) = usage::check_forwards(&self.usage()) {
+            return ProtocolResponse::error(ErrorCode::PermissionDenied, e.to_string());
+        }
+
         // Start a TCP listener for this port
         let listener_addr = format!("0.0.0.0:{}", local_port);
         match tokio::net::TcpListener::bind(&listener_addr).await {
             Ok(listener) => {
+                self.meter.add_forward();
                 let response_buffer = self.response_buffer.clone();
                 let active_connections = self.active_connections.clone();
                 let next_connection_id = self.next_connection_id.clone();
+                let tasks = self.tasks.clone();
+                let publisher = self.publisher();
+                let flow_control = self.negotiated.contains(&extension::FLOW_CONTROL.id);
+                let kind = TaskKind::PortForward {
+                    local_port,
+                    remote_host: remote_host.clone(),
+                    remote_port,
+                };
 
                 // Spawn task to handle incoming connections
-                tokio::spawn(async move {
+                self.tasks.spawn(kind, async move {
                     loop {
                         match listener.accept().await {
                             Ok((client_stream, addr)) => {
@@ -249,23 +1355,39 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> RemoteServer<T> {
                                 let target_addr = format!("{}:{}", remote_host, remote_port);
                                 let response_buffer_clone = response_buffer.clone();
                                 let active_connections_clone = active_connections.clone();
+                                let publisher = publisher.clone();
 
                                 // Handle each connection in a separate task
-                                tokio::spawn(async move {
+                                let kind = TaskKind::Connection {
+                                    connection_id,
+                                    local_port,
+                                };
+                                tasks.spawn(kind, async move {
                                     // Notify client of new connection
                                     {
                                         let mut buffer = response_buffer_clone.write().await;
                                         buffer.add_new_connection(connection_id, local_port);
                                     }
+                                    publisher.publish(TopicEvent::ConnectionOpened {
+                                        connection_id,
+                                        local_port,
+                                    });
 
                                     // Connect to target server
                                     match tokio::net::TcpStream::connect(&target_addr).await {
                                         Ok(target_stream) => {
                                             let (tx, rx) = mpsc::unbounded_channel();
+                                            let flow = Arc::new(forward::Flow::new(flow_control));
                                             {
                                                 let mut connections =
                                                     active_connections_clone.write().await;
-                                                connections.insert(connection_id, tx);
+                                                connections.insert(
+                                                    connection_id,
+                                                    forward::RelayHandle {
+                                                        data: tx,
+                                                        flow: flow.clone(),
+                                                    },
+                                                );
                                             }
 
                                             Self::handle_connection(
@@ -275,6 +1397,7 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> RemoteServer<T> {
                                                 response_buffer_clone,
                                                 active_connections_clone,
                                                 rx,
+                                                flow,
                                             )
                                             .await;
                                         }
@@ -284,6 +1407,8 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> RemoteServer<T> {
                                             buffer.add_close_connection(connection_id);
                                         }
                                     }
+                                    publisher
+                                        .publish(TopicEvent::ConnectionClosed { connection_id });
                                 });
                             }
                             Err(e) => {
@@ -296,68 +1421,317 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> RemoteServer<T> {
 
                 ProtocolResponse::Success
             }
-            Err(e) => ProtocolResponse::Error {
-                message: format!("Failed to bind to {}: {}", listener_addr, e),
-            },
+            Err(e) => ProtocolResponse::error(
+                ErrorCode::from_io(e.kind()),
+                format!("Failed to bind to {}: {}", listener_addr, e),
+            ),
         }
     }
 
+    /// List active port forwards in the page selected by `query`
+    async fn list_port_forwards(&self, query: &ListQuery) -> ProtocolResponse {
+        let mut pager = query.pager();
+        let items = self
+            .forwards()
+            .into_iter()
+            .filter(|forward| {
+                pager.accept(&format!("{}:{}", forward.remote_host, forward.remote_port))
+            })
+            .map(|forward| ResponseItem::PortForward { forward })
+            .collect();
+        ProtocolResponse::Data {
+            items,
+            next: pager.next_cursor(),
+        }
+    }
+
+    /// Snapshot the forwards and clipboard content of this session
+    async fn export_session_state(&self) -> ProtocolResponse {
+        let state = SessionState {
+            forwards: self.forwards(),
+            topics: self
+                .subscriber
+                .as_ref()
+                .map(Subscriber::topics)
+                .unwrap_or_default(),
+        };
+        ProtocolResponse::Data {
+            items: vec![ResponseItem::SessionState { state }],
+            next: None,
+        }
+    }
+
+    /// Start the forwards of `state` not running yet, replacing those on
+    /// the same port with another target, and subscribe to its topics
+    async fn import_session_state(&mut self, state: SessionState) -> ProtocolResponse {
+        let active = self.forwards();
+        let mut failures = Vec::new();
+        for forward in state.missing_forwards(&active) {
+            self.stop_port_forward(forward.local_port).await;
+            let started = self
+                .start_port_forward(
+                    forward.local_port,
+                    forward.remote_host.clone(),
+                    forward.remote_port,
+                )
+                .await;
+            if let ProtocolResponse::Error { message, .. } = started {
+                failures.push(message);
+            }
+        }
+
+        for topic in state.topics {
+            self.subscribe(topic);
+        }
+
+        if failures.is_empty() {
+            ProtocolResponse::Success
+        } else {
+            ProtocolResponse::error(ErrorCode::Internal, failures.join("; "))
+        }
+    }
+
+    /// Push events of `topic` to the client
+    fn subscribe(&mut self, topic: Topic) {
+        let workspace = self.workspace.as_ref().map(|workspace| workspace.name());
+        self.subscriber
+            .get_or_insert_with(|| self.topics.subscriber(workspace))
+            .subscribe(topic);
+    }
+
     /// Stop port forwarding
     async fn stop_port_forward(&self, local_port: u16) -> ProtocolResponse {
         info!("Stopping port forward for port {}", local_port);
+        self.cancel_tasks(|task| task.kind.local_port() == local_port)
+            .await;
+        ProtocolResponse::Success
+    }
+
+    /// Stop listening on `local_port`, leaving the connections accepted
+    /// before running until they close
+    async fn release_port_forward(&self, local_port: u16) -> ProtocolResponse {
+        info!("Releasing port {}", local_port);
+        self.cancel_tasks(|task| {
+            matches!(task.kind, TaskKind::PortForward { local_port: port, .. } if port == local_port)
+        })
+        .await;
+        ProtocolResponse::Success
+    }
 
-        // Close all connections for this port
-        let mut connections = self.active_connections.write().await;
-        let mut to_remove = Vec::new();
-        for (&connection_id, _) in connections.iter() {
-            to_remove.push(connection_id);
+    /// Cancel the background task `id`
+    async fn cancel_task(&self, id: TaskId) -> ProtocolResponse {
+        if self.cancel_tasks(|task| task.id == id).await.is_empty() {
+            return ProtocolResponse::error(ErrorCode::NotFound, format!("No task {}", id))
+                .with_detail("task", id.to_string());
         }
+        ProtocolResponse::Success
+    }
 
-        for connection_id in to_remove {
-            connections.remove(&connection_id);
+    /// Cancel the tasks matching `predicate` and release what they held
+    ///
+    /// Connections of other sessions of the workspace are released by their
+    /// own session, whose `forward_data` finds them closed.
+    async fn cancel_tasks(&self, predicate: impl Fn(&TaskInfo) -> bool) -> Vec<TaskInfo> {
+        let cancelled = self.tasks.cancel_where(predicate);
+        for task in cancelled.iter().filter(|task| task.session == self.peer) {
+            if let TaskKind::Connection { connection_id, .. } = task.kind {
+                self.active_connections.write().await.remove(&connection_id);
+                let mut buffer = self.response_buffer.write().await;
+                buffer.add_close_connection(connection_id);
+                self.publisher()
+                    .publish(TopicEvent::ConnectionClosed { connection_id });
+            }
+        }
+        cancelled
+    }
+
+    /// Forward data to connection
+    async fn forward_data(&self, connection_id: u32, data: Bytes) -> ProtocolResponse {
+        let connections = self.active_connections.read().await;
+        let Some(relay) = connections.get(&connection_id) else {
+            return ProtocolResponse::Success;
+        };
+        if !relay.flow.inbound.consume(data.len()) {
+            return ProtocolResponse::error(
+                ErrorCode::Internal,
+                format!("Connection {} exceeded its credit", connection_id),
+            );
+        }
+        if let Err(e) = relay.data.send(data) {
+            warn!("Failed to send data to connection {}: {}", connection_id, e);
+            drop(connections);
+            self.active_connections.write().await.remove(&connection_id);
             let mut buffer = self.response_buffer.write().await;
             buffer.add_close_connection(connection_id);
+            self.publisher()
+                .publish(TopicEvent::ConnectionClosed { connection_id });
+            return ProtocolResponse::error(
+                ErrorCode::Internal,
+                format!("Failed to send data: {}", e),
+            );
         }
+        ProtocolResponse::Success
+    }
 
+    /// Let a connection send more of its target's data to the client
+    ///
+    /// Grants for connections that closed meanwhile are ignored.
+    async fn grant_credit(&self, connection_id: u32, bytes: u32) -> ProtocolResponse {
+        if let Some(relay) = self.active_connections.read().await.get(&connection_id) {
+            relay.flow.outbound.grant(bytes as usize);
+        }
         ProtocolResponse::Success
     }
 
-    /// Forward data to connection
-    async fn forward_data(&self, connection_id: u32, data: Bytes) -> ProtocolResponse {
-        let connections = self.active_connections.read().await;
-        if let Some(sender) = connections.get(&connection_id) {
-            if let Err(e) = sender.send(data) {
-                warn!("Failed to send data to connection {}: {}", connection_id, e);
-                return ProtocolResponse::Error {
-                    message: format!("Failed to send data: {}", e),
-                };
+    /// Push watched copies to the client from now on, returning how the
+    /// clipboard is watched
+    fn watch_clipboard(&mut self) -> ClipboardWatch {
+        let Some(watcher) = &self.clipboard_watcher else {
+            return ClipboardWatch::OnRead;
+        };
+        if self.clipboard_pushes.is_none() {
+            let mut changes = watcher.subscribe();
+            let workspace = self.workspace.clone();
+            let response_buffer = self.response_buffer.clone();
+            let pushes = tokio::spawn(async move {
+                loop {
+                    let text = match changes.recv().await {
+                        Ok(text) => text,
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            debug!("Skipped {} clipboard changes", skipped);
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return,
+                    };
+                    let clipboard = workspace
+                        .as_ref()
+                        .map_or(clipboard::system(), |workspace| workspace.clipboard());
+                    // Text the client set comes back from tmux; no need to
+                    // announce it
+                    if clipboard
+                        .text()
+                        .is_ok_and(|current| current.as_deref() == Some(text.as_str()))
+                    {
+                        continue;
+                    }
+                    if let Err(e) = clipboard.set_text(&text) {
+                        warn!("Failed to take the tmux buffer: {}", e);
+                    }
+                    response_buffer.write().await.add_clipboard_content(text);
+                }
+            });
+            self.clipboard_pushes = Some(pushes.abort_handle());
+        }
+        watcher.mode()
+    }
+
+    /// Take text copied in tmux since the last sync into the clipboard
+    async fn sync_from_tmux(&self) {
+        let copied = match (&self.clipboard_watcher, &self.tmux) {
+            (Some(watcher), _) => {
+                watcher.activity();
+                watcher.check().await
+            }
+            (None, Some(tmux)) => tmux.copied().await,
+            (None, None) => return,
+        };
+        match copied {
+            Ok(Some(text)) => {
+                if let Err(e) = self.clipboard().set_text(&text) {
+                    warn!("Failed to take the tmux buffer: {}", e);
+                }
             }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to read tmux buffers: {:#}", e),
+        }
+    }
+
+    /// Put clipboard text in a tmux buffer
+    async fn sync_to_tmux(&self, text: &str) {
+        if let Some(watcher) = &self.clipboard_watcher {
+            watcher.activity();
+        }
+        if let Some(tmux) = &self.tmux
+            && !text.is_empty()
+            && let Err(e) = tmux.paste(text).await
+        {
+            warn!("Failed to set tmux buffer: {:#}", e);
         }
-        ProtocolResponse::Success
     }
 
     /// Get clipboard content
     async fn get_clipboard(&self) -> ProtocolResponse {
-        match clipboard::get_clipboard() {
+        self.sync_from_tmux().await;
+        match self.clipboard().text() {
             Ok(content) => {
+                // Content without text, like an image, reads as empty; it is
+                // served by GetClipboardData instead
                 let mut buffer = self.response_buffer.write().await;
-                buffer.add_clipboard_content(content);
+                buffer.add_clipboard_content(content.unwrap_or_default());
                 let items = buffer.take_items();
-                ProtocolResponse::Data { items }
+                ProtocolResponse::Data { items, next: None }
             }
-            Err(e) => ProtocolResponse::Error {
-                message: format!("Failed to get clipboard: {}", e),
-            },
+            Err(e) => ProtocolResponse::error(
+                ErrorCode::Internal,
+                format!("Failed to get clipboard: {}", e),
+            ),
         }
     }
 
     /// Set clipboard content
     async fn set_clipboard(&self, content: String) -> ProtocolResponse {
-        match clipboard::set_clipboard(&content) {
-            Ok(()) => ProtocolResponse::Success,
-            Err(e) => ProtocolResponse::Error {
-                message: format!("Failed to set clipboard: {}", e),
+        match self.clipboard().set_text(&content) {
+            Ok(()) => {
+                self.sync_to_tmux(&content).await;
+                self.publisher().publish(TopicEvent::ClipboardChanged {
+                    text: Some(content),
+                });
+                ProtocolResponse::Success
+            }
+            Err(e) => ProtocolResponse::error(
+                ErrorCode::Internal,
+                format!("Failed to set clipboard: {}", e),
+            ),
+        }
+    }
+
+    /// Get clipboard content negotiated to one of the accepted formats
+    async fn get_clipboard_data(&self, accept: &[ClipboardFormat]) -> ProtocolResponse {
+        self.sync_from_tmux().await;
+        match self.clipboard().item(accept) {
+            Ok(item) => ProtocolResponse::Data {
+                items: item
+                    .map(|item| ResponseItem::ClipboardData { item })
+                    .into_iter()
+                    .collect(),
+                next: None,
             },
+            Err(e) => ProtocolResponse::error(
+                ErrorCode::Internal,
+                format!("Failed to get clipboard: {}", e),
+            ),
+        }
+    }
+
+    /// Set clipboard content with multiple representations
+    async fn set_clipboard_data(&self, items: Vec<ClipboardItem>) -> ProtocolResponse {
+        let text = items
+            .iter()
+            .find(|item| item.format == ClipboardFormat::Text)
+            .and_then(|item| String::from_utf8(item.data.to_vec()).ok());
+        match self.clipboard().set_items(items) {
+            Ok(()) => {
+                if let Some(text) = &text {
+                    self.sync_to_tmux(text).await;
+                }
+                self.publisher()
+                    .publish(TopicEvent::ClipboardChanged { text });
+                ProtocolResponse::Success
+            }
+            Err(e) => ProtocolResponse::error(
+                ErrorCode::Internal,
+                format!("Failed to set clipboard: {}", e),
+            ),
         }
     }
 
@@ -365,83 +1739,32 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> RemoteServer<T> {
     async fn open_browser(&self, url: String) -> ProtocolResponse {
         match browser::open_url(&url).await {
             Ok(()) => ProtocolResponse::Success,
-            Err(e) => ProtocolResponse::Error {
-                message: format!("Failed to open browser: {}", e),
-            },
+            Err(e) => ProtocolResponse::error(
+                ErrorCode::Internal,
+                format!("Failed to open browser: {}", e),
+            ),
         }
     }
 
     /// Handle a single connection between client and target server
     async fn handle_connection(
-        mut client_stream: tokio::net::TcpStream,
-        mut target_stream: tokio::net::TcpStream,
+        client_stream: tokio::net::TcpStream,
+        target_stream: tokio::net::TcpStream,
         connection_id: u32,
         response_buffer: Arc<RwLock<ResponseBuffer>>,
-        active_connections: Arc<RwLock<HashMap<u32, mpsc::UnboundedSender<Bytes>>>>,
-        mut data_rx: mpsc::UnboundedReceiver<Bytes>,
+        active_connections: Arc<RwLock<HashMap<u32, forward::RelayHandle>>>,
+        data_rx: mpsc::UnboundedReceiver<Bytes>,
+        flow: Arc<forward::Flow>,
     ) {
-        let mut client_buf = [0; 4096];
-        let mut target_buf = [0; 4096];
-
-        loop {
-            tokio::select! {
-                // Read from client and write to target
-                result = client_stream.read(&mut client_buf) => {
-                    match result {
-                        Ok(0) => {
-                            break;
-                        }
-                        Ok(n) => {
-                            if let Err(e) = target_stream.write_all(&client_buf[..n]).await {
-                                error!("Error writing to target for connection {}: {}", connection_id, e);
-                                break;
-                            }
-                        }
-                        Err(e) => {
-                            error!("Error reading from client for connection {}: {}", connection_id, e);
-                            break;
-                        }
-                    }
-                }
-                // Read from target and write to client
-                result = target_stream.read(&mut target_buf) => {
-                    match result {
-                        Ok(0) => {
-                            break;
-                        }
-                        Ok(n) => {
-                            if let Err(e) = client_stream.write_all(&target_buf[..n]).await {
-                                error!("Error writing to client for connection {}: {}", connection_id, e);
-                                break;
-                            }
-                            // Also buffer data for client polling
-                            {
-                                let mut buffer = response_buffer.write().await;
-                                buffer.add_port_forward_data(connection_id, Bytes::copy_from_slice(&target_buf[..n]));
-                            }
-                        }
-                        Err(e) => {
-                            error!("Error reading from target for connection {}: {}", connection_id, e);
-                            break;
-                        }
-                    }
-                }
-                // Handle data from client via request
-                data = data_rx.recv() => {
-                    match data {
-                        Some(data) => {
-                            if let Err(e) = target_stream.write_all(&data).await {
-                                error!("Error writing client data to target for connection {}: {}", connection_id, e);
-                                break;
-                            }
-                        }
-                        None => {
-                            break;
-                        }
-                    }
-                }
-            }
-        }
+        forward::relay(
+            client_stream,
+            target_stream,
+            connection_id,
+            response_buffer.clone(),
+            data_rx,
+            flow,
+        )
+        .await;
 
         // Clean up
         {
@@ -471,9 +1794,198 @@ struct Args {
     stdio: bool,
 
     /// IPC socket path
-    #[arg(long)]
+    #[arg(long, env = "YUHA_IPC_SOCKET")]
     ipc_socket: Option<PathBuf>,
 
+    /// Shared token required from TCP clients (falls back to YUHA_AUTH_TOKEN)
+    #[arg(long)]
+    auth_token: Option<String>,
+
+    /// Encrypt TCP sessions with Noise using the private key in this file,
+    /// generated on first use
+    #[arg(long, value_name = "PATH")]
+    noise_key: Option<PathBuf>,
+
+    /// Public key of a client allowed to connect with Noise, as hex
+    /// (repeatable)
+    #[arg(long = "noise-peer", value_name = "KEY", requires = "noise_key")]
+    noise_peers: Vec<NoisePublicKey>,
+
+    /// Seal the --noise-key file to this ssh-agent key, as an OpenSSH
+    /// public key, e.g. of a PKCS#11 token loaded with `ssh-add -s`
+    #[arg(long, value_name = "PUBKEY", requires = "noise_key")]
+    noise_key_sealed_to: Option<AgentKey>,
+
+    /// Public key of a Noise peer allowed admin requests such as reading
+    /// the access log (repeatable); others are refused them
+    #[arg(long = "admin-key", value_name = "KEY", requires = "noise_key")]
+    admin_keys: Vec<NoisePublicKey>,
+
+    /// Log requests taking at least this many milliseconds to the slow log
+    #[arg(long)]
+    slow_request_ms: Option<u64>,
+
+    /// Log requests whose request or response payload reaches this many bytes
+    #[arg(long)]
+    slow_payload_bytes: Option<usize>,
+
+    /// Number of entries kept in the slow log
+    #[arg(long)]
+    slow_log_size: Option<usize>,
+
+    /// Keep binary data inside JSON responses instead of attachment frames,
+    /// making captured traffic fully human-readable
+    #[arg(long)]
+    inline_binary: bool,
+
+    /// Refuse to negotiate the named protocol extension (repeatable)
+    #[arg(long = "disable-extension", value_name = "NAME")]
+    disabled_extensions: Vec<String>,
+
+    /// Open files with this extension using COMMAND for `OpenPath`, e.g.
+    /// `rs=code --wait` (repeatable); `{path}` marks where the path goes
+    #[arg(long = "open-handler", value_name = "EXT=COMMAND")]
+    open_handlers: Vec<String>,
+
+    /// Refuse requests beyond this many per second (long polls and
+    /// forwarded data excepted), allowing bursts of the same size
+    #[arg(long, value_name = "N")]
+    rate_limit: Option<u32>,
+
+    /// Log every request with its peer and outcome to the `yuha::audit` target
+    #[arg(long)]
+    audit: bool,
+
+    /// Only launch this program, as named in the request (repeatable);
+    /// refused launches are logged to the `yuha::audit` target
+    #[arg(long = "allow-command", value_name = "PROGRAM")]
+    allowed_commands: Vec<String>,
+
+    /// Only launch programs matching this regular expression as a whole
+    /// (repeatable), in addition to those of `--allow-command`
+    #[arg(long = "allow-command-regex", value_name = "REGEX")]
+    allowed_command_regexes: Vec<String>,
+
+    /// Host the workspace NAME confined to ROOT, e.g. `web=/srv/web`
+    /// (repeatable; a name given again adds a root). Clients must then select
+    /// a workspace, and TCP mode serves any number of clients
+    #[arg(long = "workspace", value_name = "NAME=ROOT")]
+    workspaces: Vec<String>,
+
+    /// Refuse requests once this many request and response bytes were
+    /// exchanged by a client; clients without a Noise key share the quotas
+    #[arg(long, value_name = "BYTES")]
+    quota_bytes: Option<u64>,
+
+    /// Refuse to start more than this many port forwards per client
+    #[arg(long, value_name = "N")]
+    quota_forwards: Option<u64>,
+
+    /// Refuse to launch applications once those of a client, and the
+    /// processes they started, used this much CPU time
+    #[arg(long, value_name = "SECONDS")]
+    quota_exec_cpu: Option<u64>,
+
+    /// Start usage over every SECONDS, so quotas limit usage per period
+    /// rather than over the server's lifetime
+    #[arg(long, value_name = "SECONDS")]
+    quota_window: Option<u64>,
+
+    /// Launch applications as this user; the server must run as root
+    #[arg(long, value_name = "USER")]
+    launch_user: Option<String>,
+
+    /// Limit each launched application to this percentage of one core
+    #[arg(long, value_name = "PERCENT")]
+    launch_cpu_percent: Option<u32>,
+
+    /// Limit the memory of each launched application
+    #[arg(long, value_name = "BYTES")]
+    launch_memory_bytes: Option<u64>,
+
+    /// Launch applications on a read-only view of the filesystem
+    #[arg(long)]
+    launch_read_only: bool,
+
+    /// Pass this variable of the server's environment on to launched
+    /// applications, besides the default allow-list (repeatable)
+    #[arg(long = "pass-env", value_name = "NAME")]
+    pass_env: Vec<String>,
+
+    /// Mirror the clipboard to tmux paste buffers, reaching tmux through a
+    /// control mode client attached to an existing session
+    #[arg(long)]
+    tmux_clipboard: bool,
+
+    /// Socket of the tmux server for `--tmux-clipboard`, the default one if
+    /// unset
+    #[arg(long, value_name = "PATH", requires = "tmux_clipboard")]
+    tmux_socket: Option<PathBuf>,
+
+    /// Interval between reads of the tmux buffers right after clipboard
+    /// activity (default: 250)
+    #[arg(long, value_name = "MS", requires = "tmux_clipboard")]
+    clipboard_poll_min_ms: Option<u64>,
+
+    /// Interval between reads of the tmux buffers once idle (default: 5000)
+    #[arg(long, value_name = "MS", requires = "tmux_clipboard")]
+    clipboard_poll_max_ms: Option<u64>,
+
+    /// Largest share of the time spent reading the tmux buffers, in percent
+    /// (default: 2)
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        requires = "tmux_clipboard",
+        value_parser = clap::value_parser!(u8).range(1..=100)
+    )]
+    clipboard_poll_cpu_percent: Option<u8>,
+
+    /// Keep paths removed or replaced with `--trash` here instead of
+    /// `$XDG_DATA_HOME/yuha/trash`
+    #[arg(long, value_name = "PATH")]
+    trash_dir: Option<PathBuf>,
+
+    /// Purge trash entries after this many hours; 0 keeps them until
+    /// restored (default: 168)
+    #[arg(long, value_name = "HOURS")]
+    trash_retention_hours: Option<u64>,
+
+    /// Also append the access log of TCP connections to this file, as JSON
+    /// lines
+    #[arg(long, value_name = "PATH")]
+    access_log: Option<PathBuf>,
+
+    /// Number of TCP connections kept in the access log
+    #[arg(long, value_name = "N", default_value_t = access_log::DEFAULT_CAPACITY)]
+    access_log_size: usize,
+
+    /// Post a JSON alert to this http:// URL when a source is rejected
+    /// `--auth-failure-threshold` times within `--auth-failure-window-secs`
+    #[arg(long, value_name = "URL")]
+    auth_failure_webhook: Option<String>,
+
+    /// Rejections of a source that trigger an alert
+    #[arg(long, value_name = "N", default_value_t = 5)]
+    auth_failure_threshold: usize,
+
+    /// Window rejections are counted in, in seconds
+    #[arg(long, value_name = "SECS", default_value_t = 600)]
+    auth_failure_window_secs: u64,
+
+    /// Post events as JSON to this http:// URL (can be repeated)
+    #[arg(long, value_name = "URL")]
+    event_webhook: Vec<String>,
+
+    /// Run this command on events, with the event as JSON on its stdin and
+    /// its kind in YUHA_EVENT; split on whitespace (can be repeated)
+    #[arg(long, value_name = "COMMAND")]
+    event_command: Vec<String>,
+
+    /// Events posted and run on, comma-separated (default: all)
+    #[arg(long = "event", value_name = "KIND", value_enum, value_delimiter = ',')]
+    events: Vec<EventKind>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -502,18 +2014,39 @@ enum Commands {
     Status,
     /// Ping the remote process
     Ping,
+    /// Rebuild a binary from its previous version and a delta of the client
+    #[command(hide = true)]
+    ApplyDelta {
+        /// Previous version the delta was made against
+        base: PathBuf,
+        /// Delta to apply
+        delta: PathBuf,
+        /// Where the rebuilt binary is written
+        output: PathBuf,
+    },
+    /// Fail unless a file has the given BLAKE3 digest
+    #[command(hide = true)]
+    CheckDigest {
+        /// File to hash
+        path: PathBuf,
+        /// Expected digest as lowercase hex
+        digest: String,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
+    // Stdout carries the protocol in stdio mode
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
 
     // Write a simple message to a file as soon as the server starts
     let startup_file = std::fs::OpenOptions::new()
         .create(true)
         .write(true)
         .truncate(true)
-        .open("/tmp/remote_startup.txt");
+        .open(std::env::temp_dir().join("remote_startup.txt"));
 
     if let Ok(mut file) = startup_file {
         let _ = writeln!(
@@ -529,6 +2062,7 @@ async fn main() -> Result<()> {
     }
 
     info!("REMOTE_SERVER_STARTUP: Remote server process starting");
+    info!("Checksum backend: {}", checksum::backend());
 
     // Try to log any panics to stderr
     std::panic::set_hook(Box::new(|panic_info| {
@@ -541,7 +2075,7 @@ async fn main() -> Result<()> {
             .create(true)
             .write(true)
             .truncate(true)
-            .open("/tmp/remote_panic.txt");
+            .open(std::env::temp_dir().join("remote_panic.txt"));
 
         if let Ok(mut file) = panic_file {
             let _ = writeln!(file, "{}", panic_msg);
@@ -549,22 +2083,68 @@ async fn main() -> Result<()> {
     }));
 
     let args = Args::parse();
+    let tmux = args
+        .tmux_clipboard
+        .then(|| Arc::new(TmuxBuffers::new(args.tmux_socket.clone())));
+    let events = event_bus(&args)?;
+    let options = ServerOptions {
+        slow_log: slow_log_config(&args),
+        extensions: enabled_extensions(&args)?,
+        open_handlers: open_handlers(&args)?,
+        workspaces: Workspaces::parse(&args.workspaces)?,
+        binary_encoding: if args.inline_binary {
+            BinaryEncoding::Inline
+        } else {
+            BinaryEncoding::Attachment
+        },
+        rate_limit: args.rate_limit,
+        audit: args.audit,
+        command_allow_list: command_allow_list(&args)?,
+        authorization: (!args.admin_keys.is_empty())
+            .then(|| Authorization::new(args.admin_keys.iter().map(ToString::to_string).collect())),
+        meters: Arc::new(Meters::new(args.quota_window.map(Duration::from_secs))),
+        quota: Quota {
+            bytes: args.quota_bytes,
+            forwards: args.quota_forwards,
+            exec_cpu: args.quota_exec_cpu.map(Duration::from_secs),
+        },
+        launch_sandbox: Sandbox {
+            user: args.launch_user.clone(),
+            cpu_percent: args.launch_cpu_percent,
+            memory_bytes: args.launch_memory_bytes,
+            read_only: args.launch_read_only,
+        },
+        launch_env: ChildEnv::default().allowing(args.pass_env.clone()),
+        clipboard_watcher: tmux
+            .clone()
+            .map(|tmux| ClipboardWatcher::spawn(tmux, clipboard_poll_config(&args))),
+        tmux,
+        trash: Arc::new(trash_store(&args)),
+        access: Arc::new(access_monitor(&args)?.with_events(events.clone())),
+        events,
+        topics: TopicBus::default(),
+        commands: CommandRegistry::default(),
+    };
 
     // Check if this is a shell command execution
     if let Some(command) = args.command {
         return handle_shell_command(command, args.ipc_socket).await;
     }
 
+    // Hash before a redeploy can replace the file
+    binary_hash();
+
     // Start transport server mode (called by client)
     let ipc_socket_path = args.ipc_socket.unwrap_or_else(get_default_ipc_socket_path);
 
     if args.stdio {
         info!("Starting yuha remote server using standard I/O with simple protocol and IPC");
-        let stdin = tokio::io::stdin();
-        let stdout = tokio::io::stdout();
-        let stdio_stream = StdioStream::new(stdin, stdout);
-        let message_channel = MessageChannel::new_with_stream(stdio_stream);
-        let mut server = RemoteServer::new(message_channel);
+        let message_channel = MessageChannel::new_with_stream(stdio::protocol_stream()?)
+            .with_binary_encoding(options.binary_encoding);
+        let (server_requests, outbox) = server_requests::channel();
+        let mut server = options
+            .server(message_channel, "stdio".to_string())
+            .with_server_requests(outbox);
 
         // Start IPC server in background with client communication
         let response_buffer = server.get_response_buffer();
@@ -572,6 +2152,7 @@ async fn main() -> Result<()> {
         let mut ipc_server =
             yuha_remote::ipc::IpcServer::new(ipc_socket_path.clone(), response_buffer);
         ipc_server.set_client_sender(ipc_tx.clone());
+        ipc_server.set_server_requests(server_requests);
 
         tokio::spawn(async move {
             if let Err(e) = ipc_server.start().await {
@@ -586,9 +2167,47 @@ async fn main() -> Result<()> {
             args.port
         );
         let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", args.port)).await?;
-        let (stream, _) = listener.accept().await?;
-        let message_channel = MessageChannel::new(stream);
-        let mut server = RemoteServer::new(message_channel);
+        let token = args
+            .auth_token
+            .or_else(|| std::env::var("YUHA_AUTH_TOKEN").ok());
+        let noise = noise_config(
+            &args.noise_key,
+            args.noise_key_sealed_to.as_ref(),
+            &args.noise_peers,
+        )?;
+        if token.is_none() && noise.is_none() {
+            warn!(
+                "TCP mode running without --auth-token or --noise-key; frames are not authenticated"
+            );
+        }
+
+        if !options.workspaces.is_empty() {
+            // A shared server has no single client for IPC to address
+            info!("Serving workspaces to every TCP client");
+            loop {
+                let (stream, peer) = listener.accept().await?;
+                let token = token.clone();
+                let noise = noise.clone();
+                let options = options.clone();
+                tokio::spawn(async move {
+                    let session = async {
+                        let (mut server, _accepted) =
+                            tcp_session(stream, peer, token.as_deref(), noise.as_ref(), &options)
+                                .await?;
+                        server.run().await
+                    };
+                    if let Err(e) = session.await {
+                        warn!("Session of {} failed: {:#}", peer, e);
+                    }
+                });
+            }
+        }
+
+        let (stream, peer) = listener.accept().await?;
+        let (server, _accepted) =
+            tcp_session(stream, peer, token.as_deref(), noise.as_ref(), &options).await?;
+        let (server_requests, outbox) = server_requests::channel();
+        let mut server = server.with_server_requests(outbox);
 
         // Start IPC server in background with client communication
         let response_buffer = server.get_response_buffer();
@@ -596,6 +2215,7 @@ async fn main() -> Result<()> {
         let mut ipc_server =
             yuha_remote::ipc::IpcServer::new(ipc_socket_path.clone(), response_buffer);
         ipc_server.set_client_sender(ipc_tx.clone());
+        ipc_server.set_server_requests(server_requests);
 
         tokio::spawn(async move {
             if let Err(e) = ipc_server.start().await {
@@ -609,6 +2229,325 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// BLAKE3 hash of this binary, computed on first use
+fn binary_hash() -> Option<&'static str> {
+    static HASH: OnceLock<Option<String>> = OnceLock::new();
+    HASH.get_or_init(|| {
+        let path = std::env::current_exe().ok()?;
+        checksum::digest_file(&path)
+            .inspect_err(|e| warn!("Failed to hash {}: {}", path.display(), e))
+            .ok()
+    })
+    .as_deref()
+}
+
+/// The next request in `outbox`, never ready without one
+async fn next_server_request(outbox: &mut Option<Outbox>) -> (ServerRequestId, ServerRequest) {
+    match outbox {
+        Some(outbox) => outbox.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// The next event of `subscriber`, never ready without one
+async fn next_event(subscriber: &mut Option<Subscriber>) -> TopicEvent {
+    match subscriber {
+        Some(subscriber) => subscriber.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Final response of a request the client cancelled
+fn cancelled() -> ProtocolResponse {
+    ProtocolResponse::error(ErrorCode::Cancelled, "Request cancelled by the client")
+}
+
+/// Answer a `LaunchApp` or `OpenPath` request
+fn launched(pid: Result<u32>) -> ProtocolResponse {
+    match pid {
+        Ok(pid) => ProtocolResponse::Data {
+            items: vec![ResponseItem::AppLaunched { pid }],
+            next: None,
+        },
+        Err(e) => ProtocolResponse::failure("Failed to launch application", &e),
+    }
+}
+
+/// Settings of every session's server, from the command line
+#[derive(Clone)]
+struct ServerOptions {
+    slow_log: SlowLogConfig,
+    extensions: Vec<ExtensionId>,
+    open_handlers: OpenHandlers,
+    workspaces: Workspaces,
+    binary_encoding: BinaryEncoding,
+    rate_limit: Option<u32>,
+    audit: bool,
+    command_allow_list: Option<CommandAllowList>,
+    authorization: Option<Authorization>,
+    meters: Arc<Meters>,
+    quota: Quota,
+    launch_sandbox: Sandbox,
+    launch_env: ChildEnv,
+    tmux: Option<Arc<TmuxBuffers>>,
+    clipboard_watcher: Option<Arc<ClipboardWatcher>>,
+    trash: Arc<Trash>,
+    access: Arc<AccessMonitor>,
+    events: EventBus,
+    topics: TopicBus,
+    /// Handlers of `Command` requests, those of `#[yuha_command]` traits
+    commands: CommandRegistry,
+}
+
+impl ServerOptions {
+    /// Server of the session on `message_channel`, with the enabled middleware
+    fn server<T>(&self, message_channel: MessageChannel<T>, peer: String) -> RemoteServer<T>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let mut server = RemoteServer::new(message_channel, peer, self.slow_log.clone())
+            .with_extensions(self.extensions.clone())
+            .with_open_handlers(self.open_handlers.clone())
+            .with_workspaces(self.workspaces.clone())
+            .with_meters(self.meters.clone())
+            .with_quota(self.quota.clone())
+            .with_launch_sandbox(self.launch_sandbox.clone())
+            .with_launch_env(self.launch_env.clone())
+            .with_trash(self.trash.clone())
+            .with_access_log(self.access.log().clone())
+            .with_events(self.events.clone())
+            .with_topics(self.topics.clone())
+            .with_commands(self.commands.clone());
+        if let Some(authorization) = &self.authorization {
+            server = server.with_middleware(authorization.clone());
+        }
+        if let Some(per_second) = self.rate_limit {
+            server = server.with_middleware(RateLimit::new(per_second, per_second));
+        }
+        if let Some(allow_list) = &self.command_allow_list {
+            server = server.with_middleware(allow_list.clone());
+        }
+        if let Some(tmux) = &self.tmux {
+            server = server.with_tmux_buffers(tmux.clone());
+        }
+        if let Some(watcher) = &self.clipboard_watcher {
+            server = server.with_clipboard_watcher(watcher.clone());
+        }
+        if self.audit {
+            server = server.with_middleware(Audit);
+        }
+        server
+    }
+}
+
+/// Server of a TCP client, once the session is encrypted with `noise` and
+/// the client authenticated with `token`, each if set, within
+/// [`HANDSHAKE_TIMEOUT`]
+async fn tcp_session(
+    stream: TcpStream,
+    peer: SocketAddr,
+    token: Option<&str>,
+    noise: Option<&NoiseConfig>,
+    options: &ServerOptions,
+) -> Result<(RemoteServer<TcpStream>, AcceptedConnection)> {
+    let connected_at = SystemTime::now();
+    let mut message_channel =
+        MessageChannel::new(stream).with_binary_encoding(options.binary_encoding);
+    let authenticated = async {
+        let mut client_key = None;
+        if let Some(noise) = noise {
+            let client = message_channel.encrypt_server(noise).await?;
+            info!("Session of {} encrypted, client key {}", peer, client);
+            client_key = Some(client.to_string());
+        }
+        if let Some(token) = token {
+            message_channel.authenticate_server(token).await?;
+        }
+        anyhow::Ok(client_key)
+    };
+    let authenticated = tokio::time::timeout(HANDSHAKE_TIMEOUT, authenticated)
+        .await
+        .unwrap_or_else(|_| {
+            Err(anyhow::anyhow!(
+                "Handshake not completed within {:?}",
+                HANDSHAKE_TIMEOUT
+            ))
+        });
+    let client_key = match authenticated {
+        Ok(client_key) => client_key,
+        Err(e) => {
+            options
+                .access
+                .rejected(peer, connected_at, format!("{:#}", e));
+            return Err(e);
+        }
+    };
+    let accepted = options
+        .access
+        .accepted(peer, connected_at, client_key.clone());
+    let server = options
+        .server(message_channel, peer.to_string())
+        .with_identity(client_key);
+    Ok((server, accepted))
+}
+
+/// Noise keys given on the command line, trusting only the listed peers
+fn noise_config(
+    key: &Option<PathBuf>,
+    sealed_to: Option<&AgentKey>,
+    peers: &[NoisePublicKey],
+) -> Result<Option<NoiseConfig>> {
+    let Some(key) = key else {
+        return Ok(None);
+    };
+    if peers.is_empty() {
+        bail!("--noise-key needs at least one --noise-peer to accept");
+    }
+    let keypair = match sealed_to {
+        Some(agent_key) => {
+            info!(
+                "Unsealing the Noise key with the ssh-agent's {} key, touch the token if it asks for presence",
+                agent_key.algorithm()
+            );
+            NoiseKeypair::load_or_generate_sealed(key, agent_key)?
+        }
+        None => NoiseKeypair::load_or_generate(key)?,
+    };
+    info!("Noise public key: {}", keypair.public());
+    Ok(Some(NoiseConfig::new(keypair, peers.to_vec())))
+}
+
+/// Handlers given on the command line
+fn open_handlers(args: &Args) -> Result<OpenHandlers> {
+    let mut handlers = OpenHandlers::default();
+    for spec in &args.open_handlers {
+        handlers.insert_spec(spec)?;
+    }
+    Ok(handlers)
+}
+
+/// Programs `LaunchApp` may run, unrestricted without any given
+fn command_allow_list(args: &Args) -> Result<Option<CommandAllowList>> {
+    if args.allowed_commands.is_empty() && args.allowed_command_regexes.is_empty() {
+        return Ok(None);
+    }
+    CommandAllowList::new(&args.allowed_commands, &args.allowed_command_regexes).map(Some)
+}
+
+/// Registered extensions minus those disabled on the command line
+fn enabled_extensions(args: &Args) -> Result<Vec<ExtensionId>> {
+    let mut extensions = extension::registered();
+    for name in &args.disabled_extensions {
+        let Some(disabled) = extension::by_name(name) else {
+            bail!("Unknown protocol extension: {}", name);
+        };
+        extensions.retain(|id| *id != disabled.id);
+    }
+    Ok(extensions)
+}
+
+/// Build the slow log configuration, overriding defaults with command line arguments
+fn slow_log_config(args: &Args) -> SlowLogConfig {
+    let mut config = SlowLogConfig::default();
+    if let Some(ms) = args.slow_request_ms {
+        config.duration_threshold = Duration::from_millis(ms);
+    }
+    if let Some(bytes) = args.slow_payload_bytes {
+        config.payload_threshold = bytes;
+    }
+    if let Some(size) = args.slow_log_size {
+        config.capacity = size;
+    }
+    config
+}
+
+/// Access log and authentication failure alerts of the command line
+fn access_monitor(args: &Args) -> Result<AccessMonitor> {
+    let mut log = AccessLog::new(args.access_log_size);
+    if let Some(path) = &args.access_log {
+        log = log
+            .with_file(path)
+            .with_context(|| format!("Failed to open access log {}", path.display()))?;
+    }
+    let mut monitor = AccessMonitor::new(log);
+    if let Some(url) = &args.auth_failure_webhook {
+        monitor = monitor.with_alerts(FailureAlerts::new(
+            Webhook::parse(url)?,
+            args.auth_failure_threshold,
+            Duration::from_secs(args.auth_failure_window_secs),
+        ));
+    }
+    Ok(monitor)
+}
+
+/// Bus of the server's events, with the hooks of the command line
+/// delivering them
+fn event_bus(args: &Args) -> Result<EventBus> {
+    let mut sinks = Vec::new();
+    for url in &args.event_webhook {
+        sinks.push(EventSink::Webhook(Webhook::parse(url)?));
+    }
+    for spec in &args.event_command {
+        sinks.push(EventSink::command(spec)?);
+    }
+    let bus = EventBus::default();
+    for sink in sinks {
+        EventHook::new(sink)
+            .with_kinds(args.events.clone())
+            .spawn(&bus);
+    }
+    Ok(bus)
+}
+
+/// Trash of the server, in the directory and with the retention of the
+/// command line
+fn trash_store(args: &Args) -> Trash {
+    let retention = match args.trash_retention_hours {
+        Some(0) => None,
+        Some(hours) => Some(Duration::from_secs(hours * 60 * 60)),
+        None => Some(trash::DEFAULT_RETENTION),
+    };
+    Trash::new(
+        args.trash_dir.clone().unwrap_or_else(Trash::default_dir),
+        retention,
+    )
+}
+
+/// Build the tmux buffer polling configuration, overriding defaults with
+/// command line arguments
+fn clipboard_poll_config(args: &Args) -> PollConfig {
+    let mut config = PollConfig::default();
+    if let Some(ms) = args.clipboard_poll_min_ms {
+        config.min_interval = Duration::from_millis(ms);
+    }
+    if let Some(ms) = args.clipboard_poll_max_ms {
+        config.max_interval = Duration::from_millis(ms);
+    }
+    if let Some(percent) = args.clipboard_poll_cpu_percent {
+        config.max_cpu_percent = percent;
+    }
+    config
+}
+
+/// Write the binary rebuilt from `base` and `delta` to `output`, executable
+/// like `base`
+fn apply_delta(base: &Path, delta: &Path, output: &Path) -> Result<()> {
+    let base_data = std::fs::read(base)?;
+    let rebuilt = yuha_core::delta::apply(&base_data, &std::fs::read(delta)?)?;
+    std::fs::write(output, rebuilt)?;
+    std::fs::set_permissions(output, std::fs::metadata(base)?.permissions())?;
+    Ok(())
+}
+
+/// Fail unless the file at `path` has the BLAKE3 digest `digest`
+fn check_digest(path: &Path, digest: &str) -> Result<()> {
+    let actual = yuha_core::checksum::digest_file(path)?;
+    if actual != digest {
+        anyhow::bail!("{} has digest {}, not {}", path.display(), actual, digest);
+    }
+    Ok(())
+}
+
 /// Handle shell command execution
 async fn handle_shell_command(command: Commands, ipc_socket: Option<PathBuf>) -> Result<()> {
     let socket_path = ipc_socket.unwrap_or_else(get_default_ipc_socket_path);
@@ -621,6 +2560,12 @@ async fn handle_shell_command(command: Commands, ipc_socket: Option<PathBuf>) ->
         Commands::SendToClient { message } => IpcCommand::SendToClient { message },
         Commands::Status => IpcCommand::Status,
         Commands::Ping => IpcCommand::Ping,
+        Commands::ApplyDelta {
+            base,
+            delta,
+            output,
+        } => return apply_delta(&base, &delta, &output),
+        Commands::CheckDigest { path, digest } => return check_digest(&path, &digest),
     };
 
     match client.send_command(ipc_command).await {
@@ -658,3 +2603,321 @@ async fn handle_shell_command(command: Commands, ipc_socket: Option<PathBuf>) ->
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tokio::io::{DuplexStream, duplex};
+    use yuha_core::protocol::command::{Command, CommandSender, yuha_command};
+
+    #[yuha_command]
+    trait Counter {
+        /// Add `by` to the counter, returning its new value
+        async fn add(&self, by: u64) -> Result<u64>;
+    }
+
+    #[derive(Default)]
+    struct Count(AtomicU64);
+
+    #[async_trait]
+    impl Counter for Count {
+        async fn add(&self, by: u64) -> Result<u64> {
+            Ok(self.0.fetch_add(by, Ordering::SeqCst) + by)
+        }
+    }
+
+    /// Sends commands to a server over its message channel
+    struct Remote(tokio::sync::Mutex<MessageChannel<DuplexStream>>);
+
+    impl Remote {
+        async fn request(&self, request: &ProtocolRequest) -> ProtocolResponse {
+            let mut channel = self.0.lock().await;
+            channel.send_request(request).await.unwrap();
+            channel.receive_response().await.unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl CommandSender for Remote {
+        type Error = String;
+
+        async fn call<C: Command>(&self, command: C) -> Result<C::Output, String> {
+            let request = command.request().map_err(|e| e.to_string())?;
+            match self.request(&request).await {
+                ProtocolResponse::Data { items, .. } => C::output(items).map_err(|e| e.to_string()),
+                ProtocolResponse::Error { message, .. } => Err(message),
+                response => Err(format!("Unexpected response {:?}", response)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serves_registered_commands() {
+        let (client, server) = duplex(1 << 16);
+        let mut commands = CommandRegistry::new();
+        commands.register(CounterHandler(Arc::new(Count::default())));
+        let channel = MessageChannel::new_with_stream(server);
+        let mut server = RemoteServer::new(channel, "test".to_string(), SlowLogConfig::default())
+            .with_commands(commands);
+        tokio::spawn(async move { server.run().await });
+
+        let remote = Remote(tokio::sync::Mutex::new(MessageChannel::new_with_stream(
+            client,
+        )));
+        assert_eq!(remote.add(2).await, Ok(2));
+        assert_eq!(remote.add(3).await, Ok(5));
+
+        let malformed = ProtocolRequest::Command {
+            name: "add".to_string(),
+            args: serde_json::json!({ "by": "three" }),
+        };
+        assert!(matches!(
+            remote.request(&malformed).await,
+            ProtocolResponse::Error {
+                code: ErrorCode::InvalidArguments,
+                ..
+            }
+        ));
+        let unknown = ProtocolRequest::Command {
+            name: "reset".to_string(),
+            args: serde_json::json!({}),
+        };
+        assert!(matches!(
+            remote.request(&unknown).await,
+            ProtocolResponse::Error {
+                code: ErrorCode::Unsupported,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_control_answered_beside_long_poll() {
+        let (client, server) = duplex(1 << 16);
+        let mut server = RemoteServer::new(
+            MessageChannel::new_with_stream(server),
+            "test".to_string(),
+            SlowLogConfig::default(),
+        );
+        tokio::spawn(async move { server.run().await });
+        let mut client = MessageChannel::new_with_stream(client);
+        let hello = ProtocolRequest::Hello {
+            extensions: vec![extension::TASKS.id, extension::CANCEL.id],
+            workspace: None,
+        };
+        client.send_request(&hello).await.unwrap();
+        client.receive_response().await.unwrap();
+
+        // Nothing to poll, so the poll stalls while the control request is
+        // answered past it
+        client
+            .send_request_with_id(1, &ProtocolRequest::PollData)
+            .await
+            .unwrap();
+        client
+            .send_request_with_id(2, &ProtocolRequest::ListTasks)
+            .await
+            .unwrap();
+        let (id, response) = client.receive_response_with_id().await.unwrap();
+        assert_eq!(id, Some(2));
+        assert!(matches!(response, ProtocolResponse::Data { .. }));
+
+        // The poll is still waiting, to be cancelled
+        client.send_cancel(1).await.unwrap();
+        let (id, response) = client.receive_response_with_id().await.unwrap();
+        assert_eq!(id, Some(1));
+        assert!(matches!(
+            response,
+            ProtocolResponse::Error {
+                code: ErrorCode::Cancelled,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_paths_outside_workspace_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("web");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(root.join("index.html"), "inside").unwrap();
+        std::fs::write(dir.path().join("secret"), "outside").unwrap();
+        let inside = root.join("index.html").display().to_string();
+        let outside = dir.path().join("secret").display().to_string();
+
+        let (client, server) = duplex(1 << 16);
+        let workspaces = Workspaces::parse(&[format!("web={}", root.display())]).unwrap();
+        let mut server = RemoteServer::new(
+            MessageChannel::new_with_stream(server),
+            "test".to_string(),
+            SlowLogConfig::default(),
+        )
+        .with_workspaces(workspaces);
+        tokio::spawn(async move { server.run().await });
+        let mut client = MessageChannel::new_with_stream(client);
+        let hello = ProtocolRequest::Hello {
+            extensions: extension::REGISTRY.iter().map(|e| e.id).collect(),
+            workspace: Some("web".to_string()),
+        };
+        client.send_request(&hello).await.unwrap();
+        client.receive_response().await.unwrap();
+
+        let requests = [
+            ProtocolRequest::ListFiles {
+                paths: vec![inside.clone(), outside.clone()],
+                query: ListQuery::default(),
+            },
+            ProtocolRequest::ListDirectory {
+                path: dir.path().display().to_string(),
+            },
+            ProtocolRequest::ReadFileChunk {
+                path: outside.clone(),
+                offset: 0,
+                len: 64,
+            },
+            ProtocolRequest::ReadFileRange {
+                path: outside.clone(),
+                offset: 0,
+                len: 64,
+            },
+            ProtocolRequest::HashPath {
+                path: outside.clone(),
+                algo: HashAlgorithm::Blake3,
+            },
+            ProtocolRequest::DiskUsage {
+                path: outside.clone(),
+                depth: 0,
+            },
+            ProtocolRequest::WriteFileChunk {
+                path: outside.clone(),
+                offset: 0,
+                data: Bytes::from_static(b"written"),
+                crc32c: checksum::crc32c(b"written"),
+                last: true,
+                expected_digest: None,
+            },
+            ProtocolRequest::OpenPath {
+                path: outside.clone(),
+            },
+            ProtocolRequest::RemovePath {
+                path: outside.clone(),
+                trash: false,
+            },
+            ProtocolRequest::MovePath {
+                from: outside.clone(),
+                to: inside.clone(),
+                trash: false,
+            },
+            ProtocolRequest::MovePath {
+                from: inside.clone(),
+                to: outside.clone(),
+                trash: false,
+            },
+            ProtocolRequest::RestorePath {
+                id: "missing".to_string(),
+                to: Some(outside.clone()),
+            },
+        ];
+        for request in &requests {
+            client.send_request(request).await.unwrap();
+            let response = client.receive_response().await.unwrap();
+            assert!(
+                matches!(
+                    response,
+                    ProtocolResponse::Error {
+                        code: ErrorCode::PermissionDenied,
+                        ..
+                    }
+                ),
+                "{} answered {:?}",
+                request.kind(),
+                response
+            );
+        }
+        assert_eq!(std::fs::read_to_string(&outside).unwrap(), "outside");
+        assert_eq!(std::fs::read_to_string(&inside).unwrap(), "inside");
+    }
+
+    /// Recorded sessions of every wire revision, see `yuha-core`'s wire tests
+    const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../core/src/tests/golden");
+
+    /// Payload of a recorded client frame, or `None` for handshake and
+    /// server frames
+    fn recorded_payload(line: &str) -> Option<Bytes> {
+        let frame = line.strip_prefix("> sealed ")?;
+        let (kind, rest) = frame.splitn(3, ' ').nth(2)?.split_once(' ')?;
+        Some(match kind {
+            "json" => Bytes::copy_from_slice(rest.as_bytes()),
+            "attachment" => {
+                let mut payload = vec![0x00];
+                payload.extend((0..rest.len()).step_by(2).map(|i| {
+                    u8::from_str_radix(&rest[i..i + 2], 16).expect("Invalid hex in golden file")
+                }));
+                Bytes::from(payload)
+            }
+            other => panic!("Unknown golden frame kind: {}", other),
+        })
+    }
+
+    /// The client of every recorded revision is still served: each request
+    /// of its session gets an answer, and none is refused as unsupported or
+    /// malformed
+    #[tokio::test]
+    async fn test_serves_recorded_clients() {
+        let mut sessions: Vec<_> = std::fs::read_dir(GOLDEN_DIR)
+            .unwrap()
+            .flat_map(|revision| std::fs::read_dir(revision.unwrap().path()).unwrap())
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with("session"))
+            })
+            .collect();
+        sessions.sort();
+        assert!(!sessions.is_empty(), "No recorded sessions");
+
+        for path in sessions {
+            let session = std::fs::read_to_string(&path).unwrap();
+            let (client, server) = duplex(1 << 16);
+            let mut client = MessageChannel::new_with_stream(client).with_frame_magic();
+            let mut server = MessageChannel::new_with_stream(server).with_frame_magic();
+            let (authenticated, _) = tokio::join!(
+                client.authenticate_client("golden-token"),
+                server.authenticate_server("golden-token")
+            );
+            authenticated.unwrap();
+            let mut server =
+                RemoteServer::new(server, "test".to_string(), SlowLogConfig::default());
+            tokio::spawn(async move { server.run().await });
+
+            for payload in session.lines().filter_map(recorded_payload) {
+                let attachment = payload[0] == 0x00;
+                client.send(payload).await.unwrap();
+                if attachment {
+                    continue;
+                }
+                loop {
+                    match client.receive_response().await.unwrap() {
+                        ProtocolResponse::Batch { more: true, .. }
+                        | ProtocolResponse::Event { .. } => {}
+                        ProtocolResponse::Error { code, message, .. } => {
+                            assert!(
+                                !matches!(
+                                    code,
+                                    ErrorCode::Unsupported | ErrorCode::InvalidArguments
+                                ),
+                                "{} refused a request: {}",
+                                path.display(),
+                                message
+                            );
+                            break;
+                        }
+                        _ => break,
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,476 @@
+//! Request middleware
+//!
+//! Cross-cutting policies run as a chain around the request dispatcher
+//! instead of being inlined in every handler. Each [`Middleware`] may answer
+//! a request itself before it is dispatched, and sees the response of every
+//! request it let through; `after` hooks run in reverse order, so the first
+//! middleware wraps all others.
+//!
+//! Built in are [`ExtensionCheck`] (requests of extensions that were not
+//! negotiated are refused), [`Authorization`], [`RateLimit`],
+//! [`CommandAllowList`], [`Audit`] and [`RequestMetrics`].
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use yuha_core::METRICS;
+use yuha_core::protocol::{ErrorCode, ExtensionId, ProtocolRequest, ProtocolResponse};
+
+/// What middleware knows about the request being served
+#[derive(Debug, Clone, Copy)]
+pub struct RequestContext<'a> {
+    /// Peer the request came from
+    pub peer: &'a str,
+    /// Key the client authenticated with, if the session is encrypted
+    pub identity: Option<&'a str>,
+    /// Request kind (see [`ProtocolRequest::kind`])
+    pub kind: &'static str,
+    /// Extensions negotiated on this connection so far
+    pub negotiated: &'a [ExtensionId],
+}
+
+/// A policy wrapped around the request dispatcher
+pub trait Middleware: Send + Sync {
+    /// Inspect `request` before dispatch; a returned response answers the
+    /// request without dispatching it
+    fn before(
+        &self,
+        _context: &RequestContext,
+        _request: &ProtocolRequest,
+    ) -> Option<ProtocolResponse> {
+        None
+    }
+
+    /// Observe or replace the response to a request served in `elapsed`,
+    /// called only when `before` let the request through or answered it
+    fn after(
+        &self,
+        _context: &RequestContext,
+        _response: &mut ProtocolResponse,
+        _elapsed: Duration,
+    ) {
+    }
+}
+
+/// Middleware applied in order around the dispatcher
+#[derive(Default)]
+pub struct MiddlewareChain {
+    layers: Vec<Box<dyn Middleware>>,
+}
+
+/// Number of layers whose `before` hook ran for a request, and whose
+/// `after` hooks run for its response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entered(usize);
+
+impl MiddlewareChain {
+    /// The chain every server starts with: metrics of every request, and
+    /// extension checks
+    pub fn standard() -> Self {
+        Self::default().with(RequestMetrics).with(ExtensionCheck)
+    }
+
+    /// Append `middleware`, running inside those added before it
+    pub fn with(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.layers.push(Box::new(middleware));
+        self
+    }
+
+    /// Run the `before` hooks until one answers the request
+    pub fn before(
+        &self,
+        context: &RequestContext,
+        request: &ProtocolRequest,
+    ) -> (Entered, Option<ProtocolResponse>) {
+        for (index, layer) in self.layers.iter().enumerate() {
+            if let Some(response) = layer.before(context, request) {
+                return (Entered(index + 1), Some(response));
+            }
+        }
+        (Entered(self.layers.len()), None)
+    }
+
+    /// Run the `after` hooks of the `entered` layers, innermost first
+    pub fn after(
+        &self,
+        entered: Entered,
+        context: &RequestContext,
+        response: &mut ProtocolResponse,
+        elapsed: Duration,
+    ) {
+        for layer in self.layers[..entered.0].iter().rev() {
+            layer.after(context, response, elapsed);
+        }
+    }
+}
+
+/// Refuses requests of extensions that were not negotiated
+pub struct ExtensionCheck;
+
+impl Middleware for ExtensionCheck {
+    fn before(
+        &self,
+        context: &RequestContext,
+        request: &ProtocolRequest,
+    ) -> Option<ProtocolResponse> {
+        let extension = request.extension()?;
+        (!context.negotiated.contains(&extension.id)).then(|| {
+            ProtocolResponse::error(
+                ErrorCode::Unsupported,
+                format!("Extension {} was not negotiated", extension.name),
+            )
+            .with_detail("extension", extension.name)
+        })
+    }
+}
+
+/// Requests only admins may send, as they expose other clients' sessions
+/// or alter the server
+pub const ADMIN_REQUESTS: &[&str] = &["GetAccessLog", "InjectFault"];
+
+/// Refuses [`ADMIN_REQUESTS`] from clients not authenticated with one of
+/// the admin keys, logging each refusal to the `yuha::audit` target
+///
+/// Sessions without an identity, e.g. unencrypted ones, are never admins.
+#[derive(Debug, Clone, Default)]
+pub struct Authorization {
+    admins: Vec<String>,
+}
+
+impl Authorization {
+    /// Accept admin requests from clients authenticated with `admins`
+    pub fn new(admins: Vec<String>) -> Self {
+        Self { admins }
+    }
+
+    /// Whether the client authenticated with `identity` is an admin
+    pub fn is_admin(&self, identity: Option<&str>) -> bool {
+        identity.is_some_and(|identity| self.admins.iter().any(|admin| admin == identity))
+    }
+}
+
+impl Middleware for Authorization {
+    fn before(
+        &self,
+        context: &RequestContext,
+        _request: &ProtocolRequest,
+    ) -> Option<ProtocolResponse> {
+        if !ADMIN_REQUESTS.contains(&context.kind) || self.is_admin(context.identity) {
+            return None;
+        }
+        warn!(
+            target: "yuha::audit",
+            peer = context.peer,
+            identity = context.identity,
+            request = context.kind,
+            "Admin request refused"
+        );
+        Some(ProtocolResponse::error(
+            ErrorCode::PermissionDenied,
+            format!("{} needs an admin key", context.kind),
+        ))
+    }
+}
+
+/// Token bucket limiting the request rate; long polls and forwarded data
+/// are exempt, as they are paced by traffic rather than by the user
+pub struct RateLimit {
+    per_second: f64,
+    burst: f64,
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimit {
+    /// Allow `per_second` requests on average and up to `burst` at once
+    pub fn new(per_second: u32, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            per_second: f64::from(per_second),
+            burst,
+            bucket: Mutex::new((burst, Instant::now())),
+        }
+    }
+}
+
+impl Middleware for RateLimit {
+    fn before(
+        &self,
+        _context: &RequestContext,
+        request: &ProtocolRequest,
+    ) -> Option<ProtocolResponse> {
+        if matches!(
+            request,
+            ProtocolRequest::PollData | ProtocolRequest::PortForwardData { .. }
+        ) {
+            return None;
+        }
+
+        let mut bucket = self.bucket.lock().unwrap();
+        let (tokens, refilled) = &mut *bucket;
+        let now = Instant::now();
+        *tokens = (*tokens + now.duration_since(*refilled).as_secs_f64() * self.per_second)
+            .min(self.burst);
+        *refilled = now;
+        if *tokens < 1.0 {
+            return Some(ProtocolResponse::error(
+                ErrorCode::Busy,
+                "Rate limit exceeded",
+            ));
+        }
+        *tokens -= 1.0;
+        None
+    }
+}
+
+/// A program allowed by a [`CommandAllowList`]
+#[derive(Debug, Clone)]
+pub enum CommandPattern {
+    /// This program name or path, exactly as requested
+    Exact(String),
+    /// Programs matching this regular expression as a whole
+    Regex(Regex),
+}
+
+/// Refuses `LaunchApp` requests for programs matching none of its patterns,
+/// logging each refusal to the `yuha::audit` target
+///
+/// Only the program is checked, not its arguments; `OpenPath` runs the
+/// server's own handlers and is not restricted.
+#[derive(Debug, Clone, Default)]
+pub struct CommandAllowList {
+    patterns: Vec<CommandPattern>,
+}
+
+impl CommandAllowList {
+    /// Allow the `exact` programs and those matching one of `regexes`
+    pub fn new(exact: &[String], regexes: &[String]) -> Result<Self> {
+        let mut patterns: Vec<_> = exact.iter().cloned().map(CommandPattern::Exact).collect();
+        for regex in regexes {
+            let anchored = Regex::new(&format!("^(?:{})$", regex))
+                .with_context(|| format!("Invalid command pattern {}", regex))?;
+            patterns.push(CommandPattern::Regex(anchored));
+        }
+        Ok(Self { patterns })
+    }
+
+    /// Whether `program` may be launched
+    pub fn allows(&self, program: &str) -> bool {
+        self.patterns.iter().any(|pattern| match pattern {
+            CommandPattern::Exact(allowed) => allowed == program,
+            CommandPattern::Regex(regex) => regex.is_match(program),
+        })
+    }
+}
+
+impl Middleware for CommandAllowList {
+    fn before(
+        &self,
+        context: &RequestContext,
+        request: &ProtocolRequest,
+    ) -> Option<ProtocolResponse> {
+        let ProtocolRequest::LaunchApp { command, .. } = request else {
+            return None;
+        };
+        // Without a program the launch fails on its own
+        let program = command.first()?;
+        if self.allows(program) {
+            return None;
+        }
+        warn!(
+            target: "yuha::audit",
+            peer = context.peer,
+            request = context.kind,
+            program = program.as_str(),
+            "Command not allowed"
+        );
+        Some(
+            ProtocolResponse::error(
+                ErrorCode::PermissionDenied,
+                format!("Command {} is not allowed", program),
+            )
+            .with_detail("command", program),
+        )
+    }
+}
+
+/// Logs every request but long polls and forwarded data, with its outcome,
+/// to the `yuha::audit` target
+pub struct Audit;
+
+impl Middleware for Audit {
+    fn after(&self, context: &RequestContext, response: &mut ProtocolResponse, elapsed: Duration) {
+        if matches!(context.kind, "PollData" | "PortForwardData") {
+            return;
+        }
+        let outcome = match response {
+            ProtocolResponse::Error { message, .. } => message.as_str(),
+            _ => "ok",
+        };
+        info!(
+            target: "yuha::audit",
+            peer = context.peer,
+            request = context.kind,
+            elapsed_ms = elapsed.as_millis() as u64,
+            "{}",
+            outcome
+        );
+    }
+}
+
+/// Counts requests and error responses by kind and records their duration
+pub struct RequestMetrics;
+
+impl Middleware for RequestMetrics {
+    fn after(&self, context: &RequestContext, response: &mut ProtocolResponse, elapsed: Duration) {
+        let labels = HashMap::from([("type".to_string(), context.kind.to_string())]);
+        METRICS.counter("requests_total", 1.0, labels.clone());
+        if matches!(response, ProtocolResponse::Error { .. }) {
+            METRICS.counter("request_errors_total", 1.0, labels.clone());
+        }
+        METRICS.record_histogram("request_seconds", elapsed.as_secs_f64(), labels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use yuha_core::protocol::extension::SLOW_LOG;
+
+    fn context(negotiated: &[ExtensionId]) -> RequestContext<'_> {
+        RequestContext {
+            peer: "test",
+            identity: None,
+            kind: "GetClipboard",
+            negotiated,
+        }
+    }
+
+    fn is_error(response: &Option<ProtocolResponse>) -> bool {
+        matches!(response, Some(ProtocolResponse::Error { .. }))
+    }
+
+    #[test]
+    fn test_extension_check() {
+        let chain = MiddlewareChain::standard();
+        let request = ProtocolRequest::GetSlowLog;
+        assert!(is_error(&chain.before(&context(&[]), &request).1));
+        assert!(chain.before(&context(&[SLOW_LOG.id]), &request).1.is_none());
+        assert!(
+            chain
+                .before(&context(&[]), &ProtocolRequest::GetClipboard)
+                .1
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_authorization() {
+        let authorization = Authorization::new(vec!["admin-key".to_string()]);
+        let admin = |identity| RequestContext {
+            identity,
+            kind: "GetAccessLog",
+            ..context(&[])
+        };
+        let request = ProtocolRequest::GetAccessLog;
+        assert!(
+            authorization
+                .before(&admin(Some("admin-key")), &request)
+                .is_none()
+        );
+        assert!(is_error(
+            &authorization.before(&admin(Some("other-key")), &request)
+        ));
+        assert!(is_error(&authorization.before(&admin(None), &request)));
+        // Other requests need no admin
+        assert!(
+            authorization
+                .before(&context(&[]), &ProtocolRequest::GetClipboard)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let limit = RateLimit::new(0, 2);
+        let request = ProtocolRequest::GetClipboard;
+        assert!(limit.before(&context(&[]), &request).is_none());
+        assert!(limit.before(&context(&[]), &request).is_none());
+        assert!(is_error(&limit.before(&context(&[]), &request)));
+        // Long polls are never limited
+        assert!(
+            limit
+                .before(&context(&[]), &ProtocolRequest::PollData)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_command_allow_list() {
+        let allowed = CommandAllowList::new(
+            &["git".to_string(), "/usr/bin/make".to_string()],
+            &["cargo|rustc".to_string()],
+        )
+        .unwrap();
+        assert!(allowed.allows("git"));
+        assert!(allowed.allows("/usr/bin/make"));
+        assert!(allowed.allows("rustc"));
+        // Patterns match the whole program
+        assert!(!allowed.allows("/usr/bin/git"));
+        assert!(!allowed.allows("cargo-evil"));
+        assert!(CommandAllowList::new(&[], &["(".to_string()]).is_err());
+
+        let launch = |program: &str| ProtocolRequest::LaunchApp {
+            command: vec![program.to_string(), "status".to_string()],
+            display_env: Default::default(),
+            sandbox: Default::default(),
+        };
+        assert!(allowed.before(&context(&[]), &launch("git")).is_none());
+        assert!(is_error(&allowed.before(&context(&[]), &launch("bash"))));
+        assert!(
+            allowed
+                .before(&context(&[]), &ProtocolRequest::GetClipboard)
+                .is_none()
+        );
+    }
+
+    /// Records hook calls as `<name>.before` / `<name>.after`
+    struct Trace(&'static str, Arc<Mutex<Vec<String>>>, bool);
+
+    impl Middleware for Trace {
+        fn before(&self, _: &RequestContext, _: &ProtocolRequest) -> Option<ProtocolResponse> {
+            self.1.lock().unwrap().push(format!("{}.before", self.0));
+            self.2.then_some(ProtocolResponse::Success)
+        }
+
+        fn after(&self, _: &RequestContext, _: &mut ProtocolResponse, _: Duration) {
+            self.1.lock().unwrap().push(format!("{}.after", self.0));
+        }
+    }
+
+    #[test]
+    fn test_chain_order() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let chain = MiddlewareChain::default()
+            .with(Trace("outer", calls.clone(), false))
+            .with(Trace("inner", calls.clone(), true))
+            .with(Trace("skipped", calls.clone(), false));
+
+        let (entered, response) = chain.before(&context(&[]), &ProtocolRequest::GetClipboard);
+        chain.after(
+            entered,
+            &context(&[]),
+            &mut response.unwrap(),
+            Duration::ZERO,
+        );
+
+        // Layers the request did not reach see no response
+        assert_eq!(
+            *calls.lock().unwrap(),
+            ["outer.before", "inner.before", "inner.after", "outer.after"]
+        );
+    }
+}
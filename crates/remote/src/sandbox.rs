@@ -0,0 +1,256 @@
+//! Sandboxed application launch
+//!
+//! Applies the [`Sandbox`] of a `LaunchApp` request by wrapping the command
+//! in the tools the host provides, so a shared server can offer restricted
+//! launches:
+//!
+//! - CPU and memory limits run the command in a transient systemd scope
+//!   (`systemd-run --scope`), a cgroup of the system manager when the server
+//!   is root and of the user's manager otherwise
+//! - A read-only filesystem runs it under bubblewrap (`bwrap`), with a
+//!   private `/tmp` and the X11 sockets bound back in
+//! - Another user is switched to by the server, or by `systemd-run` when the
+//!   command also runs in a scope
+//!
+//! A restriction the host cannot apply fails the launch instead of running
+//! the command without it.
+
+use anyhow::{Result, bail};
+use tokio::process::Command;
+use yuha_core::child_env::ChildEnv;
+use yuha_core::protocol::request_response::Sandbox;
+
+use crate::tools;
+
+/// Command running `command` under `sandbox` with the tools of this host,
+/// starting with the variables of `env`
+///
+/// A command run as another user starts in that user's home directory.
+pub fn command(sandbox: &Sandbox, command: &[String], env: &ChildEnv) -> Result<Command> {
+    let path_var = std::env::var_os("PATH").unwrap_or_default();
+    let available = |tool: &str| tools::find_in_path(tool, &path_var).is_some();
+    let (argv, switch_user) = wrap(sandbox, command, available, is_root())?;
+    let Some((program, args)) = argv.split_first() else {
+        bail!("No command to launch");
+    };
+
+    let mut cmd = Command::new(program);
+    cmd.args(args).env_clear().envs(env.vars());
+    #[cfg(unix)]
+    if let Some(user) = &sandbox.user {
+        let (uid, gid, home) = lookup_user(user)?;
+        if switch_user {
+            cmd.uid(uid).gid(gid);
+        }
+        cmd.current_dir(&home);
+        cmd.env("HOME", home).env("USER", user).env("LOGNAME", user);
+    }
+    Ok(cmd)
+}
+
+fn is_root() -> bool {
+    #[cfg(unix)]
+    {
+        // SAFETY: geteuid cannot fail
+        unsafe { libc::geteuid() == 0 }
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+/// Command line running `command` under `sandbox`, and whether the server
+/// must switch to the sandbox user itself
+///
+/// `available` tells whether a tool is on the `PATH`, `root` whether the
+/// server runs as root.
+pub fn wrap(
+    sandbox: &Sandbox,
+    command: &[String],
+    available: impl Fn(&str) -> bool,
+    root: bool,
+) -> Result<(Vec<String>, bool)> {
+    if sandbox.is_unrestricted() {
+        return Ok((command.to_vec(), false));
+    }
+    if !cfg!(target_os = "linux") {
+        bail!("Sandboxed launches are only supported on Linux");
+    }
+    if sandbox.user.is_some() && !root {
+        bail!("Launching as another user needs a server running as root");
+    }
+
+    let mut argv = Vec::new();
+    let scoped = sandbox.cpu_percent.is_some() || sandbox.memory_bytes.is_some();
+    if scoped {
+        if !available("systemd-run") {
+            bail!("CPU and memory limits need systemd-run on the remote");
+        }
+        argv.extend(["systemd-run", "--scope", "--quiet", "--collect"].map(String::from));
+        if !root {
+            argv.push("--user".to_string());
+        }
+        if let Some(percent) = sandbox.cpu_percent {
+            argv.extend(["-p".to_string(), format!("CPUQuota={}%", percent)]);
+        }
+        if let Some(bytes) = sandbox.memory_bytes {
+            argv.extend(["-p".to_string(), format!("MemoryMax={}", bytes)]);
+        }
+        if let Some(user) = &sandbox.user {
+            argv.push(format!("--uid={}", user));
+        }
+        argv.push("--".to_string());
+    }
+    if sandbox.read_only {
+        if !available("bwrap") {
+            bail!("A read-only filesystem needs bubblewrap (bwrap) on the remote");
+        }
+        argv.extend(
+            [
+                "bwrap",
+                "--ro-bind",
+                "/",
+                "/",
+                "--dev",
+                "/dev",
+                "--proc",
+                "/proc",
+                "--tmpfs",
+                "/tmp",
+                "--ro-bind-try",
+                "/tmp/.X11-unix",
+                "/tmp/.X11-unix",
+                "--",
+            ]
+            .map(String::from),
+        );
+    }
+    argv.extend_from_slice(command);
+    Ok((argv, sandbox.user.is_some() && !scoped))
+}
+
+/// User and group ids and home directory of `name`
+#[cfg(unix)]
+pub fn lookup_user(name: &str) -> Result<(u32, u32, String)> {
+    use anyhow::Context;
+    use std::ffi::{CStr, CString};
+
+    let c_name = CString::new(name).context("Invalid user name")?;
+    // SAFETY: `passwd` is plain data that getpwnam_r fills in
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut found = std::ptr::null_mut();
+    // SAFETY: every pointer refers to storage living through the call, and
+    // `buffer` holds the strings `passwd` points to afterwards
+    let status = unsafe {
+        libc::getpwnam_r(
+            c_name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        )
+    };
+    if status != 0 || found.is_null() {
+        bail!("No user {} on the remote", name);
+    }
+    // SAFETY: `pw_dir` points into `buffer`, NUL terminated
+    let home = unsafe { CStr::from_ptr(passwd.pw_dir) }
+        .to_string_lossy()
+        .into_owned();
+    Ok((passwd.pw_uid, passwd.pw_gid, home))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command() -> Vec<String> {
+        vec!["xterm".to_string()]
+    }
+
+    #[test]
+    fn test_unrestricted_runs_as_is() {
+        let (argv, switch) = wrap(&Sandbox::default(), &command(), |_| false, false).unwrap();
+        assert_eq!(argv, command());
+        assert!(!switch);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_wrap() {
+        let sandbox = Sandbox {
+            user: Some("guest".to_string()),
+            cpu_percent: Some(50),
+            memory_bytes: Some(1 << 20),
+            read_only: true,
+        };
+        let (argv, switch) = wrap(&sandbox, &command(), |_| true, true).unwrap();
+        assert_eq!(
+            argv[..9],
+            [
+                "systemd-run",
+                "--scope",
+                "--quiet",
+                "--collect",
+                "-p",
+                "CPUQuota=50%",
+                "-p",
+                "MemoryMax=1048576",
+                "--uid=guest",
+            ]
+        );
+        assert_eq!(argv[9..12], ["--", "bwrap", "--ro-bind"]);
+        assert_eq!(argv.last().unwrap(), "xterm");
+        // systemd-run switches the user
+        assert!(!switch);
+
+        let read_only = Sandbox {
+            user: Some("guest".to_string()),
+            read_only: true,
+            ..Default::default()
+        };
+        let (argv, switch) = wrap(&read_only, &command(), |_| true, true).unwrap();
+        assert_eq!(argv[0], "bwrap");
+        assert!(switch);
+
+        let limited = Sandbox {
+            memory_bytes: Some(1 << 20),
+            ..Default::default()
+        };
+        let (argv, _) = wrap(&limited, &command(), |_| true, false).unwrap();
+        assert!(argv.contains(&"--user".to_string()));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_refuses_what_it_cannot_apply() {
+        let read_only = Sandbox {
+            read_only: true,
+            ..Default::default()
+        };
+        let error = wrap(&read_only, &command(), |tool| tool != "bwrap", true).unwrap_err();
+        assert!(error.to_string().contains("bwrap"));
+
+        let limited = Sandbox {
+            cpu_percent: Some(10),
+            ..Default::default()
+        };
+        assert!(wrap(&limited, &command(), |tool| tool == "bwrap", true).is_err());
+
+        let other_user = Sandbox {
+            user: Some("guest".to_string()),
+            ..Default::default()
+        };
+        assert!(wrap(&other_user, &command(), |_| true, false).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_lookup_user() {
+        let (uid, gid, _) = lookup_user("root").unwrap();
+        assert_eq!((uid, gid), (0, 0));
+        assert!(lookup_user("no-such-user-yuha").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+//! Requests of the server to its client
+//!
+//! Whatever holds a [`ServerRequests`] handle, e.g. the IPC server taking
+//! commands of the remote shell, asks the client of a session something,
+//! such as opening a URL on the client's host. The session takes the
+//! requests from its [`Outbox`], numbers them and sends them out of turn
+//! to a client negotiating `server-requests`; the client's `ServerReply`
+//! completes the request. Without such a client, requests are answered
+//! `Unhandled` right away.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use yuha_core::protocol::{ServerReply, ServerRequest, ServerRequestId};
+
+/// Time the client is given to reply
+pub const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A request waiting to be sent, and where its reply goes if awaited
+struct Queued {
+    request: ServerRequest,
+    reply: Option<oneshot::Sender<ServerReply>>,
+}
+
+/// Handle sending requests to the client of a session
+#[derive(Debug, Clone)]
+pub struct ServerRequests {
+    sender: mpsc::UnboundedSender<Queued>,
+}
+
+/// Requests a session sends its client and the replies it waits for
+#[derive(Default)]
+pub struct Outbox {
+    receiver: Option<mpsc::UnboundedReceiver<Queued>>,
+    /// Requests sent and not replied to, with where their reply goes if
+    /// awaited
+    replies: HashMap<ServerRequestId, Option<oneshot::Sender<ServerReply>>>,
+    last_id: ServerRequestId,
+}
+
+/// A handle and the outbox of the session its requests go to
+pub fn channel() -> (ServerRequests, Outbox) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let outbox = Outbox {
+        receiver: Some(receiver),
+        ..Outbox::default()
+    };
+    (ServerRequests { sender }, outbox)
+}
+
+impl ServerRequests {
+    /// Ask the client, waiting up to [`REPLY_TIMEOUT`] for its reply
+    ///
+    /// Fails once the session ended or the client did not reply in time.
+    pub async fn request(&self, request: ServerRequest) -> Result<ServerReply> {
+        let (reply, replied) = oneshot::channel();
+        self.send(request, Some(reply))?;
+        tokio::time::timeout(REPLY_TIMEOUT, replied)
+            .await
+            .context("Client did not reply in time")?
+            .context("Session ended before the client replied")
+    }
+
+    /// Tell the client, without waiting for its reply
+    pub fn notify(&self, request: ServerRequest) -> Result<()> {
+        self.send(request, None)
+    }
+
+    fn send(
+        &self,
+        request: ServerRequest,
+        reply: Option<oneshot::Sender<ServerReply>>,
+    ) -> Result<()> {
+        self.sender
+            .send(Queued { request, reply })
+            .ok()
+            .context("No client session")
+    }
+}
+
+impl Outbox {
+    /// The next request to send, numbered, its reply now awaited
+    ///
+    /// Cancel safe; never ready once every handle is dropped.
+    pub async fn next(&mut self) -> (ServerRequestId, ServerRequest) {
+        let queued = match &mut self.receiver {
+            Some(receiver) => receiver.recv().await,
+            None => None,
+        };
+        let Some(Queued { request, reply }) = queued else {
+            self.receiver = None;
+            return std::future::pending().await;
+        };
+        self.last_id += 1;
+        self.replies.insert(self.last_id, reply);
+        (self.last_id, request)
+    }
+
+    /// Complete the request `id` with `reply`, returning whether it was sent
+    /// and not replied to before
+    pub fn reply(&mut self, id: ServerRequestId, reply: ServerReply) -> bool {
+        let Some(awaited) = self.replies.remove(&id) else {
+            return false;
+        };
+        if let Some(sender) = awaited {
+            // The asker may have given up waiting
+            let _ = sender.send(reply);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_replies_complete_requests() {
+        let (requests, mut outbox) = channel();
+        let url = ServerRequest::OpenUrl {
+            url: "https://example.com".to_string(),
+        };
+        let asked = tokio::spawn({
+            let requests = requests.clone();
+            let url = url.clone();
+            async move { requests.request(url).await }
+        });
+        assert_eq!(outbox.next().await, (1, url));
+        requests
+            .notify(ServerRequest::ClipboardChanged {
+                content: "copied".to_string(),
+            })
+            .unwrap();
+        assert_eq!(outbox.next().await.0, 2);
+
+        assert!(outbox.reply(1, ServerReply::Done));
+        assert_eq!(asked.await.unwrap().unwrap(), ServerReply::Done);
+        assert!(!outbox.reply(1, ServerReply::Done));
+        assert!(outbox.reply(2, ServerReply::Unhandled));
+        assert!(!outbox.reply(3, ServerReply::Done));
+
+        drop(outbox);
+        assert!(
+            requests
+                .request(ServerRequest::OpenUrl {
+                    url: "https://example.com".to_string()
+                })
+                .await
+                .is_err()
+        );
+    }
+}
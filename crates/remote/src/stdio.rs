@@ -0,0 +1,81 @@
+//! Protocol over standard I/O
+//!
+//! In `--stdio` mode stdout carries the protocol, so anything else printed
+//! there, by a dependency or by an application launched with the inherited
+//! stream, would corrupt its framing. [`protocol_stream`] moves the protocol
+//! to a private handle and points standard output at stderr, the diagnostic
+//! stream clients log, where stray output then lands.
+//!
+//! On Unix descriptor 1 is replaced with `dup2`, which covers every writer.
+//! On Windows the standard output handle is replaced with `SetStdHandle`,
+//! which Rust code and launched applications follow, and the protocol handle
+//! is kept from being inherited. Output written through a C runtime's
+//! descriptor 1, bound to the handle when the runtime started, still reaches
+//! the protocol there. Other platforms refuse to serve over stdio rather than
+//! run unguarded.
+
+use std::io::Result;
+use tokio::io::{Join, Stdin};
+
+/// Where the protocol is written
+pub type ProtocolOutput = tokio::fs::File;
+
+/// Stdin joined with the protocol output, taking stdout over for good
+pub fn protocol_stream() -> Result<Join<Stdin, ProtocolOutput>> {
+    Ok(tokio::io::join(tokio::io::stdin(), take_stdout()?))
+}
+
+#[cfg(unix)]
+fn take_stdout() -> Result<ProtocolOutput> {
+    use std::io::Write;
+    use std::os::fd::AsFd;
+
+    std::io::stdout().flush()?;
+    // Duplicated close-on-exec, so launched applications do not inherit it
+    let protocol = std::io::stdout().as_fd().try_clone_to_owned()?;
+    // SAFETY: stdout and stderr stay open for the life of the process
+    if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(tokio::fs::File::from_std(std::fs::File::from(protocol)))
+}
+
+#[cfg(windows)]
+fn take_stdout() -> Result<ProtocolOutput> {
+    use std::io::Write;
+    use std::os::windows::io::{FromRawHandle, OwnedHandle};
+    use windows_sys::Win32::Foundation::{
+        HANDLE_FLAG_INHERIT, INVALID_HANDLE_VALUE, SetHandleInformation,
+    };
+    use windows_sys::Win32::System::Console::{
+        GetStdHandle, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE, SetStdHandle,
+    };
+
+    std::io::stdout().flush()?;
+    // SAFETY: the standard handles are only queried and replaced; the
+    // protocol handle is no standard handle anymore once replaced, so it is
+    // owned here from then on
+    unsafe {
+        let protocol = GetStdHandle(STD_OUTPUT_HANDLE);
+        if protocol == INVALID_HANDLE_VALUE || protocol.is_null() {
+            return Err(std::io::Error::other("No stdout to carry the protocol"));
+        }
+        // Launched applications inherit inheritable handles whatever their
+        // standard ones are
+        if SetHandleInformation(protocol, HANDLE_FLAG_INHERIT, 0) == 0
+            || SetStdHandle(STD_OUTPUT_HANDLE, GetStdHandle(STD_ERROR_HANDLE)) == 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+        let protocol = OwnedHandle::from_raw_handle(protocol);
+        Ok(tokio::fs::File::from_std(std::fs::File::from(protocol)))
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn take_stdout() -> Result<ProtocolOutput> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Stdout cannot be kept for the protocol on this platform",
+    ))
+}
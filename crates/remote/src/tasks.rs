@@ -0,0 +1,197 @@
+//! Task tracking
+//!
+//! Every background task a session starts (port forward listeners and the
+//! connections they accept) is spawned through a [`TaskRegistry`], which
+//! records what it does and keeps a handle to cancel it. Finished tasks
+//! drop out by themselves; whatever still runs when the session ends is
+//! cancelled with it, so no task outlives its session.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::task::AbortHandle;
+use tracing::debug;
+use yuha_core::protocol::request_response::{TaskId, TaskInfo, TaskKind};
+
+struct Entry {
+    info: TaskInfo,
+    handle: AbortHandle,
+}
+
+#[derive(Default)]
+struct Tasks {
+    next_id: TaskId,
+    entries: BTreeMap<TaskId, Entry>,
+}
+
+/// Tasks started on behalf of one session, or of the sessions of a
+/// workspace sharing them through [`TaskRegistry::for_session`]
+#[derive(Clone)]
+pub struct TaskRegistry {
+    session: Arc<str>,
+    tasks: Arc<Mutex<Tasks>>,
+}
+
+impl TaskRegistry {
+    /// Registry for the tasks of `session`
+    pub fn new(session: &str) -> Self {
+        Self {
+            session: session.into(),
+            tasks: Arc::default(),
+        }
+    }
+
+    /// The same tasks, spawning new ones on behalf of `session`
+    pub fn for_session(&self, session: &str) -> Self {
+        Self {
+            session: session.into(),
+            tasks: self.tasks.clone(),
+        }
+    }
+
+    /// Spawn `future` as a tracked task
+    pub fn spawn<F>(&self, kind: TaskKind, future: F) -> TaskId
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        // Held until the entry is in, so a task finishing right away cannot
+        // try to remove it before
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.next_id += 1;
+        let id = tasks.next_id;
+
+        let registry = Arc::downgrade(&self.tasks);
+        let handle = tokio::spawn(async move {
+            future.await;
+            if let Some(tasks) = registry.upgrade() {
+                tasks.lock().unwrap().entries.remove(&id);
+            }
+        })
+        .abort_handle();
+
+        let info = TaskInfo {
+            id,
+            kind,
+            session: self.session.to_string(),
+            started_at: SystemTime::now(),
+        };
+        tasks.entries.insert(id, Entry { info, handle });
+        id
+    }
+
+    /// Running tasks by id
+    pub fn list(&self) -> Vec<TaskInfo> {
+        let tasks = self.tasks.lock().unwrap();
+        tasks
+            .entries
+            .values()
+            .map(|entry| entry.info.clone())
+            .collect()
+    }
+
+    /// Cancel the running tasks matching `predicate` and return them
+    pub fn cancel_where(&self, predicate: impl Fn(&TaskInfo) -> bool) -> Vec<TaskInfo> {
+        let mut tasks = self.tasks.lock().unwrap();
+        let ids: Vec<_> = tasks
+            .entries
+            .values()
+            .filter(|entry| predicate(&entry.info))
+            .map(|entry| entry.info.id)
+            .collect();
+        ids.into_iter()
+            .filter_map(|id| tasks.entries.remove(&id))
+            .map(|entry| {
+                entry.handle.abort();
+                debug!("Cancelled task {} ({:?})", entry.info.id, entry.info.kind);
+                entry.info
+            })
+            .collect()
+    }
+
+    /// Cancel every running task
+    pub fn cancel_all(&self) -> Vec<TaskInfo> {
+        self.cancel_where(|_| true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+
+    fn forward(local_port: u16) -> TaskKind {
+        TaskKind::PortForward {
+            local_port,
+            remote_host: "localhost".to_string(),
+            remote_port: 80,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_finished_tasks_drop_out() {
+        let registry = TaskRegistry::new("peer");
+        let (done_tx, done_rx) = oneshot::channel();
+        registry.spawn(forward(8080), async move {
+            let _ = done_rx.await;
+        });
+
+        let [task] = &registry.list()[..] else {
+            panic!("expected one task");
+        };
+        assert_eq!(task.session, "peer");
+        assert_eq!(task.kind, forward(8080));
+
+        done_tx.send(()).unwrap();
+        for _ in 0..100 {
+            if registry.list().is_empty() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("finished task still listed");
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let registry = TaskRegistry::new("peer");
+        let (dropped_tx, dropped_rx) = oneshot::channel::<()>();
+        registry.spawn(forward(8080), async move {
+            // Dropped, closing the channel, when the task is aborted
+            let _guard = dropped_tx;
+            std::future::pending::<()>().await;
+        });
+        let other = registry.spawn(forward(9090), std::future::pending());
+
+        let cancelled = registry.cancel_where(|task| task.kind.local_port() == 8080);
+        assert_eq!(cancelled.len(), 1);
+        assert!(dropped_rx.await.is_err());
+        assert_eq!(
+            registry
+                .list()
+                .iter()
+                .map(|task| task.id)
+                .collect::<Vec<_>>(),
+            vec![other]
+        );
+
+        assert_eq!(registry.cancel_all().len(), 1);
+        assert!(registry.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_shared_between_sessions() {
+        let registry = TaskRegistry::new("workspace");
+        let first = registry.for_session("first");
+        let second = registry.for_session("second");
+        first.spawn(forward(8080), std::future::pending());
+        second.spawn(forward(9090), std::future::pending());
+
+        let sessions: Vec<_> = second.list().into_iter().map(|task| task.session).collect();
+        assert_eq!(sessions, ["first", "second"]);
+        assert_eq!(first.cancel_where(|task| task.session == "second").len(), 1);
+        assert_eq!(registry.list().len(), 1);
+        registry.cancel_all();
+    }
+}
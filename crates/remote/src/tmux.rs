@@ -0,0 +1,274 @@
+//! # tmux Paste Buffers
+//!
+//! With `--tmux-clipboard`, the clipboard is mirrored to the paste buffers of
+//! the tmux server the remote can reach, so terminal-only workflows sync
+//! too: text the client sets can be pasted with `prefix ]`, and text copied
+//! in copy mode replaces the clipboard when the client next reads it.
+//!
+//! Commands go through one long-lived control mode client (`tmux -C`)
+//! attached to an existing session with pane output turned off. tmux does
+//! not announce buffer changes to it, so reads compare the newest buffer
+//! with the text last synced.
+
+use anyhow::{Context, Result, bail};
+use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex;
+use tracing::debug;
+
+/// Paste buffers of a tmux server
+pub struct TmuxBuffers {
+    /// Socket of the tmux server, the default one if unset
+    socket: Option<PathBuf>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    control: Option<ControlClient>,
+    /// Text last read from or written to the buffers
+    synced: Option<String>,
+}
+
+impl TmuxBuffers {
+    /// Buffers of the tmux server listening on `socket`, or the default one
+    pub fn new(socket: Option<PathBuf>) -> Self {
+        Self {
+            socket,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Text of the newest buffer if it changed since the last sync
+    pub async fn copied(&self) -> Result<Option<String>> {
+        let mut state = self.state.lock().await;
+        let Some(text) = self.newest(&mut state).await? else {
+            return Ok(None);
+        };
+        if state.synced.as_deref() == Some(text.as_str()) {
+            return Ok(None);
+        }
+        state.synced = Some(text.clone());
+        Ok(Some(text))
+    }
+
+    /// Put `text` in a new buffer
+    pub async fn paste(&self, text: &str) -> Result<()> {
+        let mut state = self.state.lock().await;
+        let reply = self
+            .run(&mut state, &format!("set-buffer -- {}", quote(text)))
+            .await?;
+        if !reply.ok {
+            bail!("tmux refused the buffer: {}", reply.lines.join(" "));
+        }
+        state.synced = Some(text.to_string());
+        Ok(())
+    }
+
+    /// Text of the newest buffer, `None` if there are no buffers
+    async fn newest(&self, state: &mut State) -> Result<Option<String>> {
+        let shown = self.run(state, "show-buffer").await?;
+        if !shown.ok {
+            debug!("No tmux buffer to read: {}", shown.lines.join(" "));
+            return Ok(None);
+        }
+        let sizes = self.run(state, "list-buffers -F '#{buffer_size}'").await?;
+
+        let mut data = Vec::new();
+        for (i, line) in shown.lines.iter().enumerate() {
+            if i > 0 {
+                data.push(b'\n');
+            }
+            unescape(line, &mut data);
+        }
+        // Output lines do not tell whether the buffer ends with a newline
+        let size = sizes.lines.first().and_then(|size| size.parse().ok());
+        if size == Some(data.len() + 1) {
+            data.push(b'\n');
+        }
+        Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+    }
+
+    /// Run `command`, attaching a control client first if none is up
+    async fn run(&self, state: &mut State, command: &str) -> Result<Reply> {
+        let control = match &mut state.control {
+            Some(control) => control,
+            None => state
+                .control
+                .insert(ControlClient::attach(&self.socket).await?),
+        };
+        let reply = control.run(command).await;
+        // A broken client is replaced on the next command
+        if reply.is_err() {
+            state.control = None;
+        }
+        reply
+    }
+}
+
+/// Output of a control mode command
+struct Reply {
+    ok: bool,
+    lines: Vec<String>,
+}
+
+/// A `tmux -C` process
+struct ControlClient {
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl ControlClient {
+    async fn attach(socket: &Option<PathBuf>) -> Result<Self> {
+        let mut command = Command::new("tmux");
+        if let Some(socket) = socket {
+            command.arg("-S").arg(socket);
+        }
+        let mut child = command
+            .args(["-C", "attach-session"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Failed to start tmux")?;
+        let mut control = Self {
+            stdin: child.stdin.take().context("tmux stdin unavailable")?,
+            stdout: BufReader::new(child.stdout.take().context("tmux stdout unavailable")?).lines(),
+            _child: child,
+        };
+
+        let attached = control.reply().await?;
+        if !attached.ok {
+            bail!("Failed to attach to tmux: {}", attached.lines.join(" "));
+        }
+        control.run("refresh-client -f no-output").await?;
+        debug!("Attached to tmux in control mode");
+        Ok(control)
+    }
+
+    async fn run(&mut self, command: &str) -> Result<Reply> {
+        self.stdin
+            .write_all(format!("{}\n", command).as_bytes())
+            .await?;
+        self.stdin.flush().await?;
+        self.reply().await
+    }
+
+    /// Read the next command output block, skipping notifications
+    async fn reply(&mut self) -> Result<Reply> {
+        let mut block: Option<(String, Vec<String>)> = None;
+        loop {
+            let Some(line) = self.stdout.next_line().await? else {
+                bail!("tmux control mode exited");
+            };
+            match &mut block {
+                None => {
+                    if let Some(guard) = line.strip_prefix("%begin") {
+                        block = Some((guard.to_string(), Vec::new()));
+                    }
+                }
+                // The guard repeats the command's time and number, so
+                // output lines cannot end the block
+                Some((guard, lines)) => {
+                    if line.strip_prefix("%end") == Some(guard.as_str()) {
+                        return Ok(Reply {
+                            ok: true,
+                            lines: std::mem::take(lines),
+                        });
+                    }
+                    if line.strip_prefix("%error") == Some(guard.as_str()) {
+                        return Ok(Reply {
+                            ok: false,
+                            lines: std::mem::take(lines),
+                        });
+                    }
+                    lines.push(line);
+                }
+            }
+        }
+    }
+}
+
+/// `text` as a double quoted tmux command argument
+fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '\\' | '"' | '$' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_ascii_control() => quoted.push_str(&format!("\\{:03o}", c as u8)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Append the bytes of a `show-buffer` output line, which escapes
+/// backslashes and control characters, to `data`
+fn unescape(line: &str, data: &mut Vec<u8>) {
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' || i + 1 == bytes.len() {
+            data.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let octal = bytes
+            .get(i + 1..i + 4)
+            .filter(|digits| digits.iter().all(|d| (b'0'..=b'7').contains(d)));
+        if let Some(digits) = octal {
+            let value = digits
+                .iter()
+                .fold(0u32, |value, d| value * 8 + (d - b'0') as u32);
+            data.push(value as u8);
+            i += 4;
+            continue;
+        }
+        data.push(match bytes[i + 1] {
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b't' => b'\t',
+            b'n' => b'\n',
+            b'v' => 0x0b,
+            b'f' => 0x0c,
+            b'r' => b'\r',
+            b's' => b' ',
+            other => other,
+        });
+        i += 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("plain"), "\"plain\"");
+        assert_eq!(
+            quote("a \"b\" $HOME\\\n\t\x1b"),
+            "\"a \\\"b\\\" \\$HOME\\\\\\n\\t\\033\""
+        );
+        assert_eq!(quote("ünï"), "\"ünï\"");
+    }
+
+    #[test]
+    fn test_unescape() {
+        let mut data = Vec::new();
+        unescape("p\\\\q\\tr\\rs\\033é\\", &mut data);
+        assert_eq!(data, "p\\q\tr\rs\x1bé\\".as_bytes());
+    }
+}
@@ -0,0 +1,152 @@
+//! Toolchain probing
+//!
+//! Locates tools on the remote's `PATH` and asks each for its version, so
+//! clients can adapt their integrations and report missing prerequisites.
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::debug;
+use yuha_core::protocol::request_response::{COMMON_TOOLS, ToolInfo};
+
+/// Time a tool gets to print its version
+const VERSION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Probe `tools`, or [`COMMON_TOOLS`] when empty, in the given order
+pub async fn probe(tools: &[String]) -> Vec<ToolInfo> {
+    let path_var = std::env::var_os("PATH").unwrap_or_default();
+    let names: Vec<&str> = if tools.is_empty() {
+        COMMON_TOOLS.to_vec()
+    } else {
+        tools.iter().map(String::as_str).collect()
+    };
+
+    let mut probed = Vec::with_capacity(names.len());
+    for name in names {
+        let path = find_in_path(name, &path_var);
+        let version = match &path {
+            Some(path) => version(path).await,
+            None => None,
+        };
+        probed.push(ToolInfo {
+            name: name.to_string(),
+            path: path.map(|path| path.to_string_lossy().into_owned()),
+            version,
+        });
+    }
+    probed
+}
+
+/// First executable called `name` in the directories of `path_var`
+pub(crate) fn find_in_path(name: &str, path_var: &OsStr) -> Option<PathBuf> {
+    // Names with a separator would escape the search path
+    if name.is_empty() || name.contains(['/', '\\']) {
+        return None;
+    }
+    std::env::split_paths(path_var)
+        .flat_map(|dir| {
+            let suffixes: &[&str] = if cfg!(windows) {
+                &[".exe", ".cmd", ".bat"]
+            } else {
+                &[""]
+            };
+            suffixes
+                .iter()
+                .map(move |suffix| dir.join(format!("{}{}", name, suffix)))
+                .collect::<Vec<_>>()
+        })
+        .find(|candidate| is_executable(candidate))
+}
+
+fn is_executable(path: &Path) -> bool {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.is_file()
+    }
+}
+
+/// Version reported by `path --version`
+async fn version(path: &Path) -> Option<String> {
+    let output = Command::new(path)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = match tokio::time::timeout(VERSION_TIMEOUT, output).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => {
+            debug!("Failed to run {} --version: {}", path.display(), e);
+            return None;
+        }
+        Err(_) => {
+            debug!("{} --version timed out", path.display());
+            return None;
+        }
+    };
+    // Some tools print their version on stderr
+    first_line(&output.stdout).or_else(|| first_line(&output.stderr))
+}
+
+fn first_line(output: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(output)
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_first_line() {
+        assert_eq!(
+            first_line(b"\n  Python 3.12.1  \nmore\n"),
+            Some("Python 3.12.1".to_string())
+        );
+        assert_eq!(first_line(b" \n"), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_probe() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let tool = dir.path().join("fake-tool");
+        std::fs::write(&tool, "#!/bin/sh\necho 'fake-tool 1.2.3' >&2\n").unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        // Not executable, so not a match
+        std::fs::write(dir.path().join("plain"), "").unwrap();
+
+        assert_eq!(
+            find_in_path("fake-tool", dir.path().as_os_str()),
+            Some(tool.clone())
+        );
+        assert_eq!(find_in_path("plain", dir.path().as_os_str()), None);
+        assert_eq!(find_in_path("../fake-tool", dir.path().as_os_str()), None);
+        assert_eq!(version(&tool).await, Some("fake-tool 1.2.3".to_string()));
+
+        let probed = probe(&["yuha-missing-tool".to_string()]).await;
+        assert_eq!(
+            probed,
+            vec![ToolInfo {
+                name: "yuha-missing-tool".to_string(),
+                path: None,
+                version: None,
+            }]
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Events of topics pushed to subscribed clients
+//!
+//! Sessions publish what they do on a [`TopicBus`] shared by the server:
+//! clipboard content set, connections to port forwards opened and closed,
+//! files written, removed, moved and restored. A session whose client
+//! subscribed to topics takes their events from its [`Subscriber`] and
+//! pushes them to the client as `Event` responses.
+//!
+//! Events are scoped to the workspace of the session publishing them, so a
+//! client only learns of its own workspace.
+
+use std::collections::HashSet;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+use yuha_core::protocol::{Topic, TopicEvent};
+
+/// Events kept for sessions that fall behind
+const BUS_CAPACITY: usize = 256;
+
+/// An event and the workspace it happened in
+#[derive(Debug, Clone)]
+struct Published {
+    workspace: Option<String>,
+    event: TopicEvent,
+}
+
+/// Broadcast of the events of every topic, shared by the server's sessions
+#[derive(Debug, Clone)]
+pub struct TopicBus {
+    sender: broadcast::Sender<Published>,
+}
+
+impl Default for TopicBus {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(BUS_CAPACITY).0,
+        }
+    }
+}
+
+impl TopicBus {
+    /// Handle publishing the events of `workspace`
+    pub fn publisher(&self, workspace: Option<&str>) -> Publisher {
+        Publisher {
+            sender: self.sender.clone(),
+            workspace: workspace.map(str::to_string),
+        }
+    }
+
+    /// Receiver of the events of `workspace`, of no topic yet
+    pub fn subscriber(&self, workspace: Option<&str>) -> Subscriber {
+        Subscriber {
+            receiver: self.sender.subscribe(),
+            workspace: workspace.map(str::to_string),
+            topics: HashSet::new(),
+        }
+    }
+}
+
+/// Publishes the events of one workspace, or of sessions outside workspaces
+#[derive(Debug, Clone)]
+pub struct Publisher {
+    sender: broadcast::Sender<Published>,
+    workspace: Option<String>,
+}
+
+impl Publisher {
+    /// Publish `event`; never blocks, and without subscribers it is dropped
+    pub fn publish(&self, event: TopicEvent) {
+        let _ = self.sender.send(Published {
+            workspace: self.workspace.clone(),
+            event,
+        });
+    }
+}
+
+/// Events of the subscribed topics of one workspace
+#[derive(Debug)]
+pub struct Subscriber {
+    receiver: broadcast::Receiver<Published>,
+    workspace: Option<String>,
+    topics: HashSet<Topic>,
+}
+
+impl Subscriber {
+    pub fn subscribe(&mut self, topic: Topic) {
+        self.topics.insert(topic);
+    }
+
+    pub fn unsubscribe(&mut self, topic: Topic) {
+        self.topics.remove(&topic);
+    }
+
+    /// Topics subscribed to, in order
+    pub fn topics(&self) -> Vec<Topic> {
+        let mut topics: Vec<_> = self.topics.iter().copied().collect();
+        topics.sort();
+        topics
+    }
+
+    /// The next event of a subscribed topic
+    ///
+    /// Cancel safe; events a slow session missed are skipped with a warning.
+    pub async fn next(&mut self) -> TopicEvent {
+        loop {
+            match self.receiver.recv().await {
+                Ok(published)
+                    if published.workspace == self.workspace
+                        && self.topics.contains(&published.event.topic()) =>
+                {
+                    return published.event;
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => warn!("Missed {} topic events", missed),
+                // The subscriber holds a handle to the bus
+                Err(RecvError::Closed) => unreachable!("topic bus closed"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_subscriber_receives_its_topics_and_workspace() {
+        let bus = TopicBus::default();
+        let mut subscriber = bus.subscriber(Some("frontend"));
+        subscriber.subscribe(Topic::Files);
+        let frontend = bus.publisher(Some("frontend"));
+        let removed = |path: &str| TopicEvent::PathRemoved {
+            path: path.to_string(),
+        };
+
+        bus.publisher(None).publish(removed("/shared"));
+        bus.publisher(Some("backend")).publish(removed("/backend"));
+        frontend.publish(TopicEvent::ClipboardChanged { text: None });
+        frontend.publish(removed("/frontend"));
+        assert_eq!(subscriber.next().await, removed("/frontend"));
+
+        subscriber.unsubscribe(Topic::Files);
+        subscriber.subscribe(Topic::Clipboard);
+        frontend.publish(removed("/frontend"));
+        frontend.publish(TopicEvent::ClipboardChanged { text: None });
+        assert_eq!(
+            subscriber.next().await,
+            TopicEvent::ClipboardChanged { text: None }
+        );
+    }
+}
@@ -0,0 +1,250 @@
+//! Trash of removed and replaced files
+//!
+//! `RemovePath` and `MovePath` requests asking for it move what they would
+//! destroy into a [`Trash`] directory instead, from which `RestorePath`
+//! moves it back, so a typo in a scripted clean-up can be undone. Each
+//! entry is a directory named by its id, holding the trashed path as
+//! `item` and its [`TrashEntry`] as `info.json`. Entries older than the
+//! retention are purged whenever something is trashed.
+//!
+//! Trashing and restoring block on the filesystem and are run on blocking
+//! threads by the server.
+
+use crate::files;
+use anyhow::{Context, Result, bail};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+use yuha_core::protocol::request_response::TrashEntry;
+
+/// Name of the trashed path inside its entry
+const ITEM: &str = "item";
+/// Name of the entry's description inside its entry
+const INFO: &str = "info.json";
+
+/// Default time entries are kept for
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Trash directory of a server
+#[derive(Debug)]
+pub struct Trash {
+    dir: PathBuf,
+    /// How long entries are kept, forever if `None`
+    retention: Option<Duration>,
+    next_id: AtomicU64,
+}
+
+impl Trash {
+    pub fn new(dir: impl Into<PathBuf>, retention: Option<Duration>) -> Self {
+        Self {
+            dir: dir.into(),
+            retention,
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// `yuha/trash` under `$XDG_DATA_HOME`, or under `~/.local/share`
+    pub fn default_dir() -> PathBuf {
+        let data = std::env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })
+            .unwrap_or_else(std::env::temp_dir);
+        data.join("yuha").join("trash")
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Move `path` into the trash
+    pub fn trash(&self, path: &str) -> Result<TrashEntry> {
+        let original =
+            std::path::absolute(path).with_context(|| format!("Cannot resolve {}", path))?;
+        std::fs::symlink_metadata(&original).with_context(|| format!("Failed to stat {}", path))?;
+        if let Err(e) = self.purge_expired() {
+            warn!("Failed to purge expired trash entries: {:#}", e);
+        }
+
+        let (id, entry_dir) = self.create_entry_dir()?;
+        let entry = TrashEntry {
+            id,
+            original_path: original.to_string_lossy().into_owned(),
+            trashed_at: SystemTime::now(),
+        };
+        let moved = std::fs::write(entry_dir.join(INFO), serde_json::to_vec(&entry)?)
+            .context("Failed to describe the trash entry")
+            .and_then(|_| files::move_tree(&original, &entry_dir.join(ITEM)));
+        if let Err(e) = moved {
+            let _ = std::fs::remove_dir_all(&entry_dir);
+            return Err(e);
+        }
+        info!("Trashed {} as {}", entry.original_path, entry.id);
+        Ok(entry)
+    }
+
+    /// Move the entry `id` back to where it was trashed from, or to `to`
+    ///
+    /// Fails when something is at the destination already.
+    pub fn restore(&self, id: &str, to: Option<&str>) -> Result<TrashEntry> {
+        let entry = self.entry(id)?;
+        let destination = PathBuf::from(to.unwrap_or(&entry.original_path));
+        if std::fs::symlink_metadata(&destination).is_ok() {
+            bail!("{} exists already", destination.display());
+        }
+        if let Some(parent) = destination.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        let entry_dir = self.dir.join(id);
+        files::move_tree(&entry_dir.join(ITEM), &destination)?;
+        std::fs::remove_dir_all(&entry_dir)
+            .with_context(|| format!("Failed to remove trash entry {}", id))?;
+        info!("Restored trash entry {} to {}", id, destination.display());
+        Ok(entry)
+    }
+
+    /// The entry `id`
+    pub fn entry(&self, id: &str) -> Result<TrashEntry> {
+        if id.is_empty() || id.contains(['/', '\\']) || id.starts_with('.') {
+            bail!("Invalid trash entry id {}", id);
+        }
+        let info = std::fs::read(self.dir.join(id).join(INFO))
+            .with_context(|| format!("No trash entry {}", id))?;
+        serde_json::from_slice(&info).with_context(|| format!("Corrupt trash entry {}", id))
+    }
+
+    /// The entries, oldest first; corrupt ones are skipped
+    pub fn list(&self) -> Result<Vec<TrashEntry>> {
+        let dir = match std::fs::read_dir(&self.dir) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", self.dir.display()));
+            }
+        };
+        let mut entries = Vec::new();
+        for dir_entry in dir {
+            let id = dir_entry?.file_name().to_string_lossy().into_owned();
+            match self.entry(&id) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!("Skipping trash entry: {:#}", e),
+            }
+        }
+        entries.sort_by(|a, b| (a.trashed_at, &a.id).cmp(&(b.trashed_at, &b.id)));
+        Ok(entries)
+    }
+
+    /// Remove the entries kept longer than the retention, returning how many
+    pub fn purge_expired(&self) -> Result<usize> {
+        let Some(retention) = self.retention else {
+            return Ok(0);
+        };
+        let now = SystemTime::now();
+        let mut purged = 0;
+        for entry in self.list()? {
+            let age = now.duration_since(entry.trashed_at).unwrap_or_default();
+            if age >= retention {
+                std::fs::remove_dir_all(self.dir.join(&entry.id))
+                    .with_context(|| format!("Failed to purge trash entry {}", entry.id))?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Create the directory of a new entry, named `<millis>-<n>` so ids sort
+    /// by age and do not repeat across restarts
+    fn create_entry_dir(&self) -> Result<(String, PathBuf)> {
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder
+            .create(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        builder.recursive(false);
+        loop {
+            let id = format!(
+                "{}-{}",
+                millis,
+                self.next_id.fetch_add(1, Ordering::Relaxed)
+            );
+            let entry_dir = self.dir.join(&id);
+            match builder.create(&entry_dir) {
+                Ok(()) => return Ok((id, entry_dir)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to create {}", entry_dir.display()));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_trash_and_restore() {
+        let dir = tempdir().unwrap();
+        let trash = Trash::new(dir.path().join("trash"), None);
+        let file = dir.path().join("notes.txt");
+        std::fs::write(&file, b"notes").unwrap();
+        let tree = dir.path().join("tree");
+        std::fs::create_dir_all(tree.join("sub")).unwrap();
+
+        let first = trash.trash(&file.to_string_lossy()).unwrap();
+        let second = trash.trash(&tree.to_string_lossy()).unwrap();
+        assert!(!file.exists() && !tree.exists());
+        assert_eq!(first.original_path, file.to_string_lossy());
+        assert_eq!(trash.list().unwrap(), vec![first.clone(), second.clone()]);
+
+        trash.restore(&first.id, None).unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), b"notes");
+        let elsewhere = dir.path().join("restored/tree");
+        trash
+            .restore(&second.id, Some(&elsewhere.to_string_lossy()))
+            .unwrap();
+        assert!(elsewhere.join("sub").is_dir());
+        assert!(trash.list().unwrap().is_empty());
+
+        // Nothing is overwritten by a restore
+        let again = trash.trash(&file.to_string_lossy()).unwrap();
+        std::fs::write(&file, b"new").unwrap();
+        assert!(trash.restore(&again.id, None).is_err());
+        assert_eq!(std::fs::read(&file).unwrap(), b"new");
+        assert!(trash.restore("../escape", None).is_err());
+    }
+
+    #[test]
+    fn test_purge_expired() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("a");
+        std::fs::write(&file, b"a").unwrap();
+
+        let trash = Trash::new(dir.path().join("trash"), Some(Duration::ZERO));
+        trash.trash(&file.to_string_lossy()).unwrap();
+        assert_eq!(trash.purge_expired().unwrap(), 1);
+        assert!(trash.list().unwrap().is_empty());
+
+        std::fs::write(&file, b"a").unwrap();
+        let kept = Trash::new(dir.path().join("trash"), Some(DEFAULT_RETENTION));
+        kept.trash(&file.to_string_lossy()).unwrap();
+        assert_eq!(kept.purge_expired().unwrap(), 0);
+        assert_eq!(kept.list().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,324 @@
+//! io_uring backend (Linux, `io-uring` feature)
+//!
+//! Runs file chunk reads and port forwarding relays on a dedicated
+//! tokio-uring thread, avoiding the blocking thread pool that `tokio::fs`
+//! uses and the readiness round trips of the epoll path.
+//!
+//! Support is detected once at runtime: kernels without io_uring, or
+//! sandboxes that block it through seccomp, fall back to the epoll path.
+
+use anyhow::{Context, Result, anyhow};
+use bytes::Bytes;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{Mutex, RwLock, mpsc, oneshot};
+use tokio_uring::buf::IoBuf;
+use tokio_uring::net::TcpStream;
+use tracing::{error, info, warn};
+use yuha_core::protocol::ResponseBuffer;
+
+use crate::forward::{Flow, RELAY_BUF_LEN};
+
+type Job = Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = ()>>> + Send>;
+
+static RUNTIME: OnceLock<Option<UringRuntime>> = OnceLock::new();
+
+/// The shared io_uring runtime, or `None` when io_uring is unavailable
+pub fn runtime() -> Option<&'static UringRuntime> {
+    RUNTIME.get_or_init(UringRuntime::start).as_ref()
+}
+
+/// Whether the running kernel allows creating an io_uring instance
+pub fn is_supported() -> bool {
+    io_uring::IoUring::new(2).is_ok()
+}
+
+/// Handle to the thread running the tokio-uring runtime
+///
+/// io_uring resources are bound to the thread that created them, so jobs are
+/// sent to the runtime thread and their results returned over a channel.
+pub struct UringRuntime {
+    jobs: mpsc::UnboundedSender<Job>,
+}
+
+impl UringRuntime {
+    fn start() -> Option<Self> {
+        if !is_supported() {
+            warn!("io_uring is not available, using the epoll backend");
+            return None;
+        }
+
+        let (jobs, mut job_rx) = mpsc::unbounded_channel::<Job>();
+        let spawned = std::thread::Builder::new()
+            .name("yuha-uring".to_string())
+            .spawn(move || {
+                tokio_uring::start(async move {
+                    while let Some(job) = job_rx.recv().await {
+                        tokio_uring::spawn(job());
+                    }
+                })
+            });
+        if let Err(e) = spawned {
+            warn!(
+                "Failed to start io_uring thread, using the epoll backend: {}",
+                e
+            );
+            return None;
+        }
+
+        info!("Using the io_uring backend");
+        Some(Self { jobs })
+    }
+
+    /// Run `job` on the runtime thread and wait for its output
+    async fn run<F, Fut, T>(&self, job: F) -> Result<T>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = T> + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job = Box::new(move || {
+            Box::pin(async move {
+                let _ = tx.send(job().await);
+            })
+        });
+        self.jobs
+            .send(job)
+            .map_err(|_| anyhow!("io_uring runtime has stopped"))?;
+        rx.await.map_err(|_| anyhow!("io_uring job was dropped"))
+    }
+
+    /// Read up to `len` bytes at `offset`, returning the data and the file size
+    pub async fn read_at(&self, path: PathBuf, offset: u64, len: usize) -> Result<(Vec<u8>, u64)> {
+        self.run(move || async move {
+            let file = tokio_uring::fs::File::open(&path)
+                .await
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            let size = std::fs::metadata(&path)?.len();
+
+            let mut data = Vec::with_capacity(len);
+            while data.len() < len {
+                let filled = data.len();
+                let (result, slice) = file
+                    .read_at(data.slice(filled..), offset + filled as u64)
+                    .await;
+                data = slice.into_inner();
+                if result? == 0 {
+                    break;
+                }
+            }
+            file.close().await?;
+            Ok((data, size))
+        })
+        .await?
+    }
+
+    /// Relay a forwarded connection until either side closes
+    ///
+    /// Behaves like [`crate::forward::epoll_relay`].
+    pub async fn relay(
+        &self,
+        client: std::net::TcpStream,
+        target: std::net::TcpStream,
+        connection_id: u32,
+        response_buffer: Arc<RwLock<ResponseBuffer>>,
+        data_rx: mpsc::UnboundedReceiver<Bytes>,
+        flow: Arc<Flow>,
+    ) -> Result<()> {
+        self.run(move || {
+            relay(
+                TcpStream::from_std(client),
+                TcpStream::from_std(target),
+                connection_id,
+                response_buffer,
+                data_rx,
+                flow,
+            )
+        })
+        .await
+    }
+}
+
+async fn relay(
+    client: TcpStream,
+    target: TcpStream,
+    connection_id: u32,
+    response_buffer: Arc<RwLock<ResponseBuffer>>,
+    mut data_rx: mpsc::UnboundedReceiver<Bytes>,
+    flow: Arc<Flow>,
+) {
+    // Client data and injected data are both written to the target; keep each
+    // write whole so the two streams cannot interleave
+    let target_writes = Mutex::new(());
+
+    let upstream = async {
+        let mut buf = vec![0; RELAY_BUF_LEN];
+        loop {
+            let (result, read_buf) = client.read(buf).await;
+            let n = match result {
+                Ok(0) => return,
+                Ok(n) => n,
+                Err(e) => {
+                    error!(
+                        "Error reading from client for connection {}: {}",
+                        connection_id, e
+                    );
+                    return;
+                }
+            };
+            let _write = target_writes.lock().await;
+            let (result, written) = target.write_all(read_buf.slice(..n)).await;
+            if let Err(e) = result {
+                error!(
+                    "Error writing to target for connection {}: {}",
+                    connection_id, e
+                );
+                return;
+            }
+            buf = written.into_inner();
+        }
+    };
+
+    let downstream = async {
+        let mut buf = vec![0; RELAY_BUF_LEN];
+        loop {
+            let window = flow.outbound.available().await.min(RELAY_BUF_LEN);
+            let (result, read_buf) = target.read(buf.slice(..window)).await;
+            let read_buf = read_buf.into_inner();
+            let n = match result {
+                Ok(0) => return,
+                Ok(n) => n,
+                Err(e) => {
+                    error!(
+                        "Error reading from target for connection {}: {}",
+                        connection_id, e
+                    );
+                    return;
+                }
+            };
+            flow.outbound.consume(n);
+            // Buffer data for client polling before the client can react to it
+            response_buffer
+                .write()
+                .await
+                .add_port_forward_data(connection_id, Bytes::copy_from_slice(&read_buf[..n]));
+            let (result, written) = client.write_all(read_buf.slice(..n)).await;
+            if let Err(e) = result {
+                error!(
+                    "Error writing to client for connection {}: {}",
+                    connection_id, e
+                );
+                return;
+            }
+            buf = written.into_inner();
+        }
+    };
+
+    let injected = async {
+        while let Some(data) = data_rx.recv().await {
+            let _write = target_writes.lock().await;
+            let (result, _) = target.write_all(data.to_vec()).await;
+            if let Err(e) = result {
+                error!(
+                    "Error writing client data to target for connection {}: {}",
+                    connection_id, e
+                );
+                return;
+            }
+            flow.written(connection_id, data.len(), &response_buffer)
+                .await;
+        }
+    };
+
+    tokio::select! {
+        _ = upstream => {}
+        _ = downstream => {}
+        _ = injected => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use yuha_core::protocol::ResponseItem;
+    use yuha_core::protocol::buffer::ProtocolBuffer;
+
+    fn uring() -> &'static UringRuntime {
+        runtime().expect("io_uring supported on the test host")
+    }
+
+    #[tokio::test]
+    async fn test_read_at() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        let content: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &content).unwrap();
+
+        let (data, size) = uring().read_at(path.clone(), 100, 4096).await.unwrap();
+        assert_eq!(size, content.len() as u64);
+        assert_eq!(data, &content[100..4196]);
+
+        let (data, _) = uring().read_at(path, 9_000, 4096).await.unwrap();
+        assert_eq!(data, &content[9_000..]);
+    }
+
+    #[tokio::test]
+    async fn test_relay() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (client_side, _) = listener.accept().unwrap();
+        let target_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target_side =
+            std::net::TcpStream::connect(target_listener.local_addr().unwrap()).unwrap();
+        let (mut target, _) = target_listener.accept().unwrap();
+
+        let response_buffer = Arc::new(RwLock::new(ResponseBuffer::new()));
+        let (data_tx, data_rx) = mpsc::unbounded_channel();
+        let relay = tokio::spawn({
+            let response_buffer = response_buffer.clone();
+            async move {
+                uring()
+                    .relay(
+                        client_side,
+                        target_side,
+                        7,
+                        response_buffer,
+                        data_rx,
+                        Arc::new(Flow::new(false)),
+                    )
+                    .await
+            }
+        });
+
+        let mut buf = [0; 5];
+        let exchanged = tokio::task::spawn_blocking(move || {
+            client.write_all(b"hello").unwrap();
+            target.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"hello");
+
+            data_tx.send(Bytes::from_static(b"polled")).unwrap();
+            let mut injected = [0; 6];
+            target.read_exact(&mut injected).unwrap();
+            assert_eq!(&injected, b"polled");
+
+            target.write_all(b"reply").unwrap();
+            client.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"reply");
+            drop(client);
+            target
+        });
+        let _target = exchanged.await.unwrap();
+
+        relay.await.unwrap().unwrap();
+        let items = response_buffer.write().await.take_items();
+        assert!(matches!(
+            &items[..],
+            [ResponseItem::PortForwardData { connection_id: 7, data }] if &data[..] == b"reply"
+        ));
+    }
+}
@@ -0,0 +1,274 @@
+//! Usage accounting
+//!
+//! Each client identity, e.g. the Noise key a client authenticated with, has
+//! a [`Meter`] counting the bytes its sessions exchange, the forwards they
+//! start and the CPU time of the applications they launch, whatever
+//! workspace they select; sessions without an identity share one. Meters
+//! start over every window when the server has one. [`check`] and
+//! [`check_forwards`] refuse to use more of a resource once its [`Quota`] is
+//! reached.
+
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use yuha_core::protocol::ProtocolRequest;
+use yuha_core::protocol::request_response::{Quota, Usage};
+
+/// The meters of a server's clients
+#[derive(Debug, Default)]
+pub struct Meters {
+    window: Option<Duration>,
+    meters: Mutex<HashMap<Option<String>, Arc<Meter>>>,
+}
+
+impl Meters {
+    /// Meters starting over every `window`, or never without one
+    pub fn new(window: Option<Duration>) -> Self {
+        Self {
+            window,
+            meters: Mutex::default(),
+        }
+    }
+
+    /// Meter of the client authenticated with `identity`
+    pub fn of(&self, identity: Option<&str>) -> Arc<Meter> {
+        self.meters
+            .lock()
+            .unwrap()
+            .entry(identity.map(str::to_string))
+            .or_insert_with(|| Arc::new(Meter::new(self.window)))
+            .clone()
+    }
+}
+
+/// Resources used so far in the current window
+#[derive(Debug, Default)]
+pub struct Meter {
+    window: Option<Duration>,
+    counters: Mutex<Counters>,
+}
+
+#[derive(Debug)]
+struct Counters {
+    started: Instant,
+    bytes: u64,
+    forwards: u64,
+    exec_cpu: Duration,
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            bytes: 0,
+            forwards: 0,
+            exec_cpu: Duration::ZERO,
+        }
+    }
+}
+
+impl Meter {
+    /// Meter starting over every `window`, or never without one
+    pub fn new(window: Option<Duration>) -> Self {
+        Self {
+            window,
+            counters: Mutex::default(),
+        }
+    }
+
+    /// Counters of the current window, starting a new one when it is over
+    fn counters(&self) -> std::sync::MutexGuard<'_, Counters> {
+        let mut counters = self.counters.lock().unwrap();
+        if let Some(window) = self.window
+            && counters.started.elapsed() >= window
+        {
+            *counters = Counters::default();
+        }
+        counters
+    }
+
+    pub fn add_bytes(&self, bytes: u64) {
+        let mut counters = self.counters();
+        counters.bytes = counters.bytes.saturating_add(bytes);
+    }
+
+    pub fn add_forward(&self) {
+        self.counters().forwards += 1;
+    }
+
+    pub fn add_exec_cpu(&self, cpu: Duration) {
+        self.counters().exec_cpu += cpu;
+    }
+
+    /// Usage of the client `identity` against `quota`
+    pub fn usage(&self, identity: Option<&str>, quota: &Quota) -> Usage {
+        let counters = self.counters();
+        Usage {
+            client: identity.map(str::to_string),
+            bytes: counters.bytes,
+            forwards: counters.forwards,
+            exec_cpu: counters.exec_cpu,
+            resets_in: self
+                .window
+                .map(|window| window.saturating_sub(counters.started.elapsed())),
+            quota: quota.clone(),
+        }
+    }
+}
+
+/// Fail if `request` needs a resource whose quota `usage` reached
+///
+/// Past the transfer quota only requests releasing resources or reporting
+/// usage are served.
+pub fn check(usage: &Usage, request: &ProtocolRequest) -> Result<()> {
+    if let Some(max) = usage.quota.bytes
+        && usage.bytes >= max
+        && !matches!(
+            request,
+            ProtocolRequest::Hello { .. }
+                | ProtocolRequest::GetUsage
+                | ProtocolRequest::StopPortForward { .. }
+                | ProtocolRequest::ReleasePortForward { .. }
+                | ProtocolRequest::CancelTask { .. }
+        )
+    {
+        bail!(
+            "Transfer quota exceeded: {} of {} bytes used",
+            usage.bytes,
+            max
+        );
+    }
+    if let Some(max) = usage.quota.exec_cpu
+        && usage.exec_cpu >= max
+        && matches!(
+            request,
+            ProtocolRequest::LaunchApp { .. } | ProtocolRequest::OpenPath { .. }
+        )
+    {
+        bail!(
+            "CPU time quota exceeded: launched applications used {:.1}s of {:.1}s",
+            usage.exec_cpu.as_secs_f64(),
+            max.as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+/// Fail if `usage` reached the quota of port forwards, checked by every
+/// forward start including those of an imported session state
+pub fn check_forwards(usage: &Usage) -> Result<()> {
+    if let Some(max) = usage.quota.forwards
+        && usage.forwards >= max
+    {
+        bail!(
+            "Port forward quota exceeded: {} of {} forwards started",
+            usage.forwards,
+            max
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(meter: &Meter) -> Usage {
+        meter.usage(
+            Some("client-key"),
+            &Quota {
+                bytes: Some(100),
+                forwards: Some(1),
+                exec_cpu: Some(Duration::from_secs(1)),
+            },
+        )
+    }
+
+    fn launch() -> ProtocolRequest {
+        ProtocolRequest::LaunchApp {
+            command: vec!["xterm".to_string()],
+            display_env: Default::default(),
+            sandbox: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_meter() {
+        let meter = Meter::default();
+        meter.add_bytes(40);
+        meter.add_bytes(2);
+        meter.add_forward();
+        meter.add_exec_cpu(Duration::from_millis(250));
+
+        let usage = usage(&meter);
+        assert_eq!(usage.client.as_deref(), Some("client-key"));
+        assert_eq!(usage.bytes, 42);
+        assert_eq!(usage.forwards, 1);
+        assert_eq!(usage.exec_cpu, Duration::from_millis(250));
+        assert_eq!(usage.resets_in, None);
+    }
+
+    #[test]
+    fn test_meters_per_client() {
+        let meters = Meters::default();
+        meters.of(Some("alice")).add_bytes(10);
+        meters.of(Some("alice")).add_bytes(5);
+        meters.of(Some("bob")).add_bytes(1);
+        let bytes = |identity| meters.of(identity).usage(identity, &Quota::default()).bytes;
+        assert_eq!(bytes(Some("alice")), 15);
+        assert_eq!(bytes(Some("bob")), 1);
+        assert_eq!(bytes(None), 0);
+    }
+
+    #[test]
+    fn test_window() {
+        let meter = Meter::new(Some(Duration::from_millis(50)));
+        meter.add_bytes(100);
+        meter.add_forward();
+        let usage = meter.usage(None, &Quota::default());
+        assert_eq!((usage.bytes, usage.forwards), (100, 1));
+        assert!(usage.resets_in.unwrap() <= Duration::from_millis(50));
+
+        std::thread::sleep(Duration::from_millis(60));
+        let usage = meter.usage(None, &Quota::default());
+        assert_eq!((usage.bytes, usage.forwards), (0, 0));
+    }
+
+    #[test]
+    fn test_quotas() {
+        let meter = Meter::default();
+        assert!(check_forwards(&usage(&meter)).is_ok());
+        assert!(check(&usage(&meter), &launch()).is_ok());
+
+        meter.add_forward();
+        meter.add_exec_cpu(Duration::from_secs(1));
+        let error = check_forwards(&usage(&meter)).unwrap_err();
+        assert!(error.to_string().contains("1 of 1 forwards"));
+        assert!(check(&usage(&meter), &launch()).is_err());
+        assert!(check(&usage(&meter), &ProtocolRequest::GetClipboard).is_ok());
+
+        meter.add_bytes(100);
+        assert!(check(&usage(&meter), &ProtocolRequest::GetClipboard).is_err());
+        // Usage can still be read and resources released
+        assert!(check(&usage(&meter), &ProtocolRequest::GetUsage).is_ok());
+        assert!(
+            check(
+                &usage(&meter),
+                &ProtocolRequest::StopPortForward { local_port: 8080 }
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let meter = Meter::default();
+        meter.add_bytes(u64::MAX / 2);
+        meter.add_forward();
+        let usage = meter.usage(None, &Quota::default());
+        assert!(check_forwards(&usage).is_ok());
+        assert!(check(&usage, &launch()).is_ok());
+        assert!(check(&usage, &ProtocolRequest::GetClipboard).is_ok());
+    }
+}
@@ -0,0 +1,212 @@
+//! Workspaces
+//!
+//! One server process can host several isolated workspaces, which clients
+//! select by name in their `Hello`. Each has a clipboard of its own, a
+//! forward namespace (its forwards and tasks are seen and stopped only by
+//! its sessions) and root directories confining the paths of file requests.
+//! Servers without workspaces give every session the host's clipboard and
+//! unrestricted paths.
+//!
+//! Workspaces keep cooperating users and projects apart; they are no
+//! sandbox, as requests such as `LaunchApp` still run as the server's user
+//! unless the server sets a launch sandbox (see [`crate::sandbox`]).
+
+use anyhow::{Context, Result, bail};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use yuha_core::clipboard::ClipboardStore;
+
+use crate::tasks::TaskRegistry;
+
+/// A named, isolated part of the server
+pub struct Workspace {
+    name: String,
+    /// Canonical directories paths must be under
+    roots: Vec<PathBuf>,
+    clipboard: ClipboardStore,
+    tasks: TaskRegistry,
+}
+
+impl Workspace {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            roots: Vec::new(),
+            clipboard: ClipboardStore::new(),
+            tasks: TaskRegistry::new(name),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Clipboard shared by the sessions of this workspace
+    pub fn clipboard(&self) -> &ClipboardStore {
+        &self.clipboard
+    }
+
+    /// The workspace's tasks, spawning new ones on behalf of `session`
+    pub fn tasks(&self, session: &str) -> TaskRegistry {
+        self.tasks.for_session(session)
+    }
+
+    /// Fail unless `path` is under one of the roots once symlinks and `..`
+    /// are resolved; a path not existing yet is resolved by its parent
+    pub fn check_path(&self, path: &str) -> Result<()> {
+        let path = Path::new(path);
+        let resolved = match path.canonicalize() {
+            Ok(resolved) => resolved,
+            Err(_) => {
+                let name = path
+                    .file_name()
+                    .with_context(|| format!("Cannot resolve {}", path.display()))?;
+                let parent = path
+                    .parent()
+                    .filter(|parent| !parent.as_os_str().is_empty())
+                    .unwrap_or(Path::new("."));
+                parent
+                    .canonicalize()
+                    .with_context(|| format!("Cannot resolve {}", path.display()))?
+                    .join(name)
+            }
+        };
+        if !self.roots.iter().any(|root| resolved.starts_with(root)) {
+            bail!("{} is outside workspace {}", path.display(), self.name);
+        }
+        Ok(())
+    }
+}
+
+/// Workspaces hosted by a server, by name
+#[derive(Default, Clone)]
+pub struct Workspaces(BTreeMap<String, Arc<Workspace>>);
+
+impl Workspaces {
+    /// Workspaces from `NAME=ROOT` specs; a name given again adds a root
+    pub fn parse(specs: &[String]) -> Result<Self> {
+        let mut workspaces = BTreeMap::new();
+        for spec in specs {
+            let Some((name, root)) = spec.split_once('=') else {
+                bail!("Invalid workspace '{}', expected NAME=ROOT", spec);
+            };
+            if name.is_empty() {
+                bail!("Invalid workspace '{}', the name is empty", spec);
+            }
+            let root = Path::new(root)
+                .canonicalize()
+                .with_context(|| format!("Invalid root of workspace {}: {}", name, root))?;
+            workspaces
+                .entry(name.to_string())
+                .or_insert_with(|| Workspace::new(name))
+                .roots
+                .push(root);
+        }
+        Ok(Self(
+            workspaces
+                .into_iter()
+                .map(|(name, workspace)| (name, Arc::new(workspace)))
+                .collect(),
+        ))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The workspace a client selected with `name`, which it must do when
+    /// the server hosts any
+    pub fn select(&self, name: Option<&str>) -> Result<Option<Arc<Workspace>>> {
+        match name {
+            Some(name) => match self.0.get(name) {
+                Some(workspace) => Ok(Some(workspace.clone())),
+                None => bail!("Unknown workspace {}", name),
+            },
+            None if self.is_empty() => Ok(None),
+            None => bail!(
+                "Select one of the workspaces: {}",
+                self.0.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workspaces(dir: &Path) -> Workspaces {
+        std::fs::create_dir_all(dir.join("frontend")).unwrap();
+        std::fs::create_dir_all(dir.join("backend")).unwrap();
+        Workspaces::parse(&[
+            format!("frontend={}", dir.join("frontend").display()),
+            format!("backend={}", dir.join("backend").display()),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_select() {
+        let dir = tempfile::tempdir().unwrap();
+        let workspaces = workspaces(dir.path());
+
+        let selected = workspaces.select(Some("frontend")).unwrap().unwrap();
+        assert_eq!(selected.name(), "frontend");
+        assert!(workspaces.select(Some("ops")).is_err());
+        let error = workspaces.select(None).err().unwrap();
+        assert!(error.to_string().contains("backend, frontend"));
+
+        let none = Workspaces::default();
+        assert!(none.select(None).unwrap().is_none());
+        assert!(none.select(Some("frontend")).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_specs() {
+        assert!(Workspaces::parse(&["frontend".to_string()]).is_err());
+        assert!(Workspaces::parse(&["=/tmp".to_string()]).is_err());
+        assert!(Workspaces::parse(&["frontend=/no/such/dir".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_check_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let workspaces = workspaces(dir.path());
+        let frontend = workspaces.select(Some("frontend")).unwrap().unwrap();
+        let root = dir.path().join("frontend");
+        std::fs::write(root.join("index.html"), "").unwrap();
+        let path = |path: PathBuf| path.to_str().unwrap().to_string();
+
+        assert!(frontend.check_path(&path(root.join("index.html"))).is_ok());
+        // Uploads name files not existing yet
+        assert!(frontend.check_path(&path(root.join("new.html"))).is_ok());
+        assert!(frontend.check_path(&path(root.clone())).is_ok());
+        assert!(
+            frontend
+                .check_path(&path(root.join("../backend/main.rs")))
+                .is_err()
+        );
+        assert!(
+            frontend
+                .check_path(&path(dir.path().join("backend")))
+                .is_err()
+        );
+        assert!(frontend.check_path("/").is_err());
+    }
+
+    #[test]
+    fn test_clipboards_are_separate() {
+        let dir = tempfile::tempdir().unwrap();
+        let workspaces = workspaces(dir.path());
+        let frontend = workspaces.select(Some("frontend")).unwrap().unwrap();
+        let backend = workspaces.select(Some("backend")).unwrap().unwrap();
+
+        frontend.clipboard().set_text("frontend").unwrap();
+        assert_eq!(backend.clipboard().text().unwrap(), None);
+        assert_eq!(
+            frontend.clipboard().text().unwrap().as_deref(),
+            Some("frontend")
+        );
+    }
+}
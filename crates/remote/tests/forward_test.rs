@@ -0,0 +1,204 @@
+//! Integration tests for port forwarding and flow control through the remote
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::process::Stdio;
+use tempfile::{TempDir, tempdir};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, Join};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::time::{Duration, timeout};
+use yuha_core::message_channel::MessageChannel;
+use yuha_core::protocol::extension::{self, ExtensionId};
+use yuha_core::protocol::flow::{INITIAL_BATCH_CREDIT, INITIAL_CREDIT};
+use yuha_core::protocol::{ProtocolRequest, ProtocolResponse, ResponseItem};
+
+/// Bytes the target sends, well over a connection's initial credit
+const TARGET_LEN: usize = 4 * INITIAL_CREDIT;
+/// Files of a listing streamed in well over the initial batch credit
+const LISTED_FILES: usize = 2000;
+
+/// A remote serving one session over stdio
+struct Remote {
+    channel: MessageChannel<Join<ChildStdout, ChildStdin>>,
+    _child: Child,
+    _dir: TempDir,
+}
+
+impl Remote {
+    /// Start a remote and negotiate `extensions` with it
+    async fn start(extensions: Vec<ExtensionId>) -> Result<Self> {
+        let dir = tempdir()?;
+        let mut child = Command::new(env!("CARGO_BIN_EXE_yuha-remote"))
+            .arg("--stdio")
+            .arg("--ipc-socket")
+            .arg(dir.path().join("ipc.sock"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stream = tokio::io::join(child.stdout.take().unwrap(), child.stdin.take().unwrap());
+        let mut remote = Self {
+            channel: MessageChannel::new_with_stream(stream),
+            _child: child,
+            _dir: dir,
+        };
+        let hello = ProtocolRequest::Hello {
+            extensions,
+            workspace: None,
+        };
+        assert!(matches!(
+            remote.request(hello).await?,
+            ProtocolResponse::Data { .. }
+        ));
+        Ok(remote)
+    }
+
+    async fn request(&mut self, request: ProtocolRequest) -> Result<ProtocolResponse> {
+        self.channel.send_request(&request).await?;
+        Ok(timeout(Duration::from_secs(10), self.channel.receive_response()).await??)
+    }
+
+    /// Forward a free port to `target_port`, returning the forwarded port
+    async fn forward(&mut self, target_port: u16) -> Result<u16> {
+        let local_port = std::net::TcpListener::bind("127.0.0.1:0")?
+            .local_addr()?
+            .port();
+        let request = ProtocolRequest::StartPortForward {
+            local_port,
+            remote_host: "127.0.0.1".to_string(),
+            remote_port: target_port,
+        };
+        assert!(matches!(
+            self.request(request).await?,
+            ProtocolResponse::Success
+        ));
+        Ok(local_port)
+    }
+}
+
+/// Send [`TARGET_LEN`] bytes to the first connection on a free port
+async fn target() -> Result<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        if let Ok((mut stream, _)) = listener.accept().await {
+            let _ = stream.write_all(&vec![7; TARGET_LEN]).await;
+        }
+    });
+    Ok(port)
+}
+
+/// Read what the forward on `port` relays until the target closed
+async fn read_forward(port: u16) -> Result<usize> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await?;
+    let mut data = Vec::new();
+    stream.read_to_end(&mut data).await?;
+    Ok(data.len())
+}
+
+#[tokio::test]
+async fn test_forward_without_flow_control() -> Result<()> {
+    let mut remote = Remote::start(Vec::new()).await?;
+    let port = remote.forward(target().await?).await?;
+
+    // Nothing polls the data, so nothing grants credit for it either
+    let relayed = timeout(Duration::from_secs(10), read_forward(port)).await??;
+    assert_eq!(relayed, TARGET_LEN);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_forward_with_granted_credit() -> Result<()> {
+    let mut remote = Remote::start(vec![extension::FLOW_CONTROL.id]).await?;
+    let port = remote.forward(target().await?).await?;
+    let reading = tokio::spawn(read_forward(port));
+
+    let mut polled = 0;
+    while polled < TARGET_LEN {
+        let ProtocolResponse::Data { items, .. } =
+            remote.request(ProtocolRequest::PollData).await?
+        else {
+            panic!("unexpected poll response");
+        };
+        let mut consumed = HashMap::new();
+        for item in items {
+            if let ResponseItem::PortForwardData {
+                connection_id,
+                data,
+            } = item
+            {
+                polled += data.len();
+                *consumed.entry(connection_id).or_insert(0) += data.len() as u32;
+            }
+        }
+        for (connection_id, bytes) in consumed {
+            let grant = ProtocolRequest::GrantCredit {
+                connection_id,
+                bytes,
+            };
+            assert!(matches!(
+                remote.request(grant).await?,
+                ProtocolResponse::Success
+            ));
+        }
+    }
+    assert_eq!(polled, TARGET_LEN);
+    let relayed = timeout(Duration::from_secs(10), reading).await???;
+    assert_eq!(relayed, TARGET_LEN);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_streamed_listing_waits_for_grants() -> Result<()> {
+    let files = tempdir()?;
+    for i in 0..LISTED_FILES {
+        std::fs::write(files.path().join(format!("{:0>200}", i)), b"")?;
+    }
+    let mut remote = Remote::start(vec![extension::FLOW_CONTROL.id]).await?;
+    let listing = ProtocolRequest::ListFiles {
+        paths: vec![files.path().to_string_lossy().into_owned()],
+        query: Default::default(),
+    };
+    remote.channel.send_request_with_id(1, &listing).await?;
+
+    for _ in 0..INITIAL_BATCH_CREDIT {
+        let (id, response) = timeout(
+            Duration::from_secs(10),
+            remote.channel.receive_response_with_id(),
+        )
+        .await??;
+        assert_eq!(id, Some(1));
+        assert!(matches!(
+            response,
+            ProtocolResponse::Batch { more: true, .. }
+        ));
+    }
+    let held = timeout(
+        Duration::from_millis(500),
+        remote.channel.receive_response_with_id(),
+    )
+    .await;
+    assert!(held.is_err(), "streamed past the credit");
+
+    let grant = ProtocolRequest::GrantBatches {
+        id: 1,
+        batches: u32::MAX,
+    };
+    remote.channel.send_request_with_id(2, &grant).await?;
+    let (mut granted, mut done) = (false, false);
+    while !(granted && done) {
+        let (id, response) = timeout(
+            Duration::from_secs(10),
+            remote.channel.receive_response_with_id(),
+        )
+        .await??;
+        match (id, response) {
+            (Some(2), ProtocolResponse::Success) => granted = true,
+            (Some(1), ProtocolResponse::Batch { more, .. }) => done = !more,
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,51 @@
+//! Integration tests for the stdio mode protocol stream
+
+use anyhow::Result;
+use std::process::Stdio;
+use tempfile::tempdir;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tokio::time::{Duration, timeout};
+use yuha_core::message_channel::MessageChannel;
+use yuha_core::protocol::{ProtocolRequest, ProtocolResponse};
+
+#[tokio::test]
+async fn test_logs_stay_off_the_protocol_stream() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_yuha-remote"))
+        .arg("--stdio")
+        .arg("--ipc-socket")
+        .arg(temp_dir.path().join("ipc.sock"))
+        // Every request is logged, on stdout before the guard
+        .env("RUST_LOG", "debug")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stream = tokio::io::join(child.stdout.take().unwrap(), child.stdin.take().unwrap());
+    let mut channel = MessageChannel::new_with_stream(stream);
+
+    for content in ["first", "second"] {
+        channel
+            .send_request(&ProtocolRequest::SetClipboard {
+                content: content.to_string(),
+            })
+            .await?;
+        // Corrupted framing leaves the response unread
+        let response = timeout(Duration::from_secs(10), channel.receive_response()).await??;
+        assert!(matches!(response, ProtocolResponse::Success));
+    }
+
+    drop(channel);
+    child.wait().await?;
+    let mut stderr = String::new();
+    child
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut stderr)
+        .await?;
+    assert!(stderr.contains("REMOTE_SERVER_STARTUP"));
+    Ok(())
+}
@@ -0,0 +1,141 @@
+//! # Yuha
+//!
+//! The stable API for embedding a yuha client: connecting to a remote,
+//! sending it requests and handling what it pushes back.
+//!
+//! ## Stability
+//!
+//! Only the items exported by this crate follow semantic versioning: they
+//! change incompatibly only in a major release, or in a minor release while
+//! the version is `0.x`. The crates they come from (`yuha-core`,
+//! `yuha-client`) are internal to yuha and may change in any release;
+//! depending on them directly opts out of this promise.
+//!
+//! Enums of the protocol gain variants as the protocol grows, so matches on
+//! them need a wildcard arm.
+//!
+//! ## Contents
+//!
+//! - **Connecting**: [`connect`] a [`Client`] to a remote described by a
+//!   [`transport::TransportBuilder`], or create its [`transport()`] to set
+//!   the client up before connecting
+//! - **Requests**: the methods of [`Client`], and [`protocol`] types for
+//!   their arguments and answers
+//! - **Errors**: [`ClientError`], with the [`protocol::ErrorCode`] of errors
+//!   the remote reported
+//! - **Server Requests**: [`handlers`] answering what the remote asks of the
+//!   client
+//! - **Credentials**: [`credentials`] providers asked for secrets the
+//!   configuration lacks
+//!
+//! ## Usage
+//!
+//! ```rust,no_run
+//! # async fn example() -> yuha::Result<()> {
+//! use yuha::transport::TransportBuilder;
+//!
+//! let config = TransportBuilder::ssh()
+//!     .host("example.com")
+//!     .username("user")
+//!     .key_file("/path/to/key")
+//!     .build()?;
+//! let client = yuha::connect(&config).await?;
+//! println!("{}", client.get_clipboard().await?);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::Arc;
+
+use yuha_client::transport_factory::ClientTransportFactory;
+
+pub use yuha_client::{Client, ClientError};
+
+/// Result of the operations of a client
+pub type Result<T, E = ClientError> = std::result::Result<T, E>;
+
+/// Describing and creating the connection to a remote
+pub mod transport {
+    pub use yuha_client::transport::Transport;
+    pub use yuha_client::transport_factory::AnyTransport;
+    pub use yuha_core::transport::builder::{
+        LocalTransportBuilder, SshTransportBuilder, TcpTransportBuilder, TlsBuilder,
+        WslTransportBuilder,
+    };
+    pub use yuha_core::transport::{TransportBuilder, TransportConfig, TransportType};
+}
+
+/// Requests, responses and events exchanged with the remote
+pub mod protocol {
+    pub use yuha_core::clipboard::{ClipboardFormat, ClipboardItem};
+    pub use yuha_core::protocol::extension::ExtensionId;
+    pub use yuha_core::protocol::request_response::{
+        PortForwardEntry, TaskId, TaskInfo, ToolInfo, TrashEntry, Usage,
+    };
+    pub use yuha_core::protocol::{
+        CorrelationId, Cursor, ErrorCode, LaunchAppBuilder, ListFilesBuilder, ListQuery,
+        ProtocolRequest, ProtocolResponse, ResponseItem, ServerReply, ServerRequest, Topic,
+        TopicEvent,
+    };
+}
+
+/// Handlers answering requests of the remote
+pub mod handlers {
+    pub use yuha_client::server_requests::{BrowserHandler, FnHandler, ServerRequestHandler};
+}
+
+/// Providers of passwords, passphrases and one-time codes
+pub mod credentials {
+    pub use yuha_client::credentials::{
+        Credential, CredentialsProvider, EnvCredentials, Fallback, FnCredentials,
+        NonInteractiveCredentials, TerminalCredentials,
+    };
+}
+
+/// Items of streamed and paged responses, and responses of pipelined
+/// requests
+pub mod stream {
+    pub use yuha_client::pipeline::PendingResponse;
+    pub use yuha_client::stream::{PageStream, ResponseStream};
+}
+
+use credentials::{CredentialsProvider, EnvCredentials};
+use transport::{AnyTransport, TransportConfig};
+
+/// Transport to the remote `config` describes, asking `credentials` for
+/// the secrets it lacks
+pub fn transport(
+    config: &TransportConfig,
+    credentials: Arc<dyn CredentialsProvider>,
+) -> Result<AnyTransport> {
+    ClientTransportFactory::create_transport_with_credentials(config, credentials)
+        .map_err(|e| ClientError::Connection(e.to_string()))
+}
+
+/// Connect a client to the remote `config` describes, taking secrets it
+/// lacks from the environment
+pub async fn connect(config: &TransportConfig) -> Result<Client<AnyTransport>> {
+    let mut client = Client::new(transport(config, Arc::new(EnvCredentials))?);
+    client.connect().await?;
+    Ok(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::ErrorCode;
+    use transport::TransportBuilder;
+
+    #[tokio::test]
+    async fn test_connect_to_local_remote() {
+        let config = TransportBuilder::local()
+            .binary_path(yuha_client::get_remote_binary_path())
+            .build()
+            .unwrap();
+        let client = connect(&config).await.unwrap();
+        assert!(!client.extensions().is_empty());
+
+        let error = client.cancel_task(u64::MAX).await.unwrap_err();
+        assert_eq!(error.error_code(), Some(ErrorCode::NotFound));
+    }
+}